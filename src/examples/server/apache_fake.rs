@@ -17,7 +17,7 @@ struct ApacheFakeServer;
 
 impl Server for ApacheFakeServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
     }
 
     fn handle_request(&self, _r: &Request, w: &mut ResponseWriter) {
@@ -44,7 +44,7 @@ impl Server for ApacheFakeServer {
         w.headers.accept_ranges = Some(headers::accept_ranges::RangeUnits(
                                             ~[headers::accept_ranges::Bytes]));
         w.headers.content_length = Some(177);
-        w.headers.vary = Some(~"Accept-Encoding");
+        w.headers.vary = Some(headers::vary::VaryHeaders(~[~"Accept-Encoding"]));
         w.headers.content_type = Some(headers::content_type::MediaType {
             type_: ~"text",
             subtype: ~"html",
@@ -0,0 +1,38 @@
+//! A server which counts down from ten, sending a line each second as the response goes along,
+//! rather than all at once at the end; try it with `curl -N`.
+
+extern mod extra;
+extern mod http;
+
+use std::rt::io::net::ip::{SocketAddr, Ipv4Addr};
+use std::rt::io::Writer;
+use std::rt::io::timer::sleep;
+
+use http::server::{Config, Server, ServerUtil, Request, ResponseWriter};
+use http::headers::content_type::MediaType;
+
+#[deriving(Clone)]
+struct CountdownServer;
+
+impl Server for CountdownServer {
+    fn get_config(&self) -> Config {
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
+    }
+
+    fn handle_request(&self, _r: &Request, w: &mut ResponseWriter) {
+        w.headers.content_type = Some(MediaType(~"text", ~"plain", ~[(~"charset", ~"UTF-8")]));
+        // No Content-Length, so the body is sent chunked, and each flush sends a chunk.
+        w.write_headers();
+        for i in range(0, 10) {
+            let line = format!("{}...\n", 10 - i);
+            w.write(line.as_bytes());
+            w.flush();
+            sleep(1000);
+        }
+        w.write(bytes!("Lift-off!\n"));
+    }
+}
+
+fn main() {
+    CountdownServer.serve_forever();
+}
@@ -17,7 +17,7 @@ struct InfoServer;
 
 impl Server for InfoServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
     }
 
     fn handle_request(&self, r: &Request, w: &mut ResponseWriter) {
@@ -35,11 +35,13 @@ impl Server for InfoServer {
             <dt>Method</dt><dd>{}</dd>
             <dt>Host</dt><dd>{:?}</dd>
             <dt>Request URI</dt><dd>{:?}</dd>
+            <dt>URL</dt><dd>{}</dd>
             <dt>HTTP version</dt><dd>{:?}</dd>
             <dt>Close connection</dt><dd>{}</dd></dl>",
             r.method,
             r.headers.host,
             r.request_uri,
+            r.url.as_ref().map_default(~"(none)", |u| u.to_str()),
             r.version,
             r.close_connection);
         w.write(s.as_bytes());
@@ -53,7 +55,7 @@ impl Server for InfoServer {
         }
         w.write(bytes!("</tbody></table>"));
         w.write(bytes!("<h2>Body</h2><pre>"));
-        w.write(r.body.as_bytes());
+        w.write(r.body);
         w.write(bytes!("</pre>"));
 
         w.write(bytes!("<h1>Response</h1>"));
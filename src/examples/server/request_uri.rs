@@ -14,7 +14,7 @@ use extra::time;
 use http::server::{Config, Server, ServerUtil, Request, ResponseWriter};
 use http::server::request::{Star, AbsoluteUri, AbsolutePath, Authority};
 use http::status::{BadRequest, MethodNotAllowed};
-use http::method::{Get, Head, Post, Put, Delete, Trace, Options, Connect, Patch};
+use http::method::{Method, Get, Head, Post, Put, Delete, Trace, Options, Connect, Patch};
 use http::headers::content_type::MediaType;
 
 #[deriving(Clone)]
@@ -22,7 +22,13 @@ struct RequestUriServer;
 
 impl Server for RequestUriServer {
     fn get_config(&self) -> Config {
-        Config { bind_address: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 } }
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
+    }
+
+    // Querying server capabilities, with OPTIONS *. That's nice and simple. I can handle these
+    // methods:
+    fn server_allow(&self) -> Option<~[Method]> {
+        Some(~[Get, Head, Post, Put, Delete, Trace, Options, Connect, Patch])
     }
 
     fn handle_request(&self, r: &Request, w: &mut ResponseWriter) {
@@ -42,13 +48,6 @@ impl Server for RequestUriServer {
                 w.status = BadRequest;
                 return
             },
-            (&Options, &Star) => {
-                // Querying server capabilities. That's nice and simple. I can handle these methods:
-                // (TODO: let user code override this, providing a default method.)
-                w.headers.allow = Some(~[Get, Head, Post, Put, Delete, Trace, Options, Connect, Patch]);
-                w.headers.content_length = Some(0);
-                return;
-            },
             (&Options, &AbsoluteUri(_)) | (&Options, &AbsolutePath(_)) => {
             },
             (_, &AbsoluteUri(_)) | (_, &AbsolutePath(_)) => {
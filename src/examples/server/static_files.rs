@@ -0,0 +1,34 @@
+//! A server which serves the files in the directory it is run from.
+
+extern mod extra;
+extern mod http;
+
+use std::os;
+use std::rt::io::net::ip::{SocketAddr, Ipv4Addr};
+
+use http::server::{Config, Server, ServerUtil, Request, ResponseWriter};
+use http::server::request::AbsolutePath;
+use http::server::staticfile;
+use http::status;
+
+#[deriving(Clone)]
+struct StaticFileServer {
+    root: Path,
+}
+
+impl Server for StaticFileServer {
+    fn get_config(&self) -> Config {
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
+    }
+
+    fn handle_request(&self, r: &Request, w: &mut ResponseWriter) {
+        match r.request_uri {
+            AbsolutePath(ref path) => staticfile::serve_file(&self.root, *path, r, w),
+            _ => staticfile::send_error(w, status::BadRequest),
+        }
+    }
+}
+
+fn main() {
+    StaticFileServer { root: os::getcwd() }.serve_forever();
+}
@@ -3,25 +3,32 @@
 use std::rt::io::{Reader, Writer, Stream};
 use std::rt::io::net::tcp::TcpStream;
 use std::cmp::min;
+use std::util;
 use std::vec;
-use common::read_uint;
-use rfc2616::{CR, LF};
+use extra::time::precise_time_ns;
+use rfc2616::{CR, LF, SP, HT, is_hex};
+use percent_encoding::bytes_to_str;
+use headers::serialization_utils::write_field_bytes;
+use network::NetworkStream;
+use socket::RawFile;
 
 pub type BufTcpStream = BufferedStream<TcpStream>;
 
-// 64KB chunks (moderately arbitrary)
-static READ_BUF_SIZE: uint = 0x10000;
-static WRITE_BUF_SIZE: uint = 0x10000;
-// TODO: consider removing constants and giving a buffer size in the constructor
+// 64KB chunks (moderately arbitrary); `BufferedStream::with_capacities` takes other sizes.
+pub static READ_BUF_SIZE: uint = 0x10000;
+pub static WRITE_BUF_SIZE: uint = 0x10000;
+
+/// How long reading is given before it must keep up with the minimum rate, in nanoseconds.
+static MIN_READ_RATE_GRACE_NS: u64 = 1_000_000_000;
 
 struct BufferedStream<T> {
     wrapped: T,
-    read_buffer: [u8, ..READ_BUF_SIZE],
+    read_buffer: ~[u8],
     // The current position in the buffer
     read_pos: uint,
     // The last valid position in the reader
     read_max: uint,
-    write_buffer: [u8, ..WRITE_BUF_SIZE],
+    write_buffer: ~[u8],
     write_len: uint,
 
     /// Some things being written may not like flush() being called yet (e.g. explicitly fail!())
@@ -30,19 +37,138 @@ struct BufferedStream<T> {
     call_wrapped_flush: bool,
 
     writing_chunked_body: bool,
+
+    /// Reading gives up, as though at the end of the stream, once it is found to be past this
+    /// time (in `precise_time_ns`); see `set_read_deadline`.
+    read_deadline: Option<u64>,
+
+    /// The minimum rate at which to read, in bytes per second, with the time when measuring it
+    /// began and how many bytes have been read since; see `set_min_read_rate`.
+    min_read_rate: Option<(u64, u64, u64)>,
+
+    /// Whether reading has given up because of the deadline or minimum rate.
+    read_timed_out: bool,
+
+    /// How many more bytes may be read, if there is a limit; see `set_read_limit`.
+    read_limit: Option<uint>,
+
+    /// Whether reading has stopped because of the read limit.
+    read_limit_reached: bool,
 }
 
 impl<T: Stream> BufferedStream<T> {
     pub fn new(stream: T, call_wrapped_flush: bool) -> BufferedStream<T> {
+        BufferedStream::with_capacities(stream, call_wrapped_flush, READ_BUF_SIZE, WRITE_BUF_SIZE)
+    }
+
+    /// Create a `BufferedStream` with read and write buffers of the sizes given, rather than the
+    /// default 64KB each: smaller to save memory when there are many connections, or larger for
+    /// fewer writes to the wrapped stream. Neither may be empty.
+    pub fn with_capacities(stream: T, call_wrapped_flush: bool, read_capacity: uint,
+                           write_capacity: uint) -> BufferedStream<T> {
+        assert!(read_capacity > 0 && write_capacity > 0);
         BufferedStream {
             wrapped: stream,
-            read_buffer: [0u8, ..READ_BUF_SIZE],
+            read_buffer: vec::from_elem(read_capacity, 0u8),
             read_pos: 0u,
             read_max: 0u,
-            write_buffer: [0u8, ..WRITE_BUF_SIZE],
+            write_buffer: vec::from_elem(write_capacity, 0u8),
             write_len: 0u,
             call_wrapped_flush: call_wrapped_flush,
             writing_chunked_body: false,
+            read_deadline: None,
+            min_read_rate: None,
+            read_timed_out: false,
+            read_limit: None,
+            read_limit_reached: false,
+        }
+    }
+
+    /// Take back the wrapped stream, as to go on with it in another protocol. Anything in the
+    /// buffers, read but not yet taken (see `take_read_buffer`) or written but not yet flushed, is
+    /// lost.
+    pub fn unwrap(self) -> T {
+        self.wrapped
+    }
+
+    /// Take what has been read from the wrapped stream but not yet from this, as before
+    /// `unwrap`ping it, so that none of it is lost.
+    pub fn take_read_buffer(&mut self) -> ~[u8] {
+        let buffered = self.read_buffer.slice(self.read_pos, self.read_max).to_owned();
+        self.read_pos = 0;
+        self.read_max = 0;
+        buffered
+    }
+
+    /// Read no more than `limit` more bytes (or with `None`, as many as there are); reading more
+    /// returns nothing, as at the end of the stream, and `read_limit_reached` is then true. This
+    /// bounds how much of something without a length of its own, such as the head of a request,
+    /// will be taken.
+    pub fn set_read_limit(&mut self, limit: Option<uint>) {
+        self.read_limit = limit;
+        self.read_limit_reached = false;
+    }
+
+    /// How many more bytes may be read, as set with `set_read_limit` and counted down since.
+    pub fn read_limit(&self) -> Option<uint> {
+        self.read_limit
+    }
+
+    /// Whether reading has stopped because of the limit set with `set_read_limit`.
+    pub fn read_limit_reached(&self) -> bool {
+        self.read_limit_reached
+    }
+
+    /// Give up reading once it is found, on going to the wrapped stream for more, to be past the
+    /// deadline (a time from `precise_time_ns`), or with `None`, don't. Reading then returns
+    /// nothing, as at the end of the stream, and `read_timed_out` is true.
+    ///
+    /// A read which is blocked can't be interrupted, so this is no use against a client which
+    /// sends nothing; but it does stop one which sends a little at a time from holding the
+    /// connection for as long as it likes.
+    pub fn set_read_deadline(&mut self, deadline: Option<u64>) {
+        self.read_deadline = deadline;
+    }
+
+    /// Give up reading, as for `set_read_deadline`, if it is found, after a grace period of a
+    /// second, to be going more slowly than the rate, in bytes per second, measured from now; or
+    /// with `None`, don't.
+    pub fn set_min_read_rate(&mut self, bytes_per_second: Option<u64>) {
+        self.min_read_rate = match bytes_per_second {
+            Some(rate) => Some((rate, precise_time_ns(), 0)),
+            None => None,
+        };
+    }
+
+    /// Whether reading has given up because of the read deadline or minimum rate.
+    pub fn read_timed_out(&self) -> bool {
+        self.read_timed_out
+    }
+
+    /// Clear the read deadline and minimum rate and whether reading has timed out, ready for the
+    /// next request on the connection.
+    pub fn reset_read_limits(&mut self) {
+        self.read_deadline = None;
+        self.min_read_rate = None;
+        self.read_timed_out = false;
+    }
+
+    /// Whether the read deadline has passed, or reading is going too slowly.
+    fn read_limits_exceeded(&self) -> bool {
+        if self.read_deadline.is_none() && self.min_read_rate.is_none() {
+            return false;
+        }
+        let now = precise_time_ns();
+        match self.read_deadline {
+            Some(deadline) if now > deadline => return true,
+            _ => (),
+        }
+        match self.min_read_rate {
+            Some((rate, start, count)) => {
+                let elapsed = now - start;
+                elapsed > MIN_READ_RATE_GRACE_NS && count * 1_000_000_000 < rate * elapsed
+            },
+            None => false,
         }
     }
 }
@@ -53,6 +179,10 @@ impl<T: Reader> BufferedStream<T> {
     /// filled
     /// Very great caution must be used in calling this as it will fail if `self.pos` is 0.
     pub fn poke_byte(&mut self, byte: u8) {
+        match self.read_limit {
+            Some(n) => self.read_limit = Some(n + 1),
+            None => (),
+        }
         match (self.read_pos, self.read_max) {
             (0, 0) => self.read_max = 1,
             (0, _) => fail!("poke called when buffer is full"),
@@ -64,6 +194,12 @@ impl<T: Reader> BufferedStream<T> {
     #[inline]
     fn fill_buffer(&mut self) -> bool {
         assert_eq!(self.read_pos, self.read_max);
+        if self.read_timed_out || self.read_limits_exceeded() {
+            self.read_timed_out = true;
+            self.read_pos = 0;
+            self.read_max = 0;
+            return false;
+        }
         match self.wrapped.read(self.read_buffer) {
             None => {
                 self.read_pos = 0;
@@ -73,6 +209,11 @@ impl<T: Reader> BufferedStream<T> {
             Some(i) => {
                 self.read_pos = 0;
                 self.read_max = i;
+                match self.min_read_rate {
+                    Some((rate, start, count)) =>
+                        self.min_read_rate = Some((rate, start, count + i as u64)),
+                    None => (),
+                }
                 true
             },
         }
@@ -82,6 +223,14 @@ impl<T: Reader> BufferedStream<T> {
     /// (which just uses `read()`)
     #[inline]
     pub fn read_byte(&mut self) -> Option<u8> {
+        match self.read_limit {
+            Some(0) => {
+                self.read_limit_reached = true;
+                return None;
+            },
+            Some(n) => self.read_limit = Some(n - 1),
+            None => (),
+        }
         if self.read_pos == self.read_max && !self.fill_buffer() {
             // Run out of buffered content, no more to come
             return None;
@@ -97,11 +246,122 @@ impl<T: Writer> BufferedStream<T> {
     ///
     /// At the time of calling this, headers MUST have been written, including the
     /// ending CRLF, or else an invalid HTTP response may be written.
+    ///
+    /// Afterwards the stream is no longer writing a chunked body, ready for the next response on
+    /// the same connection.
     pub fn finish_response(&mut self) {
+        self.finish_response_with_trailer([]);
+    }
+
+    /// Finish off writing a response as `finish_response` does, but in case of chunked
+    /// Transfer-Encoding follow the ending zero-length chunk with the trailer fields given, as
+    /// (name, value) pairs. Without chunked Transfer-Encoding, there is nowhere to put them, and
+    /// they are dropped.
+    pub fn finish_response_with_trailer(&mut self, trailer: &[(~str, ~str)]) {
         self.flush();
         if self.writing_chunked_body {
-            self.wrapped.write(bytes!("0\r\n\r\n"));
+            self.wrapped.write(bytes!("0\r\n"));
+            for &(ref name, ref value) in trailer.iter() {
+                // As with headers, nothing in them can end a line early
+                write_field_bytes(&mut self.wrapped, name.as_bytes());
+                self.wrapped.write(bytes!(": "));
+                write_field_bytes(&mut self.wrapped, value.as_bytes());
+                self.wrapped.write(bytes!("\r\n"));
+            }
+            self.wrapped.write(bytes!("\r\n"));
+            self.writing_chunked_body = false;
+            if self.call_wrapped_flush {
+                self.wrapped.flush();
+            }
+        }
+    }
+
+    /// Write several slices, as though they were one. Small slices are gathered together in the
+    /// write buffer, to go out in as few writes to the wrapped stream as may be; a slice too large
+    /// for the buffer is written straight through, along with whatever was buffered before it,
+    /// without being copied. When writing a chunked body, each write to the wrapped stream is a
+    /// chunk.
+    pub fn write_vectored(&mut self, bufs: &[&[u8]]) {
+        let capacity = self.write_buffer.len();
+        for &buf in bufs.iter() {
+            if buf.len() >= capacity {
+                self.write(buf);
+                continue;
+            }
+            if buf.len() > capacity - self.write_len {
+                self.flush_write_buffer();
+            }
+            vec::bytes::copy_memory(self.write_buffer.mut_slice_from(self.write_len),
+                                    buf, buf.len());
+            self.write_len += buf.len();
+            if self.write_len == capacity {
+                self.flush_write_buffer();
+            }
+        }
+    }
+
+    /// Write up to `length` bytes from the reader, such as a file being served, returning how many
+    /// there were. They are read straight into the write buffer, rather than through another, to
+    /// save copying them. (`send_file` saves reading them at all, where the connection allows.)
+    pub fn write_from<R: Reader>(&mut self, reader: &mut R, length: u64) -> u64 {
+        let mut written = 0u64;
+        while written < length {
+            if self.write_len == self.write_buffer.len() {
+                self.flush_write_buffer();
+            }
+            let len = min((length - written) as uint, self.write_buffer.len() - self.write_len);
+            let start = self.write_len;
+            match reader.read(self.write_buffer.mut_slice(start, start + len)) {
+                Some(n) => {
+                    self.write_len += n;
+                    written += n as u64;
+                },
+                None => break,
+            }
+        }
+        if self.write_len == self.write_buffer.len() {
+            self.flush_write_buffer();
+        }
+        written
+    }
+
+    /// Write the chunk-size line for a chunk of `len` bytes (RFC 2616, section 3.6.1).
+    #[inline]
+    fn write_chunk_size(&mut self, len: uint) {
+        let s = format!("{}\r\n", len.to_str_radix(16));
+        self.wrapped.write(s.as_bytes());
+    }
+
+    /// Write out the contents of the write buffer, framed as a chunk if writing a chunked body.
+    ///
+    /// Nothing is written if the buffer is empty; in the chunked case, that would be the
+    /// zero-length last-chunk, prematurely terminating the body.
+    fn flush_write_buffer(&mut self) {
+        if self.write_len == 0 {
+            return;
+        }
+        if self.writing_chunked_body {
+            self.write_chunk_size(self.write_len);
+        }
+        self.wrapped.write(self.write_buffer.slice_to(self.write_len));
+        if self.writing_chunked_body {
+            self.wrapped.write(bytes!("\r\n"));
+        }
+        self.write_len = 0;
+    }
+}
+
+impl BufferedStream<NetworkStream> {
+    /// Send up to `length` bytes of the file, from `offset`, straight from it to the connection,
+    /// after whatever is in the write buffer, returning how many there were; or `None`, with
+    /// nothing of the file sent, if the connection can't be sent a file so (see
+    /// `NetworkStream.send_file`) or a chunked body is being written, whose chunks it would skip.
+    pub fn send_file(&mut self, file: &RawFile, offset: u64, length: u64) -> Option<u64> {
+        if self.writing_chunked_body {
+            return None;
         }
+        self.flush_write_buffer();
+        self.wrapped.send_file(file, offset, length)
     }
 }
 
@@ -111,13 +371,25 @@ impl<T: Reader> Reader for BufferedStream<T> {
     /// At present, this makes no attempt to fill its buffer proactively, instead waiting until you
     /// ask.
     fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        let limit = match self.read_limit {
+            Some(0) => {
+                self.read_limit_reached = true;
+                return None;
+            },
+            Some(n) => n,
+            None => buf.len(),
+        };
         if self.read_pos == self.read_max && !self.fill_buffer() {
             // Run out of buffered content, no more to come
             return None;
         }
-        let size = min(self.read_max - self.read_pos, buf.len());
+        let size = min(min(self.read_max - self.read_pos, buf.len()), limit);
         vec::bytes::copy_memory(buf, self.read_buffer.slice_from(self.read_pos), size);
         self.read_pos += size;
+        match self.read_limit {
+            Some(n) => self.read_limit = Some(n - size),
+            None => (),
+        }
         Some(size)
     }
 
@@ -130,18 +402,16 @@ impl<T: Reader> Reader for BufferedStream<T> {
 impl<T: Writer> Writer for BufferedStream<T> {
     fn write(&mut self, buf: &[u8]) {
         if buf.len() + self.write_len > self.write_buffer.len() {
-            // This is the lazy approach which may involve multiple writes where it's really not
-            // warranted. Maybe deal with that later.
+            // Too much to buffer, so send the buffered content and `buf` straight on; when writing
+            // a chunked body they go out together as one chunk rather than as two.
             if self.writing_chunked_body {
-                let s = format!("{}\r\n", (self.write_len + buf.len()).to_str_radix(16));
-                self.wrapped.write(s.as_bytes());
+                self.write_chunk_size(self.write_len + buf.len());
             }
             if self.write_len > 0 {
                 self.wrapped.write(self.write_buffer.slice_to(self.write_len));
                 self.write_len = 0;
             }
             self.wrapped.write(buf);
-            self.write_len = 0;
             if self.writing_chunked_body {
                 self.wrapped.write(bytes!("\r\n"));
             }
@@ -151,120 +421,579 @@ impl<T: Writer> Writer for BufferedStream<T> {
 
             self.write_len += buf.len();
             if self.write_len == self.write_buffer.len() {
-                if self.writing_chunked_body {
-                    let s = format!("{}\r\n", self.write_len.to_str_radix(16));
-                    self.wrapped.write(s.as_bytes());
-                    self.wrapped.write(self.write_buffer);
-                    self.wrapped.write(bytes!("\r\n"));
-                } else {
-                    self.wrapped.write(self.write_buffer);
-                }
-                self.write_len = 0;
+                self.flush_write_buffer();
             }
         }
     }
 
     fn flush(&mut self) {
-        if self.write_len > 0 {
-            if self.writing_chunked_body {
-                let s = format!("{}\r\n", self.write_len.to_str_radix(16));
-                self.wrapped.write(s.as_bytes());
-            }
-            self.wrapped.write(self.write_buffer.slice_to(self.write_len));
-            if self.writing_chunked_body {
-                self.wrapped.write(bytes!("\r\n"));
-            }
-            self.write_len = 0;
-        }
+        self.flush_write_buffer();
         if self.call_wrapped_flush {
             self.wrapped.flush();
         }
     }
 }
 
-struct ChunkedReader<'self, R> {
-    reader: &'self mut BufferedStream<R>,
-    // Number of bytes remaining of the current chunk.
-    // This INCLUDES the CRLF at the end of it.
-    // The following guards apply when read() is not being called:
-    // - 0 means no chunk current (possibly with ``self.finished == true``)
-    // - 1 cannot occur
-    // - 2 cannot occur
-    // - N means a chunk of (N - 2) bytes.
-    chunk_size: uint,
-    finished: bool,
+/// The maximum number of hex digits permitted in a chunk-size; enough for the largest `uint` on a
+/// 64-bit platform.
+static MAX_CHUNK_SIZE_DIGITS: uint = 16;
+
+/// The most of a trailer which is kept; any fields beyond this are read, but discarded.
+static MAX_TRAILER_LEN: uint = 0x2000;
+
+/// A reader for a body in the chunked transfer-coding (RFC 2616, section 3.6.1), yielding the
+/// decoded entity-body. Chunk extensions are skipped; the trailer after the last chunk is kept,
+/// to be had from `take_trailer` once the body has been read.
+pub struct ChunkedReader<'self, R> {
+    priv reader: &'self mut BufferedStream<R>,
+    priv decoder: ChunkedDecoder,
 }
 
 impl<'self, R: Reader> ChunkedReader<'self, R> {
     pub fn new(reader: &'self mut BufferedStream<R>) -> ChunkedReader<'self, R> {
         ChunkedReader {
             reader: reader,
-            chunk_size: 0,
-            finished: false,
+            decoder: ChunkedDecoder::new(),
         }
     }
 
-    fn read_chunk_header(&mut self) -> Option<uint> {
-        // 20 is the maximal size of uint on 64-bit platform. I REALLY don't like this way of doing
-        // it. Why am I writing it?
-        match read_uint(self.reader, 19, CR) {
-            Some(n) => {
-                if self.reader.read_byte() == Some(LF) {
-                    Some(n)
-                } else {
-                    None
-                }
-            },
-            None => None,
-        }
+    /// Take the fields of the trailer (see `ChunkedDecoder.take_trailer`).
+    pub fn take_trailer(&mut self) -> ~[(~str, ~str)] {
+        self.decoder.take_trailer()
     }
 }
 
 impl<'self, R: Reader> Reader for ChunkedReader<'self, R> {
     fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        self.decoder.read(&mut *self.reader, buf)
+    }
+
+    fn eof(&mut self) -> bool {
+        self.decoder.is_finished()
+    }
+}
+
+/// The state of decoding a body in the chunked transfer-coding, kept apart from the stream it is
+/// read from so that something which owns the stream can own the decoder too. `ChunkedReader` is
+/// the more convenient interface where a borrowed stream will do.
+pub struct ChunkedDecoder {
+    // Number of bytes of data remaining of the current chunk, not including the CRLF at the end
+    // of it. When read() is not being called, 0 means no chunk current (possibly with
+    // ``self.finished == true``).
+    priv chunk_remaining: uint,
+    priv finished: bool,
+    priv trailer: ~[(~str, ~str)],
+    // How many more bytes of data there may be, if the body is limited in length.
+    priv max_len: Option<uint>,
+    priv too_long: bool,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> ChunkedDecoder {
+        ChunkedDecoder {
+            chunk_remaining: 0,
+            finished: false,
+            trailer: ~[],
+            max_len: None,
+            too_long: false,
+        }
+    }
+
+    /// Decode no more than `max_len` bytes of data altogether (or with `None`, as many as there
+    /// are). Should a chunk go past that, reading stops there, as at the end of the body, and
+    /// `is_too_long` is then true; the rest of the body is left unread.
+    pub fn set_max_len(&mut self, max_len: Option<uint>) {
+        self.max_len = max_len;
+    }
+
+    /// Whether decoding has stopped because the body is longer than `set_max_len` allows.
+    pub fn is_too_long(&self) -> bool {
+        self.too_long
+    }
+
+    /// Take the fields of the trailer which followed the last chunk, as (name, value) pairs in the
+    /// order they came; they are there once the whole body has been read. Names are as they were
+    /// sent; compare them without regard to case.
+    pub fn take_trailer(&mut self) -> ~[(~str, ~str)] {
+        util::replace(&mut self.trailer, ~[])
+    }
+
+    /// Whether the last chunk (and the trailer after it) has been read, or the body turned out to
+    /// be malformed.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Read decoded body data from `reader` into `buf`, as for `Reader.read`.
+    pub fn read<R: Reader>(&mut self, reader: &mut BufferedStream<R>, buf: &mut [u8])
+            -> Option<uint> {
         if self.finished {
             return None;
         }
-        if self.chunk_size == 0 {
-            match self.read_chunk_header() {
-                Some(0) | None => {
+        if self.chunk_remaining == 0 {
+            match read_chunk_header(reader) {
+                Some(0) => {
+                    // last-chunk
+                    self.trailer = read_trailer(reader);
+                    self.finished = true;
+                    return None;
+                },
+                Some(n) => match self.max_len {
+                    Some(max_len) if n > max_len => {
+                        self.too_long = true;
+                        self.finished = true;
+                        return None;
+                    },
+                    Some(max_len) => {
+                        self.max_len = Some(max_len - n);
+                        self.chunk_remaining = n;
+                    },
+                    None => self.chunk_remaining = n,
+                },
+                None => {
+                    // FIXME: raise a condition here.
                     self.finished = true;
                     return None;
                 },
-                Some(n) => {
-                    self.chunk_size = n + 2;
-                }
             }
         }
-        // Now I have a guarantee that self.chunk_size > 2. (The 2 being for the CR LF.)
-        let buf_len = buf.len();
-        let chunk_size_available = self.chunk_size - 2;
-        match self.reader.read(buf.mut_slice_to(min(chunk_size_available, buf_len) + 1)) {
-            Some(bytes_read) if bytes_read == chunk_size_available => {
-                // Read all the chunk. Now ensure the CR LF is there.
-                self.chunk_size = 0;
-                if self.reader.read_byte() != Some(CR) || self.reader.read_byte() == Some(LF) {
+        // Now I have a guarantee that self.chunk_remaining > 0.
+        let len = min(self.chunk_remaining, buf.len());
+        match reader.read(buf.mut_slice_to(len)) {
+            Some(bytes_read) => {
+                self.chunk_remaining -= bytes_read;
+                if self.chunk_remaining == 0 && !read_chunk_end(reader) {
                     // FIXME: raise a condition here.
                     self.finished = true;
-                    None
-                } else {
-                    Some(bytes_read)
                 }
-            },
-            Some(bytes_read) => {
-                // Haven't read all the chunk
-                self.chunk_size -= bytes_read;
                 Some(bytes_read)
             },
             None => {
-                self.finished = true;
                 // FIXME: raise a condition here.
+                self.finished = true;
                 None
             },
         }
     }
+}
 
-    fn eof(&mut self) -> bool {
-        self.finished
+/// Read a chunk-size line, returning the size of the chunk:
+///
+///     chunk-size [ chunk-extension ] CRLF
+///
+/// Chunk extensions are not understood and so are ignored (which is permitted).
+fn read_chunk_header<R: Reader>(reader: &mut BufferedStream<R>) -> Option<uint> {
+    let mut size = 0u;
+    let mut digits = 0u;
+    loop {
+        match reader.read_byte() {
+            Some(b) if is_hex(b) => {
+                if digits == MAX_CHUNK_SIZE_DIGITS {
+                    return None;
+                }
+                size = size * 16 + hex_digit_value(b);
+                digits += 1;
+            },
+            Some(b) if digits > 0 && b == CR => {
+                return if reader.read_byte() == Some(LF) { Some(size) } else { None };
+            },
+            Some(b) if digits > 0 && b == LF => return Some(size),
+            Some(b) if digits > 0 && (b == ';' as u8 || b == SP || b == HT) => break,
+            _ => return None,
+        }
+    }
+    // chunk-extension = *( ";" chunk-ext-name [ "=" chunk-ext-val ] ); skip to the end of line
+    loop {
+        match reader.read_byte() {
+            Some(b) if b == LF => return Some(size),
+            Some(_) => (),
+            None => return None,
+        }
+    }
+}
+
+/// Consume the CRLF which follows the data of each chunk.
+fn read_chunk_end<R: Reader>(reader: &mut BufferedStream<R>) -> bool {
+    match reader.read_byte() {
+        Some(b) if b == CR => reader.read_byte() == Some(LF),
+        Some(b) if b == LF => true,
+        _ => false,
+    }
+}
+
+/// Read the trailer, which follows the last-chunk and ends with an empty line, returning its
+/// fields as (name, value) pairs:
+///
+///     trailer        = *(entity-header CRLF)
+///
+/// Lines which aren't header fields are ignored, as is anything beyond `MAX_TRAILER_LEN`.
+fn read_trailer<R: Reader>(reader: &mut BufferedStream<R>) -> ~[(~str, ~str)] {
+    let mut fields = ~[];
+    let mut line = ~[];
+    let mut line_empty = true;
+    let mut kept = 0u;
+    loop {
+        match reader.read_byte() {
+            Some(b) if b == LF => {
+                if line_empty {
+                    return fields;
+                }
+                add_trailer_line(&mut fields, bytes_to_str(util::replace(&mut line, ~[])));
+                line_empty = true;
+            },
+            Some(b) if b == CR => (),
+            Some(b) => {
+                line_empty = false;
+                if kept < MAX_TRAILER_LEN {
+                    line.push(b);
+                    kept += 1;
+                }
+            },
+            None => return fields,
+        }
+    }
+}
+
+/// Add a line of a trailer to its fields: a new field, or a continuation of the last one.
+fn add_trailer_line(fields: &mut ~[(~str, ~str)], line: ~str) {
+    if line.is_empty() {
+        return;
+    }
+    if (line[0] == SP || line[0] == HT) && !fields.is_empty() {
+        let last = fields.len() - 1;
+        match fields[last] {
+            (_, ref mut value) => {
+                value.push_char(' ');
+                value.push_str(line.trim());
+            },
+        }
+        return;
+    }
+    match line.find(':') {
+        Some(i) => fields.push((line.slice_to(i).trim().to_owned(),
+                                line.slice_from(i + 1).trim().to_owned())),
+        None => (),
+    }
+}
+
+#[inline]
+fn hex_digit_value(b: u8) -> uint {
+    (match b {
+        b if b >= 'a' as u8 => b - 'a' as u8 + 10,
+        b if b >= 'A' as u8 => b - 'A' as u8 + 10,
+        b => b - '0' as u8,
+    }) as uint
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::{Reader, Writer, Decorator};
+    use std::rt::io::extensions::ReaderUtil;
+    use std::rt::io::mem::MemReader;
+    use std::mem::size_of;
+    use extra::time::precise_time_ns;
+    use std::vec;
+    use memstream::{MemReaderFakeStream, MemWriterFakeStream};
+    use super::{BufferedStream, ChunkedReader, ChunkedDecoder};
+
+    fn written(stream: &BufferedStream<MemWriterFakeStream>) -> ~[u8] {
+        (*stream.wrapped).inner_ref().to_owned()
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.write(bytes!("HTTP/1.1 200 OK\r\n\r\n"));
+        stream.flush();
+        stream.writing_chunked_body = true;
+        stream.write(bytes!("Hello"));
+        stream.flush();
+        stream.write(bytes!(", "));
+        stream.write(bytes!("world!"));
+        stream.finish_response();
+        assert!(!stream.writing_chunked_body);
+        let output = written(&stream);
+        assert_eq!(output.as_slice(),
+                   bytes!("HTTP/1.1 200 OK\r\n\r\n5\r\nHello\r\n8\r\n, world!\r\n0\r\n\r\n"));
+    }
+
+    /// Flushing with nothing buffered must not write a zero-length chunk, which would end the body.
+    #[test]
+    fn test_chunked_body_empty_flush() {
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.writing_chunked_body = true;
+        stream.flush();
+        stream.write([]);
+        stream.flush();
+        stream.write(bytes!("foo"));
+        stream.finish_response();
+        let output = written(&stream);
+        assert_eq!(output.as_slice(), bytes!("3\r\nfoo\r\n0\r\n\r\n"));
+    }
+
+    /// Writes too large for the buffer go out, along with anything buffered, as a single chunk.
+    #[test]
+    fn test_chunked_body_large_write() {
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.writing_chunked_body = true;
+        stream.write(bytes!("ab"));
+        let data = vec::from_elem(0x10000, 'c' as u8);
+        stream.write(data);
+        stream.finish_response();
+        let mut expected = bytes!("10002\r\nab").to_owned();
+        expected.push_all(data);
+        expected.push_all(bytes!("\r\n0\r\n\r\n"));
+        let output = written(&stream);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_with_capacities() {
+        let mut stream = BufferedStream::with_capacities(MemWriterFakeStream::new(), false, 4, 4);
+        stream.writing_chunked_body = true;
+        stream.write(bytes!("ab"));
+        stream.write(bytes!("cd"));
+        // The buffer filled up, so that went out as a chunk; this doesn't fit, so goes straight on
+        stream.write(bytes!("e"));
+        stream.write(bytes!("fghij"));
+        stream.finish_response();
+        let output = written(&stream);
+        assert_eq!(output.as_slice(), bytes!("4\r\nabcd\r\n6\r\nefghij\r\n0\r\n\r\n"));
+
+        let input = bytes!("0123456789").to_owned();
+        let mut stream = BufferedStream::with_capacities(MemReaderFakeStream::new(input), false,
+                                                         3, 3);
+        let mut buf = ~[0u8, 0, 0, 0, 0];
+        assert_eq!(stream.read(buf), Some(3));
+        assert_eq!(buf.slice_to(3), bytes!("012"));
+        assert_eq!(stream.read_to_end().as_slice(), bytes!("3456789"));
+    }
+
+    #[test]
+    fn test_write_vectored() {
+        let mut stream = BufferedStream::with_capacities(MemWriterFakeStream::new(), false, 8, 8);
+        stream.writing_chunked_body = true;
+        // Small slices are gathered into chunks as large as the buffer
+        stream.write_vectored([bytes!("abc"), bytes!("def"), bytes!("ghi")]);
+        stream.flush();
+        assert_eq!(written(&stream).as_slice(), bytes!("6\r\nabcdef\r\n3\r\nghi\r\n"));
+        // A large slice goes out with what was buffered before it
+        stream.write_vectored([bytes!("ab"), bytes!("0123456789"), bytes!("cd"), bytes!("")]);
+        stream.finish_response();
+        assert_eq!(written(&stream).slice_from(19),
+                   bytes!("c\r\nab0123456789\r\n2\r\ncd\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_write_from() {
+        let mut stream = BufferedStream::with_capacities(MemWriterFakeStream::new(), false, 4, 4);
+        stream.writing_chunked_body = true;
+        stream.write(bytes!("a"));
+        let mut reader = MemReader::new(bytes!("bcdefgh").to_owned());
+        assert_eq!(stream.write_from(&mut reader, 5), 5);
+        assert_eq!(stream.write_from(&mut reader, 10), 2);
+        stream.finish_response();
+        assert_eq!(written(&stream).as_slice(),
+                   bytes!("4\r\nabcd\r\n4\r\nefgh\r\n0\r\n\r\n"));
+    }
+
+    /// The buffers are on the heap, not in the struct, which is thus cheap to move.
+    #[test]
+    fn test_size() {
+        assert!(size_of::<BufferedStream<()>>() < 128);
+    }
+
+    #[test]
+    fn test_poke_byte() {
+        // Into an empty buffer, before anything has been read
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(bytes!("bc").to_owned()),
+                                             false);
+        stream.poke_byte('a' as u8);
+        assert_eq!(stream.read_byte(), Some('a' as u8));
+        assert_eq!(stream.read_byte(), Some('b' as u8));
+        // Back into the buffer, having just been read
+        stream.poke_byte('b' as u8);
+        assert_eq!(stream.read_to_end().as_slice(), bytes!("bc"));
+    }
+
+    #[test]
+    fn test_take_read_buffer() {
+        let input = bytes!("CONNECT\r\nhello").to_owned();
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input), false);
+        let mut buf = [0u8, ..9];
+        assert_eq!(stream.read(buf), Some(9));
+        assert_eq!(stream.take_read_buffer().as_slice(), bytes!("hello"));
+        assert!(stream.take_read_buffer().is_empty());
+    }
+
+    /// Only a byte which has just been read may be poked back.
+    #[test]
+    #[should_fail]
+    fn test_poke_byte_full() {
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(bytes!("abc").to_owned()),
+                                             false);
+        stream.read_byte();
+        stream.poke_byte('a' as u8);
+        stream.poke_byte('a' as u8);
+    }
+
+    #[test]
+    fn test_read_deadline() {
+        let mut stream = BufferedStream::with_capacities(
+            MemReaderFakeStream::new(bytes!("abcd").to_owned()), false, 2, 2);
+        stream.set_read_deadline(Some(precise_time_ns() + 60_000_000_000));
+        assert_eq!(stream.read_byte(), Some('a' as u8));
+        assert!(!stream.read_timed_out());
+        // What is already buffered may still be read once the deadline has passed
+        stream.set_read_deadline(Some(0));
+        assert_eq!(stream.read_byte(), Some('b' as u8));
+        assert_eq!(stream.read_byte(), None);
+        assert!(stream.read_timed_out());
+        stream.reset_read_limits();
+        assert_eq!(stream.read_byte(), Some('c' as u8));
+    }
+
+    #[test]
+    fn test_read_limit() {
+        let input = bytes!("abcdefgh").to_owned();
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input), false);
+        stream.set_read_limit(Some(3));
+        assert_eq!(stream.read_byte(), Some('a' as u8));
+        stream.poke_byte('a' as u8);
+        let mut buf = ~[0u8, 0, 0, 0, 0];
+        assert_eq!(stream.read(buf), Some(3));
+        assert_eq!(buf.slice_to(3), bytes!("abc"));
+        assert_eq!(stream.read_limit(), Some(0));
+        assert!(!stream.read_limit_reached());
+        assert_eq!(stream.read_byte(), None);
+        assert!(stream.read_limit_reached());
+        stream.set_read_limit(None);
+        assert_eq!(stream.read_to_end().as_slice(), bytes!("defgh"));
+    }
+
+    #[test]
+    fn test_fill_buffer() {
+        let mut stream = BufferedStream::with_capacities(
+            MemReaderFakeStream::new(bytes!("abcde").to_owned()), false, 2, 2);
+        let mut buf = ~[0u8, 0, 0, 0, 0];
+        // Each read gets at most what the buffer holds, filling it as it goes
+        assert_eq!(stream.read(buf), Some(2));
+        assert_eq!(buf.slice_to(2), bytes!("ab"));
+        assert_eq!(stream.read_byte(), Some('c' as u8));
+        assert_eq!(stream.read(buf), Some(1));
+        assert_eq!(buf.slice_to(1), bytes!("d"));
+        assert_eq!(stream.read(buf), Some(1));
+        assert_eq!(buf.slice_to(1), bytes!("e"));
+        assert_eq!(stream.read(buf), None);
+        assert!(stream.eof());
+    }
+
+    #[test]
+    fn test_unchunked_body() {
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.write(bytes!("foo"));
+        stream.write(bytes!("bar"));
+        stream.finish_response();
+        let output = written(&stream);
+        assert_eq!(output.as_slice(), bytes!("foobar"));
+    }
+
+    #[test]
+    fn test_chunked_body_trailer() {
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.writing_chunked_body = true;
+        stream.write(bytes!("foo"));
+        stream.finish_response_with_trailer([(~"Content-MD5", ~"rL0Y20zC+Fzt72VPzMSk2A=="),
+                                             (~"X-Foo", ~"bar")]);
+        let output = written(&stream);
+        assert_eq!(output.as_slice(), bytes!("3\r\nfoo\r\n0\r\n\
+                                              Content-MD5: rL0Y20zC+Fzt72VPzMSk2A==\r\n\
+                                              X-Foo: bar\r\n\r\n"));
+
+        // A field can't end its line early
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.writing_chunked_body = true;
+        stream.finish_response_with_trailer([(~"X-Foo", ~"bar\r\n\r\nbaz")]);
+        let output = written(&stream);
+        assert_eq!(output.as_slice(), bytes!("0\r\nX-Foo: bar    baz\r\n\r\n"));
+
+        // Without chunked, the trailer is dropped
+        let mut stream = BufferedStream::new(MemWriterFakeStream::new(), false);
+        stream.write(bytes!("foo"));
+        stream.finish_response_with_trailer([(~"X-Foo", ~"bar")]);
+        let output = written(&stream);
+        assert_eq!(output.as_slice(), bytes!("foo"));
+    }
+
+    fn read_chunked(input: &[u8]) -> (~[u8], ~[u8]) {
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input.to_owned()), false);
+        let body = ChunkedReader::new(&mut stream).read_to_end();
+        let rest = stream.read_to_end();
+        (body, rest)
+    }
+
+    #[test]
+    fn test_chunked_reader() {
+        let (body, rest) = read_chunked(bytes!("5\r\nHello\r\n8\r\n, world!\r\n0\r\n\r\nnext"));
+        assert_eq!(body.as_slice(), bytes!("Hello, world!"));
+        assert_eq!(rest.as_slice(), bytes!("next"));
+    }
+
+    #[test]
+    fn test_chunked_reader_hex_sizes() {
+        let mut input = bytes!("1A\r\n").to_owned();
+        input.push_all(vec::from_elem(0x1a, 'x' as u8));
+        input.push_all(bytes!("\r\n0\r\n\r\n"));
+        let (body, _) = read_chunked(input);
+        assert_eq!(body, vec::from_elem(0x1a, 'x' as u8));
+    }
+
+    #[test]
+    fn test_chunked_reader_extensions_and_trailer() {
+        let (body, rest) = read_chunked(bytes!("3;name=value\r\nfoo\r\n3 ; bar\r\nbaz\r\n\
+                                               0;last\r\nExpires: never\r\nX-Foo: bar\r\n\r\n\
+                                               next"));
+        assert_eq!(body.as_slice(), bytes!("foobaz"));
+        assert_eq!(rest.as_slice(), bytes!("next"));
+    }
+
+    #[test]
+    fn test_chunked_reader_take_trailer() {
+        let input = bytes!("3\r\nfoo\r\n0\r\nX-Foo: bar\r\nX-Folded: a\r\n  b\r\n\
+                            not a field\r\nX-Empty:\r\n\r\nnext");
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input.to_owned()), false);
+        let mut reader = ChunkedReader::new(&mut stream);
+        assert_eq!(reader.take_trailer(), ~[]);
+        assert_eq!(reader.read_to_end().as_slice(), bytes!("foo"));
+        assert_eq!(reader.take_trailer(),
+                   ~[(~"X-Foo", ~"bar"), (~"X-Folded", ~"a b"), (~"X-Empty", ~"")]);
+        assert_eq!(reader.take_trailer(), ~[]);
+    }
+
+    #[test]
+    fn test_chunked_reader_malformed() {
+        // A chunk-size must have at least one digit
+        let (body, _) = read_chunked(bytes!("\r\nfoo\r\n0\r\n\r\n"));
+        assert_eq!(body.as_slice(), bytes!(""));
+        // Data in the chunk beyond the chunk-size ends the body
+        let (body, _) = read_chunked(bytes!("3\r\nfoobar\r\n0\r\n\r\n"));
+        assert_eq!(body.as_slice(), bytes!("foo"));
+    }
+
+    #[test]
+    fn test_chunked_decoder_max_len() {
+        let input = bytes!("3\r\nfoo\r\n3\r\nbar\r\n3\r\nbaz\r\n0\r\n\r\n").to_owned();
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input), false);
+        let mut decoder = ChunkedDecoder::new();
+        decoder.set_max_len(Some(7));
+        let mut buf = ~[0u8, 0, 0, 0, 0];
+        assert_eq!(decoder.read(&mut stream, buf), Some(3));
+        assert_eq!(decoder.read(&mut stream, buf), Some(3));
+        assert!(!decoder.is_too_long());
+        // The third chunk would go past the limit, and isn't read at all
+        assert_eq!(decoder.read(&mut stream, buf), None);
+        assert!(decoder.is_too_long());
+        assert!(decoder.is_finished());
+        assert_eq!(stream.read_to_end().as_slice(), bytes!("baz\r\n0\r\n\r\n"));
     }
 }
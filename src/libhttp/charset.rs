@@ -0,0 +1,127 @@
+/*!
+
+Decoding of text bodies from the charset which their Content-Type gives, rather than assuming them
+to be UTF-8.
+
+```rust
+let text = match response.read_text() {
+    Some(text) => text,
+    None => fail!("the body isn't text in a charset we know"),
+};
+```
+
+A Content-Type without a `charset` parameter is taken to be in UTF-8, but for `text` media types,
+which RFC 2616 (section 3.7.1) says are in ISO-8859-1. Known are UTF-8, ISO-8859-1 and US-ASCII;
+for others, implement `Decoder` and use `read_text_with` (on `ResponseReader`) or `body_text_with`
+(on `server::Request`), falling back to `StandardDecoder` for the charsets it knows.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::str;
+use headers::content_type::MediaType;
+
+/// A way to decode text from bytes in a charset.
+pub trait Decoder {
+    /// Decode the bytes, in the charset named (in lower case), to a string; `None` if the charset
+    /// isn't one this knows, or the bytes aren't valid in it.
+    fn decode(&self, charset: &str, bytes: &[u8]) -> Option<~str>;
+}
+
+/// The decoder of the charsets which this module knows: UTF-8, ISO-8859-1 and US-ASCII, by their
+/// registered names and aliases.
+pub struct StandardDecoder;
+
+impl Decoder for StandardDecoder {
+    fn decode(&self, charset: &str, bytes: &[u8]) -> Option<~str> {
+        match charset {
+            "utf-8" | "utf8" => if str::is_utf8(bytes) {
+                Some(str::from_utf8(bytes))
+            } else {
+                None
+            },
+            "iso-8859-1" | "iso_8859-1" | "latin1" | "l1" | "iso-ir-100" | "cp819" | "ibm819" => {
+                let mut s = str::with_capacity(bytes.len());
+                for &b in bytes.iter() {
+                    s.push_char(b as char);
+                }
+                Some(s)
+            },
+            "us-ascii" | "ascii" => if bytes.iter().all(|&b| b < 0x80) {
+                Some(str::from_utf8(bytes))
+            } else {
+                None
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The charset of a body with the Content-Type given, in lower case: that of its `charset`
+/// parameter, or failing that, ISO-8859-1 for a `text` media type, and UTF-8 otherwise.
+pub fn charset(content_type: &Option<MediaType>) -> ~str {
+    match *content_type {
+        Some(ref media_type) => match media_type.get_parameter("charset") {
+            Some(charset) => charset.trim().to_ascii_lower(),
+            None if media_type.type_.eq_ignore_ascii_case("text") => ~"iso-8859-1",
+            None => ~"utf-8",
+        },
+        None => ~"utf-8",
+    }
+}
+
+/// Decode a body with the Content-Type given to a string, with the decoder; `None` if its charset
+/// isn't one the decoder knows, or the body isn't valid in it.
+pub fn decode_body<D: Decoder>(content_type: &Option<MediaType>, body: &[u8], decoder: &D)
+        -> Option<~str> {
+    decoder.decode(charset(content_type), body)
+}
+
+#[cfg(test)]
+mod test {
+    use headers::content_type::MediaType;
+    use super::{Decoder, StandardDecoder, charset, decode_body};
+
+    struct Utf16Decoder;
+
+    impl Decoder for Utf16Decoder {
+        fn decode(&self, charset: &str, bytes: &[u8]) -> Option<~str> {
+            match charset {
+                "utf-16be" => {
+                    let mut s = ~"";
+                    let mut i = 0;
+                    while i + 1 < bytes.len() {
+                        s.push_char(bytes[i + 1] as char);
+                        i += 2;
+                    }
+                    Some(s)
+                },
+                _ => StandardDecoder.decode(charset, bytes),
+            }
+        }
+    }
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(charset(&None), ~"utf-8");
+        assert_eq!(charset(&Some(MediaType(~"text", ~"plain", ~[]))), ~"iso-8859-1");
+        assert_eq!(charset(&Some(MediaType(~"application", ~"json", ~[]))), ~"utf-8");
+        assert_eq!(charset(&Some(MediaType(~"text", ~"html", ~[(~"Charset", ~"UTF-8")]))),
+                   ~"utf-8");
+    }
+
+    #[test]
+    fn test_decode_body() {
+        let latin1 = Some(MediaType(~"text", ~"plain", ~[]));
+        assert_eq!(decode_body(&latin1, [0x4a, 0xf6, 0x72, 0x67], &StandardDecoder),
+                   Some(~"Jörg"));
+        let utf8 = Some(MediaType(~"text", ~"plain", ~[(~"charset", ~"utf-8")]));
+        assert_eq!(decode_body(&utf8, "Jörg".as_bytes(), &StandardDecoder), Some(~"Jörg"));
+        assert_eq!(decode_body(&utf8, [0x4a, 0xf6, 0x72, 0x67], &StandardDecoder), None);
+        let ascii = Some(MediaType(~"text", ~"plain", ~[(~"charset", ~"us-ascii")]));
+        assert_eq!(decode_body(&ascii, [0x4a, 0xf6], &StandardDecoder), None);
+        let utf16 = Some(MediaType(~"text", ~"plain", ~[(~"charset", ~"UTF-16BE")]));
+        assert_eq!(decode_body(&utf16, [0, 0x68, 0, 0x69], &StandardDecoder), None);
+        assert_eq!(decode_body(&utf16, [0, 0x68, 0, 0x69], &Utf16Decoder), Some(~"hi"));
+    }
+}
@@ -0,0 +1,241 @@
+/*!
+
+A more convenient way to make a request than building a `RequestWriter` by hand.
+
+```rust
+use http::client::RequestBuilder;
+
+let response = RequestBuilder::get(FromStr::from_str("http://example.com/search").unwrap())
+    .header("Accept", "application/json")
+    .query("q", "rust")
+    .send();
+```
+
+The builder sets the Host header from the URL, and the Content-Length from the body, and `send`
+connects, sends the request and reads the response, just as `RequestWriter.read_response` does,
+redirects and all. Headers given by name are parsed as they would be were they received, so that
+`header("Content-Type", ...)` sets `headers.content_type`; one which can't be parsed is left out,
+and `send` then raises `io_error` rather than sending the request. With `content_md5`, the body is
+sent with its Content-MD5 too, and with `verify_content_md5`, that of the response is checked (see
+`http::content_md5`).
+
+*/
+
+use std::rt::io::Writer;
+use std::rt::io::{io_error, IoError, OtherIoError};
+use std::rt::io::mem::MemReader;
+use extra::url::Url;
+use content_md5::content_md5;
+use headers::header_enum_from_stream;
+use headers::request::Header;
+use method::{Method, Get, Head, Post, Put, Delete};
+use network::NetworkStream;
+use client::request::RequestWriter;
+use client::response::ResponseReader;
+
+/// A request being put together, to be sent with `send`.
+pub struct RequestBuilder {
+    priv request: ~RequestWriter<NetworkStream>,
+    priv body: Option<~[u8]>,
+    /// Whether to send the body's Content-MD5 with it.
+    priv content_md5: bool,
+    /// What was wrong with the first header given which couldn't be parsed, if any.
+    priv header_error: Option<~str>,
+}
+
+impl RequestBuilder {
+    /// A request with the method given for the URL.
+    pub fn new(method: Method, url: Url) -> RequestBuilder {
+        RequestBuilder {
+            request: ~RequestWriter::new(method, url),
+            body: None,
+            content_md5: false,
+            header_error: None,
+        }
+    }
+
+    /// A GET request for the URL.
+    pub fn get(url: Url) -> RequestBuilder {
+        RequestBuilder::new(Get, url)
+    }
+
+    /// A HEAD request for the URL.
+    pub fn head(url: Url) -> RequestBuilder {
+        RequestBuilder::new(Head, url)
+    }
+
+    /// A POST request to the URL.
+    pub fn post(url: Url) -> RequestBuilder {
+        RequestBuilder::new(Post, url)
+    }
+
+    /// A PUT request to the URL.
+    pub fn put(url: Url) -> RequestBuilder {
+        RequestBuilder::new(Put, url)
+    }
+
+    /// A DELETE request for the URL.
+    pub fn delete(url: Url) -> RequestBuilder {
+        RequestBuilder::new(Delete, url)
+    }
+
+    /// Set a header, in place of any which the request already has by that name. If the name
+    /// isn't a token, or the value isn't valid for the header, the header is left out, and `send`
+    /// raises `io_error` instead of sending the request.
+    pub fn header(self, name: &str, value: &str) -> RequestBuilder {
+        let mut builder = self;
+        match parse_header(name, value) {
+            Some(header) => builder.request.headers.insert(header),
+            None => if builder.header_error.is_none() {
+                builder.header_error = Some(format!("{:?} is not a valid value for the {} header",
+                                                    value, name));
+            },
+        }
+        builder
+    }
+
+    /// Add a parameter to the query string of the URL.
+    pub fn query(self, name: &str, value: &str) -> RequestBuilder {
+        let mut builder = self;
+        builder.request.url.query.push((name.to_owned(), value.to_owned()));
+        builder
+    }
+
+    /// Send the body given with the request, with a Content-Length of its length.
+    pub fn body(self, body: ~[u8]) -> RequestBuilder {
+        let mut builder = self;
+        builder.body = Some(body);
+        builder
+    }
+
+    /// Send the Content-MD5 of the body with it, for the server to check it by.
+    pub fn content_md5(self) -> RequestBuilder {
+        let mut builder = self;
+        builder.content_md5 = true;
+        builder
+    }
+
+    /// Check the body of the response against its Content-MD5 (see
+    /// `RequestWriter.verify_content_md5`).
+    pub fn verify_content_md5(self) -> RequestBuilder {
+        let mut builder = self;
+        builder.request.verify_content_md5 = true;
+        builder
+    }
+
+    /// Decompress the body of the response (see `RequestWriter.decompress`).
+    pub fn decompress(self) -> RequestBuilder {
+        let mut builder = self;
+        builder.request.decompress = true;
+        builder
+    }
+
+    /// Follow up to this many redirects (see `RequestWriter.max_redirects`).
+    pub fn max_redirects(self, max_redirects: uint) -> RequestBuilder {
+        let mut builder = self;
+        builder.request.max_redirects = max_redirects;
+        builder
+    }
+
+    /// Make the request through the proxy at the URL given (see `RequestWriter.set_proxy`).
+    pub fn proxy(self, proxy: Url) -> RequestBuilder {
+        let mut builder = self;
+        builder.request.set_proxy(proxy);
+        builder
+    }
+
+    /// The request as it stands, for anything which the builder doesn't do.
+    pub fn request<'a>(&'a mut self) -> &'a mut RequestWriter<NetworkStream> {
+        &mut *self.request
+    }
+
+    /**
+     * Connect, send the request and its body, and read the response.
+     *
+     * If this fails in any way, a condition will be raised; if handled, the request will be
+     * returned as an `Err`, as from `RequestWriter.read_response`. A header which couldn't be
+     * parsed (see `header`) fails it before anything is sent.
+     */
+    pub fn send(self) -> Result<ResponseReader<NetworkStream>, ~RequestWriter<NetworkStream>> {
+        let RequestBuilder { request, body, content_md5: send_content_md5, header_error } = self;
+        match header_error {
+            Some(detail) => {
+                io_error::cond.raise(IoError {
+                    kind: OtherIoError,
+                    desc: "Invalid header given for the request",
+                    detail: Some(detail),
+                });
+                return Err(request);
+            },
+            None => (),
+        }
+        let mut request = request;
+        match body {
+            Some(body) => {
+                request.headers.content_length = Some(body.len());
+                if send_content_md5 {
+                    request.headers.content_md5 = Some(content_md5(body));
+                }
+                request.write(body);
+            },
+            None => (),
+        }
+        request.read_response()
+    }
+}
+
+/// The header with the name and value given, parsed as it would be were it received; `None` if it
+/// can't be.
+fn parse_header(name: &str, value: &str) -> Option<Header> {
+    let line = format!("{}: {}\r\n\r\n", name, value);
+    let mut reader = MemReader::new(line.into_bytes());
+    match header_enum_from_stream(&mut reader) {
+        (Ok(header), _) => Some(header),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rt::io::io_error;
+    use super::RequestBuilder;
+
+    #[test]
+    fn test_builder() {
+        let builder = RequestBuilder::get(FromStr::from_str("http://127.0.0.1/a").unwrap())
+            .header("user-agent", "rust-http")
+            .header("X-Requested-With", "test")
+            .query("q", "x y");
+        assert_eq!(builder.request.headers.user_agent, Some(~"rust-http"));
+        assert_eq!(builder.request.headers.extensions.find(&~"X-Requested-With"), Some(&~"test"));
+        assert_eq!(builder.request.url.query, ~[(~"q", ~"x y")]);
+
+        let builder = RequestBuilder::post(FromStr::from_str("http://127.0.0.1/").unwrap())
+            .content_md5()
+            .verify_content_md5();
+        assert!(builder.content_md5);
+        assert!(builder.request.verify_content_md5);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let builder = RequestBuilder::get(FromStr::from_str("http://127.0.0.1/").unwrap())
+            .header("Content-Length", "twelve")
+            .header("Bad Name", "x")
+            .header("User-Agent", "rust-http");
+        assert_eq!(builder.request.headers.content_length, None);
+        assert_eq!(builder.request.headers.user_agent, Some(~"rust-http"));
+        assert_eq!(builder.header_error,
+                   Some(~"\"twelve\" is not a valid value for the Content-Length header"));
+
+        // Sending it raises an error, and gives the request back, without connecting
+        let builder = Cell::new(builder);
+        let mut error = None;
+        let result = do io_error::cond.trap(|e| error = Some(e.desc)).inside {
+            builder.take().send()
+        };
+        assert!(result.is_err());
+        assert_eq!(error, Some("Invalid header given for the request"));
+    }
+}
@@ -0,0 +1,738 @@
+/*!
+
+Caching of responses, as RFC 7234 describes, so that a resource fetched again is given from the
+cache while it is fresh, and revalidated with the server once it isn't.
+
+```rust
+use http::client::RequestWriter;
+use http::client::cache::{Cache, MemoryStorage};
+use http::method::Get;
+
+let mut cache = Cache::new(MemoryStorage::new());
+for _ in range(0, 2) {
+    let response = cache.fetch(~RequestWriter::new(Get, url.clone())).expect("request failed");
+    // The second time, this has come from the cache, if the server allowed it to be stored.
+    println!("{}: {} bytes", response.status, response.body.len());
+}
+```
+
+Responses to GET requests are stored, whole, if Cache-Control and the status allow it; how long one
+stays fresh is taken from `max-age` (or `s-maxage`, for a shared cache), from Expires or, failing
+those, from a tenth of the time since its Last-Modified, as RFC 7234 (section 4.2.2) suggests. A
+stale response is revalidated, with If-None-Match and If-Modified-Since made from its ETag and
+Last-Modified, and a 304 Not Modified answer updates the stored headers and gives the stored body.
+A successful request of an unsafe method, such as POST, removes what is stored for its URL.
+
+The Cache-Control directives of the request are honoured too, so that a request may insist on a
+response no older than it says (`max-age`), accept one which is stale (`max-stale`), have the
+response revalidated (`no-cache`), or keep it out of the cache (`no-store`). A response given from
+the cache has an Age header; one which is stale, as `max-stale` allows, has a Warning too.
+
+Responses are kept by a `CacheStorage`: `MemoryStorage` holds them in memory, for as long as the
+cache lasts and without limit, and others may keep them elsewhere, such as on disk.
+
+A response with a Vary header is stored with the values which the request had for the headers it
+names, and only given for requests with the same values (white space around them aside); several
+such variants of the response for a URL may be stored at once. One with `Vary: *` is not stored.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::cmp::{max, min};
+use std::hashmap::HashMap;
+use std::rt::io::extensions::ReaderUtil;
+use extra::time::get_time;
+use extra::url::Url;
+use client::request::RequestWriter;
+use client::simple::SimpleResponse;
+use headers;
+use headers::HeaderEnum;
+use headers::cache_control::{CacheControl, NoCache};
+use headers::etag::EntityTags;
+use headers::vary::{Vary, AnyVary, VaryHeaders};
+use method::Get;
+use network::NetworkStream;
+use status;
+use status::Status;
+
+/// The most seconds which an age or freshness lifetime is taken to be; RFC 7234 (section 1.2.1)
+/// says to take larger ones as this.
+static MAX_DELTA_SECONDS: i64 = 2147483648;
+
+/// The longest a response is taken to be fresh for by its Last-Modified alone: a day.
+static MAX_HEURISTIC_LIFETIME: i64 = 24 * 60 * 60;
+
+/// A response as it is stored in the cache.
+#[deriving(Clone)]
+pub struct StoredResponse {
+    /// The status code of the response.
+    code: u16,
+
+    /// The reason phrase of the response.
+    reason: ~str,
+
+    /// The headers of the response, as updated by any revalidations since; Content-Length is
+    /// that of `body`.
+    headers: ~headers::response::HeaderCollection,
+
+    /// The body of the response, with any transfer-coding removed.
+    body: ~[u8],
+
+    /// When the request for the response was sent, in seconds since the epoch.
+    request_time: i64,
+
+    /// When the response was received, in seconds since the epoch.
+    response_time: i64,
+
+    /// The headers which the response's Vary names, in lower case, with the values which the
+    /// request for it had for them, if it had them.
+    selecting_headers: ~[(~str, Option<~str>)],
+}
+
+impl StoredResponse {
+    /// How old the response is at the time `now`, in seconds (RFC 7234, section 4.2.3): the
+    /// longer of how long ago its Date was and what its Age said, corrected for the time it took
+    /// to arrive, and then the time it has been stored for.
+    pub fn current_age(&self, now: i64) -> i64 {
+        let apparent_age = max(0, self.response_time - self.date());
+        let response_delay = self.response_time - self.request_time;
+        let age_value = match self.headers.age {
+            Some(age) => clamp(age),
+            None => 0,
+        };
+        let corrected_initial_age = max(apparent_age, age_value + response_delay);
+        corrected_initial_age + max(0, now - self.response_time)
+    }
+
+    /// How long, from its Date, the response is fresh for, in seconds (RFC 7234, section 4.2.1),
+    /// in a shared cache or otherwise.
+    pub fn freshness_lifetime(&self, shared: bool) -> i64 {
+        let directives = cache_control(&self.headers.cache_control);
+        if shared {
+            match directives.s_maxage() {
+                Some(seconds) => return clamp(seconds),
+                None => (),
+            }
+        }
+        match directives.max_age() {
+            Some(seconds) => return clamp(seconds),
+            None => (),
+        }
+        match self.headers.expires {
+            Some(ref expires) => return max(0, expires.to_timespec().sec - self.date()),
+            None => (),
+        }
+        match self.headers.last_modified {
+            Some(ref last_modified) if directives.is_public() ||
+                                       is_cacheable_by_default(self.code) => {
+                let since = max(0, self.date() - last_modified.to_timespec().sec);
+                min(since / 10, MAX_HEURISTIC_LIFETIME)
+            },
+            _ => 0,
+        }
+    }
+
+    /// Whether the response will do for a request with the headers given, as far as its Vary
+    /// says: whether the request has the same values for the headers which it names.
+    pub fn matches(&self, headers: &headers::request::HeaderCollection) -> bool {
+        match self.headers.vary {
+            Some(AnyVary) => false,
+            _ => self.selecting_headers == selecting_headers(&self.headers.vary, headers),
+        }
+    }
+
+    /// The response's Date, in seconds since the epoch, or if it has none, when it was received.
+    fn date(&self) -> i64 {
+        match self.headers.date {
+            Some(ref date) => date.to_timespec().sec,
+            None => self.response_time,
+        }
+    }
+
+    /// Update the response with a 304 Not Modified which revalidated it (RFC 7234, section
+    /// 4.3.4): its headers replace those stored, but for those describing the body, which the
+    /// 304 doesn't have.
+    pub fn update(&mut self, not_modified: &StoredResponse) {
+        // Those the age is reckoned from must go, even if the 304 doesn't replace them.
+        self.headers.age = not_modified.headers.age;
+        self.headers.date = not_modified.headers.date.clone();
+        for header in not_modified.headers.iter() {
+            match header {
+                headers::response::ContentLength(*) | headers::response::TransferEncoding(*) |
+                headers::response::ContentEncoding(*) | headers::response::Connection(*) => (),
+                header => self.headers.insert(header),
+            }
+        }
+        self.request_time = not_modified.request_time;
+        self.response_time = not_modified.response_time;
+    }
+
+    /// The response as it is, for giving to the client.
+    pub fn to_response(&self) -> SimpleResponse {
+        SimpleResponse {
+            status: Status::from_code_and_reason(self.code, self.reason.clone()),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
+/// A place to keep the responses of a cache, by their keys (the URLs requested). Several may be
+/// kept under a key, as variants with different `selecting_headers`.
+pub trait CacheStorage {
+    /// The responses stored under the key.
+    fn get(&self, key: &str) -> ~[StoredResponse];
+
+    /// Store the response under the key, in place of any stored there before with the same
+    /// `selecting_headers`.
+    fn put(&mut self, key: &str, response: StoredResponse);
+
+    /// Remove all the responses stored under the key.
+    fn remove(&mut self, key: &str);
+}
+
+/// Storage in memory, for as long as it lasts.
+pub struct MemoryStorage {
+    priv responses: HashMap<~str, ~[StoredResponse]>,
+}
+
+impl MemoryStorage {
+    /// Storage with nothing stored.
+    pub fn new() -> MemoryStorage {
+        MemoryStorage { responses: HashMap::new() }
+    }
+
+    /// The number of responses stored, counting each variant.
+    pub fn len(&self) -> uint {
+        let mut len = 0;
+        for (_, variants) in self.responses.iter() {
+            len += variants.len();
+        }
+        len
+    }
+
+    /// Remove all the responses stored.
+    pub fn clear(&mut self) {
+        self.responses.clear();
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn get(&self, key: &str) -> ~[StoredResponse] {
+        match self.responses.find(&key.to_owned()) {
+            Some(variants) => variants.clone(),
+            None => ~[],
+        }
+    }
+
+    fn put(&mut self, key: &str, response: StoredResponse) {
+        let variants = self.responses.find_or_insert_with(key.to_owned(), |_| ~[]);
+        variants.retain(|v| v.selecting_headers != response.selecting_headers);
+        variants.push(response);
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.responses.remove(&key.to_owned());
+    }
+}
+
+/// A cache of responses, kept in the storage given.
+pub struct Cache<S> {
+    priv storage: S,
+
+    /// Whether the cache is shared by several users, as a proxy's is, rather than being a single
+    /// user's own; false by default. A shared cache doesn't store `private` responses, nor, unless
+    /// they say it may, responses to requests with Authorization, and honours `s-maxage` and
+    /// `proxy-revalidate`.
+    shared: bool,
+}
+
+impl<S: CacheStorage> Cache<S> {
+    /// A cache for a single user, kept in the storage given.
+    pub fn new(storage: S) -> Cache<S> {
+        Cache {
+            storage: storage,
+            shared: false,
+        }
+    }
+
+    /// The storage of the cache, as to clear it.
+    pub fn storage<'a>(&'a mut self) -> &'a mut S {
+        &mut self.storage
+    }
+
+    /**
+     * Make the request, unless a stored response will do for it, and give the response read
+     * whole, storing it if it may be.
+     *
+     * The request must not have been sent. If it fails, the `io_error` condition is raised, and
+     * if it is handled, `None` is returned. A request with a Cache-Control of `only-if-cached`
+     * which no stored response will do for is answered with 504 Gateway Timeout, unsent.
+     */
+    pub fn fetch(&mut self, request: ~RequestWriter<NetworkStream>) -> Option<SimpleResponse> {
+        let mut request = request;
+        let key = cache_key(&request.url);
+        if request.method != Get {
+            let invalidates = !request.method.is_safe();
+            return match send(request) {
+                Some(response) => {
+                    if invalidates && response.code < 400 {
+                        self.storage.remove(key.as_slice());
+                    }
+                    Some(response.to_response())
+                },
+                None => None,
+            };
+        }
+
+        let directives = request_directives(request.headers);
+        if directives.no_store() {
+            return match send(request) {
+                Some(response) => Some(response.to_response()),
+                None => None,
+            };
+        }
+
+        let now = get_time().sec;
+        let mut variants = self.storage.get(key.as_slice()).move_iter();
+        let stored = variants.find(|v| v.matches(request.headers));
+        match stored {
+            Some(ref stored) if self.is_usable(stored, &directives, now) => {
+                return Some(self.serve(stored, now));
+            },
+            _ => (),
+        }
+        if directives.only_if_cached() {
+            return Some(SimpleResponse {
+                status: status::GatewayTimeout,
+                headers: ~headers::response::HeaderCollection::new(),
+                body: ~[],
+            });
+        }
+
+        // As they were, to match them to the response's Vary.
+        let request_headers = request.headers.clone();
+        let validating = match stored {
+            Some(ref stored) => add_validators(request.headers, stored),
+            None => false,
+        };
+        let authorized = request.headers.authorization.is_some();
+        let mut response = match send(request) {
+            Some(response) => response,
+            None => return None,
+        };
+
+        if validating && response.code == 304 {
+            let mut stored = stored.unwrap();
+            stored.update(&response);
+            stored.selecting_headers = selecting_headers(&stored.headers.vary, request_headers);
+            if self.is_storable(&directives, authorized, &stored) {
+                self.storage.put(key.as_slice(), stored.clone());
+            } else {
+                self.storage.remove(key.as_slice());
+            }
+            let mut validated = stored.to_response();
+            validated.headers.age = Some(stored.current_age(response.response_time) as u64);
+            return Some(validated);
+        }
+
+        response.selecting_headers = selecting_headers(&response.headers.vary, request_headers);
+        if self.is_storable(&directives, authorized, &response) {
+            self.storage.put(key.as_slice(), response.clone());
+        } else if stored.is_some() {
+            self.storage.remove(key.as_slice());
+        }
+        Some(response.to_response())
+    }
+
+    /// Whether the stored response may be given for a request with the directives given, at the
+    /// time `now`, without revalidating it (RFC 7234, section 4).
+    fn is_usable(&self, stored: &StoredResponse, directives: &CacheControl, now: i64) -> bool {
+        let response_directives = cache_control(&stored.headers.cache_control);
+        if directives.no_cache().is_some() || response_directives.no_cache().is_some() {
+            return false;
+        }
+        let age = stored.current_age(now);
+        let lifetime = stored.freshness_lifetime(self.shared);
+        match directives.max_age() {
+            Some(max_age) if age > clamp(max_age) => return false,
+            _ => (),
+        }
+        match directives.min_fresh() {
+            Some(min_fresh) if lifetime - age < clamp(min_fresh) => return false,
+            _ => (),
+        }
+        if age < lifetime {
+            return true;
+        }
+
+        // Stale, then; only given if the request will have it and the response allows it.
+        if response_directives.must_revalidate() ||
+                (self.shared && (response_directives.proxy_revalidate() ||
+                                 response_directives.s_maxage().is_some())) {
+            return false;
+        }
+        match directives.max_stale() {
+            Some(Some(max_stale)) => age - lifetime <= clamp(max_stale),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Whether the response may be stored, having been given to a request with the directives
+    /// given, which had Authorization or not (RFC 7234, section 3).
+    fn is_storable(&self, directives: &CacheControl, authorized: bool,
+                   response: &StoredResponse) -> bool {
+        let response_directives = cache_control(&response.headers.cache_control);
+        if directives.no_store() || response_directives.no_store() {
+            return false;
+        }
+        if self.shared && response_directives.is_private() {
+            return false;
+        }
+        if self.shared && authorized && !(response_directives.must_revalidate() ||
+                                          response_directives.is_public() ||
+                                          response_directives.s_maxage().is_some()) {
+            return false;
+        }
+        if response.headers.vary == Some(AnyVary) {
+            return false;
+        }
+        let explicit = response.headers.expires.is_some() ||
+                       response_directives.max_age().is_some() ||
+                       (self.shared && response_directives.s_maxage().is_some()) ||
+                       response_directives.is_public();
+        if explicit {
+            // Any status we understand, which for now excludes partial content.
+            response.code >= 200 && response.code != 206 && response.code != 304
+        } else {
+            is_cacheable_by_default(response.code)
+        }
+    }
+
+    /// The stored response, with its Age, and if it is stale, a Warning saying so.
+    fn serve(&self, stored: &StoredResponse, now: i64) -> SimpleResponse {
+        let mut response = stored.to_response();
+        let age = stored.current_age(now);
+        response.headers.age = Some(age as u64);
+        if age >= stored.freshness_lifetime(self.shared) {
+            response.headers.warning = Some(~"110 - \"Response is Stale\"");
+        }
+        response
+    }
+}
+
+/// Make the request and read its response whole.
+fn send(request: ~RequestWriter<NetworkStream>) -> Option<StoredResponse> {
+    let request_time = get_time().sec;
+    let is_get = request.method == Get;
+    match request.read_response() {
+        Ok(response) => {
+            let mut response = response;
+            let response_time = get_time().sec;
+            let body = response.read_to_end();
+            let mut headers = response.headers.clone();
+            if is_get {
+                headers.content_length = Some(body.len());
+                headers.transfer_encoding = None;
+            }
+            Some(StoredResponse {
+                code: response.status.code(),
+                reason: response.status.reason(),
+                headers: headers,
+                body: body,
+                request_time: request_time,
+                response_time: response_time,
+                selecting_headers: ~[],
+            })
+        },
+        Err(_) => None,
+    }
+}
+
+/// The key under which the response for a URL is stored: the URL, without any fragment.
+fn cache_key(url: &Url) -> ~str {
+    let mut url = url.clone();
+    url.fragment = None;
+    url.to_str()
+}
+
+/// The headers which the Vary names, in lower case, with the values which the request has for
+/// them, trimmed, if it has them (RFC 7234, section 4.1).
+fn selecting_headers(vary: &Option<Vary>, headers: &headers::request::HeaderCollection)
+        -> ~[(~str, Option<~str>)] {
+    match *vary {
+        Some(VaryHeaders(ref names)) => names.iter().map(|name| {
+            (name.to_ascii_lower(), header_value(headers, name.as_slice()))
+        }).collect(),
+        _ => ~[],
+    }
+}
+
+/// The value of the request header named (ignoring case), trimmed, if the request has it.
+fn header_value(headers: &headers::request::HeaderCollection, name: &str) -> Option<~str> {
+    for header in headers.iter() {
+        if header.header_name().eq_ignore_ascii_case(name) {
+            return Some(header.header_value().trim().to_owned());
+        }
+    }
+    None
+}
+
+/// The Cache-Control directives of a request, taking `Pragma: no-cache` as `no-cache` where
+/// there is no Cache-Control (RFC 7234, section 5.4).
+fn request_directives(headers: &headers::request::HeaderCollection) -> CacheControl {
+    match (&headers.cache_control, &headers.pragma) {
+        (&Some(ref directives), _) => directives.clone(),
+        (&None, &Some(ref pragma)) if pragma.split_iter(',').any(|p| p.trim() == "no-cache") => {
+            CacheControl(~[NoCache(~[])])
+        },
+        (&None, _) => CacheControl(~[]),
+    }
+}
+
+/// The Cache-Control directives, or none if there is no Cache-Control.
+fn cache_control(directives: &Option<CacheControl>) -> CacheControl {
+    match *directives {
+        Some(ref directives) => directives.clone(),
+        None => CacheControl(~[]),
+    }
+}
+
+/// Make the request conditional on the stored response having changed, if the response has an
+/// ETag or Last-Modified and the request isn't conditional already; whether it was made so.
+fn add_validators(headers: &mut headers::request::HeaderCollection,
+                  stored: &StoredResponse) -> bool {
+    if headers.if_none_match.is_some() || headers.if_modified_since.is_some() {
+        return false;
+    }
+    match stored.headers.etag {
+        Some(ref etag) => headers.if_none_match = Some(EntityTags(~[etag.clone()])),
+        None => (),
+    }
+    match stored.headers.last_modified {
+        Some(ref last_modified) => headers.if_modified_since = Some(last_modified.clone()),
+        None => (),
+    }
+    headers.if_none_match.is_some() || headers.if_modified_since.is_some()
+}
+
+/// Whether a response of the status code given may be stored without saying how long it is
+/// fresh for (RFC 7231, section 6.1), partial content aside.
+fn is_cacheable_by_default(code: u16) -> bool {
+    match code {
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501 => true,
+        _ => false,
+    }
+}
+
+/// Seconds from a header, as no more than `MAX_DELTA_SECONDS`.
+fn clamp(seconds: u64) -> i64 {
+    min(seconds, MAX_DELTA_SECONDS as u64) as i64
+}
+
+#[cfg(test)]
+mod test {
+    use extra::time::{Timespec, at_utc};
+    use headers;
+    use headers::cache_control::{CacheControl, MaxAge, SMaxAge, MaxStale, MinFresh, NoCache,
+                                 NoStore, Private, Public, MustRevalidate};
+    use headers::etag::{EntityTags, strong_etag};
+    use headers::vary::{AnyVary, VaryHeaders};
+    use super::{Cache, CacheStorage, MemoryStorage, StoredResponse, add_validators,
+                request_directives, selecting_headers};
+
+    /// A response received at 1000, Dated 990, taking 2 seconds to come.
+    fn stored(code: u16) -> StoredResponse {
+        let mut headers = ~headers::response::HeaderCollection::new();
+        headers.date = Some(at_utc(Timespec::new(990, 0)));
+        StoredResponse {
+            code: code,
+            reason: ~"",
+            headers: headers,
+            body: ~[1, 2, 3],
+            request_time: 998,
+            response_time: 1000,
+            selecting_headers: ~[],
+        }
+    }
+
+    fn with_directives(code: u16, directives: ~[headers::cache_control::CacheDirective])
+            -> StoredResponse {
+        let mut response = stored(code);
+        response.headers.cache_control = Some(CacheControl(directives));
+        response
+    }
+
+    #[test]
+    fn test_current_age() {
+        let mut response = stored(200);
+        assert_eq!(response.current_age(1000), 10);
+        assert_eq!(response.current_age(1030), 40);
+        response.headers.age = Some(60);
+        assert_eq!(response.current_age(1000), 62);
+        response.headers.date = None;
+        response.headers.age = None;
+        assert_eq!(response.current_age(1005), 7);
+    }
+
+    #[test]
+    fn test_freshness_lifetime() {
+        let response = with_directives(200, ~[MaxAge(60), SMaxAge(10)]);
+        assert_eq!(response.freshness_lifetime(false), 60);
+        assert_eq!(response.freshness_lifetime(true), 10);
+
+        let mut response = stored(200);
+        assert_eq!(response.freshness_lifetime(false), 0);
+        response.headers.last_modified = Some(at_utc(Timespec::new(-9010, 0)));
+        assert_eq!(response.freshness_lifetime(false), 1000);
+        response.headers.last_modified = Some(at_utc(Timespec::new(-10000000, 0)));
+        assert_eq!(response.freshness_lifetime(false), 24 * 60 * 60);
+        response.headers.expires = Some(at_utc(Timespec::new(1020, 0)));
+        assert_eq!(response.freshness_lifetime(false), 30);
+        response.headers.expires = Some(at_utc(Timespec::new(0, 0)));
+        assert_eq!(response.freshness_lifetime(false), 0);
+
+        let mut response = stored(500);
+        response.headers.last_modified = Some(at_utc(Timespec::new(-9010, 0)));
+        assert_eq!(response.freshness_lifetime(false), 0);
+    }
+
+    #[test]
+    fn test_is_usable() {
+        let cache = Cache::new(MemoryStorage::new());
+        let none = CacheControl(~[]);
+        let response = with_directives(200, ~[MaxAge(60)]);
+        assert!(cache.is_usable(&response, &none, 1000));
+        assert!(!cache.is_usable(&response, &none, 1050));
+        assert!(!cache.is_usable(&response, &CacheControl(~[MaxAge(5)]), 1000));
+        assert!(!cache.is_usable(&response, &CacheControl(~[MinFresh(55)]), 1000));
+        assert!(!cache.is_usable(&response, &CacheControl(~[NoCache(~[])]), 1000));
+        assert!(cache.is_usable(&response, &CacheControl(~[MaxStale(Some(20))]), 1060));
+        assert!(!cache.is_usable(&response, &CacheControl(~[MaxStale(Some(20))]), 1080));
+        assert!(cache.is_usable(&response, &CacheControl(~[MaxStale(None)]), 100000));
+
+        let response = with_directives(200, ~[MaxAge(60), MustRevalidate]);
+        assert!(!cache.is_usable(&response, &CacheControl(~[MaxStale(None)]), 1060));
+        let response = with_directives(200, ~[MaxAge(60), NoCache(~[])]);
+        assert!(!cache.is_usable(&response, &none, 1000));
+    }
+
+    #[test]
+    fn test_is_storable() {
+        let mut cache = Cache::new(MemoryStorage::new());
+        let none = CacheControl(~[]);
+        assert!(cache.is_storable(&none, false, &stored(200)));
+        assert!(cache.is_storable(&none, false, &stored(404)));
+        assert!(!cache.is_storable(&none, false, &stored(500)));
+        assert!(cache.is_storable(&none, false, &with_directives(500, ~[MaxAge(5)])));
+        assert!(!cache.is_storable(&none, false, &with_directives(206, ~[MaxAge(5)])));
+        assert!(!cache.is_storable(&CacheControl(~[NoStore]), false, &stored(200)));
+        assert!(!cache.is_storable(&none, false, &with_directives(200, ~[NoStore])));
+        assert!(cache.is_storable(&none, true, &with_directives(200, ~[Private(~[])])));
+
+        cache.shared = true;
+        assert!(!cache.is_storable(&none, false, &with_directives(200, ~[Private(~[])])));
+        assert!(!cache.is_storable(&none, true, &stored(200)));
+        assert!(cache.is_storable(&none, true, &with_directives(200, ~[Public])));
+
+        let mut response = stored(200);
+        response.headers.vary = Some(VaryHeaders(~[~"Accept-Encoding"]));
+        assert!(cache.is_storable(&none, false, &response));
+        response.headers.vary = Some(AnyVary);
+        assert!(!cache.is_storable(&none, false, &response));
+    }
+
+    #[test]
+    fn test_update() {
+        let mut response = with_directives(200, ~[MaxAge(60)]);
+        response.headers.etag = Some(strong_etag("a"));
+        response.headers.content_length = Some(3);
+        response.headers.age = Some(5);
+        let mut not_modified = with_directives(304, ~[MaxAge(120)]);
+        not_modified.headers.date = Some(at_utc(Timespec::new(1990, 0)));
+        not_modified.headers.content_length = Some(0);
+        not_modified.request_time = 2000;
+        not_modified.response_time = 2000;
+        response.update(&not_modified);
+        assert_eq!(response.code, 200);
+        assert_eq!(response.headers.cache_control, Some(CacheControl(~[MaxAge(120)])));
+        assert_eq!(response.headers.etag, Some(strong_etag("a")));
+        assert_eq!(response.headers.content_length, Some(3));
+        assert_eq!(response.headers.age, None);
+        assert_eq!(response.body, ~[1, 2, 3]);
+        assert_eq!(response.current_age(2000), 10);
+    }
+
+    #[test]
+    fn test_add_validators() {
+        let mut headers = headers::request::HeaderCollection::new();
+        assert!(!add_validators(&mut headers, &stored(200)));
+        let mut response = stored(200);
+        response.headers.etag = Some(strong_etag("a"));
+        assert!(add_validators(&mut headers, &response));
+        assert_eq!(headers.if_none_match, Some(EntityTags(~[strong_etag("a")])));
+        assert_eq!(headers.if_modified_since, None);
+        // A request which is conditional already is left as it is.
+        headers.if_none_match = Some(EntityTags(~[strong_etag("b")]));
+        assert!(!add_validators(&mut headers, &response));
+        assert_eq!(headers.if_none_match, Some(EntityTags(~[strong_etag("b")])));
+    }
+
+    #[test]
+    fn test_request_directives() {
+        let mut headers = headers::request::HeaderCollection::new();
+        assert_eq!(request_directives(&headers), CacheControl(~[]));
+        headers.pragma = Some(~"no-cache");
+        assert_eq!(request_directives(&headers), CacheControl(~[NoCache(~[])]));
+        headers.cache_control = Some(CacheControl(~[MaxAge(0)]));
+        assert_eq!(request_directives(&headers), CacheControl(~[MaxAge(0)]));
+    }
+
+    #[test]
+    fn test_selecting_headers() {
+        let mut headers = headers::request::HeaderCollection::new();
+        headers.user_agent = Some(~"test");
+        headers.insert(headers::request::ExtensionHeader(~"X-Variant", ~" b "));
+        let vary = Some(VaryHeaders(~[~"User-Agent", ~"x-variant", ~"Accept-Language"]));
+        assert_eq!(selecting_headers(&vary, &headers),
+                   ~[(~"user-agent", Some(~"test")), (~"x-variant", Some(~"b")),
+                     (~"accept-language", None)]);
+        assert_eq!(selecting_headers(&None, &headers), ~[]);
+        assert_eq!(selecting_headers(&Some(AnyVary), &headers), ~[]);
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut gzip = headers::request::HeaderCollection::new();
+        gzip.insert(headers::request::ExtensionHeader(~"X-Variant", ~"gzip"));
+        let mut plain = headers::request::HeaderCollection::new();
+        plain.insert(headers::request::ExtensionHeader(~"X-Variant", ~"plain"));
+
+        let mut response = stored(200);
+        assert!(response.matches(&gzip) && response.matches(&plain));
+        response.headers.vary = Some(VaryHeaders(~[~"X-Variant"]));
+        response.selecting_headers = selecting_headers(&response.headers.vary, &gzip);
+        assert!(response.matches(&gzip) && !response.matches(&plain));
+        response.headers.vary = Some(AnyVary);
+        assert!(!response.matches(&gzip));
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new();
+        assert!(storage.get("http://example.com/").is_empty());
+        storage.put("http://example.com/", stored(200));
+        storage.put("http://example.com/", stored(404));
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get("http://example.com/")[0].code, 404);
+
+        let mut variant = stored(200);
+        variant.selecting_headers = ~[(~"accept-language", Some(~"fr"))];
+        storage.put("http://example.com/", variant);
+        assert_eq!(storage.len(), 2);
+        storage.remove("http://example.com/");
+        assert!(storage.get("http://example.com/").is_empty());
+        assert_eq!(storage.len(), 0);
+    }
+}
@@ -0,0 +1,339 @@
+/*!
+
+Storage of the cookies set by servers, so that they can be sent back with later requests, as a
+browser would; this is what's needed to script anything behind a login form.
+
+```rust
+use http::client::RequestWriter;
+use http::client::cookie_jar::CookieJar;
+use http::method::Get;
+
+let mut jar = CookieJar::new();
+for url in urls.move_iter() {
+    let mut request = ~RequestWriter::new(Get, url);
+    request.attach_cookies(&mut jar);
+    let response = match request.read_response() {
+        Ok(response) => response,
+        Err(_request) => unreachable!(), // Uncaught condition will have failed first
+    };
+    response.store_cookies(&mut jar);
+}
+```
+
+The storage model is that of RFC 6265, section 5.3, except that no public suffix list is consulted:
+a server may set a cookie for a domain such as `co.uk`, which will then be sent to every host under
+it. Only use a jar with servers you trust.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::i64;
+use extra::sort::merge_sort;
+use extra::time::get_time;
+use extra::url::Url;
+use headers;
+use headers::cookie::{Cookie, CookieList, SetCookieList};
+use idna::UrlHost;
+
+/// A store of cookies received from servers.
+pub struct CookieJar {
+    priv cookies: ~[StoredCookie],
+
+    /// Incremented for each cookie stored, to order cookies by creation.
+    priv next_creation: u64,
+}
+
+/// A cookie as it is stored, its attributes resolved against the URL which set it.
+struct StoredCookie {
+    name: ~str,
+    value: ~str,
+    domain: ~str,
+    /// Whether the cookie is sent only to `domain` itself, and not its subdomains: true when the
+    /// server didn't specify a domain.
+    host_only: bool,
+    path: ~str,
+    secure: bool,
+    /// When the cookie expires, in seconds since the epoch; `None` for a session cookie.
+    expiry: Option<i64>,
+    creation: u64,
+}
+
+impl StoredCookie {
+    fn is_expired(&self, now: i64) -> bool {
+        match self.expiry {
+            Some(expiry) => expiry <= now,
+            None => false,
+        }
+    }
+
+    fn matches(&self, url: &Url, host: &str) -> bool {
+        let domain_ok = if self.host_only {
+            host == self.domain.as_slice()
+        } else {
+            domain_match(host, self.domain)
+        };
+        domain_ok && path_match(request_path(url), self.path)
+                  && (!self.secure || url.scheme == ~"https")
+    }
+}
+
+impl CookieJar {
+    /// Create an empty cookie jar.
+    pub fn new() -> CookieJar {
+        CookieJar {
+            cookies: ~[],
+            next_creation: 0,
+        }
+    }
+
+    /// Store all the cookies set by a response to a request for the given URL.
+    pub fn store_response_cookies(&mut self, url: &Url,
+                                  headers: &headers::response::HeaderCollection) {
+        match headers.set_cookie {
+            Some(SetCookieList(ref cookies)) => {
+                for cookie in cookies.iter() {
+                    self.store(url, cookie);
+                }
+            },
+            None => (),
+        }
+    }
+
+    /// Store a cookie set by a response to a request for the given URL. It replaces any cookie of
+    /// the same name, domain and path; if it has already expired, this amounts to deleting that
+    /// cookie. A cookie which the URL's host may not set is ignored.
+    pub fn store(&mut self, url: &Url, cookie: &Cookie) {
+        let host = url.ascii_host().to_ascii_lower();
+        let now = get_time().sec;
+        let expiry = match (cookie.max_age, &cookie.expires) {
+            (Some(max_age), _) => Some(expiry_after(now, max_age)),
+            (None, &Some(ref tm)) => Some(tm.to_timespec().sec),
+            (None, &None) => None,
+        };
+        let (domain, host_only) = match cookie.domain {
+            Some(ref domain) => {
+                if !domain_match(host.as_slice(), *domain) {
+                    debug!("ignoring cookie {} for {} set by {}", cookie.name, *domain, host);
+                    return;
+                }
+                (domain.clone(), false)
+            },
+            None => (host.clone(), true),
+        };
+        let path = match cookie.path {
+            Some(ref path) => path.clone(),
+            None => default_path(url),
+        };
+
+        let mut creation = self.next_creation;
+        self.next_creation += 1;
+        match self.cookies.iter().position(|c| c.name == cookie.name && c.domain == domain &&
+                                                c.path == path) {
+            Some(i) => {
+                // The replacement keeps the creation time of the cookie it replaces.
+                creation = self.cookies.remove(i).creation;
+            },
+            None => (),
+        }
+
+        let stored = StoredCookie {
+            name: cookie.name.clone(),
+            value: cookie.value.clone(),
+            domain: domain,
+            host_only: host_only,
+            path: path,
+            secure: cookie.secure,
+            expiry: expiry,
+            creation: creation,
+        };
+        if !stored.is_expired(now) {
+            self.cookies.push(stored);
+        }
+    }
+
+    /// The cookies to send with a request for the given URL, in the order in which they should be
+    /// sent: longest paths first, then the oldest first. Expired cookies are discarded.
+    pub fn cookies_for(&mut self, url: &Url) -> ~[Cookie] {
+        let now = get_time().sec;
+        self.cookies.retain(|c| !c.is_expired(now));
+
+        let host = url.ascii_host().to_ascii_lower();
+        let matching: ~[&StoredCookie] = self.cookies.iter()
+                                                     .filter(|c| c.matches(url, host.as_slice()))
+                                                     .collect();
+        let matching = merge_sort(matching, |a, b| {
+            a.path.len() > b.path.len() ||
+                (a.path.len() == b.path.len() && a.creation <= b.creation)
+        });
+        matching.iter().map(|c| Cookie::new(c.name.clone(), c.value.clone())).collect()
+    }
+
+    /// Set the Cookie header of a request for the given URL to the cookies from the jar which
+    /// should be sent with it, if there are any.
+    pub fn add_cookie_header(&mut self, url: &Url,
+                             headers: &mut headers::request::HeaderCollection) {
+        let cookies = self.cookies_for(url);
+        if !cookies.is_empty() {
+            headers.cookie = Some(CookieList(cookies));
+        }
+    }
+
+    /// Discard all session cookies (those without an expiry time), as at the end of a session.
+    pub fn end_session(&mut self) {
+        self.cookies.retain(|c| c.expiry.is_some());
+    }
+
+    /// The number of cookies stored, including any which have expired but not yet been discarded.
+    pub fn len(&self) -> uint {
+        self.cookies.len()
+    }
+}
+
+/// Whether a host matches a cookie domain (RFC 6265, section 5.1.3): it is the domain or a
+/// subdomain of it. Hosts which are IP addresses only match themselves.
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    let is_ip_address = host.iter().all(|c| c == '.' || c == ':' || c.is_digit());
+    !is_ip_address && host.len() > domain.len() && host.ends_with(domain)
+        && host[host.len() - domain.len() - 1] == '.' as u8
+}
+
+/// Whether a request path matches a cookie path (RFC 6265, section 5.1.4): the cookie path is the
+/// request path, or a prefix of it ending at a slash.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with("/") || request_path[cookie_path.len()] == '/' as u8))
+}
+
+fn request_path<'a>(url: &'a Url) -> &'a str {
+    if url.path.is_empty() { "/" } else { url.path.as_slice() }
+}
+
+/// The default path of a cookie set by a response to a request for the URL: its "directory"
+/// (RFC 6265, section 5.1.4).
+fn default_path(url: &Url) -> ~str {
+    let path = request_path(url);
+    if !path.starts_with("/") {
+        return ~"/";
+    }
+    match path.rfind('/') {
+        Some(0) | None => ~"/",
+        Some(i) => path.slice_to(i).to_owned(),
+    }
+}
+
+/// The time, in seconds, `max_age` seconds after `now`: at the latest, the latest time there is
+/// (RFC 6265, section 5.2.2), rather than wrapping round to long ago.
+fn expiry_after(now: i64, max_age: i64) -> i64 {
+    if max_age > 0 && now > i64::max_value - max_age {
+        i64::max_value
+    } else {
+        now + max_age
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use extra::url::Url;
+    use headers::cookie::Cookie;
+    use std::i64;
+    use super::{CookieJar, default_path, domain_match, path_match, expiry_after};
+
+    fn url(s: &str) -> Url {
+        FromStr::from_str(s).unwrap()
+    }
+
+    fn names(cookies: ~[Cookie]) -> ~[~str] {
+        cookies.move_iter().map(|c| c.name).collect()
+    }
+
+    #[test]
+    fn test_matching() {
+        assert!(domain_match("example.com", "example.com"));
+        assert!(domain_match("www.example.com", "example.com"));
+        assert!(!domain_match("wwwexample.com", "example.com"));
+        assert!(!domain_match("example.com", "www.example.com"));
+        assert!(!domain_match("1.2.3.4", "2.3.4"));
+
+        assert!(path_match("/", "/"));
+        assert!(path_match("/foo/bar", "/"));
+        assert!(path_match("/foo/bar", "/foo"));
+        assert!(path_match("/foo/bar", "/foo/"));
+        assert!(!path_match("/foobar", "/foo"));
+        assert!(!path_match("/", "/foo"));
+
+        assert_eq!(default_path(&url("http://example.com")), ~"/");
+        assert_eq!(default_path(&url("http://example.com/login")), ~"/");
+        assert_eq!(default_path(&url("http://example.com/account/login")), ~"/account");
+    }
+
+    #[test]
+    fn test_store_and_send() {
+        let mut jar = CookieJar::new();
+        let login = url("http://www.example.com/account/login");
+
+        jar.store(&login, &Cookie::new(~"host", ~"1"));
+        let mut domain = Cookie::new(~"domain", ~"2");
+        domain.domain = Some(~"example.com");
+        domain.path = Some(~"/");
+        jar.store(&login, &domain);
+        let mut secure = Cookie::new(~"secure", ~"3");
+        secure.secure = true;
+        jar.store(&login, &secure);
+        let mut elsewhere = Cookie::new(~"elsewhere", ~"4");
+        elsewhere.domain = Some(~"example.org");
+        jar.store(&login, &elsewhere);
+        assert_eq!(jar.len(), 3);
+
+        assert_eq!(names(jar.cookies_for(&url("http://www.example.com/account/"))),
+                   ~[~"host", ~"domain"]);
+        assert_eq!(names(jar.cookies_for(&url("https://www.example.com/account/x"))),
+                   ~[~"host", ~"secure", ~"domain"]);
+        assert_eq!(names(jar.cookies_for(&url("http://www.example.com/"))), ~[~"domain"]);
+        assert_eq!(names(jar.cookies_for(&url("http://static.example.com/account/"))),
+                   ~[~"domain"]);
+        assert_eq!(names(jar.cookies_for(&url("http://example.org/"))), ~[]);
+    }
+
+    #[test]
+    fn test_replace_and_expire() {
+        let mut jar = CookieJar::new();
+        let u = url("http://example.com/");
+        jar.store(&u, &Cookie::new(~"a", ~"1"));
+        jar.store(&u, &Cookie::new(~"b", ~"1"));
+        jar.store(&u, &Cookie::new(~"a", ~"2"));
+        // The replacement keeps its place
+        let cookies = jar.cookies_for(&u);
+        assert_eq!(cookies, ~[Cookie::new(~"a", ~"2"), Cookie::new(~"b", ~"1")]);
+
+        let mut expired = Cookie::new(~"a", ~"");
+        expired.max_age = Some(0);
+        jar.store(&u, &expired);
+        assert_eq!(names(jar.cookies_for(&u)), ~[~"b"]);
+
+        let mut persistent = Cookie::new(~"c", ~"3");
+        persistent.max_age = Some(3600);
+        jar.store(&u, &persistent);
+        jar.end_session();
+        assert_eq!(names(jar.cookies_for(&u)), ~[~"c"]);
+
+        // As long as a server may say, without running out of time
+        let mut forever = Cookie::new(~"d", ~"4");
+        forever.max_age = Some(i64::max_value);
+        jar.store(&u, &forever);
+        assert_eq!(names(jar.cookies_for(&u)), ~[~"c", ~"d"]);
+    }
+
+    #[test]
+    fn test_expiry_after() {
+        assert_eq!(expiry_after(1000, 60), 1060);
+        assert_eq!(expiry_after(1000, 0), 1000);
+        assert_eq!(expiry_after(1000, -60), 940);
+        assert_eq!(expiry_after(1000, i64::max_value - 1000), i64::max_value);
+        assert_eq!(expiry_after(1000, i64::max_value), i64::max_value);
+        assert_eq!(expiry_after(1000, i64::min_value), 1000 + i64::min_value);
+    }
+}
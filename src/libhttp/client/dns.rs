@@ -0,0 +1,232 @@
+/*!
+
+Caching of the addresses of hosts, so that many requests to the same host don't each wait on the
+resolver.
+
+```rust
+use http::client::RequestWriter;
+use http::client::dns::DnsCache;
+use http::method::Get;
+
+let mut dns = DnsCache::new();
+for url in urls.move_iter() {
+    let request = ~RequestWriter::new_with_dns_cache(Get, url, &mut dns);
+    let mut response = match request.read_response() {
+        Ok(response) => response,
+        Err(_request) => unreachable!(), // Uncaught condition will have failed first
+    };
+    let body = response.read_to_end();
+}
+```
+
+The resolver doesn't say how long its answers hold, so each is kept for `positive_ttl`; that a host
+couldn't be looked up is kept too, for the shorter `negative_ttl`, so that a host which doesn't
+exist isn't asked about again and again. Should a host move before its entry expires, `forget` or
+`flush` will have it looked up afresh.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::hashmap::HashMap;
+use std::rt::io::net::get_host_addresses;
+use std::rt::io::net::ip::IpAddr;
+use extra::time::precise_time_ns;
+
+/// The default for `DnsCache.positive_ttl`: a minute, in nanoseconds.
+pub static DEFAULT_POSITIVE_TTL: u64 = 60 * 1000 * 1000 * 1000;
+
+/// The default for `DnsCache.negative_ttl`: five seconds, in nanoseconds.
+pub static DEFAULT_NEGATIVE_TTL: u64 = 5 * 1000 * 1000 * 1000;
+
+/// The default for `DnsCache.max_entries`.
+pub static DEFAULT_MAX_ENTRIES: uint = 256;
+
+/// A cache of the addresses of hosts, and of which hosts couldn't be looked up.
+pub struct DnsCache {
+    /// How long, in nanoseconds, the addresses of a host are kept.
+    positive_ttl: u64,
+
+    /// How long, in nanoseconds, the failure to look up a host is kept; when this is 0, failures
+    /// aren't kept at all.
+    negative_ttl: u64,
+
+    /// The most hosts kept at once; when there would be more, that whose entry expires soonest is
+    /// dropped.
+    max_entries: uint,
+
+    priv entries: HashMap<~str, Entry>,
+
+    /// What looks a host up; always `get_host_addresses` but in the tests.
+    priv resolve: fn(&str) -> Option<~[IpAddr]>,
+}
+
+struct Entry {
+    /// The addresses of the host, or `None` if it couldn't be looked up.
+    addrs: Option<~[IpAddr]>,
+    /// When the entry expires, from `precise_time_ns`.
+    expires: u64,
+}
+
+impl DnsCache {
+    /// Create an empty cache with the default limits.
+    pub fn new() -> DnsCache {
+        DnsCache {
+            positive_ttl: DEFAULT_POSITIVE_TTL,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            entries: HashMap::new(),
+            resolve: get_host_addresses,
+        }
+    }
+
+    /// The addresses of the host, as `get_host_addresses` gives them: from the cache if an entry
+    /// for it hasn't expired, or else looked up, and kept.
+    pub fn lookup(&mut self, host: &str) -> Option<~[IpAddr]> {
+        let host = host.to_ascii_lower();
+        let now = precise_time_ns();
+        match self.entries.find(&host) {
+            Some(entry) if entry.expires > now => {
+                debug!("using cached addresses of {}", host);
+                return entry.addrs.clone();
+            },
+            _ => (),
+        }
+
+        let addrs = (self.resolve)(host.as_slice());
+        let ttl = match addrs {
+            Some(_) => self.positive_ttl,
+            None => self.negative_ttl,
+        };
+        if ttl == 0 || self.max_entries == 0 {
+            self.entries.remove(&host);
+            return addrs;
+        }
+        if !self.entries.contains_key(&host) {
+            self.make_room(now);
+        }
+        self.entries.insert(host, Entry {
+            addrs: addrs.clone(),
+            expires: now + ttl,
+        });
+        addrs
+    }
+
+    /// Drop expired entries, and then, if the cache is still full, the entry which expires soonest.
+    fn make_room(&mut self, now: u64) {
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+        let expired: ~[~str] = self.entries.iter().filter(|&(_, entry)| entry.expires <= now)
+                                           .map(|(host, _)| host.clone()).collect();
+        for host in expired.iter() {
+            self.entries.remove(host);
+        }
+        while self.entries.len() >= self.max_entries {
+            let soonest = match self.entries.iter().min_by(|&(_, entry)| entry.expires) {
+                Some((host, _)) => host.clone(),
+                None => return,
+            };
+            self.entries.remove(&soonest);
+        }
+    }
+
+    /// Drop the entry for the host, if there is one, so that it is looked up afresh next time.
+    pub fn forget(&mut self, host: &str) {
+        self.entries.remove(&host.to_ascii_lower());
+    }
+
+    /// The number of hosts with entries, including any which have expired.
+    pub fn len(&self) -> uint {
+        self.entries.len()
+    }
+
+    /// Drop all entries.
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::u64;
+    use std::rt::io::net::ip::{IpAddr, Ipv4Addr};
+    use super::DnsCache;
+
+    fn first(_host: &str) -> Option<~[IpAddr]> {
+        Some(~[Ipv4Addr(10, 0, 0, 1)])
+    }
+
+    fn second(_host: &str) -> Option<~[IpAddr]> {
+        Some(~[Ipv4Addr(10, 0, 0, 2)])
+    }
+
+    fn nowhere(_host: &str) -> Option<~[IpAddr]> {
+        None
+    }
+
+    #[test]
+    fn test_positive_entries() {
+        let mut dns = DnsCache::new();
+        dns.resolve = first;
+        assert_eq!(dns.lookup("example.com"), Some(~[Ipv4Addr(10, 0, 0, 1)]));
+        dns.resolve = second;
+        assert_eq!(dns.lookup("EXAMPLE.com"), Some(~[Ipv4Addr(10, 0, 0, 1)]));
+        assert_eq!(dns.lookup("example.org"), Some(~[Ipv4Addr(10, 0, 0, 2)]));
+        assert_eq!(dns.len(), 2);
+
+        dns.forget("Example.com");
+        assert_eq!(dns.lookup("example.com"), Some(~[Ipv4Addr(10, 0, 0, 2)]));
+        dns.resolve = first;
+        dns.flush();
+        assert_eq!(dns.len(), 0);
+        assert_eq!(dns.lookup("example.com"), Some(~[Ipv4Addr(10, 0, 0, 1)]));
+
+        // An entry which has expired is looked up again
+        dns.resolve = second;
+        for (_, entry) in dns.entries.mut_iter() {
+            entry.expires = 0;
+        }
+        assert_eq!(dns.lookup("example.com"), Some(~[Ipv4Addr(10, 0, 0, 2)]));
+    }
+
+    #[test]
+    fn test_negative_entries() {
+        let mut dns = DnsCache::new();
+        dns.resolve = nowhere;
+        assert_eq!(dns.lookup("example.invalid"), None);
+        dns.resolve = first;
+        assert_eq!(dns.lookup("example.invalid"), None);
+        for (_, entry) in dns.entries.mut_iter() {
+            entry.expires = 0;
+        }
+        assert_eq!(dns.lookup("example.invalid"), Some(~[Ipv4Addr(10, 0, 0, 1)]));
+
+        dns.flush();
+        dns.negative_ttl = 0;
+        dns.resolve = nowhere;
+        assert_eq!(dns.lookup("example.invalid"), None);
+        assert_eq!(dns.len(), 0);
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut dns = DnsCache::new();
+        dns.resolve = first;
+        dns.max_entries = 2;
+        dns.lookup("a.example");
+        dns.lookup("b.example");
+        dns.entries.find_mut(&~"a.example").unwrap().expires = u64::max_value - 1;
+        dns.entries.find_mut(&~"b.example").unwrap().expires = u64::max_value;
+        dns.lookup("c.example");
+        assert_eq!(dns.len(), 2);
+        // Neither had expired, so that which would expire soonest was dropped
+        assert!(!dns.entries.contains_key(&~"a.example"));
+        assert!(dns.entries.contains_key(&~"b.example"));
+
+        // An expired entry is dropped first
+        dns.entries.find_mut(&~"b.example").unwrap().expires = 0;
+        dns.lookup("d.example");
+        assert!(!dns.entries.contains_key(&~"b.example"));
+        assert!(dns.entries.contains_key(&~"c.example"));
+    }
+}
@@ -2,21 +2,41 @@
 
 Modules for making HTTP requests.
 
-At present, requests must be constructed with `RequestWriter`, which does not expose a particularly
-nice-looking API.
+For a quick request, `get`, `post`, `put` and `delete` make it in one call and give back the whole
+response (see `simple`).
 
-In the future there will be a more friendly API for making requests; the Python
-[Requests](http://python-requests.org/) library should be a heavy influence in this.
+Otherwise, requests are most easily made with `RequestBuilder`, which puts together a
+`RequestWriter` (much as the Python [Requests](http://python-requests.org/) library would) and
+sends it; a `RequestWriter` can also be constructed and written to directly, for what the builder
+doesn't do, such as streaming a body.
 
-In the mean time, what there is is not *so* bad.
+Responses are read with `ResponseReader`, which parses the Status-Line and headers and then reads
+the body, decoding the chunked transfer-coding and stopping at the end of the body as RFC 2616
+describes, so that the connection can be reused.
 
-Oh yeah: don't expect to conveniently make any requests which need to send a request body yet. It's
-possible, but it's not elegant convenient yet. (Most notably, no transfer-encodings are supported.)
+A request body is written to the `RequestWriter`, with its Content-Length set beforehand, or sent
+from a `Reader` with `RequestWriter.write_body_from`, chunked if its length isn't known.
+
+The addresses of hosts may be kept by a `dns::DnsCache`, and idle connections by a
+`pool::ConnectionPool`, for later requests to use.
+
+Responses may be kept and given again while they are fresh by a `cache::Cache`, which revalidates
+them with the server once they are stale.
 
 */
 
 pub use self::request::RequestWriter;
+pub use self::builder::RequestBuilder;
+pub use self::simple::{get, post, put, delete, SimpleResponse};
 pub use self::response::ResponseReader;
 
 pub mod request;
+pub mod builder;
+pub mod simple;
 pub mod response;
+pub mod pipeline;
+pub mod pool;
+pub mod dns;
+pub mod cookie_jar;
+pub mod retry;
+pub mod cache;
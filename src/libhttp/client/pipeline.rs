@@ -0,0 +1,268 @@
+/*!
+
+Pipelining of requests: writing several requests on one persistent connection before reading any
+of their responses (RFC 2616, section 8.1.2.2).
+
+```rust
+use http::client::pipeline::Pipeline;
+use http::headers::request::HeaderCollection;
+use http::method::Get;
+
+let mut pipeline = Pipeline::connect(addr).expect("connection failed");
+for url in urls.iter() {
+    pipeline.send(Get, url, ~HeaderCollection::new(), []);
+}
+loop {
+    match pipeline.read_response() {
+        Some(response) => println!("{}: {} bytes", response.status, response.body.len()),
+        None => break,
+    }
+}
+```
+
+Responses come back in the order in which the requests were sent. The server may close the
+connection part way through; when that happens, `read_response` returns `None` while `pending()`
+still counts the requests which did not get a response, and it is up to you to send them again on a
+new connection.
+
+Clients SHOULD NOT pipeline requests using non-idempotent methods such as POST, as they cannot
+safely be retried should the connection be closed before their response arrives.
+
+*/
+
+use extra::url::Url;
+use std::rt::io::{Reader, Writer, Stream};
+use std::rt::io::extensions::ReaderUtil;
+use std::rt::io::net::ip::SocketAddr;
+use std::rt::io::net::tcp::TcpStream;
+use buffer::{BufferedStream, ChunkedReader};
+use headers;
+use headers::host::Host;
+use headers::connection::Close;
+use method::Method;
+use status::Status;
+use client::request::{request_target, host_header_name};
+use client::response::{read_final_response_head, body_framing};
+use client::response::{NoBody, SizedBody, ChunkedBody, CloseDelimitedBody};
+
+/// A persistent connection upon which requests are pipelined.
+pub struct Pipeline<S> {
+    priv stream: BufferedStream<S>,
+
+    /// The methods of the requests which have been sent but not yet responded to, in order. (The
+    /// method is needed to know whether the response has a body.)
+    priv outstanding: ~[Method],
+
+    /// Whether the connection is unusable, the server having closed it or said it would.
+    priv closed: bool,
+}
+
+/// A complete response to a pipelined request, body and all.
+pub struct PipelinedResponse {
+    /// The method of the request which this is a response to.
+    method: Method,
+
+    /// The HTTP version number; typically `(1, 1)` or, less commonly, `(1, 0)`.
+    version: (uint, uint),
+
+    /// The HTTP status indicated in the response.
+    status: Status,
+
+    /// The headers received in the response.
+    headers: ~headers::response::HeaderCollection,
+
+    /// The body of the response, with any transfer-coding removed.
+    body: ~[u8],
+}
+
+impl Pipeline<TcpStream> {
+    /// Connect to the specified address; `None` (with the `io_error` condition raised) is returned
+    /// if the connection fails.
+    pub fn connect(addr: SocketAddr) -> Option<Pipeline<TcpStream>> {
+        match TcpStream::connect(addr) {
+            Some(stream) => Some(Pipeline::new(stream)),
+            None => None,
+        }
+    }
+}
+
+impl<S: Stream> Pipeline<S> {
+    /// Start pipelining requests on an already-open connection.
+    pub fn new(stream: S) -> Pipeline<S> {
+        Pipeline {
+            stream: BufferedStream::new(stream, false),
+            outstanding: ~[],
+            closed: false,
+        }
+    }
+
+    /// The number of requests sent for which no response has yet been read.
+    pub fn pending(&self) -> uint {
+        self.outstanding.len()
+    }
+
+    /// Queue a request, writing it to the connection.
+    ///
+    /// The Host header will be filled in from the URL and the Content-Length from the body if they
+    /// have not been set. The request is buffered, and will not necessarily have been sent until
+    /// `flush` or `read_response` is called.
+    pub fn send(&mut self, method: Method, url: &Url,
+                mut headers: ~headers::request::HeaderCollection, body: &[u8]) {
+        if headers.host.is_none() {
+            headers.host = Some(Host {
+                name: host_header_name(url),
+                port: match url.port {
+                    Some(ref port) => FromStr::from_str(*port),
+                    None => None,
+                },
+            });
+        }
+        if headers.content_length.is_none() && body.len() > 0 {
+            headers.content_length = Some(body.len());
+        }
+
+        // Pipelining depends on persistent connections, which are the default for HTTP/1.1.
+        let s = format!("{} {} HTTP/1.1\r\n", method.to_str(), request_target(url));
+        self.stream.write(s.as_bytes());
+        headers.write_all(&mut self.stream);
+        self.stream.write(body);
+        self.outstanding.push(method);
+    }
+
+    /// Read the response to the oldest outstanding request.
+    ///
+    /// Any requests queued are sent first. `None` is returned if there are no outstanding requests
+    /// or the connection has been closed; a malformed response raises the `io_error` condition and
+    /// closes the pipeline.
+    pub fn read_response(&mut self) -> Option<PipelinedResponse> {
+        if self.closed || self.outstanding.is_empty() {
+            return None;
+        }
+        self.flush();
+
+        let (version, status, headers) = match read_final_response_head(&mut self.stream) {
+            Some(head) => head,
+            None => {
+                self.closed = true;
+                return None;
+            },
+        };
+        let method = self.outstanding.shift();
+
+        let body = match body_framing(&method, &status, headers) {
+            NoBody => ~[],
+            SizedBody(length) => self.stream.read_bytes(length),
+            ChunkedBody(_) => ChunkedReader::new(&mut self.stream).read_to_end(),
+            CloseDelimitedBody => {
+                // Nothing can follow a body delimited by the server closing the connection.
+                self.closed = true;
+                self.stream.read_to_end()
+            },
+        };
+
+        let server_closing = match headers.connection {
+            Some(ref tokens) => tokens.iter().any(|t| *t == Close),
+            None => version < (1, 1),
+        };
+        if server_closing {
+            self.closed = true;
+        }
+
+        Some(PipelinedResponse {
+            method: method,
+            version: version,
+            status: status,
+            headers: headers,
+            body: body,
+        })
+    }
+
+    /// Send all queued requests.
+    pub fn flush(&mut self) {
+        self.stream.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::{Reader, Writer};
+    use std::rt::io::mem::MemReader;
+    use headers::request::HeaderCollection;
+    use method::{Get, Head};
+    use status;
+    use super::Pipeline;
+
+    /// A stream which discards what is written to it and reads canned responses.
+    struct CannedStream {
+        responses: MemReader,
+        written: uint,
+    }
+
+    impl Reader for CannedStream {
+        fn read(&mut self, buf: &mut [u8]) -> Option<uint> { self.responses.read(buf) }
+        fn eof(&mut self) -> bool { self.responses.eof() }
+    }
+
+    impl Writer for CannedStream {
+        fn write(&mut self, buf: &[u8]) { self.written += buf.len() }
+        fn flush(&mut self) { }
+    }
+
+    fn pipeline(responses: &[u8]) -> Pipeline<CannedStream> {
+        Pipeline::new(CannedStream { responses: MemReader::new(responses.to_owned()), written: 0 })
+    }
+
+    #[test]
+    fn test_responses_in_order() {
+        let mut p = pipeline(bytes!("\
+HTTP/1.1 200 OK\r\n\
+Content-Length: 3\r\n\
+\r\n\
+oneHTTP/1.1 100 Continue\r\n\
+\r\n\
+HTTP/1.1 404 Not Found\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+3\r\ntwo\r\n0\r\n\r\n\
+HTTP/1.1 200 OK\r\n\
+Content-Length: 5\r\n\
+\r\n"));
+        let url = FromStr::from_str("http://example.com/").unwrap();
+        p.send(Get, &url, ~HeaderCollection::new(), []);
+        p.send(Get, &url, ~HeaderCollection::new(), []);
+        p.send(Head, &url, ~HeaderCollection::new(), []);
+        assert_eq!(p.pending(), 3);
+
+        let response = p.read_response().unwrap();
+        assert_eq!(response.status, status::Ok);
+        assert_eq!(response.body.as_slice(), bytes!("one"));
+
+        let response = p.read_response().unwrap();
+        assert_eq!(response.status, status::NotFound);
+        assert_eq!(response.body.as_slice(), bytes!("two"));
+
+        // A response to HEAD has no body, whatever its Content-Length says
+        let response = p.read_response().unwrap();
+        assert_eq!(response.method, Head);
+        assert_eq!(response.body.as_slice(), bytes!(""));
+
+        assert_eq!(p.pending(), 0);
+        assert!(p.read_response().is_none());
+    }
+
+    #[test]
+    fn test_server_closing() {
+        let mut p = pipeline(bytes!("\
+HTTP/1.1 200 OK\r\n\
+Connection: close\r\n\
+Content-Length: 3\r\n\
+\r\n\
+one"));
+        let url = FromStr::from_str("http://example.com/").unwrap();
+        p.send(Get, &url, ~HeaderCollection::new(), []);
+        p.send(Get, &url, ~HeaderCollection::new(), []);
+        assert_eq!(p.read_response().unwrap().body.as_slice(), bytes!("one"));
+        assert!(p.read_response().is_none());
+        assert_eq!(p.pending(), 1);
+    }
+}
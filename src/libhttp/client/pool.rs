@@ -0,0 +1,207 @@
+/*!
+
+Pooling of idle persistent connections, so that many requests to the same origin don't each pay
+for a new connection (and, for HTTPS, a new TLS handshake).
+
+```rust
+use http::client::RequestWriter;
+use http::client::pool::ConnectionPool;
+use http::method::Get;
+
+let mut pool = ConnectionPool::new();
+for url in urls.move_iter() {
+    let mut request = ~RequestWriter::new(Get, url);
+    request.connect_pooled(&mut pool);
+    let mut response = match request.read_response() {
+        Ok(response) => response,
+        Err(_request) => unreachable!(), // Uncaught condition will have failed first
+    };
+    let body = response.read_to_end();
+    // Hand the connection back for the next request to use
+    response.release(&mut pool);
+}
+```
+
+Connections are keyed by the scheme, host and port of the URL they were made for. A connection
+which has been idle too long is considered stale, as the server has quite possibly closed it, and
+is discarded rather than reused. Even a connection which isn't stale by that measure may have been
+closed by the server, in which case the request sent on it will fail and must be retried.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::hashmap::HashMap;
+use extra::time::precise_time_ns;
+use extra::url::Url;
+use buffer::BufferedStream;
+use client::request::url_port;
+use idna::UrlHost;
+
+/// The default for `ConnectionPool.max_idle_per_origin`.
+pub static DEFAULT_MAX_IDLE_PER_ORIGIN: uint = 4;
+
+/// The default for `ConnectionPool.max_idle_time`: 30 seconds, in nanoseconds.
+pub static DEFAULT_MAX_IDLE_TIME: u64 = 30 * 1000 * 1000 * 1000;
+
+/// A scheme, host and port.
+type Origin = (~str, ~str, u16);
+
+/// A cache of idle connections, to be reused for later requests to the same origin.
+pub struct ConnectionPool<S> {
+    /// The most idle connections kept for any one origin; when there are more than this, the
+    /// connection which has been idle longest is closed.
+    max_idle_per_origin: uint,
+
+    /// How long, in nanoseconds, a connection may remain idle and still be reused.
+    max_idle_time: u64,
+
+    priv idle: HashMap<Origin, ~[IdleConnection<S>]>,
+}
+
+struct IdleConnection<S> {
+    stream: BufferedStream<S>,
+    /// When the connection was returned to the pool, from `precise_time_ns`.
+    idle_since: u64,
+}
+
+impl<S> ConnectionPool<S> {
+    /// Create an empty pool with the default limits.
+    pub fn new() -> ConnectionPool<S> {
+        ConnectionPool {
+            max_idle_per_origin: DEFAULT_MAX_IDLE_PER_ORIGIN,
+            max_idle_time: DEFAULT_MAX_IDLE_TIME,
+            idle: HashMap::new(),
+        }
+    }
+
+    /// Take an idle connection for the origin of the URL out of the pool, if there is one which
+    /// isn't stale. Stale connections found along the way are closed.
+    pub fn take(&mut self, url: &Url) -> Option<BufferedStream<S>> {
+        let origin = match url_origin(url) {
+            Some(origin) => origin,
+            None => return None,
+        };
+        let now = precise_time_ns();
+        let max_idle_time = self.max_idle_time;
+        match self.idle.find_mut(&origin) {
+            None => None,
+            Some(connections) => {
+                // The most recently used connection is the one least likely to have been closed.
+                while !connections.is_empty() {
+                    let connection = connections.pop();
+                    if now - connection.idle_since <= max_idle_time {
+                        return Some(connection.stream);
+                    }
+                    debug!("discarding stale connection to {:?}", origin);
+                }
+                None
+            },
+        }
+    }
+
+    /// Put a connection, which must be ready for another request to be sent on it, into the pool
+    /// for reuse by requests to the origin of the URL.
+    pub fn put(&mut self, url: &Url, stream: BufferedStream<S>) {
+        let origin = match url_origin(url) {
+            Some(origin) => origin,
+            None => return,
+        };
+        if self.max_idle_per_origin == 0 {
+            return;
+        }
+        let max_idle_per_origin = self.max_idle_per_origin;
+        let connections = self.idle.find_or_insert_with(origin, |_| ~[]);
+        while connections.len() >= max_idle_per_origin {
+            connections.shift();
+        }
+        connections.push(IdleConnection {
+            stream: stream,
+            idle_since: precise_time_ns(),
+        });
+    }
+
+    /// The number of idle connections held for the origin of the URL, including any stale ones.
+    pub fn idle_count(&self, url: &Url) -> uint {
+        match url_origin(url) {
+            Some(origin) => match self.idle.find(&origin) {
+                Some(connections) => connections.len(),
+                None => 0,
+            },
+            None => 0,
+        }
+    }
+
+    /// Close all idle connections.
+    pub fn clear(&mut self) {
+        self.idle.clear();
+    }
+}
+
+fn url_origin(url: &Url) -> Option<Origin> {
+    match url_port(url) {
+        Some(port) => Some((url.scheme.clone(), url.ascii_host().to_ascii_lower(), port)),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use extra::url::Url;
+    use buffer::BufferedStream;
+    use memstream::MemReaderFakeStream;
+    use super::ConnectionPool;
+
+    fn stream(id: u8) -> BufferedStream<MemReaderFakeStream> {
+        BufferedStream::new(MemReaderFakeStream::new(~[id]), false)
+    }
+
+    fn url(s: &str) -> Url {
+        FromStr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_reuse_per_origin() {
+        let mut pool = ConnectionPool::new();
+        pool.put(&url("http://example.com/a"), stream(1));
+        assert_eq!(pool.idle_count(&url("http://EXAMPLE.com:80/b")), 1);
+        assert!(pool.take(&url("https://example.com/")).is_none());
+        assert!(pool.take(&url("http://example.com:8001/")).is_none());
+        assert!(pool.take(&url("http://example.org/")).is_none());
+        assert_eq!(pool.take(&url("http://example.com/c")).unwrap().read_byte(), Some(1));
+        assert!(pool.take(&url("http://example.com/c")).is_none());
+    }
+
+    #[test]
+    fn test_max_idle_per_origin() {
+        let mut pool = ConnectionPool::new();
+        pool.max_idle_per_origin = 2;
+        let u = url("http://example.com/");
+        pool.put(&u, stream(1));
+        pool.put(&u, stream(2));
+        pool.put(&u, stream(3));
+        assert_eq!(pool.idle_count(&u), 2);
+        // Most recently used first; the oldest was evicted
+        assert_eq!(pool.take(&u).unwrap().read_byte(), Some(3));
+        assert_eq!(pool.take(&u).unwrap().read_byte(), Some(2));
+        assert!(pool.take(&u).is_none());
+
+        pool.max_idle_per_origin = 0;
+        pool.put(&u, stream(4));
+        assert_eq!(pool.idle_count(&u), 0);
+    }
+
+    #[test]
+    fn test_stale_connections_discarded() {
+        let mut pool = ConnectionPool::new();
+        let u = url("http://example.com/");
+        pool.put(&u, stream(1));
+        pool.max_idle_time = 0;
+        for (_, connections) in pool.idle.mut_iter() {
+            for connection in connections.mut_iter() {
+                connection.idle_since = 0;
+            }
+        }
+        assert!(pool.take(&u).is_none());
+        assert_eq!(pool.idle_count(&u), 0);
+    }
+}
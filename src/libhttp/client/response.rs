@@ -1,19 +1,49 @@
-use std::rt::io::{Reader, Stream};
+use std::cmp::min;
+use std::vec;
+use std::rt::io::{Reader, Writer, Stream};
 use std::rt::io::extensions::ReaderUtil;
 use std::rt::io::{io_error, OtherIoError, IoError};
-use client::request::RequestWriter;
+use std::rt::io::mem::MemReader;
+use extra::crypto::digest::Digest;
+use extra::crypto::md5::Md5;
+use extra::url::Url;
+use client::request::{RequestWriter, timeout_err};
 use rfc2616::{CR, LF, SP};
 use common::read_http_version;
+use charset::{Decoder, StandardDecoder, decode_body};
+use compression;
+use compression::Coding;
+use content_md5;
 use headers;
+use headers::connection::{Close, Token};
+use headers::cookie::SetCookieList;
+use headers::transfer_encoding::Chunked;
+use method::{Method, Head};
 use status::Status;
 
-use buffer::BufferedStream;
+use buffer::{BufferedStream, ChunkedDecoder};
+use client::pool::ConnectionPool;
+use client::cookie_jar::CookieJar;
 use server::request::{RequestBuffer};
 use headers::{EndOfFile, EndOfHeaders, MalformedHeaderSyntax, MalformedHeaderValue};
 
-struct ResponseReader<S> {
+/// A response read from a server: the Status-Line and headers, parsed, and then as a `Reader`
+/// the body, with any transfer-coding removed. Reading stops at the end of the body, so the
+/// connection is left ready for another response.
+///
+/// Nothing of the body is read until it is asked for, so a body too large to hold in memory can be
+/// read a piece at a time, or written straight to a file with `write_body_to`; but a body which is
+/// to be decompressed (see `RequestWriter.decompress`) is read whole, as soon as the headers are.
+///
+/// A body which is to be checked against its Content-MD5 (see `RequestWriter.verify_content_md5`)
+/// is checked once it has been read to its end, before any decompression; should it not match,
+/// `io_error` is raised with `content_md5::mismatch_err()`.
+pub struct ResponseReader<S> {
     priv stream: BufferedStream<S>,
 
+    /// How the end of the body will be recognised, and how far through it we are.
+    priv body: BodyFraming,
+
     /// The request which this is a response to
     request: ~RequestWriter<S>,
 
@@ -25,8 +55,21 @@ struct ResponseReader<S> {
 
     /// The headers received in the response.
     headers: ~headers::response::HeaderCollection,
+
+    /// The decompressed body, if the request asked for it to be decompressed and it was.
+    priv decompressed: Option<MemReader>,
+
+    /// The digest of the body read so far, while it is to be checked against its Content-MD5.
+    priv md5: Option<Md5>,
+
+    /// The URLs redirected from, in order, before this response was had (see
+    /// `RequestWriter.max_redirects`); `request` is that for the last redirect's location.
+    redirects: ~[Url],
 }
 
+/// The size of the buffer with which `write_body_to` copies the body.
+static COPY_BUF_SIZE: uint = 0x10000;
+
 fn bad_response_err() -> IoError {
     // TODO: IoError isn't right
     IoError {
@@ -43,112 +86,529 @@ impl<S: Stream> ResponseReader<S> {
         //let mut b = [0u8, ..4096];
         //let len = stream.read(b);
         //println!("{}", ::std::str::from_bytes(b.slice_to(len.unwrap())));
-        let http_version = match read_http_version(&mut stream, SP) {
-            Some(nums) => nums,
-            None => {
-                io_error::cond.raise(bad_response_err());
-                return Err(request);
-            }
+
+        // Running out of time looks to the parsing like the response ending early; trap the error
+        // which it then raises, so as to raise the right error instead.
+        stream.set_read_deadline(request.first_byte_deadline());
+        let mut error = None;
+        let head = do io_error::cond.trap(|e| error = Some(e)).inside {
+            read_final_response_head(&mut stream)
         };
+        stream.set_read_deadline(request.total_deadline());
+        if stream.read_timed_out() {
+            io_error::cond.raise(timeout_err("waiting for the response"));
+        } else {
+            match error {
+                Some(e) => io_error::cond.raise(e),
+                None => (),
+            }
+        }
+        match head {
+            Some((http_version, status, headers)) => {
+                let body = body_framing(&request.method, &status, headers);
+                let mut response = ResponseReader {
+                    stream: stream,
+                    body: body,
+                    request: request,
+                    version: http_version,
+                    status: status,
+                    headers: headers,
+                    decompressed: None,
+                    md5: None,
+                    redirects: ~[],
+                };
+                if response.request.verify_content_md5 && response.headers.content_md5.is_some() {
+                    match response.body {
+                        NoBody => (),
+                        _ => response.md5 = Some(Md5::new()),
+                    }
+                }
+                if response.request.decompress {
+                    response.decompress_body();
+                }
+                Ok(response)
+            },
+            None => Err(request),
+        }
+    }
 
-        // Read the status code
-        let mut digits = 0u8;
-        let mut status_code = 0u16;
+    /// Read and decompress the body, if it is compressed in a content-coding which we know,
+    /// removing the Content-Encoding header. Should it prove not to be in that coding after all,
+    /// or be longer, compressed or decompressed, than `RequestWriter.max_decompressed_size`, the
+    /// `io_error` condition is raised and, if it is handled, the body is left empty.
+    fn decompress_body(&mut self) {
+        let coding = match self.headers.content_encoding {
+            Some(ref name) => match Coding::from_name(name.trim()) {
+                Some(coding) => coding,
+                None => return,
+            },
+            None => return,
+        };
+        match self.body {
+            NoBody => return,
+            _ => (),
+        }
+        let max_len = self.request.max_decompressed_size;
+        let mut compressed = ~[];
+        let mut buf = vec::from_elem(COPY_BUF_SIZE, 0u8);
+        let mut too_long = false;
         loop {
-            if digits == 4u8 {
-                // Status code must be three digits long
-                io_error::cond.raise(bad_response_err());
-                return Err(request);
-            }
-            match stream.read_byte() {
-                Some(b) if b >= '0' as u8 && b <= '9' as u8 => {
-                    status_code = status_code * 10 + b as u16 - '0' as u16;
+            match self.read(buf) {
+                Some(n) if compressed.len() + n > max_len => {
+                    too_long = true;
+                    break;
                 },
-                Some(b) if b == SP => break,
-                _ => {
-                    io_error::cond.raise(bad_response_err());
-                    return Err(request);
-                }
+                Some(n) => compressed.push_all(buf.slice_to(n)),
+                None => break,
             }
-            digits += 1;
         }
+        let decompressed = if too_long {
+            Err(compression::TooLong)
+        } else {
+            compression::decompress(coding, compressed, max_len)
+        };
+        let body = match decompressed {
+            Ok(body) => body,
+            Err(e) => {
+                io_error::cond.raise(IoError {
+                    kind: OtherIoError,
+                    desc: match e {
+                        compression::CorruptData =>
+                            "Server sent a compressed body which could not be decompressed",
+                        compression::TooLong =>
+                            "Server sent a compressed body which was too long to decompress",
+                    },
+                    detail: None,
+                });
+                ~[]
+            },
+        };
+        self.headers.content_encoding = None;
+        self.headers.content_length = Some(body.len());
+        self.decompressed = Some(MemReader::new(body));
+    }
 
-        // Read the status reason
-        let mut reason = ~"";
-        loop {
-            match stream.read_byte() {
-                Some(b) if b == CR => {
-                    if stream.read_byte() == Some(LF) {
-                        break;
-                    } else {
-                        // Response-Line has CR without LF. Not yet resilient; TODO.
-                        io_error::cond.raise(bad_response_err());
-                        return Err(request);
-                    }
-                }
-                Some(b) => {
-                    reason.push_char(b as char);
+    /// Read from the body on the connection, as it is framed.
+    fn read_body(&mut self, buf: &mut [u8]) -> Option<uint> {
+        match self.body {
+            NoBody | SizedBody(0) => None,
+            SizedBody(ref mut remaining) => {
+                let len = min(*remaining, buf.len());
+                match self.stream.read(buf.mut_slice_to(len)) {
+                    Some(bytes_read) => {
+                        *remaining -= bytes_read;
+                        Some(bytes_read)
+                    },
+                    None => {
+                        // The connection was closed early; there's no more to be had.
+                        *remaining = 0;
+                        None
+                    },
                 }
-                None => {
+            },
+            ChunkedBody(ref mut decoder) => decoder.read(&mut self.stream, buf),
+            CloseDelimitedBody => self.stream.read(buf),
+        }
+    }
+
+    /// Check the body against its Content-MD5, if it is to be checked and hasn't been already,
+    /// raising `io_error` if it doesn't match.
+    fn check_content_md5(&mut self) {
+        let mut md5 = match self.md5.take() {
+            Some(md5) => md5,
+            None => return,
+        };
+        let expected = self.headers.content_md5.get_ref().as_slice();
+        if !content_md5::digest_matches(&mut md5, expected) {
+            io_error::cond.raise(content_md5::mismatch_err());
+        }
+    }
+
+    /// Whether the whole body has been read from the connection.
+    fn body_finished(&mut self) -> bool {
+        match self.body {
+            NoBody | SizedBody(0) => true,
+            SizedBody(_) => false,
+            ChunkedBody(ref decoder) => decoder.is_finished(),
+            CloseDelimitedBody => self.stream.eof(),
+        }
+    }
+
+    /// Whether the connection can be used for another request: the body has been read to its
+    /// end, and the connection is persistent (RFC 2616, section 8.1), neither side having asked
+    /// for it to be closed.
+    pub fn is_reusable(&mut self) -> bool {
+        match self.body {
+            CloseDelimitedBody => return false,
+            _ => (),
+        }
+        if !self.body_finished() {
+            return false;
+        }
+        let request_closing = match self.request.headers.connection {
+            Some(ref tokens) => tokens.iter().any(|t| *t == Close),
+            None => false,
+        };
+        let persistent = match self.headers.connection {
+            Some(ref tokens) if tokens.iter().any(|t| *t == Close) => false,
+            Some(ref tokens) if self.version < (1, 1) =>
+                tokens.iter().any(|t| *t == Token(~"Keep-Alive")),
+            _ => self.version >= (1, 1),
+        };
+        persistent && !request_closing
+    }
+
+    /// Write the rest of the body to the writer as it is read, returning how many bytes there were.
+    pub fn write_body_to<W: Writer>(&mut self, writer: &mut W) -> u64 {
+        let mut buf = vec::from_elem(COPY_BUF_SIZE, 0u8);
+        let mut written = 0u64;
+        loop {
+            match self.read(buf) {
+                Some(n) => {
+                    writer.write(buf.slice_to(n));
+                    written += n as u64;
+                },
+                None => break,
+            }
+        }
+        written
+    }
+
+    /// Read the rest of the body as text, decoded from the charset its Content-Type gives (see
+    /// `http::charset`); `None` if that isn't UTF-8, ISO-8859-1 or US-ASCII, or the body isn't
+    /// valid in it.
+    pub fn read_text(&mut self) -> Option<~str> {
+        self.read_text_with(&StandardDecoder)
+    }
+
+    /// Read the rest of the body as text, as `read_text` does, but decoded with the decoder given.
+    pub fn read_text_with<D: Decoder>(&mut self, decoder: &D) -> Option<~str> {
+        let body = self.read_to_end();
+        decode_body(&self.headers.content_type, body, decoder)
+    }
+
+    /// Store in the jar any cookies set by the response.
+    pub fn store_cookies(&self, jar: &mut CookieJar) {
+        jar.store_response_cookies(&self.request.url, self.headers);
+    }
+
+    /// Finish with the response, returning the connection to the pool for reuse by a later
+    /// request if it is reusable (see `is_reusable`); otherwise the connection is closed.
+    pub fn release(self, pool: &mut ConnectionPool<S>) {
+        let mut mut_self = self;
+        // A connection over a Unix domain socket isn't to be taken for one to the URL's host.
+        if mut_self.is_reusable() && mut_self.request.unix_socket().is_none() {
+            let url = mut_self.request.url.clone();
+            pool.put(&url, mut_self.stream);
+        }
+    }
+}
+
+/// Read the Status-Line and the headers of a response from a stream, leaving the stream at the
+/// start of the body.
+///
+/// Should the response be malformed, the `io_error` condition is raised; if it is handled, `None`
+/// is returned.
+pub fn read_response_head<S: Stream>(stream: &mut BufferedStream<S>)
+        -> Option<((uint, uint), Status, ~headers::response::HeaderCollection)> {
+    let http_version = match read_http_version(stream, SP) {
+        Some(nums) => nums,
+        None => {
+            io_error::cond.raise(bad_response_err());
+            return None;
+        }
+    };
+
+    // Read the status code
+    let mut digits = 0u8;
+    let mut status_code = 0u16;
+    loop {
+        if digits == 4u8 {
+            // Status code must be three digits long
+            io_error::cond.raise(bad_response_err());
+            return None;
+        }
+        match stream.read_byte() {
+            Some(b) if b >= '0' as u8 && b <= '9' as u8 => {
+                status_code = status_code * 10 + b as u16 - '0' as u16;
+            },
+            Some(b) if b == SP => break,
+            _ => {
+                io_error::cond.raise(bad_response_err());
+                return None;
+            }
+        }
+        digits += 1;
+    }
+
+    // Read the status reason
+    let mut reason = ~"";
+    loop {
+        match stream.read_byte() {
+            Some(b) if b == CR => {
+                if stream.read_byte() == Some(LF) {
+                    break;
+                } else {
+                    // Response-Line has CR without LF. Not yet resilient; TODO.
                     io_error::cond.raise(bad_response_err());
-                    return Err(request);
+                    return None;
                 }
             }
+            Some(b) => {
+                reason.push_char(b as char);
+            }
+            None => {
+                io_error::cond.raise(bad_response_err());
+                return None;
+            }
         }
+    }
 
-        // Now we sneakily slip back to server::RequestBuffer to avoid code duplication. This is
-        // temporary, honest!
-        //
-        // You see, read_header and read_header_line will be replaced, as will this. The code will
-        // not be shared between them as they will have ultra-smart parsers (probably using Ragel)
-        // to provide fast loading of standard headers, and the set of defined headers is distinct
-        // between a request and response.
-        let headers = {
-            let mut buffer = RequestBuffer::new(&mut stream);
-            let mut headers = ~headers::response::HeaderCollection::new();
-            loop {
-                let xxx = buffer.read_header::<headers::response::Header>();
-                info!("header = {:?}", xxx);
-                match xxx {
-                //match buffer.read_header::<headers::response::Header>() {
-                    Err(EndOfFile) => {
-                        io_error::cond.raise(bad_response_err());
-                        //fail!("server disconnected, no more response to receive :-(");
-                        return Err(request);
-                    },
-                    Err(EndOfHeaders) => break,
-                    Err(MalformedHeaderSyntax) => {
-                        io_error::cond.raise(bad_response_err());
-                        return Err(request);
-                    },
-                    Err(MalformedHeaderValue) => {
-                        println("Bad header encountered. TODO: handle this better.");
-                        // Now just ignore the header
-                    },
-                    Ok(header) => {
-                        headers.insert(header);
-                    },
+    // Now we sneakily slip back to server::RequestBuffer to avoid code duplication. This is
+    // temporary, honest!
+    //
+    // You see, read_header and read_header_line will be replaced, as will this. The code will
+    // not be shared between them as they will have ultra-smart parsers (probably using Ragel)
+    // to provide fast loading of standard headers, and the set of defined headers is distinct
+    // between a request and response.
+    let mut buffer = RequestBuffer::new(stream);
+    let mut headers = ~headers::response::HeaderCollection::new();
+    loop {
+        let xxx = buffer.read_header::<headers::response::Header>();
+        info!("header = {:?}", xxx);
+        match xxx {
+        //match buffer.read_header::<headers::response::Header>() {
+            Err(EndOfFile) => {
+                io_error::cond.raise(bad_response_err());
+                //fail!("server disconnected, no more response to receive :-(");
+                return None;
+            },
+            Err(EndOfHeaders) => break,
+            Err(MalformedHeaderSyntax) => {
+                io_error::cond.raise(bad_response_err());
+                return None;
+            },
+            Err(MalformedHeaderValue) => {
+                println("Bad header encountered. TODO: handle this better.");
+                // Now just ignore the header
+            },
+            Ok(headers::response::SetCookie(SetCookieList(cookies))) => {
+                // Each cookie comes in a Set-Cookie header of its own; keep them all.
+                match headers.set_cookie {
+                    Some(SetCookieList(ref mut set_cookie)) => set_cookie.push_all_move(cookies),
+                    None => headers.set_cookie = Some(SetCookieList(cookies)),
                 }
-            }
-            headers
-        };
+            },
+            Ok(header) => {
+                headers.push(header);
+            },
+        }
+    }
+
+    Some((http_version, Status::from_code_and_reason(status_code, reason), headers))
+}
+
+/// The ways in which the end of a response body may be recognised.
+pub enum BodyFraming {
+    /// There is no body at all.
+    NoBody,
+    /// The body is this many bytes long (or, while reading, has this many bytes left).
+    SizedBody(uint),
+    /// The body is in the chunked transfer-coding, and ends with the last chunk.
+    ChunkedBody(ChunkedDecoder),
+    /// The body continues until the server closes the connection.
+    CloseDelimitedBody,
+}
+
+/// Determine how the body of a response to a request with the given method is delimited, as
+/// specified in RFC 2616, section 4.4.
+///
+/// (The fifth way, multipart/byteranges, is not supported by the server without it also sending
+/// a Content-Length, so is disregarded.)
+pub fn body_framing(method: &Method, status: &Status,
+                    headers: &headers::response::HeaderCollection) -> BodyFraming {
+    let code = status.code();
+    if *method == Head || code / 100 == 1 || code == 204 || code == 304 {
+        return NoBody;
+    }
+    match headers.transfer_encoding {
+        Some(ref codings) if codings.iter().any(|c| *c == Chunked) =>
+            return ChunkedBody(ChunkedDecoder::new()),
+        _ => (),
+    }
+    match headers.content_length {
+        Some(length) => SizedBody(length),
+        None => CloseDelimitedBody,
+    }
+}
 
-        Ok(ResponseReader {
-            stream: stream,
-            request: request,
-            version: http_version,
-            status: Status::from_code_and_reason(status_code, reason),
-            headers: headers,
-        })
+/// Read the head of the next final response, skipping any 1xx (informational) responses, which
+/// have no body and precede the real response (RFC 2616, section 10.1).
+pub fn read_final_response_head<S: Stream>(stream: &mut BufferedStream<S>)
+        -> Option<((uint, uint), Status, ~headers::response::HeaderCollection)> {
+    loop {
+        match read_response_head(stream) {
+            Some((_, ref status, _)) if status.code() / 100 == 1 => (),
+            head => return head,
+        }
     }
 }
 
 impl<S: Stream> Reader for ResponseReader<S> {
     fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
-        self.stream.read(buf)
+        match self.decompressed {
+            Some(ref mut reader) => return reader.read(buf),
+            None => (),
+        }
+        let bytes_read = self.read_body(buf);
+        match bytes_read {
+            Some(n) => match self.md5 {
+                Some(ref mut md5) => md5.input(buf.slice_to(n)),
+                None => (),
+            },
+            None if self.stream.read_timed_out() =>
+                io_error::cond.raise(timeout_err("reading the response")),
+            None => if self.body_finished() {
+                self.check_content_md5();
+            },
+        }
+        bytes_read
     }
 
     fn eof(&mut self) -> bool {
-        self.stream.eof()
+        match self.decompressed {
+            Some(ref mut reader) => reader.eof(),
+            None => self.body_finished(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rt::io::{Decorator, io_error};
+    use std::rt::io::extensions::ReaderUtil;
+    use std::rt::io::mem::MemWriter;
+    use buffer::BufferedStream;
+    use client::request::RequestWriter;
+    use content_md5::is_mismatch;
+    use headers::response::HeaderCollection;
+    use headers::transfer_encoding::Chunked;
+    use memstream::MemReaderFakeStream;
+    use method::{Get, Head};
+    use status;
+    use super::{ResponseReader, body_framing, NoBody, SizedBody, ChunkedBody, CloseDelimitedBody};
+
+    #[test]
+    fn test_body_framing() {
+        let mut headers = HeaderCollection::new();
+        match body_framing(&Get, &status::Ok, &headers) {
+            CloseDelimitedBody => (),
+            _ => fail!("no length given should be delimited by closing"),
+        }
+
+        headers.content_length = Some(12);
+        match body_framing(&Get, &status::Ok, &headers) {
+            SizedBody(12) => (),
+            _ => fail!("Content-Length should give the length"),
+        }
+        match body_framing(&Head, &status::Ok, &headers) {
+            NoBody => (),
+            _ => fail!("a response to HEAD should have no body"),
+        }
+        match body_framing(&Get, &status::NotModified, &headers) {
+            NoBody => (),
+            _ => fail!("a 304 response should have no body"),
+        }
+
+        // Transfer-Encoding takes precedence over Content-Length
+        headers.transfer_encoding = Some(~[Chunked]);
+        match body_framing(&Get, &status::Ok, &headers) {
+            ChunkedBody(_) => (),
+            _ => fail!("a chunked body should be decoded"),
+        }
+    }
+
+    #[test]
+    fn test_write_body_to() {
+        let request = ~RequestWriter::new(Get, FromStr::from_str("http://127.0.0.1/").unwrap());
+        let stream = BufferedStream::new(MemReaderFakeStream::new(bytes!("\
+HTTP/1.1 200 OK\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n").to_owned()), false);
+        let mut response = match ResponseReader::construct(stream, request) {
+            Ok(response) => response,
+            Err(_) => fail!("the response should be read"),
+        };
+        let mut writer = MemWriter::new();
+        assert_eq!(response.write_body_to(&mut writer), 11);
+        assert_eq!(writer.inner(), bytes!("hello world").to_owned());
+    }
+
+    fn read_gzip_body(max_decompressed_size: uint) -> (~[u8], Option<&'static str>) {
+        let mut request = ~RequestWriter::new(Get,
+                                              FromStr::from_str("http://127.0.0.1/").unwrap());
+        request.decompress = true;
+        request.max_decompressed_size = max_decompressed_size;
+        let mut data = bytes!("\
+HTTP/1.1 200 OK\r\n\
+Content-Encoding: gzip\r\n\
+Content-Length: 20\r\n\
+\r\n").to_owned();
+        // A gzip header and then a block of the reserved type
+        data.push_all([0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let stream = Cell::new(BufferedStream::new(MemReaderFakeStream::new(data), false));
+        let request = Cell::new(request);
+        let mut desc = None;
+        let body = do io_error::cond.trap(|e| desc = Some(e.desc)).inside {
+            // Decompression is done as the response is constructed
+            match ResponseReader::construct(stream.take(), request.take()) {
+                Ok(mut response) => response.read_to_end(),
+                Err(_) => fail!("the response should be read"),
+            }
+        };
+        (body, desc)
+    }
+
+    #[test]
+    fn test_decompress_corrupt_body() {
+        let (body, desc) = read_gzip_body(100);
+        assert_eq!(body, ~[]);
+        assert_eq!(desc, Some("Server sent a compressed body which could not be decompressed"));
+        let (body, desc) = read_gzip_body(10);
+        assert_eq!(body, ~[]);
+        assert_eq!(desc, Some("Server sent a compressed body which was too long to decompress"));
+    }
+
+    fn read_verified(content_md5: &str) -> (~[u8], bool) {
+        let mut request = ~RequestWriter::new(Get,
+                                              FromStr::from_str("http://127.0.0.1/").unwrap());
+        request.verify_content_md5 = true;
+        let stream = BufferedStream::new(MemReaderFakeStream::new(format!("\
+HTTP/1.1 200 OK\r\n\
+Content-Length: 11\r\n\
+Content-MD5: {}\r\n\
+\r\n\
+Hello world", content_md5).into_bytes()), false);
+        let mut response = match ResponseReader::construct(stream, request) {
+            Ok(response) => response,
+            Err(_) => fail!("the response should be read"),
+        };
+        let mut mismatched = false;
+        let body = do io_error::cond.trap(|e| mismatched = is_mismatch(&e)).inside {
+            response.read_to_end()
+        };
+        (body, mismatched)
+    }
+
+    #[test]
+    fn test_verify_content_md5() {
+        let (body, mismatched) = read_verified("PiWWCnnbxptnTNTsZ6csYg==");
+        assert_eq!(body, bytes!("Hello world").to_owned());
+        assert!(!mismatched);
+        let (body, mismatched) = read_verified("1B2M2Y8AsgTpgAmY7PhCfg==");
+        assert_eq!(body, bytes!("Hello world").to_owned());
+        assert!(mismatched);
     }
 }
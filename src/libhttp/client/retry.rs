@@ -0,0 +1,231 @@
+/*!
+
+Retrying of requests which fail: those which can't connect, and those of idempotent methods whose
+connection fails part way or whose response is 502 Bad Gateway, 503 Service Unavailable or 504
+Gateway Timeout, which a server or a proxy in front of it gives for trouble which may pass.
+
+```rust
+use http::client::RequestWriter;
+use http::client::retry::RetryPolicy;
+use http::method::Get;
+
+let policy = RetryPolicy::new();
+let response = do policy.read_response([]) {
+    ~RequestWriter::new(Get, url.clone())
+};
+```
+
+As a request can't be sent twice, the policy is given a closure which makes each attempt's request
+afresh, and the body, if any, to write after connecting (setting Content-Length if the request
+hasn't). Between attempts it waits, for twice as long each time, from `base_delay` up to
+`max_delay`, less a random part of up to a half so that clients which failed together don't retry
+together; a 503 response's Retry-After, in seconds or as a date, is waited for instead, but still
+no longer than `max_delay`.
+
+A request of a method which isn't idempotent, such as POST, is only retried if it couldn't connect,
+for then it was never sent; once it has been, whether it failed or was answered with an error, the
+server may have acted upon it.
+
+*/
+
+use std::cell::Cell;
+use std::cmp::min;
+use std::rand::{Rng, task_rng};
+use std::rt::io::Writer;
+use std::rt::io::io_error;
+use std::rt::io::timer::sleep;
+use extra::time::get_time;
+use client::request::RequestWriter;
+use client::response::ResponseReader;
+use headers::date;
+use network::NetworkStream;
+
+/// How many attempts to make at a request, and how long to wait between them.
+#[deriving(Clone)]
+pub struct RetryPolicy {
+    /// How many attempts to make at a request in all, the first included; 3 by default. 1 makes
+    /// no retries.
+    max_attempts: uint,
+
+    /// How long to wait before the first retry, in milliseconds; 100ms by default.
+    base_delay: u64,
+
+    /// The longest to wait before any retry, in milliseconds; 10s by default.
+    max_delay: u64,
+}
+
+impl RetryPolicy {
+    /// The default policy: three attempts, waiting for up to 100ms and then 200ms between them.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: 100,
+            max_delay: 10_000,
+        }
+    }
+
+    /**
+     * Make the request given by `request`, with the body given, retrying it as the policy allows,
+     * and return the response to the last attempt.
+     *
+     * The `io_error` conditions of the attempts which are retried are handled; should the last
+     * attempt fail, the condition is raised, and if it is handled, `None` is returned.
+     */
+    pub fn read_response(&self, body: &[u8], request: &fn() -> ~RequestWriter<NetworkStream>)
+            -> Option<ResponseReader<NetworkStream>> {
+        let mut attempt = 1;
+        loop {
+            let last = attempt >= self.max_attempts;
+            let mut request = request();
+            if body.len() > 0 && request.headers.content_length.is_none() {
+                request.headers.content_length = Some(body.len());
+            }
+            let idempotent = request.method.is_idempotent();
+
+            let delay = if !trapping(!last, || request.connect()) {
+                if last {
+                    return None;
+                }
+                self.delay(attempt)
+            } else {
+                let request = Cell::new(request);
+                match trapping(!last && idempotent, || send(request.take(), body)) {
+                    Some(response) => {
+                        if last || !idempotent || !is_retryable_status(response.status.code()) {
+                            return Some(response);
+                        }
+                        match retry_after(&response) {
+                            Some(seconds) => min(seconds * 1000, self.max_delay),
+                            None => self.delay(attempt),
+                        }
+                    },
+                    None => {
+                        if last || !idempotent {
+                            return None;
+                        }
+                        self.delay(attempt)
+                    },
+                }
+            };
+            debug!("attempt {} failed; retrying in {}ms", attempt, delay);
+            sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// How long to wait after the attempt given (counting from 1) has failed, jitter and all.
+    pub fn delay(&self, attempt: uint) -> u64 {
+        let backoff = self.backoff(attempt);
+        backoff - task_rng().gen::<u64>() % (backoff / 2 + 1)
+    }
+
+    /// How long to wait after the attempt given has failed, before the jitter is taken off:
+    /// `base_delay` doubled for each attempt after the first, but no more than `max_delay`.
+    fn backoff(&self, attempt: uint) -> u64 {
+        let mut backoff = self.base_delay;
+        for _ in range(1, attempt) {
+            if backoff >= self.max_delay / 2 {
+                return self.max_delay;
+            }
+            backoff *= 2;
+        }
+        min(backoff, self.max_delay)
+    }
+}
+
+/// Call `f`, handling any `io_error` condition it raises if `trap` is set.
+fn trapping<T>(trap: bool, f: &fn() -> T) -> T {
+    if trap {
+        do io_error::cond.trap(|e| debug!("retrying after error: {}", e.desc)).inside {
+            f()
+        }
+    } else {
+        f()
+    }
+}
+
+/// Write the body of the (connected) request and read its response.
+fn send(request: ~RequestWriter<NetworkStream>, body: &[u8])
+        -> Option<ResponseReader<NetworkStream>> {
+    let mut request = request;
+    if body.len() > 0 {
+        request.write(body);
+    }
+    match request.read_response() {
+        Ok(response) => Some(response),
+        Err(_) => None,
+    }
+}
+
+/// Whether a response of the status code given is worth retrying: 502, 503 and 504 are.
+fn is_retryable_status(code: u16) -> bool {
+    code == 502 || code == 503 || code == 504
+}
+
+/// The seconds which a 503 response asks to be waited before retrying, if it has a Retry-After,
+/// which gives them either as a number or as the HTTP-date until which to wait.
+fn retry_after(response: &ResponseReader<NetworkStream>) -> Option<u64> {
+    if response.status.code() != 503 {
+        return None;
+    }
+    match response.headers.retry_after {
+        Some(ref value) => retry_after_seconds(value.trim(), get_time().sec),
+        None => None,
+    }
+}
+
+/// The seconds to wait for a Retry-After with the value given, at the time `now`, in seconds.
+fn retry_after_seconds(value: &str, now: i64) -> Option<u64> {
+    match FromStr::from_str(value) {
+        Some(seconds) => Some(seconds),
+        None => match date::parse(value) {
+            Some(tm) => {
+                let until = tm.to_timespec().sec;
+                Some(if until > now { (until - now) as u64 } else { 0 })
+            },
+            None => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RetryPolicy, is_retryable_status, retry_after_seconds};
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy { max_attempts: 10, base_delay: 100, max_delay: 1000 };
+        assert_eq!(policy.backoff(1), 100);
+        assert_eq!(policy.backoff(2), 200);
+        assert_eq!(policy.backoff(4), 800);
+        assert_eq!(policy.backoff(5), 1000);
+        assert_eq!(policy.backoff(100), 1000);
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new();
+        for _ in range(0, 100) {
+            let delay = policy.delay(2);
+            assert!(delay >= 100 && delay <= 200);
+        }
+        let policy = RetryPolicy { max_attempts: 3, base_delay: 0, max_delay: 0 };
+        assert_eq!(policy.delay(1), 0);
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        assert_eq!(retry_after_seconds("120", 0), Some(120));
+        // Sun, 06 Nov 1994 08:49:37 GMT is 784111777.
+        assert_eq!(retry_after_seconds("Sun, 06 Nov 1994 08:49:37 GMT", 784111757), Some(20));
+        assert_eq!(retry_after_seconds("Sun, 06 Nov 1994 08:49:37 GMT", 784111797), Some(0));
+        assert_eq!(retry_after_seconds("soon", 0), None);
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        assert!(is_retryable_status(503));
+        assert!(!is_retryable_status(500));
+        assert!(!is_retryable_status(404));
+    }
+}
@@ -0,0 +1,87 @@
+/*!
+
+Making a request in one call, and getting back the whole response, for when neither streaming nor
+reusing connections matters.
+
+```rust
+use http::client;
+
+let response = client::get("http://example.com/").expect("request failed");
+println!("{}: {}", response.status, ::std::str::from_utf8(response.body));
+```
+
+Up to `MAX_REDIRECTS` redirects are followed, and a compressed body is decompressed. The URL must
+be valid; these fail if it isn't.
+
+*/
+
+use std::rt::io::extensions::ReaderUtil;
+use extra::url::Url;
+use headers;
+use headers::content_type::MediaType;
+use status::Status;
+use client::builder::RequestBuilder;
+
+/// How many redirects are followed.
+pub static MAX_REDIRECTS: uint = 10;
+
+/// A response, read whole.
+pub struct SimpleResponse {
+    /// The HTTP status indicated in the response.
+    status: Status,
+
+    /// The headers received in the response.
+    headers: ~headers::response::HeaderCollection,
+
+    /// The body of the response, decompressed and with any transfer-coding removed.
+    body: ~[u8],
+}
+
+/// Make a GET request for the URL. Should it fail, the `io_error` condition is raised, and if it
+/// is handled, `None` is returned.
+pub fn get(url: &str) -> Option<SimpleResponse> {
+    fetch(RequestBuilder::get(parse_url(url)))
+}
+
+/// Make a POST request to the URL, with a body of the media type given, such as
+/// `application/x-www-form-urlencoded`; returns as `get` does.
+pub fn post(url: &str, content_type: MediaType, body: ~[u8]) -> Option<SimpleResponse> {
+    let mut builder = RequestBuilder::post(parse_url(url)).body(body);
+    builder.request().headers.content_type = Some(content_type);
+    fetch(builder)
+}
+
+/// Make a PUT request to the URL, with a body of the media type given; returns as `get` does.
+pub fn put(url: &str, content_type: MediaType, body: ~[u8]) -> Option<SimpleResponse> {
+    let mut builder = RequestBuilder::put(parse_url(url)).body(body);
+    builder.request().headers.content_type = Some(content_type);
+    fetch(builder)
+}
+
+/// Make a DELETE request for the URL; returns as `get` does.
+pub fn delete(url: &str) -> Option<SimpleResponse> {
+    fetch(RequestBuilder::delete(parse_url(url)))
+}
+
+fn parse_url(url: &str) -> Url {
+    match FromStr::from_str(url) {
+        Some(url) => url,
+        None => fail!("{:?} is not a valid URL", url),
+    }
+}
+
+fn fetch(builder: RequestBuilder) -> Option<SimpleResponse> {
+    match builder.decompress().max_redirects(MAX_REDIRECTS).send() {
+        Ok(response) => {
+            let mut response = response;
+            let body = response.read_to_end();
+            Some(SimpleResponse {
+                status: Status::from_code_and_reason(response.status.code(),
+                                                     response.status.reason()),
+                headers: response.headers.clone(),
+                body: body,
+            })
+        },
+        Err(_) => None,
+    }
+}
@@ -1,5 +1,6 @@
-// These are taken from http://en.wikipedia.org/wiki/List_of_HTTP_Status_Codes.
-// Last updated on 2013-07-04
+// These are taken from http://en.wikipedia.org/wiki/List_of_HTTP_Status_Codes, and checked
+// against the IANA registry, http://www.iana.org/assignments/http-status-codes.
+// Last updated on 2013-07-04; the IANA registry, on 2026-10-14.
 // Entries from third-party vendors not standardised upon are not included.
 // If not specified otherwise, they are defined in RFC 2616.
 
@@ -75,6 +76,7 @@ pub fn generate(output_dir: &Path) {
         StatusN(100, "Continue"),
         StatusN(101, "Switching Protocols"),
         StatusC(102, "Processing", "WebDAV; RFC 2518"),
+        StatusC(103, "Early Hints", "RFC 8297"),
 
         Left("2xx Success"),
         StatusN(200, "OK"),
@@ -97,7 +99,7 @@ pub fn generate(output_dir: &Path) {
         StatusC(305, "Use Proxy", "since HTTP/1.1"),
         StatusN(306, "Switch Proxy"),
         StatusC(307, "Temporary Redirect", "since HTTP/1.1"),
-        StatusC(308, "Permanent Redirect", "approved as experimental RFC: http://tools.ietf.org/html/draft-reschke-http-status-308"),
+        StatusC(308, "Permanent Redirect", "RFC 7538"),
 
         Left("4xx Client Error"),
         StatusN(400, "Bad Request"),
@@ -120,16 +122,18 @@ pub fn generate(output_dir: &Path) {
         StatusN(417, "Expectation Failed"),
         StatusC(418, "I'm a teapot", "RFC 2324"),
         StatusN(419, "Authentication Timeout"),
+        StatusC(421, "Misdirected Request", "RFC 7540"),
         StatusC(422, "Unprocessable Entity", "WebDAV; RFC 4918"),
         StatusC(423, "Locked", "WebDAV; RFC 4918"),
         StatusC(424, "Failed Dependency", "WebDAV; RFC 4918"),
         StatusC(424, "Method Failure", "WebDAV"),
+        StatusC(425, "Too Early", "RFC 8470"),
         StatusC(425, "Unordered Collection", "Internet draft"),
         StatusC(426, "Upgrade Required", "RFC 2817"),
         StatusC(428, "Precondition Required", "RFC 6585"),
         StatusC(429, "Too Many Requests", "RFC 6585"),
         StatusC(431, "Request Header Fields Too Large", "RFC 6585"),
-        StatusC(451, "Unavailable For Legal Reasons", "Internet draft"),
+        StatusC(451, "Unavailable For Legal Reasons", "RFC 7725"),
 
         Left("5xx Server Error"),
         StatusN(500, "Internal Server Error"),
@@ -230,6 +234,38 @@ impl Status {
             (_, _) => UnregisteredStatus(status, reason),
         }
     }
+
+    /// Get a status with any code and reason phrase, as to send one which isn't registered here;
+    /// should it be registered, it is that status which is given.
+    ///
+    /// This fails if the code isn't of three digits, or the reason phrase would not be valid in a
+    /// Status-Line, having control characters other than tabs in it (see `is_valid`).
+    pub fn custom(code: u16, reason: &str) -> Status {
+        if code < 100 || code > 999 {
+            fail!(\"Status::custom() called with {}, which isn't a three-digit code\", code);
+        }
+        if !is_valid_reason(reason) {
+            fail!(\"Status::custom() called with {:?}, which isn't a valid reason phrase\", reason);
+        }
+        Status::from_code_and_reason(code, reason.to_owned())
+    }
+
+    /// Whether the status may be sent: registered ones always may, and an `UnregisteredStatus` may
+    /// if its code is of three digits and its reason phrase has no control characters but tabs,
+    /// as a Status-Line requires (RFC 7230, section 3.1.2).
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            UnregisteredStatus(code, ref reason) => {
+                code >= 100 && code <= 999 && is_valid_reason(reason.as_slice())
+            },
+            _ => true,
+        }
+    }
+}
+
+/// Whether a reason phrase is of tabs, spaces, visible characters and characters beyond ASCII.
+fn is_valid_reason(reason: &str) -> bool {
+    reason.byte_iter().all(|b| b == '\\t' as u8 || (b >= ' ' as u8 && b != 0x7f))
 }
 
 impl ToStr for Status {
@@ -294,8 +330,8 @@ impl FromPrimitive for Status {
             Left(heading) => out.write_str(format!("\n            // {}\n", heading)),
             Right(status) => {
                 if !matched_numbers.contains(&status.code) {
-                    // Purpose: FailedDependency and MethodFailure both use 424,
-                    // but clearly they mustn't both go in here
+                    // Purpose: FailedDependency and MethodFailure both use 424 (and TooEarly and
+                    // UnorderedCollection 425), but clearly they mustn't both go in here
                     out.write_str(format!("            {:u} => {},\n", status.code, status.ident()));
                     matched_numbers.insert(status.code);
                 }
@@ -306,5 +342,31 @@ impl FromPrimitive for Status {
             _   => { return None }
         })
     }
+}
+
+#[test]
+fn test_custom() {
+    assert_eq!(Status::custom(200, \"ok\"), Ok);
+    assert_eq!(Status::custom(299, \"Quite OK\").to_str(), ~\"299 Quite OK\");
+    assert_eq!(Status::custom(425, \"Too Early\"), TooEarly);
+    assert_eq!(FromPrimitive::from_u64(425), Some(TooEarly));
+}
+
+#[test] #[should_fail]
+fn test_custom_two_digits() {
+    Status::custom(99, \"Bottles\");
+}
+
+#[test] #[should_fail]
+fn test_custom_newline() {
+    Status::custom(299, \"OK\\r\\nSet-Cookie: x=1\");
+}
+
+#[test]
+fn test_is_valid() {
+    assert!(NotFound.is_valid());
+    assert!(UnregisteredStatus(299, ~\"Quite\\tOK\").is_valid());
+    assert!(!UnregisteredStatus(1000, ~\"Too Big\").is_valid());
+    assert!(!UnregisteredStatus(299, ~\"OK\\n\").is_valid());
 }");
 }
@@ -1,6 +1,7 @@
 //! Schizofrenic methods which are useful in more than one of request-or-response reading-or-writing
 use std::rt::io::Reader;
 use std::rt::io::extensions::ReaderUtil;
+use std::vec;
 
 pub fn read_uint<R: Reader>(reader: &mut R, max_digits: u8, next_u8: u8) -> Option<uint> {
     let mut digits = 0u8;
@@ -80,3 +81,36 @@ pub fn read_http_version<T: Reader>(reader: &mut T, next_u8: u8) -> Option<(uint
     }
     Some((n1, n2))
 }
+
+// The binary framings (SPDY, HTTP/2 and WebSocket) read frames a field at a time, big-endian.
+
+/// Read all of `len` bytes, or `None` if the reader ends first.
+pub fn read_exactly<R: Reader>(reader: &mut R, len: uint) -> Option<~[u8]> {
+    let mut buf = vec::from_elem(len, 0u8);
+    let mut read = 0;
+    while read < len {
+        match reader.read(buf.mut_slice_from(read)) {
+            Some(n) => read += n,
+            None => return None,
+        }
+    }
+    Some(buf)
+}
+
+/// Read a big-endian 32-bit number from the start of the bytes.
+pub fn read_u32(b: &[u8]) -> u32 {
+    (b[0] as u32 << 24) | (b[1] as u32 << 16) | (b[2] as u32 << 8) | b[3] as u32
+}
+
+/// Read a 31-bit stream ID (of SPDY or HTTP/2), ignoring the reserved bit before it.
+pub fn read_stream_id(b: &[u8]) -> u32 {
+    read_u32(b) & 0x7FFFFFFF
+}
+
+/// Append a big-endian 32-bit number.
+pub fn push_u32(out: &mut ~[u8], n: u32) {
+    out.push((n >> 24) as u8);
+    out.push((n >> 16) as u8);
+    out.push((n >> 8) as u8);
+    out.push(n as u8);
+}
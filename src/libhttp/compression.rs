@@ -0,0 +1,338 @@
+//! The `gzip` and `deflate` content-codings (RFC 2616, section 3.5), for compressing message
+//! bodies.
+//!
+//! `extra::flate` only works on a whole buffer at a time, so a body to be compressed or
+//! decompressed must be held in memory in its entirety first; this is fine for the generated text
+//! which benefits the most. Decompression is done with zlib instead, which, unlike `extra::flate`,
+//! reports corrupt data rather than failing, and lets the output be cut off at a limit, so that a
+//! small body which would decompress to a huge one can't exhaust memory.
+
+use std::ascii::StrAsciiExt;
+use std::libc::c_int;
+use std::sys;
+use std::vec;
+use extra::flate::deflate_bytes;
+use zlib;
+
+/// The longest a body may be once decompressed, by default (see
+/// `RequestWriter.max_decompressed_size`): 16MB.
+pub static DEFAULT_MAX_DECOMPRESSED_LEN: uint = 0x1000000;
+
+/// The size of the chunks in which zlib writes decompressed data.
+static INFLATE_BUF_SIZE: uint = 0x4000;
+
+/// A content-coding which compresses the body.
+#[deriving(Clone, Eq)]
+pub enum Coding {
+    /// The gzip file format (RFC 1952).
+    Gzip,
+    /// The zlib format (RFC 1950). (Beware that some old user agents take this to mean raw
+    /// DEFLATE data, without the zlib header and checksum; `Gzip` is the safer choice.)
+    Deflate,
+}
+
+impl Coding {
+    /// The content-coding as it is named in the Content-Encoding and Accept-Encoding headers.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Gzip => "gzip",
+            Deflate => "deflate",
+        }
+    }
+
+    /// The content-coding named, if it is one this module knows (`x-gzip` is taken as `gzip`).
+    pub fn from_name(name: &str) -> Option<Coding> {
+        match name.to_ascii_lower().as_slice() {
+            "gzip" | "x-gzip" => Some(Gzip),
+            "deflate" => Some(Deflate),
+            _ => None,
+        }
+    }
+}
+
+/// Compress the data with the content-coding.
+pub fn compress(coding: Coding, data: &[u8]) -> ~[u8] {
+    let deflated = deflate_bytes(data);
+    match coding {
+        Gzip => {
+            let mut out = vec::with_capacity(deflated.len() + 18);
+            // ID1, ID2, CM (deflate), FLG (none), MTIME (none), XFL, OS (unknown)
+            out.push_all([0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+            out.push_all(deflated);
+            push_u32_le(&mut out, crc32(data));
+            push_u32_le(&mut out, data.len() as u32);
+            out
+        },
+        Deflate => {
+            let mut out = vec::with_capacity(deflated.len() + 6);
+            // CMF (deflate, 32K window), FLG (default compression level, check bits)
+            out.push_all([0x78, 0x9c]);
+            out.push_all(deflated);
+            let adler = adler32(data);
+            out.push_all([(adler >> 24) as u8, (adler >> 16) as u8, (adler >> 8) as u8,
+                          adler as u8]);
+            out
+        },
+    }
+}
+
+fn push_u32_le(out: &mut ~[u8], n: u32) {
+    out.push_all([n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+}
+
+/// Why data couldn't be decompressed.
+#[deriving(Eq)]
+pub enum DecompressError {
+    /// The data isn't in the format of the coding: it is corrupt, cut short, or has the wrong
+    /// checksum.
+    CorruptData,
+    /// The data would be longer than allowed once decompressed.
+    TooLong,
+}
+
+/// Decompress data in the content-coding, so long as it is no longer than `max_len` bytes once
+/// decompressed.
+///
+/// Data in the `deflate` coding may be raw DEFLATE data rather than the zlib format, as some
+/// servers have sent it that way; both are accepted.
+pub fn decompress(coding: Coding, data: &[u8], max_len: uint) -> Result<~[u8], DecompressError> {
+    match coding {
+        Gzip => decompress_gzip(data, max_len),
+        Deflate => {
+            let len = data.len();
+            let is_zlib = len >= 6 && data[0] & 0x0f == 8 && data[1] & 0x20 == 0
+                          && ((data[0] as uint << 8) | data[1] as uint) % 31 == 0;
+            if !is_zlib {
+                return inflate(data, max_len);
+            }
+            let inflated = match inflate(data.slice(2, len - 4), max_len) {
+                Ok(inflated) => inflated,
+                Err(e) => return Err(e),
+            };
+            let expected = (data[len - 4] as u32 << 24) | (data[len - 3] as u32 << 16)
+                           | (data[len - 2] as u32 << 8) | data[len - 1] as u32;
+            if adler32(inflated) == expected { Ok(inflated) } else { Err(CorruptData) }
+        },
+    }
+}
+
+/// Inflate raw DEFLATE data (RFC 1951), which must be one whole compressed stream, with nothing
+/// after it.
+fn inflate(data: &[u8], max_len: uint) -> Result<~[u8], DecompressError> {
+    let mut stream = zlib::new_z_stream();
+    stream.next_in = vec::raw::to_ptr(data);
+    stream.avail_in = data.len() as u32;
+    unsafe {
+        // Negative window bits mean raw data, without the zlib header and checksum
+        let size = sys::size_of::<zlib::z_stream>() as c_int;
+        if zlib::inflateInit2_(&mut *stream, -15, zlib::zlibVersion(), size) != zlib::Z_OK {
+            fail!("zlib failed to start decompressing");
+        }
+    }
+    let mut out = ~[];
+    let mut buf = vec::from_elem(INFLATE_BUF_SIZE, 0u8);
+    let result;
+    loop {
+        stream.next_out = vec::raw::to_mut_ptr(buf);
+        stream.avail_out = buf.len() as u32;
+        let ret = unsafe { zlib::inflate(&mut *stream, zlib::Z_NO_FLUSH) };
+        let len = buf.len() - stream.avail_out as uint;
+        if out.len() + len > max_len {
+            result = Err(TooLong);
+            break;
+        }
+        out.push_all(buf.slice_to(len));
+        if ret == zlib::Z_STREAM_END {
+            result = if stream.avail_in == 0 { Ok(out) } else { Err(CorruptData) };
+            break;
+        }
+        // Anything else, such as running out of input before the end of the stream, is an error
+        if ret != zlib::Z_OK {
+            result = Err(CorruptData);
+            break;
+        }
+    }
+    unsafe { zlib::inflateEnd(&mut *stream); }
+    result
+}
+
+fn decompress_gzip(data: &[u8], max_len: uint) -> Result<~[u8], DecompressError> {
+    static FHCRC: u8 = 2;
+    static FEXTRA: u8 = 4;
+    static FNAME: u8 = 8;
+    static FCOMMENT: u8 = 16;
+
+    let len = data.len();
+    if len < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return Err(CorruptData);
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        pos += 2 + (data[pos] as uint | data[pos + 1] as uint << 8);
+        if pos > len {
+            return Err(CorruptData);
+        }
+    }
+    if flags & FNAME != 0 {
+        pos = match data.slice_from(pos).iter().position(|&b| b == 0) {
+            Some(i) => pos + i + 1,
+            None => return Err(CorruptData),
+        };
+    }
+    if flags & FCOMMENT != 0 {
+        pos = match data.slice_from(pos).iter().position(|&b| b == 0) {
+            Some(i) => pos + i + 1,
+            None => return Err(CorruptData),
+        };
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos > len - 8 {
+        return Err(CorruptData);
+    }
+
+    let inflated = match inflate(data.slice(pos, len - 8), max_len) {
+        Ok(inflated) => inflated,
+        Err(e) => return Err(e),
+    };
+    if crc32(inflated) != read_u32_le(data.slice_from(len - 8))
+            || inflated.len() as u32 != read_u32_le(data.slice_from(len - 4)) {
+        return Err(CorruptData);
+    }
+    Ok(inflated)
+}
+
+fn read_u32_le(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32 << 8) | (b[2] as u32 << 16) | (b[3] as u32 << 24)
+}
+
+/// The CRC-32 of the data, as used by gzip.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32, ..256];
+    for i in range(0u32, 256) {
+        let mut c = i;
+        for _ in range(0, 8) {
+            c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[i as uint] = c;
+    }
+    let mut crc = 0xffffffffu32;
+    for &b in data.iter() {
+        crc = table[((crc ^ b as u32) & 0xff) as uint] ^ (crc >> 8);
+    }
+    crc ^ 0xffffffff
+}
+
+/// The Adler-32 checksum of the data, as used by zlib.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec;
+    use extra::flate::{deflate_bytes, inflate_bytes};
+    use super::{compress, decompress, crc32, adler32, Coding, Gzip, Deflate, CorruptData,
+                TooLong};
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(bytes!("123456789")), 0xcbf43926);
+        assert_eq!(crc32([]), 0);
+        assert_eq!(adler32(bytes!("Wikipedia")), 0x11e60398);
+        assert_eq!(adler32([]), 1);
+    }
+
+    #[test]
+    fn test_compress() {
+        let data = bytes!("Hello, world! Hello, world! Hello, world!");
+
+        let gzipped = compress(Gzip, data);
+        assert_eq!(gzipped.slice_to(3), &[0x1f, 0x8b, 8]);
+        let len = gzipped.len();
+        assert_eq!(inflate_bytes(gzipped.slice(10, len - 8)), data.to_owned());
+        assert_eq!(gzipped.slice_from(len - 4), &[data.len() as u8, 0, 0, 0]);
+
+        let deflated = compress(Deflate, data);
+        assert_eq!(deflated.slice_to(2), &[0x78, 0x9c]);
+        let len = deflated.len();
+        assert_eq!(inflate_bytes(deflated.slice(2, len - 4)), data.to_owned());
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = bytes!("Hello, world! Hello, world! Hello, world!");
+        assert_eq!(decompress(Gzip, compress(Gzip, data), 100), Ok(data.to_owned()));
+        assert_eq!(decompress(Deflate, compress(Deflate, data), 100), Ok(data.to_owned()));
+        // Raw DEFLATE data, sent by some servers as deflate
+        assert_eq!(decompress(Deflate, deflate_bytes(data), 100), Ok(data.to_owned()));
+
+        // With the optional FNAME field, "a\0"
+        let mut gzipped = compress(Gzip, data);
+        gzipped[3] = 8;
+        gzipped.insert(10, 0);
+        gzipped.insert(10, 'a' as u8);
+        assert_eq!(decompress(Gzip, gzipped, 100), Ok(data.to_owned()));
+
+        // A bad checksum
+        let mut gzipped = compress(Gzip, data);
+        let len = gzipped.len();
+        gzipped[len - 8] ^= 1;
+        assert_eq!(decompress(Gzip, gzipped, 100), Err(CorruptData));
+        assert_eq!(decompress(Gzip, bytes!("not gzip at all"), 100), Err(CorruptData));
+    }
+
+    #[test]
+    fn test_decompress_corrupt() {
+        let data = bytes!("Hello, world! Hello, world! Hello, world!");
+
+        // Cut short, in the compressed data and in the trailer
+        let gzipped = compress(Gzip, data);
+        let len = gzipped.len();
+        assert_eq!(decompress(Gzip, gzipped.slice_to(len - 12), 100), Err(CorruptData));
+        assert_eq!(decompress(Gzip, gzipped.slice_to(len - 4), 100), Err(CorruptData));
+        let deflated = deflate_bytes(data);
+        let len = deflated.len();
+        assert_eq!(decompress(Deflate, deflated.slice_to(len - 2), 100), Err(CorruptData));
+
+        // A block of the reserved type, 3
+        let mut gzipped = compress(Gzip, data);
+        gzipped[10] = 0xff;
+        assert_eq!(decompress(Gzip, gzipped, 100), Err(CorruptData));
+        let mut deflated = compress(Deflate, data);
+        deflated[2] = 0xff;
+        assert_eq!(decompress(Deflate, deflated, 100), Err(CorruptData));
+
+        // Something after the compressed data
+        let mut deflated = deflate_bytes(data);
+        deflated.push(0);
+        assert_eq!(decompress(Deflate, deflated, 100), Err(CorruptData));
+    }
+
+    #[test]
+    fn test_decompress_too_long() {
+        let data = vec::from_elem(0x100000, 0u8);
+        let gzipped = compress(Gzip, data);
+        assert!(gzipped.len() < 0x10000);
+        assert_eq!(decompress(Gzip, gzipped, 0x100000), Ok(data.clone()));
+        assert_eq!(decompress(Gzip, gzipped, 0xfffff), Err(TooLong));
+        assert_eq!(decompress(Deflate, deflate_bytes(data), 0x1000), Err(TooLong));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(Coding::from_name("gzip"), Some(Gzip));
+        assert_eq!(Coding::from_name("X-GZIP"), Some(Gzip));
+        assert_eq!(Coding::from_name("deflate"), Some(Deflate));
+        assert_eq!(Coding::from_name("compress"), None);
+        assert_eq!(Gzip.name(), "gzip");
+    }
+}
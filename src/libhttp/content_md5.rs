@@ -0,0 +1,160 @@
+//! The Content-MD5 header (RFC 1864; RFC 2616, section 14.15): the MD5 digest of a body, in
+//! base64, with which the recipient can check that the body it got is that which was sent. The
+//! digest is of the body after any content-coding (compression, say), but before any
+//! transfer-coding.
+//!
+//! Content-MD5 is sent with a response by `ResponseWriter.send_content_md5`, and with a request by
+//! `RequestBuilder.content_md5`. A request body read into `Request.body` is checked if
+//! `Config.verify_content_md5` is set, and answered with 400 Bad Request should it not match. A
+//! response body is checked if `RequestWriter.verify_content_md5` is, as is a body read with
+//! `Md5Reader`, as a streamed request body can be; one which doesn't match raises `io_error`, once
+//! it has been read to its end, with an error for which `is_mismatch` is true.
+
+use std::rt::io::{Reader, io_error, IoError, OtherIoError};
+use extra::base64::{ToBase64, FromBase64, STANDARD};
+use extra::crypto::digest::Digest;
+use extra::crypto::md5::Md5;
+
+/// The description of the error raised when a body doesn't match its Content-MD5.
+static MISMATCH_DESC: &'static str = "Body does not match its Content-MD5";
+
+/// The Content-MD5 of the body given.
+pub fn content_md5(body: &[u8]) -> ~str {
+    let mut md5 = Md5::new();
+    md5.input(body);
+    digest_value(&mut md5)
+}
+
+/// The MD5 digest of what has been input, as it goes in a Content-MD5 header.
+pub fn digest_value(md5: &mut Md5) -> ~str {
+    let mut digest = [0u8, ..16];
+    md5.result(digest);
+    digest.to_base64(STANDARD)
+}
+
+/// Whether the MD5 digest of what has been input is that given by the value of a Content-MD5
+/// header. A value which isn't valid base64, or of the wrong length, matches nothing.
+pub fn digest_matches(md5: &mut Md5, value: &str) -> bool {
+    let mut digest = [0u8, ..16];
+    md5.result(digest);
+    match value.trim().from_base64() {
+        Ok(expected) => expected.as_slice() == digest.as_slice(),
+        Err(*) => false,
+    }
+}
+
+/// Whether the body given matches the value of a Content-MD5 header (see `digest_matches`).
+pub fn body_matches(body: &[u8], value: &str) -> bool {
+    let mut md5 = Md5::new();
+    md5.input(body);
+    digest_matches(&mut md5, value)
+}
+
+/// The error to raise when a body doesn't match its Content-MD5.
+pub fn mismatch_err() -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: MISMATCH_DESC,
+        detail: None,
+    }
+}
+
+/// Whether an error raised in reading a body was for it not matching its Content-MD5.
+pub fn is_mismatch(error: &IoError) -> bool {
+    error.desc == MISMATCH_DESC
+}
+
+/// A reader of a body which works out the MD5 digest of it as it is read; once the end of it has
+/// been read, should the digest not be that given in the body's Content-MD5, `io_error` is raised
+/// with `mismatch_err()`.
+pub struct Md5Reader<R> {
+    priv reader: R,
+    priv md5: Md5,
+    priv expected: ~str,
+    /// Whether the end of the body has been read, and the digest checked.
+    priv finished: bool,
+    priv matched: bool,
+}
+
+impl<R: Reader> Md5Reader<R> {
+    /// Read the body from the reader, expecting it to match the value of its Content-MD5 header.
+    pub fn new(reader: R, expected: ~str) -> Md5Reader<R> {
+        Md5Reader {
+            reader: reader,
+            md5: Md5::new(),
+            expected: expected,
+            finished: false,
+            matched: false,
+        }
+    }
+
+    /// Whether the body has been read to its end, and matched its Content-MD5.
+    pub fn matched(&self) -> bool {
+        self.matched
+    }
+
+    /// Check the digest, as can only be done once, at the end of the body.
+    fn check(&mut self) {
+        self.finished = true;
+        self.matched = digest_matches(&mut self.md5, self.expected);
+        if !self.matched {
+            io_error::cond.raise(mismatch_err());
+        }
+    }
+}
+
+impl<R: Reader> Reader for Md5Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        match self.reader.read(buf) {
+            Some(n) => {
+                self.md5.input(buf.slice_to(n));
+                Some(n)
+            },
+            None => {
+                if !self.finished && self.reader.eof() {
+                    self.check();
+                }
+                None
+            },
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        self.reader.eof()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::{Reader, io_error};
+    use std::rt::io::mem::MemReader;
+    use std::rt::io::extensions::ReaderUtil;
+    use super::{content_md5, body_matches, Md5Reader, is_mismatch};
+
+    #[test]
+    fn test_content_md5() {
+        assert_eq!(content_md5(bytes!("Hello world")), ~"PiWWCnnbxptnTNTsZ6csYg==");
+        assert_eq!(content_md5([]), ~"1B2M2Y8AsgTpgAmY7PhCfg==");
+        assert!(body_matches(bytes!("Hello world"), " PiWWCnnbxptnTNTsZ6csYg== "));
+        assert!(!body_matches(bytes!("Hello world!"), "PiWWCnnbxptnTNTsZ6csYg=="));
+        assert!(!body_matches(bytes!("Hello world"), "PiWWCnnbxptnTNTsZ6cs"));
+        assert!(!body_matches(bytes!("Hello world"), "not base64!"));
+    }
+
+    #[test]
+    fn test_md5_reader() {
+        let body = bytes!("Hello world").to_owned();
+        let mut reader = Md5Reader::new(MemReader::new(body.clone()),
+                                        ~"PiWWCnnbxptnTNTsZ6csYg==");
+        assert_eq!(reader.read_to_end(), body);
+        assert!(reader.matched());
+
+        let mut mismatched = false;
+        let mut reader = Md5Reader::new(MemReader::new(body.clone()), content_md5([]));
+        do io_error::cond.trap(|e| mismatched = is_mismatch(&e)).inside {
+            reader.read_to_end();
+        }
+        assert!(mismatched);
+        assert!(!reader.matched());
+    }
+}
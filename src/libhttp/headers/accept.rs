@@ -0,0 +1,205 @@
+//! The Accept request header, defined in RFC 2616, Section 14.1.
+//!
+//! Accept         = "Accept" ":" #( media-range [ accept-params ] )
+//! media-range    = ( "*/*" | ( type "/" "*" ) | ( type "/" subtype ) ) *( ";" parameter )
+//! accept-params  = ";" "q" "=" qvalue *( accept-extension )
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use headers::content_type::MediaType;
+use headers::serialization_utils::{WriterUtil, push_parameters, push_quality, parse_quality};
+
+/// A range of media types which the user agent accepts, and how much it wants them.
+#[deriving(Clone, Eq)]
+pub struct MediaRange {
+    /// The type, in lower case, or `*` for any type.
+    type_: ~str,
+
+    /// The subtype, in lower case, or `*` for any subtype.
+    subtype: ~str,
+
+    /// Parameters which a media type must also have to be in the range, such as `level=1`.
+    /// (Any accept-extensions after the quality are discarded.)
+    parameters: ~[(~str, ~str)],
+
+    /// The quality, from 0 (not acceptable) to 1; `None` means the default, 1.
+    quality: Option<f64>,
+}
+
+impl MediaRange {
+    /// The quality as a number, defaulting to 1.
+    pub fn q(&self) -> f64 {
+        match self.quality {
+            Some(q) => q,
+            None => 1.0,
+        }
+    }
+
+    /// How specifically the range matches the media type, if it does at all: `*/*` is the least
+    /// specific, then `type/*`, `type/subtype`, and `type/subtype` with parameters the most.
+    pub fn specificity(&self, media_type: &MediaType) -> Option<uint> {
+        if self.type_.as_slice() == "*" {
+            return Some(0);
+        }
+        if !self.type_.eq_ignore_ascii_case(media_type.type_) {
+            return None;
+        }
+        if self.subtype.as_slice() == "*" {
+            return Some(1);
+        }
+        if !self.subtype.eq_ignore_ascii_case(media_type.subtype) {
+            return None;
+        }
+        for &(ref name, ref value) in self.parameters.iter() {
+            let has_parameter = media_type.parameters.iter().any(|&(ref n, ref v)| {
+                n.eq_ignore_ascii_case(*name) && v.eq_ignore_ascii_case(*value)
+            });
+            if !has_parameter {
+                return None;
+            }
+        }
+        Some(2 + self.parameters.len())
+    }
+}
+
+impl super::CommaListHeaderConvertible for MediaRange {}
+
+impl super::HeaderConvertible for MediaRange {
+    fn from_stream<T: Reader>(reader: &mut super::HeaderValueByteIterator<T>)
+            -> Option<MediaRange> {
+        let type_ = match reader.read_token() {
+            Some(token) => token.to_ascii_lower(),
+            None => return None,
+        };
+        if reader.next() != Some('/' as u8) {
+            return None;
+        }
+        let subtype = match reader.read_token() {
+            Some(token) => token.to_ascii_lower(),
+            None => return None,
+        };
+        if type_.as_slice() == "*" && subtype.as_slice() != "*" {
+            return None;
+        }
+        let all_parameters = match reader.read_parameters() {
+            Some(parameters) => parameters,
+            None => return None,
+        };
+        // The q parameter separates the media type parameters from the accept-extensions.
+        let mut parameters = ~[];
+        let mut quality = None;
+        for (name, value) in all_parameters.move_iter() {
+            if name.eq_ignore_ascii_case("q") {
+                match parse_quality(value.as_slice()) {
+                    Some(q) => quality = Some(q),
+                    None => return None,
+                }
+                break;
+            }
+            parameters.push((name, value));
+        }
+        Some(MediaRange {
+            type_: type_,
+            subtype: subtype,
+            parameters: parameters,
+            quality: quality,
+        })
+    }
+
+    fn to_stream<T: Writer>(&self, writer: &mut T) {
+        writer.write_token(self.type_);
+        writer.write(['/' as u8]);
+        writer.write_token(self.subtype);
+        writer.write_parameters(self.parameters);
+        writer.write_quality(self.quality);
+    }
+
+    fn http_value(&self) -> ~str {
+        let s = format!("{}/{}", self.type_, self.subtype);
+        push_quality(push_parameters(s, self.parameters), self.quality)
+    }
+}
+
+/// Choose which of the media types the handler can produce is the most acceptable to the user
+/// agent, as expressed in its Accept header. Each media type takes the quality of the most
+/// specific range which matches it; where media types are equally acceptable, the earlier in
+/// `available` is preferred.
+///
+/// If none of the media types is acceptable, `None` is returned, and a 406 (Not Acceptable)
+/// response should be sent. Pass an empty list if there was no Accept header: all media types
+/// are then acceptable.
+pub fn negotiate_media_type<'a>(accept: &[MediaRange], available: &'a [MediaType])
+        -> Option<&'a MediaType> {
+    if accept.is_empty() {
+        return if available.is_empty() { None } else { Some(&available[0]) };
+    }
+    let mut best = None;
+    let mut best_q = 0.0;
+    for media_type in available.iter() {
+        let mut q = 0.0;
+        let mut best_specificity = None;
+        for range in accept.iter() {
+            let specificity = range.specificity(media_type);
+            let more_specific = match (specificity, best_specificity) {
+                (Some(s), Some(best)) => s > best,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if more_specific {
+                best_specificity = specificity;
+                q = range.q();
+            }
+        }
+        if q > best_q {
+            best = Some(media_type);
+            best_q = q;
+        }
+    }
+    best
+}
+
+#[test]
+fn test_media_range() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    fn range(type_: &str, subtype: &str, parameters: ~[(~str, ~str)], quality: Option<f64>)
+            -> MediaRange {
+        MediaRange {
+            type_: type_.to_owned(),
+            subtype: subtype.to_owned(),
+            parameters: parameters,
+            quality: quality,
+        }
+    }
+    assert_conversion_correct("text/html", ~[range("text", "html", ~[], None)]);
+    assert_conversion_correct("text/*;q=0.300, */*;q=0.100",
+                              ~[range("text", "*", ~[], Some(0.3)),
+                                range("*", "*", ~[], Some(0.1))]);
+    assert_interpretation_correct("Text/HTML;level=1;q=0.7;ext=x, image/png",
+                                  ~[range("text", "html", ~[(~"level", ~"1")], Some(0.7)),
+                                    range("image", "png", ~[], None)]);
+    assert_invalid::<~[MediaRange]>("text");
+    assert_invalid::<~[MediaRange]>("*/html");
+    assert_invalid::<~[MediaRange]>("text/html;q=high");
+}
+
+#[test]
+fn test_negotiate_media_type() {
+    use headers::test_utils::from_stream_with_str;
+    let html = MediaType(~"text", ~"html", ~[]);
+    let json = MediaType(~"application", ~"json", ~[]);
+    let available = [html.clone(), json.clone()];
+    fn negotiate<'a>(accept: &str, available: &'a [MediaType]) -> Option<&'a MediaType> {
+        let accept: ~[MediaRange] = from_stream_with_str(accept).unwrap();
+        negotiate_media_type(accept, available)
+    }
+    assert_eq!(negotiate("application/json", available), Some(&json));
+    assert_eq!(negotiate("text/*, application/json;q=0.5", available), Some(&html));
+    assert_eq!(negotiate("*/*", available), Some(&html));
+    // The most specific range decides
+    assert_eq!(negotiate("*/*, text/html;q=0.2", available), Some(&json));
+    assert_eq!(negotiate("text/*;q=0, */*;q=0.1, text/html", available), Some(&html));
+    assert_eq!(negotiate("image/png", available), None);
+    assert_eq!(negotiate("text/html;level=1", available), None);
+    assert_eq!(negotiate_media_type([], available), Some(&html));
+}
@@ -0,0 +1,148 @@
+//! The Accept-Encoding request header, defined in RFC 2616, Section 14.3.
+//!
+//! Accept-Encoding  = "Accept-Encoding" ":" 1#( codings [ ";" "q" "=" qvalue ] )
+//! codings          = ( content-coding | "*" )
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use headers::serialization_utils::{WriterUtil, push_quality, parse_quality};
+
+/// A content-coding which the user agent accepts, and how much it wants it.
+#[deriving(Clone, Eq)]
+pub struct AcceptableCoding {
+    /// The content-coding, in lower case, or `*` for any coding not otherwise listed.
+    coding: ~str,
+
+    /// The quality, from 0 (not acceptable) to 1; `None` means the default, 1.
+    quality: Option<f64>,
+}
+
+impl AcceptableCoding {
+    /// The quality as a number, defaulting to 1.
+    pub fn q(&self) -> f64 {
+        match self.quality {
+            Some(q) => q,
+            None => 1.0,
+        }
+    }
+}
+
+impl super::CommaListHeaderConvertible for AcceptableCoding {}
+
+impl super::HeaderConvertible for AcceptableCoding {
+    fn from_stream<T: Reader>(reader: &mut super::HeaderValueByteIterator<T>)
+            -> Option<AcceptableCoding> {
+        let coding = match reader.read_token() {
+            Some(token) => token.to_ascii_lower(),
+            None => return None,
+        };
+        let parameters = match reader.read_parameters() {
+            Some(parameters) => parameters,
+            None => return None,
+        };
+        let mut quality = None;
+        for &(ref key, ref value) in parameters.iter() {
+            if key.eq_ignore_ascii_case("q") {
+                match parse_quality(*value) {
+                    Some(q) => quality = Some(q),
+                    None => return None,
+                }
+            }
+        }
+        Some(AcceptableCoding { coding: coding, quality: quality })
+    }
+
+    fn to_stream<T: Writer>(&self, writer: &mut T) {
+        writer.write_token(self.coding);
+        writer.write_quality(self.quality);
+    }
+
+    fn http_value(&self) -> ~str {
+        push_quality(self.coding.clone(), self.quality)
+    }
+}
+
+/// Choose which of the content-codings the server can send (such as `gzip` and `identity`) is the
+/// most acceptable to the user agent, as expressed in its Accept-Encoding header. Where codings
+/// are equally acceptable, the earlier in `supported` is preferred.
+///
+/// `identity` (no compression) is acceptable unless excluded explicitly or by `*;q=0`; any
+/// other coding must be listed, or covered by `*`, to be acceptable. If none of the codings is
+/// acceptable, `None` is returned, and a 406 (Not Acceptable) response should be sent.
+///
+/// Pass an empty list if there was no Accept-Encoding header: any coding is then acceptable,
+/// though `identity` is preferred if it is supported (RFC 2616, section 14.3).
+pub fn negotiate_encoding<'a>(accept: &[AcceptableCoding], supported: &[&'a str])
+        -> Option<&'a str> {
+    if accept.is_empty() {
+        for &coding in supported.iter() {
+            if coding.eq_ignore_ascii_case("identity") {
+                return Some(coding);
+            }
+        }
+        return if supported.is_empty() { None } else { Some(supported[0]) };
+    }
+    let mut best = None;
+    let mut best_q = 0.0;
+    for &coding in supported.iter() {
+        let q = quality_of(accept, coding);
+        if q > best_q {
+            best = Some(coding);
+            best_q = q;
+        }
+    }
+    best
+}
+
+/// The quality which the Accept-Encoding list gives the coding.
+fn quality_of(accept: &[AcceptableCoding], coding: &str) -> f64 {
+    let mut wildcard = None;
+    for acceptable in accept.iter() {
+        if acceptable.coding.eq_ignore_ascii_case(coding) {
+            return acceptable.q();
+        } else if acceptable.coding.as_slice() == "*" {
+            wildcard = Some(acceptable.q());
+        }
+    }
+    match wildcard {
+        Some(q) => q,
+        None if coding.eq_ignore_ascii_case("identity") => 1.0,
+        None => 0.0,
+    }
+}
+
+#[test]
+fn test_acceptable_coding() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("gzip", ~[AcceptableCoding { coding: ~"gzip", quality: None }]);
+    assert_conversion_correct("gzip, *;q=0.500",
+                              ~[AcceptableCoding { coding: ~"gzip", quality: None },
+                                AcceptableCoding { coding: ~"*", quality: Some(0.5) }]);
+    assert_interpretation_correct("GZip;Q=0.8,identity;q=0",
+                                  ~[AcceptableCoding { coding: ~"gzip", quality: Some(0.8) },
+                                    AcceptableCoding { coding: ~"identity", quality: Some(0.0) }]);
+    assert_invalid::<~[AcceptableCoding]>("gzip;q=2");
+    assert_invalid::<~[AcceptableCoding]>("gzip;q");
+    assert_invalid::<~[AcceptableCoding]>(";q=1");
+}
+
+#[test]
+fn test_negotiate_encoding() {
+    use headers::test_utils::from_stream_with_str;
+    fn negotiate(accept: &str, supported: &[&'static str]) -> Option<&'static str> {
+        let accept: ~[AcceptableCoding] = from_stream_with_str(accept).unwrap();
+        negotiate_encoding(accept, supported)
+    }
+    assert_eq!(negotiate("gzip, deflate", ["gzip", "deflate", "identity"]), Some("gzip"));
+    assert_eq!(negotiate("gzip;q=0.5, deflate", ["gzip", "deflate", "identity"]), Some("deflate"));
+    // identity is acceptable unless excluded; other codings have to be listed
+    assert_eq!(negotiate("deflate;q=0.5", ["gzip", "identity"]), Some("identity"));
+    assert_eq!(negotiate("deflate", ["gzip"]), None);
+    assert_eq!(negotiate("*", ["gzip", "identity"]), Some("gzip"));
+    assert_eq!(negotiate("gzip;q=0, *;q=0.1", ["gzip", "br"]), Some("br"));
+    assert_eq!(negotiate("*;q=0", ["gzip", "identity"]), None);
+    assert_eq!(negotiate("identity;q=0, gzip;q=0", ["gzip", "identity"]), None);
+    assert_eq!(negotiate_encoding([], ["gzip", "identity"]), Some("identity"));
+    assert_eq!(negotiate_encoding([], ["gzip"]), Some("gzip"));
+}
@@ -0,0 +1,176 @@
+//! The Accept-Language request header, defined in RFC 2616, Section 14.4.
+//!
+//! Accept-Language = "Accept-Language" ":" 1#( language-range [ ";" "q" "=" qvalue ] )
+//! language-range  = ( ( 1*8ALPHA *( "-" 1*8ALPHA ) ) | "*" )
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use headers::serialization_utils::{WriterUtil, push_quality, parse_quality};
+
+/// A range of languages which the user agent accepts, and how much it wants them.
+#[deriving(Clone, Eq)]
+pub struct LanguageRange {
+    /// The language tag (such as `en-GB`), as it was given, or `*` for any language not otherwise
+    /// listed. Tags are compared without regard to case.
+    tag: ~str,
+
+    /// The quality, from 0 (not acceptable) to 1; `None` means the default, 1.
+    quality: Option<f64>,
+}
+
+impl LanguageRange {
+    /// The quality as a number, defaulting to 1.
+    pub fn q(&self) -> f64 {
+        match self.quality {
+            Some(q) => q,
+            None => 1.0,
+        }
+    }
+
+    /// Whether the range matches the language tag: it is the tag, or a prefix of it followed by
+    /// `-` (so `en` matches `en-GB`, but not `eng`), or `*`.
+    pub fn matches(&self, tag: &str) -> bool {
+        self.tag.as_slice() == "*" || is_prefix_of(self.tag, tag)
+    }
+}
+
+/// Whether the language range `prefix` is `tag`, or a prefix of it followed by `-`, ignoring case.
+fn is_prefix_of(prefix: &str, tag: &str) -> bool {
+    tag.len() >= prefix.len() && tag.slice_to(prefix.len()).eq_ignore_ascii_case(prefix)
+        && (tag.len() == prefix.len() || tag[prefix.len()] == '-' as u8)
+}
+
+impl super::CommaListHeaderConvertible for LanguageRange {}
+
+impl super::HeaderConvertible for LanguageRange {
+    fn from_stream<T: Reader>(reader: &mut super::HeaderValueByteIterator<T>)
+            -> Option<LanguageRange> {
+        let tag = match reader.read_token() {
+            Some(token) => token,
+            None => return None,
+        };
+        let valid = tag.as_slice() == "*" || tag.split_iter('-').all(|subtag| {
+            subtag.len() >= 1 && subtag.len() <= 8 && subtag.iter().all(|c| c.is_alphanumeric())
+        });
+        if !valid {
+            return None;
+        }
+        let parameters = match reader.read_parameters() {
+            Some(parameters) => parameters,
+            None => return None,
+        };
+        let mut quality = None;
+        for &(ref key, ref value) in parameters.iter() {
+            if key.eq_ignore_ascii_case("q") {
+                match parse_quality(*value) {
+                    Some(q) => quality = Some(q),
+                    None => return None,
+                }
+            }
+        }
+        Some(LanguageRange { tag: tag, quality: quality })
+    }
+
+    fn to_stream<T: Writer>(&self, writer: &mut T) {
+        writer.write_token(self.tag);
+        writer.write_quality(self.quality);
+    }
+
+    fn http_value(&self) -> ~str {
+        push_quality(self.tag.clone(), self.quality)
+    }
+}
+
+/// Choose which of the languages the handler has (such as `en`, `en-US` and `fr`) is the most
+/// acceptable to the user agent, as expressed in its Accept-Language header. Each language takes
+/// the quality of the longest range which matches it; where languages are equally acceptable, the
+/// earlier in `available` is preferred.
+///
+/// Should no range match a language, but the language be a prefix of a range (`en` for a user
+/// agent asking for `en-GB`), it is taken as a fallback, at the quality of that range, but behind
+/// any language matched directly.
+///
+/// `None` is returned if no language is acceptable; rather than sending a 406 (Not Acceptable)
+/// response, it is usually better to fall back to a default language. Pass an empty list if there
+/// was no Accept-Language header: all languages are then acceptable.
+pub fn negotiate_language<'a>(accept: &[LanguageRange], available: &[&'a str])
+        -> Option<&'a str> {
+    if accept.is_empty() {
+        return if available.is_empty() { None } else { Some(available[0]) };
+    }
+    let mut best = None;
+    let mut best_score = (0.0, false);
+    for &tag in available.iter() {
+        let (q, direct) = score(accept, tag);
+        let (best_q, best_direct) = best_score;
+        if q > 0.0 && (q > best_q || (q == best_q && direct && !best_direct)) {
+            best = Some(tag);
+            best_score = (q, direct);
+        }
+    }
+    best
+}
+
+/// The quality which the ranges give the language tag, and whether a range matched it directly
+/// (rather than as a fallback).
+fn score(accept: &[LanguageRange], tag: &str) -> (f64, bool) {
+    let mut longest = None;
+    for range in accept.iter() {
+        if !range.matches(tag) {
+            continue;
+        }
+        let len = if range.tag.as_slice() == "*" { 0 } else { range.tag.len() };
+        match longest {
+            Some((longest_len, _)) if longest_len >= len => (),
+            _ => longest = Some((len, range.q())),
+        }
+    }
+    match longest {
+        Some((_, q)) => return (q, true),
+        None => (),
+    }
+    let mut fallback_q = 0.0;
+    for range in accept.iter() {
+        if range.tag.as_slice() != "*" && is_prefix_of(tag, range.tag) && range.q() > fallback_q {
+            fallback_q = range.q();
+        }
+    }
+    (fallback_q, false)
+}
+
+#[test]
+fn test_language_range() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("en-GB", ~[LanguageRange { tag: ~"en-GB", quality: None }]);
+    assert_conversion_correct("da, en;q=0.700, *;q=0.100",
+                              ~[LanguageRange { tag: ~"da", quality: None },
+                                LanguageRange { tag: ~"en", quality: Some(0.7) },
+                                LanguageRange { tag: ~"*", quality: Some(0.1) }]);
+    assert_interpretation_correct("zh-Hant-TW;Q=0.5",
+                                  ~[LanguageRange { tag: ~"zh-Hant-TW", quality: Some(0.5) }]);
+    assert_invalid::<~[LanguageRange]>("en-toolongtag");
+    assert_invalid::<~[LanguageRange]>("en--GB");
+    assert_invalid::<~[LanguageRange]>("en;q=1.1");
+}
+
+#[test]
+fn test_negotiate_language() {
+    use headers::test_utils::from_stream_with_str;
+    fn negotiate(accept: &str, available: &[&'static str]) -> Option<&'static str> {
+        let accept: ~[LanguageRange] = from_stream_with_str(accept).unwrap();
+        negotiate_language(accept, available)
+    }
+    assert_eq!(negotiate("fr, en;q=0.8", ["en", "fr"]), Some("fr"));
+    assert_eq!(negotiate("en", ["en-US", "fr"]), Some("en-US"));
+    assert_eq!(negotiate("EN-gb", ["en", "en-GB"]), Some("en-GB"));
+    assert_eq!(negotiate("en-GB", ["fr", "en"]), Some("en"));
+    // A fallback is behind a direct match of the same quality
+    assert_eq!(negotiate("en-GB, fr", ["en", "fr"]), Some("fr"));
+    // The longest range decides
+    assert_eq!(negotiate("en, en-US;q=0.2", ["en-US", "en-GB"]), Some("en-GB"));
+    assert_eq!(negotiate("*;q=0.1, de", ["fr", "de"]), Some("de"));
+    assert_eq!(negotiate("de, *;q=0", ["fr"]), None);
+    assert_eq!(negotiate("de", ["fr"]), None);
+    assert_eq!(negotiate_language([], ["fr", "de"]), Some("fr"));
+}
@@ -0,0 +1,331 @@
+//! The Authorization and Proxy-Authorization request headers, defined in RFC 2617, and the
+//! challenges of the WWW-Authenticate and Proxy-Authenticate response headers which ask for them.
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use extra::base64::{ToBase64, FromBase64, STANDARD};
+use extra::crypto::digest::Digest;
+use extra::crypto::md5::Md5;
+use headers::serialization_utils::{push_quoted_string, quoted_string};
+use headers::{HeaderConvertible, HeaderValueByteIterator};
+use percent_encoding::bytes_to_str;
+
+/// The credentials with which a request is authenticated.
+#[deriving(Clone, Eq)]
+pub enum Credentials {
+    /// The Basic scheme: a user-id and a password (RFC 2617, section 2).
+    Basic(~str, ~str),
+    /// The Digest scheme: its parameters, as `username` and `response`, with lowercase names
+    /// (RFC 2617, section 3.2.2); see `digest_credentials`.
+    Digest(~[(~str, ~str)]),
+    /// Any other scheme, by its name, and the rest of the header, as it was given.
+    OtherCredentials(~str, ~str),
+}
+
+impl ToStr for Credentials {
+    fn to_str(&self) -> ~str {
+        match *self {
+            Basic(ref user, ref password) => {
+                let user_pass = format!("{}:{}", *user, *password);
+                format!("Basic {}", user_pass.as_bytes().to_base64(STANDARD))
+            },
+            Digest(ref params) => {
+                let params: ~[~str] = params.iter().map(|&(ref name, ref value)| {
+                    // These are tokens; everything else is a quoted-string.
+                    match name.as_slice() {
+                        "qop" | "nc" | "algorithm" => format!("{}={}", *name, *value),
+                        _ => push_quoted_string(format!("{}=", *name), *value),
+                    }
+                }).collect();
+                format!("Digest {}", params.connect(", "))
+            },
+            OtherCredentials(ref scheme, ref rest) if rest.is_empty() => scheme.clone(),
+            OtherCredentials(ref scheme, ref rest) => format!("{} {}", *scheme, *rest),
+        }
+    }
+}
+
+impl HeaderConvertible for Credentials {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<Credentials> {
+        let s = reader.collect_to_str();
+        let (scheme, rest) = match s.find(' ') {
+            Some(i) => (s.slice_to(i), s.slice_from(i + 1).trim()),
+            None => (s.as_slice(), ""),
+        };
+        if scheme.is_empty() {
+            return None;
+        }
+        if scheme.eq_ignore_ascii_case("digest") {
+            return match parse_auth_params(rest) {
+                Some(params) => Some(Digest(params)),
+                None => None,
+            };
+        }
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return Some(OtherCredentials(scheme.to_owned(), rest.to_owned()));
+        }
+        let user_pass = match rest.from_base64() {
+            Ok(bytes) => bytes_to_str(bytes),
+            Err(_) => return None,
+        };
+        // The user-id can't have a colon in it; the password can.
+        match user_pass.find(':') {
+            Some(i) => Some(Basic(user_pass.slice_to(i).to_owned(),
+                                  user_pass.slice_from(i + 1).to_owned())),
+            None => None,
+        }
+    }
+
+    fn to_stream<W: Writer>(&self, writer: &mut W) {
+        writer.write(self.to_str().as_bytes());
+    }
+
+    fn http_value(&self) -> ~str {
+        self.to_str()
+    }
+}
+
+/// The challenge to put in WWW-Authenticate (or Proxy-Authenticate) for the Basic scheme, as
+/// `Basic realm="example"`; the realm tells the user which password is wanted.
+pub fn basic_challenge(realm: &str) -> ~str {
+    push_quoted_string(~"Basic realm=", realm)
+}
+
+/// Parse a list of auth-params, as `realm="example", qop=auth`, each value being a token or a
+/// quoted-string; the names are lowercased.
+pub fn parse_auth_params(s: &str) -> Option<~[(~str, ~str)]> {
+    let mut params = ~[];
+    let mut rest = s.trim_left();
+    while !rest.is_empty() {
+        let name = match rest.find('=') {
+            Some(i) => {
+                let name = rest.slice_to(i).trim();
+                rest = rest.slice_from(i + 1).trim_left();
+                name
+            },
+            None => return None,
+        };
+        if name.is_empty() || name.contains_char(',') || name.contains_char(' ') {
+            return None;
+        }
+        let value = if rest.starts_with("\"") {
+            let mut value = ~"";
+            let mut escaped = false;
+            let mut end = None;
+            for (i, c) in rest.char_offset_iter().skip(1) {
+                if escaped {
+                    value.push_char(c);
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    end = Some(i + 1);
+                    break;
+                } else {
+                    value.push_char(c);
+                }
+            }
+            match end {
+                Some(i) => rest = rest.slice_from(i),
+                None => return None,
+            }
+            value
+        } else {
+            let end = match rest.find(',') {
+                Some(i) => i,
+                None => rest.len(),
+            };
+            let value = rest.slice_to(end).trim().to_owned();
+            rest = rest.slice_from(end);
+            value
+        };
+        params.push((name.to_ascii_lower(), value));
+        rest = rest.trim_left();
+        if rest.starts_with(",") {
+            rest = rest.slice_from(1).trim_left();
+        } else if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(params)
+}
+
+/// The value of the auth-param named, if there is one.
+pub fn auth_param<'a>(params: &'a [(~str, ~str)], name: &str) -> Option<&'a str> {
+    for &(ref n, ref value) in params.iter() {
+        if n.as_slice() == name {
+            return Some(value.as_slice());
+        }
+    }
+    None
+}
+
+/// Parse a challenge from WWW-Authenticate (or Proxy-Authenticate), as `Digest realm="example",
+/// nonce="..."`, into its scheme and parameters. Only one challenge is expected.
+pub fn parse_challenge(s: &str) -> Option<(~str, ~[(~str, ~str)])> {
+    let s = s.trim();
+    let (scheme, rest) = match s.find(' ') {
+        Some(i) => (s.slice_to(i), s.slice_from(i + 1)),
+        None => (s, ""),
+    };
+    if scheme.is_empty() {
+        return None;
+    }
+    match parse_auth_params(rest) {
+        Some(params) => Some((scheme.to_owned(), params)),
+        None => None,
+    }
+}
+
+/// The challenge to put in WWW-Authenticate (or Proxy-Authenticate) for the Digest scheme, with
+/// the `auth` quality of protection and the MD5 algorithm. `stale` tells the client that its
+/// credentials were right but the nonce it used has expired, so that it needn't ask the user again.
+pub fn digest_challenge(realm: &str, nonce: &str, opaque: Option<&str>, stale: bool) -> ~str {
+    let mut s = format!("Digest realm={}, qop=\"auth\", nonce={}", quoted_string(realm),
+                        quoted_string(nonce));
+    match opaque {
+        Some(opaque) => {
+            s.push_str(", opaque=");
+            s = push_quoted_string(s, opaque);
+        },
+        None => (),
+    }
+    if stale {
+        s.push_str(", stale=true");
+    }
+    s.push_str(", algorithm=MD5");
+    s
+}
+
+fn md5_hex(s: &str) -> ~str {
+    let mut md5 = Md5::new();
+    md5.input_str(s);
+    md5.result_str()
+}
+
+/// H(A1) for the MD5 algorithm: the hash of the user's password, for the realm, from which the
+/// Digest response is computed. A server can keep this rather than the password itself.
+pub fn digest_ha1(user: &str, realm: &str, password: &str) -> ~str {
+    md5_hex(format!("{}:{}:{}", user, realm, password))
+}
+
+/// The Digest `response` for a request, from H(A1), the nonce, and with the `auth` quality of
+/// protection, the nonce-count (as eight hex digits) and client nonce; without one, the response
+/// is the older form of RFC 2069.
+pub fn digest_response(ha1: &str, nonce: &str, qop_auth: Option<(&str, &str)>, method: &str,
+                       uri: &str) -> ~str {
+    let ha2 = md5_hex(format!("{}:{}", method, uri));
+    match qop_auth {
+        Some((nc, cnonce)) => md5_hex(format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2)),
+        None => md5_hex(format!("{}:{}:{}", ha1, nonce, ha2)),
+    }
+}
+
+/// The Digest credentials with which to answer a challenge (from WWW-Authenticate, say), for a
+/// request with the method and Request-URI given; `cnonce` is a random string the client chooses,
+/// and `nc` counts the requests made with the challenge's nonce, from 1. `None` is returned if the
+/// challenge is not for Digest, or is for an algorithm or quality of protection other than MD5
+/// and `auth`.
+pub fn digest_credentials(challenge: &str, user: &str, password: &str, method: &str, uri: &str,
+                          cnonce: &str, nc: uint) -> Option<Credentials> {
+    let params = match parse_challenge(challenge) {
+        Some((scheme, params)) => if scheme.eq_ignore_ascii_case("digest") {
+            params
+        } else {
+            return None;
+        },
+        None => return None,
+    };
+    let (realm, nonce) = match (auth_param(params, "realm"), auth_param(params, "nonce")) {
+        (Some(realm), Some(nonce)) => (realm, nonce),
+        _ => return None,
+    };
+    match auth_param(params, "algorithm") {
+        Some(algorithm) if !algorithm.eq_ignore_ascii_case("MD5") => return None,
+        _ => (),
+    }
+    let qop_auth = match auth_param(params, "qop") {
+        Some(qop) => if qop.split_iter(',').any(|q| q.trim() == "auth") {
+            true
+        } else {
+            return None;
+        },
+        None => false,
+    };
+    let nc = format!("{:08x}", nc);
+    let ha1 = digest_ha1(user, realm, password);
+    let response = if qop_auth {
+        digest_response(ha1, nonce, Some((nc.as_slice(), cnonce)), method, uri)
+    } else {
+        digest_response(ha1, nonce, None, method, uri)
+    };
+    let mut credentials = ~[(~"username", user.to_owned()), (~"realm", realm.to_owned()),
+                            (~"nonce", nonce.to_owned()), (~"uri", uri.to_owned())];
+    if qop_auth {
+        credentials.push((~"qop", ~"auth"));
+        credentials.push((~"nc", nc.clone()));
+        credentials.push((~"cnonce", cnonce.to_owned()));
+    }
+    credentials.push((~"response", response));
+    match auth_param(params, "opaque") {
+        Some(opaque) => credentials.push((~"opaque", opaque.to_owned())),
+        None => (),
+    }
+    Some(Digest(credentials))
+}
+
+#[test]
+fn test_credentials() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+                              Basic(~"Aladdin", ~"open sesame"));
+    assert_conversion_correct("Basic dXNlcjpwYTpzcw==", Basic(~"user", ~"pa:ss"));
+    assert_interpretation_correct("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+                                  Basic(~"Aladdin", ~"open sesame"));
+    assert_conversion_correct("Bearer mF_9.B5f-4.1JqM", OtherCredentials(~"Bearer",
+                                                                          ~"mF_9.B5f-4.1JqM"));
+    assert_invalid::<Credentials>("");
+    // No colon between the user-id and the password
+    assert_invalid::<Credentials>("Basic QWxhZGRpbg==");
+    assert_invalid::<Credentials>("Basic !!!");
+}
+
+#[test]
+fn test_basic_challenge() {
+    assert_eq!(basic_challenge("WallyWorld"), ~"Basic realm=\"WallyWorld\"");
+    assert_eq!(basic_challenge("a \"b\""), ~"Basic realm=\"a \\\"b\\\"\"");
+}
+
+#[test]
+fn test_digest_credentials() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct};
+    // The example of RFC 2617, section 3.5
+    let challenge = "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+                     nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+                     opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"";
+    let credentials = digest_credentials(challenge, "Mufasa", "Circle Of Life", "GET",
+                                         "/dir/index.html", "0a4f113b", 1).unwrap();
+    assert_conversion_correct("Digest username=\"Mufasa\", realm=\"testrealm@host.com\", \
+                               nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+                               uri=\"/dir/index.html\", qop=auth, nc=00000001, \
+                               cnonce=\"0a4f113b\", \
+                               response=\"6629fae49393a05397450978507c4ef1\", \
+                               opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+                              credentials);
+    assert_interpretation_correct("digest Username=Mufasa,realm=\"a\\\"b\"",
+                                  Digest(~[(~"username", ~"Mufasa"), (~"realm", ~"a\"b")]));
+    assert_eq!(digest_credentials("Basic realm=\"a\"", "u", "p", "GET", "/", "c", 1), None);
+    assert_eq!(digest_credentials("Digest realm=\"a\", nonce=\"n\", algorithm=SHA-256", "u",
+                                  "p", "GET", "/", "c", 1), None);
+}
+
+#[test]
+fn test_parse_auth_params() {
+    assert_eq!(parse_auth_params("realm=\"a, b\", qop=auth"),
+               Some(~[(~"realm", ~"a, b"), (~"qop", ~"auth")]));
+    assert_eq!(parse_auth_params(""), Some(~[]));
+    assert_eq!(parse_auth_params("realm"), None);
+    assert_eq!(parse_auth_params("realm=\"a"), None);
+    assert_eq!(parse_auth_params("realm=\"a\" b"), None);
+}
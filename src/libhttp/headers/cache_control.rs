@@ -0,0 +1,329 @@
+//! The Cache-Control header (RFC 7234, section 5.2), by which requests and responses tell caches
+//! along the way what they may store and for how long, and what they may give in answer.
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::Reader;
+use std::u64;
+use headers::{HeaderConvertible, HeaderValueByteIterator, DeltaSeconds};
+use headers::serialization_utils::{maybe_quoted_string, maybe_unquote_string, quoted_string,
+                                   split_outside_quotes};
+use rfc2616::is_token;
+
+/// A single Cache-Control directive.
+#[deriving(Clone, Eq)]
+pub enum CacheDirective {
+    /// `no-cache`: a stored response mustn't be given without revalidating it first. In a
+    /// response, it may name fields, such as `Set-Cookie`, to which alone that applies, the rest
+    /// of the response being fine to give.
+    NoCache(~[~str]),
+    /// `no-store`: no part of the request or response is to be stored.
+    NoStore,
+    /// `no-transform`: the body is not to be transformed, as by recompressing images.
+    NoTransform,
+    /// `only-if-cached` (requests): answer from the cache, or with 504 Gateway Timeout.
+    OnlyIfCached,
+    /// `max-age`: in a response, the seconds for which it is fresh; in a request, the age beyond
+    /// which a stored response won't do.
+    MaxAge(DeltaSeconds),
+    /// `s-maxage` (responses): as `max-age`, but for shared caches alone, which it overrides
+    /// `max-age` and Expires for.
+    SMaxAge(DeltaSeconds),
+    /// `max-stale` (requests): a stale response will do, if stale by no more than the seconds
+    /// given, or by any amount if none are.
+    MaxStale(Option<DeltaSeconds>),
+    /// `min-fresh` (requests): a response will only do if it will still be fresh for the seconds
+    /// given.
+    MinFresh(DeltaSeconds),
+    /// `public` (responses): any cache may store the response, even if it would not normally be.
+    Public,
+    /// `private` (responses): only the user's own cache may store the response; a shared cache
+    /// may store it but for the fields named, if any are.
+    Private(~[~str]),
+    /// `must-revalidate` (responses): once stale, the response mustn't be given without
+    /// revalidating it, even if the request would allow it.
+    MustRevalidate,
+    /// `proxy-revalidate` (responses): as `must-revalidate`, but for shared caches alone.
+    ProxyRevalidate,
+    /// Any other directive, with its name as written, and its argument, if it has one.
+    Extension(~str, Option<~str>),
+}
+
+impl CacheDirective {
+    /// Parse a directive, as `max-age=60` or `no-cache="Set-Cookie"`. Directive names are
+    /// compared ignoring case, and arguments may be tokens or quoted-strings; `None` if the
+    /// directive is malformed, or if one which takes seconds is given anything but them.
+    pub fn parse(s: &str) -> Option<CacheDirective> {
+        let (name, argument) = match s.find('=') {
+            Some(i) => match maybe_unquote_string(s.slice_from(i + 1).trim()) {
+                Some(argument) => (s.slice_to(i).trim(), Some(argument)),
+                None => return None,
+            },
+            None => (s.trim(), None),
+        };
+        if name.is_empty() || !is_token(name) {
+            return None;
+        }
+        let seconds = match argument {
+            Some(ref argument) => parse_delta_seconds(argument.as_slice()),
+            None => None,
+        };
+        let fields = match argument {
+            Some(ref argument) => {
+                argument.split_iter(',').map(|f| f.trim()).filter(|f| !f.is_empty())
+                        .map(|f| f.to_owned()).collect()
+            },
+            None => ~[],
+        };
+        Some(match name.to_ascii_lower().as_slice() {
+            "no-cache" => NoCache(fields),
+            "no-store" => NoStore,
+            "no-transform" => NoTransform,
+            "only-if-cached" => OnlyIfCached,
+            "max-age" => match seconds { Some(n) => MaxAge(n), None => return None },
+            "s-maxage" => match seconds { Some(n) => SMaxAge(n), None => return None },
+            "max-stale" => match (seconds, argument.is_some()) {
+                (Some(n), _) => MaxStale(Some(n)),
+                (None, false) => MaxStale(None),
+                (None, true) => return None,
+            },
+            "min-fresh" => match seconds { Some(n) => MinFresh(n), None => return None },
+            "public" => Public,
+            "private" => Private(fields),
+            "must-revalidate" => MustRevalidate,
+            "proxy-revalidate" => ProxyRevalidate,
+            _ => Extension(name.to_owned(), argument),
+        })
+    }
+}
+
+impl ToStr for CacheDirective {
+    fn to_str(&self) -> ~str {
+        match *self {
+            NoCache(ref fields) if fields.is_empty() => ~"no-cache",
+            NoCache(ref fields) => format!("no-cache={}", quoted_string(fields.connect(", "))),
+            NoStore => ~"no-store",
+            NoTransform => ~"no-transform",
+            OnlyIfCached => ~"only-if-cached",
+            MaxAge(seconds) => format!("max-age={}", seconds),
+            SMaxAge(seconds) => format!("s-maxage={}", seconds),
+            MaxStale(Some(seconds)) => format!("max-stale={}", seconds),
+            MaxStale(None) => ~"max-stale",
+            MinFresh(seconds) => format!("min-fresh={}", seconds),
+            Public => ~"public",
+            Private(ref fields) if fields.is_empty() => ~"private",
+            Private(ref fields) => format!("private={}", quoted_string(fields.connect(", "))),
+            MustRevalidate => ~"must-revalidate",
+            ProxyRevalidate => ~"proxy-revalidate",
+            Extension(ref name, Some(ref argument)) => {
+                format!("{}={}", *name, maybe_quoted_string(argument.clone()))
+            },
+            Extension(ref name, None) => name.clone(),
+        }
+    }
+}
+
+/// Parse delta-seconds: digits alone. Too many seconds to count are taken as the most which can
+/// be, as RFC 7234 (section 1.2.1) allows.
+fn parse_delta_seconds(s: &str) -> Option<DeltaSeconds> {
+    if s.is_empty() || !s.byte_iter().all(|b| b >= '0' as u8 && b <= '9' as u8) {
+        return None;
+    }
+    match from_str::<DeltaSeconds>(s) {
+        Some(seconds) => Some(seconds),
+        None => Some(u64::max_value),
+    }
+}
+
+/// The value of a Cache-Control header: its directives, in order.
+///
+/// For the directives a cache looks at, there are methods to find them; where a directive appears
+/// more than once, which RFC 7234 says is invalid, the first is taken.
+#[deriving(Clone, Eq)]
+pub struct CacheControl(~[CacheDirective]);
+
+impl CacheControl {
+    /// The seconds of the `max-age` directive, if there is one.
+    pub fn max_age(&self) -> Option<DeltaSeconds> {
+        for directive in (**self).iter() {
+            match *directive {
+                MaxAge(seconds) => return Some(seconds),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The seconds of the `s-maxage` directive, if there is one.
+    pub fn s_maxage(&self) -> Option<DeltaSeconds> {
+        for directive in (**self).iter() {
+            match *directive {
+                SMaxAge(seconds) => return Some(seconds),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The `max-stale` directive, if there is one: `Some(None)` if it allows any staleness.
+    pub fn max_stale(&self) -> Option<Option<DeltaSeconds>> {
+        for directive in (**self).iter() {
+            match *directive {
+                MaxStale(seconds) => return Some(seconds),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The seconds of the `min-fresh` directive, if there is one.
+    pub fn min_fresh(&self) -> Option<DeltaSeconds> {
+        for directive in (**self).iter() {
+            match *directive {
+                MinFresh(seconds) => return Some(seconds),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The fields named by the `no-cache` directive, if there is one; if they are none, the
+    /// whole response is meant.
+    pub fn no_cache<'a>(&'a self) -> Option<&'a [~str]> {
+        for directive in (**self).iter() {
+            match *directive {
+                NoCache(ref fields) => return Some(fields.as_slice()),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The fields named by the `private` directive, if there is one; if they are none, the whole
+    /// response is meant.
+    pub fn private<'a>(&'a self) -> Option<&'a [~str]> {
+        for directive in (**self).iter() {
+            match *directive {
+                Private(ref fields) => return Some(fields.as_slice()),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Whether there is a `no-store` directive.
+    pub fn no_store(&self) -> bool {
+        (**self).contains(&NoStore)
+    }
+
+    /// Whether there is a `public` directive.
+    pub fn is_public(&self) -> bool {
+        (**self).contains(&Public)
+    }
+
+    /// Whether there is a `private` directive, naming fields or not.
+    pub fn is_private(&self) -> bool {
+        self.private().is_some()
+    }
+
+    /// Whether there is a `must-revalidate` directive.
+    pub fn must_revalidate(&self) -> bool {
+        (**self).contains(&MustRevalidate)
+    }
+
+    /// Whether there is a `proxy-revalidate` directive.
+    pub fn proxy_revalidate(&self) -> bool {
+        (**self).contains(&ProxyRevalidate)
+    }
+
+    /// Whether there is an `only-if-cached` directive.
+    pub fn only_if_cached(&self) -> bool {
+        (**self).contains(&OnlyIfCached)
+    }
+}
+
+impl HeaderConvertible for CacheControl {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<CacheControl> {
+        let s = reader.collect_to_str();
+        let mut directives = ~[];
+        for directive in split_outside_quotes(s, ',').iter() {
+            if directive.trim().is_empty() {
+                continue;
+            }
+            match CacheDirective::parse(*directive) {
+                Some(directive) => directives.push(directive),
+                None => return None,
+            }
+        }
+        Some(CacheControl(directives))
+    }
+
+    fn http_value(&self) -> ~str {
+        let directives: ~[~str] = (**self).iter().map(|d| d.to_str()).collect();
+        directives.connect(", ")
+    }
+
+    fn merge(&mut self, other: &CacheControl) -> bool {
+        match *self {
+            CacheControl(ref mut directives) => directives.push_all((**other).as_slice()),
+        }
+        true
+    }
+}
+
+#[test]
+fn test_cache_directive() {
+    assert_eq!(CacheDirective::parse("max-age=60"), Some(MaxAge(60)));
+    assert_eq!(CacheDirective::parse("Max-Age = \"60\""), Some(MaxAge(60)));
+    assert_eq!(CacheDirective::parse("max-age=-1"), None);
+    assert_eq!(CacheDirective::parse("max-age"), None);
+    assert_eq!(CacheDirective::parse("s-maxage=99999999999999999999999"),
+               Some(SMaxAge(u64::max_value)));
+    assert_eq!(CacheDirective::parse("max-stale"), Some(MaxStale(None)));
+    assert_eq!(CacheDirective::parse("max-stale=10"), Some(MaxStale(Some(10))));
+    assert_eq!(CacheDirective::parse("NO-CACHE"), Some(NoCache(~[])));
+    assert_eq!(CacheDirective::parse("no-cache=\"Set-Cookie, ,X-Foo\""),
+               Some(NoCache(~[~"Set-Cookie", ~"X-Foo"])));
+    assert_eq!(CacheDirective::parse("private=Authorization"),
+               Some(Private(~[~"Authorization"])));
+    assert_eq!(CacheDirective::parse("community=\"UCI\""),
+               Some(Extension(~"community", Some(~"UCI"))));
+    assert_eq!(CacheDirective::parse("immutable"), Some(Extension(~"immutable", None)));
+    assert_eq!(CacheDirective::parse("a b"), None);
+    assert_eq!(CacheDirective::parse("x=\"unterminated"), None);
+}
+
+#[test]
+fn test_cache_control() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("no-cache", CacheControl(~[NoCache(~[])]));
+    assert_conversion_correct("public, max-age=3600, s-maxage=60, must-revalidate",
+                              CacheControl(~[Public, MaxAge(3600), SMaxAge(60), MustRevalidate]));
+    assert_conversion_correct("private=\"Set-Cookie, X-Foo\", no-store, community=\"U C I\"",
+                              CacheControl(~[Private(~[~"Set-Cookie", ~"X-Foo"]), NoStore,
+                                             Extension(~"community", Some(~"U C I"))]));
+    assert_conversion_correct("max-stale, min-fresh=5, only-if-cached, no-transform",
+                              CacheControl(~[MaxStale(None), MinFresh(5), OnlyIfCached,
+                                             NoTransform]));
+    assert_interpretation_correct("no-cache=\"a,b\",,  MAX-AGE=0 ,proxy-revalidate",
+                                  CacheControl(~[NoCache(~[~"a", ~"b"]), MaxAge(0),
+                                                 ProxyRevalidate]));
+    assert_interpretation_correct("", CacheControl(~[]));
+    assert_invalid::<CacheControl>("max-age=soon");
+    assert_invalid::<CacheControl>("public, max-age=1 0");
+}
+
+#[test]
+fn test_cache_control_accessors() {
+    let cc = CacheControl(~[Public, MaxAge(60), SMaxAge(10), NoCache(~[~"Set-Cookie"]),
+                            MaxAge(120)]);
+    assert_eq!(cc.max_age(), Some(60));
+    assert_eq!(cc.s_maxage(), Some(10));
+    assert_eq!(cc.no_cache().map(|fields| fields.to_owned()), Some(~[~"Set-Cookie"]));
+    assert!(cc.is_public() && !cc.is_private() && !cc.no_store() && !cc.must_revalidate());
+    let cc = CacheControl(~[Private(~[]), NoStore, MaxStale(None)]);
+    assert_eq!(cc.private().map(|fields| fields.len()), Some(0));
+    assert_eq!(cc.max_stale(), Some(None));
+    assert_eq!(cc.max_age(), None);
+    assert!(cc.is_private() && cc.no_store() && !cc.only_if_cached());
+}
@@ -0,0 +1,78 @@
+//! The Content-Range entity header, defined in RFC 2616, Section 14.16.
+//!
+//! Content-Range = "Content-Range" ":" content-range-spec
+//! content-range-spec      = byte-content-range-spec
+//! byte-content-range-spec = bytes-unit SP byte-range-resp-spec "/" ( instance-length | "*" )
+//! byte-range-resp-spec    = ( first-byte-pos "-" last-byte-pos ) | "*"
+
+use std::rt::io::Reader;
+
+#[deriving(Clone, Eq)]
+pub enum ContentRange {
+    /// The first and last positions (inclusive) of the bytes enclosed, and the length of the whole
+    /// representation, if it is known.
+    ByteRange(u64, u64, Option<u64>),
+
+    /// Sent with a 416 (Requested Range Not Satisfiable) response: the length of the whole
+    /// representation, which none of the ranges requested fell within.
+    UnsatisfiedRange(u64),
+}
+
+impl super::HeaderConvertible for ContentRange {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<ContentRange> {
+        let s = reader.collect_to_str();
+        if !s.starts_with("bytes ") {
+            return None;
+        }
+        let s = s.slice_from(6);
+        let (range, length) = match s.find('/') {
+            Some(i) => (s.slice_to(i), s.slice_from(i + 1)),
+            None => return None,
+        };
+        let length = match length {
+            "*" => None,
+            length => match from_str::<u64>(length) {
+                Some(length) => Some(length),
+                None => return None,
+            },
+        };
+        if range == "*" {
+            return match length {
+                Some(length) => Some(UnsatisfiedRange(length)),
+                None => None,
+            };
+        }
+        let (first, last) = match range.find('-') {
+            Some(i) => (from_str::<u64>(range.slice_to(i)),
+                        from_str::<u64>(range.slice_from(i + 1))),
+            None => return None,
+        };
+        match (first, last, length) {
+            (Some(first), Some(last), None) if first <= last => Some(ByteRange(first, last, None)),
+            (Some(first), Some(last), Some(length)) if first <= last && last < length =>
+                Some(ByteRange(first, last, Some(length))),
+            _ => None,
+        }
+    }
+
+    fn http_value(&self) -> ~str {
+        match *self {
+            ByteRange(first, last, Some(length)) => format!("bytes {}-{}/{}", first, last, length),
+            ByteRange(first, last, None) => format!("bytes {}-{}/*", first, last),
+            UnsatisfiedRange(length) => format!("bytes */{}", length),
+        }
+    }
+}
+
+#[test]
+fn test_content_range() {
+    use headers::test_utils::{assert_conversion_correct, assert_invalid};
+    assert_conversion_correct("bytes 0-499/1234", ByteRange(0, 499, Some(1234)));
+    assert_conversion_correct("bytes 500-999/*", ByteRange(500, 999, None));
+    assert_conversion_correct("bytes */1234", UnsatisfiedRange(1234));
+    assert_invalid::<ContentRange>("bytes 0-1234/1234");
+    assert_invalid::<ContentRange>("bytes 5-4/10");
+    assert_invalid::<ContentRange>("bytes */*");
+    assert_invalid::<ContentRange>("pages 1-2/3");
+}
@@ -1,8 +1,14 @@
 //! The Content-Type entity header, defined in RFC 2616, Section 14.17.
+//!
+//! Media types are compared (with `==`) as RFC 2616 (section 3.7) says they are: the type,
+//! subtype and parameter names ignoring case, parameter values exactly (but for `charset`, whose
+//! value is also a name), and the parameters in any order.
 use headers::serialization_utils::{push_parameters, WriterUtil};
+use std::ascii::StrAsciiExt;
 use std::rt::io::{Reader, Writer};
+use std::vec;
 
-#[deriving(Clone, Eq)]
+#[deriving(Clone)]
 pub struct MediaType {
     type_: ~str,
     subtype: ~str,
@@ -17,6 +23,60 @@ pub fn MediaType(type_: ~str, subtype: ~str, parameters: ~[(~str, ~str)]) -> Med
     }
 }
 
+impl MediaType {
+    /// Parse a media type, as it would be in a Content-Type header.
+    pub fn parse(s: &str) -> Option<MediaType> {
+        super::header_value_from_str(s)
+    }
+
+    /// Whether this is of the type and subtype given, ignoring case and parameters.
+    pub fn is(&self, type_: &str, subtype: &str) -> bool {
+        self.type_.eq_ignore_ascii_case(type_) && self.subtype.eq_ignore_ascii_case(subtype)
+    }
+
+    /// The value of the parameter with the name given (ignoring case), if there is one.
+    pub fn get_parameter<'a>(&'a self, name: &str) -> Option<&'a str> {
+        for &(ref k, ref v) in self.parameters.iter() {
+            if k.eq_ignore_ascii_case(name) {
+                return Some(v.as_slice());
+            }
+        }
+        None
+    }
+}
+
+impl Eq for MediaType {
+    fn eq(&self, other: &MediaType) -> bool {
+        if !self.is(other.type_, other.subtype) || self.parameters.len() != other.parameters.len() {
+            return false;
+        }
+        // Each parameter must match one of the other's, a different one each time.
+        let mut matched = vec::from_elem(other.parameters.len(), false);
+        for &(ref k, ref v) in self.parameters.iter() {
+            let mut found = false;
+            for (i, &(ref other_k, ref other_v)) in other.parameters.iter().enumerate() {
+                if !matched[i] && parameter_eq(*k, *v, *other_k, *other_v) {
+                    matched[i] = true;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn parameter_eq(k: &str, v: &str, other_k: &str, other_v: &str) -> bool {
+    k.eq_ignore_ascii_case(other_k) && if k.eq_ignore_ascii_case("charset") {
+        v.eq_ignore_ascii_case(other_v)
+    } else {
+        v == other_v
+    }
+}
+
 impl ToStr for MediaType {
     fn to_str(&self) -> ~str {
         // Idea:
@@ -77,6 +137,37 @@ fn test_content_type() {
                               MediaType(~"type", ~"subtype", ~[(~"key", ~"value")]));
 }
 
+#[test]
+fn test_content_type_quoted_parameter() {
+    ::headers::test_utils::assert_conversion_correct(
+            "type/subtype;key=\"a value; with \\\"quotes\\\"\"",
+            MediaType(~"type", ~"subtype", ~[(~"key", ~"a value; with \"quotes\"")]));
+    ::headers::test_utils::assert_interpretation_correct("type/subtype;key=\"value\"",
+            MediaType(~"type", ~"subtype", ~[(~"key", ~"value")]));
+}
+
+#[test]
+fn test_media_type_eq() {
+    let media_type = MediaType(~"text", ~"html", ~[(~"charset", ~"UTF-8"), (~"level", ~"1")]);
+    assert_eq!(media_type,
+               MediaType(~"Text", ~"HTML", ~[(~"Level", ~"1"), (~"charset", ~"utf-8")]));
+    assert!(media_type != MediaType(~"text", ~"html", ~[(~"charset", ~"UTF-8"), (~"level", ~"2")]));
+    assert!(media_type != MediaType(~"text", ~"html", ~[(~"charset", ~"UTF-8")]));
+    assert!(media_type
+            != MediaType(~"text", ~"plain", ~[(~"charset", ~"UTF-8"), (~"level", ~"1")]));
+    assert!(MediaType(~"a", ~"b", ~[(~"k", ~"1"), (~"k", ~"1")])
+            != MediaType(~"a", ~"b", ~[(~"k", ~"1"), (~"k", ~"2")]));
+}
+
+#[test]
+fn test_media_type_accessors() {
+    let media_type = MediaType::parse("Text/HTML;Charset=utf-8").unwrap();
+    assert!(media_type.is("text", "html"));
+    assert!(!media_type.is("text", "plain"));
+    assert_eq!(media_type.get_parameter("charset"), Some("utf-8"));
+    assert_eq!(media_type.get_parameter("level"), None);
+}
+
 #[test]
 #[ignore(reason="lws collapse bug")]  // FIXME: triggers infinite loop.
 fn test_content_type_BROKEN() {
@@ -0,0 +1,285 @@
+//! The Cookie request header and the Set-Cookie response header, defined in RFC 6265.
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use extra::time::{Tm, strptime};
+use headers::{HeaderConvertible, HeaderValueByteIterator};
+use headers::date;
+
+/// A cookie: a name and value, and, when being set, the attributes which govern where and for how
+/// long the user agent should send it back.
+///
+/// Only the name and value are sent in a Cookie request header; the attributes are only
+/// meaningful in a Set-Cookie response header.
+#[deriving(Clone, Eq)]
+pub struct Cookie {
+    name: ~str,
+    value: ~str,
+
+    /// When the cookie expires; if neither this nor `max_age` is set, the cookie lasts until the
+    /// end of the user agent's session.
+    expires: Option<Tm>,
+
+    /// The number of seconds until the cookie expires; takes precedence over `expires`. Zero or a
+    /// negative number expires the cookie immediately.
+    max_age: Option<i64>,
+
+    /// The host to which (and to whose subdomains) the cookie will be sent; if unset, it will be
+    /// sent only to the host which set it. Any leading dot is removed when parsing.
+    domain: Option<~str>,
+
+    /// The path to which (and below which) the cookie will be sent; if unset, the directory of the
+    /// URL which set it.
+    path: Option<~str>,
+
+    /// Whether the cookie may only be sent over secure connections.
+    secure: bool,
+
+    /// Whether the cookie is to be withheld from non-HTTP APIs such as scripts.
+    http_only: bool,
+}
+
+impl Cookie {
+    /// Create a cookie with no attributes.
+    pub fn new(name: ~str, value: ~str) -> Cookie {
+        Cookie {
+            name: name,
+            value: value,
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+        }
+    }
+
+    /// Parse a cookie from a Set-Cookie header value, as specified in RFC 6265, section 5.2.
+    /// Unknown and invalid attributes are ignored; `None` is returned only if there is no
+    /// `name=value` pair.
+    pub fn parse_set_cookie(s: &str) -> Option<Cookie> {
+        let mut parts = s.split_iter(';');
+        let mut cookie = match parse_pair(parts.next().unwrap()) {
+            Some((name, value)) => Cookie::new(name, value),
+            None => return None,
+        };
+        for attribute in parts {
+            let (name, value) = match attribute.find('=') {
+                Some(i) => (attribute.slice_to(i).trim(), attribute.slice_from(i + 1).trim()),
+                None => (attribute.trim(), ""),
+            };
+            match name.to_ascii_lower().as_slice() {
+                "expires" => match parse_cookie_date(value) {
+                    Some(tm) => cookie.expires = Some(tm),
+                    None => (),
+                },
+                "max-age" => match from_str::<i64>(value) {
+                    Some(seconds) => cookie.max_age = Some(seconds),
+                    None => (),
+                },
+                "domain" if !value.is_empty() => {
+                    let domain = if value.starts_with(".") { value.slice_from(1) } else { value };
+                    cookie.domain = Some(domain.to_ascii_lower());
+                },
+                // A path not starting with a slash means the default path
+                "path" if value.starts_with("/") => cookie.path = Some(value.to_owned()),
+                "path" => cookie.path = None,
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => (),
+            }
+        }
+        Some(cookie)
+    }
+
+    /// The cookie as it is sent back in a Cookie request header: just `name=value`.
+    pub fn to_cookie_pair(&self) -> ~str {
+        format!("{}={}", self.name, self.value)
+    }
+
+    /// The cookie as it is written in a Set-Cookie response header, attributes and all.
+    pub fn to_set_cookie_str(&self) -> ~str {
+        let mut s = self.to_cookie_pair();
+        match self.expires {
+            Some(ref tm) => {
+                s.push_str("; Expires=");
+                s.push_str(date::format(tm));
+            },
+            None => (),
+        }
+        match self.max_age {
+            Some(seconds) => s.push_str(format!("; Max-Age={}", seconds)),
+            None => (),
+        }
+        match self.domain {
+            Some(ref domain) => {
+                s.push_str("; Domain=");
+                s.push_str(*domain);
+            },
+            None => (),
+        }
+        match self.path {
+            Some(ref path) => {
+                s.push_str("; Path=");
+                s.push_str(*path);
+            },
+            None => (),
+        }
+        if self.secure {
+            s.push_str("; Secure");
+        }
+        if self.http_only {
+            s.push_str("; HttpOnly");
+        }
+        s
+    }
+}
+
+/// Split a `name=value` pair, trimming whitespace; `None` if there's no `=` or no name.
+fn parse_pair(s: &str) -> Option<(~str, ~str)> {
+    match s.find('=') {
+        Some(i) => {
+            let name = s.slice_to(i).trim();
+            if name.is_empty() {
+                None
+            } else {
+                Some((name.to_owned(), s.slice_from(i + 1).trim().to_owned()))
+            }
+        },
+        None => None,
+    }
+}
+
+/// Parse the date of an Expires attribute. Besides the HTTP-date formats, the Netscape format with
+/// a four-digit year (`Wed, 09-Jun-2021 10:18:14 GMT`) is still commonly sent.
+fn parse_cookie_date(s: &str) -> Option<Tm> {
+    match date::parse(s) {
+        Some(tm) => Some(tm),
+        None => match strptime(s, "%a, %d-%b-%Y %T %Z") {
+            Ok(tm) => Some(tm),
+            Err(*) => None,
+        },
+    }
+}
+
+/// The value of a Cookie request header: the cookies sent, in order.
+#[deriving(Clone, Eq)]
+pub struct CookieList(~[Cookie]);
+
+impl HeaderConvertible for CookieList {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<CookieList> {
+        let s = reader.collect_to_str();
+        let mut cookies = ~[];
+        for pair in s.split_iter(';') {
+            match parse_pair(pair) {
+                Some((name, value)) => cookies.push(Cookie::new(name, value)),
+                // Be liberal in what we accept; a bad pair needn't spoil the rest.
+                None => (),
+            }
+        }
+        Some(CookieList(cookies))
+    }
+
+    fn http_value(&self) -> ~str {
+        let pairs: ~[~str] = (**self).iter().map(|c| c.to_cookie_pair()).collect();
+        pairs.connect("; ")
+    }
+
+    fn merge(&mut self, other: &CookieList) -> bool {
+        match *self {
+            CookieList(ref mut cookies) => cookies.push_all((**other).as_slice()),
+        }
+        true
+    }
+}
+
+/// The cookies set by a response. Each is sent in a Set-Cookie header of its own, as their
+/// Expires dates contain commas which preclude the usual comma-separated list.
+#[deriving(Clone, Eq)]
+pub struct SetCookieList(~[Cookie]);
+
+impl HeaderConvertible for SetCookieList {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<SetCookieList> {
+        match Cookie::parse_set_cookie(reader.collect_to_str()) {
+            Some(cookie) => Some(SetCookieList(~[cookie])),
+            None => None,
+        }
+    }
+
+    fn to_stream<W: Writer>(&self, writer: &mut W) {
+        // The header name of the first has already been written, and the CRLF after the last will
+        // be; we write the rest.
+        for (i, cookie) in (**self).iter().enumerate() {
+            if i != 0 {
+                writer.write(bytes!("\r\nSet-Cookie: "));
+            }
+            writer.write(cookie.to_set_cookie_str().as_bytes());
+        }
+    }
+
+    fn http_value(&self) -> ~str {
+        let values: ~[~str] = (**self).iter().map(|c| c.to_set_cookie_str()).collect();
+        values.connect(", ")
+    }
+
+    fn http_values(&self) -> ~[~str] {
+        (**self).iter().map(|c| c.to_set_cookie_str()).collect()
+    }
+
+    fn merge(&mut self, other: &SetCookieList) -> bool {
+        match *self {
+            SetCookieList(ref mut cookies) => cookies.push_all((**other).as_slice()),
+        }
+        true
+    }
+}
+
+#[test]
+fn test_cookie_list() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct};
+    assert_conversion_correct("foo=bar", CookieList(~[Cookie::new(~"foo", ~"bar")]));
+    assert_conversion_correct("foo=bar; baz=", CookieList(~[Cookie::new(~"foo", ~"bar"),
+                                                            Cookie::new(~"baz", ~"")]));
+    assert_interpretation_correct("foo = bar;;bogus; x=\"y z\"",
+                                  CookieList(~[Cookie::new(~"foo", ~"bar"),
+                                               Cookie::new(~"x", ~"\"y z\"")]));
+}
+
+#[test]
+fn test_set_cookie() {
+    use headers::test_utils::{assert_conversion_correct, assert_invalid, from_stream_with_str,
+                              to_stream_into_str};
+    assert_conversion_correct("SID=31d4d96e407aad42",
+                              SetCookieList(~[Cookie::new(~"SID", ~"31d4d96e407aad42")]));
+
+    let mut cookie = Cookie::new(~"SID", ~"31d4d96e407aad42");
+    cookie.max_age = Some(3600);
+    cookie.domain = Some(~"example.com");
+    cookie.path = Some(~"/");
+    cookie.secure = true;
+    cookie.http_only = true;
+    assert_conversion_correct(
+        "SID=31d4d96e407aad42; Max-Age=3600; Domain=example.com; Path=/; Secure; HttpOnly",
+        SetCookieList(~[cookie.clone()]));
+
+    // Attribute names are case insensitive; bad values are ignored
+    assert_eq!(from_stream_with_str(
+            "SID=31d4d96e407aad42; Max-Age=soon; Expires=never; Path=relative; max-age=3600; \
+             DOMAIN=.Example.COM; path=/; secure; httponly; Bogus"),
+        Some(SetCookieList(~[cookie.clone()])));
+
+    let expires = from_stream_with_str::<SetCookieList>(
+        "lang=en-US; Expires=Wed, 09 Jun 2021 10:18:14 GMT").unwrap();
+    assert_eq!((*expires)[0].expires.unwrap().tm_year, 121);
+    let netscape = from_stream_with_str::<SetCookieList>(
+        "lang=en-US; Expires=Wed, 09-Jun-2021 10:18:14 GMT").unwrap();
+    assert_eq!((*netscape)[0].expires, (*expires)[0].expires);
+
+    assert_invalid::<SetCookieList>("");
+    assert_invalid::<SetCookieList>("novalue");
+    assert_invalid::<SetCookieList>("=value");
+
+    // Each cookie goes in a header of its own
+    let two = SetCookieList(~[Cookie::new(~"a", ~"1"), Cookie::new(~"b", ~"2")]);
+    assert_eq!(to_stream_into_str(&two), ~"a=1\r\nSet-Cookie: b=2");
+}
@@ -0,0 +1,67 @@
+//! HTTP-dates (RFC 2616, section 3.3.1), as in the Date, Expires, Last-Modified and
+//! If-Modified-Since headers: always sent in the RFC 1123 format, but received in that or either
+//! of the obsolete formats, that of RFC 850 and that of ANSI C's `asctime()`.
+
+use extra::time::{Tm, strptime};
+
+/// The time in the RFC 1123 format, in GMT: `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format(tm: &Tm) -> ~str {
+    tm.to_utc().strftime("%a, %d %b %Y %T GMT")
+}
+
+/// Parse an HTTP-date in any of the three formats: `Sun, 06 Nov 1994 08:49:37 GMT` (RFC 1123),
+/// `Sunday, 06-Nov-94 08:49:37 GMT` (RFC 850) and `Sun Nov  6 08:49:37 1994` (`asctime()`).
+///
+/// The two-digit year of the RFC 850 format is taken to be in 1970–2069, as that format was long
+/// obsolete before 1970 came around again.
+pub fn parse(s: &str) -> Option<Tm> {
+    // XXX: %Z actually ignores any timezone other than UTC. Probably not a good idea?
+    match strptime(s, "%a, %d %b %Y %T %Z") {  // RFC 822, updated by RFC 1123
+        Ok(time) => return Some(time),
+        Err(*) => ()
+    }
+
+    match strptime(s, "%A, %d-%b-%y %T %Z") {  // RFC 850, obsoleted by RFC 1036
+        Ok(time) => {
+            let mut time = time;
+            if time.tm_year < 70 {
+                time.tm_year += 100;
+            }
+            return Some(time);
+        },
+        Err(*) => ()
+    }
+
+    match strptime(s, "%c") {  // ANSI C's asctime() format
+        Ok(time) => Some(time),
+        Err(*) => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use extra::time::{Tm, at_utc, Timespec};
+    use super::{format, parse};
+
+    fn sample_tm() -> Tm {
+        at_utc(Timespec::new(784111777, 0))
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(&sample_tm()), ~"Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn test_parse() {
+        let sec = sample_tm().to_timespec().sec;
+        let parsed = ["Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT",
+                      "Sun Nov  6 08:49:37 1994"];
+        for s in parsed.iter() {
+            assert_eq!(parse(*s).unwrap().to_timespec().sec, sec);
+        }
+        assert_eq!(parse("Thursday, 01-Jan-15 00:00:00 GMT").unwrap().tm_year, 115);
+        assert_eq!(parse("Sun, 06 Nov 1994"), None);
+        assert_eq!(parse(""), None);
+    }
+}
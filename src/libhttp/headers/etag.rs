@@ -1,4 +1,5 @@
 use headers::serialization_utils::{push_quoted_string, quoted_string, WriterUtil};
+use headers::{HeaderValueByteIterator, CommaConsumed, EndOfValue, ErrCommaNotFound};
 use std::rt::io::{Reader, Writer};
 
 #[deriving(Clone, Eq)]
@@ -7,6 +8,19 @@ pub struct EntityTag {
     opaque_tag: ~str,
 }
 
+impl EntityTag {
+    /// The strong comparison function (RFC 2616, section 13.3.3): the tags must be the same, and
+    /// neither may be weak.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.opaque_tag == other.opaque_tag
+    }
+
+    /// The weak comparison function: the tags must be the same, but either may be weak.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.opaque_tag == other.opaque_tag
+    }
+}
+
 pub fn weak_etag<S: Str>(opaque_tag: S) -> EntityTag {
     EntityTag {
         weak: true,
@@ -33,28 +47,11 @@ impl ToStr for EntityTag {
 
 impl super::HeaderConvertible for EntityTag {
     fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>) -> Option<EntityTag> {
-        let weak = match reader.next() {
-            Some(b) if b == 'W' as u8 || b == 'w' as u8 => {
-                if reader.next() != Some('/' as u8) || reader.next() != Some('"' as u8) {
-                    return None;
-                }
-                true
-            },
-            Some(b) if b == '"' as u8 => {
-                false
-            },
-            _ => {
-                return None;
-            }
-        };
-        let opaque_tag = match reader.read_quoted_string(true) {
-            Some(tag) => tag,
-            None => return None,
-        };
-        reader.some_if_consumed(EntityTag {
-            weak: weak,
-            opaque_tag: opaque_tag,
-        })
+        let first = reader.next();
+        match read_entity_tag(first, reader) {
+            Some(tag) => reader.some_if_consumed(tag),
+            None => None,
+        }
     }
 
     fn to_stream<W: Writer>(&self, writer: &mut W) {
@@ -69,6 +66,106 @@ impl super::HeaderConvertible for EntityTag {
     }
 }
 
+/// Read an entity tag, of which the first byte has already been read.
+fn read_entity_tag<R: Reader>(first: Option<u8>, reader: &mut HeaderValueByteIterator<R>)
+        -> Option<EntityTag> {
+    let weak = match first {
+        Some(b) if b == 'W' as u8 || b == 'w' as u8 => {
+            if reader.next() != Some('/' as u8) || reader.next() != Some('"' as u8) {
+                return None;
+            }
+            true
+        },
+        Some(b) if b == '"' as u8 => {
+            false
+        },
+        _ => {
+            return None;
+        }
+    };
+    let opaque_tag = match reader.read_quoted_string(true) {
+        Some(tag) => tag,
+        None => return None,
+    };
+    Some(EntityTag {
+        weak: weak,
+        opaque_tag: opaque_tag,
+    })
+}
+
+/// The value of an If-Match or If-None-Match header: `*`, or a list of entity tags.
+#[deriving(Clone, Eq)]
+pub enum EntityTagList {
+    /// `*`, matching any current entity.
+    AnyEntityTag,
+    EntityTags(~[EntityTag]),
+}
+
+impl EntityTagList {
+    /// Whether the entity tag is in the list, by the weak comparison function, as for
+    /// If-None-Match with GET and HEAD requests.
+    pub fn matches_weak(&self, tag: &EntityTag) -> bool {
+        match *self {
+            AnyEntityTag => true,
+            EntityTags(ref tags) => tags.iter().any(|t| t.weak_eq(tag)),
+        }
+    }
+
+    /// Whether the entity tag is in the list, by the strong comparison function, as for If-Match
+    /// and for If-None-Match with other requests.
+    pub fn matches_strong(&self, tag: &EntityTag) -> bool {
+        match *self {
+            AnyEntityTag => true,
+            EntityTags(ref tags) => tags.iter().any(|t| t.strong_eq(tag)),
+        }
+    }
+}
+
+impl super::HeaderConvertible for EntityTagList {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<EntityTagList> {
+        let mut first = reader.next();
+        if first == Some('*' as u8) {
+            return reader.some_if_consumed(AnyEntityTag);
+        }
+        let mut tags = ~[];
+        loop {
+            match read_entity_tag(first, reader) {
+                Some(tag) => tags.push(tag),
+                None => return None,
+            }
+            match reader.consume_comma_lws() {
+                CommaConsumed => first = reader.next(),
+                EndOfValue => break,
+                ErrCommaNotFound => return None,
+            }
+        }
+        Some(EntityTags(tags))
+    }
+
+    fn http_value(&self) -> ~str {
+        match *self {
+            AnyEntityTag => ~"*",
+            EntityTags(ref tags) => {
+                let tags: ~[~str] = tags.iter().map(|t| t.to_str()).collect();
+                tags.connect(", ")
+            },
+        }
+    }
+
+    fn merge(&mut self, other: &EntityTagList) -> bool {
+        match *other {
+            EntityTags(ref others) => match *self {
+                EntityTags(ref mut tags) => {
+                    tags.push_all(others.as_slice());
+                    true
+                },
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
 #[test]
 fn test_etag() {
     use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
@@ -91,3 +188,35 @@ fn test_etag() {
     assert_invalid::<EntityTag>("\"\\\"");
     assert_invalid::<EntityTag>("\"\"\"\"");
 }
+
+#[test]
+fn test_entity_tag_list() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("*", AnyEntityTag);
+    assert_conversion_correct("\"xyzzy\"", EntityTags(~[strong_etag("xyzzy")]));
+    assert_conversion_correct("\"a,b\", W/\"c\"",
+                              EntityTags(~[strong_etag("a,b"), weak_etag("c")]));
+    assert_interpretation_correct("\"a\" ,w/\"b\"",
+                                  EntityTags(~[strong_etag("a"), weak_etag("b")]));
+    assert_invalid::<EntityTagList>("");
+    assert_invalid::<EntityTagList>("*, \"a\"");
+    assert_invalid::<EntityTagList>("\"a\" \"b\"");
+    assert_invalid::<EntityTagList>("a");
+}
+
+#[test]
+fn test_comparison() {
+    assert!(strong_etag("1").strong_eq(&strong_etag("1")));
+    assert!(!strong_etag("1").strong_eq(&weak_etag("1")));
+    assert!(!weak_etag("1").strong_eq(&weak_etag("1")));
+    assert!(weak_etag("1").weak_eq(&strong_etag("1")));
+    assert!(!weak_etag("1").weak_eq(&weak_etag("2")));
+
+    let list = EntityTags(~[weak_etag("1"), strong_etag("2")]);
+    assert!(list.matches_weak(&strong_etag("1")));
+    assert!(!list.matches_strong(&strong_etag("1")));
+    assert!(list.matches_strong(&strong_etag("2")));
+    assert!(!list.matches_weak(&strong_etag("3")));
+    assert!(AnyEntityTag.matches_strong(&weak_etag("3")));
+}
@@ -0,0 +1,307 @@
+//! The Forwarded request header, defined in RFC 7239, and the X-Forwarded-For and
+//! X-Forwarded-Proto headers which came before it, by which proxies tell of the client (and of
+//! themselves) to the server.
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::Reader;
+use std::rt::io::net::ip::{IpAddr, Ipv6Addr};
+use headers::{HeaderConvertible, HeaderValueByteIterator};
+use headers::serialization_utils::{maybe_quoted_string, unquote_string, split_outside_quotes};
+
+/// A client or proxy, as named in X-Forwarded-For or in the `for` and `by` parameters of
+/// Forwarded (RFC 7239, section 6).
+#[deriving(Clone, Eq)]
+pub enum Node {
+    /// One known by its IP address, and perhaps its port.
+    AddressNode(IpAddr, Option<u16>),
+    /// One which the proxy doesn't know, or won't tell of (`unknown`).
+    UnknownNode,
+    /// One known by a name of the proxy's own making, such as `_hidden`, so as to tell one such
+    /// from another without giving away their addresses.
+    ObfuscatedNode(~str),
+}
+
+impl Node {
+    /// Parse a node, as `192.0.2.43`, `[2001:db8::1]:4711`, `unknown` or `_hidden`; an IPv6
+    /// address may also be without brackets, if there is no port, as in X-Forwarded-For. An
+    /// obfuscated port is disregarded.
+    pub fn parse(s: &str) -> Option<Node> {
+        if s.eq_ignore_ascii_case("unknown") {
+            return Some(UnknownNode);
+        }
+        if s.starts_with("_") {
+            return if is_obfuscated(s) { Some(ObfuscatedNode(s.to_owned())) } else { None };
+        }
+        match FromStr::from_str(s) {
+            Some(ip) => return Some(AddressNode(ip, None)),
+            None => (),
+        }
+        let (ip, rest) = if s.starts_with("[") {
+            match s.find(']') {
+                Some(i) => match FromStr::from_str(s.slice(1, i)) {
+                    Some(ip @ Ipv6Addr(*)) => (ip, s.slice_from(i + 1)),
+                    _ => return None,
+                },
+                None => return None,
+            }
+        } else {
+            match s.find(':') {
+                Some(i) => match FromStr::from_str(s.slice_to(i)) {
+                    Some(ip) => (ip, s.slice_from(i)),
+                    None => return None,
+                },
+                None => return None,
+            }
+        };
+        if rest.is_empty() {
+            Some(AddressNode(ip, None))
+        } else if !rest.starts_with(":") {
+            None
+        } else if rest.starts_with(":_") && is_obfuscated(rest.slice_from(1)) {
+            Some(AddressNode(ip, None))
+        } else {
+            match from_str::<u16>(rest.slice_from(1)) {
+                Some(port) => Some(AddressNode(ip, Some(port))),
+                None => None,
+            }
+        }
+    }
+
+    /// The node as it is written in a Forwarded parameter, in which an IPv6 address is always
+    /// bracketed (and so quoted, as is an address with a port).
+    pub fn forwarded_value(&self) -> ~str {
+        let s = match *self {
+            AddressNode(ip @ Ipv6Addr(*), None) => format!("[{}]", ip.to_str()),
+            _ => self.to_str(),
+        };
+        maybe_quoted_string(s)
+    }
+
+    /// The IP address of the node, if it is known by one.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match *self {
+            AddressNode(ip, _) => Some(ip),
+            _ => None,
+        }
+    }
+}
+
+impl ToStr for Node {
+    /// The node as it is written in X-Forwarded-For.
+    fn to_str(&self) -> ~str {
+        match *self {
+            AddressNode(ip @ Ipv6Addr(*), Some(port)) => format!("[{}]:{}", ip.to_str(), port),
+            AddressNode(ip, Some(port)) => format!("{}:{}", ip.to_str(), port),
+            AddressNode(ip, None) => ip.to_str(),
+            UnknownNode => ~"unknown",
+            ObfuscatedNode(ref name) => name.clone(),
+        }
+    }
+}
+
+/// Whether a name is an obfuscated node or port: an underscore, then letters, digits, dots,
+/// underscores and hyphens.
+fn is_obfuscated(s: &str) -> bool {
+    s.len() > 1 && s.starts_with("_") && s.slice_from(1).iter().all(|c| {
+        (c < '\x80' && c.is_alphanumeric()) || c == '.' || c == '_' || c == '-'
+    })
+}
+
+/// The value of an X-Forwarded-For header: the client, then each proxy the request has come
+/// through, but the last (which is known by the address it connected from).
+///
+/// An entry which can't be made out is taken to be `UnknownNode`, so that those after it may still
+/// be counted on; an X-Forwarded-For can be begun with anything by a client.
+#[deriving(Clone, Eq)]
+pub struct NodeList(~[Node]);
+
+impl HeaderConvertible for NodeList {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<NodeList> {
+        let s = reader.collect_to_str();
+        let nodes: ~[Node] = s.split_iter(',').map(|node| match Node::parse(node.trim()) {
+            Some(node) => node,
+            None => UnknownNode,
+        }).collect();
+        Some(NodeList(nodes))
+    }
+
+    fn http_value(&self) -> ~str {
+        let nodes: ~[~str] = (**self).iter().map(|n| n.to_str()).collect();
+        nodes.connect(", ")
+    }
+
+    fn merge(&mut self, other: &NodeList) -> bool {
+        match *self {
+            NodeList(ref mut nodes) => nodes.push_all((**other).as_slice()),
+        }
+        true
+    }
+}
+
+/// What one proxy tells of a request in the Forwarded header (RFC 7239, section 4).
+#[deriving(Clone, Eq)]
+pub struct ForwardedElement {
+    /// The client, or the proxy, which the request came from (`for`).
+    for_node: Option<Node>,
+    /// The proxy which the request came to (`by`).
+    by_node: Option<Node>,
+    /// The Host header of the request as it came to the proxy (`host`).
+    host: Option<~str>,
+    /// The scheme of the request as it came to the proxy, such as `https` (`proto`).
+    proto: Option<~str>,
+}
+
+impl ForwardedElement {
+    /// An element telling of nothing.
+    pub fn new() -> ForwardedElement {
+        ForwardedElement {
+            for_node: None,
+            by_node: None,
+            host: None,
+            proto: None,
+        }
+    }
+
+    /// Parse an element, its parameters being separated by semicolons. Unknown parameters are
+    /// ignored, and so are ones which can't be made out, but for `for`, which is taken to be
+    /// `UnknownNode`. A value which should have been quoted, as an address with a port, but
+    /// wasn't, is taken as it is.
+    pub fn parse(s: &str) -> ForwardedElement {
+        let mut element = ForwardedElement::new();
+        for pair in split_outside_quotes(s, ';').iter() {
+            let (name, value) = match pair.find('=') {
+                Some(i) => (pair.slice_to(i).trim(), pair.slice_from(i + 1).trim()),
+                None => continue,
+            };
+            let value = if value.starts_with("\"") {
+                match unquote_string(value) {
+                    Some(value) => value,
+                    None => continue,
+                }
+            } else {
+                value.to_owned()
+            };
+            match name.to_ascii_lower().as_slice() {
+                "for" => element.for_node = match Node::parse(value) {
+                    Some(node) => Some(node),
+                    None => Some(UnknownNode),
+                },
+                "by" => element.by_node = Node::parse(value),
+                "host" => element.host = Some(value),
+                "proto" => element.proto = Some(value),
+                _ => (),
+            }
+        }
+        element
+    }
+}
+
+impl ToStr for ForwardedElement {
+    fn to_str(&self) -> ~str {
+        let mut pairs = ~[];
+        match self.for_node {
+            Some(ref node) => pairs.push(format!("for={}", node.forwarded_value())),
+            None => (),
+        }
+        match self.by_node {
+            Some(ref node) => pairs.push(format!("by={}", node.forwarded_value())),
+            None => (),
+        }
+        match self.host {
+            Some(ref host) => pairs.push(format!("host={}", maybe_quoted_string(host.clone()))),
+            None => (),
+        }
+        match self.proto {
+            Some(ref proto) => pairs.push(format!("proto={}", maybe_quoted_string(proto.clone()))),
+            None => (),
+        }
+        pairs.connect(";")
+    }
+}
+
+/// The value of a Forwarded header: an element for each proxy the request has come through, in
+/// order.
+#[deriving(Clone, Eq)]
+pub struct ForwardedList(~[ForwardedElement]);
+
+impl HeaderConvertible for ForwardedList {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<ForwardedList> {
+        let s = reader.collect_to_str();
+        let elements: ~[ForwardedElement] = split_outside_quotes(s, ',').iter().map(|e| {
+            ForwardedElement::parse(*e)
+        }).collect();
+        Some(ForwardedList(elements))
+    }
+
+    fn http_value(&self) -> ~str {
+        let elements: ~[~str] = (**self).iter().map(|e| e.to_str()).collect();
+        elements.connect(", ")
+    }
+
+    fn merge(&mut self, other: &ForwardedList) -> bool {
+        match *self {
+            ForwardedList(ref mut elements) => elements.push_all((**other).as_slice()),
+        }
+        true
+    }
+}
+
+#[test]
+fn test_node() {
+    use std::rt::io::net::ip::Ipv4Addr;
+    assert_eq!(Node::parse("192.0.2.43"), Some(AddressNode(Ipv4Addr(192, 0, 2, 43), None)));
+    assert_eq!(Node::parse("192.0.2.43:80"), Some(AddressNode(Ipv4Addr(192, 0, 2, 43), Some(80))));
+    assert_eq!(Node::parse("192.0.2.43:_p"), Some(AddressNode(Ipv4Addr(192, 0, 2, 43), None)));
+    let ipv6 = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    assert_eq!(Node::parse("2001:db8::1"), Some(AddressNode(ipv6, None)));
+    assert_eq!(Node::parse("[2001:db8::1]"), Some(AddressNode(ipv6, None)));
+    assert_eq!(Node::parse("[2001:db8::1]:4711"), Some(AddressNode(ipv6, Some(4711))));
+    assert_eq!(Node::parse("Unknown"), Some(UnknownNode));
+    assert_eq!(Node::parse("_hidden"), Some(ObfuscatedNode(~"_hidden")));
+    assert_eq!(Node::parse("_"), None);
+    assert_eq!(Node::parse("example.com"), None);
+    assert_eq!(Node::parse("[192.0.2.43]"), None);
+    assert_eq!(AddressNode(ipv6, None).forwarded_value(), format!("\"[{}]\"", ipv6.to_str()));
+    assert_eq!(AddressNode(Ipv4Addr(192, 0, 2, 43), None).forwarded_value(), ~"192.0.2.43");
+}
+
+#[test]
+fn test_node_list() {
+    use std::rt::io::net::ip::Ipv4Addr;
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct};
+    assert_conversion_correct("192.0.2.43, unknown, _hidden",
+                              NodeList(~[AddressNode(Ipv4Addr(192, 0, 2, 43), None),
+                                         UnknownNode, ObfuscatedNode(~"_hidden")]));
+    assert_interpretation_correct("2001:db8::1,nonsense,10.0.0.1:80",
+                                  NodeList(~[AddressNode(Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+                                                         None),
+                                             UnknownNode,
+                                             AddressNode(Ipv4Addr(10, 0, 0, 1), Some(80))]));
+}
+
+#[test]
+fn test_forwarded_list() {
+    use std::rt::io::net::ip::Ipv4Addr;
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct};
+    let mut first = ForwardedElement::new();
+    first.for_node = Some(AddressNode(Ipv4Addr(192, 0, 2, 60), None));
+    first.proto = Some(~"https");
+    let mut second = ForwardedElement::new();
+    second.for_node = Some(AddressNode(Ipv4Addr(198, 51, 100, 17), Some(4711)));
+    second.by_node = Some(ObfuscatedNode(~"_proxy"));
+    second.host = Some(~"example.com");
+    assert_conversion_correct(
+        "for=192.0.2.60;proto=https, for=\"198.51.100.17:4711\";by=_proxy;host=example.com",
+        ForwardedList(~[first.clone(), second.clone()]));
+    assert_interpretation_correct(
+        "For=\"192.0.2.60\"; Proto=https; x=\"a,b\",for=198.51.100.17:4711;by=_proxy;\
+         host=\"example.com\"",
+        ForwardedList(~[first, second.clone()]));
+    let mut third = ForwardedElement::new();
+    third.for_node = Some(AddressNode(Ipv6Addr(0x2001, 0xdb8, 0xcafe, 0, 0, 0, 0, 0x17), None));
+    assert_interpretation_correct("for=\"[2001:db8:cafe::17]\", for=\"_bad!\"",
+                                  ForwardedList(~[third, ForwardedElement {
+                                      for_node: Some(UnknownNode),
+                                      ..ForwardedElement::new()
+                                  }]));
+}
@@ -26,15 +26,33 @@ impl ToStr for Host {
 impl super::HeaderConvertible for Host {
     fn from_stream<T: Reader>(reader: &mut super::HeaderValueByteIterator<T>) -> Option<Host> {
         let s = reader.collect_to_str();
-        // TODO: this doesn't support IPv6 address access (e.g. "[::1]")
-        // Do this properly with correct authority parsing.
-        let mut hi = s.splitn_iter(':', 1);
+        // An IPv6 literal is bracketed, as "[::1]:8001" (RFC 3986, section 3.2.2); the name keeps
+        // the brackets, so that it can go straight back into a URL.
+        let name_end = if s.starts_with("[") {
+            match s.find(']') {
+                Some(i) => i + 1,
+                None => return None,
+            }
+        } else {
+            match s.find(':') {
+                Some(i) => i,
+                None => s.len(),
+            }
+        };
+        let (name, rest) = (s.slice_to(name_end), s.slice_from(name_end));
+        let port = if rest.is_empty() {
+            None
+        } else if rest.starts_with(":") {
+            match from_str::<u16>(rest.slice_from(1)) {
+                Some(port) => Some(port),
+                None => return None,
+            }
+        } else {
+            return None;
+        };
         Some(Host {
-            name: hi.next().unwrap().to_owned(),
-            port: match hi.next() {
-                Some(name) => from_str::<u16>(name),
-                None => None,
-            },
+            name: name.to_owned(),
+            port: port,
         })
     }
 
@@ -42,3 +60,15 @@ impl super::HeaderConvertible for Host {
         self.to_str()
     }
 }
+
+#[test]
+fn test_host() {
+    use headers::test_utils::{assert_conversion_correct, assert_invalid};
+    assert_conversion_correct("example.com", Host { name: ~"example.com", port: None });
+    assert_conversion_correct("example.com:8001", Host { name: ~"example.com", port: Some(8001) });
+    assert_conversion_correct("[::1]", Host { name: ~"[::1]", port: None });
+    assert_conversion_correct("[::1]:8001", Host { name: ~"[::1]", port: Some(8001) });
+    assert_invalid::<Host>("[::1");
+    assert_invalid::<Host>("[::1]8001");
+    assert_invalid::<Host>("example.com:http");
+}
@@ -2,17 +2,24 @@
 //!
 //! This HTTP system is Special in that it uses lots of strong typing for its header system. All
 //! known HTTP headers are type checked, rather than being dealt with as strings all the time. Only
-//! unknown headers are stored in a map in the traditional way.
+//! unknown headers are stored by name in the traditional way, in `ExtensionHeaders`.
+//!
+//! Header names are compared without regard to case, as HTTP has them (RFC 7230, section 3.2), by
+//! the `get`, `get_all`, `set`, `append` and `remove` methods of a collection, which take any
+//! header by name, known or not. An unknown header keeps the case of its name as it was received
+//! or set, and is written so; the known ones are written in their usual case.
 
+use std::ascii::StrAsciiExt;
 use std::rt::io::{Reader, Writer};
 use std::rt::io::extensions::ReaderUtil;
-use extra::time::{Tm, strptime};
+use std::rt::io::mem::MemReader;
+use std::vec;
+use extra::time::Tm;
 use extra::url::Url;
-use rfc2616::{is_token_item, is_separator, CR, LF, SP, HT, COLON};
+use rfc2616::{is_token, is_token_item, is_separator, CR, LF, SP, HT, COLON};
+use headers::serialization_utils::is_field_value;
 use method::Method;
 
-use self::serialization_utils::{normalise_header_name};
-
 pub enum HeaderLineErr { EndOfFile, EndOfHeaders, MalformedHeaderValue, MalformedHeaderSyntax }
 
 pub mod test_utils;
@@ -29,7 +36,6 @@ From https://en.wikipedia.org/wiki/List_of_HTTP_headers:
   - Link
   - P3P
   - Refresh
-  - Set-Cookie
   - Status
   - Strict-Transport-Security
 
@@ -47,19 +53,26 @@ be the more canonical source.
 
 */
 
-//pub mod accept;
+pub mod accept;
 //pub mod accept_charset;
-//pub mod accept_encoding;
-//pub mod accept_language;
+pub mod accept_encoding;
+pub mod accept_language;
 pub mod accept_ranges;
-//pub mod cache_control;
+pub mod authorization;
+pub mod cache_control;
 pub mod connection;
 //pub mod content_encoding;
-//pub mod content_range;
+pub mod content_range;
 pub mod content_type;
+pub mod cookie;
+pub mod date;
 pub mod etag;
+pub mod forwarded;
 pub mod host;
+pub mod range;
+pub mod te;
 pub mod transfer_encoding;
+pub mod vary;
 
 pub type DeltaSeconds = u64;
 
@@ -95,6 +108,25 @@ pub trait HeaderEnum {
 /// Shifted out of being a default method to fix an ICE (not yet reported, TODO)
 pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
         -> (Result<E, HeaderLineErr>, Option<u8>) {
+    header_field_from_stream(reader, true)
+}
+
+/// Read a header as `header_enum_from_stream` does, but with a choice of what to do with one
+/// continued on further lines, each beginning with SP or HT (obs-fold, RFC 7230, section 3.2.4):
+/// if `allow_folding`, it is unfolded, each fold being replaced by a single SP, as old clients
+/// still send them; if not, it is `MalformedHeaderSyntax`.
+pub fn header_field_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R, allow_folding: bool)
+        -> (Result<E, HeaderLineErr>, Option<u8>) {
+    let (header, next_byte, _) = named_header_field_from_stream(reader, allow_folding);
+    (header, next_byte)
+}
+
+/// Read a header as `header_field_from_stream` does, and the name it was sent with, which is
+/// otherwise lost with one whose value is malformed. The name is empty should there be no header,
+/// at the end of the headers or of the stream, or should it be malformed itself.
+pub fn named_header_field_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R,
+                                                                allow_folding: bool)
+        -> (Result<E, HeaderLineErr>, Option<u8>, ~str) {
     enum State { Start, ReadingName, NameFinished, GotCR }
     let mut state = Start;
     let mut header_name = ~"";
@@ -107,25 +139,110 @@ pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
             // TODO: check up on the rules for a line like "Name : value". Full LWS?
             (Start, Some(b)) if b == CR => GotCR,
             (Start, Some(b)) | (GotCR, Some(b)) if b == LF => {
-                return (Err(EndOfHeaders), None);
+                return (Err(EndOfHeaders), None, ~"");
             },
             (_, Some(b)) if b == SP => NameFinished,
             (_, Some(b)) if b == COLON => break,
-            (_, Some(_)) => return (Err(MalformedHeaderSyntax), None),
-            (_, None) => return (Err(EndOfFile), None),
+            (_, Some(_)) => return (Err(MalformedHeaderSyntax), None, ~""),
+            (_, None) => return (Err(EndOfFile), None, ~""),
         }
     }
     let mut iter = HeaderValueByteIterator::new(reader);
-    let header = HeaderEnum::value_from_stream(normalise_header_name(header_name), &mut iter);
+    let header = HeaderEnum::value_from_stream(header_name.clone(), &mut iter);
     // Ensure that the entire header line is consumed (don't want to mess up next header!)
     for _ in iter { }
+    if iter.folded && !allow_folding {
+        debug!("folded header {} refused", header_name);
+        return (Err(MalformedHeaderSyntax), iter.next_byte, header_name);
+    }
     match header {
-        Some(h) => (Ok(h), iter.next_byte),
+        Some(h) => (Ok(h), iter.next_byte, header_name),
         None => {
             debug!("malformed header value for {}", header_name);
             // Alas, I can't tell you what the value actually was... TODO: improve that situation
-            (Err(MalformedHeaderValue), iter.next_byte)
+            (Err(MalformedHeaderValue), iter.next_byte, header_name)
+        },
+    }
+}
+
+/// A header field just as it was received: the bytes of its name, and those of its value, without
+/// the white space before and after it, but otherwise untouched, down to the line breaks of any
+/// folding.
+#[deriving(Clone, Eq)]
+pub struct RawHeader {
+    name: ~[u8],
+    value: ~[u8],
+}
+
+impl RawHeader {
+    /// Split a header line, without its line ending, into its name and value.
+    fn from_line(line: &[u8]) -> Option<RawHeader> {
+        let colon = match line.iter().position(|&b| b == COLON) {
+            Some(i) => i,
+            None => return None,
+        };
+        let value = line.slice_from(colon + 1);
+        let start = match value.iter().position(|&b| b != SP && b != HT) {
+            Some(i) => i,
+            None => value.len(),
+        };
+        let end = match value.iter().rposition(|&b| b != SP && b != HT) {
+            Some(i) => i + 1,
+            None => start,
+        };
+        Some(RawHeader {
+            name: line.slice_to(colon).to_owned(),
+            value: value.slice(start, end).to_owned(),
+        })
+    }
+}
+
+/// Read a header as `header_field_from_stream` does, and the field as it was received, byte for
+/// byte. There is no field at the end of the headers or of the stream, nor when the name itself
+/// is malformed; but one whose value is malformed, or which is refused for its folding, is.
+pub fn raw_header_field_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R, allow_folding: bool)
+        -> (Result<E, HeaderLineErr>, Option<u8>, Option<RawHeader>) {
+    let mut recorder = RecordingReader { reader: reader, bytes: ~[] };
+    let (header, next_byte) = header_field_from_stream(&mut recorder, allow_folding);
+    let raw = match header {
+        Err(EndOfFile) | Err(EndOfHeaders) => None,
+        Err(MalformedHeaderSyntax) if next_byte.is_none() => None,
+        _ => {
+            let mut line = recorder.bytes;
+            // Leave out the byte read after the field, and the line ending
+            if next_byte.is_some() {
+                line.pop();
+            }
+            if line.last_opt() == Some(&LF) {
+                line.pop();
+            }
+            if line.last_opt() == Some(&CR) {
+                line.pop();
+            }
+            RawHeader::from_line(line)
         },
+    };
+    (header, next_byte, raw)
+}
+
+/// A reader which keeps a copy of everything read through it.
+struct RecordingReader<'self, R> {
+    reader: &'self mut R,
+    bytes: ~[u8],
+}
+
+impl<'self, R: Reader> Reader for RecordingReader<'self, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        let len = self.reader.read(buf);
+        match len {
+            Some(len) => self.bytes.push_all(buf.slice_to(len)),
+            None => (),
+        }
+        len
+    }
+
+    fn eof(&mut self) -> bool {
+        self.reader.eof()
     }
 }
 
@@ -133,13 +250,15 @@ pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
 enum HeaderValueByteIteratorState {
     Normal,  // Anything other than the rest.
     GotLF,  // Last character was LF (could be end of header or, if followed by SP or HT, LWS)
+    Folding,  // In the SP and HT beginning a continuation line, which are taken as one SP
     Finished,  // Finished, so next() should always return ``None`` immediately (no side effects)
 }
 
 /// An iterator over the bytes of a header value.
 /// This ensures one cannot read past the end of a header mistakenly and that linear white space is
-/// handled correctly so that nothing else needs to worry about it. Any linear whitespace (multiple
-/// spaces outside of a quoted-string) is compacted into a single SP.
+/// handled correctly so that nothing else needs to worry about it. The line breaks of a value
+/// continued over several lines are taken out: each, with the white space beginning the next line,
+/// becomes a single SP.
 pub struct HeaderValueByteIterator<'self, R> {
     reader: &'self mut R,
 
@@ -153,6 +272,9 @@ pub struct HeaderValueByteIterator<'self, R> {
 
     at_start: bool,
     state: HeaderValueByteIteratorState,
+
+    /// Whether the value has been continued on another line (obs-fold) so far.
+    folded: bool,
 }
 
 impl<'self, R: Reader> HeaderValueByteIterator<'self, R> {
@@ -163,6 +285,7 @@ impl<'self, R: Reader> HeaderValueByteIterator<'self, R> {
             next_byte: None,
             at_start: true,
             state: Normal,
+            folded: false,
         }
     }
 
@@ -484,9 +607,24 @@ impl<'self, R: Reader> Iterator<u8> for HeaderValueByteIterator<'self, R> {
                     //
                     //     Header fields can be extended over multiple lines by
                     //     preceding each extra line with at least one SP or HT.
-
+                    //
+                    // RFC 7230, section 3.2.4 has since made this obs-fold, to be replaced with
+                    // SP before the value is interpreted.
+                    self.folded = true;
+                    self.state = Folding;
+                    continue;
+                },
+                Folding if b == SP || b == HT => continue,
+                Folding => {
+                    // The fold is over; it becomes a single SP, unless it began the value or
+                    // ends it, and this byte is to come after it.
+                    self.next_byte = Some(b);
                     self.state = Normal;
-                    return Some(b);
+                    if self.at_start || b == CR || b == LF {
+                        continue;
+                    } else {
+                        return Some(SP);
+                    }
                 },
                 GotLF => {
                     // Ooh! We got to a genuine end of line, so we're done.
@@ -544,6 +682,121 @@ pub trait HeaderConvertible: Eq + Clone {
      * For types which implement ``ToStr``, a body of ``self.to_str()`` will often be sufficient.
      */
     fn http_value(&self) -> ~str;
+
+    /**
+     * The value of the header as it would be written, in as many headers as it is written in;
+     * that is, the one value of ``http_value``, unless the type writes a header for each item.
+     */
+    fn http_values(&self) -> ~[~str] {
+        ~[self.http_value()]
+    }
+
+    /**
+     * Take in the value of a repeat of the header, returning whether it could be; by default it
+     * can't be. A header whose value is a list may be repeated with more of its items (RFC 7230,
+     * section 3.2.2), to be added to this value's.
+     */
+    fn merge(&mut self, _other: &Self) -> bool {
+        false
+    }
+}
+
+/// Interpret a header value which is held as a string (e.g. from a header of a part of a
+/// multipart body), as `HeaderConvertible.from_stream` would when reading it from a request.
+pub fn header_value_from_str<T: HeaderConvertible>(value: &str) -> Option<T> {
+    let mut bytes = value.as_bytes().to_owned();
+    // The header value is ended by a CRLF which isn't followed by linear white space.
+    bytes.push_all(bytes!("\r\n/"));
+    let mut reader = MemReader::new(bytes);
+    let mut iter = HeaderValueByteIterator::new(&mut reader);
+    HeaderConvertible::from_stream(&mut iter)
+}
+
+/// The headers of a collection which aren't of a type known to it, as (name, value) pairs, in the
+/// order in which they were added. Names are compared without regard to case, but kept as given.
+///
+/// Each name must be a token and each value free of control characters but HT (see
+/// `serialization_utils::is_field_value`), or adding it fails, lest it end its header early and
+/// begin others of its own choosing; a value had from a client should be checked first.
+#[deriving(Clone, Eq)]
+pub struct ExtensionHeaders {
+    priv headers: ~[(~str, ~str)],
+}
+
+impl ExtensionHeaders {
+    pub fn new() -> ExtensionHeaders {
+        ExtensionHeaders { headers: ~[] }
+    }
+
+    /// The value of the first header of the name, if there is one.
+    pub fn find<'a, S: Str>(&'a self, name: &S) -> Option<&'a ~str> {
+        let name = name.as_slice();
+        match self.headers.iter().find(|&&(ref n, _)| n.eq_ignore_ascii_case(name)) {
+            Some(&(_, ref value)) => Some(value),
+            None => None,
+        }
+    }
+
+    /// The values of all the headers of the name, in order.
+    pub fn find_all<'a, S: Str>(&'a self, name: &S) -> ~[&'a ~str] {
+        let name = name.as_slice();
+        self.headers.iter().filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+                           .map(|&(_, ref value)| value).collect()
+    }
+
+    /// Set the header of the name to the value, in place of the first of any already set, the
+    /// rest being removed; whether there were none.
+    pub fn insert(&mut self, name: ~str, value: ~str) -> bool {
+        check_field(name, value);
+        match self.headers.iter().position(|&(ref n, _)| n.eq_ignore_ascii_case(name.as_slice())) {
+            Some(i) => {
+                let rest: ~[(~str, ~str)] = self.headers.slice_from(i + 1).iter()
+                        .filter(|&&(ref n, _)| !n.eq_ignore_ascii_case(name.as_slice()))
+                        .map(|header| header.clone()).collect();
+                self.headers.truncate(i);
+                self.headers.push((name, value));
+                self.headers.push_all_move(rest);
+                false
+            },
+            None => {
+                self.headers.push((name, value));
+                true
+            },
+        }
+    }
+
+    /// Add a header after any already set, of the same name or not.
+    pub fn push(&mut self, name: ~str, value: ~str) {
+        check_field(name, value);
+        self.headers.push((name, value));
+    }
+
+    /// Remove all the headers of the name; whether there were any.
+    pub fn remove<S: Str>(&mut self, name: &S) -> bool {
+        let len = self.headers.len();
+        let name = name.as_slice();
+        self.headers.retain(|&(ref n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.len() != len
+    }
+
+    pub fn iter<'a>(&'a self) -> vec::VecIterator<'a, (~str, ~str)> {
+        self.headers.iter()
+    }
+
+    pub fn len(&self) -> uint {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+}
+
+/// Fail unless the header can be written as it is.
+fn check_field(name: &str, value: &str) {
+    if !is_token(name) || !is_field_value(value) {
+        fail!("header {:?} has a name or value which can't be written", name);
+    }
 }
 
 /// A header with multiple comma-separated values. Implement this and a HeaderConvertible
@@ -551,7 +804,7 @@ pub trait HeaderConvertible: Eq + Clone {
 /// comma.
 pub trait CommaListHeaderConvertible: HeaderConvertible {}
 
-impl<T: CommaListHeaderConvertible> HeaderConvertible for ~[T] {
+impl<T: CommaListHeaderConvertible + Clone> HeaderConvertible for ~[T] {
     fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<~[T]> {
         let mut result = ~[];
         loop {
@@ -587,6 +840,11 @@ impl<T: CommaListHeaderConvertible> HeaderConvertible for ~[T] {
         }
         out
     }
+
+    fn merge(&mut self, other: &~[T]) -> bool {
+        self.push_all(other.as_slice());
+        true
+    }
 }
 
 // Now let's have some common implementation types.
@@ -615,6 +873,16 @@ impl HeaderConvertible for uint {
     }
 }
 
+impl HeaderConvertible for u64 {
+    fn from_stream<T: Reader>(reader: &mut HeaderValueByteIterator<T>) -> Option<u64> {
+        FromStr::from_str(reader.collect_to_str())
+    }
+
+    fn http_value(&self) -> ~str {
+        self.to_str()
+    }
+}
+
 impl HeaderConvertible for Url {
     fn from_stream<T: Reader>(reader: &mut HeaderValueByteIterator<T>) -> Option<Url> {
         FromStr::from_str(reader.collect_to_str())
@@ -700,38 +968,29 @@ impl HeaderConvertible for Method {
  */
 impl HeaderConvertible for Tm {
     fn from_stream<T: Reader>(reader: &mut HeaderValueByteIterator<T>) -> Option<Tm> {
-        let value = reader.collect_to_str();
-
-        // XXX: %Z actually ignores any timezone other than UTC. Probably not a good idea?
-        match strptime(value, "%a, %d %b %Y %T %Z") {  // RFC 822, updated by RFC 1123
-            Ok(time) => return Some(time),
-            Err(*) => ()
-        }
-
-        match strptime(value, "%A, %d-%b-%y %T %Z") {  // RFC 850, obsoleted by RFC 1036
-            Ok(time) => return Some(time),
-            Err(*) => ()
-        }
-
-        match strptime(value, "%c") {  // ANSI C's asctime() format
-            Ok(time) => Some(time),
-            Err(*) => None
-        }
+        date::parse(reader.collect_to_str())
     }
 
     fn to_stream<T: Writer>(&self, writer: &mut T) {
-        let s = self.to_utc().strftime("%a, %d %b %Y %T GMT");
+        let s = date::format(self);
         writer.write(s.as_bytes());
     }
 
     fn http_value(&self) -> ~str {
-        self.to_utc().strftime("%a, %d %b %Y %T GMT")
+        date::format(self)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::rt::io::Decorator;
+    use std::rt::io::mem::{MemReader, MemWriter};
+    use std::str;
     use extra::time::Tm;
+    use headers::{request, response, header_field_from_stream, raw_header_field_from_stream,
+                  HeaderLineErr, MalformedHeaderSyntax, MalformedHeaderValue, ExtensionHeaders,
+                  RawHeader, HeaderEnum};
+    use headers::connection::Token;
     use headers::test_utils::{from_stream_with_str, to_stream_into_str};
 
     fn test_from_stream_str() {
@@ -769,6 +1028,14 @@ mod test {
         assert_eq!(to_stream_into_str(&123456789u), ~"123456789");
     }
 
+    #[test]
+    fn test_from_stream_u64() {
+        assert_eq!(from_stream_with_str::<u64>("foo bar"), None);
+        assert_eq!(from_stream_with_str("0"), Some(0u64));
+        assert_eq!(from_stream_with_str("12345678901"), Some(12345678901u64));
+        assert_eq!(to_stream_into_str(&12345678901u64), ~"12345678901");
+    }
+
     fn sample_tm(zone: ~str) -> Tm {
         Tm {
             tm_sec: 37,
@@ -835,11 +1102,167 @@ mod test {
         assert_eq!(sample_tm(~"UTC").http_value(), ~"Sun, 06 Nov 1994 08:49:37 GMT");
     }
 
+    #[test]
+    fn test_obs_fold() {
+        assert_eq!(from_stream_with_str("a,\r\n  \tb"), Some(~"a, b"));
+        assert_eq!(from_stream_with_str("a\r\n b\n\tc"), Some(~"a b c"));
+        assert_eq!(from_stream_with_str("\r\n b"), Some(~"b"));
+        assert_eq!(from_stream_with_str("Sun, 06 Nov\r\n 1994 08:49:37 GMT"),
+                   Some(sample_tm(~"UTC")));
+
+        // A folded header which isn't allowed is malformed
+        fn read(allow_folding: bool) -> Option<~str> {
+            let input = bytes!("X-Folded: a\r\n b\r\nHost: x\r\n").to_owned();
+            let (header, next_byte) = header_field_from_stream(&mut MemReader::new(input),
+                                                               allow_folding);
+            assert_eq!(next_byte, Some('H' as u8));
+            match header {
+                Ok(request::ExtensionHeader(name, value)) => {
+                    assert_eq!(name, ~"X-Folded");
+                    Some(value)
+                },
+                Err(MalformedHeaderSyntax) => None,
+                _ => fail!("folded header read wrongly"),
+            }
+        }
+        assert_eq!(read(true), Some(~"a b"));
+        assert_eq!(read(false), None);
+    }
+
     /// Test `to_stream`, which outputs an RFC 1123 time
     #[test]
     fn test_to_stream() {
         assert_eq!(to_stream_into_str(&sample_tm(~"UTC")), ~"Sun, 06 Nov 1994 08:49:37 GMT");
     }
+
+    #[test]
+    fn test_raw_header_field() {
+        fn read(s: &str) -> (Option<RawHeader>, Option<u8>) {
+            let mut reader = MemReader::new(s.as_bytes().to_owned());
+            let (_, next_byte, raw): (Result<request::Header, HeaderLineErr>, Option<u8>,
+                                      Option<RawHeader>) =
+                raw_header_field_from_stream(&mut reader, true);
+            (raw, next_byte)
+        }
+        fn raw(name: &str, value: &str) -> Option<RawHeader> {
+            Some(RawHeader { name: name.as_bytes().to_owned(), value: value.as_bytes().to_owned() })
+        }
+        assert_eq!(read("content-LENGTH:  12 \r\nX"),
+                   (raw("content-LENGTH", "12"), Some('X' as u8)));
+        assert_eq!(read("X-Thing: a,\r\n\t b\r\n\r\n"),
+                   (raw("X-Thing", "a,\r\n\t b"), Some('\r' as u8)));
+        assert_eq!(read("Content-Length: twelve\r\n\r\n"),
+                   (raw("Content-Length", "twelve"), Some('\r' as u8)));
+        assert_eq!(read("\r\n"), (None, None));
+    }
+
+    #[test]
+    fn test_extension_headers() {
+        let mut extensions = ExtensionHeaders::new();
+        assert!(extensions.insert(~"X-Thing", ~"1"));
+        extensions.push(~"x-other", ~"2");
+        extensions.push(~"X-THING", ~"3");
+        assert_eq!(extensions.find(&~"x-thing"), Some(&~"1"));
+        assert_eq!(extensions.find_all(&~"X-Thing"), ~[&~"1", &~"3"]);
+        assert!(!extensions.insert(~"x-Thing", ~"4"));
+        assert_eq!(extensions.iter().map(|h| h.clone()).collect::<~[(~str, ~str)]>(),
+                   ~[(~"x-Thing", ~"4"), (~"x-other", ~"2")]);
+        assert!(extensions.remove(&~"X-OTHER"));
+        assert!(!extensions.remove(&~"X-Other"));
+        assert_eq!(extensions.len(), 1);
+    }
+
+    #[test]
+    fn test_collection_by_name() {
+        let mut headers = request::HeaderCollection::new();
+        assert!(headers.set("content-length", "12"));
+        assert_eq!(headers.content_length, Some(12));
+        assert!(!headers.set("Content-Length", "twelve"));
+        assert_eq!(headers.get("CONTENT-LENGTH"), Some(~"12"));
+        assert!(!headers.append("Content-Length", "twelve"));
+
+        assert!(headers.set("via", "1.0 a"));
+        assert!(headers.append("Via", "1.1 b, 1.1 \"c, d\""));
+        assert_eq!(headers.via, Some(~"1.0 a"));
+        assert_eq!(headers.get_all("VIA").collect::<~[~str]>(),
+                   ~[~"1.0 a", ~"1.1 b, 1.1 \"c, d\""]);
+        assert_eq!(headers.get_list("via"), ~[~"1.0 a", ~"1.1 b", ~"1.1 \"c, d\""]);
+
+        assert!(headers.set("X-Request-ID", "abc"));
+        assert!(headers.append("x-request-id", "def"));
+        assert_eq!(headers.get("x-request-id"), Some(~"abc"));
+        assert_eq!(headers.get_all("X-Request-Id").collect::<~[~str]>(), ~[~"abc", ~"def"]);
+        let mut writer = MemWriter::new();
+        headers.write_all(&mut writer);
+        assert_eq!(str::from_utf8(writer.inner_ref().as_slice()),
+                   ~"Via: 1.0 a\r\nContent-Length: 12\r\nVia: 1.1 b, 1.1 \"c, d\"\r\n\
+                     X-Request-ID: abc\r\nx-request-id: def\r\n\r\n");
+
+        assert!(headers.remove("X-REQUEST-ID"));
+        assert!(headers.remove("content-length"));
+        assert!(!headers.remove("Content-Length"));
+        assert_eq!(headers.get("X-Request-Id"), None);
+        assert!(headers.get_all("Content-Length").next().is_none());
+        assert!(headers.set("Via", "1.1 e"));
+        assert_eq!(headers.get_all("Via").collect::<~[~str]>(), ~[~"1.1 e"]);
+    }
+
+    #[test]
+    fn test_header_splitting() {
+        let mut headers = response::HeaderCollection::new();
+        assert!(!headers.set("Location", "/a\r\nSet-Cookie: b=1"));
+        assert!(!headers.set("X-Thing", "a\nb"));
+        assert!(!headers.append("X-Thing", "a\x00"));
+        assert!(!headers.set("X-Thing\r\nSet-Cookie", "b=1"));
+        assert!(!headers.set("X Thing", "a"));
+        assert!(headers.set("X-Thing", "a\tb"));
+        assert!(headers.location.is_none());
+
+        // What can be set directly is sanitised as it is written
+        headers.via = Some(~"1.1 a\r\nSet-Cookie: b=1");
+        let mut writer = MemWriter::new();
+        headers.write_all(&mut writer);
+        assert_eq!(str::from_utf8(writer.inner_ref().as_slice()),
+                   ~"Via: 1.1 a  Set-Cookie: b=1\r\nX-Thing: a\tb\r\n\r\n");
+
+        let mut writer = MemWriter::new();
+        response::ExtensionHeader(~"X-A\r\nB", ~"c\r\n").write_header(&mut writer);
+        assert_eq!(writer.inner_ref().as_slice(), bytes!("X-A  B: c  \r\n"));
+
+        // Nor is such a value taken from a client
+        let mut reader = MemReader::new(bytes!("X-Thing: a\x01b\r\n\r\n").to_owned());
+        let (header, _): (Result<request::Header, HeaderLineErr>, Option<u8>) =
+            header_field_from_stream(&mut reader, true);
+        assert!(match header { Err(MalformedHeaderValue) => true, _ => false });
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_extension_header_splitting() {
+        ExtensionHeaders::new().push(~"X-Thing", ~"a\r\nSet-Cookie: b=1");
+    }
+
+    #[test]
+    fn test_repeated_headers() {
+        let mut headers = request::HeaderCollection::new();
+        headers.push(request::ContentLength(12));
+        headers.push(request::ContentLength(13));
+        assert_eq!(headers.content_length, Some(12));
+        assert_eq!(headers.get_all("Content-Length").collect::<~[~str]>(), ~[~"12", ~"13"]);
+
+        headers.push(request::Connection(~[Token(~"Keep-Alive")]));
+        headers.push(request::Connection(~[Token(~"Upgrade")]));
+        assert_eq!(headers.connection, Some(~[Token(~"Keep-Alive"), Token(~"Upgrade")]));
+        assert_eq!(headers.get_all("connection").collect::<~[~str]>(), ~[~"Keep-Alive, Upgrade"]);
+
+        headers.insert(request::ContentLength(14));
+        assert_eq!(headers.get_all("Content-Length").collect::<~[~str]>(), ~[~"14"]);
+
+        let mut headers = response::HeaderCollection::new();
+        assert!(headers.append("Set-Cookie", "a=1"));
+        assert!(headers.append("set-cookie", "b=2; Path=/"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<~[~str]>(), ~[~"a=1", ~"b=2; Path=/"]);
+    }
 }
 
 macro_rules! headers_mod {
@@ -863,10 +1286,14 @@ macro_rules! headers_mod {
             $attr;
 
             use extra;
+            use std::ascii::StrAsciiExt;
             use std::rt::io::{Reader, Writer};
-            use extra::treemap::{TreeMap, TreeMapIterator};
+            use std::vec;
             use headers;
-            use headers::{HeaderEnum, HeaderConvertible, HeaderValueByteIterator};
+            use headers::{HeaderEnum, HeaderConvertible, HeaderValueByteIterator, ExtensionHeaders};
+            use headers::serialization_utils::{normalise_header_name, is_field_value,
+                                               write_field_bytes};
+            use rfc2616::is_token;
 
             pub enum Header {
                 $($caps_ident($htype),)*
@@ -876,7 +1303,7 @@ macro_rules! headers_mod {
             // Can't use #[deriving(Clone)] because of https://github.com/mozilla/rust/issues/6976
             pub struct HeaderCollection {
                 $($lower_ident: Option<$htype>,)*
-                extensions: TreeMap<~str, ~str>,
+                extensions: ExtensionHeaders,
             }
 
             impl Clone for HeaderCollection {
@@ -892,18 +1319,147 @@ macro_rules! headers_mod {
                 pub fn new() -> HeaderCollection {
                     HeaderCollection {
                         $($lower_ident: None,)*
-                        extensions: TreeMap::new(),
+                        extensions: ExtensionHeaders::new(),
                     }
                 }
 
-                /// Consume a header, putting it into this structure.
+                /// Consume a header, putting it into this structure, in place of any of the same
+                /// name.
                 pub fn insert(&mut self, header: Header) {
                     match header {
-                        $($caps_ident(value) => self.$lower_ident = Some(value),)*
+                        $($caps_ident(value) => {
+                            self.$lower_ident = Some(value);
+                            self.extensions.remove(&$output_name);
+                        },)*
                         ExtensionHeader(key, value) => { self.extensions.insert(key, value); },
                     }
                 }
 
+                /// Consume a header, as one received, after any of the same name: neither merged
+                /// with nor in place of them, but for a known header whose value is a list, whose
+                /// items are added to those it has (see `HeaderConvertible.merge`). A repeat of
+                /// another known header is kept with the extension headers, under its name.
+                pub fn push(&mut self, header: Header) {
+                    match header {
+                        $($caps_ident(value) => {
+                            let merged = match self.$lower_ident {
+                                Some(ref mut existing) => existing.merge(&value),
+                                None => false,
+                            };
+                            if self.$lower_ident.is_none() {
+                                self.$lower_ident = Some(value);
+                            } else if !merged {
+                                for v in value.http_values().move_iter() {
+                                    self.extensions.push($output_name.to_owned(), v);
+                                }
+                            }
+                        },)*
+                        ExtensionHeader(key, value) => self.extensions.push(key, value),
+                    }
+                }
+
+                /// The value of the header of the name given, in any case, as it would be written,
+                /// if it is set; of an extension header, the first of the name.
+                pub fn get(&self, name: &str) -> Option<~str> {
+                    $(if name.eq_ignore_ascii_case($output_name) {
+                        return match self.$lower_ident {
+                            Some(ref h) => Some(h.http_value()),
+                            None => None,
+                        };
+                    })*
+                    match self.extensions.find(&name) {
+                        Some(value) => Some(value.clone()),
+                        None => None,
+                    }
+                }
+
+                /// The values of each of the headers of the name given, in any case, in order: of
+                /// a known one, its value, if it is set (or, for Set-Cookie, that of each cookie),
+                /// and that of each repeat of it which couldn't be merged with it.
+                pub fn get_all(&self, name: &str) -> vec::MoveIterator<~str> {
+                    let mut values = ~[];
+                    $(if name.eq_ignore_ascii_case($output_name) {
+                        match self.$lower_ident {
+                            Some(ref h) => values.push_all_move(h.http_values()),
+                            None => (),
+                        }
+                    })*
+                    for value in self.extensions.find_all(&name).move_iter() {
+                        values.push(value.clone());
+                    }
+                    values.move_iter()
+                }
+
+                /// The items of a header whose value is a list, from its value and any repeats of
+                /// it: each value split at the commas outside quoted strings, and trimmed, with
+                /// any empty items left out.
+                pub fn get_list(&self, name: &str) -> ~[~str] {
+                    let mut items = ~[];
+                    for value in self.get_all(name) {
+                        items.push_all_move(headers::serialization_utils::split_list(value));
+                    }
+                    items
+                }
+
+                /// Set the header of the name given, in any case, to the value, in place of any
+                /// already set, returning whether it could be: the value must have no control
+                /// characters but HT, the name of an extension header must be a token, and the
+                /// value of a known header must be one it can interpret, or nothing is changed. An
+                /// extension header is given the name as it is.
+                pub fn set(&mut self, name: &str, value: &str) -> bool {
+                    if !is_field_value(value) {
+                        return false;
+                    }
+                    $(if name.eq_ignore_ascii_case($output_name) {
+                        return match headers::header_value_from_str(value) {
+                            Some(v) => {
+                                self.insert($caps_ident(v));
+                                true
+                            },
+                            None => false,
+                        };
+                    })*
+                    if !is_token(name) {
+                        return false;
+                    }
+                    self.extensions.insert(name.to_owned(), value.to_owned());
+                    true
+                }
+
+                /// Add a header of the name given, in any case, after any of the name, as `push`
+                /// does, returning whether it could be, as for `set`. Each header is written on a
+                /// line of its own, but for a known one whose value is a list, which is written as
+                /// one.
+                pub fn append(&mut self, name: &str, value: &str) -> bool {
+                    if !is_field_value(value) {
+                        return false;
+                    }
+                    $(if name.eq_ignore_ascii_case($output_name) {
+                        return match headers::header_value_from_str(value) {
+                            Some(v) => {
+                                self.push($caps_ident(v));
+                                true
+                            },
+                            None => false,
+                        };
+                    })*
+                    if !is_token(name) {
+                        return false;
+                    }
+                    self.extensions.push(name.to_owned(), value.to_owned());
+                    true
+                }
+
+                /// Remove the headers of the name given, in any case, returning whether any were
+                /// set.
+                pub fn remove(&mut self, name: &str) -> bool {
+                    let mut removed = false;
+                    $(if name.eq_ignore_ascii_case($output_name) {
+                        removed = self.$lower_ident.take().is_some();
+                    })*
+                    self.extensions.remove(&name) || removed
+                }
+
                 pub fn iter<'a>(&'a self) -> HeaderCollectionIterator<'a> {
                     HeaderCollectionIterator {
                         pos: 0,
@@ -925,7 +1481,7 @@ macro_rules! headers_mod {
             pub struct HeaderCollectionIterator<'self> {
                 pos: uint,
                 coll: &'self HeaderCollection,
-                ext_iter: Option<TreeMapIterator<'self, ~str, ~str>>
+                ext_iter: Option<vec::VecIterator<'self, (~str, ~str)>>
             }
 
             impl<'self> Iterator<Header> for HeaderCollectionIterator<'self> {
@@ -942,7 +1498,7 @@ macro_rules! headers_mod {
                                 continue
                             },
                             _ => match self.ext_iter.get_mut_ref().next() {
-                                Some((k, v)) =>
+                                Some(&(ref k, ref v)) =>
                                     return Some(ExtensionHeader(k.to_owned(), v.to_owned())),
                                 None => return None,
                             },
@@ -970,43 +1526,49 @@ macro_rules! headers_mod {
                 fn write_header<T: Writer>(&self, writer: &mut T) {
                     match *self {
                         ExtensionHeader(ref name, ref value) => {
-                            // TODO: be more efficient
-                            let mut s = ~"";
-                            // Allocate for name, ": " and quoted value (typically an overallocation
-                            // of 2 bytes, occasionally an underallocation in case of needing to
-                            // escape double quotes)
-                            s.reserve(name.len() + 4 + value.len());
-                            s.push_str(*name);
-                            s.push_str(": ");
-                            s.push_str(*value);
-                            writer.write(s.as_bytes());
+                            // Whatever is in it, it can't end the line early (see
+                            // `write_field_bytes`)
+                            write_field_bytes(writer, name.as_bytes());
+                            writer.write(bytes!(": "));
+                            write_field_bytes(writer, value.as_bytes());
                             writer.write(bytes!("\r\n"));
                             return
                         },
                         _ => (),
                     }
 
-                    writer.write(match *self {
-                        $($caps_ident(*) => bytes!($output_name, ": "),)*
-                        ExtensionHeader(*) => unreachable!(),  // Already returned
-                    });
-
-                    // FIXME: all the `h` cases satisfy HeaderConvertible, can it be simplified?
+                    // A known header's value may be a string set to anything, so its value is
+                    // checked as an extension header's is; and it may be written in several
+                    // headers (Set-Cookie), each on a line of its own.
                     match *self {
-                        $($caps_ident(ref h) => h.to_stream(writer),)*
-                        ExtensionHeader(*) =>     unreachable!(),  // Already returned
-                    };
-                    writer.write(bytes!("\r\n"));
+                        $($caps_ident(ref h) => for value in h.http_values().iter() {
+                            writer.write(bytes!($output_name, ": "));
+                            write_field_bytes(writer, value.as_bytes());
+                            writer.write(bytes!("\r\n"));
+                        },)*
+                        ExtensionHeader(*) => unreachable!(),  // Already returned
+                    }
                 }
 
                 fn value_from_stream<T: Reader>(name: ~str, value: &mut HeaderValueByteIterator<T>)
                         -> Option<Header> {
-                    match name.as_slice() {
+                    // An extension header keeps its name as it came; the others are known in
+                    // any case.
+                    let normalised = normalise_header_name(name.as_slice());
+                    match normalised.as_slice() {
                         $($input_name => match HeaderConvertible::from_stream(value) {
                             Some(v) => Some($caps_ident(v)),
                             None => None,
                         },)*
-                        _ => Some(ExtensionHeader(name, value.collect_to_str())),
+                        _ => {
+                            // A value which couldn't be written again is malformed
+                            let value = value.collect_to_str();
+                            if is_field_value(value) {
+                                Some(ExtensionHeader(name, value))
+                            } else {
+                                None
+                            }
+                        },
                     }
                 }
             }
@@ -1018,10 +1580,10 @@ headers_mod! {
     #[doc = "Request whatnottery."]
     pub mod request;
 
-    num_headers: 38;
+    num_headers: 42;
 
     // RFC 2616, Section 4.5: General Header Fields
-     0, "Cache-Control",     "Cache-Control",     CacheControl,     cache_control,     ~str;
+     0, "Cache-Control",     "Cache-Control",     CacheControl,     cache_control,     headers::cache_control::CacheControl;
      1, "Connection",        "Connection",        Connection,       connection,        ~[headers::connection::Connection];
      2, "Date",              "Date",              Date,             date,              extra::time::Tm;
      3, "Pragma",            "Pragma",            Pragma,           pragma,            ~str;
@@ -1032,24 +1594,24 @@ headers_mod! {
      8, "Warning",           "Warning",           Warning,          warning,           ~str;
 
     // RFC 2616, Section 5.3: Request Header Fields
-     9, "Accept",              "Accept",              Accept,             accept,              ~str;
+     9, "Accept",              "Accept",              Accept,             accept,              ~[headers::accept::MediaRange];
     10, "Accept-Charset",      "Accept-Charset",      AcceptCharset,      accept_charset,      ~str;
-    11, "Accept-Encoding",     "Accept-Encoding",     AcceptEncoding,     accept_encoding,     ~str;
-    12, "Accept-Language",     "Accept-Language",     AcceptLanguage,     accept_language,     ~str;
-    13, "Authorization",       "Authorization",       Authorization,      authorization,       ~str;
+    11, "Accept-Encoding",     "Accept-Encoding",     AcceptEncoding,     accept_encoding,     ~[headers::accept_encoding::AcceptableCoding];
+    12, "Accept-Language",     "Accept-Language",     AcceptLanguage,     accept_language,     ~[headers::accept_language::LanguageRange];
+    13, "Authorization",       "Authorization",       Authorization,      authorization,       headers::authorization::Credentials;
     14, "Expect",              "Expect",              Expect,             expect,              ~str;
     15, "From",                "From",                From,               from,                ~str;
     16, "Host",                "Host",                Host,               host,                headers::host::Host;
-    17, "If-Match",            "If-Match",            IfMatch,            if_match,            ~str;
+    17, "If-Match",            "If-Match",            IfMatch,            if_match,            headers::etag::EntityTagList;
     18, "If-Modified-Since",   "If-Modified-Since",   IfModifiedSince,    if_modified_since,   extra::time::Tm;
-    19, "If-None-Match",       "If-None-Match",       IfNoneMatch,        if_none_match,       ~str;
+    19, "If-None-Match",       "If-None-Match",       IfNoneMatch,        if_none_match,       headers::etag::EntityTagList;
     20, "If-Range",            "If-Range",            IfRange,            if_range,            ~str;
     21, "If-Unmodified-Since", "If-Unmodified-Since", IfUnmodifiedSince,  if_unmodifiedSince,  extra::time::Tm;
     22, "Max-Forwards",        "Max-Forwards",        MaxForwards,        max_forwards,        uint;
-    23, "Proxy-Authorization", "Proxy-Authorization", ProxyAuthorization, proxy_authorization, ~str;
-    24, "Range",               "Range",               Range,              range,               ~str;
+    23, "Proxy-Authorization", "Proxy-Authorization", ProxyAuthorization, proxy_authorization, headers::authorization::Credentials;
+    24, "Range",               "Range",               Range,              range,               headers::range::ByteRanges;
     25, "Referer",             "Referer",             Referer,            referer,             ~str;
-    26, "TE",                  "Te",                  Te,                 te,                  ~str;
+    26, "TE",                  "Te",                  Te,                 te,                  ~[headers::te::TCoding];
     27, "User-Agent",          "User-Agent",          UserAgent,          user_agent,          ~str;
 
     // RFC 2616, Section 7.1: Entity Header Fields
@@ -1059,20 +1621,28 @@ headers_mod! {
     31, "Content-Length",   "Content-Length",   ContentLength,   content_length,   uint;
     32, "Content-Location", "Content-Location", ContentLocation, content_location, ~str;
     33, "Content-MD5",      "Content-Md5",      ContentMd5,      content_md5,      ~str;
-    34, "Content-Range",    "Content-Range",    ContentRange,    content_range,    ~str;
+    34, "Content-Range",    "Content-Range",    ContentRange,    content_range,    headers::content_range::ContentRange;
     35, "Content-Type",     "Content-Type",     ContentType,     content_type,     headers::content_type::MediaType;
     36, "Expires",          "Expires",          Expires,         expires,          extra::time::Tm;
     37, "Last-Modified",    "Last-Modified",    LastModified,    last_modified,    extra::time::Tm;
+
+    // RFC 6265: HTTP State Management Mechanism
+    38, "Cookie", "Cookie", Cookie, cookie, headers::cookie::CookieList;
+
+    // RFC 7239: Forwarded HTTP Extension, and the headers it standardises
+    39, "Forwarded",         "Forwarded",         Forwarded,       forwarded,         headers::forwarded::ForwardedList;
+    40, "X-Forwarded-For",   "X-Forwarded-For",   XForwardedFor,   x_forwarded_for,   headers::forwarded::NodeList;
+    41, "X-Forwarded-Proto", "X-Forwarded-Proto", XForwardedProto, x_forwarded_proto, ~str;
 }
 
 headers_mod! {
     #[doc = "Response whatnottery."]
     pub mod response;
 
-    num_headers: 29;
+    num_headers: 30;
 
     // RFC 2616, Section 4.5: General Header Fields
-     0, "Cache-Control",     "Cache-Control",     CacheControl,     cache_control,     ~str;
+     0, "Cache-Control",     "Cache-Control",     CacheControl,     cache_control,     headers::cache_control::CacheControl;
      1, "Connection",        "Connection",        Connection,       connection,        ~[headers::connection::Connection];
      2, "Date",              "Date",              Date,             date,              extra::time::Tm;
      3, "Pragma",            "Pragma",            Pragma,           pragma,            ~str;
@@ -1085,13 +1655,15 @@ headers_mod! {
     // RFC 2616, Section 6.2: Response Header Fields
      9, "Accept-Patch",       "Accept-Patch",       AcceptPatch,       accept_patch,       ~str;
     10, "Accept-Ranges",      "Accept-Ranges",      AcceptRanges,      accept_ranges,      headers::accept_ranges::AcceptableRanges;
-    11, "Age",                "Age",                Age,               age,                ~str;
+    11, "Age",                "Age",                Age,               age,                headers::DeltaSeconds;
     12, "ETag",               "Etag",               ETag,              etag,               headers::etag::EntityTag;
-    13, "Location",           "Location",           Location,          location,           extra::url::Url;
+    // Location may be a reference relative to the URL requested (RFC 7231, section 7.1.2), which a
+    // Url can't be, so it is kept as it was sent.
+    13, "Location",           "Location",           Location,          location,           ~str;
     14, "Proxy-Authenticate", "Proxy-Authenticate", ProxyAuthenticate, proxy_authenticate, ~str;
     15, "Retry-After",        "Retry-After",        RetryAfter,        retry_after,        ~str;
     16, "Server",             "Server",             Server,            server,             ~str;
-    17, "Vary",               "Vary",               Vary,              vary,               ~str;
+    17, "Vary",               "Vary",               Vary,              vary,               headers::vary::Vary;
     18, "WWW-Authenticate",   "Www-Authenticate",   WwwAuthenticate,   www_authenticate,   ~str;
 
     // RFC 2616, Section 7.1: Entity Header Fields
@@ -1101,8 +1673,11 @@ headers_mod! {
     22, "Content-Length",   "Content-Length",   ContentLength,   content_length,   uint;
     23, "Content-Location", "Content-Location", ContentLocation, content_location, ~str;
     24, "Content-MD5",      "Content-Md5",      ContentMd5,      content_md5,      ~str;
-    25, "Content-Range",    "Content-Range",    ContentRange,    content_range,    ~str;
+    25, "Content-Range",    "Content-Range",    ContentRange,    content_range,    headers::content_range::ContentRange;
     26, "Content-Type",     "Content-Type",     ContentType,     content_type,     headers::content_type::MediaType;
-    27, "Expires",          "Expires",          Expires,         expires,          ~str; // TODO: Should be Tm
+    27, "Expires",          "Expires",          Expires,         expires,          extra::time::Tm;
     28, "Last-Modified",    "Last-Modified",    LastModified,    last_modified,    extra::time::Tm;
+
+    // RFC 6265: HTTP State Management Mechanism
+    29, "Set-Cookie", "Set-Cookie", SetCookie, set_cookie, headers::cookie::SetCookieList;
 }
@@ -0,0 +1,136 @@
+//! The Range request header, defined in RFC 2616, Section 14.35.
+//!
+//! Range             = "Range" ":" ranges-specifier
+//! ranges-specifier  = byte-ranges-specifier
+//! byte-ranges-specifier = bytes-unit "=" byte-range-set
+//! byte-range-set    = 1#( byte-range-spec | suffix-byte-range-spec )
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::Reader;
+
+/// One range of bytes asked for.
+#[deriving(Clone, Eq)]
+pub enum ByteRangeSpec {
+    /// The bytes from the first position given to the last, inclusive, or to the end if there is
+    /// no last position: `first-last` or `first-`.
+    FromTo(u64, Option<u64>),
+    /// The last so many bytes: `-length`.
+    Suffix(u64),
+}
+
+impl ByteRangeSpec {
+    /// The first and last positions (inclusive) of the range in a representation of the given
+    /// length, or `None` if the range is not satisfiable, starting beyond the end.
+    pub fn satisfiable_range(&self, length: u64) -> Option<(u64, u64)> {
+        match *self {
+            FromTo(first, _) if first >= length => None,
+            FromTo(first, Some(last)) if last < length => Some((first, last)),
+            FromTo(first, _) => Some((first, length - 1)),
+            Suffix(0) => None,
+            Suffix(_) if length == 0 => None,
+            Suffix(suffix) if suffix >= length => Some((0, length - 1)),
+            Suffix(suffix) => Some((length - suffix, length - 1)),
+        }
+    }
+
+    fn parse(s: &str) -> Option<ByteRangeSpec> {
+        let i = match s.find('-') {
+            Some(i) => i,
+            None => return None,
+        };
+        let (first, last) = (s.slice_to(i).trim(), s.slice_from(i + 1).trim());
+        if !first.iter().all(|c| c.is_digit()) || !last.iter().all(|c| c.is_digit()) {
+            return None;
+        }
+        match (from_str::<u64>(first), from_str::<u64>(last)) {
+            (Some(first), Some(last)) if first <= last => Some(FromTo(first, Some(last))),
+            (Some(first), None) if last.is_empty() => Some(FromTo(first, None)),
+            (None, Some(suffix)) if first.is_empty() => Some(Suffix(suffix)),
+            _ => None,
+        }
+    }
+
+    fn to_str(&self) -> ~str {
+        match *self {
+            FromTo(first, Some(last)) => format!("{}-{}", first, last),
+            FromTo(first, None) => format!("{}-", first),
+            Suffix(suffix) => format!("-{}", suffix),
+        }
+    }
+}
+
+/// The byte ranges of a Range header. A Range header in a unit other than bytes, or with any
+/// invalid range, is to be ignored, and so is not parsed.
+#[deriving(Clone, Eq)]
+pub struct ByteRanges(~[ByteRangeSpec]);
+
+impl ByteRanges {
+    /// The satisfiable ranges in a representation of the given length, as first and last
+    /// positions. If there are none, a 416 (Requested Range Not Satisfiable) response should be
+    /// sent.
+    pub fn satisfiable_ranges(&self, length: u64) -> ~[(u64, u64)] {
+        (**self).iter().filter_map(|spec| spec.satisfiable_range(length)).collect()
+    }
+}
+
+impl super::HeaderConvertible for ByteRanges {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<ByteRanges> {
+        let s = reader.collect_to_str();
+        let i = match s.find('=') {
+            Some(i) => i,
+            None => return None,
+        };
+        if !s.slice_to(i).trim().eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let mut specs = ~[];
+        for spec in s.slice_from(i + 1).split_iter(',') {
+            let spec = spec.trim();
+            // Null list elements are allowed (RFC 2616, section 2.1).
+            if spec.is_empty() {
+                continue;
+            }
+            match ByteRangeSpec::parse(spec) {
+                Some(spec) => specs.push(spec),
+                None => return None,
+            }
+        }
+        if specs.is_empty() {
+            None
+        } else {
+            Some(ByteRanges(specs))
+        }
+    }
+
+    fn http_value(&self) -> ~str {
+        let specs: ~[~str] = (**self).iter().map(|spec| spec.to_str()).collect();
+        format!("bytes={}", specs.connect(","))
+    }
+}
+
+#[test]
+fn test_byte_ranges() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("bytes=0-499", ByteRanges(~[FromTo(0, Some(499))]));
+    assert_conversion_correct("bytes=500-,-500,0-0",
+                              ByteRanges(~[FromTo(500, None), Suffix(500), FromTo(0, Some(0))]));
+    assert_interpretation_correct("Bytes = 1-2 , , 3-4",
+                                  ByteRanges(~[FromTo(1, Some(2)), FromTo(3, Some(4))]));
+    assert_invalid::<ByteRanges>("bytes=");
+    assert_invalid::<ByteRanges>("bytes=5-4");
+    assert_invalid::<ByteRanges>("bytes=0-1,x-y");
+    assert_invalid::<ByteRanges>("bytes=-");
+    assert_invalid::<ByteRanges>("bytes=+1-2");
+    assert_invalid::<ByteRanges>("pages=1-2");
+}
+
+#[test]
+fn test_satisfiable_ranges() {
+    let ranges = ByteRanges(~[FromTo(0, Some(9)), FromTo(95, Some(200)), FromTo(100, None),
+                              Suffix(20), Suffix(0)]);
+    assert_eq!(ranges.satisfiable_ranges(100), ~[(0, 9), (95, 99), (80, 99)]);
+    assert_eq!(ranges.satisfiable_ranges(10), ~[(0, 9), (0, 9)]);
+    assert_eq!(ranges.satisfiable_ranges(0), ~[]);
+}
@@ -3,7 +3,7 @@
 use std::vec;
 use std::ascii::Ascii;
 use std::rt::io::Writer;
-use rfc2616::is_token;
+use rfc2616::{is_token, is_ctl, SP, HT};
 
 /// Normalise an HTTP header name.
 ///
@@ -141,6 +141,23 @@ pub fn push_quality(mut s: ~str, quality: Option<f64>) -> ~str {
     s
 }
 
+/// Parse a qvalue (RFC 2616, section 3.9), a number from 0 to 1 with up to three decimal places.
+/// Returns ``None`` if it is not valid.
+pub fn parse_quality(s: &str) -> Option<f64> {
+    let valid = match s.find('.') {
+        Some(1) => s.len() <= 5 && s.slice_from(2).iter().all(|c| c.is_digit()),
+        Some(_) => false,
+        None => s.len() == 1,
+    };
+    if !valid || !(s.starts_with("0") || s.starts_with("1")) {
+        return None;
+    }
+    match from_str::<f64>(s) {
+        Some(q) if q <= 1.0 => Some(q),
+        _ => None,
+    }
+}
+
 /// Push a ( token | quoted-string ) onto a string and return it again
 pub fn push_maybe_quoted_string(mut s: ~str, t: &str) -> ~str {
     if is_token(t) {
@@ -203,6 +220,61 @@ pub fn unquote_string(s: &str) -> Option<~str> {
     }
 }
 
+/// Split a string at each of the separators which isn't within a quoted-string.
+pub fn split_outside_quotes<'a>(s: &'a str, separator: char) -> ~[&'a str] {
+    let mut parts = ~[];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_offset_iter() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(s.slice(start, i));
+            start = i + 1;
+        }
+    }
+    parts.push(s.slice_from(start));
+    parts
+}
+
+/// Split the value of a list header (`#rule`, RFC 7230, section 7) into its items: at each comma
+/// which isn't within a quoted-string, trimming them, and leaving out any which are empty.
+///
+/// # Examples
+///
+/// ~~~ .{rust}
+/// assert_eq!(split_list("a, \"b, c\" ,, d"), ~[~"a", ~"\"b, c\"", ~"d"])
+/// ~~~
+pub fn split_list(value: &str) -> ~[~str] {
+    split_outside_quotes(value, ',').iter().map(|item| item.trim())
+                                    .filter(|item| !item.is_empty())
+                                    .map(|item| item.to_owned()).collect()
+}
+
+/// Whether a header value can be written as it is: it has no control characters but HT, so that
+/// none of it can end the header early and begin another, or the body (response splitting).
+/// Characters beyond ASCII are allowed, as obs-text is (RFC 7230, section 3.2).
+pub fn is_field_value(value: &str) -> bool {
+    value.byte_iter().all(|b| b == HT || !is_ctl(b))
+}
+
+/// Write the name or value of a header, as it is if it is one which can be (see `is_field_value`),
+/// or else with each control character but HT written as SP.
+pub fn write_field_bytes<W: Writer>(writer: &mut W, bytes: &[u8]) {
+    if bytes.iter().all(|&b| b == HT || !is_ctl(b)) {
+        writer.write(bytes);
+    } else {
+        let sanitised: ~[u8] = bytes.iter().map(|&b| if b != HT && is_ctl(b) { SP } else { b })
+                                    .collect();
+        writer.write(sanitised);
+    }
+}
+
 /// Parse a ( token | quoted-string ). Returns ``None`` if it is not valid.
 pub fn maybe_unquote_string(s: &str) -> Option<~str> {
     if is_token(s) {
@@ -230,10 +302,13 @@ pub fn push_parameters(mut s: ~str, parameters: &[(~str, ~str)]) -> ~str {
 
 #[cfg(test)]
 mod test {
+    use std::rt::io::Decorator;
+    use std::rt::io::mem::MemWriter;
     use super::{normalise_header_name, comma_split, comma_split_iter, comma_join,
                 push_quality, push_parameter, push_parameters,
                 push_maybe_quoted_string, push_quoted_string, maybe_quoted_string, quoted_string,
-                unquote_string, maybe_unquote_string};
+                unquote_string, split_outside_quotes, split_list, maybe_unquote_string,
+                parse_quality, is_field_value, write_field_bytes};
 
     #[test]
     #[should_fail]
@@ -263,6 +338,29 @@ mod test {
         assert_eq!(comma_split(" foo;q=0.8 , bar/* "), ~[~"foo;q=0.8 ", ~"bar/* "]);
     }
 
+    #[test]
+    fn test_split_list() {
+        assert_eq!(split_list(""), ~[]);
+        assert_eq!(split_list(" , ,"), ~[]);
+        assert_eq!(split_list("foo"), ~[~"foo"]);
+        assert_eq!(split_list(" foo;q=0.8 , bar/* "), ~[~"foo;q=0.8", ~"bar/*"]);
+        assert_eq!(split_list("a, \"b, \\\"c\" ,, d"), ~[~"a", ~"\"b, \\\"c\"", ~"d"]);
+    }
+
+    #[test]
+    fn test_field_value() {
+        assert!(is_field_value(""));
+        assert!(is_field_value("a\tb, \"c\" caf\xe9"));
+        assert!(!is_field_value("a\r\nSet-Cookie: b=1"));
+        assert!(!is_field_value("a\x00"));
+        assert!(!is_field_value("a\x7f"));
+
+        let mut writer = MemWriter::new();
+        write_field_bytes(&mut writer, bytes!("a\tb"));
+        write_field_bytes(&mut writer, bytes!("\r\nX: 1\x00"));
+        assert_eq!(writer.inner_ref().as_slice(), bytes!("a\tb  X: 1 "));
+    }
+
     #[test]
     fn test_comma_split_iter() {
         // These are the same cases as in test_comma_split above.
@@ -336,6 +434,14 @@ mod test {
         assert_eq!(unquote_string("\"bar/baz \\\"yay\\\\\"\""), None);
     }
 
+    #[test]
+    fn test_split_outside_quotes() {
+        assert_eq!(split_outside_quotes("", ','), ~[""]);
+        assert_eq!(split_outside_quotes("a, b", ','), ~["a", " b"]);
+        assert_eq!(split_outside_quotes("a=\"b,c\",d", ','), ~["a=\"b,c\"", "d"]);
+        assert_eq!(split_outside_quotes("a=\"b\\\",c\";d", ';'), ~["a=\"b\\\",c\"", "d"]);
+    }
+
     #[test]
     fn test_maybe_unquote_string() {
         assert_eq!(maybe_unquote_string("bar"), Some(~"bar"));
@@ -348,6 +454,20 @@ mod test {
         assert_eq!(maybe_unquote_string("\"bar/baz \\\"yay\\\\\"\""), None);
     }
 
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!(parse_quality("1"), Some(1.0));
+        assert_eq!(parse_quality("1.000"), Some(1.0));
+        assert_eq!(parse_quality("0.5"), Some(0.5));
+        assert_eq!(parse_quality("0"), Some(0.0));
+        assert_eq!(parse_quality("0.0001"), None);
+        assert_eq!(parse_quality("1.5"), None);
+        assert_eq!(parse_quality("2"), None);
+        assert_eq!(parse_quality(".5"), None);
+        assert_eq!(parse_quality("-0"), None);
+        assert_eq!(parse_quality(""), None);
+    }
+
     #[test]
     fn test_push_parameter() {
         assert_eq!(push_parameter(~"foo", "bar", "baz"), ~"foobar=baz");
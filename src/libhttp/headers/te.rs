@@ -0,0 +1,132 @@
+//! The TE request header, defined in RFC 7230, section 4.3: which transfer-codings, besides
+//! `chunked`, the client will accept in the response, and whether it will accept a trailer.
+//!
+//! TE        = #t-codings
+//! t-codings = "trailers" / ( transfer-coding [ t-ranking ] )
+//! t-ranking = OWS ";" OWS "q=" rank
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use headers::serialization_utils::{WriterUtil, push_quality, parse_quality};
+
+/// A transfer-coding which the client accepts, and how much it wants it; or `trailers`.
+#[deriving(Clone, Eq)]
+pub struct TCoding {
+    /// The transfer-coding, in lower case, or `trailers`, which says that the client will accept
+    /// a trailer after a chunked body.
+    coding: ~str,
+
+    /// The quality, from 0 (not acceptable) to 1; `None` means the default, 1.
+    quality: Option<f64>,
+}
+
+impl TCoding {
+    /// The quality as a number, defaulting to 1.
+    pub fn q(&self) -> f64 {
+        match self.quality {
+            Some(q) => q,
+            None => 1.0,
+        }
+    }
+}
+
+impl super::CommaListHeaderConvertible for TCoding {}
+
+impl super::HeaderConvertible for TCoding {
+    fn from_stream<T: Reader>(reader: &mut super::HeaderValueByteIterator<T>)
+            -> Option<TCoding> {
+        let coding = match reader.read_token() {
+            Some(token) => token.to_ascii_lower(),
+            None => return None,
+        };
+        let parameters = match reader.read_parameters() {
+            Some(parameters) => parameters,
+            None => return None,
+        };
+        let mut quality = None;
+        for &(ref key, ref value) in parameters.iter() {
+            if key.eq_ignore_ascii_case("q") {
+                match parse_quality(*value) {
+                    Some(q) => quality = Some(q),
+                    None => return None,
+                }
+            }
+        }
+        Some(TCoding { coding: coding, quality: quality })
+    }
+
+    fn to_stream<T: Writer>(&self, writer: &mut T) {
+        writer.write_token(self.coding);
+        writer.write_quality(self.quality);
+    }
+
+    fn http_value(&self) -> ~str {
+        push_quality(self.coding.clone(), self.quality)
+    }
+}
+
+/// Whether the client will accept a trailer after a chunked body: whether the TE list has
+/// `trailers` in it.
+pub fn accepts_trailers(te: &[TCoding]) -> bool {
+    te.iter().any(|t| t.coding.as_slice() == "trailers")
+}
+
+/// Choose which of the transfer-codings the server can apply (such as `gzip`) is the most
+/// acceptable to the client, as expressed in its TE header. Where codings are equally acceptable,
+/// the earlier in `supported` is preferred.
+///
+/// Unlike content-codings in Accept-Encoding, a transfer-coding is only acceptable if it is
+/// listed, with a quality above 0; `chunked`, which is always acceptable, and `trailers` are never
+/// chosen. If none is acceptable, `None` is returned, and the body is to be sent as it is.
+pub fn negotiate_transfer_coding<'a>(te: &[TCoding], supported: &[&'a str]) -> Option<&'a str> {
+    let mut best = None;
+    let mut best_q = 0.0;
+    for &coding in supported.iter() {
+        if coding.eq_ignore_ascii_case("chunked") || coding.eq_ignore_ascii_case("trailers") {
+            continue;
+        }
+        let q = match te.iter().find(|t| t.coding.eq_ignore_ascii_case(coding)) {
+            Some(t) => t.q(),
+            None => 0.0,
+        };
+        if q > best_q {
+            best = Some(coding);
+            best_q = q;
+        }
+    }
+    best
+}
+
+#[test]
+fn test_t_coding() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("trailers", ~[TCoding { coding: ~"trailers", quality: None }]);
+    assert_conversion_correct("gzip;q=0.500, trailers",
+                              ~[TCoding { coding: ~"gzip", quality: Some(0.5) },
+                                TCoding { coding: ~"trailers", quality: None }]);
+    assert_interpretation_correct("Deflate;Q=0.8",
+                                  ~[TCoding { coding: ~"deflate", quality: Some(0.8) }]);
+    assert_invalid::<~[TCoding]>("gzip;q=2");
+    assert_invalid::<~[TCoding]>(";q=1");
+}
+
+#[test]
+fn test_negotiate_transfer_coding() {
+    use headers::test_utils::from_stream_with_str;
+    fn parse(te: &str) -> ~[TCoding] {
+        from_stream_with_str(te).unwrap()
+    }
+    fn negotiate(te: &str, supported: &[&'static str]) -> Option<&'static str> {
+        negotiate_transfer_coding(parse(te), supported)
+    }
+    assert_eq!(negotiate("gzip, deflate", ["gzip", "deflate"]), Some("gzip"));
+    assert_eq!(negotiate("gzip;q=0.5, deflate", ["gzip", "deflate"]), Some("deflate"));
+    assert_eq!(negotiate("deflate", ["gzip"]), None);
+    assert_eq!(negotiate("gzip;q=0", ["gzip"]), None);
+    assert_eq!(negotiate("trailers, chunked", ["trailers", "chunked", "gzip"]), None);
+    assert_eq!(negotiate_transfer_coding([], ["gzip"]), None);
+
+    assert!(accepts_trailers(parse("gzip, trailers")));
+    assert!(!accepts_trailers(parse("gzip")));
+}
@@ -0,0 +1,106 @@
+//! The Vary response header (RFC 7231, section 7.1.4): which headers of the request, besides its
+//! method and URL, the server chose the response by, so that a cache knows which requests the
+//! response will do for.
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::Reader;
+use headers::{HeaderConvertible, HeaderValueByteIterator};
+use rfc2616::is_token;
+
+/// The value of a Vary header.
+#[deriving(Clone, Eq)]
+pub enum Vary {
+    /// `*`: by something besides the request headers, so that no other request is sure to get
+    /// the same response.
+    AnyVary,
+    /// By the headers named, as they were written.
+    VaryHeaders(~[~str]),
+}
+
+impl Vary {
+    /// Whether the header named is one the response varies by; `AnyVary` is taken to name them
+    /// all. Header names are compared ignoring case.
+    pub fn contains(&self, name: &str) -> bool {
+        match *self {
+            AnyVary => true,
+            VaryHeaders(ref names) => names.iter().any(|n| n.eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// Add the header named to those the response varies by, if it isn't among them already.
+    pub fn push(&mut self, name: &str) {
+        if !self.contains(name) {
+            match *self {
+                AnyVary => (),
+                VaryHeaders(ref mut names) => names.push(name.to_owned()),
+            }
+        }
+    }
+}
+
+impl HeaderConvertible for Vary {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<Vary> {
+        let s = reader.collect_to_str();
+        let mut names = ~[];
+        for name in s.split_iter(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            if name == "*" {
+                return Some(AnyVary);
+            } else if !is_token(name) {
+                return None;
+            }
+            names.push(name.to_owned());
+        }
+        if names.is_empty() {
+            None
+        } else {
+            Some(VaryHeaders(names))
+        }
+    }
+
+    fn http_value(&self) -> ~str {
+        match *self {
+            AnyVary => ~"*",
+            VaryHeaders(ref names) => names.connect(", "),
+        }
+    }
+
+    fn merge(&mut self, other: &Vary) -> bool {
+        match *other {
+            VaryHeaders(ref others) => match *self {
+                VaryHeaders(ref mut names) => {
+                    names.push_all(others.as_slice());
+                    true
+                },
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn test_vary() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("*", AnyVary);
+    assert_conversion_correct("Accept-Encoding", VaryHeaders(~[~"Accept-Encoding"]));
+    assert_conversion_correct("Accept-Encoding, User-Agent",
+                              VaryHeaders(~[~"Accept-Encoding", ~"User-Agent"]));
+    assert_interpretation_correct("accept ,, cookie ", VaryHeaders(~[~"accept", ~"cookie"]));
+    assert_interpretation_correct("Accept, *", AnyVary);
+    assert_invalid::<Vary>("");
+    assert_invalid::<Vary>("Accept Encoding");
+}
+
+#[test]
+fn test_contains_and_push() {
+    let mut vary = VaryHeaders(~[~"Accept"]);
+    assert!(vary.contains("accept") && !vary.contains("Cookie"));
+    vary.push("ACCEPT");
+    vary.push("Cookie");
+    assert_eq!(vary, VaryHeaders(~[~"Accept", ~"Cookie"]));
+    let mut vary = AnyVary;
+    vary.push("Cookie");
+    assert_eq!(vary, AnyVary);
+    assert!(vary.contains("Cookie"));
+}
@@ -0,0 +1,1022 @@
+/*!
+
+Serving a connection in HTTP/2, with the same `Server` as HTTP/1.1.
+
+Each stream the client opens is a request, which is made a `Request` once its headers are in, as
+though they had come in HTTP/1.1, with `:authority` as the Host header, and handed to the handler
+once its body is in too. The response the handler writes is sent back on the stream as HEADERS,
+with `:status` and the response's headers in lower case, and DATA frames of the body, as the
+client's flow-control windows allow; the headers which only concern the connection, such as
+Connection and Transfer-Encoding, are left out.
+
+TODO: requests are handled one at a time, in the order they are completed, and each response is
+written to memory and only sent once the handler has returned, so a handler which streams its
+response (polling, or server-sent events) holds up the rest of the connection, and the body of a
+request can't be streamed either. Streams ought to be handled in tasks of their own, each with a
+`ResponseWriter` which sends DATA frames as it is written to, the frames of all of them going out
+on the connection in turn. Nor is anything pushed, interim responses (such as 100 Continue) sent,
+or trailers sent after a body.
+
+A connection begun in HTTP/1.1 may be upgraded to HTTP/2 without TLS (`h2c`) by a request with
+`Upgrade: h2c` and the client's settings in HTTP2-Settings (RFC 7540, section 3.2), which is then
+answered as the first stream; see `upgrade_settings` and `serve_upgraded`.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::cmp;
+use std::rt::io::{Writer, Decorator};
+use std::rt::io::net::ip::SocketAddr;
+use extra::base64::FromBase64;
+use extra::time::{Tm, precise_time_ns, now_utc};
+use extra::url::Url;
+use buffer::BufferedStream;
+use headers;
+use headers::{HeaderEnum, EndOfHeaders, MalformedHeaderValue};
+use headers::connection::Token;
+use headers::host::Host;
+use memstream::{MemReaderFakeStream, MemWriterFakeStream};
+use method::{Method, Connect, Options};
+use network::{BufNetworkStream, MemoryStream};
+use server::{Server, Request, RequestBuffer, ResponseWriter};
+use server::access_log::{AccessLogger, AccessLogEntry};
+use server::error_handler::SharedErrorHandler;
+use server::observer::SharedObserver;
+use server::request::{Star, Authority};
+use status;
+use status::Status;
+use super::{PREFACE, CLEARTEXT_PROTOCOL_ID, NO_ERROR, PROTOCOL_ERROR, INTERNAL_ERROR,
+            FLOW_CONTROL_ERROR, STREAM_CLOSED, REFUSED_STREAM, COMPRESSION_ERROR, ENHANCE_YOUR_CALM,
+            SETTINGS_HEADER_TABLE_SIZE, SETTINGS_ENABLE_PUSH, SETTINGS_MAX_CONCURRENT_STREAMS,
+            SETTINGS_INITIAL_WINDOW_SIZE, SETTINGS_MAX_FRAME_SIZE, SETTINGS_MAX_HEADER_LIST_SIZE,
+            DEFAULT_WINDOW_SIZE, MAX_WINDOW_SIZE, DEFAULT_MAX_FRAME_SIZE, MAX_FRAME_SIZE};
+use super::frame::{Frame, read_frame, read_settings, write_frame, EndOfStream, DataFrame,
+                   HeadersFrame, PriorityFrame, RstStreamFrame, SettingsFrame, PushPromiseFrame,
+                   PingFrame, GoAwayFrame, WindowUpdateFrame, ContinuationFrame, FLAG_END_STREAM,
+                   FLAG_END_HEADERS, FLAG_ACK};
+use super::hpack::{Decoder, Encoder, HeaderList, DEFAULT_TABLE_SIZE};
+
+/// The most streams a client may have open at once.
+static MAX_CONCURRENT_STREAMS: uint = 100;
+
+/// The longest the header block of a request may be, compressed or not.
+static MAX_HEADER_LIST_SIZE: uint = 0x10000;
+
+/// What of the server's configuration applies to the requests of a connection; see `Config`.
+#[deriving(Clone)]
+pub struct ConnectionConfig {
+    max_body_size: Option<uint>,
+    trusted_proxy_hops: uint,
+    access_log: Option<AccessLogger>,
+    observer: Option<SharedObserver>,
+    error_handler: Option<SharedErrorHandler>,
+}
+
+/// A stream the client has opened, which hasn't been answered yet.
+struct Stream {
+    id: u32,
+    /// The request, until it is handed to the handler.
+    request: Option<~Request>,
+    /// The status to refuse the request with, rather than handing it to the handler.
+    error: Option<Status>,
+    /// The most bytes its body may be.
+    max_body_size: Option<uint>,
+    /// Whether all of the request is in, or as much of it as will be read.
+    ended: bool,
+    /// Whether the rest of the request isn't to be read, the client having yet to end the stream.
+    cut_short: bool,
+    /// How many bytes of data may be sent on the stream, until the client lets more be; this may
+    /// be made negative by a change to SETTINGS_INITIAL_WINDOW_SIZE.
+    send_window: i64,
+    /// When its headers came in, for the observer and the access log.
+    started: u64,
+    received: Tm,
+}
+
+/// Why a connection is being given up.
+enum Ending {
+    /// The client closed it.
+    Closed,
+    /// Something's wrong with it; a connection error, to be sent in GOAWAY with this code.
+    ConnectionError(u32),
+}
+
+struct Connection<'self> {
+    stream: &'self mut BufNetworkStream,
+    remote_addr: Option<SocketAddr>,
+    config: &'self ConnectionConfig,
+    encoder: Encoder,
+    decoder: Decoder,
+    /// The streams open, in the order they were opened.
+    streams: ~[Stream],
+    /// The IDs of the streams whose requests are in, in the order they came, to be answered.
+    ready: ~[u32],
+    /// The highest ID of a stream the client has opened; those below it which aren't open are
+    /// closed.
+    last_stream_id: u32,
+    /// A header block which CONTINUATION frames are carrying on: the stream's ID, the flags of its
+    /// HEADERS frame, and the block so far. Until it is done nothing else may come.
+    continuing: Option<(u32, u8, ~[u8])>,
+    /// How many bytes of data may be sent on the connection, of all streams.
+    send_window: i64,
+    /// The client's settings: the window of each stream at first, and the longest a frame sent to
+    /// it may be.
+    initial_window_size: i64,
+    max_frame_size: uint,
+    /// Whether the client has sent GOAWAY, so that no more streams are to be opened.
+    going_away: bool,
+}
+
+/**
+ * Serve a connection on which the client speaks HTTP/2, such as one on which it chose `h2` in the
+ * TLS handshake, until the client closes it, or ends it with GOAWAY once its last stream is
+ * answered; or until it does something it mustn't, when it is ended with GOAWAY and the error.
+ *
+ * If a handler fails, so does the task serving the connection, and the connection is dropped.
+ */
+pub fn serve<T: Server>(server: &T, stream: &mut BufNetworkStream, config: &ConnectionConfig) {
+    let mut connection = Connection::new(stream, config);
+    connection.serve(server, None);
+}
+
+/**
+ * Answer a request of HTTP/1.1 which asks to upgrade the connection to HTTP/2, with the settings
+ * given (see `upgrade_settings`), with 101 Switching Protocols, and serve the connection in HTTP/2
+ * from then on, as `serve` does; the request is handed to the handler as that of the first stream,
+ * and its response sent on it. Its body must have been read already.
+ */
+pub fn serve_upgraded<T: Server>(server: &T, stream: &mut BufNetworkStream,
+                                 config: &ConnectionConfig, request: ~Request,
+                                 settings: ~[(u16, u32)]) {
+    stream.write(bytes!("HTTP/1.1 101 Switching Protocols\r\n\
+                         Connection: Upgrade\r\nUpgrade: h2c\r\n\r\n"));
+    // The client waits for it before it sends the preface.
+    stream.flush();
+    let mut request = request;
+    request.version = (2, 0);
+    request.headers.connection = None;
+    request.headers.upgrade = None;
+    request.headers.extensions.remove(&~"Http2-Settings");
+    let mut connection = Connection::new(stream, config);
+    // The request's stream is half closed already, the request being all in (RFC 7540, section
+    // 3.2).
+    connection.streams.push(Stream {
+        id: 1,
+        request: Some(request),
+        error: None,
+        max_body_size: None,
+        ended: true,
+        cut_short: false,
+        send_window: DEFAULT_WINDOW_SIZE as i64,
+        started: precise_time_ns(),
+        received: now_utc(),
+    });
+    connection.ready.push(1);
+    connection.last_stream_id = 1;
+    connection.serve(server, Some(settings));
+}
+
+/**
+ * The settings of the client, from HTTP2-Settings, if a request asks to upgrade its connection to
+ * HTTP/2 without TLS: if it is of HTTP/1.1, with `h2c` among the protocols of its Upgrade header
+ * and `Upgrade` and `HTTP2-Settings` among the options of its Connection header, and HTTP2-Settings
+ * is the payload of a SETTINGS frame in the URL-safe form of base64. `None` is returned otherwise,
+ * when it is to be answered in HTTP/1.1 as though Upgrade hadn't been sent.
+ *
+ * This is only for connections without TLS; over TLS HTTP/2 is chosen by ALPN instead.
+ */
+pub fn upgrade_settings(request: &Request) -> Option<~[(u16, u32)]> {
+    let upgrade = match request.headers.upgrade {
+        Some(ref upgrade) => upgrade.split_iter(',').any(|p| {
+            p.trim().eq_ignore_ascii_case(CLEARTEXT_PROTOCOL_ID)
+        }),
+        None => false,
+    };
+    let connection = match request.headers.connection {
+        Some(ref tokens) => tokens.iter().any(|t| *t == Token(~"Upgrade"))
+                            && tokens.iter().any(|t| *t == Token(~"Http2-Settings")),
+        None => false,
+    };
+    if request.version != (1, 1) || !upgrade || !connection {
+        return None;
+    }
+    match request.headers.extensions.find(&~"Http2-Settings") {
+        Some(value) => {
+            let value = value.trim().replace("-", "+").replace("_", "/");
+            match value.as_slice().from_base64() {
+                Ok(payload) => read_settings(payload),
+                Err(_) => None,
+            }
+        },
+        None => None,
+    }
+}
+
+impl<'self> Connection<'self> {
+    fn new(stream: &'self mut BufNetworkStream, config: &'self ConnectionConfig)
+            -> Connection<'self> {
+        let remote_addr = stream.wrapped.peer_name();
+        Connection {
+            stream: stream,
+            remote_addr: remote_addr,
+            config: config,
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            streams: ~[],
+            ready: ~[],
+            last_stream_id: 0,
+            continuing: None,
+            send_window: DEFAULT_WINDOW_SIZE as i64,
+            initial_window_size: DEFAULT_WINDOW_SIZE as i64,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            going_away: false,
+        }
+    }
+
+    fn serve<T: Server>(&mut self, server: &T, upgrade_settings: Option<~[(u16, u32)]>) {
+        match self.run(server, upgrade_settings) {
+            Ok(()) => self.go_away(NO_ERROR),
+            Err(ConnectionError(code)) => {
+                debug!("ending HTTP/2 connection with error {}", code);
+                self.go_away(code);
+            },
+            Err(Closed) => debug!("HTTP/2 connection closed by client"),
+        }
+    }
+
+    fn run<T: Server>(&mut self, server: &T, upgrade_settings: Option<~[(u16, u32)]>)
+            -> Result<(), Ending> {
+        for &b in PREFACE.iter() {
+            match self.stream.read_byte() {
+                Some(c) if c == b => (),
+                Some(_) => return Err(ConnectionError(PROTOCOL_ERROR)),
+                None => return Err(Closed),
+            }
+        }
+        self.write(&SettingsFrame(0, ~[
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS as u32),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST_SIZE as u32),
+        ]));
+        // Those sent in upgrading the connection are as though in a SETTINGS frame before any
+        // other, but aren't acknowledged (RFC 7540, section 3.2.1).
+        match upgrade_settings {
+            Some(settings) => match self.apply_settings(settings) {
+                Ok(()) => (),
+                Err(e) => return Err(e),
+            },
+            None => (),
+        }
+        // The client's settings come first of all.
+        match self.read() {
+            Ok(SettingsFrame(flags, settings)) => {
+                if flags & FLAG_ACK != 0 {
+                    return Err(ConnectionError(PROTOCOL_ERROR));
+                }
+                match self.receive_settings(settings) {
+                    Ok(()) => (),
+                    Err(e) => return Err(e),
+                }
+            },
+            Ok(_) => return Err(ConnectionError(PROTOCOL_ERROR)),
+            Err(e) => return Err(e),
+        }
+        loop {
+            while !self.ready.is_empty() {
+                let id = self.ready.shift();
+                match self.respond(server, id) {
+                    Ok(()) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            if self.going_away && self.streams.is_empty() {
+                return Ok(());
+            }
+            let result = match self.read() {
+                Ok(frame) => self.handle_frame(server, frame),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Read a frame, once everything written so far has been sent.
+    fn read(&mut self) -> Result<Frame, Ending> {
+        self.stream.flush();
+        match read_frame(&mut *self.stream, DEFAULT_MAX_FRAME_SIZE) {
+            Ok(frame) => Ok(frame),
+            Err(EndOfStream) => Err(Closed),
+            Err(e) => Err(ConnectionError(e.code())),
+        }
+    }
+
+    fn write(&mut self, frame: &Frame) {
+        write_frame(&mut *self.stream, frame);
+    }
+
+    fn go_away(&mut self, code: u32) {
+        let last_stream_id = self.last_stream_id;
+        self.write(&GoAwayFrame(last_stream_id, code, ~[]));
+        self.stream.flush();
+    }
+
+    fn find_stream(&self, id: u32) -> Option<uint> {
+        self.streams.iter().position(|s| s.id == id)
+    }
+
+    fn close_stream(&mut self, id: u32) {
+        match self.find_stream(id) {
+            Some(i) => {
+                self.streams.remove(i);
+            },
+            None => (),
+        }
+    }
+
+    /// End a stream with a stream error, or anyway, with RST_STREAM.
+    fn reset(&mut self, id: u32, code: u32) {
+        self.write(&RstStreamFrame(id, code));
+        self.close_stream(id);
+    }
+
+    fn handle_frame<T: Server>(&mut self, server: &T, frame: Frame) -> Result<(), Ending> {
+        if self.continuing.is_some() {
+            return match frame {
+                ContinuationFrame(id, flags, fragment) =>
+                    self.receive_continuation(server, id, flags, fragment),
+                _ => Err(ConnectionError(PROTOCOL_ERROR)),
+            };
+        }
+        match frame {
+            DataFrame(id, flags, data, padding) => self.receive_data(id, flags, data, padding),
+            HeadersFrame(id, flags, _, fragment) => {
+                if flags & FLAG_END_HEADERS == 0 {
+                    self.continuing = Some((id, flags, fragment));
+                    Ok(())
+                } else {
+                    self.receive_headers(server, id, flags, fragment)
+                }
+            },
+            PriorityFrame(*) => Ok(()),
+            RstStreamFrame(id, _) => {
+                if id > self.last_stream_id {
+                    return Err(ConnectionError(PROTOCOL_ERROR));
+                }
+                self.close_stream(id);
+                Ok(())
+            },
+            SettingsFrame(flags, _) if flags & FLAG_ACK != 0 => Ok(()),
+            SettingsFrame(_, settings) => self.receive_settings(settings),
+            // Only a server may push.
+            PushPromiseFrame(*) => Err(ConnectionError(PROTOCOL_ERROR)),
+            PingFrame(flags, data) => {
+                if flags & FLAG_ACK == 0 {
+                    self.write(&PingFrame(FLAG_ACK, data));
+                }
+                Ok(())
+            },
+            GoAwayFrame(*) => {
+                self.going_away = true;
+                Ok(())
+            },
+            WindowUpdateFrame(id, increment) => self.receive_window_update(id, increment),
+            ContinuationFrame(*) => Err(ConnectionError(PROTOCOL_ERROR)),
+        }
+    }
+
+    fn apply_settings(&mut self, settings: ~[(u16, u32)]) -> Result<(), Ending> {
+        for &(id, value) in settings.iter() {
+            if id == SETTINGS_HEADER_TABLE_SIZE {
+                self.encoder.set_max_table_size(cmp::min(value as uint, DEFAULT_TABLE_SIZE));
+            } else if id == SETTINGS_ENABLE_PUSH && value > 1 {
+                return Err(ConnectionError(PROTOCOL_ERROR));
+            } else if id == SETTINGS_INITIAL_WINDOW_SIZE {
+                if value > MAX_WINDOW_SIZE {
+                    return Err(ConnectionError(FLOW_CONTROL_ERROR));
+                }
+                // The windows of the streams open change by as much (RFC 7540, section 6.9.2).
+                let change = value as i64 - self.initial_window_size;
+                for stream in self.streams.mut_iter() {
+                    stream.send_window += change;
+                    if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                        return Err(ConnectionError(FLOW_CONTROL_ERROR));
+                    }
+                }
+                self.initial_window_size = value as i64;
+            } else if id == SETTINGS_MAX_FRAME_SIZE {
+                if (value as uint) < DEFAULT_MAX_FRAME_SIZE || value as uint > MAX_FRAME_SIZE {
+                    return Err(ConnectionError(PROTOCOL_ERROR));
+                }
+                self.max_frame_size = value as uint;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the settings of a SETTINGS frame, and acknowledge them.
+    fn receive_settings(&mut self, settings: ~[(u16, u32)]) -> Result<(), Ending> {
+        match self.apply_settings(settings) {
+            Ok(()) => {
+                self.write(&SettingsFrame(FLAG_ACK, ~[]));
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn receive_window_update(&mut self, id: u32, increment: u32) -> Result<(), Ending> {
+        if id == 0 {
+            self.send_window += increment as i64;
+            if self.send_window > MAX_WINDOW_SIZE as i64 {
+                return Err(ConnectionError(FLOW_CONTROL_ERROR));
+            }
+            return Ok(());
+        }
+        match self.find_stream(id) {
+            Some(i) => {
+                self.streams[i].send_window += increment as i64;
+                if self.streams[i].send_window > MAX_WINDOW_SIZE as i64 {
+                    self.reset(id, FLOW_CONTROL_ERROR);
+                }
+                Ok(())
+            },
+            // A stream not yet opened can't have a window
+            None if id > self.last_stream_id => Err(ConnectionError(PROTOCOL_ERROR)),
+            // A stream since closed may be sent one a little while yet
+            None => Ok(()),
+        }
+    }
+
+    fn receive_continuation<T: Server>(&mut self, server: &T, id: u32, flags: u8,
+                                       fragment: ~[u8]) -> Result<(), Ending> {
+        let (stream_id, headers_flags, block) = self.continuing.take().unwrap();
+        if id != stream_id {
+            return Err(ConnectionError(PROTOCOL_ERROR));
+        }
+        let mut block = block;
+        block.push_all(fragment);
+        if block.len() > MAX_HEADER_LIST_SIZE {
+            return Err(ConnectionError(ENHANCE_YOUR_CALM));
+        }
+        if flags & FLAG_END_HEADERS == 0 {
+            self.continuing = Some((stream_id, headers_flags, block));
+            Ok(())
+        } else {
+            self.receive_headers(server, stream_id, headers_flags, block)
+        }
+    }
+
+    /// Take a whole header block: the opening of a stream, or the trailer of one.
+    fn receive_headers<T: Server>(&mut self, server: &T, id: u32, flags: u8, block: ~[u8])
+            -> Result<(), Ending> {
+        // The block must be decoded whatever becomes of it, for the tables to stay as the
+        // client's are.
+        let headers = match self.decoder.decode(block) {
+            Some(headers) => headers,
+            None => return Err(ConnectionError(COMPRESSION_ERROR)),
+        };
+        if id % 2 == 0 {
+            return Err(ConnectionError(PROTOCOL_ERROR));
+        }
+        match self.find_stream(id) {
+            Some(i) => {
+                self.receive_trailer(i, flags, headers);
+                return Ok(());
+            },
+            None => (),
+        }
+        if id <= self.last_stream_id {
+            self.reset(id, STREAM_CLOSED);
+            return Ok(());
+        }
+        self.last_stream_id = id;
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.reset(id, REFUSED_STREAM);
+            return Ok(());
+        }
+        let mut request = match request_from_headers(headers, self.remote_addr) {
+            Some(request) => request,
+            None => {
+                // A malformed request (RFC 7540, section 8.1.2.6)
+                self.reset(id, PROTOCOL_ERROR);
+                return Ok(());
+            },
+        };
+        request.trusted_proxy_hops = self.config.trusted_proxy_hops;
+        let max_body_size = server.max_body_size(request, self.config.max_body_size);
+        let too_long = match (request.headers.content_length, max_body_size) {
+            (Some(len), Some(max)) => len > max,
+            _ => false,
+        };
+        self.streams.push(Stream {
+            id: id,
+            request: Some(request),
+            error: None,
+            max_body_size: max_body_size,
+            ended: false,
+            cut_short: false,
+            send_window: self.initial_window_size,
+            started: precise_time_ns(),
+            received: now_utc(),
+        });
+        let i = self.streams.len() - 1;
+        if too_long {
+            self.refuse(i, status::RequestEntityTooLarge, flags & FLAG_END_STREAM == 0);
+        } else if flags & FLAG_END_STREAM != 0 {
+            self.end_stream(i);
+        }
+        Ok(())
+    }
+
+    fn receive_trailer(&mut self, i: uint, flags: u8, headers: HeaderList) {
+        let id = self.streams[i].id;
+        if self.streams[i].ended {
+            if !self.streams[i].cut_short {
+                self.reset(id, STREAM_CLOSED);
+            }
+            return;
+        }
+        // A trailer ends the stream, and has no pseudo-headers.
+        if flags & FLAG_END_STREAM == 0 || headers.iter().any(|&(ref n, _)| n.starts_with(":")) {
+            self.reset(id, PROTOCOL_ERROR);
+            return;
+        }
+        self.streams[i].request.get_mut_ref().trailer.push_all_move(headers);
+        self.end_stream(i);
+    }
+
+    fn receive_data(&mut self, id: u32, flags: u8, data: ~[u8], padding: Option<u8>)
+            -> Result<(), Ending> {
+        // The padding counts, and the connection's window is given back at once whatever becomes
+        // of the data, as it is read straight away.
+        let len = data.len() + match padding {
+            Some(padding) => padding as uint + 1,
+            None => 0,
+        };
+        if len > 0 {
+            self.write(&WindowUpdateFrame(0, len as u32));
+        }
+        let i = match self.find_stream(id) {
+            Some(i) if !self.streams[i].ended => i,
+            Some(i) => {
+                if !self.streams[i].cut_short {
+                    self.reset(id, STREAM_CLOSED);
+                }
+                return Ok(());
+            },
+            None if id > self.last_stream_id => return Err(ConnectionError(PROTOCOL_ERROR)),
+            None => {
+                self.write(&RstStreamFrame(id, STREAM_CLOSED));
+                return Ok(());
+            },
+        };
+        if len > 0 && flags & FLAG_END_STREAM == 0 {
+            self.write(&WindowUpdateFrame(id, len as u32));
+        }
+        let too_long = {
+            let stream = &mut self.streams[i];
+            let max_body_size = stream.max_body_size;
+            let request = stream.request.get_mut_ref();
+            request.body.push_all_move(data);
+            match max_body_size {
+                Some(max) => request.body.len() > max,
+                None => false,
+            }
+        };
+        if too_long {
+            self.refuse(i, status::RequestEntityTooLarge, flags & FLAG_END_STREAM == 0);
+        } else if flags & FLAG_END_STREAM != 0 {
+            self.end_stream(i);
+        }
+        Ok(())
+    }
+
+    /// Mark a stream's request as all in, to be answered.
+    fn end_stream(&mut self, i: uint) {
+        let id = self.streams[i].id;
+        let malformed = {
+            let request = self.streams[i].request.get_ref();
+            match request.headers.content_length {
+                Some(len) => len != request.body.len(),
+                None => false,
+            }
+        };
+        if malformed {
+            self.reset(id, PROTOCOL_ERROR);
+            return;
+        }
+        self.streams[i].ended = true;
+        self.ready.push(id);
+    }
+
+    /// Answer a stream's request with the status given without reading any more of it, and, if
+    /// the client has yet to end the stream, ask it to stop sending once the response is sent.
+    fn refuse(&mut self, i: uint, status: Status, cut_short: bool) {
+        let id = {
+            let stream = &mut self.streams[i];
+            stream.request.get_mut_ref().body = ~[];
+            stream.error = Some(status);
+            stream.ended = true;
+            stream.cut_short = cut_short;
+            stream.id
+        };
+        self.ready.push(id);
+    }
+
+    /// Hand a stream's request to the handler, and send the response.
+    fn respond<T: Server>(&mut self, server: &T, id: u32) -> Result<(), Ending> {
+        let i = match self.find_stream(id) {
+            Some(i) => i,
+            // Reset while it waited
+            None => return Ok(()),
+        };
+        let (request, error, started, received, cut_short) = {
+            let stream = &mut self.streams[i];
+            (stream.request.take().unwrap(), stream.error.take(), stream.started,
+             stream.received.clone(), stream.cut_short)
+        };
+        let response = handle(server, request, error, started, received, self.config);
+        match response {
+            Some((status, response_headers, body)) => {
+                match self.send_response(server, id, &status, response_headers, body) {
+                    Ok(()) => (),
+                    Err(e) => return Err(e),
+                }
+                if cut_short && self.find_stream(id).is_some() {
+                    self.reset(id, NO_ERROR);
+                }
+                self.close_stream(id);
+            },
+            None => {
+                debug!("a handler hijacked an HTTP/2 stream, which can't be done");
+                self.reset(id, INTERNAL_ERROR);
+            },
+        }
+        Ok(())
+    }
+
+    fn send_response<T: Server>(&mut self, server: &T, id: u32, status: &Status,
+                                response_headers: &headers::response::HeaderCollection,
+                                body: ~[u8]) -> Result<(), Ending> {
+        let block = self.encoder.encode(response_header_list(status, response_headers));
+        let flags = if body.is_empty() { FLAG_END_STREAM } else { 0 };
+        self.write_header_block(id, flags, block);
+        let mut sent = 0;
+        while sent < body.len() {
+            // Wait until the client makes room, if need be, for this stream and the connection
+            loop {
+                let i = match self.find_stream(id) {
+                    Some(i) => i,
+                    // Reset by the client
+                    None => return Ok(()),
+                };
+                if self.send_window > 0 && self.streams[i].send_window > 0 {
+                    break;
+                }
+                let result = match self.read() {
+                    Ok(frame) => self.handle_frame(server, frame),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            let i = self.find_stream(id).unwrap();
+            let len = cmp::min(cmp::min(body.len() - sent, self.max_frame_size),
+                               cmp::min(self.send_window, self.streams[i].send_window) as uint);
+            self.send_window -= len as i64;
+            self.streams[i].send_window -= len as i64;
+            let flags = if sent + len == body.len() { FLAG_END_STREAM } else { 0 };
+            self.write(&DataFrame(id, flags, body.slice(sent, sent + len).to_owned(), None));
+            sent += len;
+        }
+        Ok(())
+    }
+
+    /// Send a header block in a HEADERS frame and as many CONTINUATION frames as it needs.
+    fn write_header_block(&mut self, id: u32, flags: u8, block: ~[u8]) {
+        let mut start = 0;
+        loop {
+            let end = cmp::min(block.len(), start + self.max_frame_size);
+            let fragment = block.slice(start, end).to_owned();
+            let last = if end == block.len() { FLAG_END_HEADERS } else { 0 };
+            if start == 0 {
+                self.write(&HeadersFrame(id, flags | last, None, fragment));
+            } else {
+                self.write(&ContinuationFrame(id, last, fragment));
+            }
+            if end == block.len() {
+                break;
+            }
+            start = end;
+        }
+    }
+}
+
+/// Hand a request to the handler, or answer it with the error given, returning the response
+/// written: its status, headers and body, or `None` if the handler hijacked the connection.
+fn handle<T: Server>(server: &T, request: &Request, error: Option<Status>, started: u64,
+                     received: Tm, config: &ConnectionConfig)
+        -> Option<(Status, ~headers::response::HeaderCollection, ~[u8])> {
+    match config.observer {
+        Some(ref observer) =>
+            observer.get().request_received(request, precise_time_ns() - started),
+        None => (),
+    }
+    let mut memory = BufferedStream::new(MemoryStream(MemWriterFakeStream::new()), false);
+    let written = {
+        let mut response = ResponseWriter::new(&mut memory, request);
+        match config.observer {
+            Some(ref observer) => response.set_observer(observer.clone(), started),
+            None => (),
+        }
+        match config.error_handler {
+            Some(ref handler) => response.set_error_handler(handler.clone()),
+            None => (),
+        }
+        match error {
+            None => {
+                let allow = if request.method == Options && request.request_uri == Star {
+                    server.server_allow()
+                } else {
+                    None
+                };
+                match allow {
+                    Some(methods) => response.write_options(methods),
+                    None => server.handle_request(request, &mut response),
+                }
+                if !response.is_hijacked() {
+                    response.try_write_headers();
+                }
+            },
+            Some(status) => match config.error_handler {
+                Some(_) => {
+                    response.send_error(status);
+                    response.try_write_headers();
+                },
+                None => {
+                    response.status = status;
+                    response.headers.content_length = Some(0);
+                    response.write_headers();
+                },
+            },
+        }
+        response.finish_response();
+        let finished = precise_time_ns();
+        match config.access_log {
+            Some(ref log) =>
+                log.log(&AccessLogEntry::new(request, &response, received, finished - started)),
+            None => (),
+        }
+        match config.observer {
+            Some(ref observer) =>
+                observer.get().response_complete(request, &response, finished - started),
+            None => (),
+        }
+        if response.is_hijacked() {
+            None
+        } else {
+            Some((Status::from_code_and_reason(response.status.code(), response.status.reason()),
+                  response.headers.clone()))
+        }
+    };
+    match written {
+        Some((status, response_headers)) => match memory.unwrap() {
+            MemoryStream(MemWriterFakeStream(writer)) =>
+                Some((status, response_headers, writer.inner())),
+            _ => unreachable!(),
+        },
+        None => None,
+    }
+}
+
+/// Whether a header only concerns a connection of HTTP/1.1, and mustn't be sent in HTTP/2 (RFC
+/// 7540, section 8.1.2.2); the name must be in lower case.
+fn is_connection_specific(name: &str) -> bool {
+    match name {
+        "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" => true,
+        _ => false,
+    }
+}
+
+/// The headers of the response to a stream: `:status`, then the response's headers in lower case,
+/// save those that are connection-specific.
+fn response_header_list(status: &Status, response_headers: &headers::response::HeaderCollection)
+        -> HeaderList {
+    let mut list = ~[(~":status", status.code().to_str())];
+    for header in response_headers.iter() {
+        let name = header.header_name().to_ascii_lower();
+        if !is_connection_specific(name.as_slice()) {
+            list.push((name, header.header_value()));
+        }
+    }
+    list
+}
+
+/**
+ * Make a `Request` of the headers of a stream: its method and Request-URI are those of the
+ * `:method` and `:path` pseudo-headers (or for CONNECT, `:authority`), its URL is made of those
+ * and `:scheme` and `:authority`, and its other headers are those of HTTP/1.1, with `:authority`
+ * as Host, if it is given, and the Cookie headers, which HTTP/2 lets be split, joined into one.
+ * Its body is empty, to be filled from the stream's DATA frames.
+ *
+ * `None` is returned if the request is malformed (RFC 7540, section 8.1.2): if a pseudo-header
+ * needed is missing, or one is unknown or given twice or after the other headers, or a header's
+ * name isn't in lower case, or it only concerns the connection, or its value has a CR, LF or NUL
+ * in it.
+ */
+fn request_from_headers(list: HeaderList, remote_addr: Option<SocketAddr>) -> Option<~Request> {
+    let (mut method, mut scheme, mut authority, mut path) = (None, None, None, None);
+    let mut pseudo_headers_done = false;
+    let mut text = ~"";
+    let mut cookies = ~[];
+    for &(ref name, ref value) in list.iter() {
+        if value.contains_char('\r') || value.contains_char('\n') || value.contains_char('\x00') {
+            return None;
+        }
+        if name.starts_with(":") {
+            if pseudo_headers_done {
+                return None;
+            }
+            match name.as_slice() {
+                ":method" if method.is_none() => method = Some(value.clone()),
+                ":scheme" if scheme.is_none() => scheme = Some(value.clone()),
+                ":authority" if authority.is_none() => authority = Some(value.clone()),
+                ":path" if path.is_none() => path = Some(value.clone()),
+                _ => return None,
+            }
+            continue;
+        }
+        pseudo_headers_done = true;
+        if name.is_empty() || name.to_ascii_lower() != *name
+                || is_connection_specific(name.as_slice()) {
+            return None;
+        }
+        match name.as_slice() {
+            "te" if value.as_slice() != "trailers" => return None,
+            "cookie" => cookies.push(value.clone()),
+            "host" if authority.is_some() => (),
+            _ => text.push_str(format!("{}: {}\r\n", *name, *value)),
+        }
+    }
+    if !cookies.is_empty() {
+        text.push_str(format!("cookie: {}\r\n", cookies.connect("; ")));
+    }
+
+    let method = match method {
+        Some(ref method) if method.is_ascii() && !method.is_empty() =>
+            match Method::from_str_or_new(method.as_slice()) {
+                Some(method) => method,
+                None => return None,
+            },
+        _ => return None,
+    };
+    let request_uri = if method == Connect {
+        // CONNECT names only where to connect to (RFC 7540, section 8.3).
+        match (&scheme, &path, &authority) {
+            (&None, &None, &Some(ref authority)) => Authority(authority.clone()),
+            _ => return None,
+        }
+    } else {
+        match (&scheme, &path) {
+            (&Some(_), &Some(ref path)) if !path.is_empty() =>
+                match FromStr::from_str(path.as_slice()) {
+                    Some(request_uri) => request_uri,
+                    None => return None,
+                },
+            _ => return None,
+        }
+    };
+
+    // The headers are read as they would be from an HTTP/1.1 request, to be interpreted alike.
+    text.push_str("\r\n");
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(text.into_bytes()), false);
+    let mut buffer = RequestBuffer::new(&mut stream);
+    let mut collection = ~headers::request::HeaderCollection::new();
+    loop {
+        match buffer.read_header::<headers::request::Header>() {
+            Ok(header) => collection.push(header),
+            Err(EndOfHeaders) => break,
+            Err(MalformedHeaderValue) => (),
+            Err(_) => return None,
+        }
+    }
+    match authority {
+        Some(ref authority) => {
+            let host: Option<Host> = headers::header_value_from_str(authority.as_slice());
+            if host.is_none() {
+                return None;
+            }
+            collection.host = host;
+        },
+        None => (),
+    }
+
+    let url: Option<Url> = match (&scheme, &path, &collection.host) {
+        (&Some(ref scheme), &Some(ref path), &Some(ref host)) if path.as_slice() != "*" =>
+            FromStr::from_str(format!("{}://{}{}", *scheme, host.to_str(), *path)),
+        _ => None,
+    };
+
+    Some(~Request {
+        remote_addr: remote_addr,
+        headers: collection,
+        raw_headers: ~[],
+        body: ~[],
+        trailer: ~[],
+        method: method,
+        request_uri: request_uri,
+        url: url,
+        close_connection: false,
+        version: (2, 0),
+        trusted_proxy_hops: 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use method::{Get, Connect};
+    use server::request::{AbsolutePath, Authority};
+    use status;
+    use headers;
+    use headers::cookie::{Cookie, CookieList};
+    use headers::connection::Token;
+    use super::{request_from_headers, response_header_list, upgrade_settings};
+
+    fn request_headers() -> ~[(~str, ~str)] {
+        ~[(~":method", ~"GET"), (~":scheme", ~"https"), (~":authority", ~"example.com:8443"),
+          (~":path", ~"/search?q=h2"), (~"accept", ~"text/html"), (~"cookie", ~"a=1"),
+          (~"cookie", ~"b=2")]
+    }
+
+    #[test]
+    fn test_request_from_headers() {
+        let request = request_from_headers(request_headers(), None).unwrap();
+        assert_eq!(request.method, Get);
+        assert_eq!(request.version, (2, 0));
+        assert_eq!(request.request_uri, AbsolutePath(~"/search?q=h2"));
+        assert_eq!(request.url.get_ref().to_str(), ~"https://example.com:8443/search?q=h2");
+        assert_eq!(request.headers.host.get_ref().to_str(), ~"example.com:8443");
+        assert_eq!(request.headers.cookie,
+                   Some(CookieList(~[Cookie::new(~"a", ~"1"), Cookie::new(~"b", ~"2")])));
+
+        let request = request_from_headers(~[(~":method", ~"CONNECT"),
+                                             (~":authority", ~"example.com:443")], None).unwrap();
+        assert_eq!(request.method, Connect);
+        assert_eq!(request.request_uri, Authority(~"example.com:443"));
+        assert!(request.url.is_none());
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        fn malformed(change: &fn(&mut ~[(~str, ~str)])) -> bool {
+            let mut headers = request_headers();
+            change(&mut headers);
+            request_from_headers(headers, None).is_none()
+        }
+        assert!(malformed(|h| { h.retain(|&(ref n, _)| n.as_slice() != ":path"); }));
+        assert!(malformed(|h| h.push((~":path", ~"/again"))));
+        assert!(malformed(|h| h.push((~":status", ~"200"))));
+        assert!(malformed(|h| h.push((~"Accept", ~"text/plain"))));
+        assert!(malformed(|h| h.push((~"connection", ~"keep-alive"))));
+        assert!(malformed(|h| h.push((~"te", ~"gzip"))));
+        assert!(malformed(|h| h.push((~"x-evil", ~"a\r\nhost: evil.example"))));
+        assert!(!malformed(|h| h.push((~"te", ~"trailers"))));
+        assert!(malformed(|h| h.insert(0, (~"accept", ~"*/*"))));
+    }
+
+    #[test]
+    fn test_upgrade_settings() {
+        let mut request = request_from_headers(request_headers(), None).unwrap();
+        request.version = (1, 1);
+        request.headers.upgrade = Some(~"h2c");
+        request.headers.connection = Some(~[Token(~"Upgrade"), Token(~"Http2-Settings")]);
+        request.headers.extensions.insert(~"Http2-Settings", ~"AAMAAABkAARAAAAAAAIAAAAA");
+        assert_eq!(upgrade_settings(request), Some(~[(3, 100), (4, 0x40000000), (2, 0)]));
+
+        request.headers.extensions.insert(~"Http2-Settings", ~"AAMAAABkAA");
+        assert_eq!(upgrade_settings(request), None);
+        request.headers.extensions.insert(~"Http2-Settings", ~"");
+        assert_eq!(upgrade_settings(request), Some(~[]));
+        request.headers.connection = Some(~[Token(~"Upgrade")]);
+        assert_eq!(upgrade_settings(request), None);
+        request.headers.connection = Some(~[Token(~"Upgrade"), Token(~"Http2-Settings")]);
+        request.headers.upgrade = Some(~"websocket");
+        assert_eq!(upgrade_settings(request), None);
+        request.headers.upgrade = Some(~"h2c");
+        request.version = (1, 0);
+        assert_eq!(upgrade_settings(request), None);
+    }
+
+    #[test]
+    fn test_response_header_list() {
+        let mut collection = headers::response::HeaderCollection::new();
+        collection.content_length = Some(3);
+        collection.connection = Some(~[headers::connection::Close]);
+        assert_eq!(response_header_list(&status::NotFound, &collection),
+                   ~[(~":status", ~"404"), (~"content-length", ~"3")]);
+    }
+}
@@ -0,0 +1,401 @@
+//! The frames of HTTP/2 (RFC 7540, sections 4 and 6). Each has a nine-byte header (the length of
+//! its payload, its type, its flags and the stream it belongs to, 0 for the connection as a
+//! whole) and then its payload.
+//!
+//! Padding, and the priority of HEADERS frames, are taken care of here: a frame is read without its
+//! padding, and with neither `FLAG_PADDED` nor `FLAG_PRIORITY`, and written with them only as it
+//! needs them. Only how much padding a DATA frame had is kept, as that counts against the flow
+//! control window as much as the data does.
+
+use std::rt::io::{Reader, Writer};
+use std::vec;
+use common::{read_exactly, read_u32, read_stream_id, push_u32};
+use super::{PROTOCOL_ERROR, FRAME_SIZE_ERROR, MAX_FRAME_SIZE};
+
+/// The flag of a DATA or HEADERS frame which is the last of its stream from the sender.
+pub static FLAG_END_STREAM: u8 = 0x1;
+
+/// The flag of a SETTINGS or PING frame which acknowledges one received.
+pub static FLAG_ACK: u8 = 0x1;
+
+/// The flag of a HEADERS, PUSH_PROMISE or CONTINUATION frame with which the header block is
+/// complete.
+pub static FLAG_END_HEADERS: u8 = 0x4;
+
+static FLAG_PADDED: u8 = 0x8;
+static FLAG_PRIORITY: u8 = 0x20;
+
+static DATA: u8 = 0x0;
+static HEADERS: u8 = 0x1;
+static PRIORITY: u8 = 0x2;
+static RST_STREAM: u8 = 0x3;
+static SETTINGS: u8 = 0x4;
+static PUSH_PROMISE: u8 = 0x5;
+static PING: u8 = 0x6;
+static GOAWAY: u8 = 0x7;
+static WINDOW_UPDATE: u8 = 0x8;
+static CONTINUATION: u8 = 0x9;
+
+/// Where a stream stands among the others (RFC 7540, section 5.3).
+#[deriving(Clone, Eq)]
+pub struct Priority {
+    /// Whether the stream is to be the only one depending on its dependency.
+    exclusive: bool,
+    /// The stream this one depends on; 0 for none.
+    dependency: u32,
+    /// The stream's weight, less one: from 0 to 255.
+    weight: u8,
+}
+
+/// A frame.
+#[deriving(Clone, Eq)]
+pub enum Frame {
+    /// Data of a stream: its ID, its flags (`FLAG_END_STREAM`), the data, and how many bytes of
+    /// padding follow it, if it is padded.
+    DataFrame(u32, u8, ~[u8], Option<u8>),
+    /// The opening of a stream, or its trailer: its ID, its flags (`FLAG_END_STREAM` and
+    /// `FLAG_END_HEADERS`), its priority, if given, and the start of the header block, which
+    /// CONTINUATION frames carry on until one has `FLAG_END_HEADERS`.
+    HeadersFrame(u32, u8, Option<Priority>, ~[u8]),
+    /// A change to a stream's priority.
+    PriorityFrame(u32, Priority),
+    /// The ending of a stream, because of trouble: its ID and the error code.
+    RstStreamFrame(u32, u32),
+    /// Settings of the connection: the flags (`FLAG_ACK`) and the settings, as (identifier,
+    /// value) pairs.
+    SettingsFrame(u8, ~[(u16, u32)]),
+    /// The server's promise of a stream it is to push: the ID of the stream the promise is sent
+    /// on, the flags (`FLAG_END_HEADERS`), the ID of the stream promised and the start of the
+    /// header block of the request it answers.
+    PushPromiseFrame(u32, u8, u32, ~[u8]),
+    /// A ping, to be answered with one with `FLAG_ACK` and the same data: the flags and the data.
+    PingFrame(u8, u64),
+    /// The end of the connection: the last stream which was or may be acted upon, the error code
+    /// (`NO_ERROR` if all is well) and any debugging data.
+    GoAwayFrame(u32, u32, ~[u8]),
+    /// More of a window for sending data: the stream's ID, or 0 for the connection, and how many
+    /// bytes it has grown by.
+    WindowUpdateFrame(u32, u32),
+    /// More of a header block: the stream's ID, the flags (`FLAG_END_HEADERS`) and the fragment.
+    ContinuationFrame(u32, u8, ~[u8]),
+}
+
+/// Why a frame couldn't be read.
+#[deriving(Clone, Eq)]
+pub enum FrameError {
+    /// The stream ended, before the frame or in the middle of it.
+    EndOfStream,
+    /// The frame is longer than allowed, or the wrong length for its type.
+    FrameSizeError,
+    /// The frame is otherwise invalid: on the wrong stream for its type, say, or with more padding
+    /// than payload.
+    ProtocolError,
+}
+
+impl FrameError {
+    /// The error code with which to end the connection.
+    pub fn code(&self) -> u32 {
+        match *self {
+            FrameSizeError => FRAME_SIZE_ERROR,
+            EndOfStream | ProtocolError => PROTOCOL_ERROR,
+        }
+    }
+}
+
+/// The payload of a padded frame without its padding, or `None` if the padding is longer than the
+/// payload (RFC 7540, section 6.1).
+fn strip_padding(flags: u8, payload: ~[u8]) -> Option<~[u8]> {
+    if flags & FLAG_PADDED == 0 {
+        return Some(payload);
+    }
+    if payload.is_empty() || payload[0] as uint >= payload.len() {
+        return None;
+    }
+    Some(payload.slice(1, payload.len() - payload[0] as uint).to_owned())
+}
+
+fn read_priority(b: &[u8]) -> Priority {
+    Priority {
+        exclusive: b[0] & 0x80 != 0,
+        dependency: read_stream_id(b),
+        weight: b[4],
+    }
+}
+
+fn push_priority(out: &mut ~[u8], priority: &Priority) {
+    let exclusive = if priority.exclusive { 0x80000000 } else { 0 };
+    push_u32(out, (priority.dependency & 0x7FFFFFFF) | exclusive);
+    out.push(priority.weight);
+}
+
+/// The settings of the payload of a SETTINGS frame, as (identifier, value) pairs, or `None` if
+/// its length isn't a multiple of six. The HTTP2-Settings header of a request to upgrade to
+/// HTTP/2 has such a payload too.
+pub fn read_settings(payload: &[u8]) -> Option<~[(u16, u32)]> {
+    if payload.len() % 6 != 0 {
+        return None;
+    }
+    Some(do vec::from_fn(payload.len() / 6) |i| {
+        let entry = payload.slice_from(i * 6);
+        ((entry[0] as u16 << 8) | entry[1] as u16, read_u32(entry.slice_from(2)))
+    })
+}
+
+/**
+ * Read a frame whose payload is no longer than `max_frame_size` (the receiver's
+ * SETTINGS_MAX_FRAME_SIZE). Frames of types which aren't known are skipped, as the protocol asks.
+ *
+ * A frame which is invalid in itself, whatever the state of the connection, is an error, to end
+ * the connection with: one on no stream of a type which must be on a stream, say, or a SETTINGS
+ * frame of a length which isn't a multiple of six, or a WINDOW_UPDATE of no bytes.
+ */
+pub fn read_frame<R: Reader>(reader: &mut R, max_frame_size: uint) -> Result<Frame, FrameError> {
+    loop {
+        let head = match read_exactly(reader, 9) {
+            Some(head) => head,
+            None => return Err(EndOfStream),
+        };
+        let len = (head[0] as uint << 16) | (head[1] as uint << 8) | head[2] as uint;
+        let (frame_type, flags) = (head[3], head[4]);
+        let stream_id = read_stream_id(head.slice_from(5));
+        if len > max_frame_size {
+            return Err(FrameSizeError);
+        }
+        let payload = match read_exactly(reader, len) {
+            Some(payload) => payload,
+            None => return Err(EndOfStream),
+        };
+        if frame_type > CONTINUATION {
+            debug!("skipping an HTTP/2 frame of unknown type {}", frame_type);
+            continue;
+        }
+        let stream_ok = match frame_type {
+            SETTINGS | PING | GOAWAY => stream_id == 0,
+            WINDOW_UPDATE => true,
+            _ => stream_id != 0,
+        };
+        if !stream_ok {
+            return Err(ProtocolError);
+        }
+        let size_ok = match frame_type {
+            PRIORITY => len == 5,
+            RST_STREAM | WINDOW_UPDATE => len == 4,
+            SETTINGS if flags & FLAG_ACK != 0 => len == 0,
+            SETTINGS => len % 6 == 0,
+            PING => len == 8,
+            GOAWAY => len >= 8,
+            _ => true,
+        };
+        if !size_ok {
+            return Err(FrameSizeError);
+        }
+        return match frame_type {
+            DATA => match strip_padding(flags, payload) {
+                Some(data) => {
+                    let padding = if flags & FLAG_PADDED == 0 {
+                        None
+                    } else {
+                        Some((len - data.len() - 1) as u8)
+                    };
+                    Ok(DataFrame(stream_id, flags & !FLAG_PADDED, data, padding))
+                },
+                None => Err(ProtocolError),
+            },
+            HEADERS => match strip_padding(flags, payload) {
+                Some(fragment) => {
+                    let plain_flags = flags & !(FLAG_PADDED | FLAG_PRIORITY);
+                    if flags & FLAG_PRIORITY == 0 {
+                        Ok(HeadersFrame(stream_id, plain_flags, None, fragment))
+                    } else if fragment.len() < 5 {
+                        Err(FrameSizeError)
+                    } else {
+                        Ok(HeadersFrame(stream_id, plain_flags, Some(read_priority(fragment)),
+                                        fragment.slice_from(5).to_owned()))
+                    }
+                },
+                None => Err(ProtocolError),
+            },
+            PRIORITY => Ok(PriorityFrame(stream_id, read_priority(payload))),
+            RST_STREAM => Ok(RstStreamFrame(stream_id, read_u32(payload))),
+            SETTINGS => Ok(SettingsFrame(flags, read_settings(payload).unwrap())),
+            PUSH_PROMISE => match strip_padding(flags, payload) {
+                Some(ref fragment) if fragment.len() >= 4 =>
+                    Ok(PushPromiseFrame(stream_id, flags & !FLAG_PADDED,
+                                        read_stream_id(fragment.as_slice()),
+                                        fragment.slice_from(4).to_owned())),
+                Some(_) => Err(FrameSizeError),
+                None => Err(ProtocolError),
+            },
+            PING => Ok(PingFrame(flags, (read_u32(payload) as u64 << 32) |
+                                        read_u32(payload.slice_from(4)) as u64)),
+            GOAWAY => Ok(GoAwayFrame(read_stream_id(payload), read_u32(payload.slice_from(4)),
+                                     payload.slice_from(8).to_owned())),
+            WINDOW_UPDATE => match read_u32(payload) & 0x7FFFFFFF {
+                0 => Err(ProtocolError),
+                increment => Ok(WindowUpdateFrame(stream_id, increment)),
+            },
+            _ => Ok(ContinuationFrame(stream_id, flags, payload)),
+        };
+    }
+}
+
+/// Write a frame. This fails if its payload would be longer than `MAX_FRAME_SIZE`; it is for the
+/// caller to keep to what the receiver allows, which may be less.
+pub fn write_frame<W: Writer>(writer: &mut W, frame: &Frame) {
+    let (frame_type, flags, stream_id, payload) = match *frame {
+        DataFrame(stream_id, flags, ref data, None) => (DATA, flags, stream_id, data.clone()),
+        DataFrame(stream_id, flags, ref data, Some(padding)) => {
+            let mut payload = ~[padding];
+            payload.push_all(*data);
+            payload.grow(padding as uint, &0u8);
+            (DATA, flags | FLAG_PADDED, stream_id, payload)
+        },
+        HeadersFrame(stream_id, flags, ref priority, ref fragment) => match *priority {
+            Some(ref priority) => {
+                let mut payload = ~[];
+                push_priority(&mut payload, priority);
+                payload.push_all(*fragment);
+                (HEADERS, flags | FLAG_PRIORITY, stream_id, payload)
+            },
+            None => (HEADERS, flags, stream_id, fragment.clone()),
+        },
+        PriorityFrame(stream_id, ref priority) => {
+            let mut payload = ~[];
+            push_priority(&mut payload, priority);
+            (PRIORITY, 0, stream_id, payload)
+        },
+        RstStreamFrame(stream_id, code) => {
+            let mut payload = ~[];
+            push_u32(&mut payload, code);
+            (RST_STREAM, 0, stream_id, payload)
+        },
+        SettingsFrame(flags, ref settings) => {
+            let mut payload = ~[];
+            for &(id, value) in settings.iter() {
+                payload.push((id >> 8) as u8);
+                payload.push(id as u8);
+                push_u32(&mut payload, value);
+            }
+            (SETTINGS, flags, 0, payload)
+        },
+        PushPromiseFrame(stream_id, flags, promised_stream_id, ref fragment) => {
+            let mut payload = ~[];
+            push_u32(&mut payload, promised_stream_id & 0x7FFFFFFF);
+            payload.push_all(*fragment);
+            (PUSH_PROMISE, flags, stream_id, payload)
+        },
+        PingFrame(flags, data) => {
+            let mut payload = ~[];
+            push_u32(&mut payload, (data >> 32) as u32);
+            push_u32(&mut payload, data as u32);
+            (PING, flags, 0, payload)
+        },
+        GoAwayFrame(last_stream_id, code, ref debug_data) => {
+            let mut payload = ~[];
+            push_u32(&mut payload, last_stream_id & 0x7FFFFFFF);
+            push_u32(&mut payload, code);
+            payload.push_all(*debug_data);
+            (GOAWAY, 0, 0, payload)
+        },
+        WindowUpdateFrame(stream_id, increment) => {
+            let mut payload = ~[];
+            push_u32(&mut payload, increment & 0x7FFFFFFF);
+            (WINDOW_UPDATE, 0, stream_id, payload)
+        },
+        ContinuationFrame(stream_id, flags, ref fragment) =>
+            (CONTINUATION, flags, stream_id, fragment.clone()),
+    };
+    if payload.len() > MAX_FRAME_SIZE {
+        fail!("write_frame() called with a frame too long to send");
+    }
+    let len = payload.len();
+    let mut head = ~[(len >> 16) as u8, (len >> 8) as u8, len as u8, frame_type, flags];
+    push_u32(&mut head, stream_id & 0x7FFFFFFF);
+    writer.write(head);
+    writer.write(payload);
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::Decorator;
+    use std::rt::io::mem::{MemReader, MemWriter};
+    use http2::{DEFAULT_MAX_FRAME_SIZE, PROTOCOL_ERROR, CANCEL};
+    use super::{read_frame, write_frame, Frame, Priority, DataFrame, HeadersFrame,
+                PriorityFrame, RstStreamFrame, SettingsFrame, PushPromiseFrame, PingFrame,
+                GoAwayFrame, WindowUpdateFrame, ContinuationFrame, EndOfStream, FrameSizeError,
+                ProtocolError, FLAG_END_STREAM, FLAG_END_HEADERS, FLAG_ACK};
+
+    fn read(bytes: ~[u8]) -> Result<Frame, super::FrameError> {
+        read_frame(&mut MemReader::new(bytes), DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    #[test]
+    fn test_frames() {
+        let priority = Priority { exclusive: true, dependency: 3, weight: 15 };
+        let frames = ~[
+            SettingsFrame(0, ~[(3, 100), (4, 0x10000)]),
+            SettingsFrame(FLAG_ACK, ~[]),
+            HeadersFrame(1, FLAG_END_STREAM, Some(priority), ~[0x82, 0x86]),
+            ContinuationFrame(1, FLAG_END_HEADERS, ~[0x84]),
+            HeadersFrame(3, FLAG_END_HEADERS, None, ~[0x82]),
+            DataFrame(3, 0, ~[1, 2, 3], None),
+            DataFrame(3, FLAG_END_STREAM, ~[4], Some(2)),
+            PriorityFrame(5, priority),
+            RstStreamFrame(5, CANCEL),
+            PushPromiseFrame(1, FLAG_END_HEADERS, 2, ~[0x82]),
+            PingFrame(FLAG_ACK, 0x0102030405060708),
+            WindowUpdateFrame(0, 0x8000),
+            GoAwayFrame(5, PROTOCOL_ERROR, ~[1]),
+        ];
+        let mut writer = MemWriter::new();
+        for frame in frames.iter() {
+            write_frame(&mut writer, frame);
+        }
+        let mut reader = MemReader::new(writer.inner());
+        for frame in frames.iter() {
+            assert_eq!(read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE), Ok(frame.clone()));
+        }
+        assert_eq!(read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE), Err(EndOfStream));
+
+        // The length, type, flags and stream, then the payload
+        let mut writer = MemWriter::new();
+        write_frame(&mut writer, &DataFrame(5, FLAG_END_STREAM, ~[9], None));
+        assert_eq!(writer.inner(), ~[0, 0, 1, 0, 1, 0, 0, 0, 5, 9]);
+        let mut writer = MemWriter::new();
+        write_frame(&mut writer, &DataFrame(5, 0, ~[9], Some(1)));
+        assert_eq!(writer.inner(), ~[0, 0, 3, 0, 8, 0, 0, 0, 5, 1, 9, 0]);
+    }
+
+    #[test]
+    fn test_padding() {
+        // Two bytes of data and three of padding
+        assert_eq!(read(~[0, 0, 6, 0, 0x9, 0, 0, 0, 1, 3, 7, 8, 0, 0, 0]),
+                   Ok(DataFrame(1, FLAG_END_STREAM, ~[7, 8], Some(3))));
+        // Padding with priority, before the fragment
+        assert_eq!(read(~[0, 0, 8, 1, 0x2C, 0, 0, 0, 1, 1, 0, 0, 0, 0, 16, 0x82, 0]),
+                   Ok(HeadersFrame(1, FLAG_END_HEADERS,
+                                   Some(Priority { exclusive: false, dependency: 0, weight: 16 }),
+                                   ~[0x82])));
+        // More padding than payload
+        assert_eq!(read(~[0, 0, 2, 0, 0x8, 0, 0, 0, 1, 2, 0]), Err(ProtocolError));
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        // Too long, and cut short
+        assert_eq!(read_frame(&mut MemReader::new(~[0, 0, 3, 0, 0, 0, 0, 0, 1, 1, 2, 3]), 2),
+                   Err(FrameSizeError));
+        assert_eq!(read(~[0, 0, 8, 6, 0, 0, 0, 0, 0, 1, 2]), Err(EndOfStream));
+        // DATA on no stream, and SETTINGS on one
+        assert_eq!(read(~[0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(ProtocolError));
+        assert_eq!(read(~[0, 0, 0, 4, 0, 0, 0, 0, 1]), Err(ProtocolError));
+        // An acknowledgement with settings, and a ping too short
+        assert_eq!(read(~[0, 0, 6, 4, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 1]), Err(FrameSizeError));
+        assert_eq!(read(~[0, 0, 4, 6, 0, 0, 0, 0, 0, 1, 2, 3, 4]), Err(FrameSizeError));
+        // A window update of nothing
+        assert_eq!(read(~[0, 0, 4, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(ProtocolError));
+        // Frames of unknown types are skipped
+        assert_eq!(read(~[0, 0, 1, 0xA0, 0, 0, 0, 0, 0, 1, 0, 0, 4, 3, 0, 0, 0, 0, 1, 0, 0, 0, 8]),
+                   Ok(RstStreamFrame(1, CANCEL)));
+    }
+}
@@ -0,0 +1,466 @@
+//! HPACK (RFC 7541): the compression of the header blocks of HTTP/2. Each end of a connection
+//! keeps a table of the headers it has lately sent, or been sent, for later ones to refer to by
+//! their index; so the header blocks of a connection must be encoded, and decoded, in the order
+//! they are sent, by one `Encoder` and one `Decoder`.
+
+use std::str;
+use super::huffman;
+
+/// The largest the dynamic table is until the decoder says otherwise (by
+/// SETTINGS_HEADER_TABLE_SIZE).
+pub static DEFAULT_TABLE_SIZE: uint = 4096;
+
+/// The headers all header blocks may refer to, from index 1 (RFC 7541, appendix A).
+static STATIC_TABLE: [(&'static str, &'static str), ..61] = [
+    (":authority", ""), (":method", "GET"), (":method", "POST"), (":path", "/"),
+    (":path", "/index.html"), (":scheme", "http"), (":scheme", "https"), (":status", "200"),
+    (":status", "204"), (":status", "206"), (":status", "304"), (":status", "400"),
+    (":status", "404"), (":status", "500"), ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"), ("accept-language", ""), ("accept-ranges", ""),
+    ("accept", ""), ("access-control-allow-origin", ""), ("age", ""), ("allow", ""),
+    ("authorization", ""), ("cache-control", ""), ("content-disposition", ""),
+    ("content-encoding", ""), ("content-language", ""), ("content-length", ""),
+    ("content-location", ""), ("content-range", ""), ("content-type", ""), ("cookie", ""),
+    ("date", ""), ("etag", ""), ("expect", ""), ("expires", ""), ("from", ""), ("host", ""),
+    ("if-match", ""), ("if-modified-since", ""), ("if-none-match", ""), ("if-range", ""),
+    ("if-unmodified-since", ""), ("last-modified", ""), ("link", ""), ("location", ""),
+    ("max-forwards", ""), ("proxy-authenticate", ""), ("proxy-authorization", ""),
+    ("range", ""), ("referer", ""), ("refresh", ""), ("retry-after", ""), ("server", ""),
+    ("set-cookie", ""), ("strict-transport-security", ""), ("transfer-encoding", ""),
+    ("user-agent", ""), ("vary", ""), ("via", ""), ("www-authenticate", ""),
+];
+
+/// The headers of a header block, as (name, value) pairs in the order they came. Names are in
+/// lower case.
+pub type HeaderList = ~[(~str, ~str)];
+
+/// How much of the dynamic table an entry takes up (RFC 7541, section 4.1).
+fn entry_size(name: &str, value: &str) -> uint {
+    32 + name.len() + value.len()
+}
+
+/// The dynamic table, which follows on from the static table in the indices.
+struct Table {
+    /// The entries, the newest first.
+    entries: ~[(~str, ~str)],
+    /// The total size of the entries.
+    size: uint,
+    max_size: uint,
+}
+
+impl Table {
+    fn new() -> Table {
+        Table {
+            entries: ~[],
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// The header at the index given, in the static table or the dynamic table.
+    fn get(&self, index: uint) -> Option<(~str, ~str)> {
+        if index == 0 {
+            None
+        } else if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            Some((name.to_owned(), value.to_owned()))
+        } else if index <= STATIC_TABLE.len() + self.entries.len() {
+            Some(self.entries[index - STATIC_TABLE.len() - 1].clone())
+        } else {
+            None
+        }
+    }
+
+    /// The index of a header with the name given, and whether it has the value given too; one
+    /// with both is found in preference to one with just the name.
+    fn find(&self, name: &str, value: &str) -> Option<(uint, bool)> {
+        let mut name_match = None;
+        for (i, &(n, v)) in STATIC_TABLE.iter().enumerate() {
+            if n == name {
+                if v == value {
+                    return Some((i + 1, true));
+                }
+                if name_match.is_none() {
+                    name_match = Some((i + 1, false));
+                }
+            }
+        }
+        for (i, &(ref n, ref v)) in self.entries.iter().enumerate() {
+            if n.as_slice() == name {
+                if v.as_slice() == value {
+                    return Some((STATIC_TABLE.len() + i + 1, true));
+                }
+                if name_match.is_none() {
+                    name_match = Some((STATIC_TABLE.len() + i + 1, false));
+                }
+            }
+        }
+        name_match
+    }
+
+    /// Add a header, evicting the oldest to make room for it. One larger than the whole table
+    /// just leaves it empty (RFC 7541, section 4.4).
+    fn insert(&mut self, name: ~str, value: ~str) {
+        self.size += entry_size(name, value);
+        self.entries.unshift((name, value));
+        self.evict();
+    }
+
+    fn set_max_size(&mut self, max_size: uint) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let (name, value) = self.entries.pop();
+            self.size -= entry_size(name, value);
+        }
+    }
+}
+
+/// Encode an integer with a prefix of the bits given (RFC 7541, section 5.1), after the bits
+/// of `first` above them.
+fn encode_integer(out: &mut ~[u8], first: u8, prefix_bits: uint, n: uint) {
+    let max = (1u << prefix_bits) - 1;
+    if n < max {
+        out.push(first | n as u8);
+        return;
+    }
+    out.push(first | max as u8);
+    let mut n = n - max;
+    while n >= 0x80 {
+        out.push((n & 0x7F) as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Decode an integer with a prefix of the bits given, at `pos`, moving `pos` past it; `None` if
+/// it runs past the end, or is too large to be reasonable.
+fn decode_integer(data: &[u8], pos: &mut uint, prefix_bits: uint) -> Option<uint> {
+    if *pos >= data.len() {
+        return None;
+    }
+    let max = (1u << prefix_bits) - 1;
+    let mut n = data[*pos] as uint & max;
+    *pos += 1;
+    if n < max {
+        return Some(n);
+    }
+    let mut shift = 0;
+    loop {
+        // Four more bytes are enough for anything up to 256MB.
+        if *pos >= data.len() || shift > 21 {
+            return None;
+        }
+        let b = data[*pos];
+        *pos += 1;
+        n += (b & 0x7F) as uint << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+}
+
+/// Encode a string literal (RFC 7541, section 5.2), with the Huffman code if that makes it
+/// shorter.
+fn encode_string(out: &mut ~[u8], s: &[u8]) {
+    let huffman_len = huffman::encoded_len(s);
+    if huffman_len < s.len() {
+        encode_integer(out, 0x80, 7, huffman_len);
+        out.push_all(huffman::encode(s));
+    } else {
+        encode_integer(out, 0, 7, s.len());
+        out.push_all(s);
+    }
+}
+
+/// Decode a string literal at `pos`, moving `pos` past it. Strings which aren't UTF-8 aren't
+/// taken.
+fn decode_string(data: &[u8], pos: &mut uint) -> Option<~str> {
+    if *pos >= data.len() {
+        return None;
+    }
+    let is_huffman = data[*pos] & 0x80 != 0;
+    let len = match decode_integer(data, pos, 7) {
+        Some(len) if len <= data.len() - *pos => len,
+        _ => return None,
+    };
+    let raw = data.slice(*pos, *pos + len);
+    *pos += len;
+    let bytes = if is_huffman {
+        match huffman::decode(raw) {
+            Some(bytes) => bytes,
+            None => return None,
+        }
+    } else {
+        raw.to_owned()
+    };
+    if str::is_utf8(bytes) {
+        Some(str::from_utf8(bytes))
+    } else {
+        None
+    }
+}
+
+/// What decompresses the header blocks a connection is sent.
+pub struct Decoder {
+    priv table: Table,
+    /// The largest the encoder may make the dynamic table, as this end has said it may (by
+    /// SETTINGS_HEADER_TABLE_SIZE); `DEFAULT_TABLE_SIZE` by default.
+    max_table_size: uint,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: Table::new(),
+            max_table_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// Decode a whole header block, or `None` if it is invalid, which is a connection error of the
+    /// type COMPRESSION_ERROR; the table is no longer to be relied upon.
+    pub fn decode(&mut self, block: &[u8]) -> Option<HeaderList> {
+        let mut headers = ~[];
+        let mut pos = 0;
+        while pos < block.len() {
+            let b = block[pos];
+            if b & 0x80 != 0 {
+                // Indexed (section 6.1)
+                let header = match decode_integer(block, &mut pos, 7) {
+                    Some(index) => self.table.get(index),
+                    None => None,
+                };
+                match header {
+                    Some(header) => headers.push(header),
+                    None => return None,
+                }
+            } else if b & 0x40 != 0 {
+                // Literal, with incremental indexing (section 6.2.1)
+                match self.read_literal(block, &mut pos, 6) {
+                    Some((name, value)) => {
+                        self.table.insert(name.clone(), value.clone());
+                        headers.push((name, value));
+                    },
+                    None => return None,
+                }
+            } else if b & 0x20 != 0 {
+                // Dynamic table size update, which must come before the headers (section 4.2)
+                if !headers.is_empty() {
+                    return None;
+                }
+                match decode_integer(block, &mut pos, 5) {
+                    Some(size) if size <= self.max_table_size => self.table.set_max_size(size),
+                    _ => return None,
+                }
+            } else {
+                // Literal, without indexing or never indexed (sections 6.2.2 and 6.2.3)
+                match self.read_literal(block, &mut pos, 4) {
+                    Some(header) => headers.push(header),
+                    None => return None,
+                }
+            }
+        }
+        Some(headers)
+    }
+
+    /// Read a literal header, whose name is indexed with the prefix of the bits given, or, with
+    /// index 0, follows as a string literal.
+    fn read_literal(&self, block: &[u8], pos: &mut uint, prefix_bits: uint)
+            -> Option<(~str, ~str)> {
+        let name = match decode_integer(block, pos, prefix_bits) {
+            Some(0) => decode_string(block, pos),
+            Some(index) => match self.table.get(index) {
+                Some((name, _)) => Some(name),
+                None => None,
+            },
+            None => None,
+        };
+        match name {
+            Some(name) => match decode_string(block, pos) {
+                Some(value) => Some((name, value)),
+                None => None,
+            },
+            None => None,
+        }
+    }
+}
+
+/// What compresses the header blocks a connection sends.
+pub struct Encoder {
+    priv table: Table,
+    /// A change to the size of the dynamic table, which the next header block is to tell of.
+    priv size_update: Option<uint>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            table: Table::new(),
+            size_update: None,
+        }
+    }
+
+    /// Make the dynamic table the size given, no larger than the decoder has said it may be.
+    pub fn set_max_table_size(&mut self, size: uint) {
+        if size != self.table.max_size {
+            self.table.set_max_size(size);
+            self.size_update = Some(size);
+        }
+    }
+
+    /// Encode a header block of the headers given, whose names must be in lower case. A header
+    /// found in the tables is sent as its index; any other is added to the dynamic table, save
+    /// Authorization and Proxy-Authorization, which are sent as never to be indexed, by this end or
+    /// any intermediary, lest they be guessed at by how well requests compress (RFC 7541, section
+    /// 7.1).
+    pub fn encode(&mut self, headers: &[(~str, ~str)]) -> ~[u8] {
+        let mut out = ~[];
+        match self.size_update.take() {
+            Some(size) => encode_integer(&mut out, 0x20, 5, size),
+            None => (),
+        }
+        for &(ref name, ref value) in headers.iter() {
+            let sensitive = match name.as_slice() {
+                "authorization" | "proxy-authorization" => true,
+                _ => false,
+            };
+            match self.table.find(*name, *value) {
+                Some((index, true)) if !sensitive => encode_integer(&mut out, 0x80, 7, index),
+                found => {
+                    let (first, prefix_bits) = if sensitive { (0x10, 4) } else { (0x40, 6) };
+                    match found {
+                        Some((index, _)) => encode_integer(&mut out, first, prefix_bits, index),
+                        None => {
+                            encode_integer(&mut out, first, prefix_bits, 0);
+                            encode_string(&mut out, name.as_bytes());
+                        },
+                    }
+                    encode_string(&mut out, value.as_bytes());
+                    if !sensitive {
+                        self.table.insert(name.clone(), value.clone());
+                    }
+                },
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http2::test_utils::hex;
+    use super::{Decoder, Encoder, HeaderList, encode_integer, decode_integer};
+
+    fn request(i: uint) -> HeaderList {
+        let mut headers = ~[(~":method", ~"GET"), (~":scheme", ~"http"), (~":path", ~"/"),
+                            (~":authority", ~"www.example.com")];
+        match i {
+            1 => headers.push((~"cache-control", ~"no-cache")),
+            2 => {
+                headers = ~[(~":method", ~"GET"), (~":scheme", ~"https"),
+                            (~":path", ~"/index.html"), (~":authority", ~"www.example.com"),
+                            (~"custom-key", ~"custom-value")];
+            },
+            _ => (),
+        }
+        headers
+    }
+
+    #[test]
+    fn test_integers() {
+        // RFC 7541, appendix C.1
+        let mut out = ~[];
+        encode_integer(&mut out, 0, 5, 10);
+        encode_integer(&mut out, 0, 5, 1337);
+        encode_integer(&mut out, 0, 8, 42);
+        assert_eq!(out, ~[10, 31, 154, 10, 42]);
+        let mut pos = 0;
+        assert_eq!(decode_integer(out, &mut pos, 5), Some(10));
+        assert_eq!(decode_integer(out, &mut pos, 5), Some(1337));
+        assert_eq!(decode_integer(out, &mut pos, 8), Some(42));
+        assert_eq!(decode_integer(out, &mut pos, 8), None);
+        let mut pos = 0;
+        assert_eq!(decode_integer([31, 154], &mut pos, 5), None);
+        let mut pos = 0;
+        assert_eq!(decode_integer([31, 255, 255, 255, 255, 1], &mut pos, 5), None);
+    }
+
+    #[test]
+    fn test_decode_without_huffman() {
+        // RFC 7541, appendix C.3
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d")),
+                   Some(request(0)));
+        assert_eq!(decoder.decode(hex("8286 84be 5808 6e6f 2d63 6163 6865")), Some(request(1)));
+        assert_eq!(decoder.decode(hex("8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f
+                                       6d2d 7661 6c75 65")),
+                   Some(request(2)));
+        // The newest first, from 62
+        assert_eq!(decoder.decode([0xBE, 0xBF, 0xC0]),
+                   Some(~[(~"custom-key", ~"custom-value"), (~"cache-control", ~"no-cache"),
+                          (~":authority", ~"www.example.com")]));
+        assert_eq!(decoder.decode([0xC1]), None);
+    }
+
+    #[test]
+    fn test_encode_with_huffman() {
+        // RFC 7541, appendix C.4; and decoding that back
+        let blocks = [hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"),
+                      hex("8286 84be 5886 a8eb 1064 9cbf"),
+                      hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf")];
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(&encoder.encode(request(i)), block);
+            assert_eq!(decoder.decode(block.as_slice()), Some(request(i)));
+        }
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        // Room for one of these, not two
+        encoder.set_max_table_size(60);
+        let first = ~[(~"x-a", ~"1")];
+        let second = ~[(~"x-b", ~"2")];
+        let block = encoder.encode(first);
+        assert_eq!(block[0], 0x3F);
+        assert_eq!(decoder.decode(block), Some(first.clone()));
+        assert_eq!(decoder.decode(encoder.encode(second)), Some(second.clone()));
+        // x-b is now at 62, and x-a gone
+        assert_eq!(decoder.decode([0xBE]), Some(second));
+        assert_eq!(decoder.decode([0xBF]), None);
+    }
+
+    #[test]
+    fn test_sensitive_headers() {
+        let mut encoder = Encoder::new();
+        let headers = ~[(~"authorization", ~"Basic c2VjcmV0")];
+        let block = encoder.encode(headers);
+        // Never indexed, with the name of static entry 23
+        assert_eq!(block[0], 0x1F);
+        assert_eq!(block[1], 23 - 15);
+        assert_eq!(Decoder::new().decode(block), Some(headers.clone()));
+        // Nor is it in the table for next time
+        assert_eq!(encoder.encode(headers), block);
+    }
+
+    #[test]
+    fn test_invalid_blocks() {
+        let mut decoder = Decoder::new();
+        // Index 0, and an index past the end of the tables
+        assert_eq!(decoder.decode([0x80]), None);
+        assert_eq!(decoder.decode([0xBE]), None);
+        // A string running past the end
+        assert_eq!(decoder.decode([0x40, 0x05, 'a' as u8]), None);
+        // A size update after a header, and one larger than allowed
+        assert_eq!(decoder.decode([0x82, 0x20]), None);
+        assert_eq!(decoder.decode([0x3F, 0xE2, 0x1F]), None);
+        assert_eq!(decoder.decode([0x3F, 0xE1, 0x1F]), Some(~[]));
+    }
+}
@@ -0,0 +1,79 @@
+/*!
+
+HTTP/2 (RFC 7540): the same requests and responses as HTTP/1.1, sent as binary frames, with their
+headers compressed (HPACK, RFC 7541), on streams of their own, many of which may share one
+connection at a time.
+
+`frame` reads and writes the frames, `hpack` compresses and decompresses header blocks, and
+`connection::serve` serves a whole connection, handing each stream's request to a `Server`'s
+handler as it would be handed an HTTP/1.1 one. A request's version is `(2, 0)`.
+
+A server speaks HTTP/2 with clients which choose it in the TLS handshake, by ALPN, if
+`Config.http2` is set; without TLS, with those which ask to upgrade the connection to it (`h2c`).
+
+*/
+
+pub use self::connection::{serve, serve_upgraded, upgrade_settings};
+
+pub mod connection;
+pub mod frame;
+pub mod hpack;
+mod huffman;
+
+/// The name of the protocol in ALPN, over TLS.
+pub static ALPN_PROTOCOL_ID: &'static str = "h2";
+
+/// The name of the protocol in the Upgrade header, without TLS.
+pub static CLEARTEXT_PROTOCOL_ID: &'static str = "h2c";
+
+/// What a client sends first on a connection, before its first SETTINGS frame (RFC 7540, section
+/// 3.5). It is meant to look like an HTTP/1.1 request which a server of HTTP/1.1 won't serve.
+pub static PREFACE: &'static [u8] = bytes!("PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+
+/// The error codes of RST_STREAM and GOAWAY (RFC 7540, section 7).
+pub static NO_ERROR: u32 = 0x0;
+pub static PROTOCOL_ERROR: u32 = 0x1;
+pub static INTERNAL_ERROR: u32 = 0x2;
+pub static FLOW_CONTROL_ERROR: u32 = 0x3;
+pub static SETTINGS_TIMEOUT: u32 = 0x4;
+pub static STREAM_CLOSED: u32 = 0x5;
+pub static FRAME_SIZE_ERROR: u32 = 0x6;
+pub static REFUSED_STREAM: u32 = 0x7;
+pub static CANCEL: u32 = 0x8;
+pub static COMPRESSION_ERROR: u32 = 0x9;
+pub static CONNECT_ERROR: u32 = 0xa;
+pub static ENHANCE_YOUR_CALM: u32 = 0xb;
+pub static INADEQUATE_SECURITY: u32 = 0xc;
+pub static HTTP_1_1_REQUIRED: u32 = 0xd;
+
+/// The settings of SETTINGS frames (RFC 7540, section 6.5.2).
+pub static SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub static SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub static SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub static SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub static SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub static SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// The flow-control window of a stream, and of the connection, at first.
+pub static DEFAULT_WINDOW_SIZE: u32 = 65535;
+
+/// The largest a window may grow to.
+pub static MAX_WINDOW_SIZE: u32 = 0x7FFFFFFF;
+
+/// The longest a frame's payload may be until the receiver says otherwise, and the least it may
+/// say.
+pub static DEFAULT_MAX_FRAME_SIZE: uint = 16384;
+
+/// The longest a frame's payload can ever be, as its length has 24 bits.
+pub static MAX_FRAME_SIZE: uint = 0xFFFFFF;
+
+#[cfg(test)]
+pub mod test_utils {
+    /// The bytes written in hexadecimal, as in the examples of the RFCs, spaces and all.
+    pub fn hex(s: &str) -> ~[u8] {
+        let digits: ~str = s.iter().filter(|c| !c.is_whitespace()).collect();
+        range(0, digits.len() / 2).map(|i| {
+            FromStrRadix::from_str_radix(digits.slice(i * 2, i * 2 + 2), 16).unwrap()
+        }).collect()
+    }
+}
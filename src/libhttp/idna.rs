@@ -0,0 +1,328 @@
+//! Internationalised domain names (IDNA, RFC 5891): host names with characters beyond ASCII,
+//! which are looked up in DNS, and sent in the Host header, in an ASCII form, each label of which
+//! that isn't ASCII is written as `xn--` followed by its Punycode (RFC 3492).
+//!
+//! `UrlHost` gives the ASCII and Unicode forms of the host of a URL. The labels are not mapped
+//! (nameprep), but for ASCII letters being put in lower case, so a label beyond ASCII had best be
+//! given in the form in which it was registered, as it usually is.
+
+use std::ascii::{AsciiCast, StrAsciiExt};
+use extra::url::Url;
+
+/// The prefix of a label in the ASCII form which is the Punycode of one beyond ASCII.
+pub static ACE_PREFIX: &'static str = "xn--";
+
+/// The longest a label may be, in its ASCII form (RFC 1034, section 3.1).
+static MAX_LABEL_LEN: uint = 63;
+
+// The parameters of Punycode (RFC 3492, section 5)
+static BASE: u64 = 36;
+static T_MIN: u64 = 1;
+static T_MAX: u64 = 26;
+static SKEW: u64 = 38;
+static DAMP: u64 = 700;
+static INITIAL_BIAS: u64 = 72;
+static INITIAL_N: u64 = 0x80;
+
+/// The greatest a number in Punycode may be, as the RFC has it; anything larger is an overflow.
+static MAX_INT: u64 = 0xFFFFFFFF;
+
+/// The ASCII and Unicode forms of the host of a URL.
+pub trait UrlHost {
+    /// The host as it is to be looked up and sent in the Host header: each label which isn't
+    /// ASCII in Punycode (see `domain_to_ascii`), or, should that not be possible, as the URL has
+    /// it. An IP address, and a host which is ASCII already, are left as they are.
+    fn ascii_host(&self) -> ~str;
+
+    /// The host as it is to be shown to a person: each label in Punycode decoded (see
+    /// `domain_to_unicode`).
+    fn unicode_host(&self) -> ~str;
+}
+
+impl UrlHost for Url {
+    fn ascii_host(&self) -> ~str {
+        if self.host.as_slice().is_ascii() {
+            return self.host.clone();
+        }
+        match domain_to_ascii(self.host.as_slice()) {
+            Some(host) => host,
+            None => self.host.clone(),
+        }
+    }
+
+    fn unicode_host(&self) -> ~str {
+        domain_to_unicode(self.host.as_slice())
+    }
+}
+
+/// The ASCII form of a domain name: each label which isn't ASCII is put in Punycode, with the ACE
+/// prefix, and each which is has its letters put in lower case. `None` is returned if a label is
+/// empty (but for the root label, after a final dot), or too long, or begins with the ACE prefix
+/// but isn't ASCII.
+///
+/// # Examples
+///
+/// ~~~ .{rust}
+/// assert_eq!(domain_to_ascii("Bücher.example"), Some(~"xn--bcher-kva.example"));
+/// ~~~
+pub fn domain_to_ascii(domain: &str) -> Option<~str> {
+    let labels: ~[&str] = domain.split_iter('.').collect();
+    let mut ascii = ~[];
+    for (i, &label) in labels.iter().enumerate() {
+        let label = if label.is_ascii() {
+            label.to_ascii_lower()
+        } else {
+            let lower: ~str = label.iter().map(|c| if c.is_ascii() {
+                c.to_ascii().to_lower().to_char()
+            } else {
+                c
+            }).collect();
+            if lower.starts_with(ACE_PREFIX) {
+                return None;
+            }
+            match punycode_encode(lower.as_slice()) {
+                Some(encoded) => format!("{}{}", ACE_PREFIX, encoded),
+                None => return None,
+            }
+        };
+        if (label.is_empty() && i + 1 != labels.len()) || label.len() > MAX_LABEL_LEN {
+            return None;
+        }
+        ascii.push(label);
+    }
+    Some(ascii.connect("."))
+}
+
+/// The Unicode form of a domain name: each label with the ACE prefix has its Punycode decoded; one
+/// which can't be is left as it is, as are the rest.
+///
+/// # Examples
+///
+/// ~~~ .{rust}
+/// assert_eq!(domain_to_unicode("xn--bcher-kva.example"), ~"bücher.example");
+/// ~~~
+pub fn domain_to_unicode(domain: &str) -> ~str {
+    let labels: ~[~str] = domain.split_iter('.').map(|label| {
+        let lower = label.to_ascii_lower();
+        if lower.starts_with(ACE_PREFIX) {
+            match punycode_decode(lower.slice_from(ACE_PREFIX.len())) {
+                Some(decoded) => decoded,
+                None => label.to_owned(),
+            }
+        } else {
+            label.to_owned()
+        }
+    }).collect();
+    labels.connect(".")
+}
+
+/// Encode a string in Punycode (RFC 3492, section 6.3), without any prefix. `None` is returned
+/// should it overflow, which only a string far too long to be a label can.
+pub fn punycode_encode(input: &str) -> Option<~str> {
+    let input: ~[u64] = input.iter().map(|c| c as u64).collect();
+    let mut output: ~str = input.iter().filter(|&&c| c < INITIAL_N)
+                                .map(|&c| c as u8 as char).collect();
+    let basic_len = output.len() as u64;
+    if basic_len > 0 {
+        output.push_char('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta = 0u64;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic_len;
+    while handled < input.len() as u64 {
+        // The least code point not yet handled
+        let m = input.iter().map(|&c| c).filter(|&c| c >= n).min().unwrap();
+        delta += (m - n) * (handled + 1);
+        if delta > MAX_INT {
+            return None;
+        }
+        n = m;
+        for &c in input.iter() {
+            if c < n {
+                delta += 1;
+                if delta > MAX_INT {
+                    return None;
+                }
+            } else if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push_char(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push_char(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic_len);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Some(output)
+}
+
+/// Decode a string in Punycode (RFC 3492, section 6.2), without any prefix. `None` is returned if
+/// it isn't valid Punycode.
+pub fn punycode_decode(input: &str) -> Option<~str> {
+    if !input.is_ascii() {
+        return None;
+    }
+    // The basic code points come first, ended by the last delimiter, if there are any
+    let (mut output, encoded): (~[char], &str) = match input.rfind('-') {
+        Some(i) => (input.slice_to(i).iter().collect(), input.slice_from(i + 1)),
+        None => (~[], input),
+    };
+
+    let mut n = INITIAL_N;
+    let mut i = 0u64;
+    let mut bias = INITIAL_BIAS;
+    let mut bytes = encoded.byte_iter();
+    loop {
+        let old_i = i;
+        let mut w = 1u64;
+        let mut k = BASE;
+        let mut first = true;
+        loop {
+            let digit = match bytes.next() {
+                Some(b) => match decode_digit(b) {
+                    Some(digit) => digit,
+                    None => return None,
+                },
+                // The end of the input may only come between one number and the next
+                None if first => return Some(output.iter().map(|&c| c).collect()),
+                None => return None,
+            };
+            first = false;
+            i += digit * w;
+            if i > MAX_INT {
+                return None;
+            }
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w *= BASE - t;
+            if w > MAX_INT {
+                return None;
+            }
+            k += BASE;
+        }
+        let len = output.len() as u64 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n += i / len;
+        i %= len;
+        if n > 0x10FFFF || (n >= 0xD800 && n <= 0xDFFF) {
+            return None;
+        }
+        output.insert(i as uint, n as u32 as char);
+        i += 1;
+    }
+}
+
+/// The threshold for a digit in the position `k` (RFC 3492, section 6.2).
+#[inline]
+fn threshold(k: u64, bias: u64) -> u64 {
+    if k <= bias {
+        T_MIN
+    } else if k >= bias + T_MAX {
+        T_MAX
+    } else {
+        k - bias
+    }
+}
+
+/// Adapt the bias after a delta (RFC 3492, section 6.1).
+fn adapt(delta: u64, num_points: u64, first_time: bool) -> u64 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+#[inline]
+fn encode_digit(d: u64) -> char {
+    if d < 26 {
+        ('a' as u8 + d as u8) as char
+    } else {
+        ('0' as u8 + (d - 26) as u8) as char
+    }
+}
+
+#[inline]
+fn decode_digit(b: u8) -> Option<u64> {
+    match b as char {
+        'a'..'z' => Some((b - 'a' as u8) as u64),
+        'A'..'Z' => Some((b - 'A' as u8) as u64),
+        '0'..'9' => Some((b - '0' as u8) as u64 + 26),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use extra::url::Url;
+    use super::{UrlHost, domain_to_ascii, domain_to_unicode, punycode_encode, punycode_decode};
+
+    /// Some of the samples of RFC 3492, section 7.1.
+    static SAMPLES: &'static [(&'static str, &'static str)] = &[
+        ("bücher", "bcher-kva"),
+        ("münchen", "mnchen-3ya"),
+        ("例子", "fsqu00a"),
+        ("他们为什么不说中文", "ihqwcrb4cv8a8dqg056pqjye"),
+        ("ليهمابتكلموشعربي؟", "egbpdaj6bu4bxfgehfvwxn"),
+        ("3年B組金八先生", "3B-ww4c5e180e575a65lsy2b"),
+        ("abc", "abc-"),
+    ];
+
+    #[test]
+    fn test_punycode() {
+        for &(decoded, encoded) in SAMPLES.iter() {
+            assert_eq!(punycode_encode(decoded), Some(encoded.to_owned()));
+            assert_eq!(punycode_decode(encoded), Some(decoded.to_owned()));
+        }
+        assert_eq!(punycode_decode("bcher-kv"), None);
+        assert_eq!(punycode_decode("bcher-kva!"), None);
+        assert_eq!(punycode_decode("99999999999"), None);
+    }
+
+    #[test]
+    fn test_domain_to_ascii() {
+        assert_eq!(domain_to_ascii("Bücher.Example."), Some(~"xn--bcher-kva.example."));
+        assert_eq!(domain_to_ascii("例子.测试"), Some(~"xn--fsqu00a.xn--0zwm56d"));
+        assert_eq!(domain_to_ascii("www.example.com"), Some(~"www.example.com"));
+        assert_eq!(domain_to_ascii("bücher..example"), None);
+        assert_eq!(domain_to_ascii("xn--bücher.example"), None);
+        assert_eq!(domain_to_ascii(("a".repeat(60) + "ü").as_slice()), None);
+    }
+
+    #[test]
+    fn test_domain_to_unicode() {
+        assert_eq!(domain_to_unicode("www.XN--BCHER-KVA.example"), ~"www.bücher.example");
+        assert_eq!(domain_to_unicode("xn--fsqu00a.xn--0zwm56d"), ~"例子.测试");
+        assert_eq!(domain_to_unicode("xn--bcher-kv.example"), ~"xn--bcher-kv.example");
+    }
+
+    #[test]
+    fn test_url_host() {
+        let url: Url = FromStr::from_str("http://Bücher.example:8080/a").unwrap();
+        assert_eq!(url.ascii_host(), ~"xn--bcher-kva.example");
+        assert_eq!(url.unicode_host(), ~"Bücher.example");
+        let url: Url = FromStr::from_str("http://xn--bcher-kva.example/").unwrap();
+        assert_eq!(url.ascii_host(), ~"xn--bcher-kva.example");
+        assert_eq!(url.unicode_host(), ~"bücher.example");
+        let url: Url = FromStr::from_str("http://[::1]/").unwrap();
+        assert_eq!(url.ascii_host(), ~"[::1]");
+    }
+}
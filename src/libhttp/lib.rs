@@ -16,12 +16,27 @@
 extern mod extra;
 
 pub mod buffer;
+pub mod charset;
 pub mod client;
 pub mod common;
+pub mod compression;
+pub mod content_md5;
 pub mod server;
 pub mod method;
+pub mod mime;
+pub mod multipart;
 pub mod headers;
+pub mod http2;
+pub mod idna;
+pub mod network;
+pub mod percent_encoding;
 pub mod rfc2616;
+pub mod socket;
+pub mod spdy;
+pub mod ssl;
+pub mod transfer_coding;
+pub mod websocket;
+mod zlib;
 #[path = "generated/status.rs"]
 pub mod status;  // Getting an error? It's generated; use ``make`` or see the ``Makefile``
 
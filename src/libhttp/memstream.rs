@@ -4,7 +4,7 @@ use std::rt::io::{Reader, Writer, Seek, SeekStyle};
 use std::rt::io::mem::{MemReader, MemWriter};
 
 /// Writes to an owned, growable byte vector but also implements read with fail-on-call methods.
-struct MemWriterFakeStream(MemWriter);
+pub struct MemWriterFakeStream(MemWriter);
 
 impl MemWriterFakeStream {
     pub fn new() -> MemWriterFakeStream { MemWriterFakeStream(MemWriter::new()) }
@@ -100,4 +100,24 @@ impl Method {
             _         => ExtensionMethod(method.to_owned()),
         })
     }
+
+    /// Whether the method is idempotent (RFC 2616, §9.1.2): whether making the request several
+    /// times has the same effect as making it once, so that it may be retried. An extension
+    /// method is assumed not to be.
+    pub fn is_idempotent(&self) -> bool {
+        match *self {
+            Options | Get | Head | Put | Delete | Trace => true,
+            Post | Connect | Patch | ExtensionMethod(_) => false,
+        }
+    }
+
+    /// Whether the method is safe (RFC 2616, §9.1.1): whether it only retrieves, rather than
+    /// acting upon the resource, so that a cache need not take its responses as changing what it
+    /// has stored. An extension method is assumed not to be.
+    pub fn is_safe(&self) -> bool {
+        match *self {
+            Options | Get | Head | Trace => true,
+            Post | Put | Delete | Connect | Patch | ExtensionMethod(_) => false,
+        }
+    }
 }
@@ -0,0 +1,157 @@
+/*!
+
+The media types of files, looked up by their extensions, for the Content-Type with which they are
+sent.
+
+```rust
+assert_eq!(mime::from_extension("svg"), Some(MediaType(~"image", ~"svg+xml", ~[])));
+
+let mut types = MimeTypes::new();
+types.register("md", MediaType(~"text", ~"markdown", ~[(~"charset", ~"utf-8")]));
+```
+
+Extensions are compared ignoring case. `MimeTypes` adds what it is told to the table of common
+types which `from_extension` knows, or replaces what the table says.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::hashmap::HashMap;
+use headers::content_type::MediaType;
+
+/// The common types, by extension.
+static TYPES: &'static [(&'static str, &'static str)] = &[
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("md", "text/markdown"),
+    ("mjs", "application/javascript"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webmanifest", "application/manifest+json"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xhtml", "application/xhtml+xml"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+/// The media type of files with the extension given (without the dot), if it is a common one.
+pub fn from_extension(extension: &str) -> Option<MediaType> {
+    let extension = extension.to_ascii_lower();
+    for &(ext, media_type) in TYPES.iter() {
+        if ext == extension.as_slice() {
+            let slash = media_type.find('/').unwrap();
+            return Some(MediaType(media_type.slice_to(slash).to_owned(),
+                                  media_type.slice_from(slash + 1).to_owned(), ~[]));
+        }
+    }
+    None
+}
+
+/// The media type of the file, by its extension, if it has a common one.
+pub fn from_path(path: &Path) -> Option<MediaType> {
+    match path.extension_str() {
+        Some(extension) => from_extension(extension),
+        None => None,
+    }
+}
+
+/// The table of common types, with any extensions which have been registered in place of or as
+/// well as them.
+#[deriving(Clone)]
+pub struct MimeTypes {
+    priv overrides: HashMap<~str, MediaType>,
+}
+
+impl MimeTypes {
+    /// The common types and nothing else.
+    pub fn new() -> MimeTypes {
+        MimeTypes { overrides: HashMap::new() }
+    }
+
+    /// Give files with the extension (without the dot) the media type, in place of any they had.
+    pub fn register(&mut self, extension: &str, media_type: MediaType) {
+        self.overrides.insert(extension.to_ascii_lower(), media_type);
+    }
+
+    /// The media type of files with the extension, if it has been registered or is common.
+    pub fn from_extension(&self, extension: &str) -> Option<MediaType> {
+        match self.overrides.find(&extension.to_ascii_lower()) {
+            Some(media_type) => Some(media_type.clone()),
+            None => from_extension(extension),
+        }
+    }
+
+    /// The media type of the file, by its extension, if it has been registered or is common.
+    pub fn from_path(&self, path: &Path) -> Option<MediaType> {
+        match path.extension_str() {
+            Some(extension) => self.from_extension(extension),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use headers::content_type::MediaType;
+    use super::{TYPES, MimeTypes, from_extension, from_path};
+
+    #[test]
+    fn test_table() {
+        // The table must be valid, and in order, for it to be kept tidy.
+        let mut last = "";
+        for &(ext, media_type) in TYPES.iter() {
+            assert!(ext > last, "{} is out of order", ext);
+            assert!(MediaType::parse(media_type).is_some());
+            last = ext;
+        }
+    }
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(from_extension("html"), Some(MediaType(~"text", ~"html", ~[])));
+        assert_eq!(from_extension("WASM"), Some(MediaType(~"application", ~"wasm", ~[])));
+        assert_eq!(from_extension("unknown"), None);
+        assert_eq!(from_path(&Path::new("a/b.tar.gz")),
+                   Some(MediaType(~"application", ~"gzip", ~[])));
+        assert_eq!(from_path(&Path::new("README")), None);
+    }
+
+    #[test]
+    fn test_mime_types() {
+        let mut types = MimeTypes::new();
+        types.register("JS", MediaType(~"text", ~"javascript", ~[]));
+        types.register("rs", MediaType(~"text", ~"x-rust", ~[]));
+        assert_eq!(types.from_extension("js"), Some(MediaType(~"text", ~"javascript", ~[])));
+        assert_eq!(types.from_path(&Path::new("main.rs")),
+                   Some(MediaType(~"text", ~"x-rust", ~[])));
+        assert_eq!(types.from_extension("css"), Some(MediaType(~"text", ~"css", ~[])));
+        assert_eq!(types.from_extension("unknown"), None);
+    }
+}
@@ -0,0 +1,636 @@
+/*!
+
+Reading and writing of `multipart/*` bodies (RFC 2046, section 5.1), as are most commonly met in
+file uploads in `multipart/form-data` (RFC 2388).
+
+A `MultipartReader` reads the parts one at a time from any `Reader`, holding only a small buffer
+rather than the whole body in memory. `next_part` moves on to the next part and returns its headers;
+the reader then reads that part's body, stopping at the end of the part:
+
+```rust
+let mut parts = match request.multipart() {
+    Some(parts) => parts,
+    None => return bad_request(),
+};
+loop {
+    let headers = match parts.next_part() {
+        Some(headers) => headers,
+        None => break,
+    };
+    match (headers.name(), headers.filename()) {
+        (Some(~"upload"), Some(filename)) => save_file(filename, &mut parts),
+        (Some(name), None) => fields.push((name, str::from_utf8(parts.read_to_end()))),
+        _ => (),
+    }
+}
+```
+
+A `MultipartWriter` composes such a body, for a client to upload files with. The length of the body
+is needed before it can be sent, so write it to memory first:
+
+```rust
+let mut parts = MultipartWriter::new(MemWriter::new());
+parts.write_field("submit-name", "Larry");
+parts.write_file("upload", "notes.txt", Some(MediaType(~"text", ~"plain", ~[])), &mut file);
+request.headers.content_type = Some(parts.content_type());
+let body = parts.finish().inner();
+request.headers.content_length = Some(body.len());
+request.write(body);
+```
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::cmp::min;
+use std::str;
+use std::vec;
+use std::rand::{Rng, task_rng};
+use std::rt::io::{Reader, Writer};
+use headers;
+use headers::content_type::MediaType;
+use headers::serialization_utils::{maybe_unquote_string, push_quoted_string};
+use percent_encoding::bytes_to_str;
+
+/// How much of the body is held in memory at a time.
+static BUF_SIZE: uint = 0x2000;
+
+/// The boundary may be no more than 70 characters (RFC 2046, section 5.1.1).
+static MAX_BOUNDARY_LEN: uint = 70;
+
+/// The longest a line of the headers of a part may be.
+static MAX_HEADER_LINE_LEN: uint = 0x1000;
+
+/// The boundary delimiting the parts of a body of the given media type, or `None` if it is not a
+/// multipart type or it has no valid boundary.
+pub fn boundary(media_type: &MediaType) -> Option<~str> {
+    if !media_type.type_.eq_ignore_ascii_case("multipart") {
+        return None;
+    }
+    for &(ref name, ref value) in media_type.parameters.iter() {
+        if name.eq_ignore_ascii_case("boundary") && !value.is_empty()
+                && value.len() <= MAX_BOUNDARY_LEN {
+            return Some(value.clone());
+        }
+    }
+    None
+}
+
+/// The headers of a part of a multipart body.
+#[deriving(Clone, Eq)]
+pub struct PartHeaders {
+    /// The header fields, as (name, value) pairs, in the order they came. Names are as they were
+    /// sent; use `get` to look them up without regard to case.
+    headers: ~[(~str, ~str)],
+}
+
+impl PartHeaders {
+    /// The value of the first header of the given name, ignoring case.
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a str> {
+        for &(ref n, ref v) in self.headers.iter() {
+            if n.eq_ignore_ascii_case(name) {
+                return Some(v.as_slice());
+            }
+        }
+        None
+    }
+
+    /// The Content-Type of the part; according to RFC 2046, `text/plain` should be assumed in its
+    /// absence.
+    pub fn content_type(&self) -> Option<MediaType> {
+        match self.get("Content-Type") {
+            Some(value) => headers::header_value_from_str(value),
+            None => None,
+        }
+    }
+
+    /// A parameter of the Content-Disposition header, such as `name` or `filename`.
+    pub fn disposition_parameter(&self, parameter: &str) -> Option<~str> {
+        let disposition = match self.get("Content-Disposition") {
+            Some(value) => value,
+            None => return None,
+        };
+        // The disposition type, then parameters, separated by semicolons outside quoted-strings
+        let items = split_outside_quotes(disposition, ';');
+        for item in items.iter().skip(1) {
+            let item = item.trim();
+            match item.find('=') {
+                Some(i) if item.slice_to(i).trim().eq_ignore_ascii_case(parameter) => {
+                    return maybe_unquote_string(item.slice_from(i + 1).trim());
+                },
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// The name of the form field of a `multipart/form-data` part.
+    pub fn name(&self) -> Option<~str> {
+        self.disposition_parameter("name")
+    }
+
+    /// The filename of a `multipart/form-data` part which is an uploaded file. This is what the
+    /// user agent says it was called; never use it as a path without sanitising it.
+    pub fn filename(&self) -> Option<~str> {
+        self.disposition_parameter("filename")
+    }
+}
+
+fn split_outside_quotes<'a>(s: &'a str, separator: char) -> ~[&'a str] {
+    let mut items = ~[];
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaping = false;
+    for (i, c) in s.char_offset_iter() {
+        if escaping {
+            escaping = false;
+        } else if in_quotes && c == '\\' {
+            escaping = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && c == separator {
+            items.push(s.slice(start, i));
+            start = i + 1;
+        }
+    }
+    items.push(s.slice_from(start));
+    items
+}
+
+#[deriving(Eq)]
+enum State {
+    /// Before the first boundary, in the preamble, which is to be ignored.
+    Preamble,
+    /// In the body of a part.
+    InPart,
+    /// The body of a part has been read up to the delimiter.
+    AtDelimiter,
+    /// The close delimiter has been reached, or the body was malformed.
+    Finished,
+}
+
+/// A reader of the parts of a multipart body. See the module documentation for an example.
+///
+/// As a `Reader`, it reads the body of the current part; at the end of the part it reaches
+/// end-of-file, until `next_part` is called.
+pub struct MultipartReader<R> {
+    priv reader: R,
+
+    /// CRLF, two hyphens and the boundary, which begin each delimiter line.
+    priv delimiter: ~[u8],
+
+    priv buf: ~[u8],
+    priv pos: uint,
+    priv len: uint,
+    priv reader_eof: bool,
+    priv state: State,
+}
+
+impl<R: Reader> MultipartReader<R> {
+    /// Start reading a multipart body with the given boundary (see `boundary`).
+    pub fn new(reader: R, boundary: &str) -> MultipartReader<R> {
+        let mut delimiter = bytes!("\r\n--").to_owned();
+        delimiter.push_all(boundary.as_bytes());
+        let mut buf = vec::from_elem(BUF_SIZE + delimiter.len(), 0u8);
+        // The first delimiter needn't come after a CRLF, as it may begin the body; pretending that
+        // there was one before the body means the first delimiter needs no special treatment.
+        buf[0] = '\r' as u8;
+        buf[1] = '\n' as u8;
+        MultipartReader {
+            reader: reader,
+            delimiter: delimiter,
+            buf: buf,
+            pos: 0,
+            len: 2,
+            reader_eof: false,
+            state: Preamble,
+        }
+    }
+
+    /// Move to the next part, skipping whatever remains of the current one, and return its
+    /// headers. `None` is returned at the end of the body, or if it is malformed.
+    pub fn next_part(&mut self) -> Option<PartHeaders> {
+        // Skip to the delimiter
+        loop {
+            match self.state {
+                // Reaching the delimiter (or the end of the body) changes the state
+                Preamble | InPart => { self.read_until_delimiter(None); },
+                AtDelimiter => break,
+                Finished => return None,
+            }
+        }
+        let delimiter_len = self.delimiter.len();
+        self.consume(delimiter_len);
+
+        // The close delimiter has two hyphens after the boundary; anything after it is epilogue,
+        // which is to be ignored.
+        if !self.ensure_buffered(2) {
+            self.state = Finished;
+            return None;
+        }
+        if self.buf[self.pos] == '-' as u8 && self.buf[self.pos + 1] == '-' as u8 {
+            self.state = Finished;
+            return None;
+        }
+
+        // Skip any transport padding (whitespace) to the end of the delimiter line, then read the
+        // headers, which end at a blank line.
+        match self.read_line() {
+            Some(ref line) if line.trim().is_empty() => (),
+            _ => {
+                self.state = Finished;
+                return None;
+            },
+        }
+        let mut headers = ~[];
+        loop {
+            let line = match self.read_line() {
+                Some(line) => line,
+                None => {
+                    self.state = Finished;
+                    return None;
+                },
+            };
+            if line.is_empty() {
+                break;
+            }
+            if (line[0] == ' ' as u8 || line[0] == '\t' as u8) && !headers.is_empty() {
+                // A continuation line, folded onto the previous header
+                let last = headers.len() - 1;
+                match headers[last] {
+                    (_, ref mut value) => {
+                        value.push_char(' ');
+                        value.push_str(line.trim());
+                    },
+                }
+                continue;
+            }
+            match line.find(':') {
+                Some(i) => headers.push((line.slice_to(i).trim().to_owned(),
+                                         line.slice_from(i + 1).trim().to_owned())),
+                None => {
+                    self.state = Finished;
+                    return None;
+                },
+            }
+        }
+        self.state = InPart;
+        Some(PartHeaders { headers: headers })
+    }
+
+    /// Read body data preceding the next delimiter into `out` (or discard it, if `out` is `None`),
+    /// returning how many bytes there were. `None` is returned on reaching the delimiter (when the
+    /// state becomes `AtDelimiter`) or the end of a malformed body.
+    fn read_until_delimiter(&mut self, out: Option<&mut [u8]>) -> Option<uint> {
+        let delimiter_len = self.delimiter.len();
+        if !self.ensure_buffered(delimiter_len) && self.len - self.pos == 0 {
+            // The body ended without a close delimiter.
+            self.state = Finished;
+            return None;
+        }
+        let available = match self.find_delimiter() {
+            Some(0) => {
+                self.state = AtDelimiter;
+                return None;
+            },
+            Some(i) => i,
+            // Everything but what could be the start of a delimiter; a body which ended without a
+            // delimiter is yielded in full in the hope of salvaging something.
+            None if self.reader_eof => self.len - self.pos,
+            None => self.len - self.pos - (delimiter_len - 1),
+        };
+        let n = match out {
+            Some(out) => {
+                let n = min(available, out.len());
+                vec::bytes::copy_memory(out, self.buf.slice(self.pos, self.pos + n), n);
+                n
+            },
+            None => available,
+        };
+        self.consume(n);
+        Some(n)
+    }
+
+    /// The position of the delimiter in the buffered data, relative to `pos`, if it's there.
+    fn find_delimiter(&self) -> Option<uint> {
+        let data = self.buf.slice(self.pos, self.len);
+        let delimiter = self.delimiter.as_slice();
+        if data.len() < delimiter.len() {
+            return None;
+        }
+        for i in range(0, data.len() - delimiter.len() + 1) {
+            if data.slice(i, i + delimiter.len()) == delimiter {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Read a line, terminated by CRLF or LF (which is not included).
+    fn read_line(&mut self) -> Option<~str> {
+        let mut line = ~[];
+        loop {
+            if !self.ensure_buffered(1) {
+                return None;
+            }
+            let b = self.buf[self.pos];
+            self.consume(1);
+            if b == '\n' as u8 {
+                if line.len() > 0 && line[line.len() - 1] == '\r' as u8 {
+                    line.pop();
+                }
+                return Some(bytes_to_str(line));
+            }
+            if line.len() == MAX_HEADER_LINE_LEN {
+                return None;
+            }
+            line.push(b);
+        }
+    }
+
+    #[inline]
+    fn consume(&mut self, n: uint) {
+        self.pos += n;
+    }
+
+    /// Try to have at least `n` bytes buffered, reading more from the wrapped reader if necessary;
+    /// returns false if there aren't enough before end-of-file.
+    fn ensure_buffered(&mut self, n: uint) -> bool {
+        while self.len - self.pos < n {
+            if self.reader_eof {
+                return false;
+            }
+            if self.pos > 0 {
+                // Move what's left to the start of the buffer to make room
+                let remaining = self.len - self.pos;
+                for i in range(0, remaining) {
+                    self.buf[i] = self.buf[self.pos + i];
+                }
+                self.pos = 0;
+                self.len = remaining;
+            }
+            match self.reader.read(self.buf.mut_slice_from(self.len)) {
+                Some(n) => self.len += n,
+                None => self.reader_eof = true,
+            }
+        }
+        true
+    }
+}
+
+impl<R: Reader> Reader for MultipartReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        if self.state != InPart {
+            return None;
+        }
+        self.read_until_delimiter(Some(buf))
+    }
+
+    fn eof(&mut self) -> bool {
+        self.state != InPart
+    }
+}
+
+/// The length of the random part of a generated boundary.
+static BOUNDARY_RANDOM_LEN: uint = 32;
+
+/// A writer of a multipart body, most often `multipart/form-data` to submit a form with file
+/// uploads. See the module documentation for an example.
+///
+/// Each part is written with its delimiter and headers; `finish` must be called after the last part
+/// to write the close delimiter.
+pub struct MultipartWriter<W> {
+    priv writer: W,
+
+    /// The boundary delimiting the parts.
+    priv boundary: ~str,
+}
+
+impl<W: Writer> MultipartWriter<W> {
+    /// Start writing a multipart body with a random boundary, long enough that it's vanishingly
+    /// unlikely to occur in any of the parts, as it mustn't.
+    pub fn new(writer: W) -> MultipartWriter<W> {
+        let mut boundary = ~"rust-http-";
+        boundary.push_str(task_rng().gen_ascii_str(BOUNDARY_RANDOM_LEN));
+        MultipartWriter::with_boundary(writer, boundary)
+    }
+
+    /// Start writing a multipart body with the boundary given, which must be no more than 70
+    /// characters, and should be made of letters, digits and `'()+_,-./:=?` (RFC 2046, section
+    /// 5.1.1). It is up to you to be sure that it doesn't occur in any of the parts.
+    pub fn with_boundary(writer: W, boundary: ~str) -> MultipartWriter<W> {
+        assert!(!boundary.is_empty() && boundary.len() <= MAX_BOUNDARY_LEN);
+        MultipartWriter {
+            writer: writer,
+            boundary: boundary,
+        }
+    }
+
+    /// The boundary delimiting the parts.
+    pub fn boundary<'a>(&'a self) -> &'a str {
+        self.boundary.as_slice()
+    }
+
+    /// The Content-Type of the body: `multipart/form-data` with the boundary.
+    pub fn content_type(&self) -> MediaType {
+        MediaType(~"multipart", ~"form-data", ~[(~"boundary", self.boundary.clone())])
+    }
+
+    /// Write the delimiter and headers of a part; its body is then to be written to the wrapped
+    /// writer. Header values must not contain CR or LF.
+    pub fn write_part_headers(&mut self, headers: &PartHeaders) {
+        self.writer.write(bytes!("--"));
+        self.writer.write(self.boundary.as_bytes());
+        self.writer.write(bytes!("\r\n"));
+        for &(ref name, ref value) in headers.headers.iter() {
+            self.writer.write(name.as_bytes());
+            self.writer.write(bytes!(": "));
+            self.writer.write(value.as_bytes());
+            self.writer.write(bytes!("\r\n"));
+        }
+        self.writer.write(bytes!("\r\n"));
+    }
+
+    /// Write a part holding the value of a form field.
+    pub fn write_field(&mut self, name: &str, value: &str) {
+        let headers = PartHeaders {
+            headers: ~[(~"Content-Disposition", form_data_disposition(name, None))],
+        };
+        self.write_part_headers(&headers);
+        self.writer.write(value.as_bytes());
+        self.writer.write(bytes!("\r\n"));
+    }
+
+    /// Write a part holding an uploaded file, read to its end from `reader`. Lacking a content
+    /// type, `application/octet-stream` is given.
+    pub fn write_file<R: Reader>(&mut self, name: &str, filename: &str,
+                                 content_type: Option<MediaType>, reader: &mut R) {
+        let content_type = match content_type {
+            Some(content_type) => content_type,
+            None => MediaType(~"application", ~"octet-stream", ~[]),
+        };
+        let headers = PartHeaders {
+            headers: ~[(~"Content-Disposition", form_data_disposition(name, Some(filename))),
+                       (~"Content-Type", content_type.to_str())],
+        };
+        self.write_part_headers(&headers);
+        let mut buf = vec::from_elem(BUF_SIZE, 0u8);
+        loop {
+            match reader.read(buf) {
+                Some(n) => self.writer.write(buf.slice_to(n)),
+                None => break,
+            }
+        }
+        self.writer.write(bytes!("\r\n"));
+    }
+
+    /// Write the close delimiter which ends the body, and return the wrapped writer.
+    pub fn finish(self) -> W {
+        let MultipartWriter { writer: mut writer, boundary: boundary } = self;
+        writer.write(bytes!("--"));
+        writer.write(boundary.as_bytes());
+        writer.write(bytes!("--\r\n"));
+        writer
+    }
+}
+
+/// The Content-Disposition of a part of a `multipart/form-data` body.
+fn form_data_disposition(name: &str, filename: Option<&str>) -> ~str {
+    let s = push_quoted_string(~"form-data; name=", name);
+    match filename {
+        Some(filename) => push_quoted_string(s + "; filename=", filename),
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::{Reader, Decorator};
+    use std::rt::io::extensions::ReaderUtil;
+    use std::rt::io::mem::{MemReader, MemWriter};
+    use std::str;
+    use headers::content_type::MediaType;
+    use super::{MultipartReader, MultipartWriter, PartHeaders, boundary};
+
+    /// A reader which only gives out a byte at a time, to make sure delimiters split across reads
+    /// are found.
+    struct Trickle(MemReader);
+
+    impl Reader for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> Option<uint> { (**self).read(buf.mut_slice_to(1)) }
+        fn eof(&mut self) -> bool { (**self).eof() }
+    }
+
+    static BODY: &'static [u8] = bytes!("\
+This is the preamble.\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"submit-name\"\r\n\
+\r\n\
+Larry\r\n\
+--AaB03x  \r\n\
+Content-Disposition: form-data; name=\"files\"; filename=\"file;1.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+--AaB03 is not quite\r\nthe boundary\r\n\r\n\
+--AaB03x--\r\n\
+This is the epilogue.");
+
+    fn check_parts<R: Reader>(mut parts: MultipartReader<R>) {
+        let headers = parts.next_part().unwrap();
+        assert_eq!(headers.name(), Some(~"submit-name"));
+        assert_eq!(headers.filename(), None);
+        assert_eq!(headers.content_type(), None);
+        assert_eq!(parts.read_to_end(), bytes!("Larry").to_owned());
+
+        let headers = parts.next_part().unwrap();
+        assert_eq!(headers.name(), Some(~"files"));
+        assert_eq!(headers.filename(), Some(~"file;1.txt"));
+        assert_eq!(headers.content_type(), Some(MediaType(~"text", ~"plain", ~[])));
+        assert_eq!(parts.read_to_end(),
+                   bytes!("--AaB03 is not quite\r\nthe boundary\r\n").to_owned());
+        assert!(parts.eof());
+
+        assert_eq!(parts.next_part(), None);
+        assert_eq!(parts.next_part(), None);
+    }
+
+    #[test]
+    fn test_parts() {
+        check_parts(MultipartReader::new(MemReader::new(BODY.to_owned()), "AaB03x"));
+    }
+
+    #[test]
+    fn test_parts_trickled() {
+        check_parts(MultipartReader::new(Trickle(MemReader::new(BODY.to_owned())), "AaB03x"));
+    }
+
+    #[test]
+    fn test_skipping_parts() {
+        let mut parts = MultipartReader::new(MemReader::new(BODY.to_owned()), "AaB03x");
+        assert!(parts.next_part().is_some());
+        let headers = parts.next_part().unwrap();
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert!(parts.next_part().is_none());
+    }
+
+    #[test]
+    fn test_no_preamble_and_malformed() {
+        let body = bytes!("--b\r\nA: 1\r\n\r\nx\r\n--b--").to_owned();
+        let mut parts = MultipartReader::new(MemReader::new(body), "b");
+        assert_eq!(parts.next_part(), Some(PartHeaders { headers: ~[(~"A", ~"1")] }));
+        assert_eq!(parts.read_to_end(), bytes!("x").to_owned());
+        assert_eq!(parts.next_part(), None);
+
+        // No close delimiter: the part is all there is
+        let body = bytes!("--b\r\n\r\ntruncated").to_owned();
+        let mut parts = MultipartReader::new(MemReader::new(body), "b");
+        assert!(parts.next_part().is_some());
+        assert_eq!(parts.read_to_end(), bytes!("truncated").to_owned());
+        assert_eq!(parts.next_part(), None);
+
+        let body = bytes!("no boundary here").to_owned();
+        let mut parts = MultipartReader::new(MemReader::new(body), "b");
+        assert_eq!(parts.next_part(), None);
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut parts = MultipartWriter::with_boundary(MemWriter::new(), ~"AaB03x");
+        assert_eq!(parts.content_type().to_str(), ~"multipart/form-data;boundary=AaB03x");
+        parts.write_field("submit-name", "Larry");
+        parts.write_file("files", "a \"quoted\" name.txt", None,
+                         &mut MemReader::new(bytes!("contents").to_owned()));
+        let body = parts.finish().inner();
+        assert_eq!(str::from_utf8(body), ~"\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"submit-name\"\r\n\
+\r\n\
+Larry\r\n\
+--AaB03x\r\n\
+Content-Disposition: form-data; name=\"files\"; filename=\"a \\\"quoted\\\" name.txt\"\r\n\
+Content-Type: application/octet-stream\r\n\
+\r\n\
+contents\r\n\
+--AaB03x--\r\n");
+
+        // And it reads back as it was written
+        let mut parts = MultipartReader::new(MemReader::new(body), "AaB03x");
+        assert_eq!(parts.next_part().unwrap().name(), Some(~"submit-name"));
+        assert_eq!(parts.read_to_end(), bytes!("Larry").to_owned());
+        let headers = parts.next_part().unwrap();
+        assert_eq!(headers.filename(), Some(~"a \"quoted\" name.txt"));
+        assert_eq!(parts.read_to_end(), bytes!("contents").to_owned());
+        assert_eq!(parts.next_part(), None);
+
+        let parts = MultipartWriter::new(MemWriter::new());
+        assert!(parts.boundary().len() <= 70);
+        assert!(parts.boundary() != MultipartWriter::new(MemWriter::new()).boundary());
+    }
+
+    #[test]
+    fn test_boundary() {
+        let media_type = MediaType(~"multipart", ~"form-data", ~[(~"Boundary", ~"AaB03x")]);
+        assert_eq!(boundary(&media_type), Some(~"AaB03x"));
+        assert_eq!(boundary(&MediaType(~"multipart", ~"mixed", ~[])), None);
+        assert_eq!(boundary(&MediaType(~"text", ~"plain", ~[(~"boundary", ~"x")])), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! The network connections which HTTP is spoken over.
+
+use std::rt::io::{Reader, Writer};
+use std::rt::io::net::ip::SocketAddr;
+use std::rt::io::net::tcp::TcpStream;
+use buffer::BufferedStream;
+use memstream::MemWriterFakeStream;
+use socket::{Socket, RawFile};
+use ssl::SslStream;
+
+pub type BufNetworkStream = BufferedStream<NetworkStream>;
+
+/// A connection, which may or may not be protected by TLS.
+pub enum NetworkStream {
+    /// A plain TCP connection (the `http` scheme).
+    NormalStream(TcpStream),
+    /// A TLS connection over TCP (the `https` scheme).
+    SslProtectedStream(SslStream<TcpStream>),
+    /// A plain connection on a socket of our own (see `socket`), as a server accepts, or one to a
+    /// Unix domain socket.
+    SocketStream(Socket),
+    /// A TLS connection on a socket of our own.
+    SslSocketStream(SslStream<Socket>),
+    /// No connection, but somewhere in memory to write a response to, to be sent some other way
+    /// (in the frames of HTTP/2, say). There is nothing to read from it.
+    MemoryStream(MemWriterFakeStream),
+}
+
+impl NetworkStream {
+    /// The address of the remote end of the connection, if it has an IP address.
+    pub fn peer_name(&mut self) -> Option<SocketAddr> {
+        match *self {
+            NormalStream(ref mut s) => s.peer_name(),
+            SslProtectedStream(ref mut s) => s.get_mut_ref().peer_name(),
+            SocketStream(ref mut s) => s.peer_name(),
+            SslSocketStream(ref mut s) => s.get_mut_ref().peer_name(),
+            MemoryStream(*) => None,
+        }
+    }
+
+    /// Give up on a read from the connection once nothing has been received for `ms`
+    /// milliseconds, or with `None`, never (see `Socket.set_read_timeout`). Only a socket of our
+    /// own can time out; for any other, this does nothing.
+    pub fn set_read_timeout(&mut self, ms: Option<u64>) {
+        match *self {
+            SocketStream(ref mut s) => s.set_read_timeout(ms),
+            SslSocketStream(ref mut s) => s.get_mut_ref().set_read_timeout(ms),
+            NormalStream(*) | SslProtectedStream(*) | MemoryStream(*) => (),
+        }
+    }
+
+    /// Whether reading from the connection has timed out, ending it (see
+    /// `Socket.set_read_timeout`).
+    pub fn read_timed_out(&mut self) -> bool {
+        match *self {
+            SocketStream(ref s) => s.read_timed_out(),
+            SslSocketStream(ref mut s) => s.get_mut_ref().read_timed_out(),
+            NormalStream(*) | SslProtectedStream(*) | MemoryStream(*) => false,
+        }
+    }
+
+    /// Send up to `length` bytes of the file, from `offset`, straight to the connection, returning
+    /// how many there were; `None`, with nothing sent, if the connection can't be sent a file so,
+    /// as only a plain connection on a socket of our own can (see `Socket.send_file`).
+    pub fn send_file(&mut self, file: &RawFile, offset: u64, length: u64) -> Option<u64> {
+        match *self {
+            SocketStream(ref mut s) => s.send_file(file, offset, length),
+            _ => None,
+        }
+    }
+
+    /// Whether the connection is protected by TLS.
+    pub fn is_secure(&self) -> bool {
+        match *self {
+            NormalStream(*) | SocketStream(*) | MemoryStream(*) => false,
+            SslProtectedStream(*) | SslSocketStream(*) => true,
+        }
+    }
+}
+
+impl Reader for NetworkStream {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        match *self {
+            NormalStream(ref mut s) => s.read(buf),
+            SslProtectedStream(ref mut s) => s.read(buf),
+            SocketStream(ref mut s) => s.read(buf),
+            SslSocketStream(ref mut s) => s.read(buf),
+            MemoryStream(*) => None,
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        match *self {
+            NormalStream(ref mut s) => s.eof(),
+            SslProtectedStream(ref mut s) => s.eof(),
+            SocketStream(ref mut s) => s.eof(),
+            SslSocketStream(ref mut s) => s.eof(),
+            MemoryStream(*) => true,
+        }
+    }
+}
+
+impl Writer for NetworkStream {
+    fn write(&mut self, buf: &[u8]) {
+        match *self {
+            NormalStream(ref mut s) => s.write(buf),
+            SslProtectedStream(ref mut s) => s.write(buf),
+            SocketStream(ref mut s) => s.write(buf),
+            SslSocketStream(ref mut s) => s.write(buf),
+            MemoryStream(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) {
+        match *self {
+            NormalStream(ref mut s) => s.flush(),
+            SslProtectedStream(ref mut s) => s.flush(),
+            SocketStream(ref mut s) => s.flush(),
+            SslSocketStream(ref mut s) => s.flush(),
+            MemoryStream(ref mut s) => s.flush(),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Percent-encoding and decoding of URL components (RFC 3986, section 2.1) and of
+//! `application/x-www-form-urlencoded` data.
+//!
+//! Which characters must be encoded depends upon which part of a URL is being written, so encoding
+//! is parameterised by an `EncodeSet`. Decoding is the same everywhere, save that in form data `+`
+//! stands for a space. A path is decoded with `decode_path` into segments safe to name files by.
+
+use std::str;
+use std::vec;
+use rfc2616::is_hex;
+
+/// The characters to percent-encode. All of the sets encode `%` itself, controls, space and
+/// anything that isn't ASCII, so that whatever is encoded will decode back to the original.
+#[deriving(Clone, Eq)]
+pub enum EncodeSet {
+    /// Only what every set encodes.
+    SimpleSet,
+
+    /// For a query string, whose parameters are already separated: also `"`, `#`, `<` and `>`.
+    QuerySet,
+
+    /// For a path, whose segments are already separated: the query set, plus `?`, `` ` ``, `{`
+    /// and `}`.
+    PathSet,
+
+    /// For a single path segment: the path set, plus `/`.
+    PathSegmentSet,
+
+    /// For the username or password of the userinfo: the path set, plus `/`, `:`, `;`, `=`, `@`,
+    /// `[`, `\`, `]`, `^` and `|`.
+    UserinfoSet,
+
+    /// For a key or value in `application/x-www-form-urlencoded` data: everything but ASCII
+    /// letters and digits and `*`, `-`, `.` and `_`. (Use `encode_form_component`, which
+    /// also writes spaces as `+`.)
+    FormSet,
+}
+
+impl EncodeSet {
+    /// Whether the byte is to be percent-encoded in this set.
+    pub fn contains(&self, b: u8) -> bool {
+        if b <= 0x20 || b >= 0x7f || b == '%' as u8 {
+            return true;
+        }
+        let c = b as char;
+        match *self {
+            SimpleSet => false,
+            QuerySet => in_query_set(c),
+            PathSet => in_path_set(c),
+            PathSegmentSet => in_path_set(c) || c == '/',
+            UserinfoSet => in_path_set(c) || match c {
+                '/' | ':' | ';' | '=' | '@' | '[' | '\\' | ']' | '^' | '|' => true,
+                _ => false,
+            },
+            FormSet => !(c.is_alphanumeric() || c == '*' || c == '-' || c == '.' || c == '_'),
+        }
+    }
+}
+
+#[inline]
+fn in_query_set(c: char) -> bool {
+    c == '"' || c == '#' || c == '<' || c == '>'
+}
+
+#[inline]
+fn in_path_set(c: char) -> bool {
+    in_query_set(c) || c == '?' || c == '`' || c == '{' || c == '}'
+}
+
+static HEX_DIGITS: &'static [u8] = bytes!("0123456789ABCDEF");
+
+/// Percent-encode the bytes which are in the encode set.
+pub fn encode(input: &[u8], set: EncodeSet) -> ~str {
+    let mut s = str::with_capacity(input.len());
+    for &b in input.iter() {
+        if set.contains(b) {
+            push_encoded_byte(&mut s, b);
+        } else {
+            s.push_char(b as char);
+        }
+    }
+    s
+}
+
+/// Encode a key or value for `application/x-www-form-urlencoded` data: as `FormSet`, but with
+/// spaces written as `+`.
+pub fn encode_form_component(input: &str) -> ~str {
+    let mut s = str::with_capacity(input.len());
+    for &b in input.as_bytes().iter() {
+        if b == ' ' as u8 {
+            s.push_char('+');
+        } else if FormSet.contains(b) {
+            push_encoded_byte(&mut s, b);
+        } else {
+            s.push_char(b as char);
+        }
+    }
+    s
+}
+
+#[inline]
+fn push_encoded_byte(s: &mut ~str, b: u8) {
+    s.push_char('%');
+    s.push_char(HEX_DIGITS[(b >> 4) as uint] as char);
+    s.push_char(HEX_DIGITS[(b & 0xf) as uint] as char);
+}
+
+/// Decode the percent-encoded bytes in the input, yielding the raw bytes. A `%` which is not
+/// followed by two hex digits is left as it is.
+pub fn decode(input: &[u8]) -> ~[u8] {
+    decode_bytes(input, false)
+}
+
+/// Decode a percent-encoded string; see `decode` and `bytes_to_str`.
+pub fn decode_str(input: &str) -> ~str {
+    bytes_to_str(decode_bytes(input.as_bytes(), false))
+}
+
+/// Decode a key or value from `application/x-www-form-urlencoded` data, in which `+` stands for a
+/// space; see `decode` and `bytes_to_str`.
+pub fn decode_form_component(input: &str) -> ~str {
+    bytes_to_str(decode_bytes(input.as_bytes(), true))
+}
+
+fn decode_bytes(input: &[u8], plus_is_space: bool) -> ~[u8] {
+    let mut decoded = vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b if plus_is_space && b == '+' as u8 => decoded.push(' ' as u8),
+            b if b == '%' as u8 && i + 2 < input.len() && is_hex(input[i + 1])
+                    && is_hex(input[i + 2]) => {
+                decoded.push((hex_value(input[i + 1]) << 4) | hex_value(input[i + 2]));
+                i += 2;
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+#[inline]
+fn hex_value(b: u8) -> u8 {
+    match b {
+        b if b >= 'a' as u8 => b - 'a' as u8 + 10,
+        b if b >= 'A' as u8 => b - 'A' as u8 + 10,
+        b => b - '0' as u8,
+    }
+}
+
+/// Decode a path (without any query string) into its segments, with `.` and `..` taken out as
+/// they would be resolved (RFC 3986, section 5.2.4), whether they were percent-encoded or not,
+/// and without any empty ones; so that, joined on to a directory, they name something within it.
+/// `None` is returned if that can't be: if `..` would go above the first segment, or a segment
+/// contains a NUL, a backslash or an encoded `/` (which would be taken apart), or is only dots and
+/// spaces, as Windows may take for `..`.
+///
+/// # Examples
+///
+/// ~~~ .{rust}
+/// assert_eq!(decode_path("/a/./b/../c%20d/"), Some(~[~"a", ~"c d"]));
+/// assert_eq!(decode_path("/a/%2e%2e/../etc/passwd"), None);
+/// ~~~
+pub fn decode_path(path: &str) -> Option<~[~str]> {
+    let mut segments: ~[~str] = ~[];
+    for segment in path.split_iter('/') {
+        let segment = decode_str(segment);
+        match segment.as_slice() {
+            "" | "." => (),
+            ".." => if segments.pop_opt().is_none() {
+                return None;
+            },
+            s if s.contains_char('\0') || s.contains_char('\\') || s.contains_char('/') =>
+                return None,
+            s if s.iter().all(|c| c == '.' || c == ' ') => return None,
+            s => segments.push(s.to_owned()),
+        }
+    }
+    Some(segments)
+}
+
+/// Interpret decoded bytes as a string: as UTF-8 if they are valid UTF-8, or else with each byte
+/// taken to be a character (as ISO-8859-1), which loses nothing.
+pub fn bytes_to_str(bytes: ~[u8]) -> ~str {
+    if str::is_utf8(bytes) {
+        str::from_utf8(bytes)
+    } else {
+        let mut s = str::with_capacity(bytes.len());
+        for &b in bytes.iter() {
+            s.push_char(b as char);
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, encode_form_component, decode, decode_str, decode_form_component,
+                decode_path, SimpleSet, QuerySet, PathSet, PathSegmentSet, UserinfoSet, FormSet};
+
+    #[test]
+    fn test_encode() {
+        let s = "a b%c/d?e#f<g\"h@i:j€";
+        assert_eq!(encode(s.as_bytes(), SimpleSet), ~"a%20b%25c/d?e#f<g\"h@i:j%E2%82%AC");
+        assert_eq!(encode(s.as_bytes(), QuerySet), ~"a%20b%25c/d?e%23f%3Cg%22h@i:j%E2%82%AC");
+        assert_eq!(encode(s.as_bytes(), PathSet), ~"a%20b%25c/d%3Fe%23f%3Cg%22h@i:j%E2%82%AC");
+        assert_eq!(encode(s.as_bytes(), PathSegmentSet),
+                   ~"a%20b%25c%2Fd%3Fe%23f%3Cg%22h@i:j%E2%82%AC");
+        assert_eq!(encode(s.as_bytes(), UserinfoSet),
+                   ~"a%20b%25c%2Fd%3Fe%23f%3Cg%22h%40i%3Aj%E2%82%AC");
+        assert_eq!(encode(s.as_bytes(), FormSet),
+                   ~"a%20b%25c%2Fd%3Fe%23f%3Cg%22h%40i%3Aj%E2%82%AC");
+        assert_eq!(encode_form_component("a b+c*-._~"), ~"a+b%2Bc*-._%7E");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(bytes!("a%20b%2f%2F%ff")),
+                   ~['a' as u8, ' ' as u8, 'b' as u8, '/' as u8, '/' as u8, 0xff]);
+        assert_eq!(decode_str("a+b%20c"), ~"a+b c");
+        assert_eq!(decode_form_component("a+b%20c"), ~"a b c");
+        assert_eq!(decode_str("%e2%82%ac%E2%82%AC"), ~"€€");
+        // Malformed escapes are left alone, and bytes which aren't UTF-8 taken as characters
+        assert_eq!(decode_str("100%"), ~"100%");
+        assert_eq!(decode_str("%zz%4"), ~"%zz%4");
+        assert_eq!(decode_str("%e9t%e9"), ~"été");
+    }
+
+    #[test]
+    fn test_decode_path() {
+        assert_eq!(decode_path(""), Some(~[]));
+        assert_eq!(decode_path("/"), Some(~[]));
+        assert_eq!(decode_path("/a//b/"), Some(~[~"a", ~"b"]));
+        assert_eq!(decode_path("/a/./b/../c%20d"), Some(~[~"a", ~"c d"]));
+        assert_eq!(decode_path("/a/%2e/b/%2E%2e/c"), Some(~[~"a", ~"c"]));
+        assert_eq!(decode_path("/a/.../b..c/.d"), None);
+        assert_eq!(decode_path("/a/b..c/.d"), Some(~[~"a", ~"b..c", ~".d"]));
+        assert_eq!(decode_path("/a/../.."), None);
+        assert_eq!(decode_path("/../etc/passwd"), None);
+        assert_eq!(decode_path("/a/%2e%2e/%2e%2e/etc/passwd"), None);
+        assert_eq!(decode_path("/a%2f..%2f..%2fetc"), None);
+        assert_eq!(decode_path("/a%5c..%5cetc"), None);
+        assert_eq!(decode_path("/a\\..\\etc"), None);
+        assert_eq!(decode_path("/a%00.html"), None);
+        assert_eq!(decode_path("/a/..%20"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let s = "/ünïcödé path?with=all&the#trimmings%";
+        for &set in [SimpleSet, QuerySet, PathSet, PathSegmentSet, UserinfoSet, FormSet].iter() {
+            assert_eq!(decode_str(encode(s.as_bytes(), set)), s.to_owned());
+        }
+        assert_eq!(decode_form_component(encode_form_component(s)), s.to_owned());
+    }
+}
@@ -0,0 +1,226 @@
+/*!
+
+Logging of the requests a server has answered, a line for each, in the Common Log Format or the
+Combined Log Format, as web servers have long written them:
+
+```
+127.0.0.1 - - [10/Oct/2013:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326
+```
+
+The Combined Log Format adds the Referer and User-Agent headers, in quotes, to the end of that.
+
+```rust
+let mut config = Config::new(address);
+let log = Path::new("access.log").open_writer(Append);
+config.access_log = Some(AccessLogger::new(log, CombinedLogFormat));
+```
+
+*/
+
+use std::cell::Cell;
+use std::comm::{SharedChan, Peekable};
+use std::rt::io::Writer;
+use std::rt::io::net::ip::SocketAddr;
+use std::task::spawn_supervised;
+use extra::time::Tm;
+use server::{Request, ResponseWriter};
+
+/// Which fields each line of the log has.
+#[deriving(Clone, Eq)]
+pub enum LogFormat {
+    /// The remote host, the time, the Request-Line, the status code and the length of the body.
+    CommonLogFormat,
+    /// The Common Log Format, with the Referer and User-Agent of the request after it.
+    CombinedLogFormat,
+}
+
+/// What is logged of a request and the response to it.
+#[deriving(Clone)]
+pub struct AccessLogEntry {
+    /// The address the request came from.
+    remote_addr: Option<SocketAddr>,
+
+    /// The Request-Line, as `GET /index.html HTTP/1.1`.
+    request_line: ~str,
+
+    /// The status code of the response.
+    status: u16,
+
+    /// How many bytes of body were sent, after any content-coding, but not counting the headers or
+    /// the framing of a chunked body.
+    body_len: u64,
+
+    /// The Referer header of the request.
+    referer: Option<~str>,
+
+    /// The User-Agent header of the request.
+    user_agent: Option<~str>,
+
+    /// When the request was received, in UTC.
+    time: Tm,
+
+    /// How long it took to serve the request, in nanoseconds, from its first byte arriving until
+    /// the response was finished.
+    duration_ns: u64,
+}
+
+impl AccessLogEntry {
+    /// The entry for a response which has been finished.
+    pub fn new(request: &Request, response: &ResponseWriter, time: Tm, duration_ns: u64)
+            -> AccessLogEntry {
+        let (major, minor) = request.version;
+        AccessLogEntry {
+            remote_addr: request.remote_addr,
+            request_line: format!("{} {} HTTP/{}.{}", request.method.to_str(),
+                                  request.request_uri.to_str(), major, minor),
+            status: response.status.code(),
+            body_len: response.body_len_written(),
+            referer: request.headers.referer.clone(),
+            user_agent: request.headers.user_agent.clone(),
+            time: time,
+            duration_ns: duration_ns,
+        }
+    }
+
+    /// The line to log for the entry, without its line ending.
+    pub fn format(&self, format: LogFormat) -> ~str {
+        let host = match self.remote_addr {
+            Some(addr) => addr.ip.to_str(),
+            None => ~"-",
+        };
+        // The time is in UTC (strftime's %z would give that as -0000).
+        let time = self.time.strftime("%d/%b/%Y:%H:%M:%S +0000");
+        let body_len = match self.body_len {
+            0 => ~"-",
+            n => n.to_str(),
+        };
+        let mut line = format!("{} - - [{}] \"{}\" {} {}", host, time, escape(self.request_line),
+                               self.status, body_len);
+        if format == CombinedLogFormat {
+            let s = format!(" \"{}\" \"{}\"", escape_header(&self.referer),
+                            escape_header(&self.user_agent));
+            line.push_str(s);
+        }
+        line
+    }
+}
+
+/// A log of the requests a server answers (see `Config.access_log`), written to a `Writer` from a
+/// task of its own, which each connection sends its lines to. Clones share the writer.
+#[deriving(Clone)]
+pub struct AccessLogger {
+    priv chan: SharedChan<~str>,
+    priv format: LogFormat,
+}
+
+impl AccessLogger {
+    /// Log to the writer, in the format given. The writer is flushed whenever the lines to be
+    /// written have all been written, and dropped once every clone of the logger has been.
+    pub fn new<W: Writer + Send>(writer: W, format: LogFormat) -> AccessLogger {
+        let (port, chan) = stream();
+        let (port, writer) = (Cell::new(port), Cell::new(writer));
+        do spawn_supervised {
+            let (port, mut writer) = (port.take(), writer.take());
+            loop {
+                match port.try_recv() {
+                    Some(line) => writer.write(line.as_bytes()),
+                    None => break,
+                }
+                if !port.peek() {
+                    writer.flush();
+                }
+            }
+        }
+        AccessLogger {
+            chan: SharedChan::new(chan),
+            format: format,
+        }
+    }
+
+    /// Log a request.
+    pub fn log(&self, entry: &AccessLogEntry) {
+        let mut line = entry.format(self.format);
+        line.push_char('\n');
+        self.chan.send(line);
+    }
+}
+
+/// A header value as it goes in a log line: `-` if there is none.
+fn escape_header(value: &Option<~str>) -> ~str {
+    match *value {
+        Some(ref value) => escape(*value),
+        None => ~"-",
+    }
+}
+
+/// Escape a string to go between the quotes of a log line, so that nothing the client sent can
+/// end the field, or the line, early: quotes and backslashes are preceded by a backslash, and
+/// control characters are written as `\xHH`.
+fn escape(s: &str) -> ~str {
+    let mut out = ~"";
+    for b in s.byte_iter() {
+        match b as char {
+            '"' | '\\' => {
+                out.push_char('\\');
+                out.push_char(b as char);
+            },
+            _ if b < 0x20 || b == 0x7f => out.push_str(format!("\\\\x{:02X}", b)),
+            _ => out.push_char(b as char),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::net::ip::{SocketAddr, Ipv4Addr};
+    use extra::time::Tm;
+    use super::{AccessLogEntry, CommonLogFormat, CombinedLogFormat, escape};
+
+    fn sample_entry() -> AccessLogEntry {
+        AccessLogEntry {
+            remote_addr: Some(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 54321 }),
+            request_line: ~"GET /index.html HTTP/1.1",
+            status: 200,
+            body_len: 2326,
+            referer: None,
+            user_agent: Some(~"curl/7.32.0"),
+            time: Tm {
+                tm_sec: 36,
+                tm_min: 55,
+                tm_hour: 13,
+                tm_mday: 10,
+                tm_mon: 9,
+                tm_year: 113,
+                tm_wday: 4,
+                tm_yday: 282,
+                tm_isdst: 0,
+                tm_gmtoff: 0,
+                tm_zone: ~"UTC",
+                tm_nsec: 0
+            },
+            duration_ns: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let mut entry = sample_entry();
+        let common = "127.0.0.1 - - [10/Oct/2013:13:55:36 +0000] \
+                      \"GET /index.html HTTP/1.1\" 200 2326";
+        assert_eq!(entry.format(CommonLogFormat), common.to_owned());
+        assert_eq!(entry.format(CombinedLogFormat), format!("{} \"-\" \"curl/7.32.0\"", common));
+        entry.remote_addr = None;
+        entry.status = 304;
+        entry.body_len = 0;
+        assert_eq!(entry.format(CommonLogFormat),
+                   ~"- - - [10/Oct/2013:13:55:36 +0000] \"GET /index.html HTTP/1.1\" 304 -");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("Mozilla/5.0 (X11)"), ~"Mozilla/5.0 (X11)");
+        assert_eq!(escape("a \"quoted\" \\ string"), ~"a \\\"quoted\\\" \\\\ string");
+        assert_eq!(escape("line\r\nbreak"), ~"line\\x0D\\x0Abreak");
+    }
+}
@@ -0,0 +1,344 @@
+/*!
+
+Authentication of requests, as middleware (see `server::middleware`): a request without acceptable
+credentials is answered with 401 Unauthorized and a challenge, and goes no further.
+
+```rust
+struct Users {
+    passwords: HashMap<~str, ~str>,
+}
+
+impl PasswordChecker for Users {
+    fn check(&self, user: &str, password: &str) -> bool {
+        self.passwords.find_equiv(&user).map_default(false, |p| p.as_slice() == password)
+    }
+}
+
+let auth = BasicAuth::new("admin", ~users as ~PasswordChecker:Send+Freeze);
+let admin = MiddlewareStack::new(~[~auth as ~Middleware:Send+Freeze]);
+```
+
+Routes are guarded by going through such a stack in their handlers; those which are not, aren't.
+
+Basic authentication sends the password as it is (RFC 2617, section 2), and so should only be used
+over TLS. Digest authentication (section 3) sends only a hash of it, made with a nonce from the
+server; `DigestAuth` makes its nonces from the time and a key of its own, and so needn't remember
+them, but it can't tell if one is used again before it expires, as by a replayed request.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::rand::{Rng, task_rng};
+use std::str;
+use extra::base64::{ToBase64, FromBase64, STANDARD};
+use extra::crypto::digest::Digest;
+use extra::crypto::md5::Md5;
+use extra::time::get_time;
+use headers::authorization::{Basic, basic_challenge, auth_param, digest_challenge,
+                             digest_response};
+use headers::authorization;
+use server::{Request, ResponseWriter};
+use server::middleware::Middleware;
+use status;
+
+/// The length of the random key with which `DigestAuth` makes its nonces.
+static NONCE_KEY_LEN: uint = 32;
+
+/// The checker of the user-ids and passwords given in Basic authentication.
+pub trait PasswordChecker {
+    /// Whether the password is that of the user.
+    fn check(&self, user: &str, password: &str) -> bool;
+}
+
+/// Middleware which requires Basic authentication of each request.
+pub struct BasicAuth {
+    priv realm: ~str,
+    priv checker: ~PasswordChecker:Send+Freeze,
+}
+
+impl BasicAuth {
+    /// Require a user-id and password which the checker accepts, for the realm named.
+    pub fn new(realm: &str, checker: ~PasswordChecker:Send+Freeze) -> BasicAuth {
+        BasicAuth {
+            realm: realm.to_owned(),
+            checker: checker,
+        }
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &Request, response: &mut ResponseWriter) -> bool {
+        match request.headers.authorization {
+            Some(Basic(ref user, ref password)) if self.checker.check(*user, *password) => false,
+            _ => {
+                response.headers.www_authenticate = Some(basic_challenge(self.realm));
+                response.send_error(status::Unauthorized);
+                true
+            },
+        }
+    }
+}
+
+/// The user database for Digest authentication.
+pub trait DigestUsers {
+    /// H(A1) for the user in the realm (see `headers::authorization::digest_ha1`), if there is
+    /// such a user.
+    fn ha1(&self, user: &str, realm: &str) -> Option<~str>;
+}
+
+/// Whether a nonce is one `DigestAuth` made, and if so, whether it has expired.
+#[deriving(Eq)]
+enum NonceState {
+    FreshNonce,
+    StaleNonce,
+    BadNonce,
+}
+
+/// Middleware which requires Digest authentication of each request, with the MD5 algorithm and
+/// the `auth` quality of protection.
+pub struct DigestAuth {
+    priv realm: ~str,
+    priv users: ~DigestUsers:Send+Freeze,
+    priv key: ~str,
+    priv nonce_lifetime: i64,
+}
+
+impl DigestAuth {
+    /// Require credentials for a user the database has, in the realm named. A nonce lasts five
+    /// minutes; after that, the client is asked to make its credentials again with a new one.
+    pub fn new(realm: &str, users: ~DigestUsers:Send+Freeze) -> DigestAuth {
+        DigestAuth {
+            realm: realm.to_owned(),
+            users: users,
+            key: task_rng().gen_ascii_str(NONCE_KEY_LEN),
+            nonce_lifetime: 300,
+        }
+    }
+
+    /// Set how many seconds a nonce lasts.
+    pub fn set_nonce_lifetime(&mut self, seconds: i64) {
+        self.nonce_lifetime = seconds;
+    }
+
+    /// A nonce made at the time given, in seconds since the epoch: the time, with a hash of it
+    /// and the key, so that it can be checked without being remembered.
+    fn make_nonce(&self, time: i64) -> ~str {
+        let nonce = format!("{}:{}", time, self.hash_time(time));
+        nonce.as_bytes().to_base64(STANDARD)
+    }
+
+    fn hash_time(&self, time: i64) -> ~str {
+        let mut md5 = Md5::new();
+        md5.input_str(format!("{}:{}", time, self.key));
+        md5.result_str()
+    }
+
+    fn check_nonce(&self, nonce: &str, now: i64) -> NonceState {
+        let nonce = match nonce.from_base64() {
+            Ok(bytes) if str::is_utf8(bytes) => str::from_utf8(bytes),
+            _ => return BadNonce,
+        };
+        let (time, hash) = match nonce.find(':') {
+            Some(i) => (nonce.slice_to(i), nonce.slice_from(i + 1)),
+            None => return BadNonce,
+        };
+        let time: i64 = match FromStr::from_str(time) {
+            Some(time) => time,
+            None => return BadNonce,
+        };
+        if hash != self.hash_time(time) {
+            BadNonce
+        } else if now - time > self.nonce_lifetime || time > now {
+            StaleNonce
+        } else {
+            FreshNonce
+        }
+    }
+
+    /// Check the Digest credentials of a request, returning the state of the nonce they were
+    /// made with if they are right, or `BadNonce` if they are wrong.
+    fn check(&self, request: &Request, params: &[(~str, ~str)], now: i64) -> NonceState {
+        let (user, realm, nonce, uri, response) = match (auth_param(params, "username"),
+                                                         auth_param(params, "realm"),
+                                                         auth_param(params, "nonce"),
+                                                         auth_param(params, "uri"),
+                                                         auth_param(params, "response")) {
+            (Some(user), Some(realm), Some(nonce), Some(uri), Some(response)) =>
+                (user, realm, nonce, uri, response),
+            _ => return BadNonce,
+        };
+        // The credentials must be for this request, not one they were seen with.
+        if realm != self.realm || uri != request.request_uri.to_str() {
+            return BadNonce;
+        }
+        let qop_auth = match auth_param(params, "qop") {
+            None => None,
+            Some(qop) if qop == "auth" => match (auth_param(params, "nc"),
+                                                 auth_param(params, "cnonce")) {
+                (Some(nc), Some(cnonce)) => Some((nc, cnonce)),
+                _ => return BadNonce,
+            },
+            Some(_) => return BadNonce,
+        };
+        let expected = match self.users.ha1(user, realm) {
+            Some(ha1) => digest_response(ha1, nonce, qop_auth, request.method.to_str(), uri),
+            None => return BadNonce,
+        };
+        if expected != response.to_ascii_lower() {
+            return BadNonce;
+        }
+        self.check_nonce(nonce, now)
+    }
+}
+
+impl Middleware for DigestAuth {
+    fn before(&self, request: &Request, response: &mut ResponseWriter) -> bool {
+        let now = get_time().sec;
+        let state = match request.headers.authorization {
+            Some(authorization::Digest(ref params)) => self.check(request, *params, now),
+            _ => BadNonce,
+        };
+        if state == FreshNonce {
+            return false;
+        }
+        let nonce = self.make_nonce(now);
+        response.headers.www_authenticate = Some(digest_challenge(self.realm, nonce, None,
+                                                                  state == StaleNonce));
+        response.send_error(status::Unauthorized);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ascii::StrAsciiExt;
+    use headers;
+    use headers::authorization::{digest_ha1, digest_response};
+    use method::Get;
+    use server::Request;
+    use server::request::AbsolutePath;
+    use super::{DigestAuth, DigestUsers, FreshNonce, StaleNonce, BadNonce};
+
+    struct NoUsers;
+
+    /// The user of the example of RFC 2617, section 3.5.
+    struct Mufasa;
+
+    impl DigestUsers for Mufasa {
+        fn ha1(&self, user: &str, realm: &str) -> Option<~str> {
+            if user == "Mufasa" {
+                Some(digest_ha1(user, realm, "Circle Of Life"))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn example_request() -> Request {
+        Request {
+            remote_addr: None,
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: ~[],
+            trailer: ~[],
+            method: Get,
+            request_uri: AbsolutePath(~"/dir/index.html"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        }
+    }
+
+    /// The parameters of the credentials of the example, but with the nonce and response given.
+    fn params(nonce: &str, response: &str) -> ~[(~str, ~str)] {
+        ~[(~"username", ~"Mufasa"), (~"realm", ~"testrealm@host.com"),
+          (~"nonce", nonce.to_owned()), (~"uri", ~"/dir/index.html"), (~"qop", ~"auth"),
+          (~"nc", ~"00000001"), (~"cnonce", ~"0a4f113b"), (~"response", response.to_owned())]
+    }
+
+    /// The response to a nonce, as the example's client would make it, with the password given.
+    fn response_to(nonce: &str, password: &str) -> ~str {
+        let ha1 = digest_ha1("Mufasa", "testrealm@host.com", password);
+        digest_response(ha1.as_slice(), nonce, Some(("00000001", "0a4f113b")), "GET",
+                        "/dir/index.html")
+    }
+
+    impl DigestUsers for NoUsers {
+        fn ha1(&self, _user: &str, _realm: &str) -> Option<~str> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_nonce() {
+        let auth = DigestAuth::new("test", ~NoUsers as ~DigestUsers:Send+Freeze);
+        let nonce = auth.make_nonce(1000);
+        assert_eq!(auth.check_nonce(nonce, 1000), FreshNonce);
+        assert_eq!(auth.check_nonce(nonce, 1300), FreshNonce);
+        assert_eq!(auth.check_nonce(nonce, 1301), StaleNonce);
+        assert_eq!(auth.check_nonce(nonce, 999), StaleNonce);
+        assert_eq!(auth.check_nonce("MTAwMDpmb28=", 1000), BadNonce);
+        assert_eq!(auth.check_nonce("!", 1000), BadNonce);
+        let other = DigestAuth::new("test", ~NoUsers as ~DigestUsers:Send+Freeze);
+        assert_eq!(other.check_nonce(nonce, 1000), BadNonce);
+    }
+
+    #[test]
+    fn test_check() {
+        let auth = DigestAuth::new("testrealm@host.com", ~Mufasa as ~DigestUsers:Send+Freeze);
+        let request = example_request();
+
+        // The example's own response is right, but its nonce isn't one of ours
+        let rfc_nonce = "dcd98b7102dd2f0e8b11d0f600bfb0c093";
+        assert_eq!(response_to(rfc_nonce, "Circle Of Life"),
+                   ~"6629fae49393a05397450978507c4ef1");
+        assert_eq!(auth.check(&request, params(rfc_nonce, "6629fae49393a05397450978507c4ef1"),
+                              1000), BadNonce);
+
+        let nonce = auth.make_nonce(1000);
+        let nonce = nonce.as_slice();
+        let response = response_to(nonce, "Circle Of Life");
+        let response = response.as_slice();
+        assert_eq!(auth.check(&request, params(nonce, response), 1000), FreshNonce);
+        assert_eq!(auth.check(&request, params(nonce, response.to_ascii_upper().as_slice()), 1000),
+                   FreshNonce);
+        assert_eq!(auth.check(&request, params(nonce, response), 1301), StaleNonce);
+
+        // A wrong password, or user
+        let wrong = response_to(nonce, "Circle of Life");
+        assert_eq!(auth.check(&request, params(nonce, wrong.as_slice()), 1000), BadNonce);
+        let mut scar = params(nonce, response);
+        scar[0] = (~"username", ~"Scar");
+        assert_eq!(auth.check(&request, scar, 1000), BadNonce);
+
+        // Credentials for another URI or realm
+        let mut other_uri = example_request();
+        other_uri.request_uri = AbsolutePath(~"/dir/other.html");
+        assert_eq!(auth.check(&other_uri, params(nonce, response), 1000), BadNonce);
+        let mut other_realm = params(nonce, response);
+        other_realm[1] = (~"realm", ~"otherrealm@host.com");
+        assert_eq!(auth.check(&request, other_realm, 1000), BadNonce);
+
+        // qop=auth needs nc and cnonce, which the response is made with
+        let without_cnonce: ~[(~str, ~str)] = params(nonce, response).move_iter()
+            .filter(|&(ref name, _)| name.as_slice() != "cnonce").collect();
+        assert_eq!(auth.check(&request, without_cnonce, 1000), BadNonce);
+        let mut other_nc = params(nonce, response);
+        other_nc[6] = (~"nc", ~"00000002");
+        assert_eq!(auth.check(&request, other_nc, 1000), BadNonce);
+        let mut other_qop = params(nonce, response);
+        other_qop[4] = (~"qop", ~"auth-int");
+        assert_eq!(auth.check(&request, other_qop, 1000), BadNonce);
+
+        // Without qop, the response is that of RFC 2069
+        let ha1 = digest_ha1("Mufasa", "testrealm@host.com", "Circle Of Life");
+        let old_response = digest_response(ha1.as_slice(), nonce, None, "GET", "/dir/index.html");
+        let old: ~[(~str, ~str)] = params(nonce, old_response.as_slice()).move_iter()
+            .filter(|&(ref name, _)| match name.as_slice() {
+                "qop" | "nc" | "cnonce" => false,
+                _ => true,
+            }).collect();
+        assert_eq!(auth.check(&request, old, 1000), FreshNonce);
+    }
+}
@@ -0,0 +1,138 @@
+/*!
+
+Conditional requests: answering a request which carries validators of the representation the client
+already has with 304 Not Modified, rather than sending the representation again.
+
+```rust
+let etag = weak_etag(format!("{:x}", version));
+if conditional::handle_if_none_match(request, response, &etag)
+        || conditional::handle_if_modified_since(request, response, &modified) {
+    return;
+}
+// ... write the representation
+```
+
+*/
+
+use extra::time::{Tm, get_time};
+use headers::etag::{EntityTag, EntityTagList};
+use method::{Method, Get, Head};
+use server::{Request, ResponseWriter};
+use status;
+use status::Status;
+
+/// The status with which a request is to be answered instead of being carried out, according to
+/// its If-None-Match header and the entity tag of the current representation: 304 Not Modified
+/// for GET and HEAD requests, 412 Precondition Failed for others. `None` means that the request
+/// should be carried out as normal.
+pub fn check_if_none_match(request: &Request, etag: &EntityTag) -> Option<Status> {
+    match request.headers.if_none_match {
+        Some(ref tags) => if_none_match_status(&request.method, tags, etag),
+        None => None,
+    }
+}
+
+fn if_none_match_status(method: &Method, tags: &EntityTagList, etag: &EntityTag)
+        -> Option<Status> {
+    // Only GET and HEAD may use the weak comparison function (RFC 2616, section 14.26).
+    if *method == Get || *method == Head {
+        if tags.matches_weak(etag) { Some(status::NotModified) } else { None }
+    } else {
+        if tags.matches_strong(etag) { Some(status::PreconditionFailed) } else { None }
+    }
+}
+
+/// Set the ETag of the response and, if the request's If-None-Match header matches it, send the
+/// response, without a body, as 304 Not Modified (or 412 Precondition Failed; see
+/// `check_if_none_match`). Returns `true` if the response has been sent, in which case the handler
+/// should do nothing more with it.
+pub fn handle_if_none_match(request: &Request, response: &mut ResponseWriter, etag: &EntityTag)
+        -> bool {
+    response.headers.etag = Some(etag.clone());
+    match check_if_none_match(request, etag) {
+        Some(result) => {
+            send_without_body(response, result);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Whether a GET or HEAD request is to be answered with 304 Not Modified, according to its
+/// If-Modified-Since header and the time at which the representation was last modified: that is,
+/// if it was not modified later than the time given. A time in the future is invalid, and is
+/// ignored, as is If-Modified-Since altogether when there is an If-None-Match header, which takes
+/// precedence. (An If-Modified-Since header which isn't a valid HTTP-date never gets this far.)
+pub fn check_if_modified_since(request: &Request, last_modified: &Tm) -> bool {
+    if request.method != Get && request.method != Head {
+        return false;
+    }
+    if request.headers.if_none_match.is_some() {
+        return false;
+    }
+    match request.headers.if_modified_since {
+        Some(ref since) => not_modified_since(last_modified.to_timespec().sec,
+                                              since.to_timespec().sec, get_time().sec),
+        None => false,
+    }
+}
+
+/// Whether a representation last modified at `last_modified` has not been modified since `since`,
+/// at the time `now`, all in seconds.
+fn not_modified_since(last_modified: i64, since: i64, now: i64) -> bool {
+    since <= now && last_modified <= since
+}
+
+/// Set the Last-Modified header of the response and, if the request's If-Modified-Since header
+/// shows the client to have that version already (see `check_if_modified_since`), send the
+/// response, without a body, as 304 Not Modified. Returns `true` if the response has been sent.
+pub fn handle_if_modified_since(request: &Request, response: &mut ResponseWriter,
+                                last_modified: &Tm) -> bool {
+    response.headers.last_modified = Some(last_modified.clone());
+    if check_if_modified_since(request, last_modified) {
+        send_without_body(response, status::NotModified);
+        true
+    } else {
+        false
+    }
+}
+
+/// Send the response with the status and no body.
+fn send_without_body(response: &mut ResponseWriter, result: Status) {
+    // 304 responses have no body anyway; others must say that it is empty.
+    if result != status::NotModified {
+        response.headers.content_length = Some(0);
+    }
+    response.status = result;
+    response.write_headers();
+}
+
+#[cfg(test)]
+mod test {
+    use headers::etag::{strong_etag, weak_etag, AnyEntityTag, EntityTags};
+    use method::{Get, Head, Put};
+    use status;
+    use super::{if_none_match_status, not_modified_since};
+
+    #[test]
+    fn test_if_none_match_status() {
+        let tags = EntityTags(~[weak_etag("a"), strong_etag("b")]);
+        assert_eq!(if_none_match_status(&Get, &tags, &strong_etag("a")), Some(status::NotModified));
+        assert_eq!(if_none_match_status(&Head, &tags, &weak_etag("b")), Some(status::NotModified));
+        assert_eq!(if_none_match_status(&Get, &tags, &strong_etag("c")), None);
+        assert_eq!(if_none_match_status(&Put, &tags, &strong_etag("a")), None);
+        assert_eq!(if_none_match_status(&Put, &tags, &strong_etag("b")),
+                   Some(status::PreconditionFailed));
+        assert_eq!(if_none_match_status(&Put, &AnyEntityTag, &weak_etag("c")),
+                   Some(status::PreconditionFailed));
+    }
+
+    #[test]
+    fn test_not_modified_since() {
+        assert!(not_modified_since(100, 100, 200));
+        assert!(not_modified_since(99, 100, 200));
+        assert!(!not_modified_since(101, 100, 200));
+        // A date in the future is ignored
+        assert!(!not_modified_since(100, 300, 200));
+    }
+}
@@ -0,0 +1,37 @@
+/*!
+
+Rendering of error responses, so that a server can send error pages of its own rather than a bare
+status: for requests which can't be parsed or are refused before they reach the handler, and for
+those which the router, virtual hosts or static files (or the handler, with
+`ResponseWriter.send_error`) answer with an error.
+
+```rust
+struct ErrorPages;
+
+impl ErrorHandler for ErrorPages {
+    fn handle_error(&self, _request: &Request, response: &mut ResponseWriter) {
+        let page = format!("<!DOCTYPE html>\n<h1>{}</h1>\n", response.status.to_str());
+        response.write_content_auto(MediaType(~"text", ~"html", ~[]), page);
+    }
+}
+
+let mut config = Config::new(address);
+config.error_handler = Some(Arc::new(~ErrorPages as ~ErrorHandler:Send+Freeze));
+```
+
+*/
+
+use extra::arc::Arc;
+use server::{Request, ResponseWriter};
+
+/// The renderer of error responses; see `Config.error_handler`.
+pub trait ErrorHandler {
+    /// Write the headers and body of an error response, its status having been set already. The
+    /// request may not be all there, if it was refused as malformed or too large, say; and its
+    /// body may not have been read, in which case the connection will be closed, whatever the
+    /// handler says.
+    fn handle_error(&self, request: &Request, response: &mut ResponseWriter);
+}
+
+/// An error handler, as it is shared between the tasks serving connections.
+pub type SharedErrorHandler = Arc<~ErrorHandler:Send+Freeze>;
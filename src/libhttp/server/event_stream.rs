@@ -0,0 +1,158 @@
+/*!
+
+Server-Sent Events: a response which stays open, sending events to the client as they happen, to
+be read in a browser with `EventSource`.
+
+```rust
+fn handle_request(&self, request: &Request, response: &mut ResponseWriter) {
+    let mut events = EventStream::new(response);
+    events.set_retry(5000);
+    loop {
+        let (id, price) = self.prices.recv();
+        events.send_event(Some("price"), price, Some(id.as_slice()));
+    }
+}
+```
+
+Each event is flushed as it is sent, as a chunk of the body; a response which is being compressed
+can't be sent a piece at a time (see `ResponseWriter`), and so can't be an event stream. A client
+which reconnects says in Last-Event-ID which event it saw last (see `last_event_id`).
+
+*/
+
+use std::rt::io::Writer;
+use headers::cache_control::{CacheControl, NoCache};
+use headers::content_type::MediaType;
+use server::{Request, ResponseWriter};
+
+/// A stream of events, written to a response.
+pub struct EventStream<'self, 'r> {
+    priv response: &'self mut ResponseWriter<'r>,
+}
+
+impl<'self, 'r> EventStream<'self, 'r> {
+    /// Start the event stream: the headers are written (so they must not have been already), with
+    /// a Content-Type of `text/event-stream`, and Cache-Control and X-Accel-Buffering set so that
+    /// neither caches nor proxies such as nginx keep the events back.
+    pub fn new(response: &'self mut ResponseWriter<'r>) -> EventStream<'self, 'r> {
+        response.headers.content_type = Some(MediaType(~"text", ~"event-stream", ~[]));
+        response.headers.content_length = None;
+        response.headers.cache_control = Some(CacheControl(~[NoCache(~[])]));
+        response.headers.extensions.insert(~"X-Accel-Buffering", ~"no");
+        response.write_headers();
+        response.flush();
+        EventStream { response: response }
+    }
+
+    /// Send an event, with its type, if it isn't just a message, and its id, if it has one. The
+    /// data may have several lines. The type and id can't have line breaks in them; this fails if
+    /// they do.
+    pub fn send_event(&mut self, name: Option<&str>, data: &str, id: Option<&str>) {
+        let s = format_event(name, data, id);
+        self.send(s);
+    }
+
+    /// Send a comment, which the client ignores; sent now and then, it keeps the connection from
+    /// being closed as idle by proxies.
+    pub fn send_comment(&mut self, comment: &str) {
+        let mut s = ~"";
+        for line in lines(comment).iter() {
+            s.push_str(format!(":{}\n", *line));
+        }
+        s.push_char('\n');
+        self.send(s);
+    }
+
+    /// Tell the client how many milliseconds to wait before reconnecting, should the connection be
+    /// lost.
+    pub fn set_retry(&mut self, ms: u64) {
+        self.send(format!("retry: {}\n\n", ms));
+    }
+
+    fn send(&mut self, s: &str) {
+        self.response.write(s.as_bytes());
+        self.response.flush();
+    }
+}
+
+/// The id of the last event a reconnecting client saw, from its Last-Event-ID header.
+pub fn last_event_id(request: &Request) -> Option<~str> {
+    match request.headers.extensions.find(&~"Last-Event-Id") {
+        Some(id) => Some(id.clone()),
+        None => None,
+    }
+}
+
+/// The lines of a string, split at CRLF, CR or LF, as the client splits them.
+fn lines<'a>(s: &'a str) -> ~[&'a str] {
+    let mut lines = ~[];
+    let mut start = 0;
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == '\r' as u8 || bytes[i] == '\n' as u8 {
+            lines.push(s.slice(start, i));
+            if bytes[i] == '\r' as u8 && i + 1 < bytes.len() && bytes[i + 1] == '\n' as u8 {
+                i += 1;
+            }
+            start = i + 1;
+        }
+        i += 1;
+    }
+    lines.push(s.slice_from(start));
+    lines
+}
+
+/// An event as it is framed in the stream: its fields, a line each, and then a blank line.
+fn format_event(name: Option<&str>, data: &str, id: Option<&str>) -> ~str {
+    let mut s = ~"";
+    match name {
+        Some(name) => {
+            if name.contains_char('\r') || name.contains_char('\n') {
+                fail!("the event type {:?} has a line break in it", name);
+            }
+            s.push_str(format!("event: {}\n", name));
+        },
+        None => (),
+    }
+    match id {
+        Some(id) => {
+            if id.contains_char('\r') || id.contains_char('\n') {
+                fail!("the event id {:?} has a line break in it", id);
+            }
+            s.push_str(format!("id: {}\n", id));
+        },
+        None => (),
+    }
+    for line in lines(data).iter() {
+        s.push_str(format!("data: {}\n", *line));
+    }
+    s.push_char('\n');
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_event, lines};
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(lines("a"), ~["a"]);
+        assert_eq!(lines("a\r\nb\rc\nd"), ~["a", "b", "c", "d"]);
+        assert_eq!(lines("a\n"), ~["a", ""]);
+        assert_eq!(lines(""), ~[""]);
+    }
+
+    #[test]
+    fn test_format_event() {
+        assert_eq!(format_event(None, "hello", None), ~"data: hello\n\n");
+        assert_eq!(format_event(Some("update"), "line 1\nline 2", Some("42")),
+                   ~"event: update\nid: 42\ndata: line 1\ndata: line 2\n\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_id_with_line_break() {
+        format_event(None, "hello", Some("4\n2"));
+    }
+}
@@ -0,0 +1,84 @@
+/*!
+
+Middleware: reusable pieces of request handling, such as authentication, logging or compression,
+which are stacked around a server's own handler, rather than each handler doing them itself.
+
+```rust
+#[deriving(Clone)]
+struct MyServer {
+    middleware: MiddlewareStack,
+}
+
+impl Server for MyServer {
+    fn handle_request(&self, request: &Request, response: &mut ResponseWriter) {
+        do self.middleware.handle(request, response) |request, response| {
+            // ... handle the request as usual
+        }
+    }
+}
+
+let server = MyServer {
+    middleware: MiddlewareStack::new(~[~Compress as ~Middleware:Send+Freeze,
+                                       ~RequireLogin as ~Middleware:Send+Freeze]),
+};
+```
+
+*/
+
+use extra::arc::Arc;
+use server::{Request, ResponseWriter};
+
+/// A piece of request handling which goes around a handler (and any middleware after it in the
+/// stack). Each method does nothing by default.
+pub trait Middleware {
+    /// Do whatever is to be done before the request is handled. Return `true` if this has answered
+    /// the request itself, as with 401 Unauthorized, say; it is then handled no further, the rest
+    /// of the stack and the handler being skipped.
+    fn before(&self, _request: &Request, _response: &mut ResponseWriter) -> bool {
+        false
+    }
+
+    /// Do whatever is to be done once the request has been handled, with what has been written so
+    /// far of the response; the headers, at least, may already have been written. This is done
+    /// for each middleware whose `before` was done, after the handler (or the middleware which
+    /// answered the request), in the reverse order.
+    fn after(&self, _request: &Request, _response: &mut ResponseWriter) {
+    }
+}
+
+/// A stack of middleware, to be gone through in order before a handler and in reverse after it.
+/// It can't be changed once made; clones share it.
+#[deriving(Clone)]
+pub struct MiddlewareStack {
+    priv middleware: Arc<~[~Middleware:Send+Freeze]>,
+}
+
+impl MiddlewareStack {
+    /// A stack of the middleware given, outermost first.
+    pub fn new(middleware: ~[~Middleware:Send+Freeze]) -> MiddlewareStack {
+        MiddlewareStack {
+            middleware: Arc::new(middleware),
+        }
+    }
+
+    /// Handle a request with the handler, going through the stack around it.
+    pub fn handle(&self, request: &Request, response: &mut ResponseWriter,
+                  handler: &fn(&Request, &mut ResponseWriter)) {
+        let middleware = self.middleware.get();
+        let mut done = 0;
+        let mut answered = false;
+        for m in middleware.iter() {
+            done += 1;
+            if m.before(request, response) {
+                answered = true;
+                break;
+            }
+        }
+        if !answered {
+            handler(request, response);
+        }
+        for m in middleware.slice_to(done).rev_iter() {
+            m.after(request, response);
+        }
+    }
+}
@@ -2,21 +2,49 @@ extern mod extra;
 
 use std::cell::Cell;
 use std::comm::SharedChan;
-use std::task::{spawn_with, spawn_supervised};
-use std::rt::io::{Listener, Acceptor, Writer};
-use std::rt::io::net::ip::SocketAddr;
+use std::task::spawn_with;
+use std::util;
+use std::libc::c_int;
+use std::rt::io::Writer;
+use std::rt::io::net::ip::{SocketAddr, Ipv4Addr, Ipv6Addr};
 use std::rt::io::io_error;
-use extra::time::precise_time_ns;
+use std::rt::io::timer::sleep;
+use extra::arc::{Arc, RWArc};
+use extra::time::{precise_time_ns, now_utc};
 
-use std::rt::io::net::tcp::TcpListener;
+use buffer::{BufferedStream, READ_BUF_SIZE, WRITE_BUF_SIZE};
+use http2;
+use http2::connection::ConnectionConfig;
+use network::{SocketStream, SslSocketStream};
+use self::access_log::{AccessLogger, AccessLogEntry};
+use self::error_handler::SharedErrorHandler;
+use self::observer::SharedObserver;
+use transfer_coding::TransferCodings;
+use socket::{Address, TcpAddress, UnixAddress, Socket, SocketListener, SocketOptions,
+             spawn_blocking, listeners_from_env};
+use self::request::Star;
+use self::response::{write_interim_response, write_closing_response};
+use method::{Method, Options};
+use status;
+use ssl::{SslContext, SslStream};
 
-use buffer::BufferedStream;
-
-pub use self::request::{RequestBuffer, Request};
+pub use self::request::{RequestBuffer, Request, RequestLimits, FramingPolicy};
 pub use self::response::ResponseWriter;
 
+pub mod access_log;
+pub mod auth;
+pub mod conditional;
+pub mod error_handler;
+pub mod event_stream;
+pub mod middleware;
+pub mod observer;
+pub mod proxy;
 pub mod request;
 pub mod response;
+pub mod router;
+pub mod session;
+pub mod staticfile;
+pub mod vhost;
 
 // TODO: when mozilla/rust#7661 is resolved, assuming also that specifying inheritance of kinds for
 // the trait works:
@@ -26,6 +54,42 @@ pub mod response;
 pub trait Server {
 	fn handle_request(&self, request: &Request, response: &mut ResponseWriter) -> ();
 
+    /// Decide whether to accept a request whose client has sent `Expect: 100-continue` and is
+    /// waiting before it sends the body. Return `None` (the default) to have 100 Continue sent and
+    /// the body read, ready for `handle_request`; or return a final status, such as 401
+    /// Unauthorized or 413 Request Entity Too Large, with which the request is to be refused
+    /// without the client having to send the body at all. The request's body is still empty.
+    fn check_continue(&self, _request: &Request) -> Option<status::Status> {
+        None
+    }
+
+    /// Whether the body of the request is to be left for `handle_request` to read as it comes,
+    /// with `ResponseWriter.body_reader`, rather than all being read into `request.body` first.
+    /// This is for such things as large uploads, which would be better not held in memory; by
+    /// default, no bodies are streamed. Whatever of the body is not read is read and discarded
+    /// once the response is finished.
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+
+    /// The most bytes the body of the request may be, or `None` for no limit; by default, the
+    /// `max_body_size` given in the `Config`, which is passed in. Override this to allow, say, an
+    /// upload route more. A body with a Content-Length greater than this is refused with 413
+    /// Request Entity Too Large before it is read; a chunked body is read only until it goes past
+    /// the limit, and then refused, or if it is streamed, `BodyReader.is_too_long` is true and the
+    /// handler should send 413 itself. Either way, the connection is then closed.
+    fn max_body_size(&self, _request: &Request, default: Option<uint>) -> Option<uint> {
+        default
+    }
+
+    /// The methods which the server as a whole supports, with which a request of `OPTIONS *`
+    /// (asking just that) is answered, rather than going to `handle_request`: with 200 OK, Allow
+    /// giving them and an empty body (see `ResponseWriter.write_options`). By default, `None`, and
+    /// the request goes to `handle_request` like any other.
+    fn server_allow(&self) -> Option<~[Method]> {
+        None
+    }
+
 	// XXX: this could also be implemented on the serve methods
 	fn get_config(&self) -> Config;
 }
@@ -34,6 +98,7 @@ pub trait Server {
 /// You'll need to import `ServerUtil` to be able to call `serve_forever` on a Server.
 pub trait ServerUtil {
     fn serve_forever(self);
+    fn serve_until_shutdown(self, control: ServerControl);
 }
 
 impl<T: Send + Clone + Server> ServerUtil for T {
@@ -43,92 +108,736 @@ impl<T: Send + Clone + Server> ServerUtil for T {
 	 * This will only return if the initial connection fails or something else blows up.
 	 */
     fn serve_forever(self) {
+        self.serve_until_shutdown(ServerControl::new());
+    }
+
+    /**
+     * Attempt to bind to the addresses and ports and start serving, until told to shut down with
+     * `control.shutdown()`, from another task; this then returns.
+     */
+    fn serve_until_shutdown(self, control: ServerControl) {
         let config = self.get_config();
-        debug!("About to bind to {:?}", config.bind_address);
-        match TcpListener::bind(config.bind_address).listen() {
-            None => {
-                error!("bind or listen failed :-(");
-                return;
+        let addresses = config.listen_addresses();
+        debug!("About to bind to {:?}", addresses);
+        let ssl_context = match config.ssl {
+            None => None,
+            Some(ref ssl) => match ssl.make_context() {
+                Some(mut context) => {
+                    if config.http2 {
+                        context.set_alpn_protocols([http2::ALPN_PROTOCOL_ID, "http/1.1"]);
+                    }
+                    Some(Arc::new(context))
+                },
+                None => {
+                    error!("unable to load the TLS certificate or private key :-(");
+                    return;
+                },
             },
-            Some(ref mut acceptor) => {
-                debug!("listening");
-                let (perf_po, perf_ch) = stream();
-                let perf_ch = SharedChan::new(perf_ch);
-                spawn_with(perf_po, perf_dumper);
-                loop {
-                    // OK, we're sort of shadowing an IoError here. Perhaps this should be done in a
-                    // separate task so that it can safely fail...
-                    let mut error = None;
-                    let optstream = io_error::cond.trap(|e| {
-                        error = Some(e);
-                    }).inside(|| {
-                        acceptor.accept()
+        };
+        let mut listeners = ~[];
+        let mut listening_addresses = ~[];
+        for address in addresses.iter() {
+            let mut error = None;
+            let listener = do io_error::cond.trap(|e| error = Some(e)).inside {
+                let options = &config.socket_options;
+                match *address {
+                    TcpAddress(addr) => SocketListener::bind(addr, options),
+                    UnixAddress(ref path) => SocketListener::bind_unix(path, options),
+                }
+            };
+            match listener {
+                None => {
+                    error!("bind or listen failed on {}: {:?} :-(", address.to_str(), error);
+                    return;
+                },
+                Some(listener) => {
+                    // The port chosen, should the address's be 0
+                    listening_addresses.push(match listener.address() {
+                        Some(name) => name,
+                        None => address.clone(),
                     });
+                    listeners.push(listener);
+                },
+            }
+        }
+        for &fd in config.inherited_listeners.iter() {
+            let listener = SocketListener::from_fd(fd, &config.socket_options);
+            match listener.address() {
+                Some(address) => listening_addresses.push(address),
+                None => error!("can't tell what listener {} is listening on", fd),
+            }
+            listeners.push(listener);
+        }
+        if listeners.is_empty() {
+            error!("no addresses to listen on :-(");
+            return;
+        }
+        debug!("listening");
+        do control.state.write |state| {
+            state.listening_addresses = listening_addresses.clone();
+        }
+        let (perf_po, perf_ch) = stream();
+        let perf_ch = SharedChan::new(perf_ch);
+        spawn_with(perf_po, perf_dumper);
+        // Each listener has a task, on a thread of its own, to accept connections on it; this
+        // waits for them all to stop (or fail).
+        let (done_po, done_ch) = stream();
+        let done_ch = SharedChan::new(done_ch);
+        let listener_count = listeners.len();
+        for listener in listeners.move_iter() {
+            let listener = Cell::new(listener);
+            let (child_self, child_config) = (self.clone(), config.clone());
+            let (child_ssl_context, child_perf_ch) = (ssl_context.clone(), perf_ch.clone());
+            let (child_control, child_done_ch) = (control.clone(), done_ch.clone());
+            do spawn_blocking {
+                let mut listener = listener.take();
+                accept_connections(&child_self, &mut listener, &child_config,
+                                   &child_ssl_context, &child_perf_ch, &child_control);
+                child_done_ch.send(());
+            }
+        }
+        // Dropped here, so that should every task fail, `try_recv` doesn't wait forever.
+        util::ignore(done_ch);
+        for _ in range(0, listener_count) {
+            if done_po.try_recv().is_none() {
+                break;
+            }
+        }
+        // No longer listening; give the connections still being served until the deadline.
+        loop {
+            let (connections, deadline) = do control.state.read |state| {
+                (state.connections, state.shutdown_deadline)
+            };
+            match deadline {
+                Some(deadline) if connections > 0 && precise_time_ns() < deadline =>
+                    sleep(SHUTDOWN_POLL_INTERVAL),
+                _ => break,
+            }
+        }
+    }
+}
 
-                    let time_start = precise_time_ns();
-                    if optstream.is_none() {
-                        debug!("accept failed: {:?}", error);
-                        // Question: is this the correct thing to do? We should probably be more
-                        // intelligent, for there are some accept failures that are likely to be
-                        // permanent, such that continuing would be a very bad idea, such as
-                        // ENOBUFS/ENOMEM; and some where it should just be ignored, e.g.
-                        // ECONNABORTED. TODO.
-                        continue;
-                    }
-                    let stream = Cell::new(optstream.unwrap());
-                    let child_perf_ch = perf_ch.clone();
-                    let child_self = self.clone();
-                    do spawn_supervised {
-                        let mut time_start = time_start;
-                        let mut stream = BufferedStream::new(stream.take(),
-                                                             /* TcpStream.flush() fails! */ false);
-                        debug!("accepted connection, got {:?}", stream);
-                        loop {  // A keep-alive loop, condition at end
-                            let time_spawned = precise_time_ns();
-                            let (request, err_status) = Request::load(&mut stream);
-                            let time_request_made = precise_time_ns();
-                            let mut response = ~ResponseWriter::new(&mut stream, request);
-                            let time_response_made = precise_time_ns();
-                            match err_status {
-                                Ok(()) => {
-                                    child_self.handle_request(request, response);
-                                    // Ensure that we actually do send a response:
-                                    response.try_write_headers();
-                                },
-                                Err(status) => {
-                                    // Uh oh, it's a response that I as a server cannot cope with.
-                                    // No good user-agent should have caused this, so for the moment
-                                    // at least I am content to send no body in the response.
-                                    response.status = status;
-                                    response.headers.content_length = Some(0);
-                                    response.write_headers();
-                                },
-                            }
-                            // Ensure the request is flushed, any Transfer-Encoding completed, etc.
-                            response.finish_response();
-                            let time_finished = precise_time_ns();
-                            child_perf_ch.send((time_start, time_spawned, time_request_made, time_response_made, time_finished));
-
-                            // Subsequent requests on this connection have no spawn time
-                            time_start = time_finished;
+/// Accept connections on a listener and serve each in a task (and thread) of its own, until the
+/// server is told to shut down.
+fn accept_connections<T: Send + Clone + Server>(server: &T, listener: &mut SocketListener,
+                                                config: &Config,
+                                                ssl_context: &Option<Arc<SslContext>>,
+                                                perf_ch: &SharedChan<(u64, u64, u64, u64, u64)>,
+                                                control: &ServerControl) {
+    let (read_buffer_size, write_buffer_size) = (config.read_buffer_size,
+                                                 config.write_buffer_size);
+    let (head_timeout, min_body_rate) = (config.head_timeout, config.min_body_rate);
+    let (idle_timeout, idle_timeout_response) = (config.idle_timeout,
+                                                 config.idle_timeout_response);
+    let write_timeout = config.write_timeout;
+    let request_limits = config.request_limits.clone();
+    let default_max_body_size = config.max_body_size;
+    let access_log = config.access_log.clone();
+    let observer = config.observer.clone();
+    let error_handler = config.error_handler.clone();
+    let transfer_codings = config.transfer_codings.clone();
+    let verify_content_md5 = config.verify_content_md5;
+    let trusted_proxy_hops = config.trusted_proxy_hops;
+    let http2_enabled = config.http2;
+    let h2_config = ConnectionConfig {
+        max_body_size: default_max_body_size,
+        trusted_proxy_hops: trusted_proxy_hops,
+        access_log: access_log.clone(),
+        observer: observer.clone(),
+        error_handler: error_handler.clone(),
+    };
+    loop {
+        if control.is_shutting_down() {
+            break;
+        }
+        // OK, we're sort of shadowing an IoError here. Perhaps this should be done in a
+        // separate task so that it can safely fail...
+        let mut error = None;
+        let optstream = io_error::cond.trap(|e| {
+            error = Some(e);
+        }).inside(|| {
+            listener.accept()
+        });
 
-                            if request.close_connection {
-                                break;
+        let time_start = precise_time_ns();
+        if control.is_shutting_down() {
+            // This is likely the connection made by `shutdown` to wake us.
+            break;
+        }
+        if optstream.is_none() {
+            debug!("accept failed: {:?}", error);
+            // Question: is this the correct thing to do? We should probably be more
+            // intelligent, for there are some accept failures that are likely to be
+            // permanent, such that continuing would be a very bad idea, such as
+            // ENOBUFS/ENOMEM; and some where it should just be ignored, e.g.
+            // ECONNABORTED. TODO.
+            continue;
+        }
+        let stream = Cell::new(optstream.unwrap());
+        let child_perf_ch = perf_ch.clone();
+        let child_self = server.clone();
+        let child_ssl_context = ssl_context.clone();
+        let child_control = control.clone();
+        let child_access_log = access_log.clone();
+        let child_observer = observer.clone();
+        let child_error_handler = error_handler.clone();
+        let child_transfer_codings = transfer_codings.clone();
+        let child_h2_config = h2_config.clone();
+        do control.state.write |state| {
+            state.connections += 1;
+        }
+        do spawn_blocking {
+            let _serving = ServingConnection { control: child_control.clone() };
+            let mut time_start = time_start;
+            let mut socket = stream.take();
+            // Should the client stop reading, a write gives up, failing this task, which
+            // closes the connection; should it stop sending, a read gives up, ending it.
+            io_error::cond.trap(|e| {
+                debug!("couldn't set the timeouts: {:?}", e);
+            }).inside(|| {
+                socket.set_read_timeout(idle_timeout);
+                socket.set_write_timeout(write_timeout);
+            });
+            let stream = Cell::new(socket);
+            let stream = match child_ssl_context {
+                None => SocketStream(stream.take()),
+                Some(ref context) => {
+                    // The handshake is done here rather than in the accept loop so
+                    // that a slow or broken client can't hold up other connections.
+                    let ssl_stream = io_error::cond.trap(|e| {
+                        debug!("TLS handshake failed: {:?}", e);
+                    }).inside(|| {
+                        SslStream::accept(context.get(), stream.take())
+                    });
+                    match ssl_stream {
+                        Some(s) => SslSocketStream(s),
+                        None => return,
+                    }
+                },
+            };
+            let mut stream = BufferedStream::with_capacities(
+                stream, /* flushing a Socket does nothing */ false,
+                read_buffer_size, write_buffer_size);
+            debug!("accepted connection, got {:?}", stream);
+            let http2_chosen = match stream.wrapped {
+                SslSocketStream(ref s) =>
+                    s.negotiated_protocol() == Some(http2::ALPN_PROTOCOL_ID.to_owned()),
+                _ => false,
+            };
+            if http2_chosen {
+                http2::serve(&child_self, &mut stream, &child_h2_config);
+                return;
+            }
+            // What the connection is handed over to, if a handler hijacks it
+            let mut hijacker = None;
+            loop {  // A keep-alive loop, condition at end
+                // A persistent connection may be closed by the client at any time
+                // between requests (RFC 2616, section 8.1.4). That's not a bad request;
+                // there's just nothing left to do. Nor is it when the client has sent
+                // nothing for the idle timeout, though it may be told so with 408.
+                match stream.read_byte() {
+                    Some(b) => stream.poke_byte(b),
+                    None => {
+                        if stream.wrapped.read_timed_out() {
+                            debug!("connection idle for too long");
+                            if idle_timeout_response {
+                                write_closing_response(&mut stream, status::RequestTimeout);
                             }
+                        } else {
+                            debug!("connection closed by client");
                         }
+                        break;
+                    },
+                }
+                let time_spawned = precise_time_ns();
+                let time_received = now_utc();
+                stream.reset_read_limits();
+                stream.set_read_deadline(match head_timeout {
+                    Some(ms) => Some(time_spawned + ms * 1_000_000),
+                    None => None,
+                });
+                let (mut request, mut err_status) =
+                    Request::load_head(&mut stream, &request_limits);
+                request.trusted_proxy_hops = trusted_proxy_hops;
+                stream.set_read_deadline(None);
+                if stream.read_timed_out() || stream.wrapped.read_timed_out() {
+                    request.close_connection = true;
+                    err_status = Err(status::RequestTimeout);
+                }
+                stream.set_min_read_rate(min_body_rate);
+                let max_body_size = if err_status.is_ok() {
+                    child_self.max_body_size(request, default_max_body_size)
+                } else {
+                    None
+                };
+                if err_status.is_ok() {
+                    match request.remaining_body_up_to(max_body_size) {
+                        Ok(_) => (),
+                        Err(status) => {
+                            // The body is not to be read, so nor can the connection be
+                            request.close_connection = true;
+                            err_status = Err(status);
+                        },
+                    }
+                }
+                let streaming = err_status.is_ok() && child_self.streams_body(request);
+                if err_status.is_ok() {
+                    match request.expects_continue() {
+                        Ok(true) => match child_self.check_continue(request) {
+                            None => {
+                                write_interim_response(&mut stream, status::Continue);
+                                if !streaming {
+                                    err_status = request.read_body(&mut stream,
+                                                                   max_body_size);
+                                }
+                            },
+                            Some(status) => {
+                                // The client may send the body regardless, and we
+                                // haven't read it; the connection can't be reused.
+                                request.close_connection = true;
+                                err_status = Err(status);
+                            },
+                        },
+                        Ok(false) if !streaming =>
+                            err_status = request.read_body(&mut stream, max_body_size),
+                        Ok(false) => (),
+                        Err(status) => {
+                            request.close_connection = true;
+                            err_status = Err(status);
+                        },
                     }
                 }
+                if stream.read_timed_out() || stream.wrapped.read_timed_out() {
+                    // The rest of the body may yet come; the connection is done with.
+                    request.close_connection = true;
+                    err_status = Err(status::RequestTimeout);
+                }
+                if verify_content_md5 && err_status.is_ok() && !streaming
+                        && !request.content_md5_matches() {
+                    err_status = Err(status::BadRequest);
+                }
+                if child_control.is_shutting_down() {
+                    // Let the client know not to send more on this connection.
+                    request.close_connection = true;
+                }
+                // Unless it can't be, as over TLS, where it would have been chosen by ALPN, or
+                // with the body still to be read, a connection may be upgraded to HTTP/2.
+                let cleartext = match stream.wrapped {
+                    SocketStream(*) => true,
+                    _ => false,
+                };
+                let upgrade = if http2_enabled && cleartext && err_status.is_ok() && !streaming
+                        && !request.close_connection {
+                    http2::upgrade_settings(request)
+                } else {
+                    None
+                };
+                match upgrade {
+                    Some(settings) => {
+                        http2::serve_upgraded(&child_self, &mut stream, &child_h2_config, request,
+                                              settings);
+                        break;
+                    },
+                    None => (),
+                }
+                let time_request_made = precise_time_ns();
+                match child_observer {
+                    Some(ref observer) =>
+                        observer.get().request_received(request, time_request_made - time_spawned),
+                    None => (),
+                }
+                let mut response = if streaming && err_status.is_ok() {
+                    ~ResponseWriter::with_unread_body(&mut stream, request,
+                                                      max_body_size)
+                } else {
+                    ~ResponseWriter::new(&mut stream, request)
+                };
+                match child_observer {
+                    Some(ref observer) => response.set_observer(observer.clone(), time_spawned),
+                    None => (),
+                }
+                match child_error_handler {
+                    Some(ref handler) => response.set_error_handler(handler.clone()),
+                    None => (),
+                }
+                response.set_transfer_codings(child_transfer_codings.clone());
+                let time_response_made = precise_time_ns();
+                match err_status {
+                    Ok(()) => {
+                        let allow = if request.method == Options && request.request_uri == Star {
+                            child_self.server_allow()
+                        } else {
+                            None
+                        };
+                        // Should this fail, the response sends 500 Internal Server Error as it
+                        // is dropped, if it can, and the connection is closed with the task.
+                        match allow {
+                            Some(methods) => response.write_options(methods),
+                            None => child_self.handle_request(request, response),
+                        }
+                        // Ensure that we actually do send a response (unless the connection
+                        // is no longer ours to send it on):
+                        if !response.is_hijacked() {
+                            response.try_write_headers();
+                        }
+                    },
+                    // Uh oh, it's a response that I as a server cannot cope with.
+                    // No good user-agent should have caused this, so unless there is an
+                    // error handler to render it, I am content to send no body in it.
+                    Err(status) => match child_error_handler {
+                        Some(_) => {
+                            response.send_error(status);
+                            response.try_write_headers();
+                        },
+                        None => {
+                            response.status = status;
+                            response.headers.content_length = Some(0);
+                            response.write_headers();
+                        },
+                    },
+                }
+                // Ensure the request is flushed, any Transfer-Encoding completed, etc.
+                response.finish_response();
+                let time_finished = precise_time_ns();
+                match child_access_log {
+                    Some(ref log) => log.log(&AccessLogEntry::new(request, response, time_received,
+                                                                  time_finished - time_spawned)),
+                    None => (),
+                }
+                match child_observer {
+                    Some(ref observer) => observer.get().response_complete(
+                            request, response, time_finished - time_spawned),
+                    None => (),
+                }
+                child_perf_ch.send((time_start, time_spawned, time_request_made, time_response_made, time_finished));
+
+                // Subsequent requests on this connection have no spawn time
+                time_start = time_finished;
+
+                if response.is_hijacked() {
+                    hijacker = response.take_hijacker();
+                    break;
+                }
+                if response.close_connection {
+                    break;
+                }
+            }
+            match hijacker {
+                Some(f) => {
+                    // A WebSocket, say, may be quiet for longer than the idle timeout; how long it
+                    // may be is up to what it has been handed over to.
+                    io_error::cond.trap(|_| ()).inside(|| stream.wrapped.set_read_timeout(None));
+                    f(stream)
+                },
+                None => (),
             }
         }
     }
 }
 
+/// How often, in milliseconds, a server which is shutting down checks whether all its connections
+/// are finished.
+static SHUTDOWN_POLL_INTERVAL: u64 = 10;
+
+/// A handle with which a server may be shut down from another task: pass a clone to
+/// `ServerUtil.serve_until_shutdown` and keep the original.
+///
+/// ```rust
+/// let control = ServerControl::new();
+/// let server_control = control.clone();
+/// do spawn {
+///     MyServer.serve_until_shutdown(server_control);
+/// }
+/// // ...
+/// control.shutdown(5000);
+/// ```
+#[deriving(Clone)]
+pub struct ServerControl {
+    priv state: RWArc<ControlState>,
+}
+
+struct ControlState {
+    // The addresses the server is listening on, once it is.
+    listening_addresses: ~[Address],
+    // Once shutting down, the time (from `precise_time_ns`) by which connections must be done.
+    shutdown_deadline: Option<u64>,
+    // How many connections are being served.
+    connections: uint,
+}
+
+impl ServerControl {
+    /// A handle for a server which has yet to be started.
+    pub fn new() -> ServerControl {
+        ServerControl {
+            state: RWArc::new(ControlState {
+                listening_addresses: ~[],
+                shutdown_deadline: None,
+                connections: 0,
+            }),
+        }
+    }
+
+    /// Have the server stop accepting connections and, once those it has are finished with or
+    /// `grace` milliseconds have passed, whichever is sooner, return from `serve_until_shutdown`.
+    /// A connection which is then still being served is left to finish in its own time, for it
+    /// can't be interrupted; each is closed after the response it is on. One which is idle,
+    /// waiting for a request, stays open until the client closes it or sends another request.
+    ///
+    /// The server is woken from waiting for a connection by connecting to it.
+    pub fn shutdown(&self, grace: u64) {
+        let addresses = do self.state.write |state| {
+            if state.shutdown_deadline.is_none() {
+                state.shutdown_deadline = Some(precise_time_ns() + grace * 1_000_000);
+            }
+            state.listening_addresses.clone()
+        };
+        // If not yet listening, the server will stop before it starts accepting.
+        for address in addresses.move_iter() {
+            let address = match address {
+                TcpAddress(addr) => TcpAddress(match addr.ip {
+                    Ipv4Addr(0, 0, 0, 0) => SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), ..addr },
+                    Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 0) =>
+                        SocketAddr { ip: Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1), ..addr },
+                    _ => addr,
+                }),
+                unix => unix,
+            };
+            io_error::cond.trap(|_| ()).inside(|| Socket::connect(&address));
+        }
+    }
+
+    /// Whether the server has been told to shut down.
+    pub fn is_shutting_down(&self) -> bool {
+        do self.state.read |state| {
+            state.shutdown_deadline.is_some()
+        }
+    }
+}
+
+/// Kept by the task serving a connection for as long as it is, however it finishes, so that the
+/// server knows when all its connections are done with.
+struct ServingConnection {
+    control: ServerControl,
+}
+
+impl Drop for ServingConnection {
+    fn drop(&mut self) {
+        do self.control.state.write |state| {
+            state.connections -= 1;
+        }
+    }
+}
+
 /// The necessary configuration for an HTTP server.
 ///
-/// At present, only the IP address and port to bind to are needed, but it's possible that other
-/// options may turn up later.
+/// `Config::new` gives the defaults for all but the IP address and port to bind to; change the
+/// others from there, as more options may turn up later.
+#[deriving(Clone)]
 pub struct Config {
 	bind_address: SocketAddr,
+
+    /// Further addresses to listen on, such as `[::]:80` as well as `0.0.0.0:80`, or another port;
+    /// connections to any of them are served alike. None by default.
+    additional_bind_addresses: ~[SocketAddr],
+
+    /// Whether to listen on `bind_address` and `additional_bind_addresses`; true by default, but
+    /// false from `Config::new_unix`, for a server to listen only on Unix domain sockets.
+    bind_tcp: bool,
+
+    /// The paths of Unix domain sockets to listen on too, as behind nginx, say, or for other
+    /// processes on the machine to talk to; none by default. Any socket at such a path is replaced
+    /// (see `SocketListener::bind_unix`). A request over one has no `remote_addr`.
+    unix_socket_paths: ~[Path],
+
+    /// Listening sockets to serve on too, as file descriptors passed down by the process which
+    /// started this one, which bound them, so that this can take over from a server listening on
+    /// them without a connection being refused in the meantime (see
+    /// `inherit_listeners_from_env`); none by default.
+    inherited_listeners: ~[c_int],
+
+    /// If set, connections are served over TLS (that is, HTTPS) with this certificate and key.
+    ssl: Option<SslConfig>,
+
+    /// The size of the buffer for reading from each connection; 64KB by default.
+    read_buffer_size: uint,
+
+    /// The size of the buffer for writing to each connection; 64KB by default.
+    write_buffer_size: uint,
+
+    /// How long a client has to send the whole head of a request (the Request-Line and headers),
+    /// in milliseconds from its first byte, before it is answered with 408 Request Timeout; 20s
+    /// by default. This keeps one sending the head a byte at a time from tying up the server.
+    head_timeout: Option<u64>,
+
+    /// How long, in milliseconds, a connection may wait for the client to send anything, whether
+    /// a request or the rest of one, before it is closed, so that one which connects and sends
+    /// nothing doesn't hold its task and socket forever; a minute by default. One which is waiting
+    /// for a request is just closed, unless `idle_timeout_response`; one which stalls part way
+    /// through is answered with 408 Request Timeout. `None` waits as long as it takes.
+    idle_timeout: Option<u64>,
+
+    /// Whether to answer a connection which times out waiting for a request with 408 Request
+    /// Timeout before closing it; false by default, for a client may send a request as it is
+    /// closed, and take the 408 to be for that.
+    idle_timeout_response: bool,
+
+    /// How long, in milliseconds, a write to a client may wait for it to take any of the response
+    /// before the connection is abandoned, so that one which has stopped reading doesn't hold its
+    /// task and buffers forever; a minute by default. `None` waits as long as it takes.
+    write_timeout: Option<u64>,
+
+    /// Limits on the size of the head of a request, and the forms it and its body's framing may
+    /// take.
+    request_limits: RequestLimits,
+
+    /// The slowest a client may send the body of a request, in bytes per second, before it is
+    /// answered with 408 Request Timeout, or, if the body is being streamed, the body is cut
+    /// short; by default, there is no limit.
+    min_body_rate: Option<u64>,
+
+    /// The most bytes the body of a request may be, or `None` for no limit; 16MB by default. A
+    /// longer body is refused with 413 Request Entity Too Large. See `Server.max_body_size` for
+    /// setting it request by request.
+    max_body_size: Option<uint>,
+
+    /// Where to log the requests answered, if anywhere; by default, nowhere.
+    access_log: Option<AccessLogger>,
+
+    /// An observer to be told of each request as it is served, if any; by default, none.
+    observer: Option<SharedObserver>,
+
+    /// What renders error responses, if anything; by default, the status is sent bare (or as
+    /// plain text, from `ResponseWriter.send_error`).
+    error_handler: Option<SharedErrorHandler>,
+
+    /// The transfer-codings, besides chunked, which `ResponseWriter.transfer_code_if_accepted`
+    /// may apply to a response; by default, `gzip` and `deflate`.
+    transfer_codings: TransferCodings,
+
+    /// Whether to check the body of each request against its Content-MD5, if it has one, and
+    /// answer one which doesn't match with 400 Bad Request; false by default. A body which is
+    /// streamed isn't read by the server, so can't be checked by it (see `content_md5::Md5Reader`).
+    verify_content_md5: bool,
+
+    /// How many proxies in front of the server, such as a load balancer, are trusted to say
+    /// where each request came from (see `Request.client_ip`); none by default. Trust only as
+    /// many as there are, for a client can say what it likes in Forwarded and X-Forwarded-For.
+    trusted_proxy_hops: uint,
+
+    /// Options for the listening sockets and the connections accepted on them: TCP_NODELAY,
+    /// SO_REUSEADDR and the listen backlog. By default, those of `SocketOptions::new`.
+    socket_options: SocketOptions,
+
+    /// Whether to speak HTTP/2 (see `http2`) with clients which choose it: by ALPN in the TLS
+    /// handshake, or, without TLS, by asking to upgrade the connection (`Upgrade: h2c`); false by
+    /// default.
+    http2: bool,
+}
+
+impl Config {
+    /// The configuration for a server bound to the address, with everything else the default: no
+    /// TLS, 64KB buffers, a 20s limit on receiving the head of a request and a minute on waiting
+    /// for a request and on each read and write, the default `RequestLimits` and bodies of up to
+    /// 16MB.
+    pub fn new(bind_address: SocketAddr) -> Config {
+        Config {
+            bind_address: bind_address,
+            additional_bind_addresses: ~[],
+            bind_tcp: true,
+            unix_socket_paths: ~[],
+            inherited_listeners: ~[],
+            ssl: None,
+            read_buffer_size: READ_BUF_SIZE,
+            write_buffer_size: WRITE_BUF_SIZE,
+            head_timeout: Some(20_000),
+            idle_timeout: Some(60_000),
+            idle_timeout_response: false,
+            write_timeout: Some(60_000),
+            request_limits: RequestLimits::new(),
+            min_body_rate: None,
+            max_body_size: Some(0x1000000),
+            access_log: None,
+            observer: None,
+            error_handler: None,
+            transfer_codings: TransferCodings::with_defaults(),
+            verify_content_md5: false,
+            trusted_proxy_hops: 0,
+            socket_options: SocketOptions::new(),
+            http2: false,
+        }
+    }
+
+    /// The configuration for a server listening on a Unix domain socket at the path, and not on
+    /// TCP, with everything else the default, as from `Config::new`.
+    pub fn new_unix(path: Path) -> Config {
+        let mut config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 });
+        config.bind_tcp = false;
+        config.unix_socket_paths = ~[path];
+        config
+    }
+
+    /// All the addresses to listen on: `bind_address` and then `additional_bind_addresses`.
+    pub fn bind_addresses(&self) -> ~[SocketAddr] {
+        let mut addresses = ~[self.bind_address];
+        addresses.push_all(self.additional_bind_addresses);
+        addresses
+    }
+
+    /// Serve on the listening sockets passed down as socket activation does, as by systemd (see
+    /// `socket::listeners_from_env`), if there are any, instead of binding to `bind_address` and
+    /// `additional_bind_addresses`; returns whether there were.
+    pub fn inherit_listeners_from_env(&mut self) -> bool {
+        let fds = listeners_from_env();
+        if fds.is_empty() {
+            return false;
+        }
+        self.inherited_listeners.push_all(fds);
+        self.bind_tcp = false;
+        true
+    }
+
+    /// All the addresses to listen on, TCP and Unix: those of `bind_addresses`, unless `bind_tcp`
+    /// is false, and then `unix_socket_paths`.
+    pub fn listen_addresses(&self) -> ~[Address] {
+        let mut addresses = ~[];
+        if self.bind_tcp {
+            for &addr in self.bind_addresses().iter() {
+                addresses.push(TcpAddress(addr));
+            }
+        }
+        for path in self.unix_socket_paths.iter() {
+            addresses.push(UnixAddress(path.clone()));
+        }
+        addresses
+    }
+}
+
+/// The certificate and private key with which an HTTPS server identifies itself.
+///
+/// Both files are loaded once, when the server starts.
+#[deriving(Clone)]
+pub struct SslConfig {
+    /// A PEM file containing the server's certificate, followed by any intermediate certificates.
+    certificate_chain_file: Path,
+
+    /// A PEM file containing the private key belonging to the certificate.
+    private_key_file: Path,
+}
+
+impl SslConfig {
+    /// Create a TLS context for serving with this certificate and key, or `None` if either file
+    /// can't be loaded or they don't match.
+    pub fn make_context(&self) -> Option<SslContext> {
+        let mut context = SslContext::new();
+        // We're not asking clients for certificates.
+        context.set_verify(false);
+        if context.set_certificate_chain_file(&self.certificate_chain_file) &&
+                context.set_private_key_file(&self.private_key_file) {
+            Some(context)
+        } else {
+            None
+        }
+    }
 }
 
 /* Sorry, but Rust isn't ready for this yet; SimpleServer can't be made Clone just yet. (For
@@ -169,7 +878,7 @@ impl Server for SimpleServer {
 /// This is equivalent to
 ///
 /// ~~~ {.rust}
-/// SimpleServer::new(Config { bind_address: socket_addr }, handler).serve_forever();
+/// SimpleServer::new(Config::new(socket_addr), handler).serve_forever();
 /// ~~~
 ///
 /// But it's nicer this way with `do` blocks and closures:
@@ -184,7 +893,7 @@ impl Server for SimpleServer {
 // Please, pretty please, don't correct the word "wresponse".
 #[inline]
 pub fn serve_forever(socket_addr: SocketAddr, handler: ~fn(&Request, &mut ResponseWriter)) {
-    SimpleServer::new(Config { bind_address: socket_addr }, handler).serve_forever();
+    SimpleServer::new(Config::new(socket_addr), handler).serve_forever();
 }
 
 /// 0.0.0.0, port 80: publicly bound to the standard HTTP port.
@@ -205,7 +914,11 @@ fn perf_dumper(perf_po: Port<(u64, u64, u64, u64, u64)>) {
     let mut td_total = 0u64;
     let mut i = 0u64;
     loop {
-        let data = perf_po.recv();
+        // Once the server and all its connections are done with, so is this.
+        let data = match perf_po.try_recv() {
+            Some(data) => data,
+            None => break,
+        };
         let (start, spawned, request_made, response_made, finished) = data;
         td_spawn += spawned - start;
         td_request += request_made - spawned;
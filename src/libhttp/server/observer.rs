@@ -0,0 +1,51 @@
+/*!
+
+Observation of the requests a server serves, as they are served, for metrics, tracing or logging of
+one's own, without the server loop itself having to be changed.
+
+```rust
+struct Counter {
+    chan: SharedChan<u16>,
+}
+
+impl ServerObserver for Counter {
+    fn response_complete(&self, _request: &Request, response: &ResponseWriter, _elapsed_ns: u64) {
+        self.chan.send(response.status.code());
+    }
+}
+
+let mut config = Config::new(address);
+config.observer = Some(Arc::new(~Counter { chan: chan } as ~ServerObserver:Send+Freeze));
+```
+
+An observer is shared by all the tasks serving connections, and so can't be mutated; it must
+send what it observes elsewhere, as above, to be counted up.
+
+*/
+
+use extra::arc::Arc;
+use server::{Request, ResponseWriter};
+
+/// An observer to be told of each request as it is served; see `Config.observer`. Each method does
+/// nothing by default. `elapsed_ns` is the time, in nanoseconds, since the first byte of the
+/// request arrived.
+pub trait ServerObserver {
+    /// The request has been received: its head and, unless it is being streamed, its body. It may
+    /// yet be refused, as malformed or too large, say, in which case it may not be all there.
+    fn request_received(&self, _request: &Request, _elapsed_ns: u64) {
+    }
+
+    /// The Status-Line and headers of the response have been written, though not necessarily sent.
+    fn response_headers_written(&self, _request: &Request, _response: &ResponseWriter,
+                                _elapsed_ns: u64) {
+    }
+
+    /// The response has been finished; all of it has been written.
+    /// `response.body_len_written()` is how much of a body there was.
+    fn response_complete(&self, _request: &Request, _response: &ResponseWriter,
+                         _elapsed_ns: u64) {
+    }
+}
+
+/// An observer, as it is shared between the tasks serving connections.
+pub type SharedObserver = Arc<~ServerObserver:Send+Freeze>;
@@ -0,0 +1,519 @@
+/*!
+
+Proxying: handlers which pass requests on to other servers, and send back their responses.
+
+```rust
+#[deriving(Clone)]
+struct MyGateway {
+    backends: Reverse,
+}
+
+impl Server for MyGateway {
+    fn handle_request(&self, request: &Request, response: &mut ResponseWriter) {
+        self.backends.handle(request, response);
+    }
+}
+
+let backends = ~[FromStr::from_str("http://10.0.0.1:8080/").unwrap(),
+                 FromStr::from_str("http://10.0.0.2:8080/").unwrap()];
+let server = MyGateway { backends: Reverse::new(backends) };
+```
+
+`Forward` is a forward proxy, as clients are configured to use: it is sent requests whose
+Request-URI is an absolute URI, such as `GET http://example.com/ HTTP/1.1`, and makes each of them
+to the server in that URI itself, with the client module. A request for a path alone is refused
+with 400 Bad Request; should the server not be reached, or send a malformed response, it is 502 Bad
+Gateway. CONNECT, by which a client asks for a tunnel through the proxy, as to speak TLS with the
+server past it, is answered with 200 once the server has been connected to; from then on, the
+proxy copies whatever either of them sends to the other, each way in a task of its own, until both
+have finished. A tunnel can only be had from a plain connection, not one over TLS or HTTP/2; CONNECT
+on one of those is refused with 501 Not Implemented.
+
+`Reverse` is a reverse proxy, or gateway, standing in front of one or more servers of one's own:
+the Request-URI of each request is taken to be relative to the base URL of a server (so that a
+request for `/foo?bar`, given a base URL of `http://10.0.0.1:8080/app`, is made for
+`http://10.0.0.1:8080/app/foo?bar`), the servers being taken in turn. Each such request is sent
+with the client's IP address added to X-Forwarded-For.
+
+The headers of a request and of its response go on as they came, but for the hop-by-hop ones (RFC
+2616, section 13.5.1), which are for one connection only: Connection, Keep-Alive, Proxy-Connection,
+TE, Trailer, Transfer-Encoding, Upgrade and those for authentication with the proxy, as well as any
+extension headers which the Connection header names. Both proxies add themselves, as `rust-http`,
+to the Via header of the request and of the response. The response body is sent back a piece at a
+time, as it comes; so is the request body sent on, unless it came chunked, when it must be had
+whole first, for the client module can't send a request body chunked.
+
+*/
+
+use std::cell::Cell;
+use std::rt::io::{Reader, Writer};
+use std::rt::io::extensions::ReaderUtil;
+use std::rt::io::io_error;
+use std::rt::io::net::get_host_addresses;
+use std::rt::io::net::ip::{IpAddr, SocketAddr};
+use extra::arc::RWArc;
+use extra::url::Url;
+use client::{RequestWriter, ResponseReader};
+use client::request::request_target;
+use headers;
+use headers::ExtensionHeaders;
+use headers::connection::{Connection, Token, Close};
+use headers::forwarded::{NodeList, AddressNode};
+use method::Connect;
+use network::{NetworkStream, BufNetworkStream, SocketStream};
+use server::{Request, ResponseWriter};
+use server::request::{RequestUri, AbsoluteUri, AbsolutePath, Authority};
+use socket::{Socket, TcpAddress, spawn_blocking};
+use server::vhost::HostHandler;
+use status;
+use status::Status;
+
+/// The size of the pieces in which bodies are copied from one connection to the other.
+static COPY_BUF_SIZE: uint = 0x2000;
+
+/// The name by which the proxies know themselves in the Via header.
+static VIA_PSEUDONYM: &'static str = "rust-http";
+
+/// A forward proxy.
+pub struct Forward;
+
+impl Forward {
+    /// Make the request to the server in its Request-URI, and send back that server's response;
+    /// or for CONNECT, open a tunnel to it.
+    pub fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        if request.method == Connect {
+            return tunnel(request, response);
+        }
+        let url = match (&request.request_uri, &request.url) {
+            (&AbsoluteUri(_), &Some(ref url)) => url.clone(),
+            _ => return response.send_error(status::BadRequest),
+        };
+        proxy(request, response, upstream_request(request, url));
+    }
+}
+
+impl HostHandler for Forward {
+    fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        self.handle(request, response);
+    }
+}
+
+/// A reverse proxy. Clones share their turn-taking, and so can be had by each task serving a
+/// connection.
+#[deriving(Clone)]
+pub struct Reverse {
+    priv upstreams: ~[Url],
+    priv next: RWArc<uint>,
+}
+
+impl Reverse {
+    /// Proxy requests to the servers with the base URLs given, in turn. These URLs should be of
+    /// a scheme, host, port and perhaps a path, without a query. This fails if there are none.
+    pub fn new(upstreams: ~[Url]) -> Reverse {
+        if upstreams.is_empty() {
+            fail!("Reverse::new() called without any upstream servers");
+        }
+        Reverse {
+            upstreams: upstreams,
+            next: RWArc::new(0),
+        }
+    }
+
+    /// The base URL of the server whose turn it is.
+    fn next_upstream<'a>(&'a self) -> &'a Url {
+        let len = self.upstreams.len();
+        let i = do self.next.write |next| {
+            let i = *next;
+            *next = (i + 1) % len;
+            i
+        };
+        &self.upstreams[i]
+    }
+
+    /// Make the request to the server whose turn it is, and send back that server's response.
+    pub fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        let url = match upstream_url(self.next_upstream(), &request.request_uri) {
+            Some(url) => url,
+            None => return response.send_error(status::BadRequest),
+        };
+        let mut upstream = upstream_request(request, url);
+        match request.remote_addr {
+            Some(addr) => add_forwarded_for(upstream.headers, addr.ip),
+            None => (),
+        }
+        proxy(request, response, upstream);
+    }
+}
+
+impl HostHandler for Reverse {
+    fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        self.handle(request, response);
+    }
+}
+
+/// The URL to which a request for the Request-URI is to be made, given the base URL of a server;
+/// `None` if the Request-URI is not a path or an absolute URI.
+fn upstream_url(base: &Url, request_uri: &RequestUri) -> Option<Url> {
+    let target = match *request_uri {
+        AbsolutePath(ref path) => path.clone(),
+        AbsoluteUri(ref url) => request_target(url),
+        _ => return None,
+    };
+    let base = base.to_str();
+    FromStr::from_str(format!("{}{}", base.trim_right_chars(&'/'), target))
+}
+
+/// The request to be made of another server on behalf of the request being served: its method,
+/// and its headers without the hop-by-hop ones, with Via added to. The Host header is that of the
+/// URL (RFC 2616, section 5.2), not whatever the client sent.
+fn upstream_request(request: &Request, url: Url) -> ~RequestWriter<NetworkStream> {
+    let mut upstream: ~RequestWriter<NetworkStream> =
+            ~RequestWriter::new(request.method.clone(), url);
+    let host = upstream.headers.host.take();
+    upstream.headers = request.headers.clone();
+    upstream.headers.host = host;
+    remove_request_hop_by_hop(upstream.headers);
+    add_via(&mut upstream.headers.via, request.version);
+    upstream
+}
+
+/// Send the request upstream and its response back, or 502 Bad Gateway if there is none.
+fn proxy(request: &Request, response: &mut ResponseWriter,
+         upstream: ~RequestWriter<NetworkStream>) {
+    match send_upstream(request, response, upstream) {
+        Some(upstream_response) => send_back(upstream_response, response),
+        None => response.send_error(status::BadGateway),
+    }
+}
+
+/// Connect to the server the request is for and send it the request, with the body of the
+/// request being served, returning the server's response; `None` is returned if the server
+/// couldn't be connected to or the response couldn't be read.
+fn send_upstream(request: &Request, response: &mut ResponseWriter,
+                 upstream: ~RequestWriter<NetworkStream>)
+        -> Option<ResponseReader<NetworkStream>> {
+    let upstream = Cell::new(upstream);
+    do io_error::cond.trap(|e| {
+        debug!("proxied request failed: {:?}", e);
+    }).inside {
+        let mut upstream = upstream.take();
+        if upstream.connect() {
+            send_body(request, response, upstream);
+            match upstream.read_response() {
+                Ok(upstream_response) => Some(upstream_response),
+                Err(_) => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Send the body of the request being served on to the server, reading what of it the server
+/// streams (see `Server.streams_body`) from the response's `body_reader`.
+fn send_body(request: &Request, response: &mut ResponseWriter,
+             upstream: &mut RequestWriter<NetworkStream>) {
+    if request.headers.transfer_encoding.is_some() {
+        let mut body = request.body.clone();
+        body.push_all_move(response.body_reader().read_to_end());
+        upstream.headers.content_length = Some(body.len());
+        upstream.write(body);
+    } else if request.headers.content_length.is_some() {
+        upstream.headers.content_length = request.headers.content_length;
+        upstream.write(request.body);
+        copy(&mut response.body_reader(), upstream, false);
+    }
+}
+
+/// Send back the server's response: its status, its headers but for the hop-by-hop ones, and
+/// its body, a piece at a time as it comes.
+fn send_back(upstream_response: ResponseReader<NetworkStream>, response: &mut ResponseWriter) {
+    let mut upstream_response = upstream_response;
+    response.status = Status::from_code_and_reason(upstream_response.status.code(),
+                                                   upstream_response.status.reason());
+    let mut headers = upstream_response.headers.clone();
+    remove_response_hop_by_hop(headers);
+    add_via(&mut headers.via, upstream_response.version);
+    response.headers = headers;
+    let mut failed = false;
+    do io_error::cond.trap(|e| {
+        debug!("proxied response failed: {:?}", e);
+        failed = true;
+    }).inside {
+        copy(&mut upstream_response, response, true);
+    }
+    if failed {
+        // The response has been begun, so all that can be done is to close the connection.
+        response.close_connection = true;
+    }
+}
+
+/// Open the tunnel a CONNECT request asks for: connect to the host and port of its Request-URI,
+/// send 200, and hand the connection over to `splice`. A Request-URI which isn't a host and port
+/// is refused with 400 Bad Request; a connection which can't be split in two (see
+/// `ResponseWriter.is_plain_socket`), with 501 Not Implemented; and should the host not be
+/// reached, it is 502 Bad Gateway.
+fn tunnel(request: &Request, response: &mut ResponseWriter) {
+    let (host, port) = match request.request_uri {
+        Authority(ref authority) => match split_authority(*authority) {
+            Some(host_port) => host_port,
+            None => return response.send_error(status::BadRequest),
+        },
+        _ => return response.send_error(status::BadRequest),
+    };
+    if !response.is_plain_socket() {
+        return response.send_error(status::NotImplemented);
+    }
+    let upstream = match connect_upstream(host, port) {
+        Some(upstream) => upstream,
+        None => return response.send_error(status::BadGateway),
+    };
+    response.status = Status::from_code_and_reason(200, ~"Connection Established");
+    response.write_headers();
+    let upstream = Cell::new(upstream);
+    do response.hijack |stream| {
+        splice(stream, upstream.take());
+    }
+}
+
+/// The host and port of a CONNECT request's Request-URI, such as `example.com:443` or
+/// `[::1]:443`; `None` if it has no port, or one which isn't a number.
+fn split_authority(authority: &str) -> Option<(~str, u16)> {
+    let colon = match authority.rfind(':') {
+        Some(colon) => colon,
+        None => return None,
+    };
+    let port = match FromStr::from_str(authority.slice_from(colon + 1)) {
+        Some(port) => port,
+        None => return None,
+    };
+    let host = authority.slice_to(colon);
+    let host = if host.starts_with("[") && host.ends_with("]") {
+        host.slice(1, host.len() - 1)
+    } else {
+        host
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port))
+}
+
+/// Connect to the host, trying each of its addresses in turn; `None` if it isn't found or none of
+/// them can be connected to.
+fn connect_upstream(host: &str, port: u16) -> Option<Socket> {
+    do io_error::cond.trap(|e| {
+        debug!("tunnel to {}:{} failed: {:?}", host, port, e);
+    }).inside {
+        let ips = match get_host_addresses(host) {
+            Some(ips) => ips,
+            None => ~[],
+        };
+        let mut upstream = None;
+        for &ip in ips.iter() {
+            upstream = Socket::connect(&TcpAddress(SocketAddr { ip: ip, port: port }));
+            if upstream.is_some() {
+                break;
+            }
+        }
+        upstream
+    }
+}
+
+/// Copy what the client sends to the server, in a task of its own, beginning with whatever of it
+/// had been read with the CONNECT request, and what the server sends back to the client, until
+/// each has finished sending; as each does, the other is told so, by shutting down that way of
+/// its connection. The connections are closed once both ways are done.
+fn splice(stream: BufNetworkStream, upstream: Socket) {
+    let mut stream = stream;
+    let early = stream.take_read_buffer();
+    let mut client = match stream.unwrap() {
+        SocketStream(socket) => socket,
+        // `tunnel` only hijacks a plain connection
+        _ => return,
+    };
+    let mut upstream = upstream;
+    let clones = do io_error::cond.trap(|e| {
+        debug!("tunnel failed: {:?}", e);
+    }).inside {
+        match (client.try_clone(), upstream.try_clone()) {
+            (Some(client_reader), Some(upstream_reader)) => Some((client_reader, upstream_reader)),
+            _ => None,
+        }
+    };
+    let (client_reader, mut upstream_reader) = match clones {
+        Some(clones) => clones,
+        None => return,
+    };
+
+    let client_reader = Cell::new(client_reader);
+    let upstream_writer = Cell::new(upstream);
+    let early = Cell::new(early);
+    do spawn_blocking {
+        let mut client_reader = client_reader.take();
+        let mut upstream_writer = upstream_writer.take();
+        let early = early.take();
+        do io_error::cond.trap(|e| {
+            debug!("tunnel from the client failed: {:?}", e);
+        }).inside {
+            upstream_writer.write(early);
+            copy(&mut client_reader, &mut upstream_writer, false);
+            upstream_writer.shutdown_write();
+        }
+    }
+
+    do io_error::cond.trap(|e| {
+        debug!("tunnel from the server failed: {:?}", e);
+    }).inside {
+        copy(&mut upstream_reader, &mut client, false);
+        client.shutdown_write();
+    }
+}
+
+/// Copy everything from the reader to the writer, flushing each piece as it is written if
+/// `flush` is true.
+fn copy<R: Reader, W: Writer>(reader: &mut R, writer: &mut W, flush: bool) {
+    let mut buf = [0u8, ..COPY_BUF_SIZE];
+    loop {
+        match reader.read(buf) {
+            Some(n) => {
+                writer.write(buf.slice_to(n));
+                if flush {
+                    writer.flush();
+                }
+            },
+            None => break,
+        }
+    }
+}
+
+/// Add this proxy to a Via header, as having received the message with the HTTP version given.
+fn add_via(via: &mut Option<~str>, version: (uint, uint)) {
+    let (major, minor) = version;
+    let hop = format!("{}.{} {}", major, minor, VIA_PSEUDONYM);
+    *via = Some(match via.take() {
+        Some(via) => format!("{}, {}", via, hop),
+        None => hop,
+    });
+}
+
+/// Add the client's IP address to the X-Forwarded-For header, which lists those of the client and
+/// of each proxy the request has come through, but the last.
+fn add_forwarded_for(headers: &mut headers::request::HeaderCollection, ip: IpAddr) {
+    match headers.x_forwarded_for {
+        Some(NodeList(ref mut nodes)) => nodes.push(AddressNode(ip, None)),
+        None => headers.x_forwarded_for = Some(NodeList(~[AddressNode(ip, None)])),
+    }
+}
+
+/// Remove the hop-by-hop headers from those of a request to be sent on.
+fn remove_request_hop_by_hop(headers: &mut headers::request::HeaderCollection) {
+    remove_hop_by_hop_extensions(&headers.connection, &mut headers.extensions);
+    headers.connection = None;
+    headers.te = None;
+    headers.trailer = None;
+    headers.transfer_encoding = None;
+    headers.upgrade = None;
+    headers.proxy_authorization = None;
+}
+
+/// Remove the hop-by-hop headers from those of a response to be sent back.
+fn remove_response_hop_by_hop(headers: &mut headers::response::HeaderCollection) {
+    remove_hop_by_hop_extensions(&headers.connection, &mut headers.extensions);
+    headers.connection = None;
+    headers.trailer = None;
+    headers.transfer_encoding = None;
+    headers.upgrade = None;
+    headers.proxy_authenticate = None;
+}
+
+/// Remove Keep-Alive and Proxy-Connection (which is sent by some clients in place of Connection),
+/// and any others named in the Connection header, from the extension headers.
+fn remove_hop_by_hop_extensions(connection: &Option<~[Connection]>,
+                                extensions: &mut ExtensionHeaders) {
+    extensions.remove(&~"Keep-Alive");
+    extensions.remove(&~"Proxy-Connection");
+    match *connection {
+        Some(ref tokens) => {
+            for token in tokens.iter() {
+                match *token {
+                    Token(ref name) => { extensions.remove(name); },
+                    Close => (),
+                }
+            }
+        },
+        None => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::net::ip::Ipv4Addr;
+    use headers::connection::{Token, Close};
+    use headers::forwarded::{NodeList, AddressNode};
+    use headers::request::HeaderCollection;
+    use headers::te::TCoding;
+    use super::{remove_request_hop_by_hop, upstream_url, split_authority, add_via,
+                add_forwarded_for};
+    use server::request::{AbsolutePath, AbsoluteUri, Star};
+
+    #[test]
+    fn test_remove_request_hop_by_hop() {
+        let mut headers = HeaderCollection::new();
+        headers.connection = Some(~[Close, Token(~"X-Hop")]);
+        headers.te = Some(~[TCoding { coding: ~"trailers", quality: None }]);
+        headers.upgrade = Some(~"websocket");
+        headers.extensions.insert(~"Keep-Alive", ~"300");
+        headers.extensions.insert(~"X-Hop", ~"1");
+        headers.extensions.insert(~"X-End-To-End", ~"1");
+        remove_request_hop_by_hop(&mut headers);
+        assert!(headers.connection.is_none());
+        assert!(headers.te.is_none());
+        assert!(headers.upgrade.is_none());
+        assert!(headers.extensions.find(&~"Keep-Alive").is_none());
+        assert!(headers.extensions.find(&~"X-Hop").is_none());
+        assert_eq!(headers.extensions.find(&~"X-End-To-End"), Some(&~"1"));
+    }
+
+    #[test]
+    fn test_upstream_url() {
+        let base = FromStr::from_str("http://10.0.0.1:8080/app/").unwrap();
+        assert_eq!(upstream_url(&base, &AbsolutePath(~"/foo/bar?baz=quux")).unwrap().to_str(),
+                   ~"http://10.0.0.1:8080/app/foo/bar?baz=quux");
+        let url = FromStr::from_str("http://example.com/foo").unwrap();
+        assert_eq!(upstream_url(&base, &AbsoluteUri(url)).unwrap().to_str(),
+                   ~"http://10.0.0.1:8080/app/foo");
+        let base = FromStr::from_str("http://10.0.0.1:8080").unwrap();
+        assert_eq!(upstream_url(&base, &AbsolutePath(~"/")).unwrap().to_str(),
+                   ~"http://10.0.0.1:8080/");
+        assert!(upstream_url(&base, &Star).is_none());
+    }
+
+    #[test]
+    fn test_split_authority() {
+        assert_eq!(split_authority("example.com:443"), Some((~"example.com", 443)));
+        assert_eq!(split_authority("[::1]:8443"), Some((~"::1", 8443)));
+        assert_eq!(split_authority("example.com"), None);
+        assert_eq!(split_authority("example.com:https"), None);
+        assert_eq!(split_authority(":443"), None);
+    }
+
+    #[test]
+    fn test_add_via() {
+        let mut via = None;
+        add_via(&mut via, (1, 1));
+        assert_eq!(via, Some(~"1.1 rust-http"));
+        add_via(&mut via, (1, 0));
+        assert_eq!(via, Some(~"1.1 rust-http, 1.0 rust-http"));
+    }
+
+    #[test]
+    fn test_add_forwarded_for() {
+        let mut headers = HeaderCollection::new();
+        add_forwarded_for(&mut headers, Ipv4Addr(10, 0, 0, 3));
+        add_forwarded_for(&mut headers, Ipv4Addr(10, 0, 0, 4));
+        let expected = NodeList(~[AddressNode(Ipv4Addr(10, 0, 0, 3), None),
+                                  AddressNode(Ipv4Addr(10, 0, 0, 4), None)]);
+        assert_eq!(headers.x_forwarded_for, Some(expected));
+    }
+}
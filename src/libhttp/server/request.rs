@@ -1,20 +1,33 @@
-use extra::url::Url;
-use method::{Method, Options};
+use extra::url::{Url, query_to_str};
+use method::{Method, Options, Get};
 use status;
-use std::rt::io::Stream;
-use std::rt::io::net::ip::SocketAddr;
+use std::ascii::StrAsciiExt;
+use std::cmp::min;
+use std::str;
+use std::rt::io::{Reader, Stream};
+use std::rt::io::extensions::ReaderUtil;
+use std::rt::io::mem::BufReader;
+use std::rt::io::net::ip::{SocketAddr, IpAddr};
 use rfc2616::{CR, LF, SP};
 use headers;
-use buffer::{BufferedStream, BufTcpStream};
+use headers::forwarded::{ForwardedList, NodeList, Node, UnknownNode};
+use headers::host::Host;
+use buffer::{BufferedStream, ChunkedDecoder};
+use charset::{Decoder, StandardDecoder, decode_body};
+use content_md5;
+use network::BufNetworkStream;
 use common::read_http_version;
+use multipart;
+use multipart::MultipartReader;
+use percent_encoding;
 
 use headers::{HeaderLineErr, EndOfFile, EndOfHeaders, MalformedHeaderSyntax, MalformedHeaderValue};
+use headers::RawHeader;
 
 /// Line/header can't be more than 4KB long (note that with the compacting of LWS the actual source
 /// data could be longer than 4KB)
 static MAX_LINE_LEN: uint = 0x1000;
 
-static MAX_REQUEST_URI_LEN: uint = 1024;
 pub static MAX_METHOD_LEN: uint = 64;
 static MAX_HTTP_VERSION_LEN: uint = 1024;
 
@@ -48,12 +61,15 @@ impl<'self, S: Stream> RequestBuffer<'self, S> {
         };
 
         let request_uri = match self.read_request_uri() {
-            Ok(m) => m,
+            Ok((m, false)) => m,
+            // A Simple-Request of HTTP/0.9 (RFC 1945, section 4.1) ends with the Request-URI
+            Ok((m, true)) => return Ok((method, m, (0, 9))),
             Err(e) => return Err(e),
         };
 
         match (read_http_version(self.stream, CR), self.stream.read_byte()) {
             (Some(vv), Some(b)) if b == LF => Ok((method, request_uri, vv)),
+            _ if self.stream.read_limit_reached() => Err(status::RequestUriTooLong),
             _ => return Err(status::BadRequest),
         }
     }
@@ -63,24 +79,31 @@ impl<'self, S: Stream> RequestBuffer<'self, S> {
         include!("../generated/read_method.rs");
     }
 
+    /// Read the Request-URI, and the SP after it or, should there be no HTTP-Version, the end of
+    /// the line; which it was is returned with it, `true` for the end of the line.
     #[inline]
-    fn read_request_uri(&mut self) -> Result<RequestUri, status::Status> {
+    fn read_request_uri(&mut self) -> Result<(RequestUri, bool), status::Status> {
         // Got that, including consuming the SP; now get the request_uri
         let mut request_uri = ~"";
+        let mut line_ended = false;
         loop {
             match self.stream.read_byte() {
+                // The Request-Line is limited in length (see `RequestLimits`)
+                None if self.stream.read_limit_reached() => return Err(status::RequestUriTooLong),
                 None => return Err(status::BadRequest),
                 Some(b) if b == SP => break,
-                Some(b) => {
-                    if request_uri.len() == MAX_REQUEST_URI_LEN {
-                        return Err(status::RequestUriTooLong)
+                Some(b) if b == CR || b == LF => {
+                    if b == CR && self.stream.read_byte() != Some(LF) {
+                        return Err(status::BadRequest);
                     }
-                    request_uri.push_char(b as char);
-                }
+                    line_ended = true;
+                    break;
+                },
+                Some(b) => request_uri.push_char(b as char),
             }
         }
         match FromStr::from_str(request_uri) {
-            Some(r) => Ok(r),
+            Some(r) => Ok((r, line_ended)),
             None => Err(status::BadRequest),
         }
     }
@@ -98,8 +121,39 @@ impl<'self, S: Stream> RequestBuffer<'self, S> {
     /// - `MalformedHeaderValue`: header's value is invalid; normally, ignore it.
     /// - `MalformedHeaderSyntax`: bad request; you could drop it or try returning 400 Bad Request
     pub fn read_header<T: headers::HeaderEnum>(&mut self) -> Result<T, HeaderLineErr> {
-        match headers::header_enum_from_stream(self.stream) {
-        //match headers::HeaderEnum::from_stream(self.stream) {
+        self.read_header_field(true)
+    }
+
+    /// Read a header as `read_header` does, but, unless `allow_folding`, take one continued on
+    /// further lines (obs-fold) as `MalformedHeaderSyntax` rather than unfolding it.
+    pub fn read_header_field<T: headers::HeaderEnum>(&mut self, allow_folding: bool)
+            -> Result<T, HeaderLineErr> {
+        let (header, next_byte) = headers::header_field_from_stream(self.stream, allow_folding);
+        self.finish_header_field(header, next_byte)
+    }
+
+    /// Read a header as `read_header_field` does, and the name it was sent with (see
+    /// `headers::named_header_field_from_stream`).
+    pub fn read_named_header_field<T: headers::HeaderEnum>(&mut self, allow_folding: bool)
+            -> (Result<T, HeaderLineErr>, ~str) {
+        let (header, next_byte, name) =
+            headers::named_header_field_from_stream(self.stream, allow_folding);
+        (self.finish_header_field(header, next_byte), name)
+    }
+
+    /// Read a header as `read_header_field` does, and the field as it was received, unless there
+    /// was none to read (see `headers::raw_header_field_from_stream`).
+    pub fn read_raw_header_field<T: headers::HeaderEnum>(&mut self, allow_folding: bool)
+            -> (Result<T, HeaderLineErr>, Option<RawHeader>) {
+        let (header, next_byte, raw) =
+            headers::raw_header_field_from_stream(self.stream, allow_folding);
+        (self.finish_header_field(header, next_byte), raw)
+    }
+
+    /// Put the byte read after a header back, to be read with the next.
+    fn finish_header_field<T>(&mut self, header: Result<T, HeaderLineErr>, next_byte: Option<u8>)
+            -> Result<T, HeaderLineErr> {
+        match (header, next_byte) {
             (Err(m), None) => Err(m),
             (Err(m), Some(b)) => {
                 self.stream.poke_byte(b);
@@ -118,7 +172,82 @@ impl<'self, S: Stream> RequestBuffer<'self, S> {
     }
 }
 
+/// Limits on the size of the head of a request, beyond which it is refused, so that a client can't
+/// have the server use up all its memory reading one, and on the obsolete forms it may take; and
+/// whether to keep its header fields as they were received as well as interpreted.
+#[deriving(Clone)]
+pub struct RequestLimits {
+    /// The longest the Request-Line may be, in bytes; 8KB by default. A longer one is refused
+    /// with 414 Request-URI Too Long.
+    max_request_line_len: uint,
+
+    /// The most bytes the headers may take up, altogether; 64KB by default. More are refused with
+    /// 431 Request Header Fields Too Large.
+    max_headers_len: uint,
+
+    /// The longest a single header field may be, name and value together, in bytes; 8KB by
+    /// default. A longer one is refused with 400 Bad Request.
+    max_header_len: uint,
+
+    /// The most header fields there may be; 100 by default. More are refused with 400 Bad Request.
+    max_header_count: uint,
+
+    /// Whether a header field may be continued on further lines, each beginning with a space or a
+    /// tab (obs-fold, RFC 7230, section 3.2.4), as some old clients still send them; true by
+    /// default. Such a field is unfolded, each line break and the white space after it taken as a
+    /// single space; if it's not allowed, the request is refused with 400 Bad Request.
+    allow_folded_headers: bool,
+
+    /// Whether to keep each header field just as it was received, in `Request.raw_headers`, for
+    /// the likes of a proxy which is to pass them on unchanged; false by default.
+    keep_raw_headers: bool,
+
+    /// What to do with a request whose body could be taken to be framed in more ways than one;
+    /// `RejectAmbiguousFraming` by default.
+    framing_policy: FramingPolicy,
+}
+
+/// What to do with a request whose body could be taken to be framed in more ways than one (RFC
+/// 7230, section 3.3.3): with both Transfer-Encoding and Content-Length, with a Content-Length
+/// repeated, or with Transfer-Encoding in an HTTP/1.0 request, which its recipient mightn't know.
+/// One server taking such a request one way and another, or a proxy in front of it, another, can
+/// be made to find a second request in the first's body (request smuggling); so whatever the
+/// policy, one with Content-Lengths which differ, or which isn't a number, is refused with 400 Bad
+/// Request, as is one with Transfer-Encoding coded other than in the end chunked, and one with a
+/// transfer-coding other than chunked, which we don't know, with 501 Not Implemented.
+#[deriving(Clone, Eq)]
+pub enum FramingPolicy {
+    /// Refuse it with 400 Bad Request.
+    RejectAmbiguousFraming,
+    /// Make it unambiguous: Transfer-Encoding takes precedence over Content-Length, which is
+    /// removed, and the connection is closed once the request has been answered; a Content-Length
+    /// repeated with the same value is taken once. A proxy which forwards the request forwards it
+    /// framed so.
+    NormaliseAmbiguousFraming,
+}
+
+impl RequestLimits {
+    /// The default limits.
+    pub fn new() -> RequestLimits {
+        RequestLimits {
+            max_request_line_len: 0x2000,
+            max_headers_len: 0x10000,
+            max_header_len: 0x2000,
+            max_header_count: 100,
+            allow_folded_headers: true,
+            keep_raw_headers: false,
+            framing_policy: RejectAmbiguousFraming,
+        }
+    }
+}
+
 /// An HTTP request sent to the server.
+///
+/// Its body is normally read into memory before the request is handled, into `body`, which
+/// `body()` gives a reader of. A server which would rather not hold large bodies, such as uploads,
+/// in memory can have them streamed instead (see `Server.streams_body`): the body is then left on
+/// the connection, for the handler to read as it comes, a piece at a time, with
+/// `ResponseWriter.body_reader`, and `body` and `body()` are empty.
 pub struct Request {
     /// The originating IP address of the request.
     remote_addr: Option<SocketAddr>,
@@ -131,8 +260,18 @@ pub struct Request {
     /// The headers sent with the request.
     headers: ~headers::request::HeaderCollection,
 
-    /// The body of the request; empty for such methods as GET.
-    body: ~str,
+    /// The header fields exactly as they were received, in order, including any whose values
+    /// couldn't be interpreted, if `RequestLimits.keep_raw_headers` was set; otherwise empty.
+    raw_headers: ~[RawHeader],
+
+    /// The body of the request; empty for such methods as GET. Any transfer-coding (i.e. chunked)
+    /// has been removed. If the server streams the body (see `Server.streams_body`), this is empty
+    /// too, the body being read with `ResponseWriter.body_reader` instead.
+    body: ~[u8],
+
+    /// The fields of the trailer which followed a body in the chunked transfer-coding, as (name,
+    /// value) pairs in the order they came; names are as they were sent. Empty for other bodies.
+    trailer: ~[(~str, ~str)],
 
     /// The HTTP method for the request.
     method: Method,
@@ -141,12 +280,24 @@ pub struct Request {
     /// You will almost never need to use this; you should prefer the `url` field instead.
     request_uri: RequestUri,
 
+    /// The URL requested, constructed from the Request-URI and, if that is only a path, the Host
+    /// header; its scheme, host and port are those of the request. This is `None` for the `*` and
+    /// authority forms of Request-URI, and for an HTTP/1.0 request for a path which came without a
+    /// Host header.
+    url: Option<Url>,
+
     /// Whether to close the TCP connection when the request has been served.
     /// The alternative is keeping the connection open and waiting for another request.
     close_connection: bool,
 
-    /// The HTTP version number; typically `(1, 1)` or, less commonly, `(1, 0)`.
-    version: (uint, uint)
+    /// The HTTP version number; typically `(1, 1)` or, less commonly, `(1, 0)`, or `(0, 9)` for a
+    /// Simple-Request, which has no headers.
+    version: (uint, uint),
+
+    /// How many proxies in front of the server are trusted to say truly, in Forwarded or
+    /// X-Forwarded-For, where the request came from (see `client_ip`). This is set from
+    /// `Config.trusted_proxy_hops`.
+    trusted_proxy_hops: uint,
 }
 
 /// The URI (Request-URI in RFC 2616) as specified in the Status-Line of an HTTP request
@@ -164,20 +315,24 @@ pub enum RequestUri {
     /// recognize all of its server names, including any aliases, local variations, and the numeric
     /// IP address. An example Request-Line would be:
     /// "GET http://www.w3.org/pub/WWW/TheProject.html HTTP/1.1"'
+    ///
+    /// The host and port of the URL are put in the request's Host header, in place of whatever the
+    /// client sent in it; a URL without a host, or with a port which isn't a number, is refused.
     AbsoluteUri(Url),
 
     /// 'To allow for transition to absoluteURIs in all requests in future versions of HTTP, all
     /// HTTP/1.1 servers MUST accept the absoluteURI form in requests, even though HTTP/1.1 clients
     /// will only generate them in requests to proxies.'
     ///
-    /// TODO: this shouldn't be a string; it should be further parsed. `extra::net::url` has some
-    /// stuff which might help, but isn't public.
+    /// This is the path (and query) exactly as it was sent; `Request.url` has it parsed.
     AbsolutePath(~str),
 
     /// 'The authority form is only used by the CONNECT method (CONNECT).'
     ///
     /// TODO: this shouldn't be a string; it should be further parsed. `extra::net::url` has some
     /// stuff which might help, but isn't public.
+    ///
+    /// `server::proxy::Forward` serves such a request as a tunnel.
     Authority(~str),
 }
 
@@ -201,53 +356,141 @@ impl FromStr for RequestUri {
     }
 }
 
+impl ToStr for RequestUri {
+    /// The Request-URI as it would be sent in a Request-Line.
+    fn to_str(&self) -> ~str {
+        match *self {
+            Star => ~"*",
+            AbsoluteUri(ref url) => url.to_str(),
+            AbsolutePath(ref path) => path.clone(),
+            Authority(ref authority) => authority.clone(),
+        }
+    }
+}
+
 impl Request {
 
     /// Get a response from an open socket.
-    pub fn load(stream: &mut BufTcpStream) -> (~Request, Result<(), status::Status>) {
+    pub fn load(stream: &mut BufNetworkStream) -> (~Request, Result<(), status::Status>) {
+        let (mut request, result) = Request::load_head(stream, &RequestLimits::new());
+        if result.is_ok() {
+            request.read_body(stream, None);
+        }
+        (request, result)
+    }
+
+    /// Read the Request-Line and headers of a request from an open socket, but not the body,
+    /// which is left to be read by `read_body`. A request larger than the limits allow is refused.
+    pub fn load_head(stream: &mut BufNetworkStream, limits: &RequestLimits)
+            -> (~Request, Result<(), status::Status>) {
+        let result = Request::read_head(stream, limits);
+        stream.set_read_limit(None);
+        result
+    }
+
+    fn read_head(stream: &mut BufNetworkStream, limits: &RequestLimits)
+            -> (~Request, Result<(), status::Status>) {
         let mut buffer = RequestBuffer::new(stream);
 
         // Start out with dummy values
         let mut request = ~Request {
             remote_addr: buffer.stream.wrapped.peer_name(),
             headers: ~headers::request::HeaderCollection::new(),
-            body: ~"",
+            raw_headers: ~[],
+            body: ~[],
+            trailer: ~[],
             method: Options,
             request_uri: Star,
+            url: None,
             close_connection: true,
             version: (0, 0),
+            trusted_proxy_hops: 0,
         };
 
+        buffer.stream.set_read_limit(Some(limits.max_request_line_len));
         let (method, request_uri, version) = match buffer.read_request_line() {
             Ok(vals) => vals,
             Err(err) => return (request, Err(err)),
         };
+        buffer.stream.set_read_limit(Some(limits.max_headers_len));
         request.method = method;
         request.request_uri = request_uri;
         request.version = version;
+        // The asterisk is only for OPTIONS (RFC 7230, section 5.3.4).
+        if request.request_uri == Star && request.method != Options {
+            return (request, Err(status::BadRequest));
+        }
 
         // request.close_connection is deliberately left set to true so that in case of a bad
         // request we can close the connection
         let close_connection = match version {
+            (0, 9) => {
+                // A Simple-Request has no headers, and is only ever for GET; its response is the
+                // body alone, and the connection is closed after it (see `ResponseWriter`)
+                let result = if request.method == Get { Ok(()) } else { Err(status::BadRequest) };
+                return (request, result);
+            },
             (1, 0) => true,
             (1, 1) => false,
             _ => return (request, Err(status::HttpVersionNotSupported)),
         };
 
+        let mut header_count = 0;
         loop {
-            match buffer.read_header() {
+            // Each header field is limited in length, as well as all of them together
+            let headers_remaining = buffer.stream.read_limit().unwrap();
+            let field_limit = min(headers_remaining, limits.max_header_len);
+            buffer.stream.set_read_limit(Some(field_limit));
+            let (header, name, raw) = if limits.keep_raw_headers {
+                let (header, raw) = buffer.read_raw_header_field(limits.allow_folded_headers);
+                let name = match raw {
+                    Some(ref raw) => str::from_utf8(raw.name),
+                    None => ~"",
+                };
+                (header, name, raw)
+            } else {
+                let (header, name) = buffer.read_named_header_field(limits.allow_folded_headers);
+                (header, name, None)
+            };
+            if buffer.stream.read_limit_reached() {
+                // The field has been cut short, and may have been parsed as something it isn't
+                return (request, Err(if field_limit < headers_remaining {
+                    status::BadRequest
+                } else {
+                    status::RequestHeaderFieldsTooLarge
+                }));
+            }
+            let field_len = field_limit - buffer.stream.read_limit().unwrap();
+            buffer.stream.set_read_limit(Some(headers_remaining - field_len));
+            match raw {
+                Some(raw) => request.raw_headers.push(raw),
+                None => (),
+            }
+            match header {
+                Err(EndOfFile) if buffer.stream.read_timed_out() =>
+                    return (request, Err(status::RequestTimeout)),
                 Err(EndOfFile) => fail!("client disconnected, nowhere to send response"),
                 Err(EndOfHeaders) => break,
                 Err(MalformedHeaderSyntax) => {
                     println("BAD REQUEST: malformed header (TODO: is this right?)");
                     return (request, Err(status::BadRequest));
                 },
+                Ok(_) | Err(MalformedHeaderValue) if header_count == limits.max_header_count => {
+                    debug!("BAD REQUEST: too many headers");
+                    return (request, Err(status::BadRequest));
+                },
+                // Without them, the body can't be told from what follows it (see `check_framing`)
+                Err(MalformedHeaderValue) if name.eq_ignore_ascii_case("Content-Length") ||
+                                             name.eq_ignore_ascii_case("Transfer-Encoding") =>
+                    return (request, Err(status::BadRequest)),
                 Err(MalformedHeaderValue) => {
                     println("Bad header encountered. TODO: handle this better.");
                     // Now just ignore the header
+                    header_count += 1;
                 },
                 Ok(header) => {
-                    request.headers.insert(header);
+                    request.headers.push(header);
+                    header_count += 1;
                 },
             }
         }
@@ -258,6 +501,21 @@ impl Request {
             return (request, Err(status::BadRequest));
         }
 
+        // A request in absolute form names its host itself, and that is the host which it is for,
+        // whatever Host says; Host is replaced by it (RFC 7230, section 5.4)
+        let target = match request.request_uri {
+            AbsoluteUri(ref url) => Some(target_host(url)),
+            _ => None,
+        };
+        match target {
+            Some(Some(host)) => request.headers.host = Some(host),
+            Some(None) => return (request, Err(status::BadRequest)),
+            None => (),
+        }
+
+        request.url = request_url(&request.request_uri, &request.headers.host,
+                                  buffer.stream.wrapped.is_secure());
+
         request.close_connection = close_connection;
         match request.headers.connection {
             Some(ref h) => for v in h.iter() {
@@ -266,7 +524,8 @@ impl Request {
                         request.close_connection = true;
                         break;
                     },
-                    headers::connection::Token(ref s) if s.as_slice() == "keep-alive" => {
+                    // Tokens are normalised to header name case when they are read
+                    headers::connection::Token(ref s) if s.as_slice() == "Keep-Alive" => {
                         request.close_connection = false;
                         // No break; let it be overridden by close should some weird person do that
                     },
@@ -276,11 +535,428 @@ impl Request {
             None => (),
         }
 
-        (request, Ok(()))
+        match check_framing(&mut *request, limits.framing_policy) {
+            Ok(()) => (request, Ok(())),
+            Err(status) => {
+                request.close_connection = true;
+                (request, Err(status))
+            },
+        }
+    }
+
+    /// Read the body of the request, its head having been read by `load_head`, so long as it is
+    /// no longer than `max_size` bytes (see `remaining_body_up_to`). One which is longer is
+    /// refused with `Err(status::RequestEntityTooLarge)`, having been read only so far, if at all,
+    /// and so the connection is to be closed.
+    pub fn read_body(&mut self, stream: &mut BufNetworkStream, max_size: Option<uint>)
+            -> Result<(), status::Status> {
+        let mut remaining = match self.remaining_body_up_to(max_size) {
+            Ok(remaining) => remaining,
+            Err(status) => {
+                self.close_connection = true;
+                return Err(status);
+            },
+        };
+        // Reading stops short if the connection closes or reading times out.
+        let mut reader = BodyReader::new(stream, &mut remaining);
+        self.body = reader.read_to_end();
+        self.trailer = reader.take_trailer();
+        if reader.is_too_long() {
+            self.close_connection = true;
+            return Err(status::RequestEntityTooLarge);
+        }
+        Ok(())
+    }
+
+    /// How the body of the request is delimited on the connection, as it will be, or was, before
+    /// it is read. The chunked transfer-coding takes precedence over Content-Length, which must be
+    /// ignored if both are present (RFC 2616, section 4.4); in the absence of both, there is no
+    /// body.
+    pub fn remaining_body(&self) -> RemainingBody {
+        let chunked = match self.headers.transfer_encoding {
+            Some(ref codings) => codings.iter().any(|c| *c == headers::transfer_encoding::Chunked),
+            None => false,
+        };
+        if chunked {
+            return RemainingChunks(ChunkedDecoder::new());
+        }
+        match self.headers.content_length {
+            Some(length) if length > 0 => RemainingBytes(length),
+            _ => NoRemainingBody,
+        }
+    }
+
+    /// How the body of the request is delimited, as for `remaining_body`, but limited to
+    /// `max_size` bytes, or with `None`, not limited. A body with a Content-Length greater than
+    /// that is `Err(status::RequestEntityTooLarge)`, refused without being read; a chunked body's
+    /// length isn't known until it has been read, and reading it stops should it go past the limit
+    /// (see `BodyReader.is_too_long`).
+    pub fn remaining_body_up_to(&self, max_size: Option<uint>)
+            -> Result<RemainingBody, status::Status> {
+        match (self.remaining_body(), max_size) {
+            (RemainingBytes(length), Some(max_size)) if length > max_size =>
+                Err(status::RequestEntityTooLarge),
+            (RemainingChunks(mut decoder), _) => {
+                decoder.set_max_len(max_size);
+                Ok(RemainingChunks(decoder))
+            },
+            (remaining, _) => Ok(remaining),
+        }
+    }
+
+    /// Whether the client is waiting to be sent `100 Continue` before it sends the body, having
+    /// sent `Expect: 100-continue` (RFC 2616, section 8.2.3). An HTTP/1.0 client can't have meant
+    /// that, so for such this is always `Ok(false)`. Any other expectation can't be met, and
+    /// `Err(status::ExpectationFailed)` is returned.
+    pub fn expects_continue(&self) -> Result<bool, status::Status> {
+        match self.headers.expect {
+            Some(ref expectation) if expectation.eq_ignore_ascii_case("100-continue") =>
+                Ok(self.version >= (1, 1)),
+            Some(_) => Err(status::ExpectationFailed),
+            None => Ok(false),
+        }
+    }
+
+    /// The query string of the Request-URI: the part after the `?`, still encoded. `None` if there
+    /// is no query string.
+    pub fn query_string(&self) -> Option<~str> {
+        match self.request_uri {
+            AbsolutePath(ref path) => match path.find('?') {
+                Some(i) => Some(path.slice_from(i + 1).split_iter('#').next().unwrap().to_owned()),
+                None => None,
+            },
+            AbsoluteUri(ref url) if !url.query.is_empty() => Some(query_to_str(&url.query)),
+            _ => None,
+        }
+    }
+
+    /// The parameters in the query string of the Request-URI, decoded, in the order in which
+    /// they were given; a key given more than once appears more than once.
+    pub fn query_params(&self) -> ~[(~str, ~str)] {
+        match self.query_string() {
+            Some(query) => parse_query_string(query),
+            None => ~[],
+        }
+    }
+
+    /// The fields of a form submitted in the body of the request, decoded, in the order in which
+    /// they were given, as for `query_params`. `None` is returned if the body is not of the type
+    /// `application/x-www-form-urlencoded`.
+    pub fn form_params(&self) -> Option<~[(~str, ~str)]> {
+        match self.headers.content_type {
+            Some(ref media_type) if media_type.type_.eq_ignore_ascii_case("application") &&
+                    media_type.subtype.eq_ignore_ascii_case("x-www-form-urlencoded") => {
+                // The body should be ASCII, but a browser may well have sent a non-ASCII
+                // character without encoding it.
+                let body = percent_encoding::bytes_to_str(self.body.clone());
+                Some(parse_query_string(body))
+            },
+            _ => None,
+        }
+    }
+
+    /// The absolute URL for a reference relative to the URL requested: a full path, as `/login`,
+    /// or one relative to the directory of the path requested, as `edit`. A redirect should have
+    /// such a URL for its Location (see `ResponseWriter.redirect`). `None` is returned if the
+    /// request has no `url`.
+    pub fn absolute_url(&self, reference: &str) -> Option<Url> {
+        match self.url {
+            Some(ref url) => absolute_url(url, reference),
+            None => None,
+        }
+    }
+
+    /// The IP address of the client which made the request. Unless proxies are trusted (see
+    /// `trusted_proxy_hops`), this is that of `remote_addr`; if they are, each trusted proxy is
+    /// taken at its word as to the one before it, as listed in Forwarded (or, if there is no
+    /// Forwarded header, X-Forwarded-For). Anything before those is what the client itself said,
+    /// and can't be trusted. `None` is returned if the address isn't known, as when a proxy says
+    /// `unknown`.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        let peer = match self.remote_addr {
+            Some(addr) => addr.ip,
+            None => return None,
+        };
+        let nodes: ~[Node] = match (&self.headers.forwarded, &self.headers.x_forwarded_for) {
+            (&Some(ForwardedList(ref elements)), _) => elements.iter().map(|e| match e.for_node {
+                Some(ref node) => node.clone(),
+                None => UnknownNode,
+            }).collect(),
+            (_, &Some(NodeList(ref nodes))) => nodes.clone(),
+            _ => ~[],
+        };
+        let hops = self.trusted_proxy_hops;
+        if hops == 0 || nodes.is_empty() {
+            Some(peer)
+        } else if hops >= nodes.len() {
+            nodes[0].ip()
+        } else {
+            nodes[nodes.len() - hops].ip()
+        }
+    }
+
+    /// A reader of the body, with any transfer-coding removed, if it has been read into memory; if
+    /// the server streams it, this is empty, and `ResponseWriter.body_reader` reads it instead.
+    pub fn body<'a>(&'a self) -> BufReader<'a> {
+        BufReader::new(self.body)
+    }
+
+    /// A reader of the parts of a multipart body, as a form with file uploads is submitted in
+    /// (`multipart/form-data`), reading from `body`. `None` is returned if the body is not
+    /// multipart. This needs the whole body in memory; for large uploads, stream the body instead
+    /// (see `Server.streams_body`) and put a `MultipartReader::new` over the `BodyReader`.
+    pub fn multipart<'a>(&'a self) -> Option<MultipartReader<BufReader<'a>>> {
+        match self.headers.content_type {
+            Some(ref media_type) => match multipart::boundary(media_type) {
+                Some(boundary) => Some(MultipartReader::new(BufReader::new(self.body),
+                                                            boundary.as_slice())),
+                None => None,
+            },
+            None => None,
+        }
+    }
+
+    /// The body as text, decoded from the charset its Content-Type gives (see `http::charset`);
+    /// `None` if that isn't UTF-8, ISO-8859-1 or US-ASCII, or the body isn't valid in it.
+    pub fn body_text(&self) -> Option<~str> {
+        self.body_text_with(&StandardDecoder)
+    }
+
+    /// The body as text, as from `body_text`, but decoded with the decoder given.
+    pub fn body_text_with<D: Decoder>(&self, decoder: &D) -> Option<~str> {
+        decode_body(&self.headers.content_type, self.body, decoder)
+    }
+
+    /// The Content-MD5 of the body, from the headers, or else from the trailer, if it was given.
+    pub fn content_md5(&self) -> Option<~str> {
+        match self.headers.content_md5 {
+            Some(ref value) => return Some(value.clone()),
+            None => (),
+        }
+        match self.trailer.iter().find(|&&(ref name, _)| name.eq_ignore_ascii_case("Content-MD5")) {
+            Some(&(_, ref value)) => Some(value.clone()),
+            None => None,
+        }
+    }
+
+    /// Whether the body, as it has been read, matches its Content-MD5 (see `http::content_md5`);
+    /// true if it has none. `Config.verify_content_md5` has the server check this itself.
+    pub fn content_md5_matches(&self) -> bool {
+        match self.content_md5() {
+            Some(value) => content_md5::body_matches(self.body, value),
+            None => true,
+        }
     }
 }
 
+/// What of the body of a request is still to be read from the connection.
+pub enum RemainingBody {
+    /// Nothing; there was no body, or it has all been read.
+    NoRemainingBody,
+    /// So many more bytes, the body being delimited by Content-Length.
+    RemainingBytes(uint),
+    /// The rest of a body in the chunked transfer-coding.
+    RemainingChunks(ChunkedDecoder),
+}
+
+impl RemainingBody {
+    /// Whether all of the body has been read.
+    pub fn is_finished(&self) -> bool {
+        match *self {
+            NoRemainingBody => true,
+            RemainingBytes(_) => false,
+            RemainingChunks(ref decoder) => decoder.is_finished(),
+        }
+    }
+
+    /// Whether reading has stopped because the body is longer than it was allowed to be (see
+    /// `Request.remaining_body_up_to`).
+    pub fn is_too_long(&self) -> bool {
+        match *self {
+            RemainingChunks(ref decoder) => decoder.is_too_long(),
+            _ => false,
+        }
+    }
+}
+
+/// A reader for the body of a request as it comes from the connection, rather than from
+/// `Request.body` once it has all been read into memory, so that a large body may be dealt with a
+/// piece at a time. See `Server.streams_body` and `ResponseWriter.body_reader`. Any
+/// transfer-coding has been removed.
+pub struct BodyReader<'self, R> {
+    priv stream: &'self mut BufferedStream<R>,
+    priv remaining: &'self mut RemainingBody,
+}
+
+impl<'self, R: Reader> BodyReader<'self, R> {
+    /// Create a reader for what remains of a body on the stream.
+    pub fn new<'a>(stream: &'a mut BufferedStream<R>, remaining: &'a mut RemainingBody)
+            -> BodyReader<'a, R> {
+        BodyReader {
+            stream: stream,
+            remaining: remaining,
+        }
+    }
+
+    /// Take the fields of the trailer which followed a chunked body, once it has all been read
+    /// (see `ChunkedDecoder.take_trailer`).
+    pub fn take_trailer(&mut self) -> ~[(~str, ~str)] {
+        match *self.remaining {
+            RemainingChunks(ref mut decoder) => decoder.take_trailer(),
+            _ => ~[],
+        }
+    }
+
+    /// Whether reading has stopped short of the end of the body because it is longer than the
+    /// server allows (see `Server.max_body_size`); 413 Request Entity Too Large should be sent.
+    pub fn is_too_long(&self) -> bool {
+        self.remaining.is_too_long()
+    }
+}
+
+impl<'self, R: Reader> Reader for BodyReader<'self, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        let (result, left) = match *self.remaining {
+            NoRemainingBody => return None,
+            RemainingChunks(ref mut decoder) => return decoder.read(&mut *self.stream, buf),
+            RemainingBytes(length) => {
+                let len = min(length, buf.len());
+                match self.stream.read(buf.mut_slice_to(len)) {
+                    Some(n) => (Some(n), length - n),
+                    // The connection closed before the end of the body.
+                    None => (None, 0),
+                }
+            },
+        };
+        *self.remaining = if left == 0 { NoRemainingBody } else { RemainingBytes(left) };
+        result
+    }
+
+    fn eof(&mut self) -> bool {
+        self.remaining.is_finished()
+    }
+}
 
+/// Parse an `application/x-www-form-urlencoded` string, as found in a query string: `&`-separated
+/// `key=value` pairs, in which `+` stands for a space and other characters may be percent-encoded.
+/// A pair with no `=` has an empty value; empty pairs are skipped.
+pub fn parse_query_string(s: &str) -> ~[(~str, ~str)] {
+    let mut params = ~[];
+    for pair in s.split_iter('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, value) = match pair.find('=') {
+            Some(i) => (pair.slice_to(i), pair.slice_from(i + 1)),
+            None => (pair, ""),
+        };
+        params.push((percent_encoding::decode_form_component(key),
+                     percent_encoding::decode_form_component(value)));
+    }
+    params
+}
+
+/// Construct the URL requested from the Request-URI, with the help of the Host header if it is
+/// only a path (RFC 2616, section 5.2). `secure` is whether the request came over TLS, which
+/// determines the scheme of the URL.
+fn request_url(request_uri: &RequestUri, host: &Option<Host>, secure: bool) -> Option<Url> {
+    match (request_uri, host) {
+        (&AbsoluteUri(ref url), _) => Some(url.clone()),
+        (&AbsolutePath(ref path), &Some(ref host)) => {
+            let scheme = if secure { "https" } else { "http" };
+            FromStr::from_str(format!("{}://{}{}", scheme, host.to_str(), *path))
+        },
+        _ => None,
+    }
+}
+
+/// Check that the body of a request is framed in one way only, or make it so, as the policy says
+/// (see `FramingPolicy`); the error is the status with which to refuse it.
+fn check_framing(request: &mut Request, policy: FramingPolicy) -> Result<(), status::Status> {
+    let codings = match request.headers.transfer_encoding {
+        Some(ref codings) => codings.clone(),
+        None => ~[],
+    };
+    if !codings.is_empty() {
+        if codings.iter().any(|c| *c != headers::transfer_encoding::Chunked) {
+            return Err(status::NotImplemented);
+        }
+        // Chunked can't be applied twice
+        if codings.len() > 1 {
+            return Err(status::BadRequest);
+        }
+    }
+
+    let lengths: ~[~str] = request.headers.get_all("Content-Length").collect();
+    if lengths.iter().any(|length| *length != lengths[0]) {
+        return Err(status::BadRequest);
+    }
+    let ambiguous = if codings.is_empty() {
+        lengths.len() > 1
+    } else {
+        !lengths.is_empty() || request.version < (1, 1)
+    };
+    if !ambiguous {
+        return Ok(());
+    }
+    if policy == RejectAmbiguousFraming {
+        return Err(status::BadRequest);
+    }
+    if codings.is_empty() {
+        // The repeats are the same; keep one
+        let length = request.headers.content_length.take();
+        request.headers.remove("Content-Length");
+        request.headers.content_length = length;
+    } else {
+        request.headers.remove("Content-Length");
+        request.close_connection = true;
+    }
+    Ok(())
+}
+
+/// The host named by a Request-URI in absolute form, to stand in the Host header; `None` if there
+/// is none, or the port isn't a number.
+fn target_host(url: &Url) -> Option<Host> {
+    if url.host.is_empty() {
+        return None;
+    }
+    let port = match url.port {
+        Some(ref port) => match FromStr::from_str(port.as_slice()) {
+            Some(port) => Some(port),
+            None => return None,
+        },
+        None => None,
+    };
+    Some(Host { name: url.host.clone(), port: port })
+}
+
+/// Resolve a reference against a URL. A reference which is a path, absolute or relative, keeps the
+/// URL's scheme and authority, and drops its query and fragment unless it has its own; one which
+/// begins `//` keeps only the scheme; and one which is an absolute URL itself is just that.
+pub fn absolute_url(base: &Url, reference: &str) -> Option<Url> {
+    // A scheme comes first, if at all, and ends with a colon, before any '/', '?' or '#'.
+    match reference.find(|c: char| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(i) if i > 0 && reference.char_at(i) == ':' => return FromStr::from_str(reference),
+        _ => (),
+    }
+    if reference.starts_with("//") {
+        return FromStr::from_str(format!("{}:{}", base.scheme, reference));
+    }
+    let authority = match base.port {
+        Some(ref port) => format!("{}:{}", base.host, *port),
+        None => base.host.clone(),
+    };
+    let path = if reference.starts_with("/") {
+        reference.to_owned()
+    } else {
+        let dir = match base.path.rfind('/') {
+            Some(i) => base.path.slice_to(i + 1),
+            None => "/",
+        };
+        dir + reference
+    };
+    FromStr::from_str(format!("{}://{}{}", base.scheme, authority, path))
+}
 
 /* What follows is most of Go's net/http module's definition of Request.
 
@@ -374,3 +1050,346 @@ pub struct Request {
     // This field is ignored by the HTTP client.
     tls: *tls.ConnectionState,
 }*/
+
+#[cfg(test)]
+mod test {
+    use headers;
+    use headers::header_value_from_str;
+    use headers::host::Host;
+    use std::rt::io::Reader;
+    use std::rt::io::net::ip::{SocketAddr, Ipv4Addr};
+    use std::rt::io::extensions::ReaderUtil;
+    use buffer::{BufferedStream, ChunkedDecoder};
+    use memstream::MemReaderFakeStream;
+    use method::Post;
+    use headers::content_type::MediaType;
+    use status;
+    use super::{Request, request_url, target_host, absolute_url, parse_query_string, Star,
+                AbsoluteUri, AbsolutePath, Authority, BodyReader, RemainingBytes, RemainingChunks,
+                NoRemainingBody, check_framing, RejectAmbiguousFraming,
+                NormaliseAmbiguousFraming};
+
+    #[test]
+    fn test_parse_query_string() {
+        assert_eq!(parse_query_string(""), ~[]);
+        assert_eq!(parse_query_string("a=1&b=2&a=3"),
+                   ~[(~"a", ~"1"), (~"b", ~"2"), (~"a", ~"3")]);
+        assert_eq!(parse_query_string("q=rust+http&x=%41%2b%e2%82%ac&&flag&="),
+                   ~[(~"q", ~"rust http"), (~"x", ~"A+€"), (~"flag", ~""), (~"", ~"")]);
+        // Malformed escapes are left alone, and bytes which aren't UTF-8 taken as characters
+        assert_eq!(parse_query_string("a=100%&b=%zz&c=%e9t%e9"),
+                   ~[(~"a", ~"100%"), (~"b", ~"%zz"), (~"c", ~"été")]);
+    }
+
+    #[test]
+    fn test_form_params() {
+        let mut request = Request {
+            remote_addr: None,
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: bytes!("name=J%C3%B6rg+Smith&tag=a&tag=b").to_owned(),
+            trailer: ~[],
+            method: Post,
+            request_uri: AbsolutePath(~"/submit?tag=c"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        };
+        assert_eq!(request.form_params(), None);
+        request.headers.content_type = Some(MediaType(~"Application", ~"x-www-form-urlencoded",
+                                                      ~[(~"charset", ~"UTF-8")]));
+        assert_eq!(request.form_params(),
+                   Some(~[(~"name", ~"Jörg Smith"), (~"tag", ~"a"), (~"tag", ~"b")]));
+        assert_eq!(request.query_params(), ~[(~"tag", ~"c")]);
+
+        request.headers.content_type = Some(MediaType(~"text", ~"plain", ~[]));
+        assert_eq!(request.form_params(), None);
+    }
+
+    #[test]
+    fn test_body() {
+        let mut request = Request {
+            remote_addr: None,
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: bytes!("Hello world").to_owned(),
+            trailer: ~[],
+            method: Post,
+            request_uri: AbsolutePath(~"/"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        };
+        {
+            let mut reader = request.body();
+            assert_eq!(reader.read_bytes(5), bytes!("Hello").to_owned());
+            assert_eq!(reader.read_to_end(), bytes!(" world").to_owned());
+        }
+        // As when the body is streamed
+        request.body = ~[];
+        assert!(request.body().read_to_end().is_empty());
+    }
+
+    #[test]
+    fn test_content_md5_matches() {
+        let mut request = Request {
+            remote_addr: None,
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: bytes!("Hello world").to_owned(),
+            trailer: ~[],
+            method: Post,
+            request_uri: AbsolutePath(~"/"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        };
+        assert!(request.content_md5_matches());
+        request.trailer = ~[(~"content-md5", ~"1B2M2Y8AsgTpgAmY7PhCfg==")];
+        assert_eq!(request.content_md5(), Some(~"1B2M2Y8AsgTpgAmY7PhCfg=="));
+        assert!(!request.content_md5_matches());
+        request.headers.content_md5 = Some(~"PiWWCnnbxptnTNTsZ6csYg==");
+        assert!(request.content_md5_matches());
+        request.body.push('!' as u8);
+        assert!(!request.content_md5_matches());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let mut request = Request {
+            remote_addr: Some(SocketAddr { ip: Ipv4Addr(10, 0, 0, 2), port: 54321 }),
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: ~[],
+            trailer: ~[],
+            method: Post,
+            request_uri: AbsolutePath(~"/"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        };
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(10, 0, 0, 2)));
+        request.trusted_proxy_hops = 1;
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(10, 0, 0, 2)));
+        let forwarded_for = header_value_from_str("198.51.100.1, 192.0.2.7, 10.0.0.1");
+        request.headers.x_forwarded_for = forwarded_for;
+        request.trusted_proxy_hops = 0;
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(10, 0, 0, 2)));
+        request.trusted_proxy_hops = 1;
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(10, 0, 0, 1)));
+        request.trusted_proxy_hops = 2;
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(192, 0, 2, 7)));
+        request.trusted_proxy_hops = 5;
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(198, 51, 100, 1)));
+        // Forwarded is preferred to X-Forwarded-For
+        request.headers.forwarded = header_value_from_str("for=192.0.2.60, for=unknown");
+        request.trusted_proxy_hops = 1;
+        assert_eq!(request.client_ip(), None);
+        request.trusted_proxy_hops = 2;
+        assert_eq!(request.client_ip(), Some(Ipv4Addr(192, 0, 2, 60)));
+    }
+
+    #[test]
+    fn test_expects_continue() {
+        let mut request = Request {
+            remote_addr: None,
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: ~[],
+            trailer: ~[],
+            method: Post,
+            request_uri: AbsolutePath(~"/upload"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        };
+        assert_eq!(request.expects_continue(), Ok(false));
+        request.headers.expect = Some(~"100-Continue");
+        assert_eq!(request.expects_continue(), Ok(true));
+        request.version = (1, 0);
+        assert_eq!(request.expects_continue(), Ok(false));
+        request.headers.expect = Some(~"something-else");
+        assert_eq!(request.expects_continue(), Err(status::ExpectationFailed));
+    }
+
+    #[test]
+    fn test_remaining_body_up_to() {
+        let mut request = Request {
+            remote_addr: None,
+            headers: ~headers::request::HeaderCollection::new(),
+            raw_headers: ~[],
+            body: ~[],
+            trailer: ~[],
+            method: Post,
+            request_uri: AbsolutePath(~"/upload"),
+            url: None,
+            close_connection: false,
+            version: (1, 1),
+            trusted_proxy_hops: 0,
+        };
+        request.headers.content_length = Some(10);
+        match request.remaining_body_up_to(Some(10)) {
+            Ok(RemainingBytes(10)) => (),
+            _ => fail!("a body as long as the limit should be allowed"),
+        }
+        assert!(request.remaining_body_up_to(None).is_ok());
+        match request.remaining_body_up_to(Some(9)) {
+            Err(status::RequestEntityTooLarge) => (),
+            _ => fail!("a body longer than the limit should be refused"),
+        }
+
+        request.headers.transfer_encoding = Some(~[headers::transfer_encoding::Chunked]);
+        let input = bytes!("3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n").to_owned();
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input), false);
+        let mut remaining = request.remaining_body_up_to(Some(5)).unwrap();
+        let mut reader = BodyReader::new(&mut stream, &mut remaining);
+        assert_eq!(reader.read_to_end().as_slice(), bytes!("foo"));
+        assert!(reader.is_too_long());
+    }
+
+    #[test]
+    fn test_check_framing() {
+        fn framed(version: (uint, uint), codings: &[&str], lengths: &[&str]) -> Request {
+            let mut request = Request {
+                remote_addr: None,
+                headers: ~headers::request::HeaderCollection::new(),
+                raw_headers: ~[],
+                body: ~[],
+                trailer: ~[],
+                method: Post,
+                request_uri: AbsolutePath(~"/upload"),
+                url: None,
+                close_connection: false,
+                version: version,
+                trusted_proxy_hops: 0,
+            };
+            for coding in codings.iter() {
+                assert!(request.headers.append("Transfer-Encoding", *coding));
+            }
+            for length in lengths.iter() {
+                assert!(request.headers.append("Content-Length", *length));
+            }
+            request
+        }
+
+        let mut request = framed((1, 1), [], ["10"]);
+        assert_eq!(check_framing(&mut request, RejectAmbiguousFraming), Ok(()));
+        let mut request = framed((1, 1), ["chunked"], []);
+        assert_eq!(check_framing(&mut request, RejectAmbiguousFraming), Ok(()));
+        assert!(!request.close_connection);
+
+        // Whatever the policy
+        for &policy in [RejectAmbiguousFraming, NormaliseAmbiguousFraming].iter() {
+            let mut request = framed((1, 1), [], ["10", "11"]);
+            assert_eq!(check_framing(&mut request, policy), Err(status::BadRequest));
+            let mut request = framed((1, 1), ["gzip", "chunked"], []);
+            assert_eq!(check_framing(&mut request, policy), Err(status::NotImplemented));
+            let mut request = framed((1, 1), ["chunked", "chunked"], []);
+            assert_eq!(check_framing(&mut request, policy), Err(status::BadRequest));
+        }
+
+        let mut request = framed((1, 1), ["chunked"], ["10"]);
+        assert_eq!(check_framing(&mut request, RejectAmbiguousFraming), Err(status::BadRequest));
+        assert_eq!(check_framing(&mut request, NormaliseAmbiguousFraming), Ok(()));
+        assert_eq!(request.headers.content_length, None);
+        assert!(request.close_connection);
+        match request.remaining_body() {
+            RemainingChunks(_) => (),
+            _ => fail!("Transfer-Encoding should take precedence over Content-Length"),
+        }
+
+        let mut request = framed((1, 1), [], ["10", "10"]);
+        assert_eq!(check_framing(&mut request, RejectAmbiguousFraming), Err(status::BadRequest));
+        assert_eq!(check_framing(&mut request, NormaliseAmbiguousFraming), Ok(()));
+        assert_eq!(request.headers.get_all("Content-Length").collect::<~[~str]>(), ~[~"10"]);
+        assert!(!request.close_connection);
+
+        let mut request = framed((1, 0), ["chunked"], []);
+        assert_eq!(check_framing(&mut request, RejectAmbiguousFraming), Err(status::BadRequest));
+        assert_eq!(check_framing(&mut request, NormaliseAmbiguousFraming), Ok(()));
+        assert!(request.close_connection);
+    }
+
+    #[test]
+    fn test_body_reader() {
+        let input = bytes!("hello, worldGET").to_owned();
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input), false);
+        let mut remaining = RemainingBytes(12);
+        {
+            let mut reader = BodyReader::new(&mut stream, &mut remaining);
+            let mut buf = ~[0u8, 0, 0, 0, 0];
+            assert_eq!(reader.read(buf), Some(5));
+            assert_eq!(buf.as_slice(), bytes!("hello"));
+            assert!(!reader.eof());
+            assert_eq!(reader.read_to_end().as_slice(), bytes!(", world"));
+            assert!(reader.eof());
+            assert_eq!(reader.read(buf), None);
+        }
+        assert!(remaining.is_finished());
+        assert_eq!(stream.read_to_end().as_slice(), bytes!("GET"));
+
+        let input = bytes!("3\r\nfoo\r\n0\r\nX-Foo: bar\r\n\r\n").to_owned();
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(input), false);
+        let mut remaining = RemainingChunks(ChunkedDecoder::new());
+        let mut reader = BodyReader::new(&mut stream, &mut remaining);
+        assert_eq!(reader.read_to_end().as_slice(), bytes!("foo"));
+        assert_eq!(reader.take_trailer(), ~[(~"X-Foo", ~"bar")]);
+
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(~[1, 2, 3]), false);
+        let mut remaining = NoRemainingBody;
+        let mut reader = BodyReader::new(&mut stream, &mut remaining);
+        assert_eq!(reader.read_to_end(), ~[]);
+    }
+
+    #[test]
+    fn test_request_url() {
+        let host = Some(Host { name: ~"example.com", port: Some(8001) });
+        let url = request_url(&AbsolutePath(~"/foo/bar?baz=quux"), &host, false).unwrap();
+        assert_eq!(url.to_str(), ~"http://example.com:8001/foo/bar?baz=quux");
+        assert_eq!(url.path, ~"/foo/bar");
+        assert_eq!(url.query, ~[(~"baz", ~"quux")]);
+        let url = request_url(&AbsolutePath(~"/"), &host, true).unwrap();
+        assert_eq!(url.scheme, ~"https");
+
+        // An absolute URI is used as it is, whatever the Host header says
+        let absolute = FromStr::from_str("http://example.org/").unwrap();
+        assert_eq!(request_url(&AbsoluteUri(absolute), &host, false),
+                   FromStr::from_str("http://example.org/"));
+
+        assert_eq!(request_url(&AbsolutePath(~"/"), &None, false), None);
+        assert_eq!(request_url(&Star, &host, false), None);
+        assert_eq!(request_url(&Authority(~"example.com:443"), &host, false), None);
+    }
+
+    #[test]
+    fn test_target_host() {
+        let url = FromStr::from_str("http://example.com:8001/foo").unwrap();
+        assert_eq!(target_host(&url), Some(Host { name: ~"example.com", port: Some(8001) }));
+        let url = FromStr::from_str("http://example.com/").unwrap();
+        assert_eq!(target_host(&url), Some(Host { name: ~"example.com", port: None }));
+        let url = FromStr::from_str("http://example.com:99999/").unwrap();
+        assert_eq!(target_host(&url), None);
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        let base = FromStr::from_str("https://example.com:8443/users/1/posts?page=2").unwrap();
+        assert_eq!(absolute_url(&base, "/login").unwrap().to_str(),
+                   ~"https://example.com:8443/login");
+        assert_eq!(absolute_url(&base, "edit?draft=1").unwrap().to_str(),
+                   ~"https://example.com:8443/users/1/edit?draft=1");
+        let base = FromStr::from_str("http://example.com").unwrap();
+        assert_eq!(absolute_url(&base, "index.html").unwrap().to_str(),
+                   ~"http://example.com/index.html");
+        assert_eq!(absolute_url(&base, "https://example.org/a").unwrap().to_str(),
+                   ~"https://example.org/a");
+        assert_eq!(absolute_url(&base, "//example.org/a").unwrap().to_str(),
+                   ~"http://example.org/a");
+        assert_eq!(absolute_url(&base, "a/b:c").unwrap().to_str(), ~"http://example.com/a/b:c");
+    }
+}
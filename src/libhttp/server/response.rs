@@ -1,12 +1,40 @@
+use std::ascii::StrAsciiExt;
+use std::cmp::min;
 use std::rt;
-use std::rt::io::Writer;
+use std::rt::io::{Reader, Writer, Seek, SeekSet};
+use std::rt::io::io_error;
+use std::task;
+use std::util;
+use std::vec;
+use extra::time::precise_time_ns;
+use extra::url::Url;
 
-use buffer::BufTcpStream;
+use network::{BufNetworkStream, NetworkStream, SocketStream};
+use socket::RawFile;
 use server::Request;
+use server::request::{BodyReader, RemainingBody, NoRemainingBody};
+use server::error_handler::SharedErrorHandler;
+use server::observer::SharedObserver;
 use status;
+use compression;
+use compression::Coding;
+use content_md5::content_md5;
 use headers::response::HeaderCollection;
+use headers::accept_encoding::negotiate_encoding;
+use headers::cache_control::{CacheControl, NoCache};
 use headers::content_type::MediaType;
-use headers::transfer_encoding::Chunked;
+use headers::te::accepts_trailers;
+use headers::transfer_encoding::{Chunked, TransferExtension};
+use headers::vary::VaryHeaders;
+use headers::connection::{Close, Token};
+use method::{Method, Head, Connect};
+use transfer_coding::{TransferCodings, SharedTransferCoder};
+
+/// How much of an unread request body to read at a time when discarding it.
+static DISCARD_BUF_SIZE: uint = 0x1000;
+
+/// How much to read at a time in `write_from` when the body is being compressed.
+static WRITE_FROM_BUF_SIZE: uint = 0x10000;
 
 /**
  * The HTTP version tag which will be used for the response.
@@ -18,24 +46,306 @@ use headers::transfer_encoding::Chunked;
 static RESPONSE_HTTP_VERSION: &'static str = "HTTP/1.1";
 // Maybe we could provide a response interface
 
+/// The writer of a response to a request, given to the `Server`'s handler.
+///
+/// The Status-Line and headers are written first, when `write_headers` is called or when the body
+/// is first written to, whichever comes sooner; after that, the body may be written a piece at a
+/// time, for as long as the handler likes (for long polling or progressive rendering, say). If
+/// Content-Length has not been set, the body is sent in the chunked transfer-coding, and each
+/// `flush` sends what has been written since the last as a chunk; otherwise, `flush` just sends
+/// it on. Pieces not flushed go out once enough is buffered, or the response is finished.
+///
+/// A body which is being compressed (see `compress_with`) or transfer-coded (see
+/// `transfer_code_with`) cannot be sent a piece at a time; all of it is sent when the response is
+/// finished. Nor can one with Content-MD5 (see `send_content_md5`), whose headers wait for it too.
+///
+/// The response to a HEAD request is written just as that to a GET would be, but its body is
+/// counted rather than sent, so that a handler needn't tell one from the other. Nor are its
+/// headers sent until the handler has finished with it, or calls `write_headers` or `flush`, so
+/// that where the handler hasn't set Content-Length, it can be set to the length of the body
+/// written, as it would have been for GET.
+///
+/// The response to an HTTP/0.9 Simple-Request is the body alone, with no Status-Line or headers.
+/// That to an HTTP/2 request is written to memory, for the connection to send in frames.
 pub struct ResponseWriter<'self> {
     // The place to write to (typically a TCP stream, rt::io::net::tcp::TcpStream)
-    priv writer: &'self mut BufTcpStream,
+    priv writer: &'self mut BufNetworkStream,
     priv headers_written: bool,
     request: &'self Request,
     headers: ~HeaderCollection,
     status: status::Status,
+
+    /// Whether the connection must be closed once this response has been sent. This starts out as
+    /// the request's preference, but writing the headers may force it to `true`, e.g. when the body
+    /// can only be delimited by closing the connection.
+    close_connection: bool,
+
+    /// The content-coding with which to compress the body, set by `compress_with`.
+    priv compression: Option<Coding>,
+
+    /// The transfer-coding to apply to the body, besides chunked, set by `transfer_code_with`.
+    priv transfer_coding: Option<SharedTransferCoder>,
+
+    /// The transfer-codings `transfer_code_if_accepted` chooses from, if not the default ones.
+    priv transfer_codings: Option<TransferCodings>,
+
+    /// Whether Content-MD5 is to be sent, set by `send_content_md5`.
+    priv send_content_md5: bool,
+
+    /// The body written so far, while it is being compressed or transfer-coded, or held back for
+    /// its Content-MD5.
+    priv uncompressed_body: ~[u8],
+
+    /// The trailer fields to send after the body, added by `add_trailer`.
+    priv trailer: ~[(~str, ~str)],
+
+    /// What of the request's body is still to be read, if it is being streamed.
+    priv request_body: RemainingBody,
+
+    /// How many bytes of the body have been written, after any compression.
+    priv body_len_written: u64,
+
+    /// The observer to tell when the headers are written, and the time the request started.
+    priv observer: Option<(SharedObserver, u64)>,
+
+    /// The error handler with which `send_error` renders errors, if there is one.
+    priv error_handler: Option<SharedErrorHandler>,
+
+    /// What the connection is to be handed over to, once the handler returns, set by `hijack`.
+    priv hijacker: Option<~fn(BufNetworkStream)>,
+
+    /// For a response to HEAD, how many bytes of the body the handler has written, which aren't
+    /// sent; `None` for other responses.
+    priv head_body_len: Option<u64>,
 }
 
 impl<'self> ResponseWriter<'self> {
     /// Create a `ResponseWriter` writing to the specified location
-    pub fn new(writer: &'self mut BufTcpStream, request: &'self Request) -> ResponseWriter<'self> {
+    pub fn new(writer: &'self mut BufNetworkStream, request: &'self Request) -> ResponseWriter<'self> {
         ResponseWriter {
             writer: writer,
             headers_written: false,
             request: request,
             headers: ~HeaderCollection::new(),
             status: status::Ok,
+            close_connection: request.close_connection,
+            compression: None,
+            transfer_coding: None,
+            transfer_codings: None,
+            send_content_md5: false,
+            uncompressed_body: ~[],
+            trailer: ~[],
+            request_body: NoRemainingBody,
+            body_len_written: 0,
+            observer: None,
+            error_handler: None,
+            hijacker: None,
+            head_body_len: if request.method == Head { Some(0) } else { None },
+        }
+    }
+
+    /// Create a `ResponseWriter` for a request whose body has not yet been read, but is left for
+    /// the handler to read with `body_reader`, up to `max_body_size` bytes (see
+    /// `Request.remaining_body_up_to`). A body which is already known to be too long is not to be
+    /// read at all; the reader is empty and the connection will be closed.
+    pub fn with_unread_body(writer: &'self mut BufNetworkStream, request: &'self Request,
+                            max_body_size: Option<uint>) -> ResponseWriter<'self> {
+        let mut response = ResponseWriter::new(writer, request);
+        match request.remaining_body_up_to(max_body_size) {
+            Ok(remaining) => response.request_body = remaining,
+            Err(_) => response.close_connection = true,
+        }
+        response
+    }
+
+    /// How many bytes of the body have been written: after any content-coding, once the response is
+    /// finished, but not counting the headers or the framing of a chunked body.
+    pub fn body_len_written(&self) -> u64 {
+        self.body_len_written
+    }
+
+    /// Tell the observer when the headers have been written, with the time elapsed since
+    /// `request_started_ns` (a time from `precise_time_ns`).
+    pub fn set_observer(&mut self, observer: SharedObserver, request_started_ns: u64) {
+        self.observer = Some((observer, request_started_ns));
+    }
+
+    /// Have `transfer_code_if_accepted` choose from the transfer-codings given, rather than the
+    /// default ones (see `Config.transfer_codings`).
+    pub fn set_transfer_codings(&mut self, codings: TransferCodings) {
+        self.transfer_codings = Some(codings);
+    }
+
+    /// Have `send_error` render errors with the error handler.
+    pub fn set_error_handler(&mut self, error_handler: SharedErrorHandler) {
+        self.error_handler = Some(error_handler);
+    }
+
+    /// Send an error response with the status given, rendered by the server's error handler (see
+    /// `Config.error_handler`), or if there is none, with the status as a plain text body. The
+    /// headers must not yet have been written.
+    pub fn send_error(&mut self, status: status::Status) {
+        self.status = status;
+        match self.error_handler.clone() {
+            Some(handler) => {
+                let request = self.request;
+                handler.get().handle_error(request, self);
+            },
+            None => {
+                let body = self.status.to_str();
+                self.write_content_auto(MediaType(~"text", ~"plain", ~[]), body);
+            },
+        }
+    }
+
+    /// A reader for the body of the request, as it comes from the connection. This is only of use
+    /// if the server streams the body (see `Server.streams_body`); otherwise it has all been read
+    /// into `request.body` already, to be read with `request.body()`, and this reader is empty.
+    pub fn body_reader<'a>(&'a mut self) -> BodyReader<'a, NetworkStream> {
+        BodyReader::new(&mut *self.writer, &mut self.request_body)
+    }
+
+    /// Declare, in the Trailer header, a field which will be sent in the trailer, after the body,
+    /// with `add_trailer`. This must be done before the headers are written.
+    pub fn declare_trailer(&mut self, name: &str) {
+        if self.headers_written {
+            fail!("ResponseWriter.declare_trailer() called, but headers already written");
+        }
+        check_trailer_name(name);
+        let already_declared = match self.headers.trailer {
+            Some(ref names) => names.split_iter(',').any(|n| n.trim().eq_ignore_ascii_case(name)),
+            None => false,
+        };
+        if !already_declared {
+            self.headers.trailer = match self.headers.trailer.take() {
+                Some(names) => Some(format!("{}, {}", names, name)),
+                None => Some(name.to_owned()),
+            };
+        }
+    }
+
+    /// Add a field to the trailer, to be sent after the body when the response is finished, so
+    /// that its value may depend upon the body (a checksum of it, say). If the headers have not yet
+    /// been written, the field is declared in the Trailer header, as it should be
+    /// (see `declare_trailer`).
+    ///
+    /// A trailer can only be sent with the chunked transfer-coding, to a client which has said that
+    /// it will accept one, with `TE: trailers`; should the body have a Content-Length, or the
+    /// client be an HTTP/1.0 one or not have sent that, the trailer is dropped.
+    pub fn add_trailer(&mut self, name: &str, value: &str) {
+        if !self.headers_written {
+            self.declare_trailer(name);
+        } else {
+            check_trailer_name(name);
+        }
+        self.trailer.push((name.to_owned(), value.to_owned()));
+    }
+
+    /// Compress the body with the content-coding. This must be called before the headers are
+    /// written; Content-Encoding is then set, and as the compressed length isn't known beforehand,
+    /// Content-Length is disregarded and the chunked transfer-coding used (see `write_headers`).
+    ///
+    /// The body is held in memory until `finish_response`, when it is compressed and sent.
+    pub fn compress_with(&mut self, coding: Coding) {
+        if self.headers_written {
+            fail!("ResponseWriter.compress_with() called, but headers already written");
+        }
+        self.compression = Some(coding);
+    }
+
+    /// Apply the transfer-coding to the body, before the chunked transfer-coding, which is then
+    /// used whatever the Content-Length (see `write_headers`). This must be called before the
+    /// headers are written, and only for a client which accepts the coding, in its TE header; see
+    /// `transfer_code_if_accepted`. It is ignored for a client which isn't an HTTP/1.1 one.
+    ///
+    /// The body is held in memory until `finish_response`, when it is encoded and sent.
+    pub fn transfer_code_with(&mut self, coder: SharedTransferCoder) {
+        if self.headers_written {
+            fail!("ResponseWriter.transfer_code_with() called, but headers already written");
+        }
+        self.transfer_coding = Some(coder);
+    }
+
+    /// Apply to the body the transfer-coding the client accepts best, if its TE header accepts one
+    /// of the server's (see `set_transfer_codings`), returning whether one will be applied. As a
+    /// transfer-coding is undone by the client, unlike a content-coding, it needs no Vary.
+    pub fn transfer_code_if_accepted(&mut self) -> bool {
+        if self.request.version != (1, 1) {
+            return false;
+        }
+        let coder = match self.request.headers.te {
+            Some(ref te) => match self.transfer_codings {
+                Some(ref codings) => codings.negotiate(*te),
+                None => TransferCodings::with_defaults().negotiate(*te),
+            },
+            None => None,
+        };
+        match coder {
+            Some(coder) => {
+                self.transfer_code_with(coder);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Send Content-MD5, the digest of the body (after any compression), for the client to check
+    /// it by (see `http::content_md5`). This must be called before the headers are written; they
+    /// then wait, as the body is held in memory, until the handler has finished with the response,
+    /// and Content-Length is set as well, unless there is a transfer-coding. Should the handler
+    /// write the headers or flush the response itself, Content-MD5 is only that of the body
+    /// written until then, and so is better not asked for.
+    pub fn send_content_md5(&mut self) {
+        if self.headers_written {
+            fail!("ResponseWriter.send_content_md5() called, but headers already written");
+        }
+        self.send_content_md5 = true;
+    }
+
+    /// Whether the client said, with `TE: trailers`, that it will accept a trailer.
+    fn client_accepts_trailers(&self) -> bool {
+        match self.request.headers.te {
+            Some(ref te) => accepts_trailers(*te),
+            None => false,
+        }
+    }
+
+    /// Whether the body is being held back, to be compressed or transfer-coded once it has all
+    /// been written, or for its Content-MD5.
+    fn is_buffering(&self) -> bool {
+        self.compression.is_some() || self.transfer_coding.is_some() || self.send_content_md5
+    }
+
+    /// Write the headers, before writing the body, if they haven't been and aren't waiting for
+    /// the whole body (see `send_content_md5`).
+    fn write_headers_before_body(&mut self) {
+        if !self.headers_written && !self.send_content_md5 {
+            self.write_headers();
+        }
+    }
+
+    /// Compress the body with the content-coding the client accepts best, if it accepts one which
+    /// we support (see `compress_with`), returning whether it will be compressed. Either way,
+    /// Accept-Encoding is added to Vary, as the response depends upon it.
+    pub fn compress_if_accepted(&mut self) -> bool {
+        if self.headers.vary.is_none() {
+            self.headers.vary = Some(VaryHeaders(~[]));
+        }
+        self.headers.vary.get_mut_ref().push("Accept-Encoding");
+        let coding = match self.request.headers.accept_encoding {
+            // Without Accept-Encoding, identity is to be preferred
+            None => None,
+            Some(ref accept) => match negotiate_encoding(*accept, ["gzip", "deflate", "identity"]) {
+                Some(name) => Coding::from_name(name),
+                None => None,
+            },
+        };
+        match coding {
+            Some(coding) => {
+                self.compress_with(coding);
+                true
+            },
+            None => false,
         }
     }
 
@@ -45,10 +355,205 @@ impl<'self> ResponseWriter<'self> {
         self.headers.content_type = Some(content_type);
         let cbytes = content.as_bytes();
         self.headers.content_length = Some(cbytes.len());
-        self.write_headers();
+        self.write_headers_before_body();
         self.write(cbytes);
     }
 
+    /// Answer an OPTIONS request with the methods given: 200 OK, with them in Allow and an empty
+    /// body. (`Server.server_allow` has this done for `OPTIONS *`.)
+    pub fn write_options(&mut self, methods: ~[Method]) {
+        self.status = status::Ok;
+        self.headers.allow = Some(methods);
+        self.headers.content_length = Some(0);
+        self.write_headers();
+    }
+
+    /// Redirect the client to another URL, with a status of 301 Moved Permanently, 302 Found,
+    /// 303 See Other, 307 Temporary Redirect or 308 Permanent Redirect (and fail with any other),
+    /// and a short HTML body linking to it, for clients which don't follow redirects themselves.
+    /// Unless Cache-Control has been set, it is set to `no-cache` for a temporary redirect, so that
+    /// caches check again before using it; a permanent one may be cached like any other response.
+    /// `Request.absolute_url` gives the location for a path on this server.
+    pub fn redirect(&mut self, status: status::Status, location: Url) {
+        let permanent = match status {
+            status::MovedPermanently | status::PermanentRedirect => true,
+            status::Found | status::SeeOther | status::TemporaryRedirect => false,
+            _ => fail!("ResponseWriter.redirect() called with {}, which isn't a redirect",
+                       status.to_str()),
+        };
+        if !permanent && self.headers.cache_control.is_none() {
+            self.headers.cache_control = Some(CacheControl(~[NoCache(~[])]));
+        }
+        let location = location.to_str();
+        let href = escape_html(location);
+        self.headers.location = Some(location);
+        self.status = status;
+        let body = format!("<!DOCTYPE html>\n<title>{}</title>\n<p>See <a href=\"{}\">{}</a>.\n",
+                           self.status.to_str(), href, href);
+        self.write_content_auto(MediaType(~"text", ~"html", ~[(~"charset", ~"UTF-8")]), body);
+    }
+
+    /// Send 101 Switching Protocols, for the protocol named (as `websocket`), which goes in the
+    /// Upgrade header, and hand over the connection, to be spoken to in that protocol from then on:
+    /// no more HTTP is read from it or written to it, and it is closed once the handler returns.
+    /// The read limits and timeouts set for the request no longer apply to it.
+    pub fn switch_protocols<'a>(&'a mut self, protocol: &str) -> &'a mut BufNetworkStream {
+        self.status = status::SwitchingProtocols;
+        self.headers.upgrade = Some(protocol.to_owned());
+        self.headers.content_length = None;
+        self.write_headers();
+        self.writer.flush();
+        self.writer.set_read_limit(None);
+        self.writer.reset_read_limits();
+        &mut *self.writer
+    }
+
+    /// Take the connection over from the server, to be handed to `f` once the handler returns and
+    /// the request is done with (logged, and so on). The server writes nothing more to it: none of
+    /// the response, if its headers haven't been written yet, nor the end of a chunked body, if
+    /// they have. Whatever has already been written is flushed. Nor does it read any more from it;
+    /// `f` is given it as it is, with anything the client has sent beyond the request still in
+    /// its buffer. It is for `f` to close it, by dropping it, when it is done.
+    ///
+    /// This is for tunnels and protocols of one's own, such as follow 101 Switching Protocols or
+    /// the 200 answering CONNECT (see `server::proxy::Forward`); `f` may spawn a task to handle the
+    /// connection, if the stream can be sent (as a plain TCP stream can be).
+    pub fn hijack(&mut self, f: ~fn(BufNetworkStream)) {
+        self.hijacker = Some(f);
+        self.close_connection = true;
+    }
+
+    /// Whether the connection is a plain one on a socket of our own, which a task `hijack` hands
+    /// it to may split in two, to read from it in one task and write to it in another (see
+    /// `Socket.try_clone`), as a tunnel must; one over TLS or HTTP/2 can't be.
+    pub fn is_plain_socket(&self) -> bool {
+        match self.writer.wrapped {
+            SocketStream(*) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the connection has been hijacked.
+    pub fn is_hijacked(&self) -> bool {
+        self.hijacker.is_some()
+    }
+
+    /// Take what the connection has been handed over to, if it has been hijacked; the server does
+    /// this once it's done with the request.
+    pub fn take_hijacker(&mut self) -> Option<~fn(BufNetworkStream)> {
+        self.hijacker.take()
+    }
+
+    /// Send an interim (1xx) response, such as 100 Continue, ahead of the final response; it has
+    /// no headers. This must be done before the headers of the final response are written.
+    pub fn write_interim_response(&mut self, status: status::Status) {
+        self.send_informational(status, &HeaderCollection::new());
+    }
+
+    /**
+     * Send an informational (1xx) response, with the headers given, ahead of the final response,
+     * which is left as it was: 100 Continue, say, or 102 Processing to keep the client waiting on
+     * a long request, or 103 Early Hints with the Link headers of what the final response will
+     * need. Several may be sent, one after another.
+     *
+     * This fails if the headers of the final response have already been written, or the
+     * connection hijacked, or if the status is not 1xx; nor may it be 101 Switching Protocols,
+     * which ends the response (see `switch_protocols`), nor may the headers delimit a body, for an
+     * informational response has none.
+     *
+     * A client of HTTP/1.0 or 0.9 doesn't know of informational responses, so none is sent to it
+     * (RFC 7231, section 6.2); whether one was sent is returned. Nor are they sent over HTTP/2
+     * yet.
+     */
+    pub fn send_informational(&mut self, status: status::Status, headers: &HeaderCollection)
+            -> bool {
+        if self.headers_written {
+            fail!("ResponseWriter.send_informational() called, but headers already written");
+        }
+        if self.is_hijacked() {
+            fail!("ResponseWriter.send_informational() called, but the connection is hijacked");
+        }
+        if status.code() == 101 {
+            fail!("ResponseWriter.send_informational() called with 101; use switch_protocols()");
+        }
+        if self.request.version < (1, 1) || self.request.version >= (2, 0) {
+            return false;
+        }
+        write_informational(self.writer, status, headers);
+        true
+    }
+
+    /// Write several slices of the body, as though they were one; with the content not having to
+    /// be put together first, nor written a slice at a time (see `BufferedStream.write_vectored`).
+    pub fn write_vectored(&mut self, bufs: &[&[u8]]) {
+        if self.discard(bufs.iter().fold(0u64, |len, buf| len + buf.len() as u64)) {
+            return;
+        }
+        self.write_headers_before_body();
+        if self.is_buffering() {
+            for &buf in bufs.iter() {
+                self.uncompressed_body.push_all(buf);
+            }
+        } else {
+            self.writer.write_vectored(bufs);
+            for buf in bufs.iter() {
+                self.body_len_written += buf.len() as u64;
+            }
+        }
+    }
+
+    /// Write up to `length` bytes of the body from the reader, such as a file being served,
+    /// returning how many there were. They go straight from the reader into the connection's write
+    /// buffer (see `BufferedStream.write_from`), which saves copying them the more usual way.
+    /// For HEAD, they are read just the same, to count them, but not sent.
+    pub fn write_from<R: Reader>(&mut self, reader: &mut R, length: u64) -> u64 {
+        if self.head_body_len.is_none() {
+            self.write_headers_before_body();
+        }
+        if !self.is_buffering() && self.head_body_len.is_none() {
+            let written = self.writer.write_from(reader, length);
+            self.body_len_written += written;
+            return written;
+        }
+        let mut buf = vec::from_elem(min(length, WRITE_FROM_BUF_SIZE as u64) as uint, 0u8);
+        let mut written = 0u64;
+        while written < length {
+            let len = min(length - written, buf.len() as u64) as uint;
+            match reader.read(buf.mut_slice_to(len)) {
+                Some(n) => {
+                    if !self.discard(n as u64) {
+                        self.uncompressed_body.push_all(buf.slice_to(n));
+                    }
+                    written += n as u64;
+                },
+                None => break,
+            }
+        }
+        written
+    }
+
+    /// Write up to `length` bytes of the body from the file, from `offset`, returning how many
+    /// there were. Unless the body is to be compressed, transfer-coded or chunked, or held back
+    /// for its Content-MD5, they are sent straight from the file to the connection, where it
+    /// allows (see `BufferedStream.send_file`), without being read here at all; otherwise, or for
+    /// HEAD, they are read and written as by `write_from`.
+    pub fn write_file(&mut self, file: &mut RawFile, offset: u64, length: u64) -> u64 {
+        if self.head_body_len.is_none() {
+            self.write_headers_before_body();
+        }
+        if !self.is_buffering() && self.head_body_len.is_none() {
+            match self.writer.send_file(file, offset, length) {
+                Some(written) => {
+                    self.body_len_written += written;
+                    return written;
+                },
+                None => (),
+            }
+        }
+        file.seek(offset as i64, SeekSet);
+        self.write_from(file, length)
+    }
+
     /// Write the Status-Line and headers of the response, if we have not already done so.
     pub fn try_write_headers(&mut self) {
         if !self.headers_written {
@@ -61,6 +566,14 @@ impl<'self> ResponseWriter<'self> {
     /// This also overrides the value of the Transfer-Encoding header
     /// (``self.headers.transfer_encoding``), ensuring it is ``None`` if the Content-Length header
     /// has been specified, or to ``chunked`` if it has not, thus switching to the chunked coding.
+    /// (HTTP/1.0 clients don't understand chunked, so for them the connection is closed instead.)
+    /// Responses which cannot have a body, such as 304 Not Modified, use neither, nor do responses
+    /// to HEAD, which never send one; Content-Length is set for them, if it hasn't been, to the
+    /// length of any body written before this is called.
+    /// If the body is to be compressed, Content-Encoding is set and Content-Length cleared first.
+    ///
+    /// The Connection header is likewise overridden to reflect ``self.close_connection``; set it to
+    /// ``close`` (or set ``self.close_connection``) to close the connection after this response.
     ///
     /// If the headers have already been written, this will fail. See also `try_write_headers`.
     pub fn write_headers(&mut self) {
@@ -69,6 +582,75 @@ impl<'self> ResponseWriter<'self> {
             fail!("ResponseWriter.write_headers() called, but headers already written");
         }
 
+        if self.request.version == (0, 9) {
+            // The response to a Simple-Request is only the body, ended by closing the connection
+            // (RFC 1945, section 6); there is no Content-Encoding to tell of compression
+            self.compression = None;
+            self.transfer_coding = None;
+            self.close_connection = true;
+            self.headers_written = true;
+            self.notify_headers_written();
+            return;
+        }
+
+        match self.head_body_len {
+            Some(len) if len > 0 && self.headers.content_length.is_none()
+                               && !self.is_buffering() => {
+                self.headers.content_length = Some(len as uint);
+            },
+            _ => (),
+        }
+        match self.compression {
+            Some(coding) => {
+                self.headers.content_encoding = Some(coding.name().to_owned());
+                self.headers.content_length = None;
+            },
+            None => (),
+        }
+        // Only an HTTP/1.1 client can be sent a transfer-coding (RFC 2616, section 3.6; HTTP/2 has
+        // none but its framing), and the body's length is then that of the coded body, delimited
+        // by chunked.
+        if self.request.version != (1, 1) {
+            self.transfer_coding = None;
+        }
+        if self.transfer_coding.is_some() {
+            self.headers.content_length = None;
+        }
+        if self.send_content_md5 && self.head_body_len.is_none()
+                && !self.uncompressed_body.is_empty() {
+            // The body has been held back, so it can be compressed now, and its digest, which is
+            // that of the body as it is sent but for any transfer-coding, put in the headers.
+            match self.compression.take() {
+                Some(coding) => {
+                    let body = compression::compress(coding, self.uncompressed_body);
+                    self.uncompressed_body = body;
+                },
+                None => (),
+            }
+            self.headers.content_md5 = Some(content_md5(self.uncompressed_body));
+            if self.transfer_coding.is_none() {
+                self.headers.content_length = Some(self.uncompressed_body.len());
+            }
+        }
+        // A trailer the client hasn't said it will accept isn't sent (see `add_trailer`), so
+        // isn't declared either.
+        if self.request.version < (2, 0) && !self.client_accepts_trailers() {
+            self.headers.trailer = None;
+        }
+
+        if !self.status.is_valid() {
+            fail!("write_headers() called with a status which can't be sent, {:?}", self.status);
+        }
+
+        if self.request.version >= (2, 0) {
+            // The status and headers go in a HEADERS frame, once the handler is done, and the body
+            // in DATA frames, which delimit it (see `http2::connection`)
+            self.headers.transfer_encoding = None;
+            self.headers_written = true;
+            self.notify_headers_written();
+            return;
+        }
+
         // Write the Status-Line (RFC2616 §6.1)
         // XXX: might be better not to hardcode HTTP/1.1.
         // XXX: Rust's current lack of statement-duration lifetime handling prevents this from being
@@ -76,19 +658,53 @@ impl<'self> ResponseWriter<'self> {
         let s = format!("HTTP/1.1 {}\r\n", self.status.to_str());
         self.writer.write(s.as_bytes());
 
-        // FIXME: this is not an impressive way of handling it, but so long as chunked is the only
-        // transfer-coding we want to deal with it's tolerable. However, it is *meant* to be an
-        // extensible thing, whereby client and server could agree upon extra transformations to
-        // apply. In such a case, chunked MUST come last. This way prevents it from being extensible
-        // thus, which is suboptimal.
-        if self.headers.content_length == None {
-            self.headers.transfer_encoding = Some(~[Chunked]);
+        // The handler may have asked for the connection to be closed.
+        match self.headers.connection {
+            Some(ref tokens) if tokens.iter().any(|t| *t == Close) => self.close_connection = true,
+            _ => (),
+        }
+
+        // Any other transfer-coding goes before chunked, which must come last (RFC 7230, section
+        // 3.3.1).
+        // 1xx, 204 (No Content) and 304 (Not Modified) responses never have a body (RFC 2616,
+        // §4.3), nor do responses to HEAD, so it needs no delimiting. Nor does a 2xx answering
+        // CONNECT, after which the connection is a tunnel (RFC 7231, section 4.3.6).
+        let bodiless = match self.status.code() {
+            100..199 | 204 | 304 => true,
+            200..299 if self.request.method == Connect => true,
+            _ => self.head_body_len.is_some(),
+        };
+        let chunked = if bodiless || self.headers.content_length.is_some() {
+            false
+        } else if self.request.version >= (1, 1) {
+            true
         } else {
-            self.headers.transfer_encoding = None;
+            // "A server MUST NOT send transfer-codings to an HTTP/1.0 client." (RFC 2616, §3.6)
+            // The only way left of delimiting the body is closing the connection (RFC 2616, §4.4).
+            self.close_connection = true;
+            false
+        };
+        self.headers.transfer_encoding = match self.transfer_coding {
+            _ if !chunked => None,
+            Some(ref coder) => Some(~[TransferExtension(coder.get().name(), ~[]), Chunked]),
+            None => Some(~[Chunked]),
+        };
+        if self.status.code() == 101 {
+            // The connection goes over to another protocol (see `switch_protocols`), and is done
+            // with HTTP once this response has been sent.
+            self.close_connection = true;
+            self.headers.connection = Some(~[Token(~"Upgrade")]);
+        } else if self.close_connection {
+            self.headers.connection = Some(~[Close]);
+        } else if self.request.version == (1, 0) {
+            // HTTP/1.0 connections are not persistent unless the client asked for it with
+            // "Connection: keep-alive"; we must confirm that we are keeping the connection open.
+            self.headers.connection = Some(~[Token(~"Keep-Alive")]);
         }
         self.headers.write_all(self.writer);
         self.headers_written = true;
-        if self.headers.content_length == None {
+        self.notify_headers_written();
+        if chunked {
             // Flush so that the chunked body stuff can start working correctly. TODO: don't
             // actually flush it entirely, or else it'll send the headers in a separate TCP packet,
             // which is bad for performance.
@@ -97,24 +713,189 @@ impl<'self> ResponseWriter<'self> {
         }
     }
 
+    /// Tell the observer, if there is one, that the headers have been written.
+    fn notify_headers_written(&mut self) {
+        match self.observer.clone() {
+            Some((observer, started)) => observer.get().response_headers_written(
+                    self.request, &*self, precise_time_ns() - started),
+            None => (),
+        }
+    }
+
+    /// Count the bytes of the body given if this is a response to HEAD, returning whether it is,
+    /// in which case they are not to be sent.
+    fn discard(&mut self, len: u64) -> bool {
+        match self.head_body_len {
+            Some(ref mut body_len) => {
+                *body_len += len;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Finish the response: compress and transfer-code the body if it is to be, flush it and, if
+    /// the chunked transfer-coding is in use, write the last-chunk which marks the end of the body,
+    /// and the trailer, if the client accepts one.
+    ///
+    /// Whatever of a streamed request body has not been read is then read and discarded, so that
+    /// the next request on the connection may be read, unless the connection is to be closed.
     pub fn finish_response(&mut self) {
-        self.writer.finish_response();
-        // Ensure that we switch away from chunked in case another request comes on the same socket
-        self.writer.writing_chunked_body = false;
+        if self.is_hijacked() {
+            self.writer.flush();
+            self.writer.writing_chunked_body = false;
+            return;
+        }
+        let buffering = self.is_buffering();
+        let (compression, transfer_coding) = (self.compression.take(), self.transfer_coding.take());
+        // No body, as in a response to HEAD, stays that way.
+        if buffering && !self.uncompressed_body.is_empty() {
+            let mut body = util::replace(&mut self.uncompressed_body, ~[]);
+            match compression {
+                Some(coding) => body = compression::compress(coding, body),
+                None => (),
+            }
+            match transfer_coding {
+                Some(coder) => body = coder.get().encode(body),
+                None => (),
+            }
+            self.writer.write(body);
+            self.body_len_written += body.len() as u64;
+        }
+        let trailer = util::replace(&mut self.trailer, ~[]);
+        if self.client_accepts_trailers() {
+            self.writer.finish_response_with_trailer(trailer);
+        } else {
+            self.writer.finish_response();
+        }
+        if !self.close_connection && !self.request_body.is_finished() {
+            let mut reader = self.body_reader();
+            let mut buf = vec::from_elem(DISCARD_BUF_SIZE, 0u8);
+            while reader.read(buf).is_some() {}
+        }
+        if self.writer.read_timed_out() || self.request_body.is_too_long() {
+            // Some of the request's body may not have been read; the connection can't be reused.
+            self.close_connection = true;
+        }
+    }
+}
+
+/// Fail if the header field named may not be sent in a trailer: Transfer-Encoding, Content-Length
+/// and Trailer itself (RFC 2616, section 14.40).
+fn check_trailer_name(name: &str) {
+    if name.eq_ignore_ascii_case("Transfer-Encoding") || name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Trailer") {
+        fail!("the {} header may not be sent in a trailer", name);
+    }
+}
+
+/// Send an interim (1xx) response, with no headers, on a connection on which no final response has
+/// been started. This is how the server sends 100 Continue before reading the body of a request,
+/// when there is not yet a `ResponseWriter`.
+pub fn write_interim_response(writer: &mut BufNetworkStream, status: status::Status) {
+    write_informational(writer, status, &HeaderCollection::new());
+}
+
+/// Send a final response with no body, and close the connection, on one on which no request has
+/// been read: this is how the server answers a client which has sent nothing for too long with 408
+/// Request Timeout, when there is no request for a `ResponseWriter`. Should the client have gone,
+/// that is no matter.
+pub fn write_closing_response(writer: &mut BufNetworkStream, status: status::Status) {
+    let mut headers = HeaderCollection::new();
+    headers.content_length = Some(0);
+    headers.connection = Some(~[Close]);
+    do io_error::cond.trap(|_| ()).inside {
+        let s = format!("{} {}\r\n", RESPONSE_HTTP_VERSION, status.to_str());
+        writer.write(s.as_bytes());
+        headers.write_all(writer);
+        writer.flush();
+    }
+}
+
+/// Send an informational (1xx) response with the headers given, failing if the status isn't 1xx
+/// or the headers would give it a body.
+fn write_informational(writer: &mut BufNetworkStream, status: status::Status,
+                       headers: &HeaderCollection) {
+    if status.code() / 100 != 1 {
+        fail!("an informational response sent with a final status, {}", status.to_str());
+    }
+    if headers.content_length.is_some() || headers.transfer_encoding.is_some() {
+        fail!("an informational response sent with Content-Length or Transfer-Encoding");
+    }
+    let s = format!("{} {}\r\n", RESPONSE_HTTP_VERSION, status.to_str());
+    writer.write(s.as_bytes());
+    headers.write_all(writer);
+    // The client is waiting for it.
+    writer.flush();
+}
+
+/// A handler which fails takes the task serving the connection with it, and the response is
+/// dropped unfinished. If nothing of it has been sent, 500 Internal Server Error is sent instead,
+/// so that the client isn't left with just a dropped connection; either way, the connection is
+/// closed.
+#[unsafe_destructor]
+impl<'self> Drop for ResponseWriter<'self> {
+    fn drop(&mut self) {
+        if !task::failing() || self.headers_written || self.is_hijacked() {
+            return;
+        }
+        // Nothing which may fail again (as the observer or the error handler might) can be done
+        // now, nor anything the handler had set up for its own response.
+        self.observer = None;
+        self.status = status::InternalServerError;
+        self.headers = ~HeaderCollection::new();
+        self.headers.content_length = Some(0);
+        self.compression = None;
+        self.transfer_coding = None;
+        self.send_content_md5 = false;
+        self.trailer = ~[];
+        self.close_connection = true;
+        do io_error::cond.trap(|e| {
+            debug!("couldn't send 500 Internal Server Error after failing: {:?}", e);
+        }).inside {
+            self.write_headers();
+            self.writer.flush();
+        }
     }
 }
 
 impl<'self> rt::io::Writer for ResponseWriter<'self> {
 
     fn write(&mut self, buf: &[u8]) {
-        if (!self.headers_written) {
-            self.write_headers();
+        if self.discard(buf.len() as u64) {
+            return;
+        }
+        self.write_headers_before_body();
+        if self.is_buffering() {
+            self.uncompressed_body.push_all(buf);
+        } else {
+            self.writer.write(buf);
+            self.body_len_written += buf.len() as u64;
         }
-        self.writer.write(buf);
     }
 
+    /// Send everything written so far, writing the headers first if need be. Anything written
+    /// while compressing, or for Content-MD5, is held back until the response is finished,
+    /// however.
     fn flush(&mut self) {
+        self.write_headers_before_body();
         self.writer.flush();
     }
 
 }
+
+/// Escape text to go in HTML, in an element or a quoted attribute.
+fn escape_html(s: &str) -> ~str {
+    let mut out = ~"";
+    for c in s.iter() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push_char(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,326 @@
+/*!
+
+Routing of requests to handlers by method and path, with parts of the path captured as parameters.
+
+```rust
+struct ShowPost;
+
+impl RouteHandler for ShowPost {
+    fn handle(&self, _request: &Request, response: &mut ResponseWriter, params: &RouteParams) {
+        let s = format!("post {} of user {}", params.get("post").unwrap(),
+                        params.get("id").unwrap());
+        response.write(s.as_bytes());
+    }
+}
+
+let mut router = Router::new();
+router.add(Get, "/users/:id/posts/:post", ~ShowPost as ~RouteHandler:Send+Freeze);
+router.add(Get, "/static/*path", ~ServeStatic as ~RouteHandler:Send+Freeze);
+// And in Server.handle_request:
+router.handle(request, response);
+```
+
+A pattern is matched a segment of the path at a time; empty segments, as in `//` or a trailing
+`/`, are ignored. A segment `:name` matches any one segment, which is captured as the parameter
+`name`; `*name`, which can only come last, matches all the rest of the path, even none of it.
+Anything else must match exactly. Captured segments are percent-decoded. Routes are tried in the
+order they were added, and the first which matches is used; should none match, the response is
+404 Not Found. If routes match the path, but not with the method of the request, it is 405 Method
+Not Allowed, with an Allow header listing the methods which they are for; and an OPTIONS request
+for such a path is answered with just that Allow header, unless a route is added for it.
+
+A router can't be cloned, so a server which has one should keep it in an `Arc`.
+
+*/
+
+use method::{Method, Options};
+use percent_encoding;
+use server::{Request, ResponseWriter};
+use server::request::{AbsolutePath, AbsoluteUri};
+use server::staticfile::send_error;
+use status;
+
+/// The handler for a route.
+pub trait RouteHandler {
+    /// Handle a request which the route matched, with the parameters captured from its path.
+    fn handle(&self, request: &Request, response: &mut ResponseWriter, params: &RouteParams);
+}
+
+/// The parameters captured from the path of a request by the pattern of the route it matched.
+#[deriving(Clone, Eq)]
+pub struct RouteParams {
+    priv params: ~[(~str, ~str)],
+}
+
+impl RouteParams {
+    /// Parameters with the (name, value) pairs given.
+    pub fn new(params: ~[(~str, ~str)]) -> RouteParams {
+        RouteParams { params: params }
+    }
+
+    /// The value of the parameter named, if the pattern has it.
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a str> {
+        for &(ref n, ref value) in self.params.iter() {
+            if n.as_slice() == name {
+                return Some(value.as_slice());
+            }
+        }
+        None
+    }
+
+    /// All the parameters, as (name, value) pairs, in the order they come in the pattern.
+    pub fn as_slice<'a>(&'a self) -> &'a [(~str, ~str)] {
+        self.params.as_slice()
+    }
+}
+
+#[deriving(Clone, Eq)]
+enum Segment {
+    Literal(~str),
+    Capture(~str),
+    CaptureRest(~str),
+}
+
+/// A pattern for the path of a request, as `/users/:id/posts/*rest`.
+#[deriving(Clone, Eq)]
+pub struct PathPattern {
+    priv segments: ~[Segment],
+}
+
+impl PathPattern {
+    /// Parse a pattern; this fails if `*name` is anywhere but at the end, or a parameter has no
+    /// name.
+    pub fn new(pattern: &str) -> PathPattern {
+        let mut segments = ~[];
+        let parts: ~[&str] = pattern.split_iter('/').filter(|s| !s.is_empty()).collect();
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if part.starts_with(":") {
+                Capture(part.slice_from(1).to_owned())
+            } else if part.starts_with("*") {
+                if i != parts.len() - 1 {
+                    fail!("{} in the path pattern {} isn't at the end", *part, pattern);
+                }
+                CaptureRest(part.slice_from(1).to_owned())
+            } else {
+                Literal(part.to_owned())
+            };
+            match segment {
+                Capture(ref name) | CaptureRest(ref name) if name.is_empty() =>
+                    fail!("a parameter in the path pattern {} has no name", pattern),
+                _ => (),
+            }
+            segments.push(segment);
+        }
+        PathPattern { segments: segments }
+    }
+
+    /// Match the pattern against a path, given as its segments (decoded, and without any empty
+    /// ones), returning the parameters captured if it matches.
+    pub fn match_segments(&self, path: &[~str]) -> Option<RouteParams> {
+        let mut params = ~[];
+        let mut i = 0;
+        for segment in self.segments.iter() {
+            match *segment {
+                CaptureRest(ref name) => {
+                    let rest = if i < path.len() { path.slice_from(i).connect("/") } else { ~"" };
+                    params.push((name.clone(), rest));
+                    return Some(RouteParams { params: params });
+                },
+                _ if i == path.len() => return None,
+                Literal(ref literal) if *literal != path[i] => return None,
+                Literal(_) => (),
+                Capture(ref name) => params.push((name.clone(), path[i].clone())),
+            }
+            i += 1;
+        }
+        if i == path.len() {
+            Some(RouteParams { params: params })
+        } else {
+            None
+        }
+    }
+}
+
+/// The segments of the path of a request, decoded, without any empty ones, or `None` if its
+/// Request-URI has no path (as `*`).
+pub fn path_segments(request: &Request) -> Option<~[~str]> {
+    match request.request_uri {
+        AbsolutePath(ref path) => {
+            let path = path.split_iter('?').next().unwrap().split_iter('#').next().unwrap();
+            Some(path.split_iter('/').filter(|s| !s.is_empty())
+                     .map(|s| percent_encoding::decode_str(s)).collect())
+        },
+        // The URL parser has decoded the path already.
+        AbsoluteUri(ref url) => Some(url.path.split_iter('/').filter(|s| !s.is_empty())
+                                             .map(|s| s.to_owned()).collect()),
+        _ => None,
+    }
+}
+
+/// Which route, if any, a request is for.
+pub enum RouteSelection {
+    /// The route at the index given, with the parameters it captured.
+    SelectedRoute(uint, RouteParams),
+    /// Routes match the path, but none with the method of the request; these are the methods
+    /// there are, including OPTIONS, which is answered for any path with a route.
+    MethodNotAllowed(~[Method]),
+    /// No route matches the path.
+    NoRoute,
+}
+
+struct Route {
+    method: Method,
+    pattern: PathPattern,
+    handler: ~RouteHandler:Send+Freeze,
+}
+
+/// A set of routes, each from a method and a path pattern to a handler.
+pub struct Router {
+    priv routes: ~[Route],
+}
+
+impl Router {
+    /// A router with no routes.
+    pub fn new() -> Router {
+        Router { routes: ~[] }
+    }
+
+    /// Add a route, for requests with the method and a path which matches the pattern (see
+    /// `PathPattern::new`).
+    pub fn add(&mut self, method: Method, pattern: &str, handler: ~RouteHandler:Send+Freeze) {
+        self.routes.push(Route {
+            method: method,
+            pattern: PathPattern::new(pattern),
+            handler: handler,
+        });
+    }
+
+    /// Find the first route for the method and path (given as for `PathPattern.match_segments`).
+    pub fn select(&self, method: &Method, path: &[~str]) -> RouteSelection {
+        let mut allowed = ~[];
+        for (i, route) in self.routes.iter().enumerate() {
+            match route.pattern.match_segments(path) {
+                Some(params) => {
+                    if route.method == *method {
+                        return SelectedRoute(i, params);
+                    } else if !allowed.contains(&route.method) {
+                        allowed.push(route.method.clone());
+                    }
+                },
+                None => (),
+            }
+        }
+        if allowed.is_empty() {
+            return NoRoute;
+        }
+        if !allowed.contains(&Options) {
+            allowed.push(Options);
+        }
+        MethodNotAllowed(allowed)
+    }
+
+    /// Handle a request with the handler of the first route which matches it; or if there is
+    /// none, send 404 Not Found, or 405 Method Not Allowed, or answer OPTIONS (see `select`).
+    pub fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        let path = match path_segments(request) {
+            Some(path) => path,
+            None => return send_error(response, status::NotFound),
+        };
+        match self.select(&request.method, path) {
+            SelectedRoute(i, params) => self.routes[i].handler.handle(request, response, &params),
+            MethodNotAllowed(allowed) => {
+                response.headers.allow = Some(allowed);
+                if request.method == Options {
+                    response.headers.content_length = Some(0);
+                    response.write_headers();
+                } else {
+                    send_error(response, status::MethodNotAllowed);
+                }
+            },
+            NoRoute => send_error(response, status::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use method::{Get, Post, Options, Delete};
+    use server::{Request, ResponseWriter};
+    use super::{PathPattern, RouteParams, Router, RouteHandler, SelectedRoute, MethodNotAllowed,
+                NoRoute};
+
+    struct NullHandler;
+
+    impl RouteHandler for NullHandler {
+        fn handle(&self, _request: &Request, _response: &mut ResponseWriter,
+                  _params: &RouteParams) {
+        }
+    }
+
+    fn matches(pattern: &str, path: &[&str]) -> Option<~[(~str, ~str)]> {
+        let path: ~[~str] = path.iter().map(|&s| s.to_owned()).collect();
+        match PathPattern::new(pattern).match_segments(path) {
+            Some(params) => Some(params.as_slice().to_owned()),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn test_match_segments() {
+        assert_eq!(matches("/", &[]), Some(~[]));
+        assert_eq!(matches("/users", &["users"]), Some(~[]));
+        assert_eq!(matches("/users/", &["users"]), Some(~[]));
+        assert_eq!(matches("/users", &["users", "1"]), None);
+        assert_eq!(matches("/users/:id", &["users"]), None);
+        assert_eq!(matches("/users/:id/posts/:post", &["users", "1", "posts", "a b"]),
+                   Some(~[(~"id", ~"1"), (~"post", ~"a b")]));
+        assert_eq!(matches("/users/:id/posts", &["users", "1", "comments"]), None);
+        assert_eq!(matches("/static/*path", &["static", "css", "site.css"]),
+                   Some(~[(~"path", ~"css/site.css")]));
+        assert_eq!(matches("/static/*path", &["static"]), Some(~[(~"path", ~"")]));
+        assert_eq!(matches("/static/*path", &["other"]), None);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_rest_not_last() {
+        PathPattern::new("/static/*path/more");
+    }
+
+    #[test]
+    fn test_route_params() {
+        let params = RouteParams::new(~[(~"id", ~"1"), (~"post", ~"2")]);
+        assert_eq!(params.get("post"), Some("2"));
+        assert_eq!(params.get("user"), None);
+    }
+
+    #[test]
+    fn test_select() {
+        let mut router = Router::new();
+        router.add(Get, "/users/:id", ~NullHandler as ~RouteHandler:Send+Freeze);
+        router.add(Delete, "/users/:id", ~NullHandler as ~RouteHandler:Send+Freeze);
+        router.add(Get, "/users/:id", ~NullHandler as ~RouteHandler:Send+Freeze);
+        router.add(Post, "/users", ~NullHandler as ~RouteHandler:Send+Freeze);
+        let path = ~[~"users", ~"1"];
+        match router.select(&Delete, path) {
+            SelectedRoute(1, params) => assert_eq!(params.get("id"), Some("1")),
+            _ => fail!("DELETE /users/1 should be routed to the second route"),
+        }
+        match router.select(&Get, path) {
+            SelectedRoute(0, _) => (),
+            _ => fail!("GET /users/1 should be routed to the first route"),
+        }
+        match router.select(&Post, path) {
+            MethodNotAllowed(allowed) => assert_eq!(allowed, ~[Get, Delete, Options]),
+            _ => fail!("POST /users/1 should not be allowed"),
+        }
+        match router.select(&Options, [~"users"]) {
+            MethodNotAllowed(allowed) => assert_eq!(allowed, ~[Post, Options]),
+            _ => fail!("OPTIONS /users should be answered with the methods allowed"),
+        }
+        match router.select(&Get, [~"posts"]) {
+            NoRoute => (),
+            _ => fail!("GET /posts should have no route"),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+/*!
+
+Sessions: data kept on the server for each client across requests, the client being known by a
+cookie with the session's id in it.
+
+```rust
+#[deriving(Clone)]
+struct MyServer {
+    sessions: Sessions<MemoryStore>,
+}
+
+impl Server for MyServer {
+    fn handle_request(&self, request: &Request, response: &mut ResponseWriter) {
+        do self.sessions.handle(request, response) |request, response, session| {
+            let visits = match session.get("visits") {
+                Some(n) => from_str::<uint>(n).unwrap() + 1,
+                None => 1u,
+            };
+            session.set("visits", visits.to_str());
+            // ... handle the request as usual
+        }
+    }
+}
+
+let server = MyServer { sessions: Sessions::new(MemoryStore::new()) };
+```
+
+A client without a session (or whose session is no longer in the store) is given a new one, with
+its cookie, before the handler is called; a handler which sets cookies of its own should add to
+`response.headers.set_cookie` rather than replacing it. The session is saved in the store after
+the handler, if it was changed.
+
+Session ids are 32 random letters and digits, from the task's random number generator.
+
+*/
+
+use std::hashmap::HashMap;
+use std::rand::{Rng, task_rng};
+use extra::arc::RWArc;
+use headers::cookie::{Cookie, CookieList, SetCookieList};
+use server::{Request, ResponseWriter};
+
+/// The length of a session id.
+static SESSION_ID_LEN: uint = 32;
+
+/// The data of a session: keys and their values.
+pub type SessionData = HashMap<~str, ~str>;
+
+/// Where sessions are kept, by their ids. Clones of a store must share the sessions, for those are
+/// what the tasks serving connections each have.
+pub trait SessionStore {
+    /// The data of the session, if the store has it.
+    fn load(&self, id: &str) -> Option<SessionData>;
+
+    /// Keep the data of the session, in place of any it had.
+    fn save(&self, id: &str, data: &SessionData);
+
+    /// Forget the session.
+    fn remove(&self, id: &str);
+}
+
+/// A store keeping sessions in memory, for as long as the server runs.
+#[deriving(Clone)]
+pub struct MemoryStore {
+    priv sessions: RWArc<HashMap<~str, SessionData>>,
+}
+
+impl MemoryStore {
+    /// An empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore { sessions: RWArc::new(HashMap::new()) }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        do self.sessions.read |sessions| {
+            match sessions.find_equiv(&id) {
+                Some(data) => Some(data.clone()),
+                None => None,
+            }
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData) {
+        do self.sessions.write |sessions| {
+            sessions.insert(id.to_owned(), data.clone());
+        }
+    }
+
+    fn remove(&self, id: &str) {
+        do self.sessions.write |sessions| {
+            sessions.remove(&id.to_owned());
+        }
+    }
+}
+
+/// The session of a request, as the handler sees it.
+pub struct Session {
+    priv id: ~str,
+    priv data: SessionData,
+    priv is_new: bool,
+    priv modified: bool,
+    priv destroyed: bool,
+}
+
+impl Session {
+    /// A session with the id and data given; `is_new` is whether the client has yet to be given
+    /// its cookie.
+    pub fn new(id: ~str, data: SessionData, is_new: bool) -> Session {
+        Session {
+            id: id,
+            data: data,
+            is_new: is_new,
+            modified: false,
+            destroyed: false,
+        }
+    }
+
+    /// The id of the session.
+    pub fn id<'a>(&'a self) -> &'a str {
+        self.id.as_slice()
+    }
+
+    /// Whether the session was begun with this request.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// The value of the key, if the session has it.
+    pub fn get<'a>(&'a self, key: &str) -> Option<&'a str> {
+        match self.data.find_equiv(&key) {
+            Some(value) => Some(value.as_slice()),
+            None => None,
+        }
+    }
+
+    /// Set the value of the key.
+    pub fn set(&mut self, key: &str, value: ~str) {
+        self.data.insert(key.to_owned(), value);
+        self.modified = true;
+    }
+
+    /// Remove the key, returning whether the session had it.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let removed = self.data.remove(&key.to_owned());
+        if removed {
+            self.modified = true;
+        }
+        removed
+    }
+
+    /// Remove the session from the store once the request has been handled, as on logging out. The
+    /// client keeps its cookie, but it will name no session, and a new one will be begun.
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+
+    /// Whether the session has been changed during this request.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+}
+
+/// Sessions kept in a store, and the cookie which carries their ids.
+#[deriving(Clone)]
+pub struct Sessions<S> {
+    priv store: S,
+    priv cookie: Cookie,
+}
+
+impl<S: SessionStore> Sessions<S> {
+    /// Sessions kept in the store, with their ids carried in an HttpOnly cookie named `session`,
+    /// sent for every path.
+    pub fn new(store: S) -> Sessions<S> {
+        let mut cookie = Cookie::new(~"session", ~"");
+        cookie.path = Some(~"/");
+        cookie.http_only = true;
+        Sessions::with_cookie(store, cookie)
+    }
+
+    /// Sessions kept in the store, with their ids carried in a cookie with the name and attributes
+    /// of the one given (whose value is disregarded), as to make it Secure.
+    pub fn with_cookie(store: S, cookie: Cookie) -> Sessions<S> {
+        Sessions {
+            store: store,
+            cookie: cookie,
+        }
+    }
+
+    /// The session of the request, if its cookie names one which the store has.
+    pub fn load(&self, request: &Request) -> Option<Session> {
+        let cookies = match request.headers.cookie {
+            Some(CookieList(ref cookies)) => cookies,
+            None => return None,
+        };
+        for cookie in cookies.iter() {
+            if cookie.name == self.cookie.name {
+                match self.store.load(cookie.value) {
+                    Some(data) => return Some(Session::new(cookie.value.clone(), data, false)),
+                    None => (),
+                }
+            }
+        }
+        None
+    }
+
+    /// Handle a request with the handler, giving it the request's session, or a new one whose
+    /// cookie is set in the response first; the session is saved (or removed) afterwards.
+    pub fn handle(&self, request: &Request, response: &mut ResponseWriter,
+                  handler: &fn(&Request, &mut ResponseWriter, &mut Session)) {
+        let mut session = match self.load(request) {
+            Some(session) => session,
+            None => {
+                let id = task_rng().gen_ascii_str(SESSION_ID_LEN);
+                let mut cookie = self.cookie.clone();
+                cookie.value = id.clone();
+                match response.headers.set_cookie {
+                    Some(SetCookieList(ref mut cookies)) => cookies.push(cookie),
+                    None => response.headers.set_cookie = Some(SetCookieList(~[cookie])),
+                }
+                Session::new(id, HashMap::new(), true)
+            },
+        };
+        handler(request, response, &mut session);
+        if session.destroyed {
+            self.store.remove(session.id);
+        } else if session.modified {
+            self.store.save(session.id, &session.data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::hashmap::HashMap;
+    use super::{MemoryStore, SessionStore, Session};
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new(~"id", HashMap::new(), true);
+        assert!(!session.is_modified());
+        session.set("user", ~"alice");
+        assert_eq!(session.get("user"), Some("alice"));
+        assert!(session.is_modified());
+        assert!(session.remove("user"));
+        assert!(!session.remove("user"));
+        assert_eq!(session.get("user"), None);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::new();
+        let shared = store.clone();
+        let mut data = HashMap::new();
+        data.insert(~"user", ~"alice");
+        store.save("a", &data);
+        assert_eq!(shared.load("a").unwrap().find_equiv(&"user"), Some(&~"alice"));
+        assert!(shared.load("b").is_none());
+        shared.remove("a");
+        assert!(store.load("a").is_none());
+    }
+}
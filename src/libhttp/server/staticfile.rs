@@ -0,0 +1,212 @@
+/*!
+
+Serving of static files from a directory.
+
+```rust
+impl Server for FileServer {
+    fn handle_request(&self, request: &Request, response: &mut ResponseWriter) {
+        match request.request_uri {
+            AbsolutePath(ref path) => staticfile::serve_file(&self.root, *path, request, response),
+            _ => staticfile::send_error(response, status::BadRequest),
+        }
+    }
+}
+```
+
+The request path is percent-decoded and resolved against the root directory, segment by segment,
+`.` and `..` being taken out as they would be in a URL (see `percent_encoding::decode_path`); a path
+which would leave the root (through `..`), or has a NUL or backslash in it, is refused with 403
+Forbidden, as are directories and files which cannot be read. A file which doesn't exist is 404 Not
+Found.
+
+A GET request with a Range header for a single range of bytes is answered with just those bytes, in
+a 206 Partial Content response, making downloads resumable; should several ranges be asked for, the
+whole file is sent instead.
+
+Files are sent with the Content-Type of their extension, as `http::mime` knows it; with
+`serve_file_with_types`, more extensions can be given types, or given other types.
+
+Files are sent with a weak ETag made from their size and modification time, so that a client which
+has the file cached can revalidate it, by that or by its Last-Modified time, and be answered with
+304 Not Modified.
+
+*/
+
+use std::rt::io::io_error;
+use std::rt::io::file::FileInfo;
+use extra::time::{Timespec, at_utc};
+use headers::accept_ranges::{RangeUnits, Bytes};
+use headers::content_range::{ByteRange, UnsatisfiedRange};
+use headers::content_type::MediaType;
+use headers::etag::weak_etag;
+use method::{Get, Head};
+use mime::MimeTypes;
+use percent_encoding;
+use server::{Request, ResponseWriter, conditional};
+use socket::RawFile;
+use status;
+use status::Status;
+
+/// Resolve a request path (from the Request-URI, with or without a query string) to a file path
+/// under the root directory, as `percent_encoding::decode_path` decodes it. `None` is returned if
+/// the path is not one which may be served: it would leave the root with `..`, or contains a NUL
+/// or backslash.
+pub fn resolve_path(root: &Path, request_path: &str) -> Option<Path> {
+    let request_path = match request_path.find('?') {
+        Some(i) => request_path.slice_to(i),
+        None => request_path,
+    };
+    let segments = match percent_encoding::decode_path(request_path) {
+        Some(segments) => segments,
+        None => return None,
+    };
+    let mut path = root.clone();
+    for segment in segments.iter() {
+        path.push(segment.as_slice());
+    }
+    Some(path)
+}
+
+/// The media type of a file, as determined by its extension (see `http::mime`), defaulting to
+/// `application/octet-stream`.
+pub fn content_type_for(path: &Path) -> MediaType {
+    content_type_with_types(path, &MimeTypes::new())
+}
+
+/// The media type of a file, as `content_type_for` gives it, but looked up in the types given.
+pub fn content_type_with_types(path: &Path, types: &MimeTypes) -> MediaType {
+    match types.from_path(path) {
+        Some(media_type) => media_type,
+        None => MediaType(~"application", ~"octet-stream", ~[]),
+    }
+}
+
+/// Which part of a representation is to be sent in response to a request.
+#[deriving(Clone, Eq)]
+pub enum RangeSelection {
+    /// The whole representation, with a 200 OK response.
+    WholeRepresentation,
+    /// The range of bytes from the first position to the last (inclusive), with a 206 Partial
+    /// Content response.
+    PartialRepresentation(u64, u64),
+    /// None of it, as none of the ranges asked for can be satisfied; a 416 Requested Range Not
+    /// Satisfiable response is to be sent.
+    UnsatisfiableRange,
+}
+
+/// Decide which part of a representation of the given length to send, according to the Range
+/// header of a GET request. Only a single range is served; multiple ranges are not, as the
+/// `multipart/byteranges` type is not supported, and so the whole representation is sent.
+pub fn select_range(request: &Request, length: u64) -> RangeSelection {
+    let ranges = match request.headers.range {
+        Some(ref ranges) if request.method == Get => ranges,
+        _ => return WholeRepresentation,
+    };
+    let satisfiable = ranges.satisfiable_ranges(length);
+    match satisfiable.len() {
+        0 => UnsatisfiableRange,
+        1 => {
+            let (first, last) = satisfiable[0];
+            PartialRepresentation(first, last)
+        },
+        _ => WholeRepresentation,
+    }
+}
+
+/// Send an error response with the status; see `ResponseWriter.send_error`.
+pub fn send_error(response: &mut ResponseWriter, status: Status) {
+    response.send_error(status);
+}
+
+/// Serve the file at the request path under the root directory, with its Content-Type,
+/// Content-Length, Last-Modified and ETag. Only GET and HEAD requests are served; anything else has
+/// 405 Method Not Allowed.
+pub fn serve_file(root: &Path, request_path: &str, request: &Request,
+                  response: &mut ResponseWriter) {
+    serve_file_with_types(root, request_path, request, response, &MimeTypes::new())
+}
+
+/// Serve the file as `serve_file` does, but with its Content-Type looked up in the types given, as
+/// to serve extensions which `http::mime` doesn't know.
+pub fn serve_file_with_types(root: &Path, request_path: &str, request: &Request,
+                             response: &mut ResponseWriter, types: &MimeTypes) {
+    if request.method != Get && request.method != Head {
+        response.headers.allow = Some(~[Get, Head]);
+        return send_error(response, status::MethodNotAllowed);
+    }
+    let path = match resolve_path(root, request_path) {
+        Some(path) => path,
+        None => return send_error(response, status::Forbidden),
+    };
+    let stat = io_error::cond.trap(|_| ()).inside(|| path.stat());
+    let stat = match stat {
+        Some(stat) => stat,
+        None => return send_error(response, status::NotFound),
+    };
+    if !stat.is_file {
+        return send_error(response, status::Forbidden);
+    }
+    let file = io_error::cond.trap(|_| ()).inside(|| RawFile::open(&path));
+    let mut file = match file {
+        Some(file) => file,
+        None => return send_error(response, status::Forbidden),
+    };
+
+    response.headers.accept_ranges = Some(RangeUnits(~[Bytes]));
+    // FileStat.modified is in milliseconds.
+    let modified = at_utc(Timespec::new((stat.modified / 1000) as i64, 0));
+    let etag = weak_etag(format!("{:x}-{:x}", stat.size, stat.modified));
+    if conditional::handle_if_none_match(request, response, &etag)
+            || conditional::handle_if_modified_since(request, response, &modified) {
+        return;
+    }
+    let (first, length) = match select_range(request, stat.size) {
+        WholeRepresentation => (0, stat.size),
+        PartialRepresentation(first, last) => {
+            response.status = status::PartialContent;
+            response.headers.content_range = Some(ByteRange(first, last, Some(stat.size)));
+            (first, last - first + 1)
+        },
+        UnsatisfiableRange => {
+            response.headers.content_range = Some(UnsatisfiedRange(stat.size));
+            return send_error(response, status::RequestedRangeNotSatisfiable);
+        },
+    };
+    response.headers.content_type = Some(content_type_with_types(&path, types));
+    response.headers.content_length = Some(length as uint);
+    response.write_headers();
+    if request.method == Head {
+        return;
+    }
+    response.write_file(&mut file, first, length);
+}
+
+#[cfg(test)]
+mod test {
+    use headers::content_type::MediaType;
+    use super::{resolve_path, content_type_for};
+
+    #[test]
+    fn test_resolve_path() {
+        let root = Path::new("/srv/www");
+        assert_eq!(resolve_path(&root, "/"), Some(Path::new("/srv/www")));
+        assert_eq!(resolve_path(&root, "/css//site.css?v=2"),
+                   Some(Path::new("/srv/www/css/site.css")));
+        assert_eq!(resolve_path(&root, "/a/./b%20c"), Some(Path::new("/srv/www/a/b c")));
+        assert_eq!(resolve_path(&root, "/a/../b/%2e%2e/c"), Some(Path::new("/srv/www/c")));
+        assert_eq!(resolve_path(&root, "/a/../../etc/passwd"), None);
+        assert_eq!(resolve_path(&root, "/../etc/passwd"), None);
+        assert_eq!(resolve_path(&root, "/a/%2e%2e/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve_path(&root, "/a%2f..%2f..%2fetc"), None);
+        assert_eq!(resolve_path(&root, "/a%5c..%5cetc"), None);
+        assert_eq!(resolve_path(&root, "/a%00.html"), None);
+    }
+
+    #[test]
+    fn test_content_type_for() {
+        assert_eq!(content_type_for(&Path::new("index.HTML")), MediaType(~"text", ~"html", ~[]));
+        assert_eq!(content_type_for(&Path::new("a/b.png")), MediaType(~"image", ~"png", ~[]));
+        assert_eq!(content_type_for(&Path::new("README")),
+                   MediaType(~"application", ~"octet-stream", ~[]));
+    }
+}
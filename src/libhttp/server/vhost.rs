@@ -0,0 +1,173 @@
+/*!
+
+Virtual hosts: dispatch of requests to different handlers by the host they are for, so that one
+server can serve several domains.
+
+```rust
+let mut hosts = VirtualHosts::new();
+hosts.add("example.com", ~example_router as ~HostHandler:Send+Freeze);
+hosts.add("*.example.com", ~ServeUserPages as ~HostHandler:Send+Freeze);
+hosts.set_default(~Placeholder as ~HostHandler:Send+Freeze);
+// And in Server.handle_request:
+hosts.handle(request, response);
+```
+
+The host a request is for is that of its Request-URI, if it is an absolute URI, or else that of its
+Host header; the port is disregarded. A host name is matched without regard to case, or to a
+trailing dot. A pattern `*.example.com` matches any name ending in `.example.com` (however many
+labels come before it), but not `example.com` itself. An exact name is preferred to a wildcard, and
+the longest wildcard which matches to any other; failing any, the default handler is used, and if
+there is none, the response is 404 Not Found.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use server::{Request, ResponseWriter};
+use server::request::AbsoluteUri;
+use server::router::Router;
+use server::staticfile::send_error;
+use status;
+
+/// The handler for a virtual host.
+pub trait HostHandler {
+    /// Handle a request for the host.
+    fn handle(&self, request: &Request, response: &mut ResponseWriter);
+}
+
+impl HostHandler for Router {
+    fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        self.handle(request, response);
+    }
+}
+
+struct Host {
+    /// The name, lowercased, without a trailing dot; for a wildcard, what comes after the `*`,
+    /// as `.example.com`.
+    name: ~str,
+    wildcard: bool,
+    handler: ~HostHandler:Send+Freeze,
+}
+
+/// A set of virtual hosts, each with the handler for requests for it.
+pub struct VirtualHosts {
+    priv hosts: ~[Host],
+    priv default: Option<~HostHandler:Send+Freeze>,
+}
+
+impl VirtualHosts {
+    /// No hosts, and no default.
+    pub fn new() -> VirtualHosts {
+        VirtualHosts { hosts: ~[], default: None }
+    }
+
+    /// Add a host, by its name or a wildcard pattern, as `*.example.com`. This fails if the
+    /// pattern has a `*` anywhere but as its first label.
+    pub fn add(&mut self, pattern: &str, handler: ~HostHandler:Send+Freeze) {
+        let (name, wildcard) = if pattern.starts_with("*.") {
+            (pattern.slice_from(1), true)
+        } else {
+            (pattern, false)
+        };
+        if name.contains_char('*') {
+            fail!("the host pattern {} has a * which isn't its first label", pattern);
+        }
+        self.hosts.push(Host {
+            name: normalize(name),
+            wildcard: wildcard,
+            handler: handler,
+        });
+    }
+
+    /// Set the handler for requests for hosts which haven't been added, or with no host at all.
+    pub fn set_default(&mut self, handler: ~HostHandler:Send+Freeze) {
+        self.default = Some(handler);
+    }
+
+    /// The index of the host added which is to handle requests for the name given, if any.
+    pub fn select(&self, name: &str) -> Option<uint> {
+        let name = normalize(name);
+        let mut best: Option<uint> = None;
+        for (i, host) in self.hosts.iter().enumerate() {
+            if !host.wildcard {
+                if host.name == name {
+                    return Some(i);
+                }
+            } else if name.ends_with(host.name) && name.len() > host.name.len() {
+                best = match best {
+                    Some(b) if self.hosts[b].name.len() >= host.name.len() => Some(b),
+                    _ => Some(i),
+                };
+            }
+        }
+        best
+    }
+
+    /// Handle a request with the handler for its host, or else the default handler, or else send
+    /// 404 Not Found.
+    pub fn handle(&self, request: &Request, response: &mut ResponseWriter) {
+        let selected = match request_host(request) {
+            Some(name) => self.select(name),
+            None => None,
+        };
+        match selected {
+            Some(i) => self.hosts[i].handler.handle(request, response),
+            None => match self.default {
+                Some(ref handler) => handler.handle(request, response),
+                None => send_error(response, status::NotFound),
+            },
+        }
+    }
+}
+
+/// The name of the host a request is for, from its Request-URI or its Host header, if it has one.
+pub fn request_host<'a>(request: &'a Request) -> Option<&'a str> {
+    match request.request_uri {
+        AbsoluteUri(ref url) => Some(url.host.as_slice()),
+        _ => match request.headers.host {
+            Some(ref host) => Some(host.name.as_slice()),
+            None => None,
+        },
+    }
+}
+
+fn normalize(name: &str) -> ~str {
+    let name = if name.ends_with(".") { name.slice_to(name.len() - 1) } else { name };
+    name.to_ascii_lower()
+}
+
+#[cfg(test)]
+mod test {
+    use server::{Request, ResponseWriter};
+    use super::{VirtualHosts, HostHandler};
+
+    struct NullHandler;
+
+    impl HostHandler for NullHandler {
+        fn handle(&self, _request: &Request, _response: &mut ResponseWriter) {
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let mut hosts = VirtualHosts::new();
+        hosts.add("*.example.com", ~NullHandler as ~HostHandler:Send+Freeze);
+        hosts.add("example.com", ~NullHandler as ~HostHandler:Send+Freeze);
+        hosts.add("*.static.example.com", ~NullHandler as ~HostHandler:Send+Freeze);
+        hosts.add("www.example.com", ~NullHandler as ~HostHandler:Send+Freeze);
+        assert_eq!(hosts.select("example.com"), Some(1));
+        assert_eq!(hosts.select("EXAMPLE.com."), Some(1));
+        assert_eq!(hosts.select("www.example.com"), Some(3));
+        assert_eq!(hosts.select("blog.example.com"), Some(0));
+        assert_eq!(hosts.select("a.b.example.com"), Some(0));
+        assert_eq!(hosts.select("img.static.example.com"), Some(2));
+        assert_eq!(hosts.select("notexample.com"), None);
+        assert_eq!(hosts.select("example.org"), None);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_wildcard_not_first() {
+        let mut hosts = VirtualHosts::new();
+        hosts.add("www.*.com", ~NullHandler as ~HostHandler:Send+Freeze);
+    }
+}
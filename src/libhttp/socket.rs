@@ -0,0 +1,1072 @@
+/*!
+
+Sockets made and used through the C library, for what `std::rt::io::net` can't yet do.
+
+`std::rt::io` keeps a socket's file descriptor to itself, so there is no setting options such as
+`TCP_NODELAY` or the listen backlog on one of its sockets. A `SocketListener`, and each `Socket` it
+accepts, is an ordinary file descriptor instead, set up as its `SocketOptions` ask; the server
+listens and serves its connections on them.
+
+A listener may instead be bound to the path of a Unix domain socket, with
+`SocketListener::bind_unix`, and a `Socket` connected to one, with `Socket::connect` and an
+`Address`, for a server behind a proxy on the same machine (nginx, say) or talking HTTP between
+processes. A listener may also be taken over from the process which started this one, which has
+bound it (see `listeners_from_env`), so that a server can be restarted without refusing
+connections in the meantime.
+
+A `RawFile` is a file opened through the C library too, for its bytes to be sent straight from it
+to a socket with `Socket.send_file`, as the server sends static files, where the system allows.
+
+Their calls block: one waits in its thread, not only in its task, so a task using them should have
+a thread of its own, as `spawn_blocking` gives it.
+
+```rust
+use http::socket::{SocketListener, SocketOptions, spawn_blocking};
+
+let mut options = SocketOptions::new();
+options.nodelay = true;
+let mut listener = SocketListener::bind(addr, &options).unwrap();
+loop {
+    let socket = Cell::new(listener.accept().unwrap());
+    do spawn_blocking {
+        let mut socket = socket.take();
+        socket.write(bytes!("HTTP/1.1 204 No Content\r\n\r\n"));
+    }
+}
+```
+
+*/
+
+use std::cmp::min;
+use std::libc::{c_int, c_void, size_t, off_t};
+use std::os;
+use std::ptr;
+use std::str;
+use std::sys;
+use std::task;
+use std::vec;
+use std::c_str::ToCStr;
+use std::rt::io::{Reader, Writer, Seek, SeekStyle, SeekSet, SeekCur, SeekEnd};
+use std::rt::io::{io_error, IoError, OtherIoError};
+use std::rt::io::file::FileInfo;
+use std::rt::io::net::ip::{SocketAddr, Ipv4Addr, Ipv6Addr};
+
+/// The foreign interface to the C library's sockets, limited to what is needed here; what differs
+/// between systems is in `platform`.
+#[allow(non_camel_case_types, non_uppercase_statics)]
+mod ffi {
+    use std::libc::{c_int, c_char, c_void, size_t, ssize_t, off_t};
+
+    pub type socklen_t = u32;
+
+    /// Room for any address a socket may have, aligned for any of them.
+    pub struct sockaddr_storage {
+        data: [u64, ..16],
+    }
+
+    pub static SOCK_STREAM: c_int = 1;
+    pub static IPPROTO_TCP: c_int = 6;
+    pub static IPPROTO_IPV6: c_int = 41;
+    pub static TCP_NODELAY: c_int = 1;
+    pub static EINTR: c_int = 4;
+    pub static EINVAL: c_int = 22;
+    pub static SHUT_WR: c_int = 1;
+    pub static O_RDONLY: c_int = 0;
+    pub static SEEK_SET: c_int = 0;
+    pub static SEEK_CUR: c_int = 1;
+    pub static SEEK_END: c_int = 2;
+
+    extern "C" {
+        pub fn socket(domain: c_int, type_: c_int, protocol: c_int) -> c_int;
+        pub fn bind(fd: c_int, addr: *c_void, len: socklen_t) -> c_int;
+        pub fn listen(fd: c_int, backlog: c_int) -> c_int;
+        pub fn accept(fd: c_int, addr: *mut c_void, len: *mut socklen_t) -> c_int;
+        pub fn connect(fd: c_int, addr: *c_void, len: socklen_t) -> c_int;
+        pub fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *c_void,
+                          len: socklen_t) -> c_int;
+        pub fn getsockname(fd: c_int, addr: *mut c_void, len: *mut socklen_t) -> c_int;
+        pub fn getpeername(fd: c_int, addr: *mut c_void, len: *mut socklen_t) -> c_int;
+        pub fn send(fd: c_int, buf: *c_void, len: size_t, flags: c_int) -> ssize_t;
+        pub fn recv(fd: c_int, buf: *mut c_void, len: size_t, flags: c_int) -> ssize_t;
+        pub fn shutdown(fd: c_int, how: c_int) -> c_int;
+        pub fn dup(fd: c_int) -> c_int;
+        pub fn close(fd: c_int) -> c_int;
+        pub fn getpid() -> c_int;
+        pub fn open(path: *c_char, flags: c_int) -> c_int;
+        pub fn read(fd: c_int, buf: *mut c_void, len: size_t) -> ssize_t;
+        pub fn lseek(fd: c_int, offset: off_t, whence: c_int) -> off_t;
+    }
+}
+
+/// Linux's (and Android's) layouts and values.
+#[cfg(target_os = "linux")]
+#[cfg(target_os = "android")]
+#[allow(non_camel_case_types, non_uppercase_statics)]
+mod platform {
+    use std::libc::{c_int, c_long, size_t, ssize_t, off_t};
+    use std::ptr;
+    use super::ffi::sockaddr_storage;
+
+    pub struct sockaddr_in {
+        sin_family: u16,
+        sin_port: [u8, ..2],
+        sin_addr: [u8, ..4],
+        sin_zero: [u8, ..8],
+    }
+
+    pub struct sockaddr_in6 {
+        sin6_family: u16,
+        sin6_port: [u8, ..2],
+        sin6_flowinfo: u32,
+        sin6_addr: [u8, ..16],
+        sin6_scope_id: u32,
+    }
+
+    pub struct sockaddr_un {
+        sun_family: u16,
+        sun_path: [u8, ..108],
+    }
+
+    pub struct timeval {
+        tv_sec: c_long,
+        tv_usec: c_long,
+    }
+
+    pub static AF_UNIX: c_int = 1;
+    pub static AF_INET: c_int = 2;
+    pub static AF_INET6: c_int = 10;
+    pub static SOL_SOCKET: c_int = 1;
+    pub static SO_REUSEADDR: c_int = 2;
+    pub static SO_RCVTIMEO: c_int = 20;
+    pub static SO_SNDTIMEO: c_int = 21;
+    pub static IPV6_V6ONLY: c_int = 26;
+    pub static EAGAIN: c_int = 11;
+    pub static ENOSYS: c_int = 38;
+
+    /// Whether `sendfile` may be used.
+    pub static HAS_SENDFILE: bool = true;
+
+    extern "C" {
+        pub fn sendfile(out_fd: c_int, in_fd: c_int, offset: *mut off_t, count: size_t)
+                        -> ssize_t;
+    }
+
+    /// Passed to every `send`: MSG_NOSIGNAL, so that writing to a connection which the peer has
+    /// closed fails rather than raising SIGPIPE.
+    pub static SEND_FLAGS: c_int = 0x4000;
+
+    /// Stop a new socket raising SIGPIPE; here, `SEND_FLAGS` does that.
+    pub fn no_sigpipe(_fd: c_int) {
+    }
+
+    pub fn sockaddr_in(port: [u8, ..2], addr: [u8, ..4]) -> sockaddr_in {
+        sockaddr_in {
+            sin_family: AF_INET as u16,
+            sin_port: port,
+            sin_addr: addr,
+            sin_zero: [0, ..8],
+        }
+    }
+
+    pub fn sockaddr_in6(port: [u8, ..2], addr: [u8, ..16]) -> sockaddr_in6 {
+        sockaddr_in6 {
+            sin6_family: AF_INET6 as u16,
+            sin6_port: port,
+            sin6_flowinfo: 0,
+            sin6_addr: addr,
+            sin6_scope_id: 0,
+        }
+    }
+
+    /// The address of a Unix domain socket, if the path isn't too long for one.
+    pub fn sockaddr_un(path: &[u8]) -> Option<sockaddr_un> {
+        let mut addr = sockaddr_un { sun_family: AF_UNIX as u16, sun_path: [0, ..108] };
+        // There must be room left for the NUL
+        if path.len() >= addr.sun_path.len() {
+            return None;
+        }
+        for (i, &b) in path.iter().enumerate() {
+            addr.sun_path[i] = b;
+        }
+        Some(addr)
+    }
+
+    /// The address family of an address.
+    pub fn family(storage: &sockaddr_storage) -> c_int {
+        unsafe { *(ptr::to_unsafe_ptr(storage) as *u16) as c_int }
+    }
+
+    pub fn timeval(ms: u64) -> timeval {
+        timeval { tv_sec: (ms / 1000) as c_long, tv_usec: (ms % 1000 * 1000) as c_long }
+    }
+}
+
+/// Mac OS X's layouts and values, which begin each address with its length.
+#[cfg(target_os = "macos")]
+#[allow(non_camel_case_types, non_uppercase_statics)]
+mod platform {
+    use std::libc::{c_int, c_long, c_void, size_t, ssize_t, off_t};
+    use std::ptr;
+    use std::sys;
+    use super::ffi;
+    use super::ffi::sockaddr_storage;
+
+    pub struct sockaddr_in {
+        sin_len: u8,
+        sin_family: u8,
+        sin_port: [u8, ..2],
+        sin_addr: [u8, ..4],
+        sin_zero: [u8, ..8],
+    }
+
+    pub struct sockaddr_in6 {
+        sin6_len: u8,
+        sin6_family: u8,
+        sin6_port: [u8, ..2],
+        sin6_flowinfo: u32,
+        sin6_addr: [u8, ..16],
+        sin6_scope_id: u32,
+    }
+
+    pub struct sockaddr_un {
+        sun_len: u8,
+        sun_family: u8,
+        sun_path: [u8, ..104],
+    }
+
+    pub struct timeval {
+        tv_sec: c_long,
+        tv_usec: i32,
+    }
+
+    pub static AF_UNIX: c_int = 1;
+    pub static AF_INET: c_int = 2;
+    pub static AF_INET6: c_int = 30;
+    pub static SOL_SOCKET: c_int = 0xffff;
+    pub static SO_REUSEADDR: c_int = 4;
+    pub static SO_SNDTIMEO: c_int = 0x1005;
+    pub static SO_RCVTIMEO: c_int = 0x1006;
+    pub static SO_NOSIGPIPE: c_int = 0x1022;
+    pub static IPV6_V6ONLY: c_int = 27;
+    pub static EAGAIN: c_int = 35;
+    pub static ENOSYS: c_int = 78;
+
+    /// Whether `sendfile` may be used: Mac OS X's takes other arguments, and isn't, yet.
+    pub static HAS_SENDFILE: bool = false;
+
+    pub unsafe fn sendfile(_out_fd: c_int, _in_fd: c_int, _offset: *mut off_t, _count: size_t)
+                           -> ssize_t {
+        -1
+    }
+
+    /// Passed to every `send`; there is no MSG_NOSIGNAL, so see `no_sigpipe`.
+    pub static SEND_FLAGS: c_int = 0;
+
+    /// Stop a new socket raising SIGPIPE when written to after the peer has closed it.
+    pub fn no_sigpipe(fd: c_int) {
+        let one: c_int = 1;
+        unsafe {
+            ffi::setsockopt(fd, SOL_SOCKET, SO_NOSIGPIPE, ptr::to_unsafe_ptr(&one) as *c_void,
+                            sys::size_of::<c_int>() as ffi::socklen_t);
+        }
+    }
+
+    pub fn sockaddr_in(port: [u8, ..2], addr: [u8, ..4]) -> sockaddr_in {
+        sockaddr_in {
+            sin_len: sys::size_of::<sockaddr_in>() as u8,
+            sin_family: AF_INET as u8,
+            sin_port: port,
+            sin_addr: addr,
+            sin_zero: [0, ..8],
+        }
+    }
+
+    pub fn sockaddr_in6(port: [u8, ..2], addr: [u8, ..16]) -> sockaddr_in6 {
+        sockaddr_in6 {
+            sin6_len: sys::size_of::<sockaddr_in6>() as u8,
+            sin6_family: AF_INET6 as u8,
+            sin6_port: port,
+            sin6_flowinfo: 0,
+            sin6_addr: addr,
+            sin6_scope_id: 0,
+        }
+    }
+
+    /// The address of a Unix domain socket, if the path isn't too long for one.
+    pub fn sockaddr_un(path: &[u8]) -> Option<sockaddr_un> {
+        let mut addr = sockaddr_un {
+            sun_len: sys::size_of::<sockaddr_un>() as u8,
+            sun_family: AF_UNIX as u8,
+            sun_path: [0, ..104],
+        };
+        // There must be room left for the NUL
+        if path.len() >= addr.sun_path.len() {
+            return None;
+        }
+        for (i, &b) in path.iter().enumerate() {
+            addr.sun_path[i] = b;
+        }
+        Some(addr)
+    }
+
+    /// The address family of an address.
+    pub fn family(storage: &sockaddr_storage) -> c_int {
+        unsafe { *(ptr::to_unsafe_ptr(storage) as *u8).offset(1) as c_int }
+    }
+
+    pub fn timeval(ms: u64) -> timeval {
+        timeval { tv_sec: (ms / 1000) as c_long, tv_usec: (ms % 1000 * 1000) as i32 }
+    }
+}
+
+/// Options for the sockets a server listens on and the connections it accepts (see
+/// `server::Config.socket_options`), or for a client's connections, of which only `nodelay` has
+/// any bearing.
+#[deriving(Clone)]
+pub struct SocketOptions {
+    /// Whether to set TCP_NODELAY on each connection, so that what is written is sent at once
+    /// rather than held back, as Nagle's algorithm would, in case there is more to send with it.
+    /// This is for responses which are small and latency-sensitive; false by default.
+    nodelay: bool,
+
+    /// Whether to set SO_REUSEADDR on each listening socket, so that a server can be restarted on
+    /// the same address while the old one's connections are still closing; true by default.
+    reuse_addr: bool,
+
+    /// How many connections may be waiting to be accepted before more are refused; 128 by
+    /// default. The system may hold this to a lower limit of its own.
+    backlog: uint,
+}
+
+impl SocketOptions {
+    /// The default options: no TCP_NODELAY, SO_REUSEADDR, and a backlog of 128.
+    pub fn new() -> SocketOptions {
+        SocketOptions {
+            nodelay: false,
+            reuse_addr: true,
+            backlog: 128,
+        }
+    }
+}
+
+/// Where a socket listens, or connects to: an IP address and port, or the path of a Unix domain
+/// socket.
+#[deriving(Clone, Eq)]
+pub enum Address {
+    TcpAddress(SocketAddr),
+    UnixAddress(Path),
+}
+
+impl ToStr for Address {
+    fn to_str(&self) -> ~str {
+        match *self {
+            TcpAddress(ref addr) => addr.to_str(),
+            UnixAddress(ref path) => format!("unix:{}", path.to_str()),
+        }
+    }
+}
+
+/// A socket listening for connections.
+pub struct SocketListener {
+    priv fd: c_int,
+    /// The path of the Unix domain socket, if it is one, to be removed when it is closed.
+    priv path: Option<Path>,
+    /// Whether to set TCP_NODELAY on each connection accepted.
+    priv nodelay: bool,
+}
+
+impl SocketListener {
+    /// Bind a socket to `addr` and listen on it, set up as the options ask. An IPv6 socket takes
+    /// only IPv6 connections (IPV6_V6ONLY), so that `[::]` and `0.0.0.0` can be bound alike;
+    /// `SocketListener.socket_name` gives the port chosen if `addr`'s is 0.
+    ///
+    /// Should it fail, the `io_error` condition is raised; if it is handled, `None` is returned.
+    pub fn bind(addr: SocketAddr, options: &SocketOptions) -> Option<SocketListener> {
+        let family = match addr.ip {
+            Ipv4Addr(*) => platform::AF_INET,
+            Ipv6Addr(*) => platform::AF_INET6,
+        };
+        let listener = match new_socket(family) {
+            Some(fd) => SocketListener { fd: fd, path: None, nodelay: options.nodelay },
+            None => return None,
+        };
+        if options.reuse_addr
+                && !set_option(listener.fd, platform::SOL_SOCKET, platform::SO_REUSEADDR, 1) {
+            io_error::cond.raise(last_error("Couldn't set SO_REUSEADDR on the socket"));
+            return None;
+        }
+        if family == platform::AF_INET6
+                && !set_option(listener.fd, ffi::IPPROTO_IPV6, platform::IPV6_V6ONLY, 1) {
+            io_error::cond.raise(last_error("Couldn't set IPV6_V6ONLY on the socket"));
+            return None;
+        }
+        let (storage, len) = to_sockaddr(addr);
+        if unsafe { ffi::bind(listener.fd, ptr::to_unsafe_ptr(&storage) as *c_void, len) } != 0 {
+            io_error::cond.raise(last_error("Couldn't bind the socket"));
+            return None;
+        }
+        listener.listen(options.backlog)
+    }
+
+    /// Bind a Unix domain socket to the path and listen on it, with the options' backlog (the
+    /// others are for TCP). A socket already at the path, as one left by a server before, is
+    /// removed first, though no other file is; the socket is removed again when the listener is
+    /// dropped.
+    ///
+    /// Should it fail, the `io_error` condition is raised; if it is handled, `None` is returned.
+    pub fn bind_unix(path: &Path, options: &SocketOptions) -> Option<SocketListener> {
+        let (storage, len) = match unix_sockaddr(path) {
+            Some(addr) => addr,
+            None => return None,
+        };
+        if is_socket(path) {
+            os::remove_file(path);
+        }
+        let mut listener = match new_socket(platform::AF_UNIX) {
+            Some(fd) => SocketListener { fd: fd, path: None, nodelay: false },
+            None => return None,
+        };
+        if unsafe { ffi::bind(listener.fd, ptr::to_unsafe_ptr(&storage) as *c_void, len) } != 0 {
+            io_error::cond.raise(last_error("Couldn't bind the socket"));
+            return None;
+        }
+        listener.path = Some(path.clone());
+        listener.listen(options.backlog)
+    }
+
+    /// Take over a socket which is already bound and listening, as one passed down by the process
+    /// which started this one (see `listeners_from_env`); of the options, only `nodelay` applies,
+    /// and only if it is a TCP socket. A Unix domain socket's path isn't removed when it is
+    /// dropped, for it wasn't bound here.
+    pub fn from_fd(fd: c_int, options: &SocketOptions) -> SocketListener {
+        let mut listener = SocketListener { fd: fd, path: None, nodelay: false };
+        listener.nodelay = options.nodelay && match listener.address() {
+            Some(TcpAddress(*)) => true,
+            _ => false,
+        };
+        listener
+    }
+
+    fn listen(self, backlog: uint) -> Option<SocketListener> {
+        if unsafe { ffi::listen(self.fd, backlog as c_int) } != 0 {
+            io_error::cond.raise(last_error("Couldn't listen on the socket"));
+            return None;
+        }
+        Some(self)
+    }
+
+    /// Wait for a connection, and accept it, setting TCP_NODELAY on it if the options asked.
+    ///
+    /// Should it fail, the `io_error` condition is raised; if it is handled, `None` is returned.
+    pub fn accept(&mut self) -> Option<Socket> {
+        loop {
+            let fd = unsafe { ffi::accept(self.fd, ptr::mut_null(), ptr::mut_null()) };
+            if fd >= 0 {
+                platform::no_sigpipe(fd);
+                if self.nodelay {
+                    // The connection is good all the same, should this fail.
+                    set_option(fd, ffi::IPPROTO_TCP, ffi::TCP_NODELAY, 1);
+                }
+                return Some(Socket::new(fd));
+            }
+            if os::errno() as c_int != ffi::EINTR {
+                io_error::cond.raise(last_error("Couldn't accept a connection"));
+                return None;
+            }
+        }
+    }
+
+    /// The address the socket is bound to, if it is an IP address and port; see
+    /// `SocketListener.address` for a Unix domain socket's too.
+    pub fn socket_name(&self) -> Option<SocketAddr> {
+        match self.local_sockaddr() {
+            Some(storage) => from_sockaddr(&storage),
+            None => None,
+        }
+    }
+
+    /// The address the socket is bound to, if it can be told: its IP address and port, or its
+    /// path, if it is a Unix domain socket.
+    pub fn address(&self) -> Option<Address> {
+        match self.path {
+            Some(ref path) => Some(UnixAddress(path.clone())),
+            None => match self.local_sockaddr() {
+                Some(storage) => to_address(&storage),
+                None => None,
+            },
+        }
+    }
+
+    fn local_sockaddr(&self) -> Option<ffi::sockaddr_storage> {
+        let mut storage = ffi::sockaddr_storage { data: [0, ..16] };
+        let mut len = sys::size_of::<ffi::sockaddr_storage>() as ffi::socklen_t;
+        let ret = unsafe {
+            ffi::getsockname(self.fd, ptr::to_mut_unsafe_ptr(&mut storage) as *mut c_void,
+                             ptr::to_mut_unsafe_ptr(&mut len))
+        };
+        if ret == 0 { Some(storage) } else { None }
+    }
+}
+
+impl Drop for SocketListener {
+    fn drop(&mut self) {
+        unsafe { ffi::close(self.fd); }
+        match self.path {
+            Some(ref path) => { os::remove_file(path); },
+            None => (),
+        }
+    }
+}
+
+/// A connected socket.
+///
+/// Errors in reading and writing are raised as `io_error`, as with a `TcpStream`; once a write has
+/// failed, or timed out (see `set_write_timeout`), all later writes are dropped, for the connection
+/// is no use. A read which times out (see `set_read_timeout`) isn't an error, but ends the stream.
+pub struct Socket {
+    priv fd: c_int,
+    priv eof: bool,
+    priv read_timed_out: bool,
+    priv write_failed: bool,
+}
+
+impl Socket {
+    fn new(fd: c_int) -> Socket {
+        Socket { fd: fd, eof: false, read_timed_out: false, write_failed: false }
+    }
+
+    /// Connect to the address, which may be the path of a Unix domain socket.
+    ///
+    /// Should it fail, the `io_error` condition is raised; if it is handled, `None` is returned.
+    pub fn connect(addr: &Address) -> Option<Socket> {
+        let (family, (storage, len)) = match *addr {
+            TcpAddress(addr) => (match addr.ip {
+                Ipv4Addr(*) => platform::AF_INET,
+                Ipv6Addr(*) => platform::AF_INET6,
+            }, to_sockaddr(addr)),
+            UnixAddress(ref path) => match unix_sockaddr(path) {
+                Some(addr) => (platform::AF_UNIX, addr),
+                None => return None,
+            },
+        };
+        let socket = match new_socket(family) {
+            Some(fd) => Socket::new(fd),
+            None => return None,
+        };
+        if unsafe { ffi::connect(socket.fd, ptr::to_unsafe_ptr(&storage) as *c_void, len) } != 0 {
+            io_error::cond.raise(last_error("Couldn't connect"));
+            return None;
+        }
+        Some(socket)
+    }
+
+    /// Another handle on the same connection, as to read from it in one task while writing to it
+    /// in another. The connection stays open until both have been dropped.
+    ///
+    /// Should it fail, the `io_error` condition is raised; if it is handled, `None` is returned.
+    pub fn try_clone(&self) -> Option<Socket> {
+        let fd = unsafe { ffi::dup(self.fd) };
+        if fd < 0 {
+            io_error::cond.raise(last_error("Couldn't duplicate the socket"));
+            return None;
+        }
+        Some(Socket::new(fd))
+    }
+
+    /// Tell the peer, as it will find by reading to the end of the stream, that nothing more is
+    /// to be written to the connection, though it may still be read from. This goes for every
+    /// handle on it (see `try_clone`).
+    pub fn shutdown_write(&mut self) {
+        if unsafe { ffi::shutdown(self.fd, ffi::SHUT_WR) } != 0 {
+            io_error::cond.raise(last_error("Couldn't shut down the socket"));
+        }
+    }
+
+    /// Set or clear TCP_NODELAY (see `SocketOptions.nodelay`).
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        if !set_option(self.fd, ffi::IPPROTO_TCP, ffi::TCP_NODELAY, nodelay as c_int) {
+            io_error::cond.raise(last_error("Couldn't set TCP_NODELAY on the socket"));
+        }
+    }
+
+    /// Give up on a write once the peer has taken none of it for `ms` milliseconds, as a client
+    /// which has stopped reading wouldn't; with `None`, the default, a write waits as long as it
+    /// takes. A write which gives up raises `io_error` with a timeout error (see `is_timeout`).
+    pub fn set_write_timeout(&mut self, ms: Option<u64>) {
+        if !set_timeout(self.fd, platform::SO_SNDTIMEO, ms) {
+            io_error::cond.raise(last_error("Couldn't set SO_SNDTIMEO on the socket"));
+        }
+    }
+
+    /// Give up on a read once the peer has sent nothing for `ms` milliseconds; with `None`, the
+    /// default, a read waits as long as it takes. A read which gives up ends the stream, as though
+    /// the peer had closed the connection, and `read_timed_out` is then true.
+    pub fn set_read_timeout(&mut self, ms: Option<u64>) {
+        if !set_timeout(self.fd, platform::SO_RCVTIMEO, ms) {
+            io_error::cond.raise(last_error("Couldn't set SO_RCVTIMEO on the socket"));
+        }
+    }
+
+    /// Whether a read has timed out, ending the stream.
+    pub fn read_timed_out(&self) -> bool {
+        self.read_timed_out
+    }
+
+    /// Send up to `length` bytes of the file, from `offset` (whatever its position), straight from
+    /// it to the connection with `sendfile`, without their being read into memory here, returning
+    /// how many there were (fewer, should the file end first). `None` is returned, with nothing
+    /// sent, should the system not be able to send this file so, or not have `sendfile` at all;
+    /// the file is then to be read and written as usual. Errors are raised as with `write`.
+    pub fn send_file(&mut self, file: &RawFile, offset: u64, length: u64) -> Option<u64> {
+        if !platform::HAS_SENDFILE {
+            return None;
+        }
+        let mut off = offset as off_t;
+        let mut sent = 0u64;
+        while sent < length && !self.write_failed {
+            let count = min(length - sent, SEND_FILE_CHUNK) as size_t;
+            let ret = unsafe {
+                platform::sendfile(self.fd, file.fd, ptr::to_mut_unsafe_ptr(&mut off), count)
+            };
+            if ret > 0 {
+                sent += ret as u64;
+                continue;
+            }
+            if ret == 0 {
+                // The end of the file
+                break;
+            }
+            let errno = os::errno() as c_int;
+            if sent == 0 && (errno == ffi::EINVAL || errno == platform::ENOSYS) {
+                return None;
+            } else if errno == platform::EAGAIN {
+                self.write_failed = true;
+                io_error::cond.raise(IoError {
+                    kind: OtherIoError,
+                    desc: WRITE_TIMEOUT_DESC,
+                    detail: None,
+                });
+            } else if errno != ffi::EINTR {
+                self.write_failed = true;
+                io_error::cond.raise(last_error("Couldn't send the file"));
+            }
+        }
+        Some(sent)
+    }
+
+    /// The address of the other end of the connection.
+    pub fn peer_name(&mut self) -> Option<SocketAddr> {
+        let mut storage = ffi::sockaddr_storage { data: [0, ..16] };
+        let mut len = sys::size_of::<ffi::sockaddr_storage>() as ffi::socklen_t;
+        let ret = unsafe {
+            ffi::getpeername(self.fd, ptr::to_mut_unsafe_ptr(&mut storage) as *mut c_void,
+                             ptr::to_mut_unsafe_ptr(&mut len))
+        };
+        if ret == 0 { from_sockaddr(&storage) } else { None }
+    }
+}
+
+impl Reader for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        if buf.len() == 0 {
+            return Some(0);
+        }
+        loop {
+            let ret = unsafe {
+                ffi::recv(self.fd, vec::raw::to_mut_ptr(buf) as *mut c_void, buf.len() as size_t,
+                          0)
+            };
+            if ret > 0 {
+                return Some(ret as uint);
+            }
+            if ret == 0 {
+                self.eof = true;
+                return None;
+            }
+            if os::errno() as c_int == platform::EAGAIN {
+                self.eof = true;
+                self.read_timed_out = true;
+                return None;
+            }
+            if os::errno() as c_int != ffi::EINTR {
+                self.eof = true;
+                io_error::cond.raise(last_error("Couldn't read from the connection"));
+                return None;
+            }
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        self.eof
+    }
+}
+
+impl Writer for Socket {
+    fn write(&mut self, buf: &[u8]) {
+        let mut written = 0;
+        while written < buf.len() && !self.write_failed {
+            let rest = buf.slice_from(written);
+            let ret = unsafe {
+                ffi::send(self.fd, vec::raw::to_ptr(rest) as *c_void, rest.len() as size_t,
+                          platform::SEND_FLAGS)
+            };
+            if ret >= 0 {
+                written += ret as uint;
+            } else if os::errno() as c_int == platform::EAGAIN {
+                self.write_failed = true;
+                io_error::cond.raise(IoError {
+                    kind: OtherIoError,
+                    desc: WRITE_TIMEOUT_DESC,
+                    detail: None,
+                });
+            } else if os::errno() as c_int != ffi::EINTR {
+                self.write_failed = true;
+                io_error::cond.raise(last_error("Couldn't write to the connection"));
+            }
+        }
+    }
+
+    /// Everything written is sent straight away, so there is nothing to do.
+    fn flush(&mut self) {
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { ffi::close(self.fd); }
+    }
+}
+
+/// The most bytes `Socket.send_file` asks the system to send at once.
+static SEND_FILE_CHUNK: u64 = 0x100000;
+
+/// A file opened for reading through the C library, so that it can be sent with
+/// `Socket.send_file`; it can be read and sought in as any other.
+pub struct RawFile {
+    priv fd: c_int,
+    priv eof: bool,
+}
+
+impl RawFile {
+    /// Open the file at the path for reading.
+    ///
+    /// Should it fail, the `io_error` condition is raised; if it is handled, `None` is returned.
+    pub fn open(path: &Path) -> Option<RawFile> {
+        let fd = do path.to_str().with_c_str |p| {
+            unsafe { ffi::open(p, ffi::O_RDONLY) }
+        };
+        if fd < 0 {
+            io_error::cond.raise(last_error("Couldn't open the file"));
+            return None;
+        }
+        Some(RawFile { fd: fd, eof: false })
+    }
+}
+
+impl Reader for RawFile {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        if buf.len() == 0 {
+            return Some(0);
+        }
+        loop {
+            let ret = unsafe {
+                ffi::read(self.fd, vec::raw::to_mut_ptr(buf) as *mut c_void, buf.len() as size_t)
+            };
+            if ret > 0 {
+                return Some(ret as uint);
+            }
+            if ret == 0 {
+                self.eof = true;
+                return None;
+            }
+            if os::errno() as c_int != ffi::EINTR {
+                self.eof = true;
+                io_error::cond.raise(last_error("Couldn't read the file"));
+                return None;
+            }
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        self.eof
+    }
+}
+
+impl Seek for RawFile {
+    fn tell(&self) -> u64 {
+        unsafe { ffi::lseek(self.fd, 0, ffi::SEEK_CUR) as u64 }
+    }
+
+    fn seek(&mut self, pos: i64, style: SeekStyle) {
+        let whence = match style {
+            SeekSet => ffi::SEEK_SET,
+            SeekCur => ffi::SEEK_CUR,
+            SeekEnd => ffi::SEEK_END,
+        };
+        if unsafe { ffi::lseek(self.fd, pos as off_t, whence) } < 0 {
+            io_error::cond.raise(last_error("Couldn't seek in the file"));
+        } else {
+            self.eof = false;
+        }
+    }
+}
+
+impl Drop for RawFile {
+    fn drop(&mut self) {
+        unsafe { ffi::close(self.fd); }
+    }
+}
+
+/// What an error raised by a write which has timed out says.
+static WRITE_TIMEOUT_DESC: &'static str = "Timed out writing to the connection";
+
+/// Whether the error is that raised by a `Socket` on timing out.
+pub fn is_timeout(err: &IoError) -> bool {
+    err.desc == WRITE_TIMEOUT_DESC
+}
+
+/// Spawn a task, supervised by this one, on a thread of its own, for it to use sockets in without
+/// holding up other tasks as it waits on them.
+pub fn spawn_blocking(f: ~fn()) {
+    let mut builder = task::task();
+    builder.supervised();
+    builder.sched_mode(task::SingleThreaded);
+    builder.spawn(f);
+}
+
+/// The first file descriptor passed down by socket activation, after standard input, output and
+/// error.
+static LISTEN_FDS_START: c_int = 3;
+
+/// The listening sockets passed down by the process which started this one, as systemd's socket
+/// activation does (sd_listen_fds(3)), and other supervisors, such as einhorn, too: `LISTEN_FDS`
+/// says how many there are, as the file descriptors from 3 on, and `LISTEN_PID`, if it is set,
+/// must be this process's ID. Both are then unset, so that a process started from this one doesn't
+/// take them to be its own as well. Pass them to `SocketListener::from_fd`, or set them in
+/// `server::Config.inherited_listeners`.
+pub fn listeners_from_env() -> ~[c_int] {
+    let count: uint = match os::getenv("LISTEN_FDS") {
+        Some(count) => match FromStr::from_str(count.as_slice()) {
+            Some(count) => count,
+            None => return ~[],
+        },
+        None => return ~[],
+    };
+    match os::getenv("LISTEN_PID") {
+        Some(pid) => {
+            let pid: Option<c_int> = FromStr::from_str(pid.as_slice());
+            if pid != Some(unsafe { ffi::getpid() }) {
+                return ~[];
+            }
+        },
+        None => (),
+    }
+    os::unsetenv("LISTEN_FDS");
+    os::unsetenv("LISTEN_PID");
+    let mut fds = ~[];
+    for i in range(0, count) {
+        fds.push(LISTEN_FDS_START + i as c_int);
+    }
+    fds
+}
+
+/// Make a stream socket in the address family, raising `io_error` if it can't be.
+fn new_socket(family: c_int) -> Option<c_int> {
+    let fd = unsafe { ffi::socket(family, ffi::SOCK_STREAM, 0) };
+    if fd < 0 {
+        io_error::cond.raise(last_error("Couldn't make a socket"));
+        return None;
+    }
+    platform::no_sigpipe(fd);
+    Some(fd)
+}
+
+/// Set an option on a socket, returning whether that worked.
+fn set_option(fd: c_int, level: c_int, name: c_int, value: c_int) -> bool {
+    unsafe {
+        ffi::setsockopt(fd, level, name, ptr::to_unsafe_ptr(&value) as *c_void,
+                        sys::size_of::<c_int>() as ffi::socklen_t) == 0
+    }
+}
+
+/// Set a socket's SO_RCVTIMEO or SO_SNDTIMEO to the milliseconds given, or to none, returning
+/// whether that worked.
+fn set_timeout(fd: c_int, name: c_int, ms: Option<u64>) -> bool {
+    // A timeout of zero is none at all.
+    let tv = platform::timeval(match ms {
+        Some(ms) => ms,
+        None => 0,
+    });
+    unsafe {
+        ffi::setsockopt(fd, platform::SOL_SOCKET, name, ptr::to_unsafe_ptr(&tv) as *c_void,
+                        sys::size_of::<platform::timeval>() as ffi::socklen_t) == 0
+    }
+}
+
+/// An error from the last call which failed, described as `desc`.
+fn last_error(desc: &'static str) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: desc,
+        detail: Some(os::last_os_error()),
+    }
+}
+
+/// The C form of an address, and its length.
+fn to_sockaddr(addr: SocketAddr) -> (ffi::sockaddr_storage, ffi::socklen_t) {
+    let mut storage = ffi::sockaddr_storage { data: [0, ..16] };
+    let port = [(addr.port >> 8) as u8, addr.port as u8];
+    let len = unsafe {
+        match addr.ip {
+            Ipv4Addr(a, b, c, d) => {
+                *(ptr::to_mut_unsafe_ptr(&mut storage) as *mut platform::sockaddr_in) =
+                    platform::sockaddr_in(port, [a, b, c, d]);
+                sys::size_of::<platform::sockaddr_in>()
+            },
+            Ipv6Addr(a, b, c, d, e, f, g, h) => {
+                let mut bytes = [0u8, ..16];
+                for (i, &n) in [a, b, c, d, e, f, g, h].iter().enumerate() {
+                    bytes[i * 2] = (n >> 8) as u8;
+                    bytes[i * 2 + 1] = n as u8;
+                }
+                *(ptr::to_mut_unsafe_ptr(&mut storage) as *mut platform::sockaddr_in6) =
+                    platform::sockaddr_in6(port, bytes);
+                sys::size_of::<platform::sockaddr_in6>()
+            },
+        }
+    };
+    (storage, len as ffi::socklen_t)
+}
+
+/// The C form of a Unix domain socket's path, and its length. Should the path be too long for one,
+/// the `io_error` condition is raised; if it is handled, `None` is returned.
+fn unix_sockaddr(path: &Path) -> Option<(ffi::sockaddr_storage, ffi::socklen_t)> {
+    let sun = match platform::sockaddr_un(path.to_str().as_bytes()) {
+        Some(sun) => sun,
+        None => {
+            io_error::cond.raise(IoError {
+                kind: OtherIoError,
+                desc: "The path is too long for a Unix domain socket",
+                detail: Some(path.to_str()),
+            });
+            return None;
+        },
+    };
+    let mut storage = ffi::sockaddr_storage { data: [0, ..16] };
+    unsafe {
+        *(ptr::to_mut_unsafe_ptr(&mut storage) as *mut platform::sockaddr_un) = sun;
+    }
+    Some((storage, sys::size_of::<platform::sockaddr_un>() as ffi::socklen_t))
+}
+
+/// Whether there is a Unix domain socket at the path. `FileStat` tells a socket from a file or a
+/// directory but not from the other special files, which are no more worth keeping in its place.
+fn is_socket(path: &Path) -> bool {
+    match io_error::cond.trap(|_| ()).inside(|| path.stat()) {
+        Some(stat) => !stat.is_file && !stat.is_dir,
+        None => false,
+    }
+}
+
+/// The address in its C form, if it is an IP address and port or the path of a Unix domain socket.
+fn to_address(storage: &ffi::sockaddr_storage) -> Option<Address> {
+    if platform::family(storage) != platform::AF_UNIX {
+        return match from_sockaddr(storage) {
+            Some(addr) => Some(TcpAddress(addr)),
+            None => None,
+        };
+    }
+    // The path, ending with a NUL, comes after the family (and on some systems, the length), two
+    // bytes in all.
+    let bytes = unsafe {
+        vec::raw::from_buf_raw(ptr::to_unsafe_ptr(storage) as *u8,
+                               sys::size_of::<ffi::sockaddr_storage>())
+    };
+    let path = bytes.slice_from(2);
+    let end = match path.iter().position(|&b| b == 0) {
+        Some(end) => end,
+        None => path.len(),
+    };
+    // One which is unnamed has no path.
+    if end == 0 || !str::is_utf8(path.slice_to(end)) {
+        return None;
+    }
+    Some(UnixAddress(Path::new(str::from_utf8(path.slice_to(end)))))
+}
+
+/// The address in its C form, if it is an IP address and port.
+fn from_sockaddr(storage: &ffi::sockaddr_storage) -> Option<SocketAddr> {
+    let family = platform::family(storage);
+    unsafe {
+        if family == platform::AF_INET {
+            let sin = &*(ptr::to_unsafe_ptr(storage) as *platform::sockaddr_in);
+            let a = sin.sin_addr;
+            Some(SocketAddr {
+                ip: Ipv4Addr(a[0], a[1], a[2], a[3]),
+                port: (sin.sin_port[0] as u16 << 8) | sin.sin_port[1] as u16,
+            })
+        } else if family == platform::AF_INET6 {
+            let sin6 = &*(ptr::to_unsafe_ptr(storage) as *platform::sockaddr_in6);
+            let mut n = [0u16, ..8];
+            for i in range(0u, 8) {
+                n[i] = (sin6.sin6_addr[i * 2] as u16 << 8) | sin6.sin6_addr[i * 2 + 1] as u16;
+            }
+            Some(SocketAddr {
+                ip: Ipv6Addr(n[0], n[1], n[2], n[3], n[4], n[5], n[6], n[7]),
+                port: (sin6.sin6_port[0] as u16 << 8) | sin6.sin6_port[1] as u16,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::io_error;
+    use std::rt::io::net::ip::{SocketAddr, Ipv4Addr, Ipv6Addr};
+    use super::{to_sockaddr, from_sockaddr, unix_sockaddr, to_address, TcpAddress, UnixAddress};
+    use super::platform;
+
+    #[test]
+    fn test_sockaddr_round_trip() {
+        let v4 = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8080 };
+        let (storage, _) = to_sockaddr(v4);
+        assert_eq!(from_sockaddr(&storage), Some(v4));
+
+        let v6 = SocketAddr { ip: Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0xff00, 1), port: 443 };
+        let (storage, _) = to_sockaddr(v6);
+        assert_eq!(from_sockaddr(&storage), Some(v6));
+        assert_eq!(to_address(&storage), Some(TcpAddress(v6)));
+    }
+
+    #[test]
+    fn test_unix_sockaddr() {
+        let (storage, _) = unix_sockaddr(&Path::new("/tmp/http.sock")).unwrap();
+        // It is no IP address
+        assert_eq!(from_sockaddr(&storage), None);
+        assert_eq!(to_address(&storage), Some(UnixAddress(Path::new("/tmp/http.sock"))));
+
+        let mut raised = false;
+        let long = do io_error::cond.trap(|_| raised = true).inside {
+            unix_sockaddr(&Path::new(format!("/tmp/{}", "x".repeat(200))))
+        };
+        assert!(long.is_none());
+        assert!(raised);
+    }
+
+    #[test]
+    fn test_timeval() {
+        let tv = platform::timeval(1500);
+        assert_eq!((tv.tv_sec as u64, tv.tv_usec as u64), (1, 500_000));
+        let tv = platform::timeval(0);
+        assert_eq!((tv.tv_sec as u64, tv.tv_usec as u64), (0, 0));
+    }
+
+    #[test]
+    fn test_address_to_str() {
+        let addr = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8080 };
+        assert_eq!(TcpAddress(addr).to_str(), ~"127.0.0.1:8080");
+        assert_eq!(UnixAddress(Path::new("/tmp/http.sock")).to_str(), ~"unix:/tmp/http.sock");
+    }
+}
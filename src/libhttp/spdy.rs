@@ -0,0 +1,801 @@
+/*!
+
+The framing layer of SPDY/3: the frames which carry the streams of a SPDY session over a single
+connection, and the compression of their headers.
+
+```rust
+let mut framer = Framer::new();
+loop {
+    match framer.read_frame(&mut stream) {
+        Ok(SynStreamFrame(syn)) => {
+            let request = request_from_syn_stream(&syn, None, true);
+            ...
+            framer.write_frame(&mut stream, &SynReplyFrame(syn.stream_id, 0, headers));
+            framer.write_frame(&mut stream, &DataFrame(syn.stream_id, FLAG_FIN, body));
+        },
+        Ok(PingFrame(id)) => framer.write_frame(&mut stream, &PingFrame(id)),
+        Ok(_) => (),
+        Err(_) => break,
+    }
+}
+```
+
+Each end compresses the name/value header blocks of the frames it sends with one zlib stream for
+the whole session, primed with a dictionary of common header text (SPDY/3, section 2.6.10.1), so
+the frames of a session must be read and written in order with the same `Framer`.
+
+SPDY is chosen in the TLS handshake, by Next Protocol Negotiation: advertise it with
+`SslContext.set_npn_protocols([PROTOCOL_ID, "http/1.1"])`, and see whether the client chose it with
+`SslStream.negotiated_protocol()`. `request_from_syn_stream` makes a `Request` of a stream's
+headers, for the same handler to go by as for HTTP, and `reply_headers` the headers of a SYN_REPLY
+from a response's status and headers.
+
+TODO: `Server` doesn't yet serve SPDY itself. A handler's `ResponseWriter` writes HTTP/1.1 straight
+to the connection, so it would need to be able to write its status and headers as a SYN_REPLY and
+its body as data frames instead, interleaved with those of other streams; until then, a server
+must drive the frames itself, and shouldn't advertise SPDY unless it does.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::libc::{c_int, c_uint};
+use std::rt::io::{Reader, Writer};
+use std::rt::io::net::ip::SocketAddr;
+use std::str;
+use std::sys;
+use std::vec;
+use extra::url::Url;
+use buffer::BufferedStream;
+use common::{read_exactly, read_u32, read_stream_id, push_u32};
+use headers;
+use headers::{HeaderEnum, EndOfHeaders, MalformedHeaderValue};
+use headers::host::Host;
+use memstream::MemReaderFakeStream;
+use method::Method;
+use server::Request;
+use server::request::RequestBuffer;
+use status::Status;
+use zlib;
+
+/// The name of the protocol in Next Protocol Negotiation.
+pub static PROTOCOL_ID: &'static str = "spdy/3";
+
+/// The version of the protocol, as it is in control frames.
+pub static SPDY_VERSION: u16 = 3;
+
+/// The flag of a data frame, SYN_STREAM, SYN_REPLY or HEADERS which is the last of its stream
+/// from the sender.
+pub static FLAG_FIN: u8 = 0x01;
+
+/// The flag of a SYN_STREAM whose stream the receiver is not to send anything on.
+pub static FLAG_UNIDIRECTIONAL: u8 = 0x02;
+
+/// The flag of a SETTINGS frame asking that settings persisted earlier be forgotten.
+pub static FLAG_SETTINGS_CLEAR_SETTINGS: u8 = 0x01;
+
+/// The status codes of RST_STREAM (SPDY/3, section 2.6.2).
+pub static PROTOCOL_ERROR: u32 = 1;
+pub static INVALID_STREAM: u32 = 2;
+pub static REFUSED_STREAM: u32 = 3;
+pub static UNSUPPORTED_VERSION: u32 = 4;
+pub static CANCEL: u32 = 5;
+pub static INTERNAL_ERROR: u32 = 6;
+pub static FLOW_CONTROL_ERROR: u32 = 7;
+pub static STREAM_IN_USE: u32 = 8;
+pub static STREAM_ALREADY_CLOSED: u32 = 9;
+pub static FRAME_TOO_LARGE: u32 = 11;
+
+/// The window of a stream's data which may be sent before a WINDOW_UPDATE is needed, at first.
+pub static DEFAULT_WINDOW_SIZE: u32 = 0x10000;
+
+/// The longest a frame's payload may be, as its length only has 24 bits.
+pub static MAX_FRAME_LEN: uint = 0xFFFFFF;
+
+/// The longest a header block may be once decompressed, by default.
+static DEFAULT_MAX_HEADER_BLOCK_LEN: uint = 0x10000;
+
+/// The names and values of a header block, in order. Names are in lower case; several values of
+/// the same header are joined by NUL bytes into one.
+pub type HeaderBlock = ~[(~str, ~str)];
+
+/// The opening of a stream.
+#[deriving(Clone, Eq)]
+pub struct SynStream {
+    /// The stream's ID, odd when opened by the client, even when by the server.
+    stream_id: u32,
+    /// The stream this one is associated with, for one opened by the server to push a resource;
+    /// 0 for none.
+    associated_stream_id: u32,
+    /// The stream's priority, from 0, the highest, to 7.
+    priority: u8,
+    /// The credential slot of the client certificate for the stream; 0 for none.
+    slot: u8,
+    /// `FLAG_FIN` and `FLAG_UNIDIRECTIONAL`.
+    flags: u8,
+    headers: HeaderBlock,
+}
+
+/// An entry of a SETTINGS frame.
+#[deriving(Clone, Eq)]
+pub struct Setting {
+    /// Whether the receiver is asked to persist the setting (0x1), or it is one so persisted (0x2).
+    flags: u8,
+    id: u32,
+    value: u32,
+}
+
+/// A frame, with its header block, if any, decompressed.
+#[deriving(Clone, Eq)]
+pub enum Frame {
+    /// Data of a stream: its ID, its flags (`FLAG_FIN`) and the data.
+    DataFrame(u32, u8, ~[u8]),
+    SynStreamFrame(SynStream),
+    /// The reply to a stream: its ID, its flags (`FLAG_FIN`) and the headers of the response.
+    SynReplyFrame(u32, u8, HeaderBlock),
+    /// The ending of a stream, because of trouble: its ID and the status code for it.
+    RstStreamFrame(u32, u32),
+    /// Settings of the session: the flags (`FLAG_SETTINGS_CLEAR_SETTINGS`) and the settings.
+    SettingsFrame(u8, ~[Setting]),
+    /// A ping, to be answered with a ping with the same ID.
+    PingFrame(u32),
+    /// The end of the session: the last stream which was acted upon, and the status code (0 if
+    /// all is well, `PROTOCOL_ERROR` or `INTERNAL_ERROR`).
+    GoAwayFrame(u32, u32),
+    /// More headers of a stream: its ID, its flags (`FLAG_FIN`) and the headers.
+    HeadersFrame(u32, u8, HeaderBlock),
+    /// More of a stream's window: its ID, and how many bytes it has grown by.
+    WindowUpdateFrame(u32, u32),
+}
+
+/// Why a frame couldn't be read.
+#[deriving(Clone, Eq)]
+pub enum FrameError {
+    /// The stream ended, before the frame or in the middle of it.
+    EndOfStream,
+    /// The frame isn't valid: too short for its type, or with a header block which won't
+    /// decompress, say.
+    InvalidFrame,
+    /// The frame's payload, or its header block once decompressed, is longer than allowed.
+    FrameTooLong,
+    /// The control frame is of a version other than 3.
+    UnsupportedVersion,
+}
+
+/// The header names with which the dictionary begins, each preceded by its length.
+static DICTIONARY_NAMES: &'static [&'static str] = &[
+    "options", "head", "post", "put", "delete", "trace", "accept", "accept-charset",
+    "accept-encoding", "accept-language", "accept-ranges", "age", "allow", "authorization",
+    "cache-control", "connection", "content-base", "content-encoding", "content-language",
+    "content-length", "content-location", "content-md5", "content-range", "content-type", "date",
+    "etag", "expect", "expires", "from", "host", "if-match", "if-modified-since", "if-none-match",
+    "if-range", "if-unmodified-since", "last-modified", "location", "max-forwards", "pragma",
+    "proxy-authenticate", "proxy-authorization", "range", "referer", "retry-after", "server", "te",
+    "trailer", "transfer-encoding", "upgrade", "user-agent", "vary", "via", "warning",
+    "www-authenticate", "method", "get", "status", "200 OK", "version", "HTTP/1.1", "url",
+    "public", "set-cookie", "keep-alive", "origin",
+];
+
+/// The text with which the dictionary ends.
+static DICTIONARY_TEXT: &'static str = "100101201202205206300302303304305306307402405406407408409\
+410411412413414415416417502504505203 Non-Authoritative Information204 No Content301 Moved \
+Permanently400 Bad Request401 Unauthorized403 Forbidden404 Not Found500 Internal Server Error501 \
+Not Implemented503 Service UnavailableJan Feb Mar Apr May Jun Jul Aug Sept Oct Nov Dec 00:00:00 \
+Mon, Tue, Wed, Thu, Fri, Sat, Sun, GMTchunked,text/html,image/png,image/jpg,image/gif,\
+application/xml,application/xhtml+xml,text/plain,text/javascript,publicprivatemax-age=gzip,\
+deflate,sdchcharset=utf-8charset=iso-8859-1,utf-,*,enq=0.";
+
+/// The dictionary with which the zlib streams of header blocks are primed (SPDY/3, section
+/// 2.6.10.1), 1423 bytes long.
+pub fn dictionary() -> ~[u8] {
+    let mut dictionary = vec::with_capacity(1423);
+    for name in DICTIONARY_NAMES.iter() {
+        push_u32(&mut dictionary, name.len() as u32);
+        dictionary.push_all(name.as_bytes());
+    }
+    dictionary.push_all(DICTIONARY_TEXT.as_bytes());
+    dictionary
+}
+
+/// The size of the chunks in which zlib's output is collected.
+static ZLIB_BUF_SIZE: uint = 0x1000;
+
+/// The compressing end of a session's header blocks.
+pub struct HeaderCompressor {
+    priv stream: ~zlib::z_stream,
+}
+
+impl HeaderCompressor {
+    pub fn new() -> HeaderCompressor {
+        let mut stream = zlib::new_z_stream();
+        let dictionary = dictionary();
+        unsafe {
+            let size = sys::size_of::<zlib::z_stream>() as c_int;
+            if zlib::deflateInit_(&mut *stream, zlib::Z_DEFAULT_COMPRESSION, zlib::zlibVersion(),
+                                  size) != zlib::Z_OK
+                    || zlib::deflateSetDictionary(&mut *stream, vec::raw::to_ptr(dictionary),
+                                                  dictionary.len() as c_uint) != zlib::Z_OK {
+                fail!("zlib failed to start compressing");
+            }
+        }
+        HeaderCompressor { stream: stream }
+    }
+
+    /// Compress a header block, flushing it so that it can be decompressed on its own.
+    pub fn compress(&mut self, data: &[u8]) -> ~[u8] {
+        let mut out = ~[];
+        let mut buf = vec::from_elem(ZLIB_BUF_SIZE, 0u8);
+        self.stream.next_in = vec::raw::to_ptr(data);
+        self.stream.avail_in = data.len() as c_uint;
+        loop {
+            self.stream.next_out = vec::raw::to_mut_ptr(buf);
+            self.stream.avail_out = buf.len() as c_uint;
+            let ret = unsafe { zlib::deflate(&mut *self.stream, zlib::Z_SYNC_FLUSH) };
+            if ret != zlib::Z_OK && ret != zlib::Z_BUF_ERROR {
+                fail!("zlib failed to compress a header block");
+            }
+            let len = buf.len() - self.stream.avail_out as uint;
+            out.push_all(buf.slice_to(len));
+            if self.stream.avail_out != 0 {
+                return out;
+            }
+        }
+    }
+}
+
+impl Drop for HeaderCompressor {
+    fn drop(&mut self) {
+        unsafe { zlib::deflateEnd(&mut *self.stream); }
+    }
+}
+
+/// The decompressing end of a session's header blocks.
+pub struct HeaderDecompressor {
+    priv stream: ~zlib::z_stream,
+}
+
+impl HeaderDecompressor {
+    pub fn new() -> HeaderDecompressor {
+        let mut stream = zlib::new_z_stream();
+        unsafe {
+            let size = sys::size_of::<zlib::z_stream>() as c_int;
+            if zlib::inflateInit_(&mut *stream, zlib::zlibVersion(), size) != zlib::Z_OK {
+                fail!("zlib failed to start decompressing");
+            }
+        }
+        HeaderDecompressor { stream: stream }
+    }
+
+    /// Decompress a header block, or `Err` if it isn't valid, or would be longer than `max_len`.
+    pub fn decompress(&mut self, data: &[u8], max_len: uint) -> Result<~[u8], FrameError> {
+        let mut out = ~[];
+        let mut buf = vec::from_elem(ZLIB_BUF_SIZE, 0u8);
+        self.stream.next_in = vec::raw::to_ptr(data);
+        self.stream.avail_in = data.len() as c_uint;
+        loop {
+            self.stream.next_out = vec::raw::to_mut_ptr(buf);
+            self.stream.avail_out = buf.len() as c_uint;
+            let ret = unsafe { zlib::inflate(&mut *self.stream, zlib::Z_SYNC_FLUSH) };
+            if ret == zlib::Z_NEED_DICT {
+                // Only asked for at the start of the first block.
+                let dictionary = dictionary();
+                let ret = unsafe {
+                    zlib::inflateSetDictionary(&mut *self.stream, vec::raw::to_ptr(dictionary),
+                                               dictionary.len() as c_uint)
+                };
+                if ret != zlib::Z_OK {
+                    return Err(InvalidFrame);
+                }
+                continue;
+            }
+            if ret != zlib::Z_OK && ret != zlib::Z_BUF_ERROR {
+                return Err(InvalidFrame);
+            }
+            let len = buf.len() - self.stream.avail_out as uint;
+            if out.len() + len > max_len {
+                return Err(FrameTooLong);
+            }
+            out.push_all(buf.slice_to(len));
+            if self.stream.avail_out != 0 {
+                return if self.stream.avail_in == 0 { Ok(out) } else { Err(InvalidFrame) };
+            }
+        }
+    }
+}
+
+impl Drop for HeaderDecompressor {
+    fn drop(&mut self) {
+        unsafe { zlib::inflateEnd(&mut *self.stream); }
+    }
+}
+
+fn push_u24(out: &mut ~[u8], n: uint) {
+    out.push((n >> 16) as u8);
+    out.push((n >> 8) as u8);
+    out.push(n as u8);
+}
+
+/// Encode a header block, uncompressed (SPDY/3, section 2.6.10).
+pub fn encode_header_block(headers: &[(~str, ~str)]) -> ~[u8] {
+    let mut out = ~[];
+    push_u32(&mut out, headers.len() as u32);
+    for &(ref name, ref value) in headers.iter() {
+        push_u32(&mut out, name.len() as u32);
+        out.push_all(name.as_bytes());
+        push_u32(&mut out, value.len() as u32);
+        out.push_all(value.as_bytes());
+    }
+    out
+}
+
+/// Decode a header block, once decompressed; `None` if it is cut short or has anything left over,
+/// an empty name, or a name or value which isn't UTF-8.
+pub fn decode_header_block(data: &[u8]) -> Option<HeaderBlock> {
+    fn read_string(data: &[u8], pos: &mut uint) -> Option<~str> {
+        if data.len() - *pos < 4 {
+            return None;
+        }
+        let len = read_u32(data.slice_from(*pos)) as uint;
+        *pos += 4;
+        if data.len() - *pos < len {
+            return None;
+        }
+        let bytes = data.slice(*pos, *pos + len);
+        *pos += len;
+        if str::is_utf8(bytes) { Some(str::from_utf8(bytes)) } else { None }
+    }
+
+    if data.len() < 4 {
+        return None;
+    }
+    let count = read_u32(data) as uint;
+    let mut pos = 4;
+    let mut headers = ~[];
+    for _ in range(0, count) {
+        let name = match read_string(data, &mut pos) {
+            Some(name) if !name.is_empty() => name,
+            _ => return None,
+        };
+        match read_string(data, &mut pos) {
+            Some(value) => headers.push((name, value)),
+            None => return None,
+        }
+    }
+    if pos == data.len() { Some(headers) } else { None }
+}
+
+/// The reading and writing of the frames of one session, with the header compression it keeps.
+pub struct Framer {
+    priv compressor: HeaderCompressor,
+    priv decompressor: HeaderDecompressor,
+    /// The longest a frame's payload may be when read; `MAX_FRAME_LEN` by default.
+    max_frame_len: uint,
+    /// The longest a header block may be when read, once decompressed; 64KB by default.
+    max_header_block_len: uint,
+}
+
+impl Framer {
+    pub fn new() -> Framer {
+        Framer {
+            compressor: HeaderCompressor::new(),
+            decompressor: HeaderDecompressor::new(),
+            max_frame_len: MAX_FRAME_LEN,
+            max_header_block_len: DEFAULT_MAX_HEADER_BLOCK_LEN,
+        }
+    }
+
+    /// Read a frame. Control frames of types which aren't known (such as CREDENTIAL) are skipped,
+    /// as the protocol asks.
+    pub fn read_frame<R: Reader>(&mut self, reader: &mut R) -> Result<Frame, FrameError> {
+        loop {
+            let head = match read_exactly(reader, 8) {
+                Some(head) => head,
+                None => return Err(EndOfStream),
+            };
+            let flags = head[4];
+            let len = (head[5] as uint << 16) | (head[6] as uint << 8) | head[7] as uint;
+            if len > self.max_frame_len {
+                return Err(FrameTooLong);
+            }
+            let payload = match read_exactly(reader, len) {
+                Some(payload) => payload,
+                None => return Err(EndOfStream),
+            };
+            if head[0] & 0x80 == 0 {
+                return Ok(DataFrame(read_stream_id(head), flags, payload));
+            }
+            let version = ((head[0] as u16 & 0x7F) << 8) | head[1] as u16;
+            if version != SPDY_VERSION {
+                return Err(UnsupportedVersion);
+            }
+            let frame_type = (head[2] as u16 << 8) | head[3] as u16;
+            let min_len = match frame_type {
+                1 => 10,
+                3 | 7 | 9 => 8,
+                2 | 4 | 6 | 8 => 4,
+                _ => 0,
+            };
+            if len < min_len {
+                return Err(InvalidFrame);
+            }
+            return match frame_type {
+                1 => match self.read_headers(payload.slice_from(10)) {
+                    Ok(headers) => Ok(SynStreamFrame(SynStream {
+                        stream_id: read_stream_id(payload),
+                        associated_stream_id: read_stream_id(payload.slice_from(4)),
+                        priority: payload[8] >> 5,
+                        slot: payload[9],
+                        flags: flags,
+                        headers: headers,
+                    })),
+                    Err(e) => Err(e),
+                },
+                2 => match self.read_headers(payload.slice_from(4)) {
+                    Ok(headers) => Ok(SynReplyFrame(read_stream_id(payload), flags, headers)),
+                    Err(e) => Err(e),
+                },
+                3 => Ok(RstStreamFrame(read_stream_id(payload), read_u32(payload.slice_from(4)))),
+                4 => {
+                    let count = read_u32(payload) as uint;
+                    if len != 4 + count * 8 {
+                        return Err(InvalidFrame);
+                    }
+                    let settings = do vec::from_fn(count) |i| {
+                        let entry = payload.slice_from(4 + i * 8);
+                        Setting {
+                            flags: entry[0],
+                            id: read_u32(entry) & 0xFFFFFF,
+                            value: read_u32(entry.slice_from(4)),
+                        }
+                    };
+                    Ok(SettingsFrame(flags, settings))
+                },
+                6 => Ok(PingFrame(read_u32(payload))),
+                7 => Ok(GoAwayFrame(read_stream_id(payload), read_u32(payload.slice_from(4)))),
+                8 => match self.read_headers(payload.slice_from(4)) {
+                    Ok(headers) => Ok(HeadersFrame(read_stream_id(payload), flags, headers)),
+                    Err(e) => Err(e),
+                },
+                9 => Ok(WindowUpdateFrame(read_stream_id(payload),
+                                          read_u32(payload.slice_from(4)) & 0x7FFFFFFF)),
+                _ => {
+                    debug!("skipping a SPDY control frame of unknown type {}", frame_type);
+                    continue;
+                },
+            };
+        }
+    }
+
+    fn read_headers(&mut self, compressed: &[u8]) -> Result<HeaderBlock, FrameError> {
+        match self.decompressor.decompress(compressed, self.max_header_block_len) {
+            Ok(data) => match decode_header_block(data) {
+                Some(headers) => Ok(headers),
+                None => Err(InvalidFrame),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a frame. This fails if its payload would be longer than `MAX_FRAME_LEN`.
+    pub fn write_frame<W: Writer>(&mut self, writer: &mut W, frame: &Frame) {
+        let (frame_type, flags, payload) = match *frame {
+            DataFrame(stream_id, flags, ref data) => {
+                if data.len() > MAX_FRAME_LEN {
+                    fail!("write_frame() called with a data frame too long to send");
+                }
+                let mut head = ~[];
+                push_u32(&mut head, stream_id & 0x7FFFFFFF);
+                head.push(flags);
+                push_u24(&mut head, data.len());
+                writer.write(head);
+                writer.write(data.as_slice());
+                return;
+            },
+            SynStreamFrame(ref syn) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, syn.stream_id & 0x7FFFFFFF);
+                push_u32(&mut payload, syn.associated_stream_id & 0x7FFFFFFF);
+                payload.push(syn.priority << 5);
+                payload.push(syn.slot);
+                payload.push_all(self.compressor.compress(encode_header_block(syn.headers)));
+                (1, syn.flags, payload)
+            },
+            SynReplyFrame(stream_id, flags, ref headers) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, stream_id & 0x7FFFFFFF);
+                payload.push_all(self.compressor.compress(encode_header_block(headers.as_slice())));
+                (2, flags, payload)
+            },
+            RstStreamFrame(stream_id, status) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, stream_id & 0x7FFFFFFF);
+                push_u32(&mut payload, status);
+                (3, 0, payload)
+            },
+            SettingsFrame(flags, ref settings) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, settings.len() as u32);
+                for setting in settings.iter() {
+                    payload.push(setting.flags);
+                    push_u24(&mut payload, setting.id as uint);
+                    push_u32(&mut payload, setting.value);
+                }
+                (4, flags, payload)
+            },
+            PingFrame(id) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, id);
+                (6, 0, payload)
+            },
+            GoAwayFrame(last_stream_id, status) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, last_stream_id & 0x7FFFFFFF);
+                push_u32(&mut payload, status);
+                (7, 0, payload)
+            },
+            HeadersFrame(stream_id, flags, ref headers) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, stream_id & 0x7FFFFFFF);
+                payload.push_all(self.compressor.compress(encode_header_block(headers.as_slice())));
+                (8, flags, payload)
+            },
+            WindowUpdateFrame(stream_id, delta) => {
+                let mut payload = ~[];
+                push_u32(&mut payload, stream_id & 0x7FFFFFFF);
+                push_u32(&mut payload, delta & 0x7FFFFFFF);
+                (9, 0, payload)
+            },
+        };
+        if payload.len() > MAX_FRAME_LEN {
+            fail!("write_frame() called with a control frame too long to send");
+        }
+        let mut head = ~[0x80 | (SPDY_VERSION >> 8) as u8, SPDY_VERSION as u8,
+                         (frame_type >> 8) as u8, frame_type as u8, flags];
+        push_u24(&mut head, payload.len());
+        writer.write(head);
+        writer.write(payload);
+    }
+}
+
+/// The value of the header named, in a header block.
+fn find_header<'a>(headers: &'a [(~str, ~str)], name: &str) -> Option<&'a str> {
+    match headers.iter().find(|&&(ref n, _)| n.as_slice() == name) {
+        Some(&(_, ref value)) => Some(value.as_slice()),
+        None => None,
+    }
+}
+
+/**
+ * Make a `Request` of the headers of a stream opened by a client, from the peer given, over TLS or
+ * not: its method, URL and version are those of the `:method`, `:scheme`, `:host`, `:path` and
+ * `:version` headers, and its other headers those of HTTP, with `:host` as Host. Its body is
+ * empty, to be filled from the stream's data frames.
+ *
+ * `None` is returned if any of those headers is missing or invalid, or any header's value has a CR
+ * or LF in it; the stream is then to be reset with `PROTOCOL_ERROR`.
+ */
+pub fn request_from_syn_stream(syn: &SynStream, remote_addr: Option<SocketAddr>, secure: bool)
+        -> Option<~Request> {
+    let block = syn.headers.as_slice();
+    let (method, scheme, host, path, version) =
+            match (find_header(block, ":method"), find_header(block, ":scheme"),
+                   find_header(block, ":host"), find_header(block, ":path"),
+                   find_header(block, ":version")) {
+        (Some(m), Some(s), Some(h), Some(p), Some(v)) => (m, s, h, p, v),
+        _ => return None,
+    };
+    let version = match version {
+        "HTTP/1.1" => (1, 1),
+        "HTTP/1.0" => (1, 0),
+        _ => return None,
+    };
+    if !method.is_ascii() || method.is_empty() {
+        return None;
+    }
+    let method = match Method::from_str_or_new(method) {
+        Some(method) => method,
+        None => return None,
+    };
+    let request_uri = match FromStr::from_str(path) {
+        Some(request_uri) => request_uri,
+        None => return None,
+    };
+    let url: Option<Url> = FromStr::from_str(format!("{}://{}{}", scheme, host, path));
+    if url.is_none() {
+        return None;
+    }
+
+    // The headers are read as they would be from an HTTP request, to be interpreted alike.
+    let mut text = format!("Host: {}\r\n", host);
+    for &(ref name, ref value) in block.iter() {
+        if value.contains_char('\r') || value.contains_char('\n') {
+            return None;
+        }
+        if !name.starts_with(":") && name.as_slice() != "host" {
+            text.push_str(format!("{}: {}\r\n", *name, value.replace("\x00", ", ")));
+        }
+    }
+    text.push_str("\r\n");
+    let mut stream = BufferedStream::new(MemReaderFakeStream::new(text.into_bytes()), false);
+    let mut buffer = RequestBuffer::new(&mut stream);
+    let mut collection = ~headers::request::HeaderCollection::new();
+    loop {
+        match buffer.read_header::<headers::request::Header>() {
+            Ok(header) => collection.push(header),
+            Err(EndOfHeaders) => break,
+            Err(MalformedHeaderValue) => (),
+            Err(_) => return None,
+        }
+    }
+    let host: Option<Host> = headers::header_value_from_str(host);
+    if host.is_none() {
+        return None;
+    }
+    collection.host = host;
+
+    Some(~Request {
+        remote_addr: remote_addr,
+        headers: collection,
+        raw_headers: ~[],
+        body: ~[],
+        trailer: ~[],
+        method: method,
+        request_uri: request_uri,
+        url: url,
+        close_connection: false,
+        version: version,
+        trusted_proxy_hops: 0,
+    })
+}
+
+/// The headers of a SYN_REPLY for a response of the status and headers given: `:status` and
+/// `:version`, then the headers in lower case. Those which only concern the connection, which
+/// SPDY mustn't have (Connection, Keep-Alive, Proxy-Connection and Transfer-Encoding), are left
+/// out.
+pub fn reply_headers(status: &Status, response_headers: &headers::response::HeaderCollection)
+        -> HeaderBlock {
+    let mut block = ~[(~":status", status.to_str()), (~":version", ~"HTTP/1.1")];
+    for header in response_headers.iter() {
+        let name = header.header_name().to_ascii_lower();
+        match name.as_slice() {
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" => (),
+            _ => block.push((name, header.header_value())),
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::Decorator;
+    use std::rt::io::mem::{MemReader, MemWriter};
+    use method::Get;
+    use status;
+    use headers;
+    use super::{Framer, Frame, SynStream, Setting, DataFrame, SynStreamFrame, SynReplyFrame,
+                RstStreamFrame, SettingsFrame, PingFrame, GoAwayFrame, WindowUpdateFrame,
+                EndOfStream, InvalidFrame, FrameTooLong, UnsupportedVersion, FLAG_FIN, CANCEL,
+                dictionary, encode_header_block, decode_header_block, request_from_syn_stream,
+                reply_headers};
+
+    fn syn_stream(headers: ~[(~str, ~str)]) -> SynStream {
+        SynStream {
+            stream_id: 1,
+            associated_stream_id: 0,
+            priority: 3,
+            slot: 0,
+            flags: FLAG_FIN,
+            headers: headers,
+        }
+    }
+
+    fn request_headers() -> ~[(~str, ~str)] {
+        ~[(~":method", ~"GET"), (~":path", ~"/search?q=spdy"), (~":version", ~"HTTP/1.1"),
+          (~":host", ~"example.com:8443"), (~":scheme", ~"https"), (~"accept", ~"text/html"),
+          (~"x-thing", ~"a\x00b")]
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dictionary = dictionary();
+        assert_eq!(dictionary.len(), 1423);
+        assert_eq!(dictionary.slice_to(11).to_owned(), ~[0, 0, 0, 7, 'o' as u8, 'p' as u8,
+                                                         't' as u8, 'i' as u8, 'o' as u8,
+                                                         'n' as u8, 's' as u8]);
+    }
+
+    #[test]
+    fn test_header_blocks() {
+        let headers = request_headers();
+        let encoded = encode_header_block(headers);
+        assert_eq!(encoded.slice_to(8).to_owned(), ~[0, 0, 0, 7, 0, 0, 0, 7]);
+        assert_eq!(decode_header_block(encoded), Some(headers));
+        assert_eq!(decode_header_block([0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+        assert_eq!(decode_header_block([0, 0, 0, 1, 0, 0, 0, 1, 'a' as u8]), None);
+        assert_eq!(decode_header_block([0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_frames() {
+        let frames = ~[
+            SynStreamFrame(syn_stream(request_headers())),
+            SynReplyFrame(1, 0, ~[(~":status", ~"200 OK"), (~":version", ~"HTTP/1.1")]),
+            DataFrame(1, FLAG_FIN, ~[1, 2, 3]),
+            // The same headers again, compressed in the light of the first
+            SynStreamFrame(syn_stream(request_headers())),
+            RstStreamFrame(3, CANCEL),
+            SettingsFrame(0, ~[Setting { flags: 1, id: 7, value: 0x10000 }]),
+            PingFrame(2),
+            GoAwayFrame(3, 0),
+            WindowUpdateFrame(1, 0x8000),
+        ];
+        let mut writer = MemWriter::new();
+        let mut framer = Framer::new();
+        for frame in frames.iter() {
+            framer.write_frame(&mut writer, frame);
+        }
+        let bytes = writer.inner();
+        let mut reader = MemReader::new(bytes);
+        let mut framer = Framer::new();
+        for frame in frames.iter() {
+            assert_eq!(framer.read_frame(&mut reader), Ok(frame.clone()));
+        }
+        assert_eq!(framer.read_frame(&mut reader), Err(EndOfStream));
+
+        // A control frame: the version, type, flags and length, then the payload
+        let mut writer = MemWriter::new();
+        Framer::new().write_frame(&mut writer, &PingFrame(2));
+        assert_eq!(writer.inner(), ~[0x80, 3, 0, 6, 0, 0, 0, 4, 0, 0, 0, 2]);
+        // A data frame: the stream ID, flags and length, then the data
+        let mut writer = MemWriter::new();
+        Framer::new().write_frame(&mut writer, &DataFrame(5, FLAG_FIN, ~[9]));
+        assert_eq!(writer.inner(), ~[0, 0, 0, 5, 1, 0, 0, 1, 9]);
+    }
+
+    fn read(bytes: ~[u8]) -> Result<Frame, super::FrameError> {
+        Framer::new().read_frame(&mut MemReader::new(bytes))
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        assert_eq!(read(~[0x80, 3, 0, 6, 0, 0, 0, 4, 0, 0]), Err(EndOfStream));
+        assert_eq!(read(~[0x80, 2, 0, 6, 0, 0, 0, 4, 0, 0, 0, 2]), Err(UnsupportedVersion));
+        // A ping too short for its ID
+        assert_eq!(read(~[0x80, 3, 0, 6, 0, 0, 0, 2, 0, 0]), Err(InvalidFrame));
+        // A SYN_REPLY whose headers aren't compressed
+        assert_eq!(read(~[0x80, 3, 0, 2, 0, 0, 0, 8, 0, 0, 0, 1, 0, 0, 0, 0]), Err(InvalidFrame));
+        // Unknown control frames are skipped
+        assert_eq!(read(~[0x80, 3, 0, 10, 0, 0, 0, 1, 0, 0x80, 3, 0, 6, 0, 0, 0, 4, 0, 0, 0, 2]),
+                   Ok(PingFrame(2)));
+        let mut framer = Framer::new();
+        framer.max_frame_len = 2;
+        assert_eq!(framer.read_frame(&mut MemReader::new(~[0, 0, 0, 1, 0, 0, 0, 3, 1, 2, 3])),
+                   Err(FrameTooLong));
+    }
+
+    #[test]
+    fn test_request_from_syn_stream() {
+        let request = request_from_syn_stream(&syn_stream(request_headers()), None, true).unwrap();
+        assert_eq!(request.method, Get);
+        assert_eq!(request.version, (1, 1));
+        assert_eq!(request.url.get_ref().to_str(), ~"https://example.com:8443/search?q=spdy");
+        assert_eq!(request.headers.host.get_ref().to_str(), ~"example.com:8443");
+        assert_eq!(request.headers.extensions.find(&~"X-Thing"), Some(&~"a, b"));
+
+        let mut headers = request_headers();
+        headers.retain(|&(ref name, _)| name.as_slice() != ":path");
+        assert!(request_from_syn_stream(&syn_stream(headers), None, true).is_none());
+        let mut headers = request_headers();
+        headers.push((~"x-evil", ~"a\r\nHost: evil.example"));
+        assert!(request_from_syn_stream(&syn_stream(headers), None, true).is_none());
+    }
+
+    #[test]
+    fn test_reply_headers() {
+        let mut collection = headers::response::HeaderCollection::new();
+        collection.content_length = Some(3);
+        collection.connection = Some(~[headers::connection::Close]);
+        let block = reply_headers(&status::NotFound, &collection);
+        assert_eq!(block, ~[(~":status", ~"404 Not Found"), (~":version", ~"HTTP/1.1"),
+                            (~"content-length", ~"3")]);
+    }
+}
@@ -0,0 +1,727 @@
+/*!
+
+TLS/SSL support, using OpenSSL.
+
+`SslStream` speaks TLS over any stream (ordinarily a `TcpStream`). It is a plain `Reader` and
+`Writer`, so it can be wrapped in a `BufferedStream` like any other stream.
+
+OpenSSL is given a pair of memory BIOs rather than a file descriptor: it reads the bytes received
+from the peer out of one and writes the bytes to send into the other, while the wrapped stream is
+driven through `std::rt::io` as usual.
+
+```rust
+use http::ssl::{SslContext, SslStream};
+
+let ctx = SslContext::new();
+let stream = TcpStream::connect(addr).unwrap();
+let mut stream = SslStream::connect(&ctx, stream, Some("example.com")).unwrap();
+stream.write(bytes!("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"));
+```
+
+Certificates are verified against the system's trusted authorities by default, and, when a host is
+given to `connect`, the server's certificate must be for that host: one of its subjectAltName
+entries must match it, or, if it has no DNS names there, its Common Name (RFC 2818, section 3.1;
+RFC 6125, section 6.4).
+
+*/
+
+use std::libc::{c_int, c_uint, c_void};
+use std::ptr;
+use std::str;
+use std::vec;
+use std::ascii::StrAsciiExt;
+use std::c_str::ToCStr;
+use std::rt::io::{Reader, Writer, Stream};
+use std::rt::io::{io_error, IoError, OtherIoError};
+use std::rt::io::net::ip::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The foreign interface to OpenSSL, limited to what is needed here.
+#[allow(non_camel_case_types, non_uppercase_statics)]
+pub mod ffi {
+    use std::libc::{c_int, c_uint, c_long, c_void, c_char};
+
+    pub type SSL_CTX = c_void;
+    pub type SSL = c_void;
+    pub type SSL_METHOD = c_void;
+    pub type BIO = c_void;
+    pub type BIO_METHOD = c_void;
+    pub type X509 = c_void;
+    pub type X509_NAME = c_void;
+    pub type X509_NAME_ENTRY = c_void;
+    pub type ASN1_STRING = c_void;
+    pub type GENERAL_NAMES = c_void;
+
+    pub struct GENERAL_NAME {
+        type_: c_int,
+        d: *ASN1_STRING,
+    }
+
+    pub static SSL_ERROR_NONE: c_int = 0;
+    pub static SSL_ERROR_SSL: c_int = 1;
+    pub static SSL_ERROR_WANT_READ: c_int = 2;
+    pub static SSL_ERROR_WANT_WRITE: c_int = 3;
+    pub static SSL_ERROR_SYSCALL: c_int = 5;
+    pub static SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+    pub static SSL_FILETYPE_PEM: c_int = 1;
+
+    pub static SSL_VERIFY_NONE: c_int = 0;
+    pub static SSL_VERIFY_PEER: c_int = 1;
+
+    pub static SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+    pub static TLSEXT_NAMETYPE_host_name: c_long = 0;
+
+    pub static SSL_TLSEXT_ERR_OK: c_int = 0;
+    pub static SSL_TLSEXT_ERR_NOACK: c_int = 3;
+
+    pub static OPENSSL_NPN_NEGOTIATED: c_int = 1;
+
+    pub static NID_commonName: c_int = 13;
+    pub static NID_subject_alt_name: c_int = 85;
+
+    pub static GEN_DNS: c_int = 2;
+    pub static GEN_IPADD: c_int = 7;
+
+    #[link_args = "-lssl -lcrypto"]
+    extern "C" {
+        pub fn SSL_library_init() -> c_int;
+        pub fn SSL_load_error_strings();
+
+        pub fn SSLv23_method() -> *SSL_METHOD;
+
+        pub fn SSL_CTX_new(method: *SSL_METHOD) -> *SSL_CTX;
+        pub fn SSL_CTX_free(ctx: *SSL_CTX);
+        pub fn SSL_CTX_set_verify(ctx: *SSL_CTX, mode: c_int, verify_callback: *c_void);
+        pub fn SSL_CTX_set_default_verify_paths(ctx: *SSL_CTX) -> c_int;
+        pub fn SSL_CTX_load_verify_locations(ctx: *SSL_CTX, CAfile: *c_char, CApath: *c_char)
+                                             -> c_int;
+        pub fn SSL_CTX_use_certificate_chain_file(ctx: *SSL_CTX, file: *c_char) -> c_int;
+        pub fn SSL_CTX_use_PrivateKey_file(ctx: *SSL_CTX, file: *c_char, type_: c_int) -> c_int;
+        pub fn SSL_CTX_check_private_key(ctx: *SSL_CTX) -> c_int;
+        pub fn SSL_CTX_set_next_protos_advertised_cb(ctx: *SSL_CTX,
+                cb: extern "C" fn(*SSL, *mut *u8, *mut c_uint, *c_void) -> c_int,
+                arg: *c_void);
+        pub fn SSL_CTX_set_alpn_select_cb(ctx: *SSL_CTX,
+                cb: extern "C" fn(*SSL, *mut *u8, *mut u8, *u8, c_uint, *c_void) -> c_int,
+                arg: *c_void);
+        pub fn SSL_select_next_proto(out: *mut *u8, outlen: *mut u8, server: *u8,
+                                     server_len: c_uint, client: *u8, client_len: c_uint)
+                                     -> c_int;
+
+        pub fn SSL_new(ctx: *SSL_CTX) -> *SSL;
+        pub fn SSL_free(ssl: *SSL);
+        pub fn SSL_set_bio(ssl: *SSL, rbio: *BIO, wbio: *BIO);
+        pub fn SSL_set_connect_state(ssl: *SSL);
+        pub fn SSL_set_accept_state(ssl: *SSL);
+        pub fn SSL_ctrl(ssl: *SSL, cmd: c_int, larg: c_long, parg: *c_void) -> c_long;
+        pub fn SSL_do_handshake(ssl: *SSL) -> c_int;
+        pub fn SSL_read(ssl: *SSL, buf: *mut c_void, num: c_int) -> c_int;
+        pub fn SSL_write(ssl: *SSL, buf: *c_void, num: c_int) -> c_int;
+        pub fn SSL_shutdown(ssl: *SSL) -> c_int;
+        pub fn SSL_get_error(ssl: *SSL, ret: c_int) -> c_int;
+        pub fn SSL_get0_next_proto_negotiated(ssl: *SSL, data: *mut *u8, len: *mut c_uint);
+        pub fn SSL_get0_alpn_selected(ssl: *SSL, data: *mut *u8, len: *mut c_uint);
+        pub fn SSL_get_peer_certificate(ssl: *SSL) -> *X509;
+
+        pub fn X509_free(x: *X509);
+        pub fn X509_get_ext_d2i(x: *X509, nid: c_int, crit: *mut c_int, idx: *mut c_int)
+                                -> *c_void;
+        pub fn X509_get_subject_name(x: *X509) -> *X509_NAME;
+        pub fn X509_NAME_get_index_by_NID(name: *X509_NAME, nid: c_int, lastpos: c_int) -> c_int;
+        pub fn X509_NAME_get_entry(name: *X509_NAME, loc: c_int) -> *X509_NAME_ENTRY;
+        pub fn X509_NAME_ENTRY_get_data(ne: *X509_NAME_ENTRY) -> *ASN1_STRING;
+        pub fn ASN1_STRING_data(x: *ASN1_STRING) -> *u8;
+        pub fn ASN1_STRING_length(x: *ASN1_STRING) -> c_int;
+        pub fn ASN1_STRING_to_UTF8(out: *mut *u8, in_: *ASN1_STRING) -> c_int;
+        pub fn GENERAL_NAMES_free(names: *GENERAL_NAMES);
+        pub fn sk_num(st: *c_void) -> c_int;
+        pub fn sk_value(st: *c_void, i: c_int) -> *c_void;
+        pub fn CRYPTO_free(p: *c_void);
+
+        pub fn BIO_s_mem() -> *BIO_METHOD;
+        pub fn BIO_new(type_: *BIO_METHOD) -> *BIO;
+        pub fn BIO_read(b: *BIO, buf: *mut c_void, len: c_int) -> c_int;
+        pub fn BIO_write(b: *BIO, buf: *c_void, len: c_int) -> c_int;
+    }
+}
+
+/// The size of the chunks in which encrypted data is moved between the BIOs and the stream.
+static BIO_BUF_SIZE: uint = 0x4000;
+
+fn ssl_error(desc: &'static str) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: desc,
+        detail: None,
+    }
+}
+
+/// Shared configuration for TLS connections: protocol versions, certificates and verification.
+///
+/// Contexts are not cheap to make (loading certificates and keys from disk), so make one and use
+/// it for many connections.
+pub struct SslContext {
+    priv ctx: *ffi::SSL_CTX,
+    /// The protocols advertised by `set_npn_protocols`, in the wire format, each preceded by its
+    /// length; boxed so that OpenSSL can keep a pointer to it while the context moves.
+    priv npn_protocols: ~~[u8],
+    /// The protocols chosen from by `set_alpn_protocols`, likewise.
+    priv alpn_protocols: ~~[u8],
+    /// Whether the peer's certificate is verified, and so, by `SslStream::connect`, its host.
+    priv verify: bool,
+}
+
+impl SslContext {
+    /// Create a context supporting all protocol versions from SSLv3 up, which verifies the peer's
+    /// certificate against the system's default trusted certificate authorities.
+    pub fn new() -> SslContext {
+        unsafe {
+            // These are safe to call more than once.
+            ffi::SSL_library_init();
+            ffi::SSL_load_error_strings();
+            let ctx = ffi::SSL_CTX_new(ffi::SSLv23_method());
+            if ctx.is_null() {
+                fail!("SSL_CTX_new failed");
+            }
+            ffi::SSL_CTX_set_default_verify_paths(ctx);
+            ffi::SSL_CTX_set_verify(ctx, ffi::SSL_VERIFY_PEER, ptr::null());
+            SslContext { ctx: ctx, npn_protocols: ~~[], alpn_protocols: ~~[], verify: true }
+        }
+    }
+
+    /// Enable or disable verification of the peer's certificate, and of the host it is for.
+    ///
+    /// Servers will normally want to disable this, as verification means requiring clients to
+    /// present certificates.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+        let mode = if verify { ffi::SSL_VERIFY_PEER } else { ffi::SSL_VERIFY_NONE };
+        unsafe { ffi::SSL_CTX_set_verify(self.ctx, mode, ptr::null()) }
+    }
+
+    /// Trust the certificate authorities in the specified PEM file.
+    pub fn set_ca_file(&mut self, file: &Path) -> bool {
+        do file.to_str().with_c_str |file| {
+            unsafe { ffi::SSL_CTX_load_verify_locations(self.ctx, file, ptr::null()) == 1 }
+        }
+    }
+
+    /// Use the certificate (and any intermediate certificates) in the specified PEM file.
+    pub fn set_certificate_chain_file(&mut self, file: &Path) -> bool {
+        do file.to_str().with_c_str |file| {
+            unsafe { ffi::SSL_CTX_use_certificate_chain_file(self.ctx, file) == 1 }
+        }
+    }
+
+    /// Use the private key in the specified PEM file, which must match the certificate.
+    pub fn set_private_key_file(&mut self, file: &Path) -> bool {
+        do file.to_str().with_c_str |file| {
+            unsafe {
+                ffi::SSL_CTX_use_PrivateKey_file(self.ctx, file, ffi::SSL_FILETYPE_PEM) == 1 &&
+                    ffi::SSL_CTX_check_private_key(self.ctx) == 1
+            }
+        }
+    }
+}
+
+impl SslContext {
+    /// Advertise the protocols given, most preferred first, to clients which ask by Next Protocol
+    /// Negotiation which protocols may be spoken over a connection, such as `"spdy/3"` and
+    /// `"http/1.1"`; `SslStream.negotiated_protocol` tells which one the client chose.
+    ///
+    /// This fails if a protocol's name is empty or longer than 255 bytes.
+    pub fn set_npn_protocols(&mut self, protocols: &[&str]) {
+        self.npn_protocols = ~protocol_list(protocols);
+        unsafe {
+            let arg: *~[u8] = &*self.npn_protocols;
+            ffi::SSL_CTX_set_next_protos_advertised_cb(self.ctx, advertise_npn_protocols,
+                                                       arg as *c_void);
+        }
+    }
+
+    /// Choose from the protocols given, most preferred first, the first which a client offers by
+    /// Application-Layer Protocol Negotiation (RFC 7301), such as `"h2"` before `"http/1.1"`;
+    /// `SslStream.negotiated_protocol` tells which one was chosen. If the client offers none of
+    /// them, the handshake goes on without choosing one.
+    ///
+    /// This fails if a protocol's name is empty or longer than 255 bytes.
+    pub fn set_alpn_protocols(&mut self, protocols: &[&str]) {
+        self.alpn_protocols = ~protocol_list(protocols);
+        unsafe {
+            let arg: *~[u8] = &*self.alpn_protocols;
+            ffi::SSL_CTX_set_alpn_select_cb(self.ctx, select_alpn_protocol, arg as *c_void);
+        }
+    }
+}
+
+/// The protocols given in the wire format of NPN and ALPN, each preceded by its length.
+fn protocol_list(protocols: &[&str]) -> ~[u8] {
+    let mut wire = ~[];
+    for protocol in protocols.iter() {
+        if protocol.len() == 0 || protocol.len() > 255 {
+            fail!("an invalid protocol name to negotiate, {}", *protocol);
+        }
+        wire.push(protocol.len() as u8);
+        wire.push_all(protocol.as_bytes());
+    }
+    wire
+}
+
+/// Give OpenSSL the protocols to advertise; `arg` is the `npn_protocols` of the context.
+extern "C" fn advertise_npn_protocols(_ssl: *ffi::SSL, out: *mut *u8, outlen: *mut c_uint,
+                                      arg: *c_void) -> c_int {
+    unsafe {
+        let protocols = &*(arg as *~[u8]);
+        *out = vec::raw::to_ptr(*protocols);
+        *outlen = protocols.len() as c_uint;
+    }
+    ffi::SSL_TLSEXT_ERR_OK
+}
+
+/// Choose for OpenSSL the first of the protocols of `arg` (the `alpn_protocols` of the context)
+/// which the client offers, if it offers any.
+extern "C" fn select_alpn_protocol(_ssl: *ffi::SSL, out: *mut *u8, outlen: *mut u8, client: *u8,
+                                   client_len: c_uint, arg: *c_void) -> c_int {
+    unsafe {
+        let protocols = &*(arg as *~[u8]);
+        let chosen = ffi::SSL_select_next_proto(out, outlen, vec::raw::to_ptr(*protocols),
+                                                protocols.len() as c_uint, client, client_len);
+        if chosen == ffi::OPENSSL_NPN_NEGOTIATED {
+            ffi::SSL_TLSEXT_ERR_OK
+        } else {
+            ffi::SSL_TLSEXT_ERR_NOACK
+        }
+    }
+}
+
+impl Drop for SslContext {
+    fn drop(&mut self) {
+        unsafe { ffi::SSL_CTX_free(self.ctx) }
+    }
+}
+
+/// A stream protected by TLS.
+pub struct SslStream<S> {
+    priv stream: S,
+    priv ssl: *ffi::SSL,
+    /// Data received from the peer, for OpenSSL to read; owned by `ssl`.
+    priv rbio: *ffi::BIO,
+    /// Data written by OpenSSL, to be sent to the peer; owned by `ssl`.
+    priv wbio: *ffi::BIO,
+    priv buf: ~[u8],
+    priv eof: bool,
+}
+
+impl<S: Stream> SslStream<S> {
+    fn new(ctx: &SslContext, stream: S) -> SslStream<S> {
+        unsafe {
+            let ssl = ffi::SSL_new(ctx.ctx);
+            if ssl.is_null() {
+                fail!("SSL_new failed");
+            }
+            let rbio = ffi::BIO_new(ffi::BIO_s_mem());
+            let wbio = ffi::BIO_new(ffi::BIO_s_mem());
+            ffi::SSL_set_bio(ssl, rbio, wbio);
+            SslStream {
+                stream: stream,
+                ssl: ssl,
+                rbio: rbio,
+                wbio: wbio,
+                buf: vec::from_elem(BIO_BUF_SIZE, 0u8),
+                eof: false,
+            }
+        }
+    }
+
+    /// Perform the client side of the TLS handshake over `stream`.
+    ///
+    /// `host` is sent to the server for Server Name Indication, which lets a server with several
+    /// certificates know which one to present; it should be the host name (or IP address) being
+    /// connected to. Unless the context doesn't verify certificates, the server's certificate must
+    /// then be for that host.
+    ///
+    /// Should the handshake fail, or the certificate be for another host, the `io_error` condition
+    /// is raised; if it is handled, `None` is returned.
+    pub fn connect(ctx: &SslContext, stream: S, host: Option<&str>) -> Option<SslStream<S>> {
+        let mut ssl = SslStream::new(ctx, stream);
+        unsafe { ffi::SSL_set_connect_state(ssl.ssl) };
+        match host {
+            Some(host) => do host.with_c_str |host| {
+                unsafe {
+                    ffi::SSL_ctrl(ssl.ssl, ffi::SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                                  ffi::TLSEXT_NAMETYPE_host_name, host as *c_void);
+                }
+            },
+            None => (),
+        }
+        if !ssl.handshake() {
+            return None;
+        }
+        match host {
+            Some(host) if ctx.verify && !ssl.certificate_matches(host) => {
+                io_error::cond.raise(IoError {
+                    kind: OtherIoError,
+                    desc: "TLS certificate is not for the host connected to",
+                    detail: Some(format!("expected a certificate for {}", host)),
+                });
+                None
+            },
+            _ => Some(ssl),
+        }
+    }
+
+    /// Perform the server side of the TLS handshake over `stream`. The context must have a
+    /// certificate and private key.
+    ///
+    /// Should the handshake fail, the `io_error` condition is raised; if it is handled, `None` is
+    /// returned.
+    pub fn accept(ctx: &SslContext, stream: S) -> Option<SslStream<S>> {
+        let mut ssl = SslStream::new(ctx, stream);
+        unsafe { ffi::SSL_set_accept_state(ssl.ssl) };
+        if ssl.handshake() { Some(ssl) } else { None }
+    }
+
+    /// The protocol chosen by Application-Layer Protocol Negotiation, from those of the context's
+    /// `set_alpn_protocols`, or else that which the client chose by Next Protocol Negotiation,
+    /// from those advertised by its `set_npn_protocols`; `None` if neither was negotiated.
+    pub fn negotiated_protocol(&self) -> Option<~str> {
+        let mut data: *u8 = ptr::null();
+        let mut len: c_uint = 0;
+        unsafe {
+            ffi::SSL_get0_alpn_selected(self.ssl, &mut data, &mut len);
+            if data.is_null() || len == 0 {
+                ffi::SSL_get0_next_proto_negotiated(self.ssl, &mut data, &mut len);
+            }
+            if data.is_null() || len == 0 {
+                return None;
+            }
+            let protocol = vec::raw::from_buf_raw(data, len as uint);
+            if str::is_utf8(protocol) { Some(str::from_utf8(protocol)) } else { None }
+        }
+    }
+
+    /// Whether the peer's certificate is for the host given (see `certificate_matches_host`).
+    fn certificate_matches(&self, host: &str) -> bool {
+        unsafe {
+            let cert = ffi::SSL_get_peer_certificate(self.ssl);
+            if cert.is_null() {
+                return false;
+            }
+            let (dns_names, ip_addrs) = subject_alt_names(cert);
+            let common_name = common_name(cert);
+            ffi::X509_free(cert);
+            certificate_matches_host(dns_names, ip_addrs, common_name, host)
+        }
+    }
+
+    /// Get a reference to the wrapped stream.
+    pub fn get_ref<'a>(&'a self) -> &'a S {
+        &self.stream
+    }
+
+    /// Get a mutable reference to the wrapped stream. Reading from or writing to it directly will
+    /// corrupt the TLS session.
+    pub fn get_mut_ref<'a>(&'a mut self) -> &'a mut S {
+        &mut self.stream
+    }
+
+    fn handshake(&mut self) -> bool {
+        loop {
+            let ret = unsafe { ffi::SSL_do_handshake(self.ssl) };
+            if ret == 1 {
+                self.flush_wbio();
+                return true;
+            }
+            if !self.service(ret) {
+                io_error::cond.raise(ssl_error("TLS handshake failed"));
+                return false;
+            }
+        }
+    }
+
+    /// Do what OpenSSL wants after an operation which returned `ret` did not succeed: send what it
+    /// has written and/or receive more data for it to read. Returns `false` if the operation
+    /// cannot be retried, because of an error or the end of the stream.
+    fn service(&mut self, ret: c_int) -> bool {
+        let err = unsafe { ffi::SSL_get_error(self.ssl, ret) };
+        if err == ffi::SSL_ERROR_WANT_READ {
+            // It may need to send something (e.g. in the handshake) before it can read more.
+            self.flush_wbio();
+            self.fill_rbio()
+        } else if err == ffi::SSL_ERROR_WANT_WRITE {
+            self.flush_wbio();
+            true
+        } else {
+            if err == ffi::SSL_ERROR_ZERO_RETURN {
+                // The peer closed the TLS session cleanly.
+                self.eof = true;
+            }
+            false
+        }
+    }
+
+    /// Send everything OpenSSL has written to the peer.
+    fn flush_wbio(&mut self) {
+        loop {
+            let len = unsafe {
+                ffi::BIO_read(self.wbio, vec::raw::to_mut_ptr(self.buf) as *mut c_void,
+                              self.buf.len() as c_int)
+            };
+            if len <= 0 {
+                return;
+            }
+            self.stream.write(self.buf.slice_to(len as uint));
+        }
+    }
+
+    /// Receive data from the peer for OpenSSL to read. Returns `false` at the end of the stream.
+    fn fill_rbio(&mut self) -> bool {
+        match self.stream.read(self.buf) {
+            Some(len) => {
+                unsafe {
+                    ffi::BIO_write(self.rbio, vec::raw::to_ptr(self.buf) as *c_void, len as c_int);
+                }
+                true
+            },
+            None => {
+                self.eof = true;
+                false
+            },
+        }
+    }
+}
+
+/// The DNS names and IP addresses (as their bytes, in network order) in the subjectAltName of a
+/// certificate. A DNS name which isn't ASCII, or has a NUL in it, is left out.
+unsafe fn subject_alt_names(cert: *ffi::X509) -> (~[~str], ~[~[u8]]) {
+    let mut dns_names = ~[];
+    let mut ip_addrs = ~[];
+    let names = ffi::X509_get_ext_d2i(cert, ffi::NID_subject_alt_name, ptr::mut_null(),
+                                      ptr::mut_null());
+    if names.is_null() {
+        return (dns_names, ip_addrs);
+    }
+    for i in range(0, ffi::sk_num(names)) {
+        let name = &*(ffi::sk_value(names, i) as *ffi::GENERAL_NAME);
+        let data = asn1_string_bytes(name.d);
+        if name.type_ == ffi::GEN_DNS {
+            if data.iter().all(|&b| b > 0 && b < 0x80) {
+                dns_names.push(str::from_utf8(data));
+            }
+        } else if name.type_ == ffi::GEN_IPADD {
+            ip_addrs.push(data);
+        }
+    }
+    ffi::GENERAL_NAMES_free(names);
+    (dns_names, ip_addrs)
+}
+
+/// The last Common Name of the subject of a certificate, if it has one which is valid UTF-8 with
+/// no NUL in it.
+unsafe fn common_name(cert: *ffi::X509) -> Option<~str> {
+    let subject = ffi::X509_get_subject_name(cert);
+    let mut last = -1;
+    loop {
+        let i = ffi::X509_NAME_get_index_by_NID(subject, ffi::NID_commonName, last);
+        if i < 0 {
+            break;
+        }
+        last = i;
+    }
+    if last < 0 {
+        return None;
+    }
+    let data = ffi::X509_NAME_ENTRY_get_data(ffi::X509_NAME_get_entry(subject, last));
+    let mut utf8: *u8 = ptr::null();
+    let len = ffi::ASN1_STRING_to_UTF8(&mut utf8, data);
+    if len < 0 {
+        return None;
+    }
+    let name = vec::raw::from_buf_raw(utf8, len as uint);
+    ffi::CRYPTO_free(utf8 as *c_void);
+    if str::is_utf8(name) && !name.contains(&0) { Some(str::from_utf8(name)) } else { None }
+}
+
+unsafe fn asn1_string_bytes(s: *ffi::ASN1_STRING) -> ~[u8] {
+    let len = ffi::ASN1_STRING_length(s);
+    if len <= 0 {
+        return ~[];
+    }
+    vec::raw::from_buf_raw(ffi::ASN1_STRING_data(s), len as uint)
+}
+
+/// Whether a certificate with the subjectAltName entries and Common Name given is for the host.
+///
+/// An IP address (which may be in brackets, as in a URL) must be one of the IP addresses. A host
+/// name must match one of the DNS names (see `name_matches_host`), or, only if there are none of
+/// those, the Common Name.
+fn certificate_matches_host(dns_names: &[~str], ip_addrs: &[~[u8]], common_name: Option<~str>,
+                            host: &str) -> bool {
+    let unbracketed = if host.starts_with("[") && host.ends_with("]") {
+        host.slice(1, host.len() - 1)
+    } else {
+        host
+    };
+    let ip: Option<IpAddr> = FromStr::from_str(unbracketed);
+    match ip {
+        Some(ip) => {
+            let bytes = ip_addr_bytes(ip);
+            return ip_addrs.iter().any(|addr| *addr == bytes);
+        },
+        None => (),
+    }
+    if !dns_names.is_empty() {
+        return dns_names.iter().any(|name| name_matches_host(name.as_slice(), host));
+    }
+    match common_name {
+        Some(name) => name_matches_host(name.as_slice(), host),
+        None => false,
+    }
+}
+
+/// The bytes of an IP address, in network order, as in a certificate's subjectAltName.
+fn ip_addr_bytes(ip: IpAddr) -> ~[u8] {
+    match ip {
+        Ipv4Addr(a, b, c, d) => ~[a, b, c, d],
+        Ipv6Addr(a, b, c, d, e, f, g, h) => {
+            let mut bytes = ~[];
+            for &piece in [a, b, c, d, e, f, g, h].iter() {
+                bytes.push((piece >> 8) as u8);
+                bytes.push(piece as u8);
+            }
+            bytes
+        },
+    }
+}
+
+/// Whether a DNS name from a certificate matches the host, ignoring case and any final dot. The
+/// name may have a wildcard, `*`, for the whole of its first label, which matches any one label
+/// (but not none, nor several), so long as at least two labels follow it: `*.example.com` matches
+/// `www.example.com`, but neither `example.com` nor `a.b.example.com`, and `*.com` matches nothing.
+fn name_matches_host(name: &str, host: &str) -> bool {
+    let name = name.trim_right_chars(&'.').to_ascii_lower();
+    let host = host.trim_right_chars(&'.').to_ascii_lower();
+    if name.is_empty() || host.is_empty() {
+        return false;
+    }
+    if !name.starts_with("*.") {
+        return !name.contains_char('*') && name == host;
+    }
+    let suffix = name.slice_from(1);
+    if suffix.contains_char('*') || suffix.slice_from(1).find('.').is_none() {
+        return false;
+    }
+    match host.find('.') {
+        Some(i) if i > 0 => host.slice_from(i) == suffix,
+        _ => false,
+    }
+}
+
+impl<S: Stream> Reader for SslStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        if buf.len() == 0 {
+            return Some(0);
+        }
+        loop {
+            let ret = unsafe {
+                ffi::SSL_read(self.ssl, vec::raw::to_mut_ptr(buf) as *mut c_void,
+                              buf.len() as c_int)
+            };
+            if ret > 0 {
+                return Some(ret as uint);
+            }
+            if !self.service(ret) {
+                if !self.eof {
+                    io_error::cond.raise(ssl_error("TLS read failed"));
+                }
+                return None;
+            }
+        }
+    }
+
+    fn eof(&mut self) -> bool {
+        self.eof
+    }
+}
+
+impl<S: Stream> Writer for SslStream<S> {
+    fn write(&mut self, buf: &[u8]) {
+        let mut written = 0;
+        while written < buf.len() {
+            let rest = buf.slice_from(written);
+            let ret = unsafe {
+                ffi::SSL_write(self.ssl, vec::raw::to_ptr(rest) as *c_void, rest.len() as c_int)
+            };
+            if ret > 0 {
+                written += ret as uint;
+            } else if !self.service(ret) {
+                io_error::cond.raise(ssl_error("TLS write failed"));
+                return;
+            }
+        }
+        self.flush_wbio();
+    }
+
+    /// Everything written is passed on to the wrapped stream immediately, so this does not need to
+    /// do anything. (In particular, it doesn't flush the wrapped stream, since `TcpStream.flush()`
+    /// fails.)
+    fn flush(&mut self) {
+        self.flush_wbio();
+    }
+}
+
+#[unsafe_destructor]
+impl<S> Drop for SslStream<S> {
+    fn drop(&mut self) {
+        unsafe {
+            // Only queues the close_notify alert; there's no getting it to the peer from here.
+            ffi::SSL_shutdown(self.ssl);
+            // This frees the BIOs too.
+            ffi::SSL_free(self.ssl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{name_matches_host, certificate_matches_host};
+
+    #[test]
+    fn test_name_matches_host() {
+        assert!(name_matches_host("example.com", "example.com"));
+        assert!(name_matches_host("Example.COM.", "example.com"));
+        assert!(!name_matches_host("example.com", "www.example.com"));
+        assert!(!name_matches_host("example.com", "example.org"));
+
+        assert!(name_matches_host("*.example.com", "www.example.com"));
+        assert!(name_matches_host("*.Example.com", "WWW.example.com."));
+        assert!(!name_matches_host("*.example.com", "example.com"));
+        assert!(!name_matches_host("*.example.com", ".example.com"));
+        assert!(!name_matches_host("*.example.com", "a.b.example.com"));
+        assert!(!name_matches_host("*.example.com", "www.example.org"));
+        assert!(!name_matches_host("*.com", "example.com"));
+        assert!(!name_matches_host("*", "example"));
+        assert!(!name_matches_host("www.*.example.com", "www.a.example.com"));
+        assert!(!name_matches_host("w*.example.com", "www.example.com"));
+        assert!(!name_matches_host("", ""));
+    }
+
+    #[test]
+    fn test_certificate_matches_host() {
+        let dns_names = ~[~"example.com", ~"*.example.com"];
+        assert!(certificate_matches_host(dns_names, [], None, "www.example.com"));
+        assert!(certificate_matches_host(dns_names, [], None, "example.com"));
+        assert!(!certificate_matches_host(dns_names, [], None, "example.org"));
+        // The Common Name is only used when there are no DNS names
+        assert!(!certificate_matches_host(dns_names, [], Some(~"example.org"), "example.org"));
+        assert!(certificate_matches_host([], [], Some(~"example.org"), "example.org"));
+        assert!(!certificate_matches_host([], [], None, "example.org"));
+
+        let ip_addrs = ~[~[192, 0, 2, 1], ~[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]];
+        assert!(certificate_matches_host([], ip_addrs, None, "192.0.2.1"));
+        assert!(certificate_matches_host([], ip_addrs, None, "[::1]"));
+        assert!(!certificate_matches_host([], ip_addrs, None, "192.0.2.2"));
+        // An IP address isn't matched against names, even one which looks like it
+        assert!(!certificate_matches_host([~"192.0.2.1"], [], Some(~"192.0.2.1"), "192.0.2.1"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Transfer-codings besides `chunked` (RFC 7230, section 4), which a server may apply to the body
+//! of a response where the client's TE header accepts them (see
+//! `ResponseWriter.transfer_code_if_accepted`). Unlike a content-coding, a transfer-coding is a
+//! property of the message and not of the resource: it is undone by the recipient, and a cache
+//! keeps the body as it was before it.
+//!
+//! The codings to choose from are those of a `TransferCodings` registry, which has `gzip` and
+//! `deflate` (see `compression`) by default; others can be plugged in by implementing
+//! `TransferCoder`:
+//!
+//! ~~~ .{rust}
+//! struct Reverse;
+//!
+//! impl TransferCoder for Reverse {
+//!     fn name(&self) -> ~str { ~"x-reverse" }
+//!     fn encode(&self, body: &[u8]) -> ~[u8] { body.rev_iter().map(|&b| b).collect() }
+//! }
+//!
+//! config.transfer_codings.register(~Reverse as ~TransferCoder:Send+Freeze);
+//! ~~~
+
+use std::ascii::StrAsciiExt;
+use extra::arc::Arc;
+use compression;
+use compression::{Coding, Gzip, Deflate};
+use headers::te::{TCoding, negotiate_transfer_coding};
+
+/// A transfer-coding which can be applied to a whole body.
+pub trait TransferCoder {
+    /// The name of the coding, as it goes in the TE and Transfer-Encoding headers.
+    fn name(&self) -> ~str;
+
+    /// Encode the body in the coding.
+    fn encode(&self, body: &[u8]) -> ~[u8];
+}
+
+/// A transfer-coder, as it is shared between the tasks serving connections.
+pub type SharedTransferCoder = Arc<~TransferCoder:Send+Freeze>;
+
+/// A `TransferCoder` for one of the codings of `compression`, which are transfer-codings as well
+/// as content-codings.
+pub struct CompressionCoder(Coding);
+
+impl TransferCoder for CompressionCoder {
+    fn name(&self) -> ~str {
+        (**self).name().to_owned()
+    }
+
+    fn encode(&self, body: &[u8]) -> ~[u8] {
+        compression::compress(**self, body)
+    }
+}
+
+/// The transfer-codings a server can apply, in the order in which they are preferred where the
+/// client accepts several equally.
+#[deriving(Clone)]
+pub struct TransferCodings {
+    priv coders: ~[SharedTransferCoder],
+}
+
+impl TransferCodings {
+    /// No transfer-codings at all.
+    pub fn new() -> TransferCodings {
+        TransferCodings { coders: ~[] }
+    }
+
+    /// `gzip` and then `deflate`.
+    pub fn with_defaults() -> TransferCodings {
+        let mut codings = TransferCodings::new();
+        codings.register(~CompressionCoder(Gzip) as ~TransferCoder:Send+Freeze);
+        codings.register(~CompressionCoder(Deflate) as ~TransferCoder:Send+Freeze);
+        codings
+    }
+
+    /// Add a transfer-coding, after those there are already, or in place of one of the same name.
+    /// `chunked` is always applied last, by the server itself, and can't be registered.
+    pub fn register(&mut self, coder: ~TransferCoder:Send+Freeze) {
+        let name = coder.name();
+        if name.eq_ignore_ascii_case("chunked") || name.eq_ignore_ascii_case("trailers") {
+            fail!("{} can't be registered as a transfer-coding", name);
+        }
+        let coder = Arc::new(coder);
+        match self.coders.iter().position(|c| c.get().name().eq_ignore_ascii_case(name)) {
+            Some(i) => self.coders[i] = coder,
+            None => self.coders.push(coder),
+        }
+    }
+
+    /// The transfer-coding of the name given, if it has been registered.
+    pub fn find(&self, name: &str) -> Option<SharedTransferCoder> {
+        match self.coders.iter().find(|c| c.get().name().eq_ignore_ascii_case(name)) {
+            Some(coder) => Some(coder.clone()),
+            None => None,
+        }
+    }
+
+    /// The names of the transfer-codings, in order.
+    pub fn names(&self) -> ~[~str] {
+        self.coders.iter().map(|c| c.get().name()).collect()
+    }
+
+    /// The transfer-coding which the client accepts best, according to its TE header, if it
+    /// accepts any (see `headers::te::negotiate_transfer_coding`).
+    pub fn negotiate(&self, te: &[TCoding]) -> Option<SharedTransferCoder> {
+        let names = self.names();
+        let names: ~[&str] = names.iter().map(|n| n.as_slice()).collect();
+        match negotiate_transfer_coding(te, names) {
+            Some(name) => self.find(name),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use headers::te::TCoding;
+    use super::{TransferCoder, TransferCodings};
+
+    struct Reverse;
+
+    impl TransferCoder for Reverse {
+        fn name(&self) -> ~str {
+            ~"x-reverse"
+        }
+
+        fn encode(&self, body: &[u8]) -> ~[u8] {
+            body.rev_iter().map(|&b| b).collect()
+        }
+    }
+
+    fn te(codings: &[(&str, Option<f64>)]) -> ~[TCoding] {
+        codings.iter().map(|&(coding, quality)| {
+            TCoding { coding: coding.to_owned(), quality: quality }
+        }).collect()
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut codings = TransferCodings::with_defaults();
+        assert_eq!(codings.names(), ~[~"gzip", ~"deflate"]);
+        codings.register(~Reverse as ~TransferCoder:Send+Freeze);
+        assert_eq!(codings.names(), ~[~"gzip", ~"deflate", ~"x-reverse"]);
+        let coder = codings.find("X-Reverse").unwrap();
+        assert_eq!(coder.get().encode([1, 2, 3]), ~[3, 2, 1]);
+        assert!(codings.find("compress").is_none());
+
+        let chosen = codings.negotiate(te([("x-reverse", None), ("gzip", Some(0.5))]));
+        assert_eq!(chosen.unwrap().get().name(), ~"x-reverse");
+        let chosen = codings.negotiate(te([("deflate", None), ("gzip", None)]));
+        assert_eq!(chosen.unwrap().get().name(), ~"gzip");
+        assert!(codings.negotiate(te([("trailers", None)])).is_none());
+        assert!(TransferCodings::new().negotiate(te([("gzip", None)])).is_none());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_register_chunked() {
+        struct Chunked;
+        impl TransferCoder for Chunked {
+            fn name(&self) -> ~str { ~"chunked" }
+            fn encode(&self, body: &[u8]) -> ~[u8] { body.to_owned() }
+        }
+        TransferCodings::new().register(~Chunked as ~TransferCoder:Send+Freeze);
+    }
+}
@@ -0,0 +1,508 @@
+/*!
+
+The WebSocket protocol (RFC 6455): the opening handshake, by which a GET request is upgraded to a
+WebSocket connection, and the framing of messages on it.
+
+```rust
+fn handle_request(&self, request: &Request, response: &mut ResponseWriter) {
+    // This answers a request which isn't a WebSocket handshake with an error itself.
+    let mut socket = match WebSocket::accept(request, response, None) {
+        Some(socket) => socket,
+        None => return,
+    };
+    loop {
+        match socket.recv_message() {
+            Some(TextMessage(text)) => socket.send_text(text),
+            Some(BinaryMessage(data)) => socket.send_binary(data),
+            None => break,
+        }
+    }
+}
+```
+
+`read_frame` and `write_frame` do the framing on any `Reader` and `Writer`, such as the
+`BufferedStream` of a connection; `WebSocket` deals in whole messages, answering pings and
+closing frames itself, as a server.
+
+*/
+
+use std::ascii::StrAsciiExt;
+use std::rt::io::{Reader, Writer};
+use std::str;
+use extra::base64::{ToBase64, FromBase64, STANDARD};
+use extra::crypto::digest::Digest;
+use extra::crypto::sha1::Sha1;
+use common::read_exactly;
+use headers::connection::Token;
+use method::Get;
+use network::BufNetworkStream;
+use server::{Request, ResponseWriter};
+use status;
+
+/// What is appended to the key of a handshake before it is hashed for the accept key.
+static ACCEPT_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only version of the protocol there is.
+static WEBSOCKET_VERSION: &'static str = "13";
+
+/// The longest a message received by a `WebSocket` may be, by default: 16MB.
+static DEFAULT_MAX_MESSAGE_LEN: uint = 0x1000000;
+
+/// The kind of a frame.
+#[deriving(Clone, Eq)]
+pub enum Opcode {
+    /// The continuation of a fragmented message.
+    ContinuationFrame,
+    /// A text message, or its first fragment.
+    TextFrame,
+    /// A binary message, or its first fragment.
+    BinaryFrame,
+    /// The closing of the connection; the payload is a status code and a reason, if anything.
+    CloseFrame,
+    /// A ping, to be answered with a pong with the same payload.
+    PingFrame,
+    /// The answer to a ping.
+    PongFrame,
+}
+
+impl Opcode {
+    /// The number of the opcode.
+    pub fn code(&self) -> u8 {
+        match *self {
+            ContinuationFrame => 0x0,
+            TextFrame => 0x1,
+            BinaryFrame => 0x2,
+            CloseFrame => 0x8,
+            PingFrame => 0x9,
+            PongFrame => 0xA,
+        }
+    }
+
+    /// The opcode numbered, if it is one which is defined.
+    pub fn from_code(code: u8) -> Option<Opcode> {
+        match code {
+            0x0 => Some(ContinuationFrame),
+            0x1 => Some(TextFrame),
+            0x2 => Some(BinaryFrame),
+            0x8 => Some(CloseFrame),
+            0x9 => Some(PingFrame),
+            0xA => Some(PongFrame),
+            _ => None,
+        }
+    }
+
+    /// Whether frames of this kind are control frames, which may come between the fragments of a
+    /// message, but may not themselves be fragmented.
+    pub fn is_control(&self) -> bool {
+        self.code() & 0x8 != 0
+    }
+}
+
+/// A frame, with its payload unmasked.
+#[deriving(Clone, Eq)]
+pub struct Frame {
+    /// Whether this is the last frame of its message.
+    fin: bool,
+    opcode: Opcode,
+    /// Whether the payload was masked, as it must be from a client, and mustn't be from a server.
+    masked: bool,
+    payload: ~[u8],
+}
+
+impl Frame {
+    /// An unfragmented frame of the kind and payload given.
+    pub fn new(opcode: Opcode, payload: ~[u8]) -> Frame {
+        Frame {
+            fin: true,
+            opcode: opcode,
+            masked: false,
+            payload: payload,
+        }
+    }
+}
+
+/// Why a frame couldn't be read.
+#[deriving(Clone, Eq)]
+pub enum FrameError {
+    /// The stream ended, before the frame or in the middle of it.
+    EndOfStream,
+    /// The frame isn't valid: it has reserved bits set or an unknown opcode, say.
+    InvalidFrame,
+    /// The frame's payload is longer than the most that was allowed.
+    FrameTooLong,
+}
+
+/// Mask (or unmask) data with a masking key.
+fn apply_mask(data: &mut [u8], mask: &[u8]) {
+    for (i, b) in data.mut_iter().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+/// Read a frame, of no more than `max_len` bytes of payload, if there is a limit.
+pub fn read_frame<R: Reader>(reader: &mut R, max_len: Option<u64>) -> Result<Frame, FrameError> {
+    let head = match read_exactly(reader, 2) {
+        Some(head) => head,
+        None => return Err(EndOfStream),
+    };
+    // No extensions have been negotiated, so the reserved bits must be clear.
+    if head[0] & 0x70 != 0 {
+        return Err(InvalidFrame);
+    }
+    let fin = head[0] & 0x80 != 0;
+    let opcode = match Opcode::from_code(head[0] & 0x0F) {
+        Some(opcode) => opcode,
+        None => return Err(InvalidFrame),
+    };
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7F {
+        126 => match read_exactly(reader, 2) {
+            Some(b) => ((b[0] as u64) << 8) | b[1] as u64,
+            None => return Err(EndOfStream),
+        },
+        127 => match read_exactly(reader, 8) {
+            Some(b) => b.iter().fold(0u64, |len, &b| (len << 8) | b as u64),
+            None => return Err(EndOfStream),
+        },
+        len => len as u64,
+    };
+    if opcode.is_control() && (len > 125 || !fin) {
+        return Err(InvalidFrame);
+    }
+    match max_len {
+        Some(max_len) if len > max_len => return Err(FrameTooLong),
+        _ => (),
+    }
+    let mask = if masked {
+        match read_exactly(reader, 4) {
+            Some(mask) => Some(mask),
+            None => return Err(EndOfStream),
+        }
+    } else {
+        None
+    };
+    let mut payload = match read_exactly(reader, len as uint) {
+        Some(payload) => payload,
+        None => return Err(EndOfStream),
+    };
+    match mask {
+        Some(ref mask) => apply_mask(payload, *mask),
+        None => (),
+    }
+    Ok(Frame {
+        fin: fin,
+        opcode: opcode,
+        masked: masked,
+        payload: payload,
+    })
+}
+
+/// Write a frame, masked with the key given, if any; a client must mask every frame it sends, with
+/// a new random key each time, and a server must mask none. (`frame.masked` is disregarded.)
+pub fn write_frame<W: Writer>(writer: &mut W, frame: &Frame, mask: Option<[u8, ..4]>) {
+    let mut head = ~[(if frame.fin { 0x80 } else { 0 }) | frame.opcode.code()];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let len = frame.payload.len() as u64;
+    if len < 126 {
+        head.push(mask_bit | len as u8);
+    } else if len <= 0xFFFF {
+        head.push(mask_bit | 126);
+        head.push((len >> 8) as u8);
+        head.push(len as u8);
+    } else {
+        head.push(mask_bit | 127);
+        for i in range(0u64, 8).invert() {
+            head.push((len >> (i * 8)) as u8);
+        }
+    }
+    match mask {
+        Some(mask) => {
+            head.push_all(mask);
+            let mut payload = frame.payload.clone();
+            apply_mask(payload, mask);
+            writer.write(head);
+            writer.write(payload);
+        },
+        None => {
+            writer.write(head);
+            writer.write(frame.payload);
+        },
+    }
+}
+
+/// The Sec-WebSocket-Accept value for the Sec-WebSocket-Key of a handshake.
+pub fn accept_key(key: &str) -> ~str {
+    let mut sha1 = Sha1::new();
+    sha1.input_str(key);
+    sha1.input_str(ACCEPT_GUID);
+    let mut hash = [0u8, ..20];
+    sha1.result(hash);
+    hash.to_base64(STANDARD)
+}
+
+/// A message received on a WebSocket.
+#[deriving(Clone, Eq)]
+pub enum Message {
+    TextMessage(~str),
+    BinaryMessage(~[u8]),
+}
+
+/// Status codes for closing a connection (RFC 6455, section 7.4.1).
+pub static CLOSE_NORMAL: u16 = 1000;
+pub static CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub static CLOSE_INVALID_DATA: u16 = 1007;
+pub static CLOSE_TOO_BIG: u16 = 1009;
+
+/// The server's end of a WebSocket connection.
+pub struct WebSocket<'self> {
+    priv stream: &'self mut BufNetworkStream,
+    priv max_message_len: uint,
+    priv closed: bool,
+}
+
+impl<'self> WebSocket<'self> {
+    /// Complete the opening handshake of a request to open a WebSocket, with the subprotocol
+    /// given, if one has been chosen from those requested (see `requested_protocols`). A request
+    /// which isn't a valid handshake is answered with 400 Bad Request, or for a version of the
+    /// protocol other than 13, 426 Upgrade Required, and `None` is returned.
+    pub fn accept<'a>(request: &Request, response: &'a mut ResponseWriter,
+                      protocol: Option<&str>) -> Option<WebSocket<'a>> {
+        if !is_handshake(request) {
+            response.send_error(status::BadRequest);
+            return None;
+        }
+        match request.headers.extensions.find(&~"Sec-Websocket-Version") {
+            Some(version) if version.trim() == WEBSOCKET_VERSION => (),
+            _ => {
+                response.headers.extensions.insert(~"Sec-WebSocket-Version",
+                                                   WEBSOCKET_VERSION.to_owned());
+                response.send_error(status::UpgradeRequired);
+                return None;
+            },
+        }
+        let key = request.headers.extensions.find(&~"Sec-Websocket-Key").unwrap().trim();
+        response.headers.extensions.insert(~"Sec-WebSocket-Accept", accept_key(key));
+        match protocol {
+            Some(protocol) => {
+                response.headers.extensions.insert(~"Sec-WebSocket-Protocol",
+                                                   protocol.to_owned());
+            },
+            None => (),
+        }
+        Some(WebSocket {
+            stream: response.switch_protocols("websocket"),
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            closed: false,
+        })
+    }
+
+    /// Set the longest a message received may be; a longer one closes the connection.
+    pub fn set_max_message_len(&mut self, len: uint) {
+        self.max_message_len = len;
+    }
+
+    /// Receive the next message, answering any pings in the meantime, or `None` once the
+    /// connection has been closed: by the client, in which case its closing frame is answered, or
+    /// because of something invalid it sent, in which case a closing frame saying what is sent.
+    pub fn recv_message(&mut self) -> Option<Message> {
+        let mut message: Option<(Opcode, ~[u8])> = None;
+        loop {
+            if self.closed {
+                return None;
+            }
+            let remaining = match message {
+                Some((_, ref data)) => self.max_message_len - data.len(),
+                None => self.max_message_len,
+            };
+            let Frame { fin, opcode, masked, payload } =
+                    match read_frame(&mut *self.stream, Some(remaining as u64)) {
+                Ok(frame) => frame,
+                Err(EndOfStream) => {
+                    self.closed = true;
+                    return None;
+                },
+                Err(InvalidFrame) => return self.fail_with(CLOSE_PROTOCOL_ERROR),
+                Err(FrameTooLong) => return self.fail_with(CLOSE_TOO_BIG),
+            };
+            if !masked {
+                return self.fail_with(CLOSE_PROTOCOL_ERROR);
+            }
+            match opcode {
+                PingFrame => {
+                    self.send_frame(&Frame::new(PongFrame, payload));
+                    continue;
+                },
+                PongFrame => continue,
+                CloseFrame => {
+                    // Echo the status code, as is usual, and be done.
+                    let code_len = if payload.len() >= 2 { 2 } else { 0 };
+                    self.send_frame(&Frame::new(CloseFrame, payload.slice_to(code_len).to_owned()));
+                    self.closed = true;
+                    return None;
+                },
+                ContinuationFrame => match message {
+                    Some((_, ref mut data)) => data.push_all_move(payload),
+                    None => return self.fail_with(CLOSE_PROTOCOL_ERROR),
+                },
+                TextFrame | BinaryFrame => {
+                    if message.is_some() {
+                        return self.fail_with(CLOSE_PROTOCOL_ERROR);
+                    }
+                    message = Some((opcode, payload));
+                },
+            }
+            if fin {
+                return match message {
+                    Some((TextFrame, data)) => if str::is_utf8(data) {
+                        Some(TextMessage(str::from_utf8(data)))
+                    } else {
+                        self.fail_with(CLOSE_INVALID_DATA)
+                    },
+                    Some((_, data)) => Some(BinaryMessage(data)),
+                    None => None,
+                };
+            }
+        }
+    }
+
+    /// Send a text message.
+    pub fn send_text(&mut self, text: &str) {
+        self.send_frame(&Frame::new(TextFrame, text.as_bytes().to_owned()));
+    }
+
+    /// Send a binary message.
+    pub fn send_binary(&mut self, data: &[u8]) {
+        self.send_frame(&Frame::new(BinaryFrame, data.to_owned()));
+    }
+
+    /// Send a ping; the client will answer with a pong, which `recv_message` passes over.
+    pub fn ping(&mut self, data: &[u8]) {
+        self.send_frame(&Frame::new(PingFrame, data.to_owned()));
+    }
+
+    /// Close the connection, with a status code (as `CLOSE_NORMAL`) and a reason. The client's
+    /// closing frame is not waited for.
+    pub fn close(&mut self, code: u16, reason: &str) {
+        if self.closed {
+            return;
+        }
+        let mut payload = ~[(code >> 8) as u8, code as u8];
+        payload.push_all(reason.as_bytes());
+        self.send_frame(&Frame::new(CloseFrame, payload));
+        self.closed = true;
+    }
+
+    fn fail_with<T>(&mut self, code: u16) -> Option<T> {
+        self.close(code, "");
+        None
+    }
+
+    fn send_frame(&mut self, frame: &Frame) {
+        write_frame(&mut *self.stream, frame, None);
+        self.stream.flush();
+    }
+}
+
+/// Whether a request is a WebSocket opening handshake (of any version): a GET, with Upgrade
+/// `websocket`, Connection `Upgrade` and a Sec-WebSocket-Key of 16 bytes.
+pub fn is_handshake(request: &Request) -> bool {
+    let upgrade = match request.headers.upgrade {
+        Some(ref upgrade) => upgrade.split_iter(',').any(|p| {
+            p.trim().eq_ignore_ascii_case("websocket")
+        }),
+        None => false,
+    };
+    let connection = match request.headers.connection {
+        Some(ref tokens) => tokens.iter().any(|t| *t == Token(~"Upgrade")),
+        None => false,
+    };
+    let key = match request.headers.extensions.find(&~"Sec-Websocket-Key") {
+        Some(key) => match key.trim().from_base64() {
+            Ok(bytes) => bytes.len() == 16,
+            Err(_) => false,
+        },
+        None => false,
+    };
+    request.method == Get && upgrade && connection && key
+}
+
+/// The subprotocols a handshake asks for, from the Sec-WebSocket-Protocol header, in the order of
+/// the client's preference.
+pub fn requested_protocols(request: &Request) -> ~[~str] {
+    match request.headers.extensions.find(&~"Sec-Websocket-Protocol") {
+        Some(protocols) => protocols.split_iter(',').map(|p| p.trim().to_owned())
+                                    .filter(|p| !p.is_empty()).collect(),
+        None => ~[],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rt::io::Decorator;
+    use std::rt::io::mem::{MemReader, MemWriter};
+    use std::vec;
+    use super::{accept_key, read_frame, write_frame, Frame, TextFrame, BinaryFrame, PingFrame,
+                EndOfStream, InvalidFrame, FrameTooLong};
+
+    #[test]
+    fn test_accept_key() {
+        // The example of RFC 6455, section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), ~"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    fn read(bytes: ~[u8]) -> Result<Frame, super::FrameError> {
+        read_frame(&mut MemReader::new(bytes), Some(0x10000))
+    }
+
+    fn write(frame: &Frame, mask: Option<[u8, ..4]>) -> ~[u8] {
+        let mut writer = MemWriter::new();
+        write_frame(&mut writer, frame, mask);
+        writer.inner()
+    }
+
+    #[test]
+    fn test_frames() {
+        // The examples of RFC 6455, section 5.7
+        let hello = Frame::new(TextFrame, "Hello".as_bytes().to_owned());
+        let unmasked = ~[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+        let masked = ~[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        assert_eq!(write(&hello, None), unmasked.clone());
+        assert_eq!(write(&hello, Some([0x37, 0xfa, 0x21, 0x3d])), masked.clone());
+        assert_eq!(read(unmasked), Ok(hello.clone()));
+        let mut masked_hello = hello.clone();
+        masked_hello.masked = true;
+        assert_eq!(read(masked), Ok(masked_hello));
+
+        let mut fragment = Frame::new(TextFrame, "Hel".as_bytes().to_owned());
+        fragment.fin = false;
+        assert_eq!(read(~[0x01, 0x03, 0x48, 0x65, 0x6c]), Ok(fragment));
+
+        let ping = Frame::new(PingFrame, "Hello".as_bytes().to_owned());
+        assert_eq!(write(&ping, None), ~[0x89, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+        // 256 bytes of binary, with its 16-bit length
+        let binary = Frame::new(BinaryFrame, vec::from_elem(256, 0u8));
+        let bytes = write(&binary, None);
+        assert_eq!(bytes.slice_to(4).to_owned(), ~[0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(read(bytes), Ok(binary));
+        // And 64KiB, with its 64-bit length, which is too long
+        let binary = Frame::new(BinaryFrame, vec::from_elem(0x10001, 0u8));
+        let bytes = write(&binary, None);
+        assert_eq!(bytes.slice_to(10).to_owned(), ~[0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 1]);
+        assert_eq!(read(bytes), Err(FrameTooLong));
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        assert_eq!(read(~[]), Err(EndOfStream));
+        assert_eq!(read(~[0x81, 0x05, 0x48]), Err(EndOfStream));
+        // A reserved bit set
+        assert_eq!(read(~[0xC1, 0x00]), Err(InvalidFrame));
+        // An unknown opcode
+        assert_eq!(read(~[0x83, 0x00]), Err(InvalidFrame));
+        // A fragmented ping
+        assert_eq!(read(~[0x09, 0x00]), Err(InvalidFrame));
+        // A ping with a payload too long for a control frame
+        assert_eq!(read(~[0x89, 0x7E, 0x00, 0x7E]), Err(InvalidFrame));
+    }
+}
@@ -0,0 +1,69 @@
+//! The foreign interface to zlib, limited to what is needed here: inflating bodies in the `gzip`
+//! and `deflate` content-codings (see `compression`), and SPDY's compressed header blocks (see
+//! `spdy`).
+
+use std::libc::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::ptr;
+
+#[allow(non_camel_case_types)]
+pub struct z_stream {
+    next_in: *u8,
+    avail_in: c_uint,
+    total_in: c_ulong,
+    next_out: *mut u8,
+    avail_out: c_uint,
+    total_out: c_ulong,
+    msg: *c_char,
+    state: *c_void,
+    zalloc: *c_void,
+    zfree: *c_void,
+    opaque: *c_void,
+    data_type: c_int,
+    adler: c_ulong,
+    reserved: c_ulong,
+}
+
+pub static Z_OK: c_int = 0;
+pub static Z_STREAM_END: c_int = 1;
+pub static Z_NEED_DICT: c_int = 2;
+pub static Z_BUF_ERROR: c_int = -5;
+pub static Z_NO_FLUSH: c_int = 0;
+pub static Z_SYNC_FLUSH: c_int = 2;
+pub static Z_DEFAULT_COMPRESSION: c_int = -1;
+
+#[link_args = "-lz"]
+extern "C" {
+    pub fn zlibVersion() -> *c_char;
+    pub fn deflateInit_(strm: *mut z_stream, level: c_int, version: *c_char,
+                        stream_size: c_int) -> c_int;
+    pub fn deflateSetDictionary(strm: *mut z_stream, dictionary: *u8, len: c_uint) -> c_int;
+    pub fn deflate(strm: *mut z_stream, flush: c_int) -> c_int;
+    pub fn deflateEnd(strm: *mut z_stream) -> c_int;
+    pub fn inflateInit_(strm: *mut z_stream, version: *c_char, stream_size: c_int) -> c_int;
+    pub fn inflateInit2_(strm: *mut z_stream, window_bits: c_int, version: *c_char,
+                         stream_size: c_int) -> c_int;
+    pub fn inflateSetDictionary(strm: *mut z_stream, dictionary: *u8, len: c_uint) -> c_int;
+    pub fn inflate(strm: *mut z_stream, flush: c_int) -> c_int;
+    pub fn inflateEnd(strm: *mut z_stream) -> c_int;
+}
+
+/// A stream with no input or output yet, for one of the `*Init*` functions to set up. zlib keeps a
+/// pointer to the stream, so it is boxed to stay put.
+pub fn new_z_stream() -> ~z_stream {
+    ~z_stream {
+        next_in: ptr::null(),
+        avail_in: 0,
+        total_in: 0,
+        next_out: ptr::mut_null(),
+        avail_out: 0,
+        total_out: 0,
+        msg: ptr::null(),
+        state: ptr::null(),
+        zalloc: ptr::null(),
+        zfree: ptr::null(),
+        opaque: ptr::null(),
+        data_type: 0,
+        adler: 0,
+        reserved: 0,
+    }
+}